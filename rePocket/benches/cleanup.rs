@@ -0,0 +1,63 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+// rePocket is a binary, the cleanup code is pulled in as it is, not all of it benchmarked.
+#[path = "../src/articlehandler/cleanup.rs"]
+#[allow(dead_code)]
+mod cleanup;
+
+use std::collections::HashMap;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use regex::Regex;
+
+const LARGE_ARTICLE: &str = include_str!("../data/bench/article.html");
+
+
+// What every article paid before the regexes became statics, on top of the cleanup itself.
+fn compile_regexes() -> Vec<Regex> {
+    vec![
+        Regex::new(r"<img(.*?)>").unwrap(),
+        Regex::new(r"<map>.*?</map>").unwrap(),
+        Regex::new(r"<source(.*?)>").unwrap(),
+        Regex::new(r#"<img.*?src="(?<url>.*?)".*?>"#).unwrap(),
+    ]
+}
+
+
+fn cleanup(c: &mut Criterion) {
+    let images: HashMap<String, String> = cleanup::image_urls(LARGE_ARTICLE).into_iter()
+        .enumerate()
+        .map(|(i, url)| (url, format!("p{i}.jpg")))
+        .collect();
+
+    let sanitization = cleanup::Sanitization::default();
+
+    c.bench_function("cleanup_html large article", |b| {
+        b.iter(|| cleanup::cleanup_html(black_box(LARGE_ARTICLE.to_string()), &images, true, &sanitization))
+    });
+
+    c.bench_function("image_urls large article", |b| {
+        b.iter(|| cleanup::image_urls(black_box(LARGE_ARTICLE)))
+    });
+
+    c.bench_function("compile regexes per article (before)", |b| {
+        b.iter(compile_regexes)
+    });
+}
+
+
+criterion_group!(benches, cleanup);
+criterion_main!(benches);
@@ -0,0 +1,507 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//
+// The string juggling that turns whatever HTML the readabilities produce into something XHTML
+// enough for xochitl. It doesn't depend on the rest of the crate so that the benchmarks can pull
+// it in as it is.
+//
+// The regexes are compiled once, the first time they are needed, rather than for every article.
+//
+use std::collections::{BTreeMap, HashMap, HashSet};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+
+
+// Every start tag ammonia writes, the values of the attributes always in double quotes.
+static START_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<(?<name>[a-zA-Z][a-zA-Z0-9]*)(?<attrs>(?:\s+[^\s"'>/=]+(?:="[^"]*")?)*)\s*>"#).unwrap());
+// The innermost map, maps may be nested.
+static MAP: Lazy<Regex> = Lazy::new(|| Regex::new(r"<map>(?:[^<]|<[^m]|<m[^a]|<ma[^p])*?</map>").unwrap());
+static NO_IMAGES: Lazy<Regex> = Lazy::new(|| Regex::new(r"<(img|source)[^>]*?/>").unwrap());
+static IMG_SRC: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<img.*?src="(?<url>.*?)".*?>"#).unwrap());
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+// Every HTML element there is, to tell typos in the settings from tags.
+const KNOWN_TAGS: [&str; 113] = [
+    "a", "abbr", "acronym", "address", "area", "article", "aside", "audio", "b", "bdi", "bdo",
+    "big", "blockquote", "body", "br", "button", "canvas", "caption", "center", "cite", "code",
+    "col", "colgroup", "data", "datalist", "dd", "del", "details", "dfn", "dialog", "div", "dl",
+    "dt", "em", "embed", "fieldset", "figcaption", "figure", "font", "footer", "form", "h1", "h2",
+    "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "i", "iframe", "img",
+    "input", "ins", "kbd", "label", "legend", "li", "link", "main", "map", "mark", "menu", "meta",
+    "meter", "nav", "noscript", "object", "ol", "optgroup", "option", "output", "p", "picture",
+    "pre", "progress", "q", "rp", "rt", "rtc", "ruby", "s", "samp", "script", "search", "section",
+    "select", "small", "source", "span", "strike", "strong", "style", "sub", "summary", "sup",
+    "table", "tbody", "td", "template", "textarea", "tfoot", "th", "thead", "time", "tr", "tt",
+    "u", "ul", "var",
+];
+// Tags whose content is dropped along with them, which ammonia won't have in the allowlist.
+const NEVER_ALLOWED: [&str; 2] = ["script", "style"];
+// The elements without an end tag, which XHTML wants closed.
+const VOID_TAGS: [&str; 13] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+
+
+//
+// What's left of the article's HTML, as in the settings:
+//
+// [sanitization]
+// preset = "standard"
+// add_tags = ["div"]
+// remove_tags = ["a"]
+// keep_classes = false
+//
+// [sanitization.tag_attributes]
+// img = ["alt"]
+//
+// The preset is the base, "strict" also drops links and layout, "standard" is what rePocket always
+// did, and "lenient" keeps divs and image descriptions. The rest goes on top.
+//
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    Strict,
+    #[default]
+    Standard,
+    Lenient,
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Sanitization {
+    pub preset: Preset,
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+    // Attributes to keep, per tag.
+    pub tag_attributes: BTreeMap<String, Vec<String>>,
+    pub keep_classes: bool,
+}
+
+
+impl Preset {
+    // The tags that go, keeping their content, and the attributes that go, on top of ammonia's
+    // defaults.
+    fn removed(self) -> (Vec<&'static str>, Vec<(&'static str, &'static str)>) {
+        match self {
+            Preset::Strict => (vec!["div", "span", "a", "nav", "aside", "header", "footer"], vec![("img", "alt")]),
+            Preset::Standard => (vec!["div"], vec![("img", "alt")]),
+            Preset::Lenient => (vec![], vec![]),
+        }
+    }
+}
+
+
+impl Sanitization {
+    // What's wrong with the settings, entries mentioned here are ignored.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for tag in self.add_tags.iter().chain(&self.remove_tags).chain(self.tag_attributes.keys()) {
+            if !KNOWN_TAGS.contains(&tag.as_str()) {
+                problems.push(format!("'{tag}' is not an HTML tag"));
+            }
+        }
+
+        for tag in self.add_tags.iter().filter(|tag| NEVER_ALLOWED.contains(&tag.as_str())) {
+            problems.push(format!("'{tag}' can't be allowed"));
+        }
+
+        for (tag, attributes) in &self.tag_attributes {
+            for attribute in attributes.iter().filter(|attribute| !Self::usable_attribute(tag, attribute)) {
+                problems.push(format!("'{attribute}' can't be kept on '{tag}'"));
+            }
+        }
+
+        problems
+    }
+
+
+    fn usable_tag(tag: &str) -> bool {
+        KNOWN_TAGS.contains(&tag) && !NEVER_ALLOWED.contains(&tag)
+    }
+
+
+    // Event handlers and inline styles aren't for xochitl, and ammonia sets rel on links itself.
+    fn usable_attribute(tag: &str, attribute: &str) -> bool {
+        !attribute.is_empty()
+            && !attribute.starts_with("on")
+            && attribute != "style"
+            && !(tag == "a" && attribute == "rel")
+    }
+
+
+    // The one place the ammonia::Builder is made.
+    pub fn builder(&self) -> ammonia::Builder<'_> {
+        let (removed, removed_attributes) = self.preset.removed();
+        let mut removed_tags: Vec<&str> = removed;
+
+        let added: Vec<&str> = self.add_tags.iter()
+            .map(String::as_str)
+            .filter(|tag| Self::usable_tag(tag))
+            .collect();
+        removed_tags.retain(|tag| !added.contains(tag));
+        removed_tags.extend(self.remove_tags.iter().map(String::as_str).filter(|tag| KNOWN_TAGS.contains(tag)));
+
+        let mut builder = ammonia::Builder::default();
+        builder.add_tags(added);
+        builder.rm_tags(removed_tags);
+
+        for (tag, attribute) in removed_attributes {
+            let kept = self.tag_attributes.get(tag).is_some_and(|kept| kept.iter().any(|kept| kept == attribute));
+
+            if !kept {
+                builder.rm_tag_attributes(tag, &[attribute]);
+            }
+        }
+
+        for (tag, attributes) in self.tag_attributes.iter().filter(|(tag, _)| Self::usable_tag(tag)) {
+            let attributes: HashSet<&str> = attributes.iter()
+                .map(String::as_str)
+                .filter(|attribute| Self::usable_attribute(tag, attribute))
+                .collect();
+            builder.add_tag_attributes(tag.as_str(), attributes);
+        }
+
+        if self.keep_classes {
+            builder.add_generic_attributes(&["class"]);
+        }
+
+        builder
+    }
+}
+
+
+// ammonia does the heavy lifting, however, it is not a conversion tool. Certain things need a
+// different approach, and for those I'm down to string substitution and regexes. I know, I
+// know, ...
+//
+// Image URLs found in `images` are replaced by the local names, without images the tags go.
+pub fn cleanup_html(dirty: String, images: &HashMap<String, String>, with_images: bool, sanitization: &Sanitization) -> String {
+    let output = sanitization.builder()
+        .clean(&dirty)
+        .to_string();
+
+    let mut output = START_TAG.replace_all(&output, xhtml_tag).into_owned();
+
+    while MAP.is_match(&output) {
+        output = MAP.replace_all(&output, "").into_owned();
+    }
+
+    let mut output = output
+        // Fixes an issue with remarkable not liking the tag, as in make it XTHML
+        .replace("<img />", "")
+        // HTML has names for entities XML doesn't
+        .replace("&nbsp;", "&#160;");
+
+    // Fix images (or attempt to anyways)
+    for (k, v) in images {
+        output = output.replace(k, v);
+    }
+
+    // Without images, drop the tags altogether rather than leaving xochitl with remote links.
+    if !with_images {
+        output = NO_IMAGES.replace_all(&output, "").into_owned();
+    }
+
+    output
+}
+
+
+// The start tag as XHTML wants it, void elements closed and no brackets in the attributes.
+fn xhtml_tag(tag: &regex::Captures) -> String {
+    let name = &tag["name"];
+    let attrs = tag["attrs"].replace('<', "&lt;").replace('>', "&gt;");
+
+    match VOID_TAGS.contains(&name) {
+        true => format!("<{name}{attrs} />"),
+        false => format!("<{name}{attrs}>"),
+    }
+}
+
+
+// The HTML without the <img> tags for any of the given local names.
+pub fn drop_images(html: &str, locs: &[&str]) -> String {
+    NO_IMAGES.replace_all(html, |tag: &regex::Captures| {
+        match locs.iter().any(|loc| tag[0].contains(&format!("\"{loc}\""))) {
+            true => String::new(),
+            false => tag[0].to_string(),
+        }
+    }).into_owned()
+}
+
+
+// The src of every <img> in the HTML, in order of appearance.
+pub fn image_urls(html: &str) -> Vec<String> {
+    IMG_SRC.captures_iter(html)
+        .map(|img| img["url"].to_string())
+        .collect()
+}
+
+
+// Just the text, roughly, for counting words. Entities are left as they are.
+pub fn text_content(html: &str) -> String {
+    TAG.replace_all(html, " ").into_owned()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use quick_xml::events::Event;
+
+    const LARGE_ARTICLE: &'static str = include_str!("../../data/bench/article.html");
+    // A few per test run, many more with the fuzz feature.
+    const CASES: u32 = if cfg!(feature = "fuzz") { 100_000 } else { 64 };
+
+    // cleanup_html() as it was when every call compiled its own regexes, kept to prove the output
+    // didn't change.
+    fn reference(html: &Vec<u8>, images: &HashMap<String, String>) -> Vec<u8> {
+        let dirty = &String::from_utf8(html.to_vec()).unwrap();
+
+        let output = ammonia::Builder::default()
+            .rm_tags(&["div"])
+            .rm_tag_attributes("img", &["alt"])
+            .clean(dirty)
+            .to_string();
+
+        let re = Regex::new(r"<img(.*?)>").unwrap();
+        let output = re.replace_all(&output, "<img$1 />");
+        let re = Regex::new(r"<map>.*?</map>").unwrap();
+        let output = re.replace_all(&output, "");
+        let re = Regex::new(r"<source(.*?)>").unwrap();
+        let mut output = re.replace_all(&output, "<source$1 />")
+            .replace("<img />", "")
+            .replace("<hr>", "<hr />")
+            .replace("<br>", "<br />");
+
+        for (k, v) in images {
+            output = output.replace(k, v);
+        }
+
+        output.into()
+    }
+
+    fn images(html: &str) -> HashMap<String, String> {
+        image_urls(html).into_iter()
+            .enumerate()
+            .map(|(i, url)| (url, format!("p{i}.png")))
+            .collect()
+    }
+
+    #[test]
+    fn identical_to_reference() {
+        let small = "<div><p>Tom &amp; Jerry<br><img src=\"https://example.com/a.png\" alt=\"a\"><hr></p>\
+            <map><area href=\"x\"></map><picture><source srcset=\"b.webp\"><img></picture></div>";
+
+        for html in [small, LARGE_ARTICLE] {
+            for images in [HashMap::new(), images(html)] {
+                let expected = reference(&html.as_bytes().to_vec(), &images);
+                let output = cleanup_html(html.to_string(), &images, true, &Sanitization::default());
+
+                assert_eq!(output.as_bytes(), expected.as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn find_image_urls() {
+        let html = "<p><img src=\"https://example.com/a.png\"> and <img class=\"x\" src=\"/b.jpg\" /></p>";
+
+        assert_eq!(image_urls(html), vec!["https://example.com/a.png", "/b.jpg"]);
+        assert!(image_urls(LARGE_ARTICLE).len() > 10);
+    }
+
+    // The presets' output for the captured article, compared against the files committed next to
+    // it, which are only ever written when UPDATE_GOLDEN is set. A missing one is a failure.
+    #[test]
+    fn golden_presets() {
+        let images = images(LARGE_ARTICLE);
+
+        for (preset, name) in [(Preset::Strict, "strict"), (Preset::Standard, "standard"), (Preset::Lenient, "lenient")] {
+            let sanitization = Sanitization { preset, ..Default::default() };
+            let output = cleanup_html(LARGE_ARTICLE.to_string(), &images, true, &sanitization);
+            let golden = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("data/bench/article.{name}.html"));
+
+            if std::env::var_os("UPDATE_GOLDEN").is_some() {
+                std::fs::write(&golden, &output).unwrap();
+            }
+
+            let expected = std::fs::read_to_string(&golden)
+                .unwrap_or_else(|e| panic!("{}: {e}, run the test with UPDATE_GOLDEN=1 and commit it", golden.display()));
+            assert_eq!(output, expected, "{name}");
+        }
+
+        // What rePocket always did.
+        let standard = cleanup_html(LARGE_ARTICLE.to_string(), &images, true, &Sanitization::default());
+        assert_eq!(standard.as_bytes(), reference(&LARGE_ARTICLE.as_bytes().to_vec(), &images).as_slice());
+    }
+
+    #[test]
+    fn presets() {
+        let html = "<div><p><a href=\"https://example.com\">Link</a></p><figure><img src=\"a.png\" alt=\"A\" />\
+            <figcaption class=\"caption\">Caption</figcaption></figure></div>";
+        let clean = |sanitization: Sanitization| cleanup_html(html.to_string(), &HashMap::new(), true, &sanitization);
+
+        let strict = clean(Sanitization { preset: Preset::Strict, ..Default::default() });
+        assert!(!strict.contains("<a ") && !strict.contains("<div>") && strict.contains("Link"));
+        assert!(strict.contains("<figcaption>Caption</figcaption>"));
+
+        let standard = clean(Sanitization::default());
+        assert!(standard.contains("<a href=\"https://example.com\"") && !standard.contains("<div>") && !standard.contains("alt="));
+
+        let lenient = clean(Sanitization { preset: Preset::Lenient, ..Default::default() });
+        assert!(lenient.contains("<div>") && lenient.contains("alt=\"A\""));
+
+        // On top of the preset.
+        let custom = clean(Sanitization {
+            add_tags: vec!["div".to_string()],
+            remove_tags: vec!["a".to_string(), "figure".to_string()],
+            tag_attributes: BTreeMap::from([("img".to_string(), vec!["alt".to_string()])]),
+            keep_classes: true,
+            ..Default::default()
+        });
+        assert!(custom.contains("<div>") && !custom.contains("<a ") && !custom.contains("<figure>"));
+        assert!(custom.contains("alt=\"A\"") && custom.contains("<figcaption class=\"caption\">"));
+    }
+
+    #[test]
+    fn sanitization_problems() {
+        let sanitization = Sanitization {
+            add_tags: vec!["figure".to_string(), "figur".to_string(), "script".to_string()],
+            remove_tags: vec!["blink".to_string()],
+            tag_attributes: BTreeMap::from([
+                ("a".to_string(), vec!["rel".to_string(), "title".to_string()]),
+                ("img".to_string(), vec!["onload".to_string()]),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(sanitization.problems(), vec![
+            "'figur' is not an HTML tag",
+            "'blink' is not an HTML tag",
+            "'script' can't be allowed",
+            "'rel' can't be kept on 'a'",
+            "'onload' can't be kept on 'img'",
+        ]);
+
+        // Ignored rather than the end of the world, ammonia would panic on some of them.
+        let html = "<p><a href=\"https://example.com\" rel=\"me\">Link</a><script>alert(1)</script></p>";
+        let output = cleanup_html(html.to_string(), &HashMap::new(), true, &sanitization);
+        assert!(!output.contains("script") && !output.contains("rel=\"me\""));
+    }
+
+    #[test]
+    fn drop_some_images() {
+        let html = "<p><img src=\"pa.png\" />Text<img class=\"x\" src=\"pb.jpg\" /></p>";
+
+        assert_eq!(drop_images(html, &["pb.jpg"]), "<p><img src=\"pa.png\" />Text</p>");
+        assert_eq!(drop_images(html, &["pa.png", "pb.jpg"]), "<p>Text</p>");
+        assert_eq!(drop_images(html, &["p.png"]), html);
+    }
+
+    #[test]
+    fn strip_tags() {
+        let html = "<p>電子<b>ペーパー</b></p><p>reading<br />time</p>";
+
+        assert_eq!(text_content(html).split_whitespace().collect::<Vec<&str>>(),
+            vec!["電子", "ペーパー", "reading", "time"]);
+    }
+
+    // Wrapped in a body, whether quick-xml reads it all as XML, attributes and end tags included.
+    fn well_formed(xhtml: &str) -> Result<(), String> {
+        let wrapped = format!("<body>{xhtml}</body>");
+        let mut reader = quick_xml::Reader::from_str(&wrapped);
+        let mut depth = 0;
+
+        loop {
+            match reader.read_event().map_err(|e| e.to_string())? {
+                Event::Start(tag) => {
+                    depth += 1;
+                    tag.attributes().try_for_each(|attr| attr.map(|_| ())).map_err(|e| e.to_string())?;
+                },
+                Event::Empty(tag) => {
+                    tag.attributes().try_for_each(|attr| attr.map(|_| ())).map_err(|e| e.to_string())?;
+                },
+                Event::End(_) => depth -= 1,
+                Event::Eof => break,
+                _ => (),
+            }
+        }
+
+        match depth {
+            0 => Ok(()),
+            _ => Err(format!("{depth} element(s) left open")),
+        }
+    }
+
+    fn presets() -> Vec<Sanitization> {
+        [Preset::Strict, Preset::Standard, Preset::Lenient].into_iter()
+            .map(|preset| Sanitization { preset, ..Default::default() })
+            .collect()
+    }
+
+    // Something like HTML, tags, attributes and entities in any order, open or not.
+    fn markup() -> impl Strategy<Value = String> {
+        let tag = prop::sample::select(KNOWN_TAGS.to_vec());
+        let attr = (
+            prop::sample::select(vec!["src", "href", "title", "lang", "alt", "class", "srcset", "x"]),
+            prop_oneof![r#"="[^"]{0,8}""#, "='[^']{0,8}'", "=[^ >]{0,8}", ""],
+        ).prop_map(|(name, value)| format!(" {name}{value}"));
+        let piece = prop_oneof![
+            (tag.clone(), prop::collection::vec(attr, 0..3)).prop_map(|(tag, attrs)| format!("<{tag}{}>", attrs.concat())),
+            tag.prop_map(|tag| format!("</{tag}>")),
+            "[^<]{0,12}",
+            "&[a-z#0-9]{0,6};?",
+            "<[!?/]?[^>]{0,8}>?",
+        ];
+
+        prop::collection::vec(piece, 0..64).prop_map(|pieces| pieces.concat())
+    }
+
+    #[test]
+    fn void_and_nested() {
+        let html = "<p title=\"a<b>c\">x<wbr>y</p><map><map><area href=\"x\"></map></map><table><col></table>\u{a0}";
+        let output = cleanup_html(html.to_string(), &HashMap::new(), true, &Sanitization::default());
+
+        assert!(output.contains("<wbr />") && output.contains("title=\"a&lt;b&gt;c\""));
+        assert!(!output.contains("map") && output.contains("&#160;"));
+        assert_eq!(well_formed(&output), Ok(()));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+        #[test]
+        fn well_formed_from_any_bytes(bytes in prop::collection::vec(any::<u8>(), 0..2048)) {
+            let dirty = String::from_utf8_lossy(&bytes).into_owned();
+
+            for sanitization in presets() {
+                let output = cleanup_html(dirty.clone(), &HashMap::new(), true, &sanitization);
+                prop_assert_eq!(well_formed(&output), Ok(()), "{}", output);
+            }
+        }
+
+        #[test]
+        fn well_formed_from_markup(dirty in markup(), with_images in any::<bool>()) {
+            for sanitization in presets() {
+                let output = cleanup_html(dirty.clone(), &HashMap::new(), with_images, &sanitization);
+                prop_assert_eq!(well_formed(&output), Ok(()), "{}", output);
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+//
+// Documentation pages, MDN's, docs.rs' and those on Read the Docs, are reference material the
+// readabilities make soup of: definition lists, parameter tables and signatures get flattened, and
+// the anchors the table of contents points at are gone. For those the main part of the page is
+// taken as it is rather than extracted:
+//
+//  - The navigation, sidebars and breadcrumbs go, by what each site marks them with, see STRIPPED,
+//    and so do the permalinks next to the headings.
+//  - The headings and terms keep their ids, see ANCHORED, and the code its look, see STYLE.
+//  - The document is set a notch smaller than the rest, see TEXT_SCALE, there's a lot to fit.
+//
+// A documentation site is told by its host, or for rustdoc and Sphinx pages hosted elsewhere, by
+// the generator the page names.
+//
+use std::fmt;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use url::Url;
+
+use super::newsletter::{attribute, find_where, generator, has_class};
+
+static ID: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\bid\s*=\s*"(?<id>[^"]*)""#).unwrap());
+
+// By site, where the documentation is, the first of those found.
+const ROOTS: [(Site, &[Selector]); 3] = [
+    (Site::Mdn, &[Selector::Class("main-page-content"), Selector::Id("content")]),
+    (Site::DocsRs, &[Selector::Id("main-content")]),
+    (Site::ReadTheDocs, &[Selector::Class("rst-content"), Selector::Class("body")]),
+];
+// By site, what goes from it.
+const STRIPPED: [(Site, &[Selector]); 3] = [
+    (Site::Mdn, &[
+        Selector::Tag("nav"), Selector::Tag("aside"), Selector::Class("breadcrumbs-container"),
+        Selector::Class("article-actions"), Selector::Class("example-header"), Selector::Class("metadata"),
+    ]),
+    (Site::DocsRs, &[
+        Selector::Tag("nav"), Selector::Tag("rustdoc-toolbar"), Selector::Tag("button"), Selector::Class("sidebar"),
+        Selector::Class("out-of-band"), Selector::Class("src"), Selector::Class("anchor"), Selector::Class("doc-anchor"),
+        Selector::Class("hideme"),
+    ]),
+    (Site::ReadTheDocs, &[
+        Selector::Tag("nav"), Selector::Tag("footer"), Selector::Class("wy-breadcrumbs"), Selector::Class("sphinxsidebar"),
+        Selector::Class("headerlink"), Selector::Class("rst-footer-buttons"),
+    ]),
+];
+// The tags whose ids are kept, for the links in the page to land somewhere, see Sanitization.
+pub const ANCHORED: [&str; 7] = ["h1", "h2", "h3", "h4", "h5", "h6", "dt"];
+// Smaller than xochitl's 1, reference material reads better dense.
+pub const TEXT_SCALE: f64 = 0.8;
+// What the sites' stylesheets did for code and definition lists, which the page goes without.
+pub const STYLE: &str = "<style>code { font-family: monospace; background-color: #eeeeee; } \
+    pre code { background-color: transparent; } dt { font-weight: bold; margin-top: 0.8em; } \
+    dd { margin-left: 1.5em; }</style>";
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Site {
+    Mdn,
+    DocsRs,
+    ReadTheDocs,
+}
+
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Site::Mdn => write!(f, "MDN"),
+            Site::DocsRs => write!(f, "docs.rs"),
+            Site::ReadTheDocs => write!(f, "Read the Docs"),
+        }
+    }
+}
+
+
+// Which elements, as CSS would put it: nav, .sidebar and #content.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Selector {
+    Tag(&'static str),
+    Class(&'static str),
+    Id(&'static str),
+}
+
+
+impl Selector {
+    fn matches(self, tag: &str) -> bool {
+        match self {
+            Selector::Tag(name) => tag[1..].strip_prefix(name).is_some_and(|rest| rest.starts_with(['>', '/', ' ', '\t', '\n'])),
+            Selector::Class(class) => has_class(tag, class),
+            Selector::Id(id) => attribute(&ID, tag).is_some_and(|found| found == id),
+        }
+    }
+}
+
+
+// The page, ready to be put in the document as it is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Docs {
+    pub site: Site,
+    pub html: String,
+}
+
+
+pub fn detect(url: &Url, html: &str) -> Option<Site> {
+    let host = url.host_str().unwrap_or_default();
+    let generator = generator(html).unwrap_or_default();
+
+    if host == "developer.mozilla.org" {
+        return Some(Site::Mdn);
+    }
+
+    if ["docs.rs", "doc.rust-lang.org"].contains(&host) || generator == "rustdoc" {
+        return Some(Site::DocsRs);
+    }
+
+    let read_the_docs = [".readthedocs.io", ".readthedocs-hosted.com"].iter().any(|suffix| host.ends_with(suffix));
+    match read_the_docs || generator.starts_with("Docutils") {
+        true => Some(Site::ReadTheDocs),
+        false => None,
+    }
+}
+
+
+// None without the main part the site would have, the page is extracted as any other then.
+pub fn prepare(site: Site, html: &str) -> Option<Docs> {
+    let roots = ROOTS.iter().find(|(roots, _)| *roots == site).map_or(&[][..], |(_, selectors)| selectors);
+    let root = roots.iter().find_map(|selector| find_where(html, |tag| selector.matches(tag)))?;
+    let mut prepared = html[root.inner].to_string();
+
+    let stripped = STRIPPED.iter().find(|(stripped, _)| *stripped == site).map_or(&[][..], |(_, selectors)| selectors);
+    for selector in stripped {
+        while let Some(element) = find_where(&prepared, |tag| selector.matches(tag)) {
+            prepared.replace_range(element.outer, "");
+        }
+    }
+
+    Some(Docs {
+        site,
+        html: prepared.trim().to_string(),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/docs");
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(format!("{FIXTURES}/{name}")).unwrap()
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn sites() {
+        assert_eq!(detect(&url("https://developer.mozilla.org/en-US/docs/Web/HTML/Element/dl"), ""), Some(Site::Mdn));
+        assert_eq!(detect(&url("https://docs.rs/serde/latest/serde/"), ""), Some(Site::DocsRs));
+        assert_eq!(detect(&url("https://docs.example.com/crate/"), &fixture("docsrs.html")), Some(Site::DocsRs));
+        assert_eq!(detect(&url("https://requests.readthedocs.io/en/latest/"), ""), Some(Site::ReadTheDocs));
+        assert_eq!(detect(&url("https://docs.example.org/"), r#"<meta name="generator" content="Docutils 0.19: https://docutils.sourceforge.io/" />"#),
+            Some(Site::ReadTheDocs));
+        assert_eq!(detect(&url("https://www.example.com/docs/"), ""), None);
+    }
+
+    #[test]
+    fn golden_mdn() {
+        let docs = prepare(Site::Mdn, &fixture("mdn.html")).unwrap();
+
+        assert_eq!(docs.html, fixture("mdn.golden.html").trim());
+    }
+
+    #[test]
+    fn golden_docsrs() {
+        let docs = prepare(Site::DocsRs, &fixture("docsrs.html")).unwrap();
+
+        assert_eq!(docs.html, fixture("docsrs.golden.html").trim());
+    }
+
+    #[test]
+    fn selectors() {
+        assert!(Selector::Tag("nav").matches("<nav class=\"sidebar\">"));
+        assert!(Selector::Tag("nav").matches("<nav>"));
+        assert!(!Selector::Tag("nav").matches("<navigation>"));
+        assert!(Selector::Class("src").matches("<a class=\"src rightside\" href=\"#\">"));
+        assert!(Selector::Id("content").matches("<main id=\"content\" class=\"main-content\">"));
+        assert!(!Selector::Id("content").matches("<div id=\"main-content\">"));
+
+        // Nothing to go by, nothing to prepare.
+        assert_eq!(prepare(Site::DocsRs, "<html><body><p>Not rustdoc's</p></body></html>"), None);
+    }
+}
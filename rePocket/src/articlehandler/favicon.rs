@@ -0,0 +1,362 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+//
+// The site's icon, shown small next to the link on the article's first page, for where an article
+// comes from to be told at a glance. It's the one the page's head names, or failing that
+// /favicon.ico, as it comes: there's nothing on board to convert images with, so of an .ico only a
+// PNG inside it will do.
+//
+// Icons are kept per domain, in the state directory, and so are sites without one, so that each
+// is asked once. Whatever goes wrong just means no icon.
+//
+use std::fs;
+use std::path::PathBuf;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use url::Url;
+
+use super::ArticleHandler;
+use crate::budget;
+use crate::fshandler;
+
+// Bigger than that isn't an icon.
+const MAX_BYTES: usize = 32 * 1024;
+// The ideal width, the smallest one at least as wide is taken from an .ico.
+const WIDTH: u16 = 32;
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+static LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)<link\b[^>]*>").unwrap());
+static REL: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)\brel\s*=\s*(?:"(?<dq>[^"]*)"|'(?<sq>[^']*)'|(?<bare>[^\s>]+))"#).unwrap());
+static HREF: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)\bhref\s*=\s*(?:"(?<dq>[^"]*)"|'(?<sq>[^']*)'|(?<bare>[^\s>]+))"#).unwrap());
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Favicon {
+    pub bytes: Vec<u8>,
+    pub mime_type: &'static str,
+}
+
+
+impl Favicon {
+    // What it goes by in the epub.
+    pub fn file_name(&self) -> String {
+        let (_, ext) = self.mime_type.split_once('/').unwrap_or_default();
+
+        format!("favicon.{ext}")
+    }
+}
+
+
+// Icons by domain, an empty file for a domain without one.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+
+    // None when the domain hasn't been asked yet, Some(None) when it has no icon.
+    pub fn get(&self, domain: &str) -> Option<Option<Favicon>> {
+        fs::read(self.dir.join(domain)).ok().map(|bytes| decode(&bytes))
+    }
+
+
+    pub fn put(&self, domain: &str, favicon: Option<&Favicon>) {
+        let bytes = favicon.map_or(&[][..], |favicon| favicon.bytes.as_slice());
+
+        let _ = fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.dir.join(domain), bytes));
+    }
+}
+
+
+pub fn cache_dir() -> PathBuf {
+    fshandler::state_dir().join("favicons")
+}
+
+
+// The icon for the page, from the cache or from the site.
+pub async fn fetch(page: &Url, html: &str, cache: &Cache) -> Option<Favicon> {
+    let domain = match page.port() {
+        Some(port) => format!("{}_{port}", page.host_str()?),
+        None => page.host_str()?.to_string(),
+    };
+
+    if let Some(cached) = cache.get(&domain) {
+        return cached;
+    }
+
+    let mut answered = true;
+
+    for url in candidates(page, html) {
+        match download(&url).await {
+            Ok(Some(favicon)) => {
+                cache.put(&domain, Some(&favicon));
+                return Some(favicon);
+            },
+            Ok(None) => (),
+            // Maybe next time, a site that couldn't be reached isn't one without an icon.
+            Err(()) => answered = false,
+        }
+    }
+
+    if answered {
+        cache.put(&domain, None);
+    }
+
+    None
+}
+
+
+// Where to look, best first: the icons the page names, then the touch icons, which are larger
+// but do too, and /favicon.ico.
+pub fn candidates(page: &Url, html: &str) -> Vec<Url> {
+    let head = html.find("</head>").map_or(html, |end| &html[..end]);
+    let (mut icons, mut touch) = (Vec::new(), Vec::new());
+
+    for link in LINK.find_iter(head).map(|link| link.as_str()) {
+        let (Some(rel), Some(href)) = (attribute(&REL, link), attribute(&HREF, link)) else {
+            continue;
+        };
+        let Ok(url) = page.join(href.trim()) else {
+            continue;
+        };
+
+        let rel = rel.to_lowercase();
+        let mut tokens = rel.split_whitespace();
+
+        if tokens.clone().any(|token| token == "icon") {
+            icons.push(url);
+        } else if tokens.any(|token| token.starts_with("apple-touch-icon")) {
+            touch.push(url);
+        }
+    }
+
+    let mut urls: Vec<Url> = icons.into_iter().chain(touch).collect();
+
+    if let Ok(ico) = page.join("/favicon.ico") {
+        urls.push(ico);
+    }
+
+    let mut seen = Vec::new();
+    urls.retain(|url| match seen.contains(url) {
+        true => false,
+        false => {
+            seen.push(url.clone());
+            true
+        },
+    });
+
+    urls
+}
+
+
+fn attribute<'h>(re: &Regex, tag: &'h str) -> Option<&'h str> {
+    let caps = re.captures(tag)?;
+
+    ["dq", "sq", "bare"].iter().find_map(|name| caps.name(name)).map(|value| value.as_str())
+}
+
+
+// Ok(None) for a site that answered without an icon xochitl can show.
+async fn download(url: &Url) -> Result<Option<Favicon>, ()> {
+    let res = ArticleHandler::client()
+        .map_err(|_| ())?
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|_| ())?;
+
+    if !res.status().is_success() {
+        return Ok(None);
+    }
+
+    let bytes = res.bytes().await.map_err(|_| ())?;
+    budget::record(bytes.len());
+
+    match bytes.len() > MAX_BYTES {
+        true => Ok(None),
+        false => Ok(decode(&bytes)),
+    }
+}
+
+
+// The icon in the bytes, told by what they start with rather than by what the server said: PNG,
+// GIF and JPEG as they are, the PNG in an .ico, and nothing else.
+pub fn decode(bytes: &[u8]) -> Option<Favicon> {
+    let mime_type = match bytes {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+        [0, 0, 1, 0, ..] => return ico_png(bytes).map(|png| Favicon { bytes: png.to_vec(), mime_type: "image/png" }),
+        _ => return None,
+    };
+
+    Some(Favicon { bytes: bytes.to_vec(), mime_type })
+}
+
+
+//
+// The PNG images in an .ico, the smallest at least WIDTH wide or else the widest. The rest are
+// bitmaps, which would take converting.
+//
+//  0   reserved, 0         u16
+//  2   type, 1 for icons   u16
+//  4   count               u16
+//  6   entries, 16 bytes each: width (0 for 256) u8, height u8, 6 bytes, size u32, offset u32
+//
+fn ico_png(ico: &[u8]) -> Option<&[u8]> {
+    let u16_at = |at: usize| Some(u16::from_le_bytes(ico.get(at..at + 2)?.try_into().ok()?));
+    let u32_at = |at: usize| Some(u32::from_le_bytes(ico.get(at..at + 4)?.try_into().ok()?) as usize);
+
+    let count = u16_at(4)? as usize;
+
+    (0..count)
+        .filter_map(|index| {
+            let entry = 6 + 16 * index;
+            let width = match *ico.get(entry)? {
+                0 => 256,
+                width => width as u16,
+            };
+            let (size, offset) = (u32_at(entry + 8)?, u32_at(entry + 12)?);
+            let image = ico.get(offset..offset.checked_add(size)?)?;
+
+            image.starts_with(PNG).then_some((width, image))
+        })
+        .min_by_key(|(width, _)| match *width >= WIDTH {
+            true => (0, *width),
+            false => (1, u16::MAX - width),
+        })
+        .map(|(_, image)| image)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mockserver::{self, Reply};
+
+    // An .ico with the images given as (width, bytes), one after the other.
+    fn ico(images: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut ico = vec![0, 0, 1, 0];
+        ico.extend((images.len() as u16).to_le_bytes());
+
+        let mut offset = 6 + 16 * images.len();
+        for (width, image) in images {
+            ico.extend([*width, *width, 0, 0, 1, 0, 32, 0]);
+            ico.extend((image.len() as u32).to_le_bytes());
+            ico.extend((offset as u32).to_le_bytes());
+            offset += image.len();
+        }
+        for (_, image) in images {
+            ico.extend(*image);
+        }
+
+        ico
+    }
+
+    fn png(tag: &str) -> Vec<u8> {
+        [PNG, tag.as_bytes()].concat()
+    }
+
+    fn cache(name: &str) -> Cache {
+        let dir = std::env::temp_dir().join(format!("repocket-favicons-{name}"));
+        let _ = fs::remove_dir_all(&dir);
+
+        Cache::new(dir)
+    }
+
+    #[test]
+    fn discovery() {
+        let page = Url::parse("https://example.com/blog/post").unwrap();
+        let html = r#"<html><head>
+            <link rel="apple-touch-icon" sizes="180x180" href="/touch.png">
+            <link rel="stylesheet" href="/style.css">
+            <link href='icons/32.png' rel="icon" type="image/png">
+            <link rel="SHORTCUT ICON" href=https://cdn.example.com/favicon.ico>
+            <link rel="icon" href="/favicon.ico">
+            </head><body><link rel="icon" href="/not-in-the-head.png"></body></html>"#;
+
+        let urls: Vec<String> = candidates(&page, html).iter().map(Url::to_string).collect();
+        assert_eq!(urls, vec![
+            "https://example.com/blog/icons/32.png",
+            "https://cdn.example.com/favicon.ico",
+            "https://example.com/favicon.ico",
+            "https://example.com/touch.png",
+        ]);
+
+        let urls: Vec<String> = candidates(&page, "<html><head></head></html>").iter().map(Url::to_string).collect();
+        assert_eq!(urls, vec!["https://example.com/favicon.ico"]);
+    }
+
+    #[test]
+    fn decoding() {
+        assert_eq!(decode(&png("icon")).unwrap().mime_type, "image/png");
+        assert_eq!(decode(b"GIF89a...").unwrap().file_name(), "favicon.gif");
+        assert_eq!(decode(b"\xff\xd8\xff\xe0...").unwrap().mime_type, "image/jpeg");
+        assert!(decode(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>").is_none());
+        assert!(decode(b"").is_none());
+    }
+
+    #[test]
+    fn ico_images() {
+        let bitmap = b"\x28\0\0\0 a bitmap, no PNG signature".as_slice();
+        let (p16, p32, p64) = (png("16"), png("32"), png("64"));
+
+        let favicon = decode(&ico(&[(16, &p16), (64, &p64), (32, bitmap), (32, &p32)])).unwrap();
+        assert_eq!((favicon.bytes, favicon.mime_type), (p32.clone(), "image/png"));
+
+        // The widest when none is wide enough, and 0 is 256.
+        assert_eq!(decode(&ico(&[(16, &p16), (24, &p64)])).unwrap().bytes, p64);
+        assert_eq!(decode(&ico(&[(0, &p64), (48, &p32)])).unwrap().bytes, p32);
+
+        assert!(decode(&ico(&[(16, bitmap)])).is_none());
+        assert!(decode(&ico(&[(32, &p32)])[..30]).is_none());
+        assert!(decode(&[0, 0, 1, 0, 0xff, 0xff]).is_none());
+    }
+
+    #[tokio::test]
+    async fn cached_per_domain() {
+        let icon = png("site");
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/favicon.ico", Reply::ok("image/x-icon", &ico(&[(32, &icon)]))),
+            ("/favicon.ico", Reply::not_found()),
+        ]).await;
+        let other = mockserver::serve(vec![]).await;
+        let cache = cache("per-domain");
+
+        let page = Url::parse(&format!("{base}/one")).unwrap();
+        assert_eq!(fetch(&page, "", &cache).await.unwrap().bytes, icon);
+        let page = Url::parse(&format!("{base}/two")).unwrap();
+        assert_eq!(fetch(&page, "", &cache).await.unwrap().bytes, icon);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // Sites without an icon are kept too, those that couldn't be reached aren't.
+        let page = Url::parse(&format!("{other}/one")).unwrap();
+        assert!(fetch(&page, "", &cache).await.is_none());
+        assert_eq!(cache.get(&format!("127.0.0.1_{}", page.port().unwrap())), Some(None));
+
+        let page = Url::parse("http://127.0.0.1:1/gone").unwrap();
+        assert!(fetch(&page, "", &cache).await.is_none());
+        assert_eq!(cache.get("127.0.0.1_1"), None);
+    }
+}
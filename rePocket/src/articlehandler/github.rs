@@ -0,0 +1,428 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//
+// GitHub pages come out of the readabilities as navigation and not much else, so for the usual
+// suspects the content is taken from the source instead:
+//
+//  github.com/owner/repo                   The README, as markdown, from the API.
+//  github.com/owner/repo/blob/ref/x.md     The markdown file, from raw.githubusercontent.com.
+//  github.com/owner/repo/issues/n          The issue or PR and its first comments, from the API.
+//  gist.github.com/owner/id                Every file in the gist, as code.
+//
+// The API takes 60 unauthenticated requests an hour, more with a token in the settings. Once
+// it says there's none left, it's not asked again until it says so. Whatever fails here is
+// extracted like any other page.
+//
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use once_cell::sync::Lazy;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use reqwest::{Response, StatusCode};
+use serde_json::Value;
+use url::Url;
+
+use super::{ArticleError, ArticleHandler};
+use crate::budget;
+use crate::text::PlainText;
+
+const API: &str = "https://api.github.com";
+const RAW: &str = "https://raw.githubusercontent.com";
+const TOP_COMMENTS: usize = 10;
+
+static TOKEN: Lazy<RwLock<Option<String>>> = Lazy::new(|| RwLock::new(None));
+// Unix time the API takes requests again, zero if it does already.
+static LIMITED_UNTIL: AtomicU64 = AtomicU64::new(0);
+
+
+#[derive(Debug, PartialEq)]
+pub enum Page {
+    Readme { owner: String, repo: String },
+    Markdown { owner: String, repo: String, reference: String, path: String },
+    Issue { owner: String, repo: String, number: u64 },
+    Gist { id: String },
+}
+
+
+pub struct Rendered {
+    pub title: String,
+    pub author: String,
+    pub html: String,
+}
+
+
+// The GitHub page the URL points to, if it's one worth fetching from the source.
+pub fn page(url: &str) -> Option<Page> {
+    let url = Url::parse(url).ok()?;
+    let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+
+    match url.host_str()? {
+        "github.com" | "www.github.com" => match segments.as_slice() {
+            [owner, repo] => Some(Page::Readme {
+                owner: owner.to_string(),
+                repo: repo.trim_end_matches(".git").to_string(),
+            }),
+            [owner, repo, "blob", reference, path @ ..] if path.last().is_some_and(|name| is_markdown(name)) => Some(Page::Markdown {
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+                reference: reference.to_string(),
+                path: path.join("/"),
+            }),
+            [owner, repo, "issues" | "pull", number, ..] => Some(Page::Issue {
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+                number: number.parse().ok()?,
+            }),
+            _ => None,
+        },
+        "gist.github.com" => match segments.as_slice() {
+            [_, id] | [id] => Some(Page::Gist { id: id.to_string() }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+
+// Called with the settings before fetching anything.
+pub fn configure(token: Option<&str>) {
+    *TOKEN.write().unwrap() = token.map(String::from);
+}
+
+
+fn is_markdown(name: &str) -> bool {
+    let name = name.to_lowercase();
+
+    name.ends_with(".md") || name.ends_with(".markdown")
+}
+
+
+// Where the pages come from, somewhere else in the tests.
+pub struct Sources {
+    api: String,
+    raw: String,
+}
+
+
+impl Default for Sources {
+    fn default() -> Self {
+        Self {
+            api: API.to_string(),
+            raw: RAW.to_string(),
+        }
+    }
+}
+
+
+impl Sources {
+    pub async fn fetch(&self, page: &Page) -> Result<Rendered, ArticleError> {
+        match page {
+            Page::Readme { owner, repo } => {
+                let md = self.api(&format!("/repos/{owner}/{repo}/readme"), "application/vnd.github.raw").await?;
+
+                Ok(Rendered {
+                    title: format!("{owner}/{repo}"),
+                    author: owner.clone(),
+                    html: markdown(&md, self.bases(owner, repo, "HEAD", "")),
+                })
+            },
+            Page::Markdown { owner, repo, reference, path } => {
+                let response = ArticleHandler::client()?
+                    .get(format!("{}/{owner}/{repo}/{reference}/{path}", self.raw))
+                    .send()
+                    .await?;
+                let md = body(response).await?;
+                let dir = path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or_default();
+
+                Ok(Rendered {
+                    title: format!("{owner}/{repo}: {path}"),
+                    author: owner.clone(),
+                    html: markdown(&md, self.bases(owner, repo, reference, dir)),
+                })
+            },
+            Page::Issue { owner, repo, number } => {
+                let issue = json(&self.api(&format!("/repos/{owner}/{repo}/issues/{number}"), "application/vnd.github+json").await?)?;
+                let comments = json(&self.api(
+                    &format!("/repos/{owner}/{repo}/issues/{number}/comments?per_page={TOP_COMMENTS}"),
+                    "application/vnd.github+json",
+                ).await?)?;
+
+                let author = issue["user"]["login"].as_str().unwrap_or("Unknown").to_string();
+                let mut html = format!("<p>Opened by {}</p>", PlainText::new(&author).to_html());
+                html += &markdown(issue["body"].as_str().unwrap_or_default(), None);
+
+                for comment in comments.as_array().into_iter().flatten().take(TOP_COMMENTS) {
+                    let login = comment["user"]["login"].as_str().unwrap_or("Unknown");
+                    html += &format!("<hr /><h2>{}</h2>", PlainText::new(login).to_html());
+                    html += &markdown(comment["body"].as_str().unwrap_or_default(), None);
+                }
+
+                Ok(Rendered {
+                    title: format!("{} · {owner}/{repo}#{number}", issue["title"].as_str().unwrap_or_default()),
+                    author,
+                    html,
+                })
+            },
+            Page::Gist { id } => {
+                let gist = json(&self.api(&format!("/gists/{id}"), "application/vnd.github+json").await?)?;
+                let mut html = String::new();
+
+                for (name, file) in gist["files"].as_object().into_iter().flatten() {
+                    html += &format!(
+                        "<h2>{}</h2><pre><code>{}</code></pre>",
+                        PlainText::new(name).to_html(),
+                        PlainText::new(file["content"].as_str().unwrap_or_default()).to_html(),
+                    );
+                }
+
+                let title = match gist["description"].as_str() {
+                    Some(description) if !description.is_empty() => description.to_string(),
+                    _ => format!("Gist {id}"),
+                };
+
+                Ok(Rendered {
+                    title,
+                    author: gist["owner"]["login"].as_str().unwrap_or("Unknown").to_string(),
+                    html,
+                })
+            },
+        }
+    }
+
+
+    async fn api(&self, path: &str, accept: &str) -> Result<String, ArticleError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let until = LIMITED_UNTIL.load(Ordering::Relaxed);
+
+        if now < until {
+            return Err(ArticleError::GitHub(format!("rate limited for another {}s", until - now)));
+        }
+
+        let mut request = ArticleHandler::client()?
+            .get(format!("{}{path}", self.api))
+            .header("Accept", accept)
+            .header("X-GitHub-Api-Version", "2022-11-28");
+
+        if let Some(token) = TOKEN.read().unwrap().as_deref() {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        let header = |name: &str| response.headers().get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+
+        if header("x-ratelimit-remaining") == Some(0) {
+            let reset = header("x-ratelimit-reset").unwrap_or(now + 3600);
+            LIMITED_UNTIL.store(reset, Ordering::Relaxed);
+
+            if matches!(response.status(), StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS) {
+                return Err(ArticleError::GitHub(format!("rate limited for another {}s", reset.saturating_sub(now))));
+            }
+        }
+
+        body(response).await
+    }
+
+
+    // Relative links in markdown lead to the repository, relative images to the raw files.
+    fn bases(&self, owner: &str, repo: &str, reference: &str, dir: &str) -> Option<(Url, Url)> {
+        let dir = match dir {
+            "" => String::new(),
+            dir => format!("{dir}/"),
+        };
+        let links = Url::parse(&format!("https://github.com/{owner}/{repo}/blob/{reference}/{dir}")).ok()?;
+        let images = Url::parse(&format!("{}/{owner}/{repo}/{reference}/{dir}", self.raw)).ok()?;
+
+        Some((links, images))
+    }
+}
+
+
+async fn body(response: Response) -> Result<String, ArticleError> {
+    let text = response.error_for_status()?.text().await?;
+    budget::record(text.len());
+
+    Ok(text)
+}
+
+
+fn json(text: &str) -> Result<Value, ArticleError> {
+    serde_json::from_str(text).map_err(|e| ArticleError::GitHub(format!("unexpected response: {e}")))
+}
+
+
+// Markdown to HTML, code blocks and all. Raw HTML in it is left to the cleanup.
+fn markdown(md: &str, bases: Option<(Url, Url)>) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let parser = Parser::new_ext(md, options).map(|event| match (&bases, event) {
+        (Some((links, _)), Event::Start(Tag::Link { link_type, dest_url, title, id })) => {
+            Event::Start(Tag::Link { link_type, dest_url: resolve(links, dest_url), title, id })
+        },
+        (Some((_, images)), Event::Start(Tag::Image { link_type, dest_url, title, id })) => {
+            Event::Start(Tag::Image { link_type, dest_url: resolve(images, dest_url), title, id })
+        },
+        (_, event) => event,
+    });
+
+    let mut output = String::new();
+    html::push_html(&mut output, parser);
+
+    output
+}
+
+
+fn resolve<'a>(base: &Url, url: CowStr<'a>) -> CowStr<'a> {
+    if url.starts_with('#') || Url::parse(&url).is_ok() {
+        return url;
+    }
+
+    match base.join(&url) {
+        Ok(joined) => CowStr::from(joined.to_string()),
+        Err(_) => url,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use crate::mockserver::{self, Reply};
+
+    const FIXTURES: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/github");
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{FIXTURES}/{name}")).unwrap()
+    }
+
+    fn sources(base: &str) -> Sources {
+        Sources {
+            api: base.to_string(),
+            raw: base.to_string(),
+        }
+    }
+
+    #[test]
+    fn url_shapes() {
+        let readme = |owner: &str, repo: &str| Some(Page::Readme { owner: owner.into(), repo: repo.into() });
+
+        assert_eq!(page("https://github.com/sm13/rePocket"), readme("sm13", "rePocket"));
+        assert_eq!(page("https://github.com/sm13/rePocket/"), readme("sm13", "rePocket"));
+        assert_eq!(page("https://github.com/sm13/rePocket.git"), readme("sm13", "rePocket"));
+        assert_eq!(page("https://github.com/sm13/rePocket/blob/main/docs/INSTALL.md"), Some(Page::Markdown {
+            owner: "sm13".into(),
+            repo: "rePocket".into(),
+            reference: "main".into(),
+            path: "docs/INSTALL.md".into(),
+        }));
+        assert_eq!(page("https://github.com/rust-lang/rust/issues/42"), Some(Page::Issue {
+            owner: "rust-lang".into(),
+            repo: "rust".into(),
+            number: 42,
+        }));
+        assert_eq!(page("https://github.com/rust-lang/rust/pull/7/files"), Some(Page::Issue {
+            owner: "rust-lang".into(),
+            repo: "rust".into(),
+            number: 7,
+        }));
+        assert_eq!(page("https://gist.github.com/someone/aa5a315d61ae9438b18d"), Some(Page::Gist { id: "aa5a315d61ae9438b18d".into() }));
+
+        assert_eq!(page("https://github.com/sm13/rePocket/blob/main/src/main.rs"), None);
+        assert_eq!(page("https://github.com/sm13/rePocket/issues"), None);
+        assert_eq!(page("https://github.com/sm13"), None);
+        assert_eq!(page("https://github.blog/2024/some-post"), None);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn readme() {
+        let base = mockserver::serve(vec![
+            ("/repos/sm13/rePocket/readme", Reply::ok("text/plain", &fixture("readme.md"))),
+            ("/sm13/rePocket/main/docs/INSTALL.md", Reply::ok("text/plain", &fixture("readme.md"))),
+        ]).await;
+        let sources = sources(&base);
+
+        let readme = page("https://github.com/sm13/rePocket").unwrap();
+        let rendered = sources.fetch(&readme).await.unwrap();
+
+        assert_eq!(rendered.title, "sm13/rePocket");
+        assert!(rendered.html.contains("<h1>rePocket</h1>"));
+        assert!(rendered.html.contains("<pre><code class=\"language-rust\">fn main() {\n    let small = 1 &lt; 2;\n}\n</code></pre>"));
+        assert!(rendered.html.contains(&format!("src=\"{base}/sm13/rePocket/HEAD/docs/screenshot.png\"")));
+        assert!(rendered.html.contains("href=\"https://github.com/sm13/rePocket/blob/HEAD/LICENSE\""));
+        assert!(rendered.html.contains("href=\"https://www.gnu.org/licenses/\""));
+
+        let markdown = page("https://github.com/sm13/rePocket/blob/main/docs/INSTALL.md").unwrap();
+        let rendered = sources.fetch(&markdown).await.unwrap();
+
+        assert_eq!(rendered.title, "sm13/rePocket: docs/INSTALL.md");
+        assert!(rendered.html.contains(&format!("src=\"{base}/sm13/rePocket/main/docs/docs/screenshot.png\"")));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn issue_and_comments() {
+        let base = mockserver::serve(vec![
+            ("/repos/sm13/rePocket/issues/12", Reply::ok("application/json", &fixture("issue.json"))),
+            ("/repos/sm13/rePocket/issues/12/comments?per_page=10", Reply::ok("application/json", &fixture("comments.json"))),
+        ]).await;
+
+        let issue = page("https://github.com/sm13/rePocket/issues/12").unwrap();
+        let rendered = sources(&base).fetch(&issue).await.unwrap();
+
+        assert_eq!(rendered.title, "Epubs show up empty · sm13/rePocket#12");
+        assert_eq!(rendered.author, "reader");
+        assert!(rendered.html.contains("<p>Opened by reader</p>"));
+        assert!(rendered.html.contains("<code>xochitl</code>"));
+        assert!(rendered.html.contains("<h2>sm13</h2>"));
+        assert!(rendered.html.contains("<pre><code>journalctl -u repocket\n</code></pre>"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn gist_files() {
+        let base = mockserver::serve(vec![
+            ("/gists/aa5a315d61ae9438b18d", Reply::ok("application/json", &fixture("gist.json"))),
+        ]).await;
+
+        let gist = page("https://gist.github.com/someone/aa5a315d61ae9438b18d").unwrap();
+        let rendered = sources(&base).fetch(&gist).await.unwrap();
+
+        assert_eq!(rendered.title, "Sync on wake");
+        assert_eq!(rendered.author, "someone");
+        assert!(rendered.html.contains("<h2>wake.sh</h2><pre><code>#!/bin/sh\nrepocket sync &amp;&amp; echo done\n</code></pre>"));
+        assert!(rendered.html.contains("<h2>notes.md</h2>"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn rate_limited() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/repos/sm13/rePocket/readme", Reply { status: 403, ..Reply::ok("application/json", b"{}") }
+                .header("x-ratelimit-remaining", "0")
+                .header("x-ratelimit-reset", "4102444800")),
+        ]).await;
+        let readme = page("https://github.com/sm13/rePocket").unwrap();
+
+        assert!(matches!(sources(&base).fetch(&readme).await, Err(ArticleError::GitHub(_))));
+        assert!(matches!(sources(&base).fetch(&readme).await, Err(ArticleError::GitHub(_))));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        LIMITED_UNTIL.store(0, Ordering::Relaxed);
+    }
+}
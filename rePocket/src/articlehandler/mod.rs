@@ -15,6 +15,7 @@
 //
 
 use std::{
+    sync::{Arc, RwLock},
     error,
     fmt,
     include_str,
@@ -24,9 +25,8 @@ use std::{
     io::Write,
 };
 use url::Url;
-use ammonia;
-use regex::Regex;
 use uuid::Uuid;
+use chrono::{DateTime, Local, Utc};
 use reqwest::StatusCode;
 use readable_readability;
 use readability;
@@ -35,19 +35,256 @@ use epub_builder::{
     ZipLibrary,
 };
 
+mod cleanup;
+mod docs;
+mod favicon;
+mod github;
+mod newsletter;
+mod precheck;
+mod trace;
+
+pub use cleanup::Sanitization;
+pub use precheck::{precheck, SizeLimits, Verdict};
+pub use trace::{Engine, ExtractionTrace};
+
 use crate::pocketitem::PocketItem;
+use crate::text::{self, PlainText, HtmlText};
+use crate::budget;
+use crate::screen;
+use crate::cookies;
+use crate::dns;
+use crate::clock;
+use crate::compat;
+use crate::crashlog;
+use crate::logging;
+use crate::device::DeviceKind;
+use crate::settings::Settings;
+use crate::strings;
 use crate::utils;
 
 static APP_USER_AGENT: &str = "rePocket/v0.3.0";
+// What the settings say to keep of the HTML, see configure().
+static SANITIZATION: once_cell::sync::Lazy<RwLock<Sanitization>> = once_cell::sync::Lazy::new(Default::default);
+// Dark enough to read on the Paper Pro's muted colors, and as dark a grey on the older screens.
+const ACCENT_STYLE: &str = "<style>header { border-bottom: 0.3em solid #1d5c96; } header h1, a { color: #1d5c96; }</style>";
+// Even an article with one line of text makes for a couple of KB of epub.
+const MIN_EPUB_SIZE: usize = 1024;
+// Images kept by Downgrade::SmallImages.
+const SMALL_IMAGE_SIZE: usize = 200 * 1024;
+
+#[cfg(test)]
+thread_local! {
+    pub static BROKEN_ZIP: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
 
 
 #[derive(Debug)]
-enum Error {
+pub enum ArticleError {
     IO(std::io::Error),
     Reqwest(reqwest::Error),
     HeaderToStr(reqwest::header::ToStrError),
     Readability(readability::error::Error),
     Tokio(tokio::task::JoinError),
+    Unreadable(StatusCode),
+    Epub(String),
+    GitHub(String),
+    // An image without a proper Content-Type.
+    Image(String),
+}
+
+
+// How much of an article to fetch, images are the first thing to go on a tight data budget.
+#[derive(Clone, Copy, Debug)]
+pub struct ArticleOptions {
+    pub images: bool,
+    // The site's icon on the first page, see the favicon module.
+    pub favicons: bool,
+    pub limits: EpubLimits,
+    pub colors: ColorPolicy,
+}
+
+
+impl Default for ArticleOptions {
+    fn default() -> Self {
+        Self {
+            images: true,
+            favicons: false,
+            limits: EpubLimits::default(),
+            colors: ColorPolicy::default(),
+        }
+    }
+}
+
+
+//
+// Whether the documents are for a color screen, the reMarkable Paper Pro's, or a greyscale one.
+// Images go in as they come either way, there's nothing on board to convert them with, so it's
+// the style that changes: an accent color for the header, which is also what the thumbnail shows,
+// and the links.
+//
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorPolicy {
+    Color,
+    #[default]
+    Greyscale,
+}
+
+
+impl ColorPolicy {
+    pub fn for_device(kind: DeviceKind) -> Self {
+        match kind.has_color() {
+            true => ColorPolicy::Color,
+            false => ColorPolicy::Greyscale,
+        }
+    }
+
+
+    fn style(self) -> &'static str {
+        match self {
+            ColorPolicy::Color => ACCENT_STYLE,
+            ColorPolicy::Greyscale => "",
+        }
+    }
+}
+
+
+//
+// xochitl chokes on epubs of ~50 MB, or with thousands of images. Over the soft limits the cover
+// page says so, over the hard ones the epub is made again with fewer images, see Downgrade, and
+// if even that doesn't do it the article fails.
+//
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EpubLimits {
+    pub soft_bytes: usize,
+    pub hard_bytes: usize,
+    pub soft_images: usize,
+    pub hard_images: usize,
+}
+
+
+impl Default for EpubLimits {
+    fn default() -> Self {
+        Self {
+            soft_bytes: 30 * 1024 * 1024,
+            hard_bytes: 50 * 1024 * 1024,
+            soft_images: 300,
+            hard_images: 1000,
+        }
+    }
+}
+
+
+// The steps down from every image there is, tried in order. There's nothing on board to scale
+// images down with, so the large ones go first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Downgrade {
+    Full,
+    SmallImages,
+    CoverOnly,
+    NoImages,
+}
+
+
+impl Downgrade {
+    const LADDER: [Downgrade; 4] = [Downgrade::Full, Downgrade::SmallImages, Downgrade::CoverOnly, Downgrade::NoImages];
+
+
+    // Whether the image makes it into the epub, the cover being the first one.
+    fn keeps(self, index: usize, bytes: usize) -> bool {
+        match self {
+            Downgrade::Full => true,
+            Downgrade::SmallImages => bytes <= SMALL_IMAGE_SIZE,
+            Downgrade::CoverOnly => index == 0,
+            Downgrade::NoImages => false,
+        }
+    }
+}
+
+
+impl fmt::Display for Downgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Downgrade::Full => write!(f, "every image"),
+            Downgrade::SmallImages => write!(f, "images over {} KB left out", SMALL_IMAGE_SIZE / 1024),
+            Downgrade::CoverOnly => write!(f, "only the cover image"),
+            Downgrade::NoImages => write!(f, "no images"),
+        }
+    }
+}
+
+
+// How big the epub came out, and what it took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpubSize {
+    pub bytes: usize,
+    pub images: usize,
+    pub downgrade: Downgrade,
+    // Why it was made with fewer images, if it was.
+    pub reason: Option<String>,
+}
+
+
+// Called with the settings before fetching anything.
+pub fn configure(settings: &Settings) {
+    cookies::configure(settings.cookies());
+    github::configure(settings.github_token());
+    *SANITIZATION.write().unwrap() = settings.sanitization().clone();
+    strings::configure(settings.locale());
+}
+
+
+// What's written in place of an item that couldn't be, with the URL to go and read it elsewhere.
+pub fn placeholder_epub(url: &str, reason: &str) -> Result<Vec<u8>, ArticleError> {
+    let strings = strings::current();
+    let reason = strings::fill(strings.failed_reason, &[("reason", PlainText::new(reason).to_html().as_str())]);
+    let url = PlainText::new(url).to_html();
+    let body = format!("<p><a href=\"{0}\">{0}</a></p><p>{1}</p>", url.as_str(), reason.replace('\n', "<br/>"));
+
+    note_epub(&PlainText::new(strings.failed_title), &HtmlText::trusted(&body))
+}
+
+
+// A document of rePocket's own rather than an article, e.g. a report.
+pub fn note_epub(title: &PlainText, body: &HtmlText) -> Result<Vec<u8>, ArticleError> {
+    chapters_epub(title, &[(title.clone(), body.clone())])
+}
+
+
+// The same, in chapters, each with its own title, for xochitl's table of contents.
+pub fn chapters_epub(title: &PlainText, chapters: &[(PlainText, HtmlText)]) -> Result<Vec<u8>, ArticleError> {
+    let zip = ZipLibrary::new().map_err(ArticleError::epub)?;
+    let mut builder = EpubBuilder::new(zip).map_err(ArticleError::epub)?;
+
+    builder.metadata("title", title.as_str()).map_err(ArticleError::epub)?;
+    builder.metadata("author", "rePocket").map_err(ArticleError::epub)?;
+    builder.epub_version(epub_builder::EpubVersion::V30);
+
+    let written = PlainText::new(&strings::current().written(&clock::now_local()));
+
+    for (i, (chapter, body)) in chapters.iter().enumerate() {
+        let html = include_str!("../../data/template.html")
+            .replace("{{dir}}", text::direction(chapter.as_str()))
+            .replace("{{style}}", ColorPolicy::Greyscale.style())
+            .replace("{{page_title}}", title.to_html().as_str())
+            .replace("{{article_title}}", chapter.to_html().as_str())
+            .replace("{{header}}", written.to_html().as_str())
+            .replace("{{content}}", body.as_str())
+            .replace("{{canonical}}", "");
+        let fname = match i {
+            0 => "note.xhtml".to_string(),
+            _ => format!("note-{i}.xhtml"),
+        };
+
+        builder.add_content(epub_builder::EpubContent::new(fname, html.as_bytes())
+            .title(chapter.as_str())
+            .reftype(epub_builder::ReferenceType::Text)).map_err(ArticleError::epub)?;
+    }
+
+    let mut epub: Vec<u8> = vec!();
+    builder.generate(&mut epub).map_err(ArticleError::epub)?;
+    ArticleHandler::verify_epub(&epub)?;
+
+    Ok(epub)
 }
 
 
@@ -57,173 +294,389 @@ pub struct ArticleHandler<'a> {
     url: String,
     is_pdf: bool,
     uuid: Uuid,
-    page_title: String,
-    article_title: String,
-    author: String,
-    header: String,
-    description: String,
+    page_title: PlainText,
+    article_title: PlainText,
+    author: PlainText,
+    header: HtmlText,
+    description: PlainText,
     content: Vec<u8>,
     canonical: Option<String>,
     images: HashMap<String, String>,
+    favicon: Option<favicon::Favicon>,
+    // Documentation, taken as it is, see the docs module.
+    docs: Option<docs::Site>,
+    options: ArticleOptions,
+    size: Option<EpubSize>,
+    minutes: Option<u64>,
+    lang: Option<String>,
+    trace: ExtractionTrace,
 }
 
 
 impl<'a> ArticleHandler<'a> {
     pub fn new(item: &'a PocketItem) -> Self {
-        let url = item.get_resolved_url().expect("🚨 No URL found");
+        // Empty for an item without any, which is never fetched, see FSHandler::new_article().
+        let url = item.best_url().unwrap_or_default();
 
         Self {
             item: item,
             url: url.to_string(),
             is_pdf: false,
             uuid :Uuid::new_v5(&Uuid::NAMESPACE_OID, url.as_bytes()),
-            page_title: String::new(),
-            article_title: String::new(),
-            author: String::new(),
-            header: String::new(),
-            description: String::new(),
+            page_title: PlainText::default(),
+            article_title: PlainText::default(),
+            // Pocket's, when it has any, rather than whatever the page says.
+            author: PlainText::new(&item.get_authors().join(", ")),
+            header: HtmlText::default(),
+            description: PlainText::default(),
             content: Vec::<u8>::new(),
             canonical: None,
             images: Self::image_list(item),
+            favicon: None,
+            docs: None,
+            options: ArticleOptions::default(),
+            size: None,
+            minutes: None,
+            lang: item.get_lang(),
+            trace: ExtractionTrace::default(),
         }
     }
 
+    pub fn set_options(mut self, options: ArticleOptions) -> Self {
+        self.options = options;
+
+        self
+    }
+
+    // The URL's own unless told otherwise, see FSHandler::document_uuid().
+    pub fn set_uuid(mut self, uuid: Uuid) -> Self {
+        self.uuid = uuid;
+
+        self
+    }
+
+    // The title as it should be shown by xochitl.
     pub fn title(&self) -> String {
-        self.article_title.clone()
+        self.article_title.visible_name()
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
     }
 
     pub fn uuid(&self) -> Uuid {
         self.uuid
     }
 
+    // Pocket's, or what the page says once fetched when Pocket had none.
+    pub fn lang(&self) -> Option<String> {
+        self.lang.clone()
+    }
+
     pub fn uuid_string(&self) -> String {
         utils::uuid_to_string(self.uuid)
     }
 
+    // Set once save_file() wrote an epub.
+    pub fn size(&self) -> Option<&EpubSize> {
+        self.size.as_ref()
+    }
+
+    // As far as the article got, see ExtractionTrace.
+    pub fn trace(&self) -> &ExtractionTrace {
+        &self.trace
+    }
+
+    // xochitl's own for anything but documentation, see docs::TEXT_SCALE.
+    pub fn text_scale(&self) -> Option<f64> {
+        self.docs.map(|_| docs::TEXT_SCALE)
+    }
+
+    // Set once there's an article to read, not for PDFs.
+    pub fn minutes(&self) -> Option<u64> {
+        self.minutes
+    }
+
+    // Whether images were left out, to save data or for the epub's sake.
+    pub fn images_omitted(&self) -> bool {
+        (!self.options.images && self.item.has_image()) || self.size.as_ref().is_some_and(|size| size.downgrade != Downgrade::Full)
+    }
+
     pub async fn get_readable(&mut self) -> Result<Vec<u8>, (Vec<u8>, StatusCode)> {
-        // Looks like to get responses from some servers it is necessary to include the user_agent()
-        let client = reqwest::Client::builder()
-            .user_agent(APP_USER_AGENT)
-            .timeout(Duration::new(30, 0))
-            .build();
+        match self.extract().await {
+            Ok(article) => Ok(article),
+            Err(e) => {
+                self.trace.error = Some(e.to_string());
+                Err(Self::error_html(self.item, e))
+            },
+        }
+    }
 
-        let p = &self.item;
 
-        let body = client.expect("🚨 Cannot open reqwest client")
+    async fn extract(&mut self) -> Result<Vec<u8>, ArticleError> {
+        crashlog::stage("extracting");
+
+        if let Some(page) = github::page(&self.url) {
+            match github::Sources::default().fetch(&page).await {
+                Ok(rendered) => return Ok(self.github_article(rendered).await),
+                Err(e) => {
+                    println!("🚨 Could not get {} from GitHub, extracting it as any other page: {e}", self.url);
+                    self.trace.fallback(Engine::Github, &e.to_string());
+                },
+            }
+        }
+
+        let body = Self::client()?
             .get(self.url.clone())
             .send()
-            .await
-            .map_err(move |e| { Self::error_html(p, Error::Reqwest(e)) })?;
+            .await?;
 
         // Check the response for content-type, and treat PDF differently.
         if body.headers()["content-type"] == "application/pdf" {
-            let body = body.bytes()
-                .await
-                .map_err(move |e| { Self::error_html(p, Error::Reqwest(e)) })?;
+            let body = body.bytes().await?;
 
+            budget::record(body.len());
             self.is_pdf = true;
             self.content = body.to_vec();
+            self.trace.candidate(Engine::Pdf, body.len());
+            self.trace.engine = Some(Engine::Pdf);
 
-            Ok(body.to_vec())
+            return Ok(body.to_vec());
+        }
 
-        } else {
-            // TODO:
-            // 1) Make this a function?
-            // 2) Can I move all the map_err to a single location? This is waaay toooo loooong
-            let body = body.text()
-            .await
-            .map_err(move |e| { Self::error_html(p, Error::Reqwest(e)) })?;
+        let body = body.text().await?;
 
-            let url = Url::parse(&self.url).unwrap();
-            let (content, meta) = readable_readability::Readability::new().base_url(Some(url.clone())).parse(&body);
-            let mut content_bytes = vec![];
+        budget::record(body.len());
 
-            content.serialize(&mut content_bytes)
-                .map_err(move |e| { Self::error_html(p, Error::IO(e)) })?;
+        if self.lang.is_none() {
+            self.lang = screen::document_language(&body);
+        }
 
-            self.content = content_bytes;
+        let url = Url::parse(&self.url).unwrap();
 
-            self.header = format!(
-                "A rePocket-able version of <a class=\"shortened\" href=\"{url}\">{url}</a><br />Retrieved on {}",
-                Self::now_string()
-            );
+        if self.options.favicons {
+            self.favicon = favicon::fetch(&url, &body, &favicon::Cache::new(favicon::cache_dir())).await;
+        }
 
-            // If some fields are missing fill them with some defaults.
-            self.author = meta.byline.unwrap_or_else(|| "Unknown".into());
-            self.page_title = Self::encode_text(&meta.page_title.unwrap_or_else(|| "Page".into()));
-            self.article_title = Self::encode_text(&meta.article_title.unwrap_or_else(|| "Article".into()));
-            self.description = Self::encode_text(&meta.description.unwrap_or_else(|| "Description".into()));
-            self.canonical = Some(url.to_string());
-
-            // Some websites appear empty or very short using readable::readability.
-            // Thus, also obtain them with readability::extractor to choose the best one.
-            // What "best" means is open to interpretation, for the time being, longer is better.
-            let blocking_url = url.clone();
-
-            let body = tokio::task::spawn_blocking(move || {
-                match readability::extractor::scrape(&blocking_url.to_string()) {
-                    Ok(body) => body,
-                    Err(e)   => readability::extractor::Product {
-                        title: "readability::extractor didn't work".to_string(),
-                        content: format!("<p>readability::extractor didn't work: {e}</p>"),
-                        text: format!("readability::extractor didn't work: {e}"),
-                    },
-                }
-            }).await
-            .map_err(move |e| { Self::error_html(p, Error::Tokio(e)) })?;
+        let newsletter = newsletter::detect(&url, &body).map(|platform| newsletter::prepare(platform, &body));
+        let page = newsletter.as_ref().map_or(body.as_str(), |newsletter| newsletter.html.as_str());
+        // The readabilities are only asked for the title and such of documentation.
+        let docs = docs::detect(&url, &body)
+            .filter(|_| newsletter.is_none())
+            .and_then(|site| docs::prepare(site, &body));
+        let (content, meta) = readable_readability::Readability::new().base_url(Some(url.clone())).parse(page);
+        let mut content_bytes = vec![];
+
+        content.serialize(&mut content_bytes)?;
 
-            if body.content.len() > self.content.len() {
-                println!("ℹ Modifying content from readable's readability to readability's extractor");
-                self.content = body.content.into();
+        self.content = content_bytes;
+        self.trace.candidate(Engine::ReadableReadability, self.content.len());
+        self.trace.engine = Some(Engine::ReadableReadability);
+
+        self.header = Self::retrieved_header(&url, self.item.get_time_added());
+
+        // If some fields are missing fill them with some defaults.
+        if self.author.as_str().is_empty() {
+            self.author = PlainText::new(&meta.byline.unwrap_or_else(|| "Unknown".into()));
+        }
+        self.page_title = PlainText::new(&meta.page_title.unwrap_or_else(|| "Page".into()));
+        self.article_title = PlainText::new(&meta.article_title.unwrap_or_else(|| "Article".into()));
+        self.description = PlainText::new(&meta.description.unwrap_or_else(|| "Description".into()));
+        self.canonical = Some(url.to_string());
+
+        // Newsletters were prepared for extraction, which the extractor would undo by fetching the
+        // page again.
+        if let Some(newsletter) = newsletter {
+            println!("ℹ Extracting {} as a {:?} newsletter", self.url, newsletter.platform);
+
+            if let Some(subtitle) = newsletter.subtitle {
+                self.description = PlainText::new(&subtitle);
             }
+            if let Some(publication) = newsletter.publication {
+                let publication = PlainText::new(&publication).to_html();
+                self.header = HtmlText::trusted(&format!("<strong>{}</strong><br />{}", publication.as_str(), self.header.as_str()));
+            }
+            self.trace.fallback(Engine::Extractor, "not used for newsletters");
+
+            return Ok(self.finish().await);
+        }
+
+        if let Some(docs) = docs {
+            println!("ℹ Extracting {} as {} documentation", self.url, docs.site);
+
+            self.content = docs.html.into_bytes();
+            self.docs = Some(docs.site);
+            self.trace.candidate(Engine::Docs, self.content.len());
+            self.trace.engine = Some(Engine::Docs);
+            self.trace.fallback(Engine::Extractor, "not used for documentation");
+
+            return Ok(self.finish().await);
+        }
+
+        // Some websites appear empty or very short using readable::readability.
+        // Thus, also obtain them with readability::extractor to choose the best one.
+        // What "best" means is open to interpretation, for the time being, longer is better.
+        // The extractor is given the page fetched above, already counted, rather than scraping it
+        // again.
+        let scraped = readability::extractor::extract(&mut body.as_bytes(), &url);
+
+        let body = match scraped {
+            Ok(body) => {
+                self.trace.candidate(Engine::Extractor, body.content.len());
+                body
+            },
+            Err(e) => {
+                self.trace.fallback(Engine::Extractor, &e.to_string());
+                readability::extractor::Product {
+                    title: "readability::extractor didn't work".to_string(),
+                    content: format!("<p>readability::extractor didn't work: {e}</p>"),
+                    text: format!("readability::extractor didn't work: {e}"),
+                }
+            },
+        };
+
+        if body.content.len() > self.content.len() {
+            println!("ℹ Modifying content from readable's readability to readability's extractor");
+            self.trace.fallback(Engine::ReadableReadability,
+                &format!("{} chars, fewer than readability::extractor's {}", self.content.len(), body.content.len()));
+            self.trace.engine = Some(Engine::Extractor);
+            self.content = body.content.into();
+        }
+
+        Ok(self.finish().await)
+    }
+
+
+    // What's common to every article, however its content was found.
+    async fn finish(&mut self) -> Vec<u8> {
+        crashlog::stage("listing images");
+
+        if logging::debug() {
+            println!("🪼 Extracted {} with {:?}, {} byte(s)", self.url, self.trace.engine, self.content.len());
+        }
+
+        let mut unreachable = Vec::new();
+        match self.options.images {
+            true => match self.image_list_all().await {
+                Ok(left_out) => unreachable = left_out,
+                Err(e) => self.trace.fallback(Engine::Images, &e.to_string()),
+            },
+            false => self.trace.fallback(Engine::Images, "left out to save data"),
+        }
+        crashlog::stage("cleaning up");
+        self.trace.sanitization = Some(format!("{:?}", SANITIZATION.read().unwrap().preset).to_lowercase());
+        self.content = self.cleanup_html(std::mem::take(&mut self.content));
+        // Only the cleaned up tags are sure to be closed, for drop_images() to find them.
+        if !unreachable.is_empty() {
+            let urls: Vec<&str> = unreachable.iter().map(String::as_str).collect();
+            self.content = cleanup::drop_images(&String::from_utf8_lossy(&self.content), &urls).into_bytes();
+        }
+
+        let minutes = text::reading_minutes(&cleanup::text_content(std::str::from_utf8(&self.content).unwrap()));
+        self.minutes = Some(minutes as u64);
+        let reading_time = PlainText::new(&strings::current().reading_time(minutes)).to_html();
+        self.header = HtmlText::trusted(&format!("{}<br />{}", self.header.as_str(), reading_time.as_str()));
+
+        if let Some(favicon) = &self.favicon {
+            let icon = format!("<img class=\"favicon\" src=\"{}\" alt=\"\" width=\"24\" height=\"24\" />", favicon.file_name());
+            self.header = HtmlText::trusted(&format!("{icon} {}", self.header.as_str()));
+        }
+
+        self.html()
+    }
+
+
+    async fn github_article(&mut self, rendered: github::Rendered) -> Vec<u8> {
+        let url = Url::parse(&self.url).unwrap();
+
+        self.header = Self::retrieved_header(&url, self.item.get_time_added());
+        self.author = PlainText::new(&rendered.author);
+        self.page_title = PlainText::new(&rendered.title);
+        self.article_title = PlainText::new(&rendered.title);
+        self.description = PlainText::new(&rendered.title);
+        self.canonical = Some(url.to_string());
+        self.content = rendered.html.into_bytes();
+
+        self.finish().await
+    }
 
-            let _ = self.image_list_all().await;
-            self.content = self.cleanup_html(&self.content.clone());
 
-            Ok(self.html())
+    // When it was saved to Pocket too, if Pocket says.
+    fn retrieved_header(url: &Url, added: Option<DateTime<Utc>>) -> HtmlText {
+        let href = PlainText::new(url.as_str()).to_html();
+
+        let link = format!("<a class=\"shortened\" href=\"{href}\">{href}</a>");
+        let mut header = strings::current().retrieved(&link, &clock::now_local());
+        if let Some(added) = added {
+            header = format!("{header}<br />{}", strings::current().saved(&added.with_timezone(&Local)));
         }
+
+        HtmlText::trusted(&header)
     }
 
 
-    async fn get_image(url: &str) -> Result<(Vec<u8>, String), Error> {
-        let client = reqwest::Client::builder()
+    // Every request for an article goes through one of these, with the cookies from the settings,
+    // resolving through the DNS cache.
+    fn client() -> Result<reqwest::Client, reqwest::Error> {
+        // Looks like to get responses from some servers it is necessary to include the user_agent()
+        reqwest::Client::builder()
             .user_agent(APP_USER_AGENT)
             .timeout(Duration::new(30, 0))
-            .build();
+            .cookie_provider(cookies::jar())
+            .dns_resolver(Arc::new(dns::cache()))
+            .build()
+    }
+
 
-        let body = client.expect("🚨 Cannot open reqwest client")
+    async fn get_image(url: &str) -> Result<(Vec<u8>, String), ArticleError> {
+        let body = Self::client()?
             .get(url)
             .send()
             .await
-            .map_err(|e| { Error::Reqwest(e) })?;
+            .and_then(|body| body.error_for_status())
+            .map_err(|e| { ArticleError::Reqwest(e) })?;
 
-        let mime_type = body.headers()["content-type"].to_str()?.to_string();
+        let mime_type = Self::content_type(&body)?;
 
         let body = body.bytes()
             .await
-            .map_err(|e| { Error::Reqwest(e) })?;
+            .map_err(|e| { ArticleError::Reqwest(e) })?;
+
+        budget::record(body.len());
 
         Ok((body.to_vec(), mime_type))
     }
 
 
 
-    fn error_html(item: &'a PocketItem, e: Error) -> (Vec<u8>, StatusCode) {
+    // Some servers leave the Content-Type out, which makes it no image of any use.
+    fn content_type(response: &reqwest::Response) -> Result<String, ArticleError> {
+        match response.headers().get(reqwest::header::CONTENT_TYPE) {
+            Some(mime_type) => Ok(mime_type.to_str()?.to_string()),
+            None => Err(ArticleError::Image(format!("no Content-Type for {}", response.url()))),
+        }
+    }
+
+
+
+    fn error_html(item: &'a PocketItem, e: ArticleError) -> (Vec<u8>, StatusCode) {
         // TODO: turn these lines into a little function
+        let strings = strings::current();
         let mut handle = Self::new(item);
-        handle.page_title = "rePocket Failed!".to_string();
-        handle.article_title = "".to_string();
-        handle.header = "Could not get the article contents".to_string();
-        handle.content = format!("Could not get the article contents. Reason:\n{e}").into();
+        handle.page_title = PlainText::new(strings.failed_title);
+        handle.article_title = PlainText::default();
+        handle.header = PlainText::new(strings.failed_header).to_html();
+        handle.content = strings::fill(strings.failed_reason, &[("reason", &e.to_string())]).into();
         handle.canonical = None;
 
         (handle.html(), StatusCode::BAD_REQUEST)
     }
 
 
-    pub async fn save_file(&mut self, file_type: &str, path: &str) {
-        // TODO: This should probably return a -> Result<(), Error>
-        let res = self.get_readable().await;
+    // Returns the name of the file written. Nothing is written unless the whole document is ready.
+    pub async fn save_file(&mut self, file_type: &str, path: &str) -> Result<String, ArticleError> {
+        let article = self.get_readable().await
+            .map_err(|(_, status)| ArticleError::Unreadable(status))?;
 
         let mut ftype = match file_type {
             "epub" | "pdf" | "html" => file_type,
@@ -235,81 +688,281 @@ impl<'a> ArticleHandler<'a> {
             ftype = "pdf";
         }
 
-        match res {
-            Ok(article) => {
-                match File::create(format!("{}/{}.{}", path, self.uuid, ftype)) {
-                    Ok(mut fh) => {
-                        match ftype {
-                            "epub" => {
-                                let _ = fh.write_all(&self.epub().await);
-                            },
-                            "html" => {
-                                let _ = fh.write_all(&article);
-                            },
-                            "pdf" => {
-                                let _ = fh.write_all(&self.content);
-                            }
-                            _ => {
-                                println!("ℹ Not saving file! Only \"pdf\", \"html\" and \"epub\" supported");
-                            },
-                        }
-                    },
-                    Err(err) => println!("🚨 Error creating file! {:?}", err),
+        let data = match ftype {
+            "epub" => match self.epub().await {
+                Ok(epub) => epub,
+                Err(e) => {
+                    self.trace.error = Some(e.to_string());
+                    return Err(e);
+                },
+            },
+            "html" => article,
+            _ => self.content.clone(),
+        };
+
+        let fname = format!("{}/{}.{}", path, self.uuid, ftype);
+
+        if let Err(e) = File::create(&fname).and_then(|mut fh| fh.write_all(&data)) {
+            let _ = std::fs::remove_file(&fname);
+            return Err(ArticleError::IO(e));
+        }
+
+        Ok(fname)
+    }
+
+
+    //
+    // Makes the epub with every image, and again with fewer of them for as long as it's over the
+    // hard limits, at most once per step of Downgrade::LADDER. The images are only fetched once.
+    //
+    async fn epub(&mut self) -> Result<Vec<u8>, ArticleError> {
+        crashlog::stage("making the epub");
+
+        // Pocket's pick, when it isn't one of the article's, is fetched on its own for the cover.
+        let top = self.item.get_top_image_url().filter(|url| self.options.images && !self.images.contains_key(url));
+        let mut images = self.fetch_images().await;
+        if let Some(url) = top {
+            self.fetch_cover(&url, &mut images).await;
+        }
+        self.trace.images_downloaded = images.len();
+        // The cover goes first, build_epub and Downgrade::CoverOnly take the first one kept.
+        if let Some(cover) = Self::cover(&self.item.get_cover_urls(), &self.images, &images) {
+            images[..=cover].rotate_right(1);
+        }
+        let limits = self.options.limits;
+        let header = self.header.clone();
+
+        let mut tried = None;
+
+        for downgrade in Downgrade::LADDER {
+            let kept: Vec<usize> = (0..images.len())
+                .filter(|index| downgrade.keeps(*index, images[*index].1.len()))
+                .collect();
+
+            // The same images as the step before, which was already too much.
+            if downgrade != Downgrade::NoImages && tried == Some(kept.len()) {
+                continue;
+            }
+            tried = Some(kept.len());
+
+            let mut epub = self.build_epub(&images, &kept)?;
+
+            let over_hard = epub.len() > limits.hard_bytes || kept.len() > limits.hard_images;
+            if over_hard && downgrade != Downgrade::NoImages {
+                continue;
+            }
+            if over_hard {
+                return Err(ArticleError::Epub(format!(
+                    "epub too large even with no images, {} over the {} limit",
+                    budget::human(epub.len() as u64),
+                    budget::human(limits.hard_bytes as u64),
+                )));
+            }
+
+            let reason = match downgrade {
+                Downgrade::Full => None,
+                _ => Some(format!(
+                    "{} with {} images is over the limits, made with {downgrade}",
+                    budget::human(Self::full_size(&images)),
+                    images.len(),
+                )),
+            };
+            let over_soft = epub.len() > limits.soft_bytes || kept.len() > limits.soft_images;
+
+            // Said on the cover page, which takes making it once more.
+            if over_soft || reason.is_some() {
+                let strings = strings::current();
+                let note = match &reason {
+                    Some(reason) => strings::fill(strings.large_article, &[("reason", reason)]),
+                    None => strings::fill(strings.slow_to_open, &[
+                        ("size", &budget::human(epub.len() as u64)),
+                        ("images", &kept.len().to_string()),
+                    ]),
+                };
+                println!("🚨 {}: {note}", self.url);
+
+                self.header = HtmlText::trusted(&format!("{}<br />{}", header.as_str(), PlainText::new(&note).to_html()));
+                epub = self.build_epub(&images, &kept)?;
+                self.header = header;
+            }
+
+            if let Some(reason) = &reason {
+                self.trace.fallback(Engine::Images, reason);
+            }
+            self.trace.images_dropped = images.len() - kept.len();
+            self.size = Some(EpubSize { bytes: epub.len(), images: kept.len(), downgrade, reason });
+
+            return Ok(epub);
+        }
+
+        unreachable!("Downgrade::LADDER ends with NoImages")
+    }
+
+
+    // What every image there is would have taken, for the logs.
+    fn full_size(images: &[(String, Vec<u8>, String)]) -> u64 {
+        images.iter().map(|(_, bin, _)| bin.len() as u64).sum()
+    }
+
+
+    // The local name, bytes and mime type of every image, in the order they appear in the article.
+    // An image that can't be had is left out, its tag and all, rather than the article. A remote
+    // link would be of no use to xochitl offline.
+    async fn fetch_images(&mut self) -> Vec<(String, Vec<u8>, String)> {
+        let content = String::from_utf8_lossy(&self.content).into_owned();
+        let mut images: Vec<(String, String)> = self.images.iter().map(|(url, loc)| (url.clone(), loc.clone())).collect();
+        images.sort_by_key(|(_, loc)| content.find(loc.as_str()).unwrap_or(usize::MAX));
+
+        let mut fetched = Vec::new();
+
+        for (url, loc) in images {
+            match Self::get_image(&url).await {
+                Ok((bin, mime_type)) => fetched.push((loc, bin, mime_type)),
+                Err(e) => {
+                    println!("🚨 Could not get an image in {}, leaving it out: {e}", self.url);
+                    println!("  ..{url}");
+                    self.images.remove(&url);
+                    self.content = cleanup::drop_images(&String::from_utf8_lossy(&self.content), &[loc.as_str()]).into_bytes();
                 }
+            }
+        }
+
+        fetched
+    }
+
+
+    // An image for the cover only, listed with the others so cover() finds it. Going without is
+    // no reason to leave out the article.
+    async fn fetch_cover(&mut self, url: &str, images: &mut Vec<(String, Vec<u8>, String)>) {
+        match Self::get_image(url).await {
+            Ok((bin, mime_type)) => {
+                let ext = mime_type.rsplit_once('/').map_or("img", |(_, ext)| ext).to_string();
+                let uuid = utils::uuid_to_string(Uuid::new_v5(&Uuid::NAMESPACE_OID, url.as_bytes()));
+                let loc = format!("p{uuid}.{ext}");
+
+                self.images.insert(url.to_string(), loc.clone());
+                images.push((loc, bin, mime_type));
+            },
+            Err(e) => {
+                println!("🚨 Could not get the cover for {}, going without: {e}", self.url);
+                println!("  ..{url}");
             },
-            Err(err) => println!("🚨 Error getting readable {:?}", err),
         }
     }
 
 
-    async fn epub(&self) -> Vec<u8> {
-        let mut builder = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+    // Which of the fetched images makes the cover: the first of Pocket's picks that was fetched,
+    // or else the largest one, the local name breaking ties so the same article gets the same
+    // cover every time.
+    fn cover(urls: &[String], names: &HashMap<String, String>, images: &[(String, Vec<u8>, String)]) -> Option<usize> {
+        let position = |loc: &String| images.iter().position(|(fetched, _, _)| fetched == loc);
+
+        urls.iter()
+            .filter_map(|url| names.get(url))
+            .find_map(position)
+            .or_else(|| (0..images.len()).max_by(|a, b| {
+                images[*a].1.len().cmp(&images[*b].1.len()).then_with(|| images[*b].0.cmp(&images[*a].0))
+            }))
+    }
+
+
+    // The epub with the kept images only, the tags for all the others taken out of the text.
+    fn build_epub(&self, images: &[(String, Vec<u8>, String)], kept: &[usize]) -> Result<Vec<u8>, ArticleError> {
+        let dropped: Vec<&str> = images.iter()
+            .enumerate()
+            .filter(|(index, _)| !kept.contains(index))
+            .map(|(_, (loc, _, _))| loc.as_str())
+            .collect();
 
-        builder.metadata("title", format!("{}", self.article_title)).unwrap();
-        builder.metadata("author", format!("{}", self.author)).unwrap();
-        builder.metadata("description", format!("{}", self.description)).unwrap();
+        let mut html = self.html();
+        if !dropped.is_empty() {
+            html = cleanup::drop_images(std::str::from_utf8(&html).unwrap(), &dropped).into_bytes();
+        }
+
+        let zip = ZipLibrary::new().map_err(ArticleError::epub)?;
+        let mut builder = EpubBuilder::new(zip).map_err(ArticleError::epub)?;
+
+        // Plain text on purpose, epub_builder escapes these when writing the OPF.
+        builder.metadata("title", self.article_title.as_str()).map_err(ArticleError::epub)?;
+        builder.metadata("author", self.author.as_str()).map_err(ArticleError::epub)?;
+        builder.metadata("description", self.description.as_str()).map_err(ArticleError::epub)?;
+        if let Some(added) = self.item.get_time_added() {
+            builder.set_publication_date(added);
+        }
+        for tag in self.item.get_tags() {
+            builder.metadata("subject", tag).map_err(ArticleError::epub)?;
+        }
         builder.epub_version(epub_builder::EpubVersion::V30);
-        builder.add_content(epub_builder::EpubContent::new("article.xhtml", self.html().as_slice())
-            .title(format!("{}", self.article_title))
-            .reftype(epub_builder::ReferenceType::Text)).unwrap();
+        // The document's own, for integrity::check() to tell it's the right one.
+        builder.set_uuid(self.uuid);
+        builder.add_content(epub_builder::EpubContent::new("article.xhtml", html.as_slice())
+            .title(self.article_title.as_str())
+            .reftype(epub_builder::ReferenceType::Text)).map_err(ArticleError::epub)?;
+
+        if let Some(favicon) = &self.favicon {
+            builder.add_resource(favicon.file_name(), favicon.bytes.as_slice(), favicon.mime_type).map_err(ArticleError::epub)?;
+        }
 
         // Add images.
         let mut set_cover = true;
-        for (url, loc) in &self.images {
-            let res = Self::get_image(&url).await;
-
-            let (bin, mime_type) = res.expect("Expected bin and mime_type");
-
-            builder.add_resource(&loc, &*bin, mime_type.clone()).unwrap();
+        for (loc, bin, mime_type) in kept.iter().map(|index| &images[*index]) {
+            builder.add_resource(loc, bin.as_slice(), mime_type.clone()).map_err(ArticleError::epub)?;
 
             if set_cover {
                 set_cover = false;
                 // Add cover image
-                builder.add_cover_image(&loc, &*bin, mime_type).unwrap();
+                builder.add_cover_image(loc, bin.as_slice(), mime_type.clone()).map_err(ArticleError::epub)?;
             }
         }
 
 
         let mut epub: Vec<u8> = vec!();
+        builder.generate(&mut epub).map_err(ArticleError::epub)?;
 
-        match builder.generate(&mut epub) {
-            Ok(())    => (),
-            Err(e)  => println!("🚨 Can't build epub: {e}"),
+        // What a ZipLibrary that ran out of room, or of temporary files, sometimes hands back.
+        #[cfg(test)]
+        if BROKEN_ZIP.with(|broken| broken.get()) {
+            epub.clear();
         }
 
-        epub
+        // What xochitl can't take, fixed where it can be, see the compat module.
+        let epub = compat::apply(epub);
+
+        Self::verify_epub(&epub)?;
+
+        Ok(epub)
+    }
+
+
+    // A zip archive, big enough to hold an article, starting with the mimetype entry as every
+    // epub must.
+    fn verify_epub(epub: &[u8]) -> Result<(), ArticleError> {
+        if epub.len() < MIN_EPUB_SIZE {
+            return Err(ArticleError::Epub(format!("epub too small, {} bytes", epub.len())));
+        }
+
+        let name_len = u16::from_le_bytes([epub[26], epub[27]]) as usize;
+
+        if !epub.starts_with(b"PK\x03\x04") || epub.get(30..30 + name_len) != Some(b"mimetype".as_slice()) {
+            return Err(ArticleError::Epub("epub doesn't start with a mimetype entry".to_string()));
+        }
+
+        Ok(())
     }
 
 
     fn html(&self) -> Vec<u8> {
         let template = include_str!("../../data/template.html");
         let mut output = template
-            .replace("{{page_title}}", &self.page_title)
-            .replace("{{article_title}}", &self.article_title)
-            .replace("{{header}}", &self.header)
-            .replace("{{content}}", &String::from_utf8(self.content.clone()).unwrap());
+            .replace("{{dir}}", text::direction(self.article_title.as_str()))
+            .replace("{{style}}", &format!("{}{}", self.options.colors.style(), self.docs.map_or("", |_| docs::STYLE)))
+            .replace("{{page_title}}", self.page_title.to_html().as_str())
+            .replace("{{article_title}}", self.article_title.to_html().as_str())
+            .replace("{{header}}", self.header.as_str())
+            .replace("{{content}}", std::str::from_utf8(&self.content).unwrap());
 
         if let Some(canonical) = &self.canonical {
+            let canonical = PlainText::new(canonical).to_html();
             output = output.replace(
                 "{{canonical}}",
                 &format!("<link rel=\"canonical\" href=\"{canonical}\" />"),
@@ -322,87 +975,49 @@ impl<'a> ArticleHandler<'a> {
     }
 
 
-    // Clean up the HTML to make it more like XHTML. ammonia will do the heavy lifting,
-    // however, it is not a conversion tool. I decided against all other solutions because
-    // they eitehr didn't really work or dpended on an external tool.
-    //
-    // Certain things need a different approach. Unfortunately, for this I'm down to string
-    // substitution and regexes. I know, I know, ...
-    fn cleanup_html(&self, html: &Vec<u8>) -> Vec<u8> {
-        // TODO: When implementing pictures in the epubs, either substitute the photos
-        // for the alt text, or download the photos and add relative links. Either way,
-        // this will probably have to go.
-        let dirty = &String::from_utf8(html.to_vec()).unwrap();
-
-        let output = ammonia::Builder::default()
-            .rm_tags(&["div"])
-            .rm_tag_attributes("img", &["alt"])
-            .clean(dirty)
-            .to_string();
-
-        let re = Regex::new(r"<img(.*?)>").unwrap();
-        let output = re.replace_all(&output, "<img$1 />");
-        let re = Regex::new(r"<map>.*?</map>").unwrap();
-        let output = re.replace_all(&output, "");
-        let re = Regex::new(r"<source(.*?)>").unwrap();
-        let mut output = re.replace_all(&output, "<source$1 />")
-            // Fixes an issue with remarkable not liking the tag, as in make it XTHML
-            .replace("<img />", "")
-            // This is to make XHTML happy
-            .replace("<hr>", "<hr />")
-            // This is also to make XHTML happy
-            .replace("<br>", "<br />");
-
-        // Fix images (or attempt to anyways)
-        for (k, v) in &self.images {
-            output = output.replace(k, v);
+    // Clean up the HTML to make it more like XHTML, see cleanup::cleanup_html(). Whatever isn't
+    // UTF-8 in a page is replaced rather than the end of the article.
+    // Documentation keeps the ids the links in it point at, see docs::ANCHORED.
+    fn cleanup_html(&self, html: Vec<u8>) -> Vec<u8> {
+        let dirty = String::from_utf8_lossy(&html).into_owned();
+        let mut sanitization = SANITIZATION.read().unwrap().clone();
+
+        if self.docs.is_some() {
+            for tag in docs::ANCHORED {
+                sanitization.tag_attributes.entry(tag.to_string()).or_default().push("id".to_string());
+            }
         }
 
-        output.into()
+        cleanup::cleanup_html(dirty, &self.images, self.options.images, &sanitization).into_bytes()
     }
 
 
-    // This function is similar to (I guess) what html_scape::encode_text() does.
-    // In summary, encondes &, >, and < in strings to make them HTML-able.
-    fn encode_text(non_html: &str) -> String {
-        // This is also to make XHTML happy, or remarkable, I don't even know anymore
-        let html = non_html
-            .replace("&", "&amp;")
-            .replace("<", "&lt;")
-            .replace(">", "&gt;");
-
-        html
-    }
-
     // Get image URLs as Pocket identifies them
     fn image_list(_item: &'a PocketItem) -> HashMap<String, String> {
         HashMap::<String, String>::new()
     }
 
 
-    // Get image URLs from the HTML, and save them into our list with **extensions**
-    async fn image_list_all(&mut self) -> Result<(), Error> {
+    // Get image URLs from the HTML, and save them into our list with **extensions**. The ones that
+    // can't be had are handed back, for their tags to be taken out once cleaned up.
+    async fn image_list_all(&mut self) -> Result<Vec<String>, ArticleError> {
         // First find the images in the HTML.
-        let re = Regex::new(r#"<img.*?src="(?<url>.*?)".*?>"#).unwrap();
-        let cont = String::from_utf8(self.content.clone()).unwrap();
-        let imgs = re.captures_iter(&cont);
-
-        let client = reqwest::Client::builder()
-            .user_agent(APP_USER_AGENT)
-            .timeout(Duration::new(30, 0))
-            .build();
-
-        for img in imgs {
-            let url = img["url"].to_string();
-
-            let body = client.as_ref().expect("🚨 Cannot open reqwest client to get Image header")
-                .head(&url)
-                .send()
-                .await
-                .map_err(|e| { Error::Reqwest(e) })?;
-
-            let mime_type = body.headers()["content-type"].to_str()?.to_string();
-            let (_, ext) = mime_type.rsplit_once("/").expect("Expected a proper mime_type");
+        let urls = cleanup::image_urls(&String::from_utf8_lossy(&self.content));
+        self.trace.images_found = urls.len();
+
+        let client = Self::client()?;
+        let mut unreachable = Vec::new();
+
+        for url in urls {
+            let ext = match Self::image_extension(&client, &url).await {
+                Ok(ext) => ext,
+                Err(e) => {
+                    println!("🚨 Could not get an image in {}, leaving it out: {e}", self.url);
+                    println!("  ..{url}");
+                    unreachable.push(url);
+                    continue;
+                }
+            };
 
             let uuid = utils::uuid_to_string(Uuid::new_v5(&Uuid::NAMESPACE_OID, url.as_bytes()));
             let fname = format!("p{}.{}", uuid, ext);
@@ -410,51 +1025,522 @@ impl<'a> ArticleHandler<'a> {
             self.images.insert(url, fname);
         }
 
-        Ok(())
+        Ok(unreachable)
     }
 
 
-    fn now_string() -> String {
-        let now = chrono::Local::now();
-        now.format("%Y.%B.%e, %T").to_string()
+    // The extension for an image, from the Content-Type a HEAD for it comes back with.
+    async fn image_extension(client: &reqwest::Client, url: &str) -> Result<String, ArticleError> {
+        let body = client.head(url).send().await?.error_for_status()?;
+
+        let mime_type = Self::content_type(&body)?;
+        match mime_type.rsplit_once("/") {
+            Some((_, ext)) => Ok(ext.to_string()),
+            None => Err(ArticleError::Image(format!("{mime_type} for {url}"))),
+        }
     }
 }
 
 
-impl fmt::Display for Error {
+impl fmt::Display for ArticleError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     write!(f, "{:?}", self)
   }
 }
 
-impl error::Error for Error { }
+impl error::Error for ArticleError { }
 
-impl From<std::io::Error> for Error {
+impl ArticleError {
+  // epub_builder's errors, whatever their type, only make it as far as the logs.
+  fn epub<E: fmt::Display>(error: E) -> Self {
+    ArticleError::Epub(error.to_string())
+  }
+}
+
+impl From<std::io::Error> for ArticleError {
   fn from(error: std::io::Error) -> Self {
-    Error::IO(error)
+    ArticleError::IO(error)
   }
 }
 
-impl From<reqwest::Error> for Error {
+impl From<reqwest::Error> for ArticleError {
   fn from(error: reqwest::Error) -> Self {
-    Error::Reqwest(error)
+    ArticleError::Reqwest(error)
   }
 }
 
-impl From<reqwest::header::ToStrError> for Error {
+impl From<reqwest::header::ToStrError> for ArticleError {
   fn from(error: reqwest::header::ToStrError) -> Self {
-    Error::HeaderToStr(error)
+    ArticleError::HeaderToStr(error)
   }
 }
 
-impl From<readability::error::Error> for Error {
+impl From<readability::error::Error> for ArticleError {
   fn from(error: readability::error::Error) -> Self {
-    Error::Readability(error)
+    ArticleError::Readability(error)
   }
 }
 
-impl From<tokio::task::JoinError> for Error {
+impl From<tokio::task::JoinError> for ArticleError {
   fn from(error: tokio::task::JoinError) -> Self {
-    Error::Tokio(error)
+    ArticleError::Tokio(error)
   }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mockserver::{self, Reply};
+    use crate::testutil;
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial]
+    async fn count_downloaded_bytes() {
+        let page = b"<html><head><title>Counted</title></head><body><p>Some words to read.</p></body></html>";
+        let pdf = b"%PDF-1.4 not really a PDF, but the right number of bytes";
+        let png = [0u8; 4096];
+
+        let base = mockserver::serve(vec![
+            ("/article", Reply::ok("text/html", page)),
+            ("/paper", Reply::ok("application/pdf", pdf)),
+            ("/image", Reply::ok("image/png", &png)),
+        ]).await;
+
+        // The page is fetched once, for both readabilities.
+        let article = testutil::item(100, &format!("{base}/article"));
+        let before = budget::downloaded();
+        let _ = ArticleHandler::new(&article).get_readable().await;
+        assert_eq!(budget::downloaded() - before, page.len() as u64);
+
+        let paper = testutil::item(100, &format!("{base}/paper"));
+        let before = budget::downloaded();
+        let _ = ArticleHandler::new(&paper).get_readable().await;
+        assert_eq!(budget::downloaded() - before, pdf.len() as u64);
+
+        let before = budget::downloaded();
+        let _ = ArticleHandler::get_image(&format!("{base}/image")).await;
+        assert_eq!(budget::downloaded() - before, png.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn verified_epub() {
+        let item = testutil::item(100, "https://example.com/article");
+        let mut handler = ArticleHandler::new(&item);
+        handler.article_title = PlainText::new("Verified");
+        handler.content = b"<p>Short, but a proper epub all the same.</p>".to_vec();
+
+        let epub = handler.epub().await.unwrap();
+        assert!(ArticleHandler::verify_epub(&epub).is_ok());
+
+        assert!(ArticleHandler::verify_epub(&[]).is_err());
+        assert!(ArticleHandler::verify_epub(&epub[..MIN_EPUB_SIZE - 1]).is_err());
+        assert!(ArticleHandler::verify_epub(&[b'x'; MIN_EPUB_SIZE]).is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn missing_images() {
+        use std::io::Read;
+
+        let png = [b"\x89PNG\r\n\x1a\n".as_slice(), b"an image"].concat();
+        let base = mockserver::serve(vec![
+            ("/good.png", Reply::ok("image/png", &png)),
+            ("/gone.png", Reply::not_found()),
+            ("/untyped.png", Reply::ok("", &png)),
+        ]).await;
+        let (good, gone, untyped) = (format!("{base}/good.png"), format!("{base}/gone.png"), format!("{base}/untyped.png"));
+
+        // Pocket's pick for the cover is the one that's gone.
+        let mut fields = serde_json::to_value(testutil::item(100, "https://example.com/article")).unwrap();
+        fields["top_image_url"] = serde_json::json!(gone);
+        let article: PocketItem = serde_json::from_value(fields).unwrap();
+        let mut handler = ArticleHandler::new(&article);
+        handler.article_title = PlainText::new("Partly illustrated");
+        handler.content = b"<p><img src=\"pgone.png\" /><img src=\"pgood.png\" /><img src=\"puntyped.png\" /></p>".to_vec();
+        handler.images = HashMap::from([
+            (gone.clone(), "pgone.png".to_string()),
+            (good.clone(), "pgood.png".to_string()),
+            (untyped.clone(), "puntyped.png".to_string()),
+        ]);
+
+        let epub = handler.epub().await.unwrap();
+        assert!(ArticleHandler::verify_epub(&epub).is_ok());
+        assert_eq!(handler.trace().images_downloaded, 1);
+        assert_eq!(handler.images.len(), 1);
+
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(epub)).unwrap();
+        let mut embedded = Vec::new();
+        zip.by_name("OEBPS/pgood.png").unwrap().read_to_end(&mut embedded).unwrap();
+        assert_eq!(embedded, png);
+        assert!(zip.by_name("OEBPS/pgone.png").is_err());
+
+        // The cover is the next best, and what couldn't be had is gone, tag and all.
+        let mut opf = String::new();
+        zip.by_name("OEBPS/content.opf").unwrap().read_to_string(&mut opf).unwrap();
+        assert!(opf.lines().any(|line| line.contains("cover-image") && line.contains("pgood.png")), "{opf}");
+        let mut xhtml = String::new();
+        zip.by_name("OEBPS/article.xhtml").unwrap().read_to_string(&mut xhtml).unwrap();
+        assert!(!xhtml.contains("pgone.png") && !xhtml.contains("puntyped.png") && !xhtml.contains(&base), "{xhtml}");
+        assert!(xhtml.contains("src=\"pgood.png\""), "{xhtml}");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn cover_from_elsewhere() {
+        use std::io::Read;
+
+        let png = [b"\x89PNG\r\n\x1a\n".as_slice(), b"an image"].concat();
+        let top = [b"\x89PNG\r\n\x1a\n".as_slice(), b"the cover"].concat();
+        let base = mockserver::serve(vec![
+            ("/good.png", Reply::ok("image/png", &png)),
+            ("/top.png", Reply::ok("image/png", &top)),
+        ]).await;
+        let good = format!("{base}/good.png");
+
+        // Pocket's pick is nowhere in the article.
+        let mut fields = serde_json::to_value(testutil::item(100, "https://example.com/article")).unwrap();
+        fields["top_image_url"] = serde_json::json!(format!("{base}/top.png"));
+        let article: PocketItem = serde_json::from_value(fields).unwrap();
+        let mut handler = ArticleHandler::new(&article);
+        handler.article_title = PlainText::new("Covered");
+        handler.content = b"<p>Text<img src=\"pgood.png\" /></p>".to_vec();
+        handler.images = HashMap::from([(good, "pgood.png".to_string())]);
+
+        let epub = handler.epub().await.unwrap();
+        assert_eq!(handler.trace().images_downloaded, 2);
+        let cover = handler.images[&format!("{base}/top.png")].clone();
+        assert!(cover.starts_with('p') && cover.ends_with(".png"), "{cover}");
+
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(epub)).unwrap();
+        let mut embedded = Vec::new();
+        zip.by_name(&format!("OEBPS/{cover}")).unwrap().read_to_end(&mut embedded).unwrap();
+        assert_eq!(embedded, top);
+        let mut opf = String::new();
+        zip.by_name("OEBPS/content.opf").unwrap().read_to_string(&mut opf).unwrap();
+        assert!(opf.lines().any(|line| line.contains("cover-image") && line.contains(&cover)), "{opf}");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn unreachable_images() {
+        let png = [b"\x89PNG\r\n\x1a\n".as_slice(), b"an image"].concat();
+        let base = mockserver::serve(vec![
+            ("/gone.png", Reply::not_found()),
+            ("/untyped.png", Reply::ok("", &png)),
+            ("/good.png", Reply::ok("image/png", &png)),
+        ]).await;
+
+        // The first two failing to list, the third still makes it.
+        let article = testutil::item(100, "https://example.com/article");
+        let mut handler = ArticleHandler::new(&article);
+        handler.content = format!("<p>Text<img src=\"{base}/gone.png\"><img src=\"{base}/untyped.png\"><img src=\"{base}/good.png\"></p>").into_bytes();
+        handler.finish().await;
+
+        let content = String::from_utf8(handler.content.clone()).unwrap();
+        assert_eq!(handler.images.len(), 1);
+        assert_eq!(handler.trace().images_found, 3);
+        assert!(!content.contains(&format!("{base}/gone.png")) && !content.contains(&format!("{base}/untyped.png")), "{content}");
+        assert!(content.contains(handler.images[&format!("{base}/good.png")].as_str()), "{content}");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn favicon_on_the_first_page() {
+        use std::io::Read;
+
+        let icon = [b"\x89PNG\r\n\x1a\n".as_slice(), b"an icon"].concat();
+        let page = b"<html><head><title>Iconic</title><link rel=\"icon\" href=\"/icon.png\"></head><body><p>Words to read, next to an icon.</p></body></html>";
+        let base = mockserver::serve(vec![
+            ("/article", Reply::ok("text/html", page)),
+            ("/icon.png", Reply::ok("image/png", &icon)),
+        ]).await;
+
+        let article = testutil::item(100, &format!("{base}/article"));
+        let mut handler = ArticleHandler::new(&article).set_options(ArticleOptions { favicons: true, ..Default::default() });
+        handler.get_readable().await.unwrap();
+        let epub = handler.epub().await.unwrap();
+
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(epub)).unwrap();
+        let mut embedded = Vec::new();
+        zip.by_name("OEBPS/favicon.png").unwrap().read_to_end(&mut embedded).unwrap();
+        assert_eq!(embedded, icon);
+        let mut xhtml = String::new();
+        zip.by_name("OEBPS/article.xhtml").unwrap().read_to_string(&mut xhtml).unwrap();
+        assert!(xhtml.contains("<img class=\"favicon\" src=\"favicon.png\""), "{xhtml}");
+
+        // Not unless asked for.
+        let mut handler = ArticleHandler::new(&article);
+        handler.get_readable().await.unwrap();
+        assert!(handler.favicon.is_none());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn pocket_metadata() {
+        use std::io::Read;
+
+        let page = b"<html><head><title>Bylined</title><meta name=\"author\" content=\"Page Byline\"></head><body><p>Words by someone, or other.</p></body></html>";
+        let base = mockserver::serve(vec![("/article", Reply::ok("text/html", page))]).await;
+
+        let fixture = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/item.json")).unwrap();
+        let mut json: serde_json::Value = serde_json::from_str(&fixture).unwrap();
+        json["resolved_url"] = format!("{base}/article").into();
+        let article: PocketItem = serde_json::from_value(json.clone()).unwrap();
+
+        let mut handler = ArticleHandler::new(&article);
+        handler.get_readable().await.unwrap();
+        assert_eq!(handler.author.as_str(), "Ada Ramos, Lin Okafor");
+        let added = article.get_time_added().unwrap();
+        assert!(handler.header.as_str().contains(&strings::current().saved(&added.with_timezone(&Local))));
+
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(handler.epub().await.unwrap())).unwrap();
+        let mut opf = String::new();
+        zip.by_name("OEBPS/content.opf").unwrap().read_to_string(&mut opf).unwrap();
+        assert!(opf.contains("Ada Ramos, Lin Okafor"), "{opf}");
+        assert!(opf.contains("<dc:subject>displays</dc:subject>"), "{opf}");
+        assert!(opf.contains("<dc:subject>history</dc:subject>"), "{opf}");
+        assert!(opf.contains("2018-03-20"), "{opf}");
+
+        // The page's say, when Pocket has none.
+        json["authors"] = serde_json::json!([]);
+        let article: PocketItem = serde_json::from_value(json).unwrap();
+        let mut handler = ArticleHandler::new(&article);
+        handler.get_readable().await.unwrap();
+        assert_ne!(handler.author.as_str(), "Ada Ramos, Lin Okafor");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn documentation_as_it_is() {
+        let page = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/docs/docsrs.html")).unwrap();
+        let base = mockserver::serve(vec![("/struct.ByteQueue.html", Reply::ok("text/html", &page))]).await;
+
+        let article = testutil::item(100, &format!("{base}/struct.ByteQueue.html"));
+        let mut handler = ArticleHandler::new(&article);
+        let html = String::from_utf8(handler.get_readable().await.unwrap()).unwrap();
+
+        assert_eq!(handler.trace().engine, Some(Engine::Docs));
+        assert_eq!(handler.text_scale(), Some(docs::TEXT_SCALE));
+        assert!(html.contains(docs::STYLE), "{html}");
+        // The anchors the links point at, and none of the sidebar.
+        assert!(html.contains("<h2 id=\"implementations\">Implementations</h2>"), "{html}");
+        assert!(html.contains("href=\"#method.push\""), "{html}");
+        assert!(!html.contains("sidebar") && !html.contains("Copy item path"), "{html}");
+    }
+
+    // Bytes that don't compress, like those of a photo.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed | 1;
+
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect()
+    }
+
+    async fn oversized(base: &str, names: &[&str], limits: EpubLimits) -> (Result<Vec<u8>, ArticleError>, Option<EpubSize>) {
+        let item = testutil::item(100, "https://example.com/article");
+        let mut handler = ArticleHandler::new(&item).set_options(ArticleOptions { limits, ..Default::default() });
+        handler.article_title = PlainText::new("Oversized");
+
+        let mut content = String::from("<p>Lots of pictures.</p>");
+        for name in names {
+            handler.images.insert(format!("{base}/{name}"), format!("p{name}.png"));
+            content.push_str(&format!("<p><img src=\"p{name}.png\" /></p>"));
+        }
+        handler.content = content.into_bytes();
+
+        let epub = handler.epub().await;
+
+        (epub, handler.size().cloned())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn downgrade_ladder() {
+        const KB: usize = 1024;
+
+        let base = mockserver::serve(vec![
+            ("/cover", Reply::ok("image/png", &noise(10 * KB, 1))),
+            ("/small", Reply::ok("image/png", &noise(10 * KB, 2))),
+            ("/large", Reply::ok("image/png", &noise(300 * KB, 3))),
+            ("/medium1", Reply::ok("image/png", &noise(60 * KB, 4))),
+            ("/medium2", Reply::ok("image/png", &noise(60 * KB, 5))),
+        ]).await;
+        let limits = |soft: usize, hard: usize| EpubLimits { soft_bytes: soft * KB, hard_bytes: hard * KB, ..Default::default() };
+
+        // Under every limit, as it was.
+        let (epub, size) = oversized(&base, &["cover", "small"], limits(100, 200)).await;
+        let size = size.unwrap();
+        assert_eq!(size.bytes, epub.unwrap().len());
+        assert_eq!((size.images, size.downgrade, size.reason), (2, Downgrade::Full, None));
+
+        // The large image goes.
+        let (epub, size) = oversized(&base, &["cover", "large", "small"], limits(100, 200)).await;
+        let size = size.unwrap();
+        assert!(epub.unwrap().len() <= 200 * KB);
+        assert_eq!((size.images, size.downgrade), (2, Downgrade::SmallImages));
+        assert!(size.reason.unwrap().contains("images over 200 KB left out"));
+
+        // Small enough images, too many of them. Only the cover is left, over the soft limit.
+        let (epub, size) = oversized(&base, &["medium1", "medium2", "cover"], limits(50, 150)).await;
+        let size = size.unwrap();
+        assert!(epub.unwrap().len() <= 150 * KB);
+        assert_eq!((size.images, size.downgrade), (1, Downgrade::CoverOnly));
+        assert!(size.reason.unwrap().contains("only the cover image"));
+
+        let (epub, size) = oversized(&base, &["medium1", "medium2"], limits(20, 40)).await;
+        let size = size.unwrap();
+        assert!(epub.unwrap().len() <= 40 * KB);
+        assert_eq!((size.images, size.downgrade), (0, Downgrade::NoImages));
+
+        // Too many images, however small.
+        let limits = EpubLimits { hard_images: 1, ..limits(100, 200) };
+        let (_, size) = oversized(&base, &["cover", "small"], limits).await;
+        assert_eq!(size.unwrap().downgrade, Downgrade::CoverOnly);
+
+        // Not even the text fits, the ladder ends.
+        let (epub, size) = oversized(&base, &["large"], limits(1, 1)).await;
+        assert!(matches!(epub, Err(ArticleError::Epub(reason)) if reason.contains("even with no images")));
+        assert_eq!(size, None);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn extraction_trace() {
+        let images = mockserver::serve(vec![("/image", Reply::ok("image/png", &noise(4096, 6)))]).await;
+        let page = format!("<html><head><title>Traced</title></head><body><p>Words to read.</p><img src=\"{images}/image\"></body></html>");
+        let base = mockserver::serve(vec![("/article", Reply::ok("text/html", page.as_bytes()))]).await;
+
+        // Both engines get a go, the longest wins and the other one says why it didn't.
+        let article = testutil::item(100, &format!("{base}/article"));
+        let mut handler = ArticleHandler::new(&article);
+        handler.get_readable().await.unwrap();
+        handler.epub().await.unwrap();
+
+        let trace = handler.trace();
+        let engines: Vec<Engine> = trace.candidates.iter().map(|(engine, _)| *engine).collect();
+        assert_eq!(engines, [Engine::ReadableReadability, Engine::Extractor]);
+        let longest = trace.candidates.iter().max_by_key(|(_, length)| *length).unwrap().0;
+        if trace.engine == Some(Engine::Extractor) {
+            assert_eq!(trace.fallbacks[0].0, Engine::ReadableReadability);
+        }
+        assert!(trace.candidates.iter().all(|(_, length)| *length > 0));
+        assert!(trace.engine == Some(longest) || trace.candidates[0].1 == trace.candidates[1].1);
+        assert_eq!((trace.images_found, trace.images_downloaded, trace.images_dropped), (1, 1, 0));
+        assert_eq!(trace.sanitization.as_deref(), Some("standard"));
+        assert_eq!(trace.error, None);
+
+        // Falling back to less: no images on a tight budget, no more than the limits allow.
+        let mut handler = ArticleHandler::new(&article).set_options(ArticleOptions { images: false, ..Default::default() });
+        handler.get_readable().await.unwrap();
+        assert!(handler.trace().fallbacks.contains(&(Engine::Images, "left out to save data".to_string())));
+
+        let limits = EpubLimits { hard_images: 0, ..Default::default() };
+        let mut handler = ArticleHandler::new(&article).set_options(ArticleOptions { limits, ..Default::default() });
+        handler.get_readable().await.unwrap();
+        handler.epub().await.unwrap();
+        let trace = handler.trace();
+        assert_eq!((trace.images_downloaded, trace.images_dropped), (1, 1));
+        assert!(trace.fallbacks.iter().any(|(engine, reason)| *engine == Engine::Images && reason.contains("no images")));
+
+        // Nothing to extract from, the trace says how far it got.
+        let gone = testutil::item(100, "http://127.0.0.1:1/article");
+        let mut handler = ArticleHandler::new(&gone);
+        assert!(handler.get_readable().await.is_err());
+        let trace = handler.trace();
+        assert_eq!(trace.engine, None);
+        assert!(trace.candidates.is_empty());
+        assert!(trace.error.is_some());
+        assert!(trace.summary().starts_with("no engine, 0/0 images, failed: "));
+
+        // Compact enough to keep around.
+        let stored = serde_json::to_value(ExtractionTrace { images_found: 1, ..Default::default() }).unwrap();
+        assert_eq!(stored, serde_json::json!({ "images_found": 1, "images_downloaded": 0, "images_dropped": 0 }));
+    }
+
+    #[test]
+    fn no_images_drops_the_tags() {
+        let item = testutil::item(100, "https://example.com/article");
+        let handler = ArticleHandler::new(&item).set_options(ArticleOptions { images: false, ..Default::default() });
+
+        let html = handler.cleanup_html(b"<p>Text<img src=\"https://example.com/a.png\"></p>".to_vec());
+
+        assert_eq!(String::from_utf8(html).unwrap(), "<p>Text</p>");
+    }
+
+    #[test]
+    fn cover() {
+        let names: HashMap<String, String> = [("a", "pa.png"), ("b", "pb.jpeg"), ("c", "pc.png"), ("d", "pd.png")]
+            .into_iter()
+            .map(|(url, loc)| (format!("https://example.com/{url}"), loc.to_string()))
+            .collect();
+        let image = |loc: &str, bytes: usize| (loc.to_string(), vec![0u8; bytes], "image/png".to_string());
+        let images = vec![image("pa.png", 10), image("pb.jpeg", 300), image("pc.png", 300), image("pd.png", 20)];
+        let urls = |urls: &[&str]| urls.iter().map(|url| format!("https://example.com/{url}")).collect::<Vec<_>>();
+
+        // Pocket's first pick, if it was fetched, then the next.
+        assert_eq!(ArticleHandler::cover(&urls(&["d", "a"]), &names, &images), Some(3));
+        assert_eq!(ArticleHandler::cover(&urls(&["elsewhere", "a"]), &names, &images), Some(0));
+
+        // Else the largest, the same one whatever order they came in.
+        assert_eq!(ArticleHandler::cover(&urls(&["elsewhere"]), &names, &images), Some(1));
+        let mut reversed = images.clone();
+        reversed.reverse();
+        assert_eq!(reversed[ArticleHandler::cover(&[], &names, &reversed).unwrap()].0, "pb.jpeg");
+
+        assert_eq!(ArticleHandler::cover(&urls(&["a"]), &names, &[]), None);
+    }
+
+    #[test]
+    fn color_accent() {
+        let item = testutil::item(100, "https://example.com/article");
+        let html = |colors: ColorPolicy| {
+            let handler = ArticleHandler::new(&item).set_options(ArticleOptions { colors, ..Default::default() });
+            String::from_utf8(handler.html()).unwrap()
+        };
+
+        let (color, greyscale) = (html(ColorPolicy::Color), html(ColorPolicy::Greyscale));
+        assert!(color.contains(ACCENT_STYLE));
+        assert!(!greyscale.contains("<style>"));
+        assert!(!greyscale.contains("{{style}}"));
+        assert_eq!(color.replace(ACCENT_STYLE, ""), greyscale);
+
+        assert_eq!(ColorPolicy::for_device(DeviceKind::RmPaperPro), ColorPolicy::Color);
+        assert_eq!(ColorPolicy::for_device(DeviceKind::Rm2), ColorPolicy::Greyscale);
+        assert_eq!(ColorPolicy::for_device(DeviceKind::Host), ColorPolicy::Greyscale);
+    }
+
+    #[test]
+    fn adversarial_titles() {
+        let item = testutil::item(100, "https://example.com/article");
+        let mut handler = ArticleHandler::new(&item);
+        handler.article_title = PlainText::new("Rust <T>: lifetimes & you");
+        handler.page_title = handler.article_title.clone();
+
+        let html = String::from_utf8(handler.html()).unwrap();
+
+        assert!(html.contains("<h1>Rust &lt;T&gt;: lifetimes &amp; you</h1>"));
+        assert!(!html.contains("<T>"));
+        assert_eq!(handler.title(), "Rust <T>: lifetimes & you");
+    }
+
+    #[test]
+    fn right_to_left_titles() {
+        let item = testutil::item(100, "https://example.com/article");
+        let mut handler = ArticleHandler::new(&item);
+        handler.article_title = PlainText::new("كيف تعمل الحواسيب اللوحية");
+
+        let html = String::from_utf8(handler.html()).unwrap();
+        assert!(html.contains("dir=\"rtl\""));
+        assert!(html.contains("<h1>كيف تعمل الحواسيب اللوحية</h1>"));
+
+        handler.article_title = PlainText::new("電子ペーパー");
+        let html = String::from_utf8(handler.html()).unwrap();
+        assert!(html.contains("dir=\"ltr\""));
+    }
+}
@@ -0,0 +1,290 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+//
+// Newsletter posts, Substack's, Ghost's and Buttondown's, come with subscribe widgets, share
+// buttons and calls to comment that the readabilities take for content, and with footnotes they
+// take for clutter. For those, the page is prepared before it's extracted:
+//
+//  - The widgets go, by the classes each platform gives them, see STRIPPED.
+//  - Substack's footnotes, popovers in the page, become endnotes at the end of the post.
+//  - The subtitle is kept as the description, and the publication's name for the header.
+//
+// A newsletter is told by its host, or for those on a domain of their own, by the page itself:
+// Ghost and Substack say so in their generator meta tag, and Substack in its substack: metadata
+// too. Loading something from Substack's CDN doesn't make a page Substack's, any page may embed
+// an image from a post.
+//
+use std::ops::Range;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use url::Url;
+
+static START_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<(?<name>[a-zA-Z][a-zA-Z0-9]*)\b[^>]*>").unwrap());
+static ANY_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"</?(?<name>[a-zA-Z][a-zA-Z0-9]*)\b[^>]*>").unwrap());
+static CLASS: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\bclass\s*=\s*"(?<classes>[^"]*)""#).unwrap());
+static CONTENT: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\bcontent\s*=\s*"(?<content>[^"]*)""#).unwrap());
+static GENERATOR: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<meta\b[^>]*\bname\s*=\s*"generator"[^>]*>"#).unwrap());
+static SUBSTACK_META: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<meta\b[^>]*\b(name|property)\s*=\s*"substack:"#).unwrap());
+static SITE_NAME: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<meta\b[^>]*\bproperty\s*=\s*"og:site_name"[^>]*>"#).unwrap());
+static FOOTNOTE_ANCHOR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"<a\b[^>]*\bclass\s*=\s*"[^"]*\bfootnote-anchor\b[^"]*"[^>]*>\s*(?<number>\d+)\s*</a>"#).unwrap()
+});
+
+// By platform, the classes of what goes before extraction.
+const STRIPPED: [(Platform, &[&str]); 3] = [
+    (Platform::Substack, &["subscription-widget-wrap", "subscribe-widget", "share-dialog", "post-ufi", "button-wrapper", "comments-section"]),
+    (Platform::Ghost, &["gh-subscribe", "kg-signup-card", "gh-post-upgrade-cta", "gh-comments", "gh-share"]),
+    (Platform::Buttondown, &["subscribe-form", "email-footer"]),
+];
+// By platform, the classes of the subtitle.
+const SUBTITLES: [(Platform, &[&str]); 3] = [
+    (Platform::Substack, &["subtitle"]),
+    (Platform::Ghost, &["gh-article-excerpt", "article-excerpt"]),
+    (Platform::Buttondown, &[]),
+];
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Platform {
+    Substack,
+    Ghost,
+    Buttondown,
+}
+
+
+// The page, ready to be extracted, and what was found in it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Newsletter {
+    pub platform: Platform,
+    pub html: String,
+    pub subtitle: Option<String>,
+    pub publication: Option<String>,
+}
+
+
+// An element, with its tags and without.
+pub(super) struct Element {
+    pub outer: Range<usize>,
+    pub inner: Range<usize>,
+}
+
+
+pub fn detect(url: &Url, html: &str) -> Option<Platform> {
+    let host = url.host_str().unwrap_or_default();
+    let generator = generator(html);
+
+    let substack = generator.as_deref().is_some_and(|generator| generator.starts_with("Substack")) || SUBSTACK_META.is_match(html);
+    if host == "substack.com" || host.ends_with(".substack.com") || substack {
+        return Some(Platform::Substack);
+    }
+
+    if generator.is_some_and(|generator| generator.starts_with("Ghost")) {
+        return Some(Platform::Ghost);
+    }
+
+    match ["buttondown.email", "buttondown.com"].contains(&host) {
+        true => Some(Platform::Buttondown),
+        false => None,
+    }
+}
+
+
+pub fn prepare(platform: Platform, html: &str) -> Newsletter {
+    let mut prepared = html.to_string();
+
+    let stripped = STRIPPED.iter().find(|(stripped, _)| *stripped == platform).map_or(&[][..], |(_, classes)| classes);
+    for class in stripped {
+        while let Some(element) = find(&prepared, class) {
+            prepared.replace_range(element.outer, "");
+        }
+    }
+
+    if platform == Platform::Substack {
+        prepared = endnotes(&prepared);
+    }
+
+    let subtitles = SUBTITLES.iter().find(|(subtitles, _)| *subtitles == platform).map_or(&[][..], |(_, classes)| classes);
+    let subtitle = subtitles.iter()
+        .find_map(|class| find(&prepared, class).map(|element| text(&prepared[element.inner])))
+        .filter(|subtitle| !subtitle.is_empty());
+    let publication = SITE_NAME.find(html).and_then(|meta| attribute(&CONTENT, meta.as_str()));
+
+    Newsletter {
+        platform,
+        html: prepared,
+        subtitle,
+        publication,
+    }
+}
+
+
+//
+// Substack's footnotes are an anchor in the text, and the note itself further down, which the
+// page shows as a popover:
+//
+//   <a class="footnote-anchor" id="footnote-anchor-1" href="#footnote-1">1</a>
+//   <div class="footnote"><a class="footnote-number" href="#footnote-anchor-1">1</a>
+//     <div class="footnote-content"><p>The note.</p></div></div>
+//
+// The anchors become plain superscripts, and the notes an ordered list where the first one was.
+//
+fn endnotes(html: &str) -> String {
+    let mut html = FOOTNOTE_ANCHOR.replace_all(html, "<sup>$number</sup>").into_owned();
+    let mut notes = Vec::new();
+    let mut at = None;
+
+    while let Some(footnote) = find(&html, "footnote") {
+        let inner = &html[footnote.inner.clone()];
+        let content = find(inner, "footnote-content").map_or("", |content| inner[content.inner].trim());
+
+        notes.push(format!("<li>{content}</li>"));
+        at.get_or_insert(footnote.outer.start);
+        html.replace_range(footnote.outer, "");
+    }
+
+    if let Some(at) = at {
+        html.insert_str(at, &format!("<section><hr /><ol>{}</ol></section>", notes.concat()));
+    }
+
+    html
+}
+
+
+// What made the page, as its generator meta tag says, e.g. "Ghost 5.82".
+pub(super) fn generator(html: &str) -> Option<String> {
+    GENERATOR.find(html).and_then(|meta| attribute(&CONTENT, meta.as_str()))
+}
+
+
+// The first element with the class among its classes, up to its matching end tag.
+fn find(html: &str, class: &str) -> Option<Element> {
+    find_where(html, |tag| has_class(tag, class))
+}
+
+
+// The first element whose start tag matches, up to its matching end tag, see docs::Selector.
+pub(super) fn find_where(html: &str, matches: impl Fn(&str) -> bool) -> Option<Element> {
+    let start = START_TAG.captures_iter(html).find(|tag| matches(&tag[0]))?;
+    let (tag, name) = (start.get(0)?, start.name("name")?.as_str());
+
+    if tag.as_str().ends_with("/>") {
+        return Some(Element { outer: tag.range(), inner: tag.end()..tag.end() });
+    }
+
+    let mut depth = 0;
+
+    for other in ANY_TAG.captures_iter(&html[tag.end()..]) {
+        let found = other.get(0)?;
+
+        if !other["name"].eq_ignore_ascii_case(name) || found.as_str().ends_with("/>") {
+            continue;
+        }
+
+        match found.as_str().starts_with("</") {
+            true if depth == 0 => return Some(Element {
+                outer: tag.start()..tag.end() + found.end(),
+                inner: tag.end()..tag.end() + found.start(),
+            }),
+            true => depth -= 1,
+            false => depth += 1,
+        }
+    }
+
+    None
+}
+
+
+pub(super) fn has_class(tag: &str, class: &str) -> bool {
+    attribute(&CLASS, tag).is_some_and(|classes| classes.split_whitespace().any(|name| name == class))
+}
+
+
+pub(super) fn attribute(re: &Regex, tag: &str) -> Option<String> {
+    re.captures(tag).map(|caps| unescape(&caps[1]))
+}
+
+
+// Just the text, with the usual entities in it turned back into characters.
+fn text(html: &str) -> String {
+    unescape(&ANY_TAG.replace_all(html, "")).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/newsletter");
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(format!("{FIXTURES}/{name}")).unwrap()
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn platforms() {
+        assert_eq!(detect(&url("https://someone.substack.com/p/a-post"), ""), Some(Platform::Substack));
+        assert_eq!(detect(&url("https://www.example.com/p/a-post"), &fixture("substack.html")), Some(Platform::Substack));
+        assert_eq!(detect(&url("https://blog.example.org/a-post/"), &fixture("ghost.html")), Some(Platform::Ghost));
+        assert_eq!(detect(&url("https://buttondown.com/someone/archive/a-post/"), ""), Some(Platform::Buttondown));
+        assert_eq!(detect(&url("https://www.example.com/a-post"), "<meta name=\"generator\" content=\"WordPress 6.6\">"), None);
+        assert_eq!(detect(&url("https://www.example.com/p/a-post"), "<meta property=\"substack:post_id\" content=\"1\">"), Some(Platform::Substack));
+
+        // Only an image from a Substack post.
+        let embedded = "<p>As seen elsewhere:</p><img src=\"https://substackcdn.com/image/fetch/w_1456/a.png\">";
+        assert_eq!(detect(&url("https://www.example.com/a-post"), embedded), None);
+    }
+
+    #[test]
+    fn golden_substack() {
+        let newsletter = prepare(Platform::Substack, &fixture("substack.html"));
+
+        assert_eq!(newsletter.html, fixture("substack.golden.html"));
+        assert_eq!(newsletter.subtitle.as_deref(), Some("What the footnotes were hiding, and more"));
+        assert_eq!(newsletter.publication.as_deref(), Some("Notes & Queries"));
+    }
+
+    #[test]
+    fn golden_ghost() {
+        let newsletter = prepare(Platform::Ghost, &fixture("ghost.html"));
+
+        assert_eq!(newsletter.html, fixture("ghost.golden.html"));
+        assert_eq!(newsletter.subtitle.as_deref(), Some("A post about \"posts\""));
+        assert_eq!(newsletter.publication.as_deref(), Some("The Weekly Ghost"));
+    }
+
+    #[test]
+    fn nested_elements() {
+        let html = r#"<div class="body"><div class="subscribe-widget x"><div><p>Subscribe</p></div><br/></div><p>Kept</p></div>"#;
+
+        assert_eq!(prepare(Platform::Substack, html).html, r#"<div class="body"><p>Kept</p></div>"#);
+    }
+}
@@ -0,0 +1,179 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+//
+// A HEAD request before an item takes up a slot in the sync, to tell from the Content-Length and
+// the Content-Type whether it's worth fetching now, later or at all:
+//
+//  Process     Nothing says otherwise, including servers that don't answer HEAD.
+//  Defer       Larger than large_item_mb, it waits until the device is charging and online,
+//              see FSHandler::defer_large().
+//  Skip        Larger than max_item_mb, or something there's no document to make of.
+//
+// Only the response to the HEAD is looked at, decide() is what the tests go through.
+//
+use std::time::Duration;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+
+use super::ArticleHandler;
+
+// A HEAD that takes longer than this isn't worth waiting for, the GET is made regardless.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+// Nothing there's a reader for on the device.
+const UNREADABLE: [&str; 4] = ["video/", "audio/", "application/zip", "application/x-"];
+
+
+// What the HEAD said, whatever of it there was.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Head {
+    pub length: Option<u64>,
+    pub content_type: Option<String>,
+}
+
+
+// In bytes, from the settings, no limit if unset.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SizeLimits {
+    pub defer_bytes: Option<u64>,
+    pub skip_bytes: Option<u64>,
+}
+
+
+impl SizeLimits {
+    pub fn any(&self) -> bool {
+        self.defer_bytes.is_some() || self.skip_bytes.is_some()
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Process,
+    Defer,
+    // Why, for the log.
+    Skip(String),
+}
+
+
+pub fn decide(head: &Head, limits: SizeLimits) -> Verdict {
+    if let Some(kind) = head.content_type.as_deref().filter(|kind| UNREADABLE.iter().any(|prefix| kind.starts_with(prefix))) {
+        return Verdict::Skip(format!("it's {kind}"));
+    }
+
+    let Some(length) = head.length else {
+        return Verdict::Process;
+    };
+
+    if limits.skip_bytes.is_some_and(|cap| length > cap) {
+        return Verdict::Skip(format!("it's {length} bytes"));
+    }
+
+    match limits.defer_bytes.is_some_and(|threshold| length > threshold) {
+        true => Verdict::Defer,
+        false => Verdict::Process,
+    }
+}
+
+
+// None when the server doesn't answer the HEAD, or not with a success.
+pub async fn head(url: &str) -> Option<Head> {
+    let response = ArticleHandler::client().ok()?
+        .head(url)
+        .timeout(HEAD_TIMEOUT)
+        .send()
+        .await
+        .ok()
+        .filter(|response| response.status().is_success())?;
+
+    let header = |name| response.headers().get(name).and_then(|value| value.to_str().ok());
+
+    Some(Head {
+        length: header(CONTENT_LENGTH).and_then(|length| length.parse().ok()),
+        content_type: header(CONTENT_TYPE).map(|kind| kind.to_ascii_lowercase()),
+    })
+}
+
+
+// Whether it's to be fetched now, as a Verdict.
+pub async fn precheck(url: &str, limits: SizeLimits) -> Verdict {
+    match head(url).await {
+        Some(head) => decide(&head, limits),
+        None => Verdict::Process,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mockserver::{self, Reply};
+
+    const MB: u64 = 1024 * 1024;
+
+    fn head(length: Option<u64>, content_type: &str) -> Head {
+        Head {
+            length,
+            content_type: Some(content_type.to_string()),
+        }
+    }
+
+    fn limits(defer: Option<u64>, skip: Option<u64>) -> SizeLimits {
+        SizeLimits {
+            defer_bytes: defer.map(|mb| mb * MB),
+            skip_bytes: skip.map(|mb| mb * MB),
+        }
+    }
+
+    #[test]
+    fn by_size() {
+        let limits = limits(Some(5), Some(50));
+
+        assert_eq!(decide(&head(Some(MB), "text/html"), limits), Verdict::Process);
+        assert_eq!(decide(&head(Some(5 * MB), "application/pdf"), limits), Verdict::Process);
+        assert_eq!(decide(&head(Some(5 * MB + 1), "application/pdf"), limits), Verdict::Defer);
+        assert_eq!(decide(&head(Some(51 * MB), "application/pdf"), limits), Verdict::Skip(format!("it's {} bytes", 51 * MB)));
+        assert_eq!(decide(&head(None, "application/pdf"), limits), Verdict::Process);
+    }
+
+    #[test]
+    fn without_limits() {
+        assert_eq!(decide(&head(Some(500 * MB), "application/pdf"), SizeLimits::default()), Verdict::Process);
+        assert_eq!(decide(&head(Some(500 * MB), "application/pdf"), limits(None, Some(600))), Verdict::Process);
+        assert_eq!(decide(&head(Some(500 * MB), "application/pdf"), limits(Some(100), None)), Verdict::Defer);
+    }
+
+    #[test]
+    fn by_content_type() {
+        assert_eq!(decide(&head(Some(MB), "video/mp4"), SizeLimits::default()), Verdict::Skip("it's video/mp4".to_string()));
+        assert_eq!(decide(&head(None, "audio/mpeg"), SizeLimits::default()), Verdict::Skip("it's audio/mpeg".to_string()));
+        assert_eq!(decide(&head(None, "text/html; charset=utf-8"), SizeLimits::default()), Verdict::Process);
+        assert_eq!(decide(&Head::default(), limits(Some(1), Some(2))), Verdict::Process);
+    }
+
+    #[tokio::test]
+    async fn from_the_server() {
+        let base = mockserver::serve(vec![
+            ("/big.pdf", Reply::ok("application/pdf", &vec![0; 2 * MB as usize])),
+            ("/missing", Reply::not_found()),
+        ]).await;
+        let limits = limits(Some(1), None);
+
+        assert_eq!(precheck(&format!("{base}/big.pdf"), limits).await, Verdict::Defer);
+        // Servers that don't answer the HEAD are left to the GET.
+        assert_eq!(precheck(&format!("{base}/missing"), limits).await, Verdict::Process);
+    }
+}
@@ -0,0 +1,125 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+
+//
+// How an article's document came about, for when it comes out empty, or not at all: which engine
+// won and with how much, what was tried before, and what happened to the images. It's filled in
+// as the article is worked on, so that a failure halfway through still says how far it got.
+//
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtractionTrace {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub engine: Option<Engine>,
+    // Characters of content each engine came up with.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<(Engine, usize)>,
+    // What didn't work out, and why.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<(Engine, String)>,
+    pub images_found: usize,
+    pub images_downloaded: usize,
+    pub images_dropped: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sanitization: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Engine {
+    Github,
+    Docs,
+    ReadableReadability,
+    Extractor,
+    Pdf,
+    Images,
+}
+
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Github => write!(f, "GitHub"),
+            Engine::Docs => write!(f, "documentation"),
+            Engine::ReadableReadability => write!(f, "readable_readability"),
+            Engine::Extractor => write!(f, "readability::extractor"),
+            Engine::Pdf => write!(f, "PDF"),
+            Engine::Images => write!(f, "images"),
+        }
+    }
+}
+
+
+impl ExtractionTrace {
+    pub fn candidate(&mut self, engine: Engine, length: usize) {
+        self.candidates.push((engine, length));
+    }
+
+
+    pub fn fallback(&mut self, engine: Engine, reason: &str) {
+        self.fallbacks.push((engine, reason.to_string()));
+    }
+
+
+    // One line of it, e.g. "readable_readability, 1234 chars, 2/3 images".
+    pub fn summary(&self) -> String {
+        let engine = self.engine.map(|engine| engine.to_string()).unwrap_or_else(|| "no engine".to_string());
+        let length = self.candidates.iter()
+            .find(|(candidate, _)| Some(*candidate) == self.engine)
+            .map(|(_, length)| format!(", {length} chars"))
+            .unwrap_or_default();
+        let error = self.error.as_ref().map(|error| format!(", failed: {error}")).unwrap_or_default();
+
+        format!("{engine}{length}, {}/{} images{error}", self.images_downloaded - self.images_dropped.min(self.images_downloaded), self.images_found)
+    }
+}
+
+
+impl fmt::Display for ExtractionTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.engine {
+            Some(engine) => writeln!(f, "Engine: {engine}")?,
+            None => writeln!(f, "Engine: none")?,
+        }
+
+        for (engine, length) in &self.candidates {
+            writeln!(f, "  {engine}: {length} chars")?;
+        }
+
+        for (engine, reason) in &self.fallbacks {
+            writeln!(f, "Not {engine}: {reason}")?;
+        }
+
+        writeln!(f, "Images: {} found, {} downloaded, {} dropped", self.images_found, self.images_downloaded, self.images_dropped)?;
+
+        if let Some(sanitization) = &self.sanitization {
+            writeln!(f, "Sanitization: {sanitization}")?;
+        }
+
+        if let Some(error) = &self.error {
+            writeln!(f, "Failed: {error}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+//
+// Data accounting, for those syncing over a metered connection. Every article body, PDF and
+// image downloaded is counted here, the sync adds it up per calendar month in the FSHandler
+// state, and the monthly budget (if any) decides how much a sync is allowed to fetch.
+//
+static DOWNLOADED: AtomicU64 = AtomicU64::new(0);
+
+// Articles up to this many words are still fetched when the budget is nearly used up.
+pub const SHORT_WORDS: u64 = 1500;
+
+
+pub fn record(bytes: usize) {
+    DOWNLOADED.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+
+// Bytes downloaded since the last take().
+pub fn downloaded() -> u64 {
+    DOWNLOADED.load(Ordering::Relaxed)
+}
+
+
+pub fn take() -> u64 {
+    DOWNLOADED.swap(0, Ordering::Relaxed)
+}
+
+
+// The key months are accounted under, e.g. "2024-10".
+pub fn month(now: chrono::DateTime<chrono::Local>) -> String {
+    now.format("%Y-%m").to_string()
+}
+
+
+// What a sync is allowed to do, from everything down to nothing at all.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Full,
+    NoImages,
+    ShortTextOnly,
+    Skip,
+}
+
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Full => "full",
+            Level::NoImages => "no-images",
+            Level::ShortTextOnly => "short-text-only",
+            Level::Skip => "skip",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+
+// The ladder: images go first at 75% of the budget, long articles at 90%, and nothing gets
+// downloaded once the budget is used up.
+pub fn level(used: u64, budget: Option<u64>) -> Level {
+    let budget = match budget {
+        Some(budget) => budget,
+        None => return Level::Full,
+    };
+
+    if used >= budget {
+        Level::Skip
+    } else if used >= budget / 10 * 9 {
+        Level::ShortTextOnly
+    } else if used >= budget / 4 * 3 {
+        Level::NoImages
+    } else {
+        Level::Full
+    }
+}
+
+
+// Whether an item may be fetched at the given level. Items of unknown length (PDFs, mostly)
+// don't count as short.
+pub fn allows(level: Level, word_count: u64) -> bool {
+    match level {
+        Level::Full | Level::NoImages => true,
+        Level::ShortTextOnly => word_count > 0 && word_count <= SHORT_WORDS,
+        Level::Skip => false,
+    }
+}
+
+
+// For the logs, e.g. "1.5 MB".
+pub fn human(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn no_budget() {
+        assert_eq!(level(u64::MAX, None), Level::Full);
+    }
+
+    #[test]
+    fn degradation_ladder() {
+        let budget = Some(100 * MB);
+
+        assert_eq!(level(0, budget), Level::Full);
+        assert_eq!(level(74 * MB, budget), Level::Full);
+        assert_eq!(level(75 * MB, budget), Level::NoImages);
+        assert_eq!(level(90 * MB, budget), Level::ShortTextOnly);
+        assert_eq!(level(100 * MB, budget), Level::Skip);
+        assert_eq!(level(250 * MB, budget), Level::Skip);
+    }
+
+    #[test]
+    fn items_allowed_per_level() {
+        assert!(allows(Level::Full, 10000));
+        assert!(allows(Level::NoImages, 0));
+        assert!(allows(Level::ShortTextOnly, SHORT_WORDS));
+        assert!(!allows(Level::ShortTextOnly, SHORT_WORDS + 1));
+        assert!(!allows(Level::ShortTextOnly, 0));
+        assert!(!allows(Level::Skip, 10));
+    }
+
+    #[test]
+    fn month_key() {
+        let now = chrono::Local.with_ymd_and_hms(2024, 3, 31, 23, 59, 0).unwrap();
+
+        assert_eq!(month(now), "2024-03");
+    }
+}
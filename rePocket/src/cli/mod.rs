@@ -0,0 +1,588 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+// Without arguments rePocket watches the Sync folder, which is what the service does. Everything
+// else is a one-off command meant to be run by hand over SSH, e.g. `repocket sync --ignore-budget`
+// to sync right away regardless of the monthly data budget, or `repocket sync --offline-fixtures
+// data/fixtures` to run a whole sync against canned responses, without an account or a device.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    // With --skip-verify the credentials aren't tried on Pocket first, e.g. on a boot without Wi-Fi.
+    Watch {
+        skip_verify: bool,
+    },
+    Sync {
+        ignore_budget: bool,
+        offline_fixtures: Option<String>,
+        // A whole sync even in safe mode, see the safemode module.
+        once: bool,
+        // Everything unread, not only what changed since the last sync, see sync::resync.
+        resync: bool,
+    },
+    PushPending {
+        include_readonly_history: bool,
+    },
+    Uninstall {
+        keep_documents: bool,
+        dry_run: bool,
+    },
+    Restore {
+        search: Option<String>,
+        tag: Option<String>,
+        count: u8,
+        // Which of the listed items to restore, e.g. "1,3-4", otherwise asked for.
+        select: Option<String>,
+    },
+    // Tidy up the state against the device, e.g. after an OS update. With --dry-run only says what
+    // would change.
+    Consolidate {
+        dry_run: bool,
+    },
+    // Give the documents the UUIDs the uuid_source setting calls for, renaming their files. Without
+    // --apply only says what would change.
+    MigrateUuids {
+        apply: bool,
+    },
+    // The state again from the folders on the device, when it's gone, see fshandler::rebuild. Not
+    // over a valid one unless --force.
+    RebuildState {
+        force: bool,
+    },
+    // What's on the device, with flags at a glance.
+    Status,
+    // Read back every document in the Pocket folder, with --repair to write broken ones again.
+    Verify {
+        repair: bool,
+    },
+    // How the document with the given UUID came about, or why it didn't.
+    Inspect {
+        uuid: String,
+    },
+    // What in an epub xochitl is known not to take, see the compat module.
+    Lint {
+        file: String,
+    },
+    // Whether rePocket is in safe mode, with --clear to leave it.
+    SafeMode {
+        clear: bool,
+    },
+    // A copy of the whole account in a directory, see the export module.
+    ExportAll {
+        out: String,
+        format: String,
+        state: String,
+    },
+    // Everything for a first run, see the setup module. With --defaults nothing is asked.
+    Setup {
+        defaults: bool,
+        consumer_key: Option<String>,
+        skip: Vec<String>,
+    },
+}
+
+
+impl Command {
+    // The commands with a JSON document to print, see take_json().
+    pub fn speaks_json(&self) -> bool {
+        matches!(self,
+            Command::Sync { offline_fixtures: None, .. } | Command::Consolidate { .. } | Command::Status |
+            Command::Verify { .. } | Command::Lint { .. })
+    }
+}
+
+
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter();
+
+    let command = match args.next() {
+        None => return Ok(Command::Watch { skip_verify: false }),
+        Some(command) => command,
+    };
+
+    let flags: Vec<String> = args.collect();
+
+    match command.as_str() {
+        "watch" => {
+            let mut skip_verify = false;
+
+            for flag in &flags {
+                match flag.as_str() {
+                    "--skip-verify" => skip_verify = true,
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::Watch { skip_verify })
+        },
+        "sync" => {
+            let (mut ignore_budget, mut offline_fixtures, mut once, mut resync) = (false, None, false, false);
+            let mut flags = flags.iter();
+
+            while let Some(flag) = flags.next() {
+                match flag.as_str() {
+                    "--ignore-budget" => ignore_budget = true,
+                    "--once" => once = true,
+                    "--resync" => resync = true,
+                    "--offline-fixtures" => offline_fixtures = Some(value(flag, flags.next())?),
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::Sync { ignore_budget, offline_fixtures, once, resync })
+        },
+        "push-pending" => {
+            let mut include_readonly_history = false;
+
+            for flag in &flags {
+                match flag.as_str() {
+                    "--include-readonly-history" => include_readonly_history = true,
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::PushPending { include_readonly_history })
+        },
+        "uninstall" => {
+            let mut mode = None;
+            let mut dry_run = false;
+
+            for flag in &flags {
+                match (flag.as_str(), mode) {
+                    ("--keep-documents", None) => mode = Some(true),
+                    ("--remove-documents", None) => mode = Some(false),
+                    ("--keep-documents" | "--remove-documents", Some(_)) => {
+                        return Err("Use either --keep-documents or --remove-documents".to_string());
+                    },
+                    ("--dry-run", _) => dry_run = true,
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            // Keeping the documents is the safe choice.
+            Ok(Command::Uninstall { keep_documents: mode.unwrap_or(true), dry_run })
+        },
+        "restore" => {
+            let (mut search, mut tag, mut count, mut select) = (None, None, 10, None);
+            let mut flags = flags.iter();
+
+            while let Some(flag) = flags.next() {
+                match flag.as_str() {
+                    "--search" => search = Some(value(flag, flags.next())?),
+                    "--tag" => tag = Some(value(flag, flags.next())?),
+                    "--select" => select = Some(value(flag, flags.next())?),
+                    "--count" => {
+                        count = value(flag, flags.next())?.parse::<u8>()
+                            .map_err(|_| "--count takes a number between 1 and 255".to_string())?;
+                    },
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::Restore { search, tag, count, select })
+        },
+        "safe-mode" => {
+            let mut clear = false;
+
+            for flag in &flags {
+                match flag.as_str() {
+                    "--clear" => clear = true,
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::SafeMode { clear })
+        },
+        "export-all" => {
+            let (mut out, mut format, mut state) = (None, "epub".to_string(), "all".to_string());
+            let mut flags = flags.iter();
+
+            while let Some(flag) = flags.next() {
+                match flag.as_str() {
+                    "--out" => out = Some(value(flag, flags.next())?),
+                    "--format" => format = value(flag, flags.next())?,
+                    "--state" => state = value(flag, flags.next())?,
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            if crate::export::Format::from_name(&format).is_none() {
+                return Err("--format is either epub or html".to_string());
+            }
+            if crate::export::state_name(&state).is_none() {
+                return Err("--state is one of all, unread or archive".to_string());
+            }
+
+            match out {
+                Some(out) => Ok(Command::ExportAll { out, format, state }),
+                None => Err("'export-all' needs somewhere to export to, e.g. --out ~/pocket".to_string()),
+            }
+        },
+        "setup" => {
+            let (mut defaults, mut consumer_key, mut skip) = (false, None, Vec::new());
+            let mut flags = flags.iter();
+
+            while let Some(flag) = flags.next() {
+                match flag.as_str() {
+                    "--defaults" => defaults = true,
+                    "--consumer-key" => consumer_key = Some(value(flag, flags.next())?),
+                    "--skip" => {
+                        for step in value(flag, flags.next())?.split(',') {
+                            if !crate::setup::STEPS.contains(&step) {
+                                return Err(format!("No step '{step}' to skip, the steps are {}", crate::setup::STEPS.join(", ")));
+                            }
+                            skip.push(step.to_string());
+                        }
+                    },
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::Setup { defaults, consumer_key, skip })
+        },
+        "status" => {
+            no_flags(&command, &flags)?;
+            Ok(Command::Status)
+        },
+        "verify" => {
+            let mut repair = false;
+
+            for flag in &flags {
+                match flag.as_str() {
+                    "--repair" => repair = true,
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::Verify { repair })
+        },
+        "inspect" => match flags.as_slice() {
+            [uuid] if !uuid.starts_with("--") => Ok(Command::Inspect { uuid: uuid.clone() }),
+            _ => Err("'inspect' takes the UUID of a document, e.g. repocket inspect <uuid>".to_string()),
+        },
+        "lint" => match flags.as_slice() {
+            [file] if !file.starts_with("--") => Ok(Command::Lint { file: file.clone() }),
+            _ => Err("'lint' takes an epub, e.g. repocket lint article.epub".to_string()),
+        },
+        "consolidate" => {
+            let mut dry_run = false;
+
+            for flag in &flags {
+                match flag.as_str() {
+                    "--dry-run" => dry_run = true,
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::Consolidate { dry_run })
+        },
+        "migrate-uuids" => {
+            let mut apply = false;
+
+            for flag in &flags {
+                match flag.as_str() {
+                    "--apply" => apply = true,
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::MigrateUuids { apply })
+        },
+        "rebuild-state" => {
+            let mut force = false;
+
+            for flag in &flags {
+                match flag.as_str() {
+                    "--force" => force = true,
+                    _ => return Err(format!("Unknown flag '{flag}' for '{command}'")),
+                }
+            }
+
+            Ok(Command::RebuildState { force })
+        },
+        _ => Err(format!("Unknown command '{command}'")),
+    }
+}
+
+
+// --creds goes with any command, e.g. `repocket sync --creds /tmp/.repocket.key`, it's taken out
+// of the arguments before they're parsed.
+pub fn take_creds(args: &mut Vec<String>) -> Result<Option<String>, String> {
+    let position = match args.iter().position(|arg| arg == "--creds") {
+        Some(position) => position,
+        None => return Ok(None),
+    };
+
+    let path = value("--creds", args.get(position + 1))?;
+    args.drain(position..=position + 1);
+
+    Ok(Some(path))
+}
+
+
+// --json goes with sync, consolidate, status, verify and lint, e.g. `repocket status --json`: a
+// single JSON document on stdout and the rest on stderr, see the output module. The other commands
+// won't take it, see Command::speaks_json().
+pub fn take_json(args: &mut Vec<String>) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != "--json");
+
+    args.len() != before
+}
+
+
+// -v and -vv go with any command too, for more to be said than usual, see the logging module.
+pub fn take_verbosity(args: &mut Vec<String>) -> u8 {
+    let mut count = 0;
+
+    args.retain(|arg| match arg.as_str() {
+        "-v" | "--verbose" => {
+            count += 1;
+            false
+        },
+        "-vv" => {
+            count += 2;
+            false
+        },
+        _ => true,
+    });
+
+    count
+}
+
+
+// Turns e.g. "1,3-4" or "all" into indices into a list of len items, counting from zero.
+pub fn parse_selection(input: &str, len: usize) -> Result<Vec<usize>, String> {
+    let input = input.trim();
+
+    if input == "all" {
+        return Ok((0..len).collect());
+    }
+
+    let mut selection = Vec::new();
+
+    for part in input.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.trim(), last.trim()),
+            None => (part, part),
+        };
+
+        let index = |n: &str| match n.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= len => Ok(n - 1),
+            _ => Err(format!("'{part}' is not between 1 and {len}")),
+        };
+
+        for i in index(first)?..=index(last)? {
+            if !selection.contains(&i) {
+                selection.push(i);
+            }
+        }
+    }
+
+    Ok(selection)
+}
+
+
+fn value(flag: &str, value: Option<&String>) -> Result<String, String> {
+    value.cloned().ok_or_else(|| format!("{flag} needs a value"))
+}
+
+
+fn no_flags(command: &str, flags: &[String]) -> Result<(), String> {
+    match flags.first() {
+        Some(flag) => Err(format!("Unknown flag '{flag}' for '{command}'")),
+        None => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn no_arguments_watches() {
+        assert_eq!(parse(args("")), Ok(Command::Watch { skip_verify: false }));
+        assert_eq!(parse(args("watch")), Ok(Command::Watch { skip_verify: false }));
+        assert_eq!(parse(args("watch --skip-verify")), Ok(Command::Watch { skip_verify: true }));
+        assert!(parse(args("watch --once")).is_err());
+    }
+
+    #[test]
+    fn sync() {
+        assert_eq!(parse(args("sync")), Ok(Command::Sync { ignore_budget: false, offline_fixtures: None, once: false, resync: false }));
+        assert_eq!(parse(args("sync --ignore-budget")), Ok(Command::Sync { ignore_budget: true, offline_fixtures: None, once: false, resync: false }));
+        assert_eq!(parse(args("sync --offline-fixtures data/fixtures")),
+            Ok(Command::Sync { ignore_budget: false, offline_fixtures: Some("data/fixtures".to_string()), once: false, resync: false }));
+        assert!(parse(args("sync --offline-fixtures")).is_err());
+        assert_eq!(parse(args("sync --once")), Ok(Command::Sync { ignore_budget: false, offline_fixtures: None, once: true, resync: false }));
+        assert_eq!(parse(args("sync --resync")), Ok(Command::Sync { ignore_budget: false, offline_fixtures: None, once: false, resync: true }));
+        assert_eq!(parse(args("safe-mode")), Ok(Command::SafeMode { clear: false }));
+        assert_eq!(parse(args("safe-mode --clear")), Ok(Command::SafeMode { clear: true }));
+    }
+
+    #[test]
+    fn push_pending() {
+        assert_eq!(parse(args("push-pending")),
+            Ok(Command::PushPending { include_readonly_history: false }));
+        assert_eq!(parse(args("push-pending --include-readonly-history")),
+            Ok(Command::PushPending { include_readonly_history: true }));
+    }
+
+    #[test]
+    fn uninstall() {
+        assert_eq!(parse(args("uninstall")),
+            Ok(Command::Uninstall { keep_documents: true, dry_run: false }));
+        assert_eq!(parse(args("uninstall --remove-documents --dry-run")),
+            Ok(Command::Uninstall { keep_documents: false, dry_run: true }));
+        assert!(parse(args("uninstall --keep-documents --remove-documents")).is_err());
+    }
+
+    #[test]
+    fn restore() {
+        let command = parse(vec!["restore", "--search", "rust lifetimes", "--tag", "long", "--count", "5"]
+            .into_iter().map(String::from));
+
+        assert_eq!(command, Ok(Command::Restore {
+            search: Some("rust lifetimes".to_string()),
+            tag: Some("long".to_string()),
+            count: 5,
+            select: None,
+        }));
+        assert_eq!(parse(args("restore --select 1,3")),
+            Ok(Command::Restore { search: None, tag: None, count: 10, select: Some("1,3".to_string()) }));
+        assert!(parse(args("restore --search")).is_err());
+        assert!(parse(args("restore --count lots")).is_err());
+    }
+
+    #[test]
+    fn consolidate() {
+        assert_eq!(parse(args("consolidate")), Ok(Command::Consolidate { dry_run: false }));
+        assert_eq!(parse(args("consolidate --dry-run")), Ok(Command::Consolidate { dry_run: true }));
+        assert!(parse(args("consolidate --force")).is_err());
+        assert_eq!(parse(args("migrate-uuids")), Ok(Command::MigrateUuids { apply: false }));
+        assert_eq!(parse(args("migrate-uuids --apply")), Ok(Command::MigrateUuids { apply: true }));
+        assert!(parse(args("migrate-uuids --dry-run")).is_err());
+        assert_eq!(parse(args("rebuild-state")), Ok(Command::RebuildState { force: false }));
+        assert_eq!(parse(args("rebuild-state --force")), Ok(Command::RebuildState { force: true }));
+        assert!(parse(args("rebuild-state --dry-run")).is_err());
+        assert_eq!(parse(args("status")), Ok(Command::Status));
+        assert_eq!(parse(args("verify")), Ok(Command::Verify { repair: false }));
+        assert_eq!(parse(args("verify --repair")), Ok(Command::Verify { repair: true }));
+        assert_eq!(parse(args("inspect 5f3c")), Ok(Command::Inspect { uuid: "5f3c".to_string() }));
+        assert!(parse(args("inspect")).is_err());
+        assert!(parse(args("inspect --all")).is_err());
+    }
+
+    #[test]
+    fn lint() {
+        assert_eq!(parse(args("lint article.epub")), Ok(Command::Lint { file: "article.epub".to_string() }));
+        assert!(parse(args("lint")).is_err());
+        assert!(parse(args("lint a.epub b.epub")).is_err());
+    }
+
+    #[test]
+    fn export_all() {
+        assert_eq!(parse(args("export-all --out /tmp/pocket")),
+            Ok(Command::ExportAll { out: "/tmp/pocket".to_string(), format: "epub".to_string(), state: "all".to_string() }));
+        assert_eq!(parse(args("export-all --format html --state archive --out x")),
+            Ok(Command::ExportAll { out: "x".to_string(), format: "html".to_string(), state: "archive".to_string() }));
+        assert!(parse(args("export-all")).is_err());
+        assert!(parse(args("export-all --out x --format pdf")).is_err());
+        assert!(parse(args("export-all --out x --state deleted")).is_err());
+    }
+
+    #[test]
+    fn setup() {
+        assert_eq!(parse(args("setup")), Ok(Command::Setup { defaults: false, consumer_key: None, skip: vec![] }));
+        assert_eq!(parse(args("setup --defaults --consumer-key 123-abc --skip service,folders")), Ok(Command::Setup {
+            defaults: true,
+            consumer_key: Some("123-abc".to_string()),
+            skip: vec!["service".to_string(), "folders".to_string()],
+        }));
+        assert!(parse(args("setup --consumer-key")).is_err());
+        assert!(parse(args("setup --skip everything")).is_err());
+    }
+
+    #[test]
+    fn json_with_any_command() {
+        let mut line = args("consolidate --json --dry-run");
+        assert!(take_json(&mut line));
+        assert_eq!(parse(line), Ok(Command::Consolidate { dry_run: true }));
+
+        let mut line = args("status");
+        assert!(!take_json(&mut line));
+        assert_eq!(parse(line), Ok(Command::Status));
+
+        // Only those with a document to print.
+        let speaks = |line: &str| parse(args(line)).unwrap().speaks_json();
+        for line in ["sync", "consolidate", "status", "verify --repair", "lint article.epub"] {
+            assert!(speaks(line), "{line}");
+        }
+        for line in ["", "sync --offline-fixtures data/fixtures", "push-pending", "safe-mode", "setup --defaults"] {
+            assert!(!speaks(line), "{line}");
+        }
+    }
+
+    #[test]
+    fn verbosity_with_any_command() {
+        let mut line = args("sync -vv --once");
+        assert_eq!(take_verbosity(&mut line), 2);
+        assert_eq!(parse(line), Ok(Command::Sync { ignore_budget: false, offline_fixtures: None, once: true, resync: false }));
+
+        let mut line = args("-v status --verbose");
+        assert_eq!(take_verbosity(&mut line), 2);
+        assert_eq!(parse(line), Ok(Command::Status));
+
+        assert_eq!(take_verbosity(&mut args("status")), 0);
+    }
+
+    #[test]
+    fn creds_with_any_command() {
+        let mut line = args("sync --creds /tmp/key --ignore-budget");
+        assert_eq!(take_creds(&mut line), Ok(Some("/tmp/key".to_string())));
+        assert_eq!(parse(line), Ok(Command::Sync { ignore_budget: true, offline_fixtures: None, once: false, resync: false }));
+
+        let mut line = args("--creds /tmp/key");
+        assert_eq!(take_creds(&mut line), Ok(Some("/tmp/key".to_string())));
+        assert_eq!(parse(line), Ok(Command::Watch { skip_verify: false }));
+
+        let mut line = args("sync");
+        assert_eq!(take_creds(&mut line), Ok(None));
+        assert!(take_creds(&mut args("sync --creds")).is_err());
+    }
+
+    #[test]
+    fn selection() {
+        assert_eq!(parse_selection("1,3-4", 5), Ok(vec![0, 2, 3]));
+        assert_eq!(parse_selection(" 2 , 2 ", 5), Ok(vec![1]));
+        assert_eq!(parse_selection("all", 3), Ok(vec![0, 1, 2]));
+        assert_eq!(parse_selection("", 3), Ok(vec![]));
+        assert!(parse_selection("0", 3).is_err());
+        assert!(parse_selection("2-9", 3).is_err());
+        assert!(parse_selection("one", 3).is_err());
+    }
+
+    #[test]
+    fn unknown_input() {
+        assert!(parse(args("frobnicate")).is_err());
+        assert!(parse(args("push-pending --force")).is_err());
+        assert!(parse(args("watch --force")).is_err());
+    }
+}
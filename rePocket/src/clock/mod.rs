@@ -0,0 +1,172 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+
+// The reMarkable's clock drifts badly when it's been off for a while. Pocket's clock, on the
+// other hand, can be trusted, so every response tells us how far off we are. Beyond this many
+// seconds, timestamps written to the device are derived from Pocket's clock instead.
+pub const SKEW_THRESHOLD: i64 = 300;
+
+// Seconds to add to the local clock to get the server's. Zero until a response says otherwise.
+static SKEW: AtomicI64 = AtomicI64::new(0);
+
+
+// Wall-clock time, and a monotonic clock that (on Linux) stops while the device is suspended.
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+    fn monotonic(&self) -> Duration;
+}
+
+
+pub struct SystemClock;
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Duration {
+        START.get_or_init(Instant::now).elapsed()
+    }
+}
+
+
+// The system clock, corrected with the skew observed against Pocket.
+pub struct CorrectedClock;
+
+impl Clock for CorrectedClock {
+    fn now(&self) -> SystemTime {
+        now()
+    }
+
+    fn monotonic(&self) -> Duration {
+        SystemClock.monotonic()
+    }
+}
+
+
+// The local time, corrected with the skew observed against Pocket, if large enough to matter.
+pub fn now() -> SystemTime {
+    corrected(&SystemClock, SKEW.load(Ordering::Relaxed))
+}
+
+
+pub fn now_millis() -> u128 {
+    now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+
+pub fn now_local() -> chrono::DateTime<chrono::Local> {
+    now().into()
+}
+
+
+// Record the skew between our clock and the Date header of a server response.
+pub fn observe(clock: &dyn Clock, date: &str) {
+    if let Some(skew) = skew_from_date(clock.now(), date) {
+        let previous = SKEW.swap(skew, Ordering::Relaxed);
+
+        if skew.abs() > SKEW_THRESHOLD && (previous - skew).abs() > SKEW_THRESHOLD {
+            println!("🚨 The device clock is off by {skew} seconds, using Pocket's clock for timestamps");
+        }
+    }
+}
+
+
+pub fn skew_from_date(local: SystemTime, date: &str) -> Option<i64> {
+    let server = chrono::DateTime::parse_from_rfc2822(date).ok()?.timestamp();
+    let local = match local.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+
+    Some(server - local)
+}
+
+
+pub fn corrected(clock: &dyn Clock, skew: i64) -> SystemTime {
+    let now = clock.now();
+
+    if skew.abs() <= SKEW_THRESHOLD {
+        now
+    } else if skew > 0 {
+        now + Duration::from_secs(skew as u64)
+    } else {
+        now - Duration::from_secs(skew.unsigned_abs())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockClock(u64);
+
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(self.0)
+        }
+
+        fn monotonic(&self) -> Duration {
+            Duration::from_secs(self.0)
+        }
+    }
+
+    const SERVER_DATE: &'static str = "Sun, 27 Oct 2024 10:00:00 GMT";
+    const SERVER_TS: u64 = 1730023200;
+
+    #[test]
+    fn no_skew() {
+        assert_eq!(skew_from_date(MockClock(SERVER_TS).now(), SERVER_DATE), Some(0));
+    }
+
+    #[test]
+    fn clock_in_1970() {
+        let clock = MockClock(3600);
+        let skew = skew_from_date(clock.now(), SERVER_DATE).unwrap();
+
+        assert_eq!(skew, SERVER_TS as i64 - 3600);
+        assert_eq!(corrected(&clock, skew), UNIX_EPOCH + Duration::from_secs(SERVER_TS));
+    }
+
+    #[test]
+    fn clock_ahead() {
+        let clock = MockClock(SERVER_TS + 86400);
+        let skew = skew_from_date(clock.now(), SERVER_DATE).unwrap();
+
+        assert_eq!(skew, -86400);
+        assert_eq!(corrected(&clock, skew), UNIX_EPOCH + Duration::from_secs(SERVER_TS));
+    }
+
+    #[test]
+    fn small_skew_is_ignored() {
+        let clock = MockClock(SERVER_TS + 30);
+
+        assert_eq!(corrected(&clock, -30), clock.now());
+    }
+
+    #[test]
+    fn garbage_date() {
+        assert_eq!(skew_from_date(MockClock(SERVER_TS).now(), "yesterday-ish"), None);
+    }
+}
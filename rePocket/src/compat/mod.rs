@@ -0,0 +1,428 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Write};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::Serialize;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+
+//
+// What xochitl is known not to take in an epub, one rule per quirk, each found the hard way with a
+// document that wouldn't open or didn't look right. Every epub rePocket makes goes through apply()
+// before it's written: the rules that can be fixed safely are, the rest are only warned about.
+// `repocket lint <file.epub>` runs the same checks over any epub, e.g. one a user says is broken.
+//
+// A new quirk is a new entry in RULES, with a fixture in data/fixtures/epub that shows it.
+//
+pub const RULES: [Rule; 6] = [
+    Rule {
+        id: "mimetype-first",
+        about: "The mimetype entry comes first in the zip, stored and saying application/epub+zip",
+        severity: Severity::Fix(mimetype_first),
+        check: check_mimetype,
+    },
+    Rule {
+        id: "unclosed-void-tag",
+        about: "Elements without an end tag are closed, e.g. <img src=\"...\" />, as XHTML wants",
+        severity: Severity::Fix(close_void_tags),
+        check: check_void_tags,
+    },
+    Rule {
+        id: "empty-img",
+        about: "No <img /> without a source",
+        severity: Severity::Fix(drop_empty_images),
+        check: check_empty_images,
+    },
+    Rule {
+        id: "html-entity",
+        about: "No named entities XML doesn't know, e.g. &nbsp;",
+        severity: Severity::Fix(number_entities),
+        check: check_entities,
+    },
+    Rule {
+        id: "giant-inline-style",
+        about: "No style attributes longer than MAX_STYLE, which xochitl chokes on",
+        severity: Severity::Fix(drop_giant_styles),
+        check: check_giant_styles,
+    },
+    Rule {
+        id: "css-position",
+        about: "No fixed, absolute or sticky positions, which xochitl lays out on top of the text",
+        severity: Severity::Warn,
+        check: check_positions,
+    },
+];
+
+const MIMETYPE: &str = "application/epub+zip";
+// Bytes in a style attribute.
+const MAX_STYLE: usize = 2048;
+// The entities HTML has and XML doesn't, those worth a number. Any other is only warned about.
+const ENTITIES: [(&str, u32); 19] = [
+    ("nbsp", 160), ("shy", 173), ("copy", 169), ("reg", 174), ("trade", 8482), ("deg", 176),
+    ("middot", 183), ("bull", 8226), ("times", 215), ("euro", 8364), ("hellip", 8230),
+    ("mdash", 8212), ("ndash", 8211), ("lsquo", 8216), ("rsquo", 8217), ("ldquo", 8220),
+    ("rdquo", 8221), ("laquo", 171), ("raquo", 187),
+];
+const XML_ENTITIES: [&str; 5] = ["amp", "lt", "gt", "quot", "apos"];
+
+static VOID_TAG: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"<(?<name>area|base|br|col|embed|hr|img|input|link|meta|source|track|wbr)\b(?<attrs>[^>]*?)\s*(?<close>/?)>").unwrap()
+});
+static EMPTY_IMG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<img\s*/?>").unwrap());
+static ENTITY: Lazy<Regex> = Lazy::new(|| Regex::new(r"&(?<name>[a-zA-Z][a-zA-Z0-9]*);").unwrap());
+static STYLE_ATTR: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\s+style\s*=\s*"(?<css>[^"]*)""#).unwrap());
+static POSITION: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)position\s*:\s*(fixed|absolute|sticky)").unwrap());
+
+
+#[derive(Clone, Copy)]
+pub struct Rule {
+    pub id: &'static str,
+    pub about: &'static str,
+    pub severity: Severity,
+    check: fn(&Epub) -> Vec<Finding>,
+}
+
+
+// What's done when a rule finds something, when the epub is made.
+#[derive(Clone, Copy)]
+pub enum Severity {
+    Fix(fn(&mut Epub)),
+    Warn,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Finding {
+    pub rule: &'static str,
+    // The zip entry, e.g. OEBPS/article.xhtml.
+    pub entry: String,
+    pub detail: String,
+    pub fixed: bool,
+}
+
+
+// An epub taken apart, its entries in the order they were in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Epub {
+    entries: Vec<Entry>,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    name: String,
+    data: Vec<u8>,
+    stored: bool,
+}
+
+
+impl Epub {
+    pub fn read(bytes: &[u8]) -> Result<Self, String> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+        let mut entries = Vec::new();
+
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index).map_err(|e| e.to_string())?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).map_err(|e| e.to_string())?;
+
+            entries.push(Entry {
+                name: file.name().to_string(),
+                data,
+                stored: file.compression() == CompressionMethod::Stored,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+
+    pub fn write(&self) -> Result<Vec<u8>, String> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for entry in &self.entries {
+            let method = match entry.stored {
+                true => CompressionMethod::Stored,
+                false => CompressionMethod::Deflated,
+            };
+            let options = FileOptions::default().compression_method(method);
+
+            match entry.name.ends_with('/') {
+                true => zip.add_directory(entry.name.as_str(), options).map_err(|e| e.to_string())?,
+                false => {
+                    zip.start_file(entry.name.as_str(), options).map_err(|e| e.to_string())?;
+                    zip.write_all(&entry.data).map_err(|e| e.to_string())?;
+                },
+            }
+        }
+
+        Ok(zip.finish().map_err(|e| e.to_string())?.into_inner())
+    }
+
+
+    // The markup and the stylesheets, as text, by index into the entries.
+    fn texts(&self, extensions: &[&str]) -> Vec<(usize, String)> {
+        self.entries.iter()
+            .enumerate()
+            .filter(|(_, entry)| extensions.iter().any(|ext| entry.name.to_lowercase().ends_with(ext)))
+            .map(|(index, entry)| (index, String::from_utf8_lossy(&entry.data).into_owned()))
+            .collect()
+    }
+}
+
+
+// Everything every rule finds, nothing fixed.
+pub fn lint(epub: &Epub) -> Vec<Finding> {
+    RULES.iter().flat_map(|rule| (rule.check)(epub)).collect()
+}
+
+
+// The rule a finding is by, for what it's about.
+pub fn rule(id: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.id == id)
+}
+
+
+// The epub with what can be fixed fixed, and the rest warned about. One that can't be read is
+// left as it is, for whoever checks it next to say why.
+pub fn apply(bytes: Vec<u8>) -> Vec<u8> {
+    let mut epub = match Epub::read(&bytes) {
+        Ok(epub) => epub,
+        Err(_) => return bytes,
+    };
+    let mut findings = Vec::new();
+    let mut changed = false;
+
+    for rule in RULES {
+        let found = (rule.check)(&epub);
+
+        if found.is_empty() {
+            continue;
+        }
+
+        let left = match rule.severity {
+            Severity::Fix(fix) => {
+                fix(&mut epub);
+                changed = true;
+                (rule.check)(&epub)
+            },
+            Severity::Warn => found.clone(),
+        };
+
+        findings.extend(found.into_iter().map(|finding| Finding { fixed: !left.contains(&finding), ..finding }));
+    }
+
+    let mut counts: BTreeMap<(&str, bool), usize> = BTreeMap::new();
+    for finding in &findings {
+        *counts.entry((finding.rule, finding.fixed)).or_default() += 1;
+    }
+    for ((rule, fixed), count) in counts {
+        match fixed {
+            true => println!("ℹ Fixed {count} × {rule} in the epub"),
+            false => println!("🚨 {count} × {rule} in the epub, which xochitl may not show right"),
+        }
+    }
+
+    if !changed {
+        return bytes;
+    }
+
+    match epub.write() {
+        Ok(fixed) => fixed,
+        Err(e) => {
+            println!("🚨 Could not write the fixed epub, keeping it as it was: {e}");
+            bytes
+        },
+    }
+}
+
+
+fn finding(rule: &'static str, entry: &str, detail: &str) -> Finding {
+    // Enough to find it, markup can go on for a while.
+    let detail: String = detail.chars().take(80).collect();
+
+    Finding { rule, entry: entry.to_string(), detail, fixed: false }
+}
+
+
+// Every match of re in the markup, or the stylesheets too, that what says is a problem.
+fn find_in<F>(epub: &Epub, rule: &'static str, extensions: &[&str], re: &Regex, what: F) -> Vec<Finding>
+where
+    F: Fn(&Captures) -> bool,
+{
+    epub.texts(extensions).into_iter()
+        .flat_map(|(index, text)| re.captures_iter(&text)
+            .filter(|caps| what(caps))
+            .map(|caps| finding(rule, &epub.entries[index].name, &caps[0]))
+            .collect::<Vec<_>>())
+        .collect()
+}
+
+
+fn replace_in<F>(epub: &mut Epub, extensions: &[&str], re: &Regex, with: F)
+where
+    F: Fn(&Captures) -> String,
+{
+    for (index, text) in epub.texts(extensions) {
+        let replaced = re.replace_all(&text, |caps: &Captures| with(caps));
+
+        if replaced != text {
+            epub.entries[index].data = replaced.into_owned().into_bytes();
+        }
+    }
+}
+
+
+const MARKUP: [&str; 3] = [".xhtml", ".html", ".htm"];
+const MARKUP_AND_CSS: [&str; 4] = [".xhtml", ".html", ".htm", ".css"];
+
+
+fn check_mimetype(epub: &Epub) -> Vec<Finding> {
+    match epub.entries.first() {
+        Some(first) if first.name == "mimetype" && first.stored && first.data == MIMETYPE.as_bytes() => Vec::new(),
+        Some(first) => vec![finding("mimetype-first", &first.name, "the first entry isn't a stored mimetype")],
+        None => vec![finding("mimetype-first", "", "there are no entries")],
+    }
+}
+
+
+fn mimetype_first(epub: &mut Epub) {
+    epub.entries.retain(|entry| entry.name != "mimetype");
+    epub.entries.insert(0, Entry { name: "mimetype".to_string(), data: MIMETYPE.as_bytes().to_vec(), stored: true });
+}
+
+
+fn check_void_tags(epub: &Epub) -> Vec<Finding> {
+    find_in(epub, "unclosed-void-tag", &MARKUP, &VOID_TAG, |tag| tag["close"].is_empty())
+}
+
+
+fn close_void_tags(epub: &mut Epub) {
+    replace_in(epub, &MARKUP, &VOID_TAG, |tag| format!("<{}{} />", &tag["name"], &tag["attrs"]));
+}
+
+
+fn check_empty_images(epub: &Epub) -> Vec<Finding> {
+    find_in(epub, "empty-img", &MARKUP, &EMPTY_IMG, |_| true)
+}
+
+
+fn drop_empty_images(epub: &mut Epub) {
+    replace_in(epub, &MARKUP, &EMPTY_IMG, |_| String::new());
+}
+
+
+fn check_entities(epub: &Epub) -> Vec<Finding> {
+    find_in(epub, "html-entity", &MARKUP, &ENTITY, |entity| !XML_ENTITIES.contains(&&entity["name"]))
+}
+
+
+fn number_entities(epub: &mut Epub) {
+    replace_in(epub, &MARKUP, &ENTITY, |entity| {
+        match ENTITIES.iter().find(|(name, _)| *name == &entity["name"]) {
+            Some((_, number)) => format!("&#{number};"),
+            None => entity[0].to_string(),
+        }
+    });
+}
+
+
+fn check_giant_styles(epub: &Epub) -> Vec<Finding> {
+    find_in(epub, "giant-inline-style", &MARKUP, &STYLE_ATTR, |style| style["css"].len() > MAX_STYLE)
+}
+
+
+fn drop_giant_styles(epub: &mut Epub) {
+    replace_in(epub, &MARKUP, &STYLE_ATTR, |style| match style["css"].len() > MAX_STYLE {
+        true => String::new(),
+        false => style[0].to_string(),
+    });
+}
+
+
+fn check_positions(epub: &Epub) -> Vec<Finding> {
+    find_in(epub, "css-position", &MARKUP_AND_CSS, &POSITION, |_| true)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/epub");
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{FIXTURES}/{name}")).unwrap()
+    }
+
+    fn rules(findings: &[Finding]) -> Vec<&'static str> {
+        let mut rules: Vec<&'static str> = findings.iter().map(|finding| finding.rule).collect();
+        rules.dedup();
+        rules
+    }
+
+    #[test]
+    fn every_quirk() {
+        let findings = lint(&Epub::read(&fixture("quirks.epub")).unwrap());
+
+        assert_eq!(rules(&findings), RULES.iter().map(|rule| rule.id).collect::<Vec<_>>());
+        assert!(findings.iter().all(|finding| !finding.fixed));
+        assert!(findings.contains(&finding("unclosed-void-tag", "OEBPS/article.xhtml", "<img src=\"p1.png\" alt=\"One\">")));
+        assert!(findings.contains(&finding("css-position", "OEBPS/stylesheet.css", "position: fixed")));
+    }
+
+    #[test]
+    fn rule_by_id() {
+        assert!(rule("css-position").unwrap().about.contains("fixed, absolute or sticky"));
+        assert!(rule("no-such-rule").is_none());
+    }
+
+    #[test]
+    fn fixed_where_safe() {
+        let fixed = apply(fixture("quirks.epub"));
+        let epub = Epub::read(&fixed).unwrap();
+
+        // Only what can't be fixed is left, and the unknown entity.
+        let left = lint(&epub);
+        assert_eq!(rules(&left), vec!["html-entity", "css-position"]);
+        assert!(left.contains(&finding("html-entity", "OEBPS/article.xhtml", "&zwnj;")));
+
+        assert_eq!(epub.entries[0], Entry { name: "mimetype".into(), data: MIMETYPE.as_bytes().to_vec(), stored: true });
+        let article = epub.entries.iter().find(|entry| entry.name == "OEBPS/article.xhtml").unwrap();
+        assert_eq!(String::from_utf8_lossy(&article.data), include_str!("../../data/fixtures/epub/quirks.fixed.xhtml"));
+    }
+
+    #[test]
+    fn nothing_to_fix() {
+        let clean = fixture("clean.epub");
+
+        assert!(lint(&Epub::read(&clean).unwrap()).is_empty());
+        assert_eq!(apply(clean.clone()), clean);
+        assert_eq!(apply(b"not a zip".to_vec()), b"not a zip");
+    }
+
+    #[test]
+    fn round_trip() {
+        let epub = Epub::read(&fixture("clean.epub")).unwrap();
+
+        assert_eq!(Epub::read(&epub.write().unwrap()).unwrap(), epub);
+    }
+}
@@ -0,0 +1,185 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use once_cell::sync::Lazy;
+use reqwest::cookie::CookieStore;
+use reqwest::header::HeaderValue;
+use serde::Deserialize;
+use url::Url;
+
+
+//
+// Some sites only serve the whole article once a consent cookie is set. The user copies it from
+// their browser into the settings, once:
+//
+// [cookies."example.eu"]
+// consent = "yes"
+//
+// A domain's cookies go to it and its subdomains only, and to nobody else, not even when the site
+// redirects elsewhere. Cookies the sites set are not kept. The values are as good as passwords,
+// so they never make it to the logs.
+//
+const REDACTED: &str = "<redacted>";
+
+static JAR: Lazy<Arc<CookieJar>> = Lazy::new(|| Arc::new(CookieJar::default()));
+
+
+// Domain to cookie name to value, as in the settings.
+#[derive(Clone, Default, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct Cookies(BTreeMap<String, BTreeMap<String, String>>);
+
+
+impl Cookies {
+    // The Cookie header for a request to the given host, if any cookies go there.
+    fn header(&self, host: &str) -> Option<HeaderValue> {
+        let host = host.to_lowercase();
+        let pairs: Vec<String> = self.0.iter()
+            .filter(|(domain, _)| {
+                let domain = domain.trim().trim_start_matches('.').to_lowercase();
+                !domain.is_empty() && (host == domain || host.ends_with(&format!(".{domain}")))
+            })
+            .flat_map(|(_, cookies)| cookies.iter().map(|(name, value)| format!("{name}={value}")))
+            .collect();
+
+        if pairs.is_empty() {
+            return None;
+        }
+
+        match HeaderValue::from_str(&pairs.join("; ")) {
+            Ok(mut value) => {
+                value.set_sensitive(true);
+                Some(value)
+            },
+            Err(_) => {
+                println!("🚨 Not sending the cookies for {host}, they're not valid in a header");
+                None
+            },
+        }
+    }
+
+
+    // The text with every cookie value blanked out.
+    pub fn redact(&self, text: &str) -> String {
+        self.0.values()
+            .flat_map(|cookies| cookies.values())
+            .filter(|value| !value.is_empty())
+            .fold(text.to_string(), |text, value| text.replace(value.as_str(), REDACTED))
+    }
+}
+
+
+impl fmt::Debug for Cookies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(domain, cookies)| {
+                (domain, cookies.keys().map(|name| (name, REDACTED)).collect::<BTreeMap<_, _>>())
+            }))
+            .finish()
+    }
+}
+
+
+// What reqwest asks for cookies, holding whatever the settings said last.
+#[derive(Default)]
+pub struct CookieJar {
+    cookies: RwLock<Cookies>,
+}
+
+
+impl CookieStore for CookieJar {
+    fn set_cookies(&self, _cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, _url: &Url) {
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        self.cookies.read().unwrap().header(url.host_str()?)
+    }
+}
+
+
+// Called with the settings before fetching anything.
+pub fn configure(cookies: &Cookies) {
+    *JAR.cookies.write().unwrap() = cookies.clone();
+}
+
+
+// For reqwest::ClientBuilder::cookie_provider().
+pub fn jar() -> Arc<CookieJar> {
+    JAR.clone()
+}
+
+
+// For anything that's about to be logged or kept, e.g. error messages.
+pub fn redact(text: &str) -> String {
+    JAR.cookies.read().unwrap().redact(text)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use crate::mockserver::{self, Reply};
+    use crate::settings::Settings;
+
+    fn cookies(toml: &str) -> Cookies {
+        Settings::from_toml(toml).cookies().clone()
+    }
+
+    #[test]
+    fn scoped_to_the_domain() {
+        let cookies = cookies("[cookies.\"example.eu\"]\nconsent = \"yes\"\nsession = \"abc\"\n");
+
+        assert_eq!(cookies.header("example.eu").unwrap(), "consent=yes; session=abc");
+        assert_eq!(cookies.header("news.Example.eu").unwrap(), "consent=yes; session=abc");
+        assert_eq!(cookies.header("notexample.eu"), None);
+        assert_eq!(cookies.header("example.eu.evil.com"), None);
+        assert_eq!(cookies.header("tracker.com"), None);
+    }
+
+    #[test]
+    fn redacted() {
+        let settings = Settings::from_toml("[cookies.\"example.eu\"]\nconsent = \"s3cr3t-consent\"\n");
+        assert!(!format!("{settings:?}").contains("s3cr3t"));
+
+        let cookies = settings.cookies();
+
+        assert_eq!(format!("{cookies:?}"), "{\"example.eu\": {\"consent\": \"<redacted>\"}}");
+        assert_eq!(cookies.redact("Cookie: consent=s3cr3t-consent; other=1"), "Cookie: consent=<redacted>; other=1");
+        assert_eq!(cookies.redact("nothing to hide"), "nothing to hide");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn sent_to_the_configured_host_only() {
+        let (base, requests) = mockserver::serve_recording(vec![("/article", Reply::ok("text/html", b"<p>Hi</p>"))]).await;
+        let other = base.replace("127.0.0.1", "localhost");
+
+        configure(&cookies("[cookies.\"127.0.0.1\"]\nconsent = \"yes\"\n"));
+        let client = reqwest::Client::builder().cookie_provider(jar()).build().unwrap();
+        client.get(format!("{base}/article")).send().await.unwrap();
+        client.get(format!("{other}/article")).send().await.unwrap();
+        configure(&Cookies::default());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].header("cookie").as_deref(), Some("consent=yes"));
+        assert_eq!(requests[1].header("cookie"), None);
+    }
+}
@@ -0,0 +1,201 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::backtrace::Backtrace;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use chrono::{DateTime, Local};
+
+use crate::clock;
+use crate::fshandler;
+use crate::pocketitem::PocketItem;
+
+
+//
+// A panic on the device ends up in the journal, where nobody is looking. The panic hook main
+// installs also writes it down next to the state, as crash-<timestamp>.log: the message, a
+// backtrace, and which item was being worked on and at what stage. run_sync says which item with
+// working_on(), and the article pipeline the stage with stage().
+//
+// The context is one for the whole process, rather than per thread or task: an article moves
+// between tokio's worker threads as it goes, and only one sync runs at a time anyway.
+//
+// Only the last KEPT crash logs are kept.
+//
+const KEPT: usize = 10;
+
+static CURRENT: Mutex<Option<Context>> = Mutex::new(None);
+
+#[cfg(test)]
+thread_local! {
+    pub static PANIC_AT: std::cell::Cell<Option<&'static str>> = const { std::cell::Cell::new(None) };
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+struct Context {
+    id: Option<u64>,
+    url: Option<String>,
+    stage: &'static str,
+}
+
+
+// The item being worked on, until dropped.
+pub struct WorkingOn;
+
+
+impl Drop for WorkingOn {
+    fn drop(&mut self) {
+        *current() = None;
+    }
+}
+
+
+pub fn crash_dir() -> PathBuf {
+    fshandler::state_dir()
+}
+
+
+pub fn install(dir: PathBuf) {
+    let previous = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        // Not waiting on the lock, the panic may have come while holding it.
+        let context = CURRENT.try_lock().ok().and_then(|current| current.clone());
+        let now = clock::now_local();
+        let report = report(&now, &info.to_string(), context.as_ref(), &Backtrace::force_capture().to_string());
+
+        match write(&dir, &now, &report) {
+            Ok(path) => println!("🚨 Wrote the crash to {}", path.display()),
+            Err(e) => println!("🚨 Could not write the crash to {}: {e}", dir.display()),
+        }
+
+        previous(info);
+    }));
+}
+
+
+pub fn working_on(item: &PocketItem) -> WorkingOn {
+    *current() = Some(Context {
+        id: item.best_id(),
+        url: item.best_url(),
+        stage: "starting",
+    });
+
+    WorkingOn
+}
+
+
+pub fn stage(stage: &'static str) {
+    if let Some(context) = current().as_mut() {
+        context.stage = stage;
+    }
+
+    #[cfg(test)]
+    if PANIC_AT.with(|at| at.get()) == Some(stage) {
+        panic!("A controlled panic while {stage}");
+    }
+}
+
+
+fn current() -> MutexGuard<'static, Option<Context>> {
+    CURRENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+
+fn report(time: &DateTime<Local>, panic: &str, context: Option<&Context>, backtrace: &str) -> String {
+    let item = match context {
+        Some(context) => format!(
+            "Item id {} with URL {}\nStage: {}",
+            context.id.map(|id| id.to_string()).unwrap_or_else(|| "unknown".into()),
+            context.url.as_deref().unwrap_or("unknown"),
+            context.stage,
+        ),
+        None => "No item was being worked on".to_string(),
+    };
+
+    format!("rePocket {} crashed on {}\n\n{panic}\n\n{item}\n\nBacktrace:\n{backtrace}\n",
+        env!("CARGO_PKG_VERSION"), time.to_rfc3339())
+}
+
+
+fn write(dir: &Path, time: &DateTime<Local>, report: &str) -> Result<PathBuf, std::io::Error> {
+    fs::create_dir_all(dir)?;
+
+    let path = dir.join(format!("crash-{}.log", time.format("%Y%m%d-%H%M%S")));
+    fs::write(&path, report)?;
+
+    // The names sort by time, the oldest go.
+    let mut logs = crash_logs(dir);
+    logs.sort();
+    for old in logs.iter().rev().skip(KEPT) {
+        let _ = fs::remove_file(old);
+    }
+
+    Ok(path)
+}
+
+
+fn crash_logs(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir).into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("crash-") && name.ends_with(".log")))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    fn at(seconds: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1729763686 + seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn reports() {
+        let context = Context { id: Some(42), url: Some("https://example.com/a".into()), stage: "extracting" };
+        let report = report(&at(0), "panicked at src/main.rs:1:1:\nboom", Some(&context), "0: main");
+
+        assert!(report.contains("boom"));
+        assert!(report.contains("Item id 42 with URL https://example.com/a\nStage: extracting"));
+        assert!(report.ends_with("Backtrace:\n0: main\n"));
+
+        assert!(super::report(&at(0), "boom", None, "").contains("No item was being worked on"));
+    }
+
+    #[test]
+    fn only_the_last_ones_are_kept() {
+        let dir = std::env::temp_dir().join(format!("repocket-crashes-{}", Uuid::new_v4()));
+
+        for seconds in 0..KEPT as i64 + 3 {
+            write(&dir, &at(seconds), "boom").unwrap();
+        }
+
+        let mut logs = crash_logs(&dir);
+        logs.sort();
+        assert_eq!(logs.len(), KEPT);
+        assert_eq!(logs[0], dir.join(format!("crash-{}.log", at(3).format("%Y%m%d-%H%M%S"))));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
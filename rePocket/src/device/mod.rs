@@ -0,0 +1,231 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::OnceLock;
+
+
+//
+// What rePocket is running on, found out when it starts rather than when it's built: musl builds,
+// and the aarch64 reMarkable Paper Pro, are just as much a reMarkable as the armv7 gnueabihf
+// build. The kernel says which model it is, failing that xochitl being installed says it's some
+// reMarkable. The target the binary was built for is only a hint, for when neither says anything.
+//
+const MACHINE: &str = "/sys/devices/soc0/machine";
+const XOCHITL: [&str; 2] = ["/usr/bin/xochitl", "/lib/systemd/system/xochitl.service"];
+// A directory per charger and battery, each with a status or online file, or both.
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+static KIND: OnceLock<DeviceKind> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    pub static CHARGING: std::cell::Cell<Option<bool>> = const { std::cell::Cell::new(None) };
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceKind {
+    Rm1,
+    Rm2,
+    RmPaperPro,
+    Host,
+}
+
+
+impl DeviceKind {
+    pub fn is_device(self) -> bool {
+        self != DeviceKind::Host
+    }
+
+
+    pub fn has_color(self) -> bool {
+        self == DeviceKind::RmPaperPro
+    }
+
+
+    // What the build target suggests.
+    fn hint() -> Self {
+        match cfg!(target_abi = "eabihf") {
+            true => DeviceKind::Rm2,
+            false => DeviceKind::Host,
+        }
+    }
+}
+
+
+// What there is to look at, so that the tests can make it up.
+#[derive(Clone, Debug, Default)]
+pub struct Probes {
+    // The contents of /sys/devices/soc0/machine, e.g. "reMarkable 2.0".
+    pub machine: Option<String>,
+    // Whether the xochitl binary or service is there.
+    pub xochitl: bool,
+    // std::env::consts::ARCH
+    pub arch: String,
+}
+
+
+impl Probes {
+    pub fn system() -> Self {
+        Self {
+            machine: read_to_string(MACHINE).ok(),
+            xochitl: XOCHITL.iter().any(|path| Path::new(path).exists()),
+            arch: std::env::consts::ARCH.to_string(),
+        }
+    }
+}
+
+
+pub fn detect(probes: &Probes, hint: DeviceKind) -> DeviceKind {
+    let machine = probes.machine.as_deref().unwrap_or_default();
+
+    if machine.contains("reMarkable") {
+        if machine.contains("1.0") {
+            return DeviceKind::Rm1;
+        }
+        if machine.contains("2.0") {
+            return DeviceKind::Rm2;
+        }
+        if machine.contains("Ferrari") {
+            return DeviceKind::RmPaperPro;
+        }
+    } else if !probes.xochitl {
+        return hint;
+    }
+
+    // Some reMarkable, but which one.
+    match (hint, probes.arch.as_str()) {
+        (DeviceKind::Host, "aarch64") => DeviceKind::RmPaperPro,
+        (DeviceKind::Host, _) => DeviceKind::Rm2,
+        (hint, _) => hint,
+    }
+}
+
+
+// Detected once, the first time it's asked for.
+pub fn kind() -> DeviceKind {
+    *KIND.get_or_init(|| {
+        let kind = detect(&Probes::system(), DeviceKind::hint());
+
+        if kind.is_device() != DeviceKind::hint().is_device() {
+            println!("ℹ Built for {}, running on {kind:?}", std::env::consts::ARCH);
+        }
+
+        kind
+    })
+}
+
+
+// What a power supply under /sys/class/power_supply says of itself.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Supply {
+    // A battery's, e.g. "Charging", "Discharging" or "Full".
+    pub status: Option<String>,
+    // A charger's, "1" when plugged in.
+    pub online: Option<String>,
+}
+
+
+fn supplies() -> Vec<Supply> {
+    let read = |path: std::path::PathBuf| read_to_string(path).ok().map(|value| value.trim().to_string());
+
+    std::fs::read_dir(POWER_SUPPLY)
+        .map(|dirs| dirs.flatten()
+            .map(|dir| Supply {
+                status: read(dir.path().join("status")),
+                online: read(dir.path().join("online")),
+            })
+            .collect())
+        .unwrap_or_default()
+}
+
+
+// Plugged in, or nothing to say otherwise, as on most hosts.
+pub fn on_charger(supplies: &[Supply]) -> bool {
+    let plugged = supplies.iter().any(|supply| {
+        supply.online.as_deref() == Some("1") || matches!(supply.status.as_deref(), Some("Charging") | Some("Full"))
+    });
+
+    plugged || supplies.iter().all(|supply| supply.status.is_none())
+}
+
+
+pub fn charging() -> bool {
+    #[cfg(test)]
+    if let Some(charging) = CHARGING.with(|charging| charging.get()) {
+        return charging;
+    }
+
+    on_charger(&supplies())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probes(machine: Option<&str>, xochitl: bool, arch: &str) -> Probes {
+        Probes {
+            machine: machine.map(String::from),
+            xochitl,
+            arch: arch.to_string(),
+        }
+    }
+
+    #[test]
+    fn models() {
+        let host = DeviceKind::Host;
+
+        assert_eq!(detect(&probes(Some("reMarkable 1.0\n"), true, "arm"), host), DeviceKind::Rm1);
+        assert_eq!(detect(&probes(Some("reMarkable 2.0\n"), true, "arm"), host), DeviceKind::Rm2);
+        assert_eq!(detect(&probes(Some("reMarkable Ferrari\n"), true, "aarch64"), host), DeviceKind::RmPaperPro);
+    }
+
+    #[test]
+    fn unknown_models() {
+        // A model from after this was written, or no /sys to look at, but xochitl is there.
+        assert_eq!(detect(&probes(Some("reMarkable Something\n"), true, "aarch64"), DeviceKind::Host), DeviceKind::RmPaperPro);
+        assert_eq!(detect(&probes(None, true, "arm"), DeviceKind::Host), DeviceKind::Rm2);
+        assert_eq!(detect(&probes(None, true, "aarch64"), DeviceKind::Rm2), DeviceKind::Rm2);
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(detect(&probes(None, false, "x86_64"), DeviceKind::Host), DeviceKind::Host);
+        assert_eq!(detect(&probes(Some("Raspberry Pi 4 Model B\n"), false, "aarch64"), DeviceKind::Host), DeviceKind::Host);
+
+        // Nothing to go by but the target, as in an eabihf build in an emulator.
+        assert_eq!(detect(&probes(None, false, "arm"), DeviceKind::Rm2), DeviceKind::Rm2);
+    }
+
+    #[test]
+    fn chargers() {
+        let battery = |status: &str| Supply { status: Some(status.to_string()), online: None };
+        let charger = |online: &str| Supply { status: None, online: Some(online.to_string()) };
+
+        assert!(on_charger(&[battery("Charging")]));
+        assert!(on_charger(&[battery("Full"), charger("0")]));
+        assert!(on_charger(&[battery("Discharging"), charger("1")]));
+        assert!(!on_charger(&[battery("Discharging"), charger("0")]));
+        assert!(!on_charger(&[battery("Not charging")]));
+
+        // No battery at all.
+        assert!(on_charger(&[]));
+        assert!(on_charger(&[charger("0")]));
+    }
+}
@@ -0,0 +1,319 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use tokio::task::JoinSet;
+
+
+//
+// The device's resolver is slow, a few hundred milliseconds a lookup, and the same hosts, CDNs
+// mostly, come up again and again within a sync. So every article and image request resolves
+// through an in-process cache, and the sync looks up the hosts of the items it's about to write
+// all at once beforehand, see warm(). Only answers are kept, for TTL, failures are asked again.
+//
+// The system resolver doesn't say how long an answer is good for, hence the fixed TTL. The watcher
+// keeps the cache for as long as it runs, so it holds no more than MAX_ENTRIES hosts either, the
+// expired ones go first, then the oldest.
+//
+const TTL: Duration = Duration::from_secs(5 * 60);
+const MAX_ENTRIES: usize = 1024;
+
+static CACHE: Lazy<Cache> = Lazy::new(|| Cache::new(Arc::new(SystemLookup), TTL));
+
+
+pub fn cache() -> Cache {
+    CACHE.clone()
+}
+
+
+// Whatever answers for a host name, the system's resolver, or a made up one in the tests.
+pub trait Lookup: Send + Sync {
+    fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+
+pub struct SystemLookup;
+
+
+impl Lookup for SystemLookup {
+    fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        Ok((host, 0).to_socket_addrs()?.map(|addr| addr.ip()).collect())
+    }
+}
+
+
+// Since the last take_stats(), for the sync summary.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub lookups: u64,
+    // Lookups that had to go to the resolver.
+    pub misses: u64,
+}
+
+
+#[derive(Clone)]
+pub struct Cache {
+    inner: Arc<Inner>,
+}
+
+
+struct Inner {
+    lookup: Arc<dyn Lookup>,
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, (Instant, Vec<IpAddr>)>>,
+    lookups: AtomicU64,
+    misses: AtomicU64,
+}
+
+
+impl Cache {
+    pub fn new(lookup: Arc<dyn Lookup>, ttl: Duration) -> Self {
+        Self::bounded(lookup, ttl, MAX_ENTRIES)
+    }
+
+
+    pub fn bounded(lookup: Arc<dyn Lookup>, ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                lookup,
+                ttl,
+                max_entries,
+                entries: Mutex::new(HashMap::new()),
+                lookups: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+            }),
+        }
+    }
+
+
+    pub async fn resolve_host(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        self.inner.lookups.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(addrs) = self.cached(host) {
+            return Ok(addrs);
+        }
+
+        self.inner.misses.fetch_add(1, Ordering::Relaxed);
+
+        // Blocking, as getaddrinfo is.
+        let (lookup, name) = (self.inner.lookup.clone(), host.to_string());
+        let addrs = tokio::task::spawn_blocking(move || lookup.lookup(&name))
+            .await
+            .map_err(io::Error::other)??;
+
+        if !addrs.is_empty() {
+            self.keep(host, addrs.clone());
+        }
+
+        Ok(addrs)
+    }
+
+
+    fn keep(&self, host: &str, addrs: Vec<IpAddr>) {
+        let mut entries = self.inner.entries.lock().unwrap();
+
+        if entries.len() >= self.inner.max_entries {
+            entries.retain(|_, (at, _)| at.elapsed() < self.inner.ttl);
+        }
+
+        while entries.len() >= self.inner.max_entries.max(1) {
+            let oldest = entries.iter()
+                .min_by_key(|(_, (at, _))| *at)
+                .map(|(host, _)| host.clone());
+
+            match oldest {
+                Some(oldest) => entries.remove(&oldest),
+                None => break,
+            };
+        }
+
+        entries.insert(host.to_string(), (Instant::now(), addrs));
+    }
+
+
+    fn cached(&self, host: &str) -> Option<Vec<IpAddr>> {
+        self.inner.entries.lock().unwrap()
+            .get(host)
+            .filter(|(at, _)| at.elapsed() < self.inner.ttl)
+            .map(|(_, addrs)| addrs.clone())
+    }
+
+
+    // Looks up the hosts all at once, returns how many resolved.
+    pub async fn warm<I: IntoIterator<Item = String>>(&self, hosts: I) -> usize {
+        let mut lookups = JoinSet::new();
+
+        for host in hosts.into_iter().collect::<BTreeSet<String>>() {
+            let cache = self.clone();
+            lookups.spawn(async move { cache.resolve_host(&host).await });
+        }
+
+        let mut resolved = 0;
+        while let Some(lookup) = lookups.join_next().await {
+            if matches!(lookup, Ok(Ok(_))) {
+                resolved += 1;
+            }
+        }
+
+        resolved
+    }
+
+
+    pub fn take_stats(&self) -> Stats {
+        Stats {
+            lookups: self.inner.lookups.swap(0, Ordering::Relaxed),
+            misses: self.inner.misses.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+
+impl Resolve for Cache {
+    fn resolve(&self, name: Name) -> Resolving {
+        let cache = self.clone();
+
+        // The port is reqwest's to fill in, from the URL.
+        Box::pin(async move {
+            let addrs = cache.resolve_host(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0))) as Addrs)
+        })
+    }
+}
+
+
+// The host to warm up for a URL, if it has one.
+pub fn host(url: &str) -> Option<String> {
+    url::Url::parse(url).ok()?.host_str().map(str::to_string)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mockserver::{self, Reply};
+
+    // Knows a few hosts, and counts how often it's asked.
+    struct Stub {
+        hosts: HashMap<&'static str, IpAddr>,
+        asked: Mutex<Vec<String>>,
+    }
+
+    impl Lookup for Stub {
+        fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+            self.asked.lock().unwrap().push(host.to_string());
+
+            match self.hosts.get(host) {
+                Some(addr) => Ok(vec![*addr]),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "no such host")),
+            }
+        }
+    }
+
+    fn stub() -> Arc<Stub> {
+        Arc::new(Stub {
+            hosts: [
+                ("cdn.example.com", "127.0.0.1".parse().unwrap()),
+                ("example.com", "127.0.0.1".parse().unwrap()),
+                ("img.example.com", "127.0.0.1".parse().unwrap()),
+            ].into(),
+            asked: Mutex::new(Vec::new()),
+        })
+    }
+
+    #[tokio::test]
+    async fn hits_and_misses() {
+        let stub = stub();
+        let cache = Cache::new(stub.clone(), TTL);
+
+        assert!(cache.resolve_host("cdn.example.com").await.is_ok());
+        assert!(cache.resolve_host("cdn.example.com").await.is_ok());
+        assert!(cache.resolve_host("nowhere.example.com").await.is_err());
+        assert!(cache.resolve_host("nowhere.example.com").await.is_err());
+
+        // Failures are asked again.
+        assert_eq!(*stub.asked.lock().unwrap(), ["cdn.example.com", "nowhere.example.com", "nowhere.example.com"]);
+        assert_eq!(cache.take_stats(), Stats { lookups: 4, misses: 3 });
+        assert_eq!(cache.take_stats(), Stats::default());
+    }
+
+    #[tokio::test]
+    async fn expired() {
+        let stub = stub();
+        let cache = Cache::new(stub.clone(), Duration::ZERO);
+
+        cache.resolve_host("cdn.example.com").await.unwrap();
+        cache.resolve_host("cdn.example.com").await.unwrap();
+
+        assert_eq!(stub.asked.lock().unwrap().len(), 2);
+        assert_eq!(cache.take_stats(), Stats { lookups: 2, misses: 2 });
+    }
+
+    #[tokio::test]
+    async fn bounded() {
+        let stub = stub();
+        let cache = Cache::bounded(stub.clone(), TTL, 2);
+
+        for host in ["cdn.example.com", "example.com", "img.example.com", "example.com"] {
+            cache.resolve_host(host).await.unwrap();
+        }
+
+        // The oldest made room for the third, the others are still there.
+        assert_eq!(cache.inner.entries.lock().unwrap().len(), 2);
+        assert_eq!(*stub.asked.lock().unwrap(), ["cdn.example.com", "example.com", "img.example.com"]);
+
+        cache.resolve_host("cdn.example.com").await.unwrap();
+        assert_eq!(stub.asked.lock().unwrap().len(), 4);
+        assert_eq!(cache.inner.entries.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn warmed_up() {
+        let stub = stub();
+        let cache = Cache::new(stub.clone(), TTL);
+
+        let hosts = ["https://cdn.example.com/a.png", "https://cdn.example.com/b.png", "https://example.com/", "not a url"];
+        let resolved = cache.warm(hosts.iter().filter_map(|url| host(url))).await;
+
+        assert_eq!(resolved, 2);
+        cache.resolve_host("example.com").await.unwrap();
+        assert_eq!(stub.asked.lock().unwrap().len(), 2);
+        assert_eq!(cache.take_stats(), Stats { lookups: 3, misses: 2 });
+    }
+
+    #[tokio::test]
+    async fn through_reqwest() {
+        let base = mockserver::serve(vec![("/article", Reply::ok("text/html", b"<p>Hi</p>"))]).await;
+        let stub = stub();
+        let cache = Cache::new(stub.clone(), TTL);
+        let client = reqwest::Client::builder().dns_resolver(Arc::new(cache.clone())).build().unwrap();
+
+        let url = base.replace("127.0.0.1", "example.com") + "/article";
+        for _ in 0..2 {
+            assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "<p>Hi</p>");
+        }
+
+        assert_eq!(*stub.asked.lock().unwrap(), ["example.com"]);
+    }
+}
@@ -0,0 +1,511 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::articlehandler::{ArticleHandler, ArticleOptions};
+use crate::pocket::{self, Pocket, MAX_ACTIONS};
+use crate::pocketitem::{ItemStatus, PocketItem};
+use crate::pocketquery::QueryBuilder;
+use crate::text::PlainText;
+
+
+//
+// `repocket export-all`, a copy of the whole account to keep, should Pocket go away. Every item
+// goes through the same pipeline as the ones synced to the device, into a directory laid out as:
+//
+// out/
+//   2024/11/1234567-some-article.epub
+//   undated/7654321-another-one.html
+//   manifest.json      what was exported where, by item id, with Pocket's state of each
+//   index.html         the same as a page to browse
+//   export.opml        the links, for whatever reads OPML
+//   .export-cursor     how far through the account it got, only while unfinished
+//
+// The account is paged through oldest first, so that items saved meanwhile only add pages at the
+// end. Items already in the manifest are skipped, so an export cut short, or run again later,
+// picks up where it was.
+//
+const MANIFEST: &str = "manifest.json";
+const INDEX: &str = "index.html";
+const OPML: &str = "export.opml";
+const CURSOR: &str = ".export-cursor";
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Epub,
+    Html,
+}
+
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "epub" => Some(Format::Epub),
+            "html" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Epub => "epub",
+            Format::Html => "html",
+        }
+    }
+}
+
+
+// Pocket's state, as the query has it.
+pub fn state_name(state: &str) -> Option<&'static str> {
+    match state {
+        "all" => Some("All"),
+        "unread" => Some("Unread"),
+        "archive" => Some("Archive"),
+        _ => None,
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    // Relative to the export directory.
+    pub file: String,
+    pub url: String,
+    pub title: String,
+    pub time_added: Option<u64>,
+    pub status: String,
+    pub favorite: bool,
+    pub tags: Vec<String>,
+}
+
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub items: BTreeMap<u64, Entry>,
+    // Why an item wasn't exported, it's tried again the next time.
+    #[serde(default)]
+    pub failed: BTreeMap<u64, String>,
+}
+
+
+impl Manifest {
+    pub fn load(out: &Path) -> Self {
+        fs::read(out.join(MANIFEST)).ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+
+    // Through a temporary file, a manifest cut in half would lose track of everything.
+    fn save(&self, out: &Path) -> io::Result<()> {
+        let tmp = out.join(format!("{MANIFEST}.tmp"));
+
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, out.join(MANIFEST))
+    }
+}
+
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Cursor {
+    state: String,
+    offset: u32,
+}
+
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub exported: usize,
+    // Already in the manifest.
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+
+#[derive(Debug)]
+pub enum ExportError {
+    IO(io::Error),
+    // What was exported so far is kept, running it again goes on from there.
+    Pocket(pocket::Error),
+}
+
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::IO(e) => write!(f, "{e}"),
+            ExportError::Pocket(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> Self {
+        ExportError::IO(e)
+    }
+}
+
+
+pub struct Export {
+    out: PathBuf,
+    format: Format,
+    // One of state_name()'s.
+    state: String,
+    page_size: u8,
+    // Between pages, not to run into Pocket's rate limits.
+    delay: Duration,
+    options: ArticleOptions,
+}
+
+
+impl Export {
+    pub fn new(out: &Path, format: Format, state: &str) -> Self {
+        Self {
+            out: out.to_path_buf(),
+            format,
+            state: state.to_string(),
+            page_size: MAX_ACTIONS as u8,
+            delay: Duration::from_secs(2),
+            options: ArticleOptions::default(),
+        }
+    }
+
+
+    pub fn set_options(mut self, options: ArticleOptions) -> Self {
+        self.options = options;
+
+        self
+    }
+
+
+    #[cfg(test)]
+    pub fn set_page_size(mut self, page_size: u8) -> Self {
+        self.page_size = page_size;
+
+        self
+    }
+
+
+    #[cfg(test)]
+    pub fn set_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+
+        self
+    }
+
+
+    pub async fn run(&self, pocket: &Pocket) -> Result<Summary, ExportError> {
+        fs::create_dir_all(&self.out)?;
+
+        let mut manifest = Manifest::load(&self.out);
+        let mut summary = Summary::default();
+        let mut offset = self.cursor().map(|cursor| cursor.offset).unwrap_or(0);
+
+        if offset > 0 {
+            println!("ℹ Resuming the export from item {offset}");
+        }
+
+        loop {
+            let query = QueryBuilder::default()
+                .set_state(state_name(&self.state).unwrap_or("All"))
+                .set_sort("Oldest")
+                .set_detail_type("Complete")
+                .set_count(self.page_size)
+                .set_offset(offset)
+                .build()
+                .unwrap();
+
+            let items = pocket.page(&query).await.map_err(ExportError::Pocket)?;
+
+            for item in &items {
+                self.export_item(item, &mut manifest, &mut summary).await?;
+            }
+
+            offset += items.len() as u32;
+            self.save_cursor(offset)?;
+            println!("ℹ {offset} item(s) looked at, {} exported so far", manifest.items.len());
+
+            if items.len() < self.page_size as usize {
+                break;
+            }
+            tokio::time::sleep(self.delay).await;
+        }
+
+        self.write_catalogs(&manifest)?;
+        let _ = fs::remove_file(self.out.join(CURSOR));
+
+        Ok(summary)
+    }
+
+
+    async fn export_item(&self, item: &PocketItem, manifest: &mut Manifest, summary: &mut Summary) -> io::Result<()> {
+        let (id, url) = match (item.best_id(), item.best_url()) {
+            (Some(id), Some(url)) if item.get_status() != Some(ItemStatus::Deleted) => (id, url),
+            _ => return Ok(()),
+        };
+
+        if manifest.items.contains_key(&id) {
+            summary.skipped += 1;
+            return Ok(());
+        }
+
+        let dir = match item.get_time_added() {
+            Some(time) => time.format("%Y/%m").to_string(),
+            None => "undated".to_string(),
+        };
+        fs::create_dir_all(self.out.join(&dir))?;
+
+        let mut article = ArticleHandler::new(item).set_options(self.options);
+        let written = match article.save_file(self.format.extension(), &self.out.join(&dir).to_string_lossy()).await {
+            Ok(written) => PathBuf::from(written),
+            Err(e) => {
+                println!("🚨 Could not export item {id}: {e}");
+                manifest.failed.insert(id, e.to_string());
+                summary.failed += 1;
+                return manifest.save(&self.out);
+            },
+        };
+
+        // A PDF stays one, whatever the format.
+        let extension = written.extension().and_then(|ext| ext.to_str()).unwrap_or(self.format.extension());
+        let file = format!("{dir}/{id}-{}.{extension}", slug(&item.get_title()));
+        fs::rename(&written, self.out.join(&file))?;
+
+        manifest.failed.remove(&id);
+        manifest.items.insert(id, Entry {
+            file,
+            url,
+            title: item.get_title(),
+            time_added: item.get_time_added().map(|time| time.timestamp() as u64),
+            status: match item.get_status() {
+                Some(ItemStatus::Archived) => "archived".to_string(),
+                _ => "unread".to_string(),
+            },
+            favorite: item.is_favorite(),
+            tags: item.get_tags(),
+        });
+        summary.exported += 1;
+
+        manifest.save(&self.out)
+    }
+
+
+    // Only for the same state, another one pages through different items.
+    fn cursor(&self) -> Option<Cursor> {
+        fs::read(self.out.join(CURSOR)).ok()
+            .and_then(|data| serde_json::from_slice::<Cursor>(&data).ok())
+            .filter(|cursor| cursor.state == self.state)
+    }
+
+
+    fn save_cursor(&self, offset: u32) -> io::Result<()> {
+        let cursor = Cursor { state: self.state.clone(), offset };
+
+        fs::write(self.out.join(CURSOR), serde_json::to_vec(&cursor)?)
+    }
+
+
+    // Newest first, by the month they were saved in.
+    fn write_catalogs(&self, manifest: &Manifest) -> io::Result<()> {
+        let mut entries: Vec<&Entry> = manifest.items.values().collect();
+        entries.sort_by(|a, b| b.time_added.cmp(&a.time_added).then(a.title.cmp(&b.title)));
+
+        let mut index = String::from("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\" /><title>Pocket export</title></head><body>\n");
+        let mut opml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\"><head><title>Pocket export</title></head><body>\n");
+        let mut month = None;
+
+        for entry in entries {
+            let this_month = entry.file.rsplit_once('/').map(|(dir, _)| dir.to_string());
+            if this_month != month {
+                if month.is_some() {
+                    index.push_str("</ul>\n");
+                }
+                index.push_str(&format!("<h2>{}</h2>\n<ul>\n", this_month.as_deref().unwrap_or("")));
+                month = this_month;
+            }
+
+            let title = PlainText::new(&entry.title).to_html();
+            let file = PlainText::new(&entry.file).to_html();
+            let url = PlainText::new(&entry.url).to_html();
+
+            index.push_str(&format!("<li><a href=\"{}\">{}</a> (<a href=\"{}\">original</a>)</li>\n", file.as_str(), title.as_str(), url.as_str()));
+            opml.push_str(&format!("<outline type=\"link\" text=\"{}\" url=\"{}\" />\n", title.as_str(), url.as_str()));
+        }
+
+        if month.is_some() {
+            index.push_str("</ul>\n");
+        }
+        index.push_str("</body></html>\n");
+        opml.push_str("</body></opml>\n");
+
+        fs::write(self.out.join(INDEX), index)?;
+        fs::write(self.out.join(OPML), opml)
+    }
+}
+
+
+// Something to tell the files apart by, e.g. "some-article" for "Some Article!".
+fn slug(title: &str) -> String {
+    let words: Vec<String> = title.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    words.join("-").chars().take(60).collect::<String>().trim_end_matches('-').to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+    use crate::mockserver::{self, Reply};
+    use crate::testutil;
+
+    fn item(id: u64, url: &str, time_added: u64) -> serde_json::Value {
+        testutil::item_with(serde_json::json!({
+            "item_id": id.to_string(),
+            "resolved_id": id.to_string(),
+            "resolved_url": url,
+            "resolved_title": format!("Article {id}"),
+            "favorite": if id == 2 { "1" } else { "0" },
+            "status": if id == 3 { "1" } else { "0" },
+            "word_count": "12",
+            "time_added": time_added.to_string(),
+            "tags": testutil::tags(id, &["rust"]),
+        }))
+    }
+
+    fn page(items: &[serde_json::Value]) -> Reply {
+        let list: serde_json::Map<String, serde_json::Value> = items.iter()
+            .map(|item| (item["item_id"].as_str().unwrap().to_string(), item.clone()))
+            .collect();
+
+        Reply::ok("application/json", serde_json::json!({ "status": 1, "since": 1729763686, "list": list }).to_string().as_bytes())
+    }
+
+    async fn site() -> String {
+        let page = b"<html><head><title>Kept</title></head><body><p>Something worth keeping around for later.</p></body></html>";
+
+        mockserver::serve(vec![("/article", Reply::ok("text/html", page))]).await
+    }
+
+    fn export(out: &Path) -> Export {
+        Export::new(out, Format::Epub, "all")
+            .set_options(ArticleOptions { images: false, ..Default::default() })
+            .set_page_size(2)
+            .set_delay(Duration::ZERO)
+    }
+
+    fn out() -> PathBuf {
+        std::env::temp_dir().join(format!("repocket-export-{}", Uuid::new_v4()))
+    }
+
+    fn offsets(requests: &mockserver::Requests) -> Vec<u64> {
+        requests.lock().unwrap().iter()
+            .filter(|request| request.path == "/v3/get")
+            .map(|request| serde_json::from_str::<serde_json::Value>(&request.body).unwrap()["offset"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn export_every_page() {
+        let site = site().await;
+        let url = format!("{site}/article");
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", page(&[item(1, &url, 1700000000), item(2, &url, 1730500000)])),
+            ("/v3/get", page(&[item(3, &url, 0)])),
+        ]).await;
+        let out = out();
+
+        let summary = export(&out).run(&Pocket::mock(&format!("{api}/v3"))).await.unwrap();
+
+        assert_eq!(summary, Summary { exported: 3, skipped: 0, failed: 0 });
+        assert_eq!(offsets(&requests), [0, 2]);
+        assert!(!out.join(CURSOR).exists());
+
+        // Every entry points at a document that's there.
+        let manifest = Manifest::load(&out);
+        assert_eq!(manifest.items.keys().copied().collect::<Vec<u64>>(), [1, 2, 3]);
+        assert_eq!(manifest.items[&1].file, "2023/11/1-article-1.epub");
+        assert_eq!(manifest.items[&2].file, "2024/11/2-article-2.epub");
+        assert_eq!(manifest.items[&3].file, "undated/3-article-3.epub");
+        for entry in manifest.items.values() {
+            assert!(fs::metadata(out.join(&entry.file)).unwrap().len() > 0, "{}", entry.file);
+            assert_eq!(entry.tags, ["rust"]);
+        }
+        assert!(manifest.items[&2].favorite && !manifest.items[&1].favorite);
+        assert_eq!((manifest.items[&3].status.as_str(), manifest.items[&1].status.as_str()), ("archived", "unread"));
+
+        let index = fs::read_to_string(out.join(INDEX)).unwrap();
+        assert!(index.find("2024/11/2-article-2.epub").unwrap() < index.find("2023/11/1-article-1.epub").unwrap());
+        assert_eq!(fs::read_to_string(out.join(OPML)).unwrap().matches("<outline").count(), 3);
+    }
+
+    #[tokio::test]
+    async fn resume_an_export() {
+        let site = site().await;
+        let url = format!("{site}/article");
+        let (api, _) = mockserver::serve_recording(vec![
+            ("/v3/get", page(&[item(1, &url, 1700000000), item(2, &url, 1730500000)])),
+            ("/v3/get", Reply { status: 503, ..Reply::not_found() }),
+        ]).await;
+        let out = out();
+
+        // Cut short after the first page.
+        assert!(matches!(export(&out).run(&Pocket::mock(&format!("{api}/v3"))).await, Err(ExportError::Pocket(_))));
+        assert_eq!(Manifest::load(&out).items.len(), 2);
+        assert!(out.join(CURSOR).exists());
+
+        // On from the second page, and the first one again for a fresh start.
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", page(&[item(3, &url, 1730500000)])),
+        ]).await;
+        let summary = export(&out).run(&Pocket::mock(&format!("{api}/v3"))).await.unwrap();
+        assert_eq!(summary, Summary { exported: 1, skipped: 0, failed: 0 });
+        assert_eq!(offsets(&requests), [2]);
+        assert!(!out.join(CURSOR).exists());
+
+        let (api, _) = mockserver::serve_recording(vec![
+            ("/v3/get", page(&[item(1, &url, 1700000000), item(2, &url, 1730500000)])),
+            ("/v3/get", page(&[item(3, &url, 1730500000), item(4, "http://127.0.0.1:1/gone", 1730600000)])),
+            ("/v3/get", page(&[])),
+        ]).await;
+        let summary = export(&out).run(&Pocket::mock(&format!("{api}/v3"))).await.unwrap();
+        assert_eq!(summary, Summary { exported: 0, skipped: 3, failed: 1 });
+
+        let manifest = Manifest::load(&out);
+        assert_eq!(manifest.items.len(), 3);
+        assert!(manifest.failed.contains_key(&4));
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slug("Some Article!"), "some-article");
+        assert_eq!(slug("  Ünïcode & “quotes” "), "ünïcode-quotes");
+        assert_eq!(slug(&"long ".repeat(20)).len(), 59);
+    }
+}
@@ -0,0 +1,104 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+//
+// What went wrong on the filesystem, always with the file it went wrong with, so that a state file
+// gone bad or a folder mounted read-only can be told apart in the log. None of these is worth
+// taking the daemon down for: what an item's files can't be written for fails the item, what the
+// state can't be saved for stops the cycle, see sync::SyncError::State.
+//
+
+use std::fmt;
+use std::io;
+
+
+#[derive(Debug)]
+pub enum FsError {
+    // Reading or writing the file at path, e.g. on a read-only filesystem.
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    // The file at path isn't the JSON it should be, e.g. the state cut short.
+    Serde {
+        path: String,
+        source: serde_json::Error,
+    },
+    // Pocket left out what an item can't be written without, e.g. its id or its URL.
+    MissingField {
+        title: String,
+        field: &'static str,
+    },
+    // Where xochitl keeps its documents isn't there, e.g. before /home is mounted.
+    RootUnavailable(String),
+}
+
+
+impl FsError {
+    pub fn io(path: &str, source: io::Error) -> Self {
+        FsError::Io { path: path.to_string(), source }
+    }
+
+
+    pub fn serde(path: &str, source: serde_json::Error) -> Self {
+        FsError::Serde { path: path.to_string(), source }
+    }
+}
+
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::Io { path, source } => write!(f, "Could not read or write {path}: {source}"),
+            FsError::Serde { path, source } => write!(f, "{path} isn't valid: {source}"),
+            FsError::MissingField { title, field } => write!(f, "Pocket has no {field} for '{title}'"),
+            FsError::RootUnavailable(path) => write!(f, "xochitl's storage isn't at {path}"),
+        }
+    }
+}
+
+
+impl std::error::Error for FsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FsError::Io { source, .. } => Some(source),
+            FsError::Serde { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let denied = FsError::io("/home/root/.repocket/config", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(denied.to_string().starts_with("Could not read or write /home/root/.repocket/config: "));
+
+        let corrupt = FsError::serde("state.json", serde_json::from_str::<u64>("{").unwrap_err());
+        assert!(corrupt.to_string().starts_with("state.json isn't valid: "));
+
+        let missing = FsError::MissingField { title: "Saved".to_string(), field: "URL" };
+        assert_eq!(missing.to_string(), "Pocket has no URL for 'Saved'");
+
+        let unmounted = FsError::RootUnavailable("/home/root/.local/share/remarkable/xochitl".to_string());
+        assert_eq!(unmounted.to_string(), "xochitl's storage isn't at /home/root/.local/share/remarkable/xochitl");
+    }
+}
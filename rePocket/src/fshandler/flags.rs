@@ -0,0 +1,116 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+use crate::articlehandler::ArticleHandler;
+use crate::pocketitem::PocketItem;
+
+
+//
+// What's worth knowing about a document at a glance, from what Pocket says about the item and
+// what was made of it, e.g. "📄 🖼 ⏱ 12 min, images omitted". Kept in the state along with the
+// item, items from before there were flags have none.
+//
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ItemFlags {
+    pub article: bool,
+    pub images: bool,
+    pub video: bool,
+    pub minutes: Option<u64>,
+    // Some or all of the images didn't make it into the document, see ArticleOptions and
+    // articlehandler::Downgrade.
+    pub images_omitted: bool,
+    // Pocket's, or the page's when Pocket had none, see ArticleHandler::lang().
+    pub lang: Option<String>,
+}
+
+
+impl ItemFlags {
+    pub fn new(item: &PocketItem, article: &ArticleHandler) -> Self {
+        Self {
+            article: item.is_article(),
+            images: item.has_image(),
+            video: item.has_video(),
+            minutes: article.minutes(),
+            images_omitted: article.images_omitted(),
+            lang: article.lang(),
+        }
+    }
+}
+
+
+impl fmt::Display for ItemFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut glance: Vec<String> = [(self.article, "📄"), (self.images, "🖼"), (self.video, "🎬")]
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| flag.to_string())
+            .collect();
+
+        if let Some(minutes) = self.minutes {
+            glance.push(format!("⏱ {minutes} min"));
+        }
+
+        if let Some(lang) = &self.lang {
+            glance.push(format!("🌐 {lang}"));
+        }
+
+        write!(f, "{}", glance.join(" "))?;
+
+        if self.images_omitted {
+            write!(f, "{}images omitted", if glance.is_empty() { "" } else { ", " })?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_a_glance() {
+        let flags = ItemFlags { article: true, images: true, minutes: Some(12), ..Default::default() };
+        assert_eq!(flags.to_string(), "📄 🖼 ⏱ 12 min");
+
+        let flags = ItemFlags { article: true, lang: Some("es".to_string()), ..Default::default() };
+        assert_eq!(flags.to_string(), "📄 🌐 es");
+
+        let flags = ItemFlags { video: true, images_omitted: true, ..Default::default() };
+        assert_eq!(flags.to_string(), "🎬, images omitted");
+
+        let flags = ItemFlags { images_omitted: true, ..Default::default() };
+        assert_eq!(flags.to_string(), "images omitted");
+
+        assert_eq!(ItemFlags::default().to_string(), "");
+    }
+
+    #[test]
+    fn stored() {
+        let flags = ItemFlags { article: true, minutes: Some(3), ..Default::default() };
+        let json = serde_json::to_string(&flags).unwrap();
+
+        assert_eq!(serde_json::from_str::<ItemFlags>(&json).unwrap(), flags);
+        // Written by a version that knew fewer flags.
+        assert_eq!(serde_json::from_str::<ItemFlags>("{\"article\": true}").unwrap(),
+            ItemFlags { article: true, ..Default::default() });
+    }
+}
@@ -0,0 +1,276 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::fmt;
+use std::fs;
+use std::io::{Cursor, Read};
+use serde::{Serialize, Serializer};
+use uuid::{Uuid, Version};
+
+use super::Metadata;
+use super::root::xochitl_root;
+
+
+// Smaller than this, and it's not a document. An epub is never below a kilobyte, see
+// articlehandler::MIN_EPUB_SIZE, a PDF's header and trailer alone take a few dozen bytes.
+const MIN_EPUB_SIZE: u64 = 1024;
+const MIN_OTHER_SIZE: u64 = 32;
+
+// Where the zip "end of central directory" record may start: its fixed size, plus a comment of
+// up to 64 KB.
+const EOCD_SIZE: usize = 22;
+const EOCD_SEARCH: usize = EOCD_SIZE + u16::MAX as usize;
+
+
+//
+// What's wrong with a document on the disk: a payload that's too small or not a zip, or sidecars
+// that don't parse or don't say what they should. Found after a crash halfway through writing,
+// see check().
+//
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    NoPayload,
+    TooSmall(String, u64),
+    BrokenZip(String),
+    Unparsable(String, String),
+    WrongType(String),
+    WrongParent(String),
+    WrongUuid(Uuid),
+}
+
+
+impl Serialize for Problem {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NoPayload => write!(f, "no epub, pdf or html"),
+            Problem::TooSmall(ext, bytes) => write!(f, "{ext} is only {bytes} bytes"),
+            Problem::BrokenZip(reason) => write!(f, "epub is not a readable zip, {reason}"),
+            Problem::Unparsable(ext, e) => write!(f, ".{ext} doesn't parse: {e}"),
+            Problem::WrongType(ftype) => write!(f, ".content says it's a {ftype}"),
+            Problem::WrongParent(parent) => write!(f, ".metadata puts it in {parent}"),
+            Problem::WrongUuid(uuid) => write!(f, "epub is document {uuid}'s"),
+        }
+    }
+}
+
+
+//
+// Everything that's wrong with the document with the given UUID, nothing if it's fine. The
+// parent is checked when given, i.e. right after writing it, but not later on, when the user may
+// well have moved it.
+//
+pub fn check(uuid: &str, parent: Option<&str>) -> Vec<Problem> {
+    let path = |ext: &str| format!("{}/{uuid}.{ext}", xochitl_root());
+    let mut problems = Vec::new();
+
+    let payload = ["epub", "pdf", "html"].into_iter()
+        .find_map(|ext| fs::read(path(ext)).ok().map(|data| (ext, data)));
+
+    match &payload {
+        None => problems.push(Problem::NoPayload),
+        Some((ext, data)) => {
+            let min = match *ext {
+                "epub" => MIN_EPUB_SIZE,
+                _ => MIN_OTHER_SIZE,
+            };
+
+            if (data.len() as u64) < min {
+                problems.push(Problem::TooSmall(ext.to_string(), data.len() as u64));
+            } else if *ext == "epub" {
+                match central_directory(data) {
+                    Ok(_) => problems.extend(wrong_uuid(uuid, data)),
+                    Err(reason) => problems.push(Problem::BrokenZip(reason)),
+                }
+            }
+        },
+    }
+
+    match fs::read_to_string(path("content")).map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).map_err(|e| e.to_string())) {
+        Ok(content) => {
+            let ftype = content["fileType"].as_str().unwrap_or_default().to_string();
+
+            if let Some((ext, _)) = &payload {
+                if ftype != *ext {
+                    problems.push(Problem::WrongType(ftype));
+                }
+            }
+        },
+        Err(e) => problems.push(Problem::Unparsable("content".to_string(), e)),
+    }
+
+    match fs::read_to_string(path("metadata")).map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str::<Metadata>(&text).map_err(|e| e.to_string())) {
+        Ok(metadata) => {
+            if parent.is_some_and(|parent| parent != metadata.parent) {
+                problems.push(Problem::WrongParent(metadata.parent));
+            }
+        },
+        Err(e) => problems.push(Problem::Unparsable("metadata".to_string(), e)),
+    }
+
+    problems
+}
+
+
+// Finds the zip's central directory through the record at its end, and checks that it's where
+// that record says, with as many entries. Returns how many there are.
+pub fn central_directory(data: &[u8]) -> Result<usize, String> {
+    let u16_at = |pos: usize| u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+    let u32_at = |pos: usize| u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+
+    if data.len() < EOCD_SIZE {
+        return Err("too short for a zip".to_string());
+    }
+
+    let lowest = data.len().saturating_sub(EOCD_SEARCH);
+    let eocd = (lowest..=data.len() - EOCD_SIZE).rev()
+        .find(|pos| data[*pos..].starts_with(b"PK\x05\x06"))
+        .ok_or("no end of central directory, likely truncated")?;
+
+    let entries = u16_at(eocd + 10);
+    let size = u32_at(eocd + 12);
+    let offset = u32_at(eocd + 16);
+
+    // Sizes off a broken zip overflow a 32 bit usize, as on the device, as easily as not.
+    if !offset.checked_add(size).is_some_and(|end| end <= eocd) {
+        return Err("the central directory is past its end".to_string());
+    }
+
+    // Walk the entries, each one starting with its own signature.
+    let mut pos = offset;
+
+    for _ in 0..entries {
+        if !pos.checked_add(46).is_some_and(|end| end <= eocd) || !data[pos..].starts_with(b"PK\x01\x02") {
+            return Err("a central directory entry is missing".to_string());
+        }
+
+        pos = [46, u16_at(pos + 28), u16_at(pos + 30), u16_at(pos + 32)].into_iter()
+            .try_fold(pos, usize::checked_add)
+            .ok_or("a central directory entry is past its end")?;
+    }
+
+    if entries == 0 {
+        return Err("no entries".to_string());
+    }
+
+    Ok(entries)
+}
+
+
+// The epub's identifier, when it's a document's UUID and not that of the one it's named after.
+// Those written before they carried it have a random one, which isn't held against them.
+fn wrong_uuid(uuid: &str, data: &[u8]) -> Option<Problem> {
+    let identifier = epub_identifier(data)?;
+
+    match identifier.get_version() == Some(Version::Sha1) && Uuid::parse_str(uuid).ok() != Some(identifier) {
+        true => Some(Problem::WrongUuid(identifier)),
+        false => None,
+    }
+}
+
+
+// The urn:uuid: in the epub's content.opf, if there's one to be read.
+fn epub_identifier(data: &[u8]) -> Option<Uuid> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(data)).ok()?;
+    let mut opf = String::new();
+    zip.by_name("OEBPS/content.opf").ok()?.read_to_string(&mut opf).ok()?;
+
+    let start = opf.find("urn:uuid:")? + "urn:uuid:".len();
+    Uuid::parse_str(opf.get(start..start + 36)?).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A zip with one stored entry, as small as they come.
+    fn zip(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let sizes = [(data.len() as u32).to_le_bytes(), (data.len() as u32).to_le_bytes()].concat();
+
+        zip.extend(b"PK\x03\x04\x0a\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        zip.extend(&sizes);
+        zip.extend((name.len() as u16).to_le_bytes());
+        zip.extend([0, 0]);
+        zip.extend(name.as_bytes());
+        zip.extend(data);
+
+        let directory = zip.len();
+        zip.extend(b"PK\x01\x02\x14\x00\x0a\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        zip.extend(&sizes);
+        zip.extend((name.len() as u16).to_le_bytes());
+        zip.extend([0; 12]);
+        zip.extend(0u32.to_le_bytes());
+        zip.extend(name.as_bytes());
+        let size = zip.len() - directory;
+
+        zip.extend(b"PK\x05\x06\x00\x00\x00\x00\x01\x00\x01\x00");
+        zip.extend((size as u32).to_le_bytes());
+        zip.extend((directory as u32).to_le_bytes());
+        zip.extend([0, 0]);
+
+        zip
+    }
+
+    #[test]
+    fn truncated_zips() {
+        let whole = zip("mimetype", b"application/epub+zip");
+        assert_eq!(central_directory(&whole), Ok(1));
+
+        // Anywhere it's cut, there's no end to find, or the directory isn't where it says.
+        for len in [0, 10, whole.len() / 2, whole.len() - 1] {
+            assert!(central_directory(&whole[..len]).is_err(), "{len}");
+        }
+
+        // The end is there, the directory isn't.
+        let mut holed = whole.clone();
+        let directory = holed.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+        holed[directory] = b'X';
+        assert!(central_directory(&holed).is_err());
+
+        // An end that says the directory is as far as it gets, and as large.
+        let mut overflowing = whole.clone();
+        let end = overflowing.len() - 10;
+        overflowing[end..end + 8].copy_from_slice(&[0xff; 8]);
+        assert!(central_directory(&overflowing).is_err());
+    }
+
+    #[test]
+    fn epub_uuids() {
+        let document = Uuid::new_v5(&Uuid::NAMESPACE_OID, b"https://example.com/article");
+        let other = Uuid::new_v5(&Uuid::NAMESPACE_OID, b"https://example.com/other");
+        let opf = |uuid: Uuid| zip("OEBPS/content.opf", format!("<dc:identifier id=\"epub-id-1\">urn:uuid:{uuid}</dc:identifier>").as_bytes());
+        let name = document.hyphenated().to_string();
+
+        assert_eq!(epub_identifier(&opf(document)), Some(document));
+        assert_eq!(wrong_uuid(&name, &opf(document)), None);
+        assert_eq!(wrong_uuid(&name, &opf(other)), Some(Problem::WrongUuid(other)));
+
+        // From before epubs carried it, or without one at all.
+        assert_eq!(wrong_uuid(&name, &opf(Uuid::from_u128(0x936d_a01f_9abd_4d9d_80c7_02af_85c8_22a8))), None);
+        assert_eq!(wrong_uuid(&name, &zip("mimetype", b"application/epub+zip")), None);
+    }
+}
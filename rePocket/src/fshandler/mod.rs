@@ -14,30 +14,84 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use std::collections::{BTreeMap, btree_map};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::io::{BufReader, BufWriter, Write};
+use std::collections::{BTreeMap, BTreeSet, HashMap, btree_map, btree_set};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
 use std::fs::read;
 use std::fs::File;
+use std::fmt;
+use std::path::Path;
 use std::str;
 use uuid::Uuid;
 use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use serde_json::json;
 
-use crate::pocketitem::PocketItem;
-use crate::articlehandler::ArticleHandler;
+pub mod error;
+pub mod flags;
+pub mod integrity;
+pub mod progress;
+pub mod rebuild;
+pub mod root;
+
+use crate::pocket::PocketAction;
+use crate::pocketitem::{PocketItem, ItemStatus};
+use crate::articlehandler::{self, ArticleHandler, ArticleOptions, ExtractionTrace};
+use crate::settings::{DeviceArchive, PocketRead, ReconcileTarget, Settings, Status, StatusTags, UuidSource, DEFAULT_QUERY};
+use crate::clock;
+use crate::crashlog;
+use crate::logging;
+use crate::device;
+use crate::cookies;
+use crate::screen;
+use crate::strings;
 use crate::utils;
+use root::xochitl_root;
+pub use error::FsError;
+use flags::ItemFlags;
+use integrity::Problem;
 
 
-#[cfg(not(target_abi = "eabihf"))]
-pub const XOCHITL_ROOT  : &'static str = concat!(env!("HOME"), "/", ".local/share/remarkable/xochitl");
-#[cfg(not(target_abi = "eabihf"))]
-pub const CONFIG_FILE   : &'static str = env!("CONFIG_FILE_HOST");
+// Where xochitl keeps its documents on a reMarkable, and where it's played along on other machines.
+pub const DEVICE_XOCHITL_ROOT: &'static str = "/home/root/.local/share/remarkable/xochitl";
+pub const HOST_XOCHITL_ROOT: &'static str = concat!(env!("HOME"), "/", ".local/share/remarkable/xochitl");
 
-#[cfg(target_abi = "eabihf")]
-pub const XOCHITL_ROOT  : &'static str = "/home/root/.local/share/remarkable/xochitl";
-#[cfg(target_abi = "eabihf")]
-pub const CONFIG_FILE   : &'static str = env!("CONFIG_FILE_RM");
+// Items listed in each section of `repocket status`, the rest are only counted, they're all in the
+// library document, see the library module, and in `repocket status --json`.
+const STATUS_LISTED: usize = 50;
+
+
+pub fn default_xochitl_root() -> &'static str {
+    match device::kind().is_device() {
+        true => DEVICE_XOCHITL_ROOT,
+        false => HOST_XOCHITL_ROOT,
+    }
+}
+
+
+pub fn config_file() -> &'static str {
+    match device::kind().is_device() {
+        true => env!("CONFIG_FILE_RM"),
+        false => env!("CONFIG_FILE_HOST"),
+    }
+}
+
+
+// Where the state is, and what goes with it, e.g. the crash logs.
+pub fn state_dir() -> std::path::PathBuf {
+    Path::new(config_file()).parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+
+// A document's UUID, always the same for the same URL, or for the same item id. Items without an
+// id go by their URL either way.
+pub fn document_uuid(source: UuidSource, id: Option<u64>, url: &str) -> Uuid {
+    match (source, id) {
+        (UuidSource::ItemId, Some(id)) => Uuid::new_v5(&Uuid::NAMESPACE_OID, format!("pocket-item:{id}").as_bytes()),
+        _ => Uuid::new_v5(&Uuid::NAMESPACE_OID, url.as_bytes()),
+    }
+}
+
+// Times to read a document's metadata again when someone else keeps writing to it.
+const METADATA_ATTEMPTS: usize = 3;
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -62,8 +116,108 @@ pub struct FSHandler {
     #[allow(dead_code)]
     #[serde(skip)]
     read_items: BTreeMap<UniqID, u64>,
+    // Archived on the device while in read-only upstream mode, never archived in Pocket.
+    #[serde(default)]
+    withheld_items: BTreeMap<UniqID, u64>,
+    // Archived in Pocket, but not yet tagged, and with what, see sync::archive_upstream().
+    #[serde(default)]
+    pending_actions: BTreeMap<u64, String>,
+    // Items that could not be turned into a document, and why.
+    #[serde(default)]
+    failed_items: BTreeMap<u64, FailedItem>,
+    // The error tag each of those has in Pocket, see error_retags().
+    #[serde(default)]
+    error_tagged: BTreeMap<u64, String>,
+    // The "rePocket Failed!" documents written for some of those, by item id, see write_placeholder().
+    #[serde(default)]
+    placeholders: BTreeMap<u64, UniqID>,
+    #[serde(skip)]
+    write_placeholders: bool,
+    // The languages synced, and the items only found not to be in one of those once fetched, see
+    // new_article().
+    #[serde(skip)]
+    languages: Vec<String>,
+    #[serde(skip)]
+    other_language: BTreeMap<u64, String>,
+    // What new documents' UUIDs are made from, see document_uuid().
+    #[serde(skip)]
+    uuid_source: UuidSource,
+    // What's done in Pocket with read items, for consolidate()'s plan, see configure(). None when
+    // Pocket is only read from.
+    #[serde(skip)]
+    status_tags: StatusTags,
+    #[serde(skip)]
+    pocket_read: Option<PocketRead>,
+    // What each item's document turned out like, see ItemFlags.
+    #[serde(default)]
+    flags: BTreeMap<u64, ItemFlags>,
+    // How each item's document came about, see ExtractionTrace.
+    #[serde(default)]
+    traces: BTreeMap<u64, ExtractionTrace>,
+    // Items pinned in xochitl, or tagged as such in Pocket, which rePocket never moves nor archives.
+    #[serde(default)]
+    pinned_on_device: BTreeSet<u64>,
+    #[serde(default)]
+    pinned_by_tag: BTreeSet<u64>,
+    // How far into each document the user got, in percent, and the progress bucket last tagged in
+    // Pocket, see the progress module.
+    #[serde(default)]
+    progress: BTreeMap<u64, u8>,
+    #[serde(default)]
+    progress_tagged: BTreeMap<u64, u8>,
+    // The "since" of each query, by name, see Settings::queries(). Before there were several it was
+    // the one in ts_last_query, moved over by consolidate().
+    #[serde(default)]
+    ts_last_queries: BTreeMap<String, u64>,
     #[serde(default)]
     ts_last_query: u64,
+    // Syncs since the tracked items were last checked against Pocket, and the "since" of that check.
+    #[serde(default)]
+    syncs_since_reconcile: u64,
+    #[serde(default)]
+    ts_last_reconcile: u64,
+    // Where xochitl's storage was found the last time around.
+    #[serde(default)]
+    xochitl_root: String,
+    // Bytes downloaded per calendar month, e.g. "2024-10".
+    #[serde(default)]
+    monthly_bytes: BTreeMap<String, u64>,
+    // Items brought back from Pocket's archive, already archived and tagged over there.
+    #[serde(default)]
+    restored_items: BTreeSet<u64>,
+    // Archived items shown in the Archive folder for rereading, see mirror_archive(). Those moved
+    // back into the Pocket folder are re-added in Pocket, and then left alone when Pocket returns
+    // them, see take_readded().
+    #[serde(default)]
+    mirrored_items: BTreeMap<UniqID, u64>,
+    #[serde(default)]
+    readd_items: BTreeSet<u64>,
+    #[serde(default)]
+    readded_items: BTreeSet<u64>,
+    // Items too large to fetch but on the charger, by id, as Pocket returned them, see
+    // articlehandler::precheck().
+    #[serde(default)]
+    large_items: BTreeMap<u64, PocketItem>,
+    // Documents of rePocket's own kept pinned in the Pocket folder, e.g. the help, by name, with
+    // the version written, see write_pinned_note().
+    #[serde(default)]
+    pinned_notes: BTreeMap<String, u32>,
+    // Of those written whenever what they say changes, e.g. the library, a digest of what they
+    // said last, see library::digest().
+    #[serde(default)]
+    note_digests: BTreeMap<String, String>,
+    // Where read documents go, as the device tree was last laid out for.
+    #[serde(default)]
+    device_archive: DeviceArchive,
+    // The roots documents are also copied to, besides xochitl_root(), see set_extra_roots().
+    #[serde(skip)]
+    extra_roots: Vec<String>,
+    // The documents waiting to be copied to each of those, by root, e.g. while it's unmounted.
+    #[serde(default)]
+    extra_root_pending: BTreeMap<String, BTreeSet<String>>,
+    // Where the state is saved, config_file() unless told otherwise.
+    #[serde(skip)]
+    config_file: Option<String>,
 }
 
 
@@ -110,37 +264,75 @@ impl FSHandler {
             archived_items: BTreeMap::new(),
             new_items: BTreeMap::new(),
             read_items: BTreeMap::new(),
+            withheld_items: BTreeMap::new(),
+            pending_actions: BTreeMap::new(),
+            failed_items: BTreeMap::new(),
+            error_tagged: BTreeMap::new(),
+            placeholders: BTreeMap::new(),
+            write_placeholders: false,
+            languages: Vec::new(),
+            other_language: BTreeMap::new(),
+            uuid_source: UuidSource::Url,
+            status_tags: StatusTags::default(),
+            pocket_read: Some(PocketRead::Archive),
+            flags: BTreeMap::new(),
+            traces: BTreeMap::new(),
+            pinned_on_device: BTreeSet::new(),
+            pinned_by_tag: BTreeSet::new(),
+            progress: BTreeMap::new(),
+            progress_tagged: BTreeMap::new(),
+            ts_last_queries: BTreeMap::new(),
             ts_last_query: 0,
+            syncs_since_reconcile: 0,
+            ts_last_reconcile: 0,
+            xochitl_root: xochitl_root().to_string(),
+            monthly_bytes: BTreeMap::new(),
+            restored_items: BTreeSet::new(),
+            mirrored_items: BTreeMap::new(),
+            readd_items: BTreeSet::new(),
+            readded_items: BTreeSet::new(),
+            large_items: BTreeMap::new(),
+            pinned_notes: BTreeMap::new(),
+            note_digests: BTreeMap::new(),
+            device_archive: DeviceArchive::Folder,
+            extra_roots: Vec::new(),
+            extra_root_pending: BTreeMap::new(),
+            config_file: None,
         }
     }
 
-    pub fn load() -> Self {
-        // Read the CONFIG_FILE if it exists,
-        let config = read(CONFIG_FILE);
+    pub fn load() -> Result<Self, FsError> {
+        Self::load_from(config_file())
+    }
 
-        match config {
-            Ok(data) => {
-                // Create a Self from the data.
-                let mut loaded : Self = serde_json::from_slice(&data).unwrap();
 
-                // TODO: Call consolidate() (perhaps this is the action that we can trigger manually?)
-                loaded.consolidate();
+    // The state kept somewhere other than config_file(), e.g. when syncing against the offline
+    // fixtures. It's also saved there.
+    pub fn load_from(fname: &str) -> Result<Self, FsError> {
+        let mut handler = Self::load_unconsolidated(fname)?;
+        handler.consolidate();
 
-                loaded
-            },
-            Err(_) => {
-                // otherwise, call new()
-                let new = Self::new();
+        Ok(handler)
+    }
 
-                // call to create the pocket folder
-                let res = new.mkdir_pocket();
 
-                match res {
-                    Ok(()) => new,
-                    Err(e) => panic!("BAD STUFF {e}"),
-                }
-            },
+    // The state just as it was saved, for a look at what consolidate() would do with it. A state
+    // that can't be read is an error rather than a new one, which would write every article again.
+    pub fn load_unconsolidated(fname: &str) -> Result<Self, FsError> {
+        let mut handler = match read(fname) {
+            // Create a Self from the data,
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| FsError::serde(fname, e))?,
+            // otherwise, call new(). The folders are created by mkdir_pocket(), once it's known
+            // whether there's an Archive folder at all.
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::new(),
+            Err(e) => return Err(FsError::io(fname, e)),
+        };
+
+        if fname != config_file() {
+            handler.config_file = Some(fname.to_string());
         }
+
+        Ok(handler)
     }
 
 
@@ -152,7 +344,10 @@ impl FSHandler {
     //      "folder": "string",
     //      "archive": "string",
     //      "sync_trigger": "string",
-    //      "ts_last_query": integer,
+    //      "ts_last_queries": {
+    //          "string": integer,
+    //          ...
+    //      },
     //      "current_items": {
     //          "string" :integer,
     //          ...
@@ -165,432 +360,3724 @@ impl FSHandler {
     //      }
     // }
     //
-    pub fn save_config(&self) {
-        match File::create(CONFIG_FILE) {
-            Ok(fh) => {
-                let buffer = BufWriter::new(fh);
-                match serde_json::to_writer(buffer, self) {
-                    Ok(()) => (),
-                    Err(e) => println!("🚨 Cannot convert string to json! {e}"),
-                }
-            },
-            Err(e) => panic!("🚨 Cannot save configuration file! {e}"),
+    // Written next to it and moved into place, as `repocket status` may read it while a sync
+    // saves, and should find the last state whole rather than half of the new one.
+    pub fn save_config(&self) -> Result<(), FsError> {
+        let fname = self.state_file();
+        let part = format!("{fname}.part");
+        let mut buffer = BufWriter::new(File::create(&part).map_err(|e| FsError::io(fname, e))?);
+
+        serde_json::to_writer(&mut buffer, self).map_err(|e| FsError::serde(fname, e))?;
+        buffer.flush().map_err(|e| FsError::io(fname, e))?;
+        drop(buffer);
+        std::fs::rename(&part, fname).map_err(|e| FsError::io(fname, e))?;
+
+        if logging::debug() {
+            println!("🪼 Saved the state to {fname}");
         }
+
+        Ok(())
+    }
+
+
+    pub fn mkdir_pocket(&self) -> Result<(), FsError> {
+        self.mkdir_pocket_named("Pocket")
     }
 
 
-    pub fn mkdir_pocket(&self) -> Result<(), std::io::Error> {
+    // The Pocket folder is only named when it's made, renaming it is up to the user.
+    // Every folder is made, or finished, whatever became of the others. The first error, if any.
+    pub fn mkdir_pocket_named(&self, name: &str) -> Result<(), FsError> {
+        if !Path::new(xochitl_root()).is_dir() {
+            return Err(FsError::RootUnavailable(xochitl_root().to_string()));
+        }
+
         // Pocket directory:
-        let _ = self.mkdir(&self.parent_uuid_string(), "Pocket", "");
+        let pocket_res = self.mkdir(&self.parent_uuid_string(), name, "");
 
-        // Pocket/Archive directory:
-        let pocket_res = self.mkdir(&self.archive_uuid_string(), "Archive", &self.parent_uuid_string());
+        // Pocket/Archive directory, unless read documents go straight to the trash:
+        let archive_res = match self.device_archive {
+            DeviceArchive::Trash => Ok(()),
+            _ => self.mkdir(&self.archive_uuid_string(), "Archive", &self.parent_uuid_string()),
+        };
 
         // Pocket/Sync directory:
-        self.mkdir(&self.sync_uuid_string(), "Sync", &self.parent_uuid_string())?;
+        let sync_res = self.mkdir(&self.sync_uuid_string(), "Sync", &self.parent_uuid_string());
 
-        pocket_res
+        pocket_res.and(archive_res).and(sync_res)
     }
 
 
-    fn mkdir(&self, uuid: &str, name: &str, parent: &str) -> Result<(), std::io::Error> {
-        // Create a file with the new UUID
-        let fname_content: String = XOCHITL_ROOT.to_string() + "/" + uuid + ".content";
-        let mut fh = File::create_new(fname_content)?;
-        writeln!(fh, "{{}}")?;
+    // Switching to a mode that needs the Archive folder creates it, switching away leaves it, and
+    // whatever is in it, alone.
+    pub fn set_device_archive(&mut self, mode: DeviceArchive) {
+        if mode == self.device_archive {
+            return;
+        }
 
-        // Create the metadatafile
-        let metadata = Metadata::new("CollectionType", name, parent);
-        let json = metadata.json()?;
-        let fname_meta = XOCHITL_ROOT.to_string() + "/" + uuid + ".metadata";
-        let mut fh = File::create_new(fname_meta)?;
-        writeln!(fh, "{}", json)?;
+        println!("ℹ Read documents now go to {mode:?}, they went to {:?}", self.device_archive);
+        self.device_archive = mode;
 
-        Ok(())
+        if mode != DeviceArchive::Trash && !self.has_archive_folder() {
+            let _ = self.mkdir(&self.archive_uuid_string(), "Archive", &self.parent_uuid_string());
+        }
     }
 
 
-    pub async fn new_article(&mut self, item: &PocketItem) {
-        // Create a file with the new UUID
-        let mut article = ArticleHandler::new(item);
-        article.save_file("epub", XOCHITL_ROOT).await;
+    pub fn set_failure_placeholders(&mut self, on: bool) {
+        self.write_placeholders = on;
+    }
 
-        // Create the content file
-        let fname_content = XOCHITL_ROOT.to_string() + "/" + &article.uuid_string() + ".content";
-        let content = Content::new("epub");
-        Self::write_file(&fname_content, &content);
 
-        // Create the metadata file
-        let fname_meta = XOCHITL_ROOT.to_string() + "/" + &article.uuid_string() + ".metadata";
-        let metadata = Metadata::new("DocumentType", &article.title(), &self.parent_uuid_string());
-        Self::write_file(&fname_meta, &metadata);
+    pub fn set_languages(&mut self, languages: &[String]) {
+        self.languages = languages.to_vec();
+    }
 
-        // Add the article to the self.new_items
-        self.new_items.insert(UniqID{uuid: article.uuid()},
-            item.get_resolved_id().expect("🚨 Expected ID, found None"));
+
+    //
+    // Every document written to xochitl_root() is also copied to these, e.g. to another device's
+    // mounted over sshfs while moving to it. Nothing is read from them, what's read, archived or
+    // deleted is only ever told by xochitl_root(). A root that isn't there when a document is
+    // written gets it later on, see catch_up_extra_roots().
+    //
+    pub fn set_extra_roots(&mut self, roots: &[String]) {
+        self.extra_roots = roots.iter().filter(|root| root.as_str() != xochitl_root()).cloned().collect();
+
+        // Whatever was waiting for a root no longer set is let go.
+        self.extra_root_pending.retain(|root, _| self.extra_roots.contains(root));
     }
 
 
-    fn write_file<T>(fname: &str, data: &T) where T: Serialize + std::fmt::Debug {
-        let json = serde_json::to_string(&data).expect("🚨 Failed to create json from contents: Content");
-        match File::create(fname) {
-            Ok(mut fh) => {
-                writeln!(fh, "{}", json).expect("🚨 Failed to write contents to file");
-            },
-            Err(_) => println!("ℹ {fname} already exists, not rewritting."),
+    async fn copy_to_extra_roots(&mut self, uuid: &str) {
+        if self.extra_roots.is_empty() {
+            return;
         }
-    }
 
+        for root in &self.extra_roots {
+            self.extra_root_pending.entry(root.clone()).or_default().insert(uuid.to_string());
+        }
 
-    pub fn last_query_ts(&self) -> u64 {
-        self.ts_last_query
+        self.catch_up_extra_roots().await;
     }
 
 
-    pub fn set_last_query_ts(&mut self, ts: u64) {
-        self.ts_last_query = ts;
-    }
+    // Copy what's waiting to the extra roots that are there, each on its own. Returns how many
+    // documents were copied.
+    pub async fn catch_up_extra_roots(&mut self) -> usize {
+        if self.extra_root_pending.is_empty() {
+            return 0;
+        }
 
+        let from = xochitl_root().to_string();
+        let mut pending = self.extra_root_pending.clone();
 
-    pub fn read_ids(&self) -> btree_map::IntoValues<UniqID, u64> {
-        self.read_items.clone().into_values()
+        // Off the runtime's threads, a root over sshfs may take its time.
+        let caught_up = tokio::task::spawn_blocking(move || {
+            let copied = root::catch_up(&from, &mut pending);
+            (copied, pending)
+        }).await;
+
+        match caught_up {
+            Ok((copied, pending)) => {
+                self.extra_root_pending = pending;
+                copied
+            },
+            Err(e) => {
+                println!("🚨 Could not copy to the other roots, trying again with the next sync: {e}");
+                0
+            },
+        }
     }
 
 
-    pub fn consolidate(&mut self) {
-        // The first issue needing consolidation is the introduction of the "archive" folder.
-        // Since it is a new field, it may not be loaded from the config file. If that's the case,
-        // force a proper UUID.
-        if self.archive.uuid.is_nil() {
-            self.archive = UniqID::new();
-        }
+    pub fn set_uuid_source(&mut self, source: UuidSource) {
+        self.uuid_source = source;
+    }
 
-        if self.sync_trigger.uuid.is_nil() {
-            self.sync_trigger = UniqID::new();
-        }
 
+    // The UUID the item's document gets, its placeholder's too, see document_uuid().
+    pub fn document_uuid(&self, item: &PocketItem) -> Uuid {
+        document_uuid(self.uuid_source, item.best_id(), &item.best_url().unwrap_or_default())
+    }
 
-        // Go through the list of current items:
-        // - If the files is missing, then archive in pocket
-        // - If the files exist, but the metadata indicates 'deleted', then archive in pocket
-        // - Otherwise it's all good.
-        for (uid, _) in self.current_items.clone() {
-            let fname = XOCHITL_ROOT.to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".metadata";
-            let metadata = Metadata::load(&fname);
-
-            if metadata.parent != self.parent_uuid_string() {
-                // That is, it is one of:
-                // - self.archive_uuid_string()
-                // - "trash"
-                // - it was moved somewhere else
-
-                // Move item from current list to read list.
-                if let Some(val) = self.current_items.remove(&uid) {
-                    // Only archive those moved to the archive folder, otherwise assume that the
-                    // user is intentionally breaking syncing with Pocket.
-                    if metadata.parent == self.archive_uuid_string() {
-                        self.read_items.insert(uid.clone(), val);
-                        println!("ℹ Moved item with uuid {} into the read_items list", &utils::uuid_to_string(uid.uuid));
-                    }
-                }
-            }
-        }
 
-        // Then move the new items to the current items list.
-        for (uid, _) in self.new_items.clone() {
-            if let Some(val) = self.new_items.remove(&uid) {
-                self.current_items.insert(uid.clone(), val);
-                println!("ℹ Moved item with uuid {} into the current_items list", &utils::uuid_to_string(uid.uuid));
-            }
-        }
+    // What the settings say becomes of read documents, on the device and in Pocket, for
+    // plan_consolidation() to go by. Nothing is made on the device, set_device_archive() first for
+    // the folders when switching modes.
+    pub fn configure(&mut self, settings: &Settings) {
+        self.device_archive = settings.device_archive();
+        self.status_tags = settings.status_tags().clone();
+        self.pocket_read = Some(settings.pocket_read()).filter(|_| !settings.read_only_upstream());
     }
 
 
-    pub fn parent_uuid_string(&self) -> String {
-        utils::uuid_to_string(self.folder.uuid)
+    // The language of the item, if new_article() left it out for it, once.
+    pub fn take_other_language(&mut self, item: &PocketItem) -> Option<String> {
+        item.best_id().and_then(|id| self.other_language.remove(&id))
     }
 
 
-    pub fn archive_uuid_string(&self) -> String {
-        utils::uuid_to_string(self.archive.uuid)
+    pub fn has_pocket_folder(&self) -> bool {
+        Path::new(&(xochitl_root().to_string() + "/" + &self.parent_uuid_string() + ".metadata")).exists()
     }
 
 
-    pub fn sync_uuid_string(&self) -> String {
-        utils::uuid_to_string(self.sync_trigger.uuid)
+    fn has_archive_folder(&self) -> bool {
+        Path::new(&(xochitl_root().to_string() + "/" + &self.archive_uuid_string() + ".metadata")).exists()
     }
 
 
+    // Whether a document moved out of the Pocket folder into parent was read. The Archive folder
+    // counts whatever the mode, there may be one from before switching to "trash".
+    fn read_into(&self, parent: &str) -> bool {
+        parent == self.archive_uuid_string() || (self.device_archive == DeviceArchive::Trash && parent == "trash")
+    }
 
-    pub fn clear_read(&mut self) {
-        // Move items to the trash in Xochitl, then clear the btreemap
-        for (uid, _) in self.read_items.clone() {
-            let fname = XOCHITL_ROOT.to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".metadata";
-            let mut metadata = Metadata::load(&fname);
 
-            // "Move" the item to the trash.
-            metadata.parent = "trash".to_string();
-            // Rewrite the file.
-            Self::write_file(&fname, &metadata);
+    //
+    // Make the folder, or whatever's missing of it, so that one half made by an earlier run, e.g.
+    // on a full disk, is finished rather than in the way for good. A folder's metadata is left as
+    // it is, the user may have renamed or moved it, one that doesn't parse is written again. Only
+    // a document's metadata with the folder's UUID is an error, besides those of the disk.
+    //
+    fn mkdir(&self, uuid: &str, name: &str, parent: &str) -> Result<(), FsError> {
+        let fname_content: String = xochitl_root().to_string() + "/" + uuid + ".content";
+        let fname_meta = xochitl_root().to_string() + "/" + uuid + ".metadata";
+
+        let write_meta = match Metadata::try_load(&fname_meta) {
+            Some(metadata) if metadata.dtype == "CollectionType" => false,
+            Some(_) => return Err(FsError::io(&fname_meta, std::io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("it's a document's, not the {name} folder's"),
+            ))),
+            None => true,
+        };
+
+        // The content file first, xochitl only goes by the metadata.
+        if !Path::new(&fname_content).exists() {
+            std::fs::write(&fname_content, "{}\n").map_err(|e| FsError::io(&fname_content, e))?;
+        }
 
-            // Remove the item from the read list.
-            if let Some(val) = self.read_items.remove(&uid) {
-                self.archived_items.insert(uid.clone(), val);
-                println!("ℹ Archived item with uuid {}", &utils::uuid_to_string(uid.uuid));
+        if write_meta {
+            if Path::new(&fname_meta).exists() {
+                println!("ℹ Writing the metadata of the {name} folder again, it was unreadable");
             }
+
+            let json = Metadata::new("CollectionType", name, parent).json().map_err(|e| FsError::serde(&fname_meta, e))?;
+            std::fs::write(&fname_meta, json + "\n").map_err(|e| FsError::io(&fname_meta, e))?;
         }
+
+        Ok(())
     }
-}
 
 
-#[derive(Clone, Default, Debug, Eq, PartialEq, Ord, PartialOrd)]
-pub struct UniqID {
-    uuid: Uuid,
-}
+    // The id an item is written by, as long as there's a URL to write it from.
+    fn required(item: &PocketItem) -> Result<u64, FsError> {
+        let missing = |field| FsError::MissingField { title: item.get_title(), field };
 
-impl UniqID {
-    pub fn new() -> Self {
-        Self {
-            uuid : Uuid::new_v4(),
+        match (item.best_id(), item.best_url()) {
+            (Some(id), Some(_)) => Ok(id),
+            (None, _) => Err(missing("id")),
+            (_, None) => Err(missing("URL")),
         }
     }
-}
 
 
-impl Serialize for UniqID {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
+    pub async fn new_article(&mut self, item: &PocketItem, options: ArticleOptions) -> Option<Written> {
+        let id = match Self::required(item) {
+            Ok(id) => id,
+            Err(e) => {
+                println!("🚨 Not writing the item: {e}");
+                return None;
+            },
+        };
 
-        let mut ebuf = Uuid::encode_buffer();
-        serializer.serialize_str(self.uuid.hyphenated().encode_lower(&mut ebuf))
+        // Create a file with the new UUID
+        let mut article = ArticleHandler::new(item).set_options(options).set_uuid(self.document_uuid(item));
+        // No document, no sidecars, xochitl would show a broken one.
+        let path = match article.save_file("epub", xochitl_root()).await {
+            Ok(path) => path,
+            Err(e) => {
+                let reason = cookies::redact(&e.to_string());
+                println!("🚨 Not writing item id {id}: {reason}");
+                self.fail_item(item, &reason);
+                self.trace_failure(item, article.trace());
+                self.write_placeholder(item, &reason);
+                return None;
+            },
+        };
+
+        // Pocket had no language for the item, the page says it's one not synced. It's skipped
+        // rather than failed, whatever was there for it goes.
+        let other_language = article.lang()
+            .filter(|lang| item.get_lang().is_none() && screen::other_language(lang, &self.languages));
+
+        if let Some(lang) = other_language {
+            println!("ℹ Not keeping item id {id}, it's in '{lang}'");
+            Self::remove_document(&article.uuid_string());
+            self.placeholders.remove(&id);
+            self.failed_items.remove(&id);
+            self.other_language.insert(id, lang);
+            return None;
+        }
+
+        crashlog::stage("saving");
+
+        // Create the content file
+        let fname_content = xochitl_root().to_string() + "/" + &article.uuid_string() + ".content";
+        let mut content = Content::new("epub");
+        if let Some(scale) = article.text_scale() {
+            content = content.set_text_scale(scale);
+        }
+
+        // Create the metadata file, over a placeholder's a version up, for xochitl to notice.
+        let fname_meta = xochitl_root().to_string() + "/" + &article.uuid_string() + ".metadata";
+        let mut metadata = Metadata::new("DocumentType", &article.title(), &self.parent_uuid_string());
+        let placeholder = self.placeholders.get(&id);
+        if let Some(replaced) = placeholder.and_then(|_| Metadata::try_load(&fname_meta)) {
+            metadata.version = replaced.version + 1;
+        }
+
+        // Read it all back before it's tracked, a crash or a full disk may have left less.
+        let written = Self::write_file(&fname_content, &content).and_then(|()| Self::write_file(&fname_meta, &metadata));
+        let problems: Vec<String> = match written {
+            Ok(()) => integrity::check(&article.uuid_string(), Some(&self.parent_uuid_string())).iter().map(Problem::to_string).collect(),
+            Err(e) => vec![e.to_string()],
+        };
+
+        if !problems.is_empty() {
+            let reason = problems.join(", ");
+            println!("🚨 Not keeping item id {id}, {reason}");
+            Self::remove_document(&article.uuid_string());
+            self.fail_item(item, &reason);
+            self.trace_failure(item, article.trace());
+            self.write_placeholder(item, &reason);
+            return None;
+        }
+
+        // Add the article to the self.new_items
+        if self.placeholders.remove(&id).is_some() {
+            println!("ℹ Replaced the placeholder of item id {id}");
+            self.failed_items.remove(&id);
+        }
+        self.new_items.insert(UniqID{uuid: article.uuid()}, id);
+        self.flags.insert(id, ItemFlags::new(item, &article));
+        self.traces.insert(id, article.trace().clone());
+        self.copy_to_extra_roots(&article.uuid_string()).await;
+
+        Some(Written {
+            uuid: article.uuid_string(),
+            title: article.title(),
+            url: article.url(),
+            format: path.rsplit_once('.').map(|(_, ext)| ext.to_string()).unwrap_or_default(),
+            path,
+        })
     }
-}
 
 
-impl<'de> Deserialize<'de> for UniqID {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s: String = Deserialize::deserialize(deserializer)?;
-        if s.is_empty() {
-            Err(std::io::ErrorKind::InvalidData).map_err(serde::de::Error::custom)
-        } else {
-            Ok(UniqID{ uuid: Uuid::parse_str(&s).map_err(serde::de::Error::custom)? })
+    //
+    // When asked for, a document saying why the item couldn't be written, where the item's own
+    // would have gone. It's not tracked, reading it archives nothing, and writing the item later
+    // on replaces it in place, see new_article(). Once the item is gone from Pocket the
+    // placeholder goes too, see scrub_placeholders().
+    //
+    pub fn write_placeholder(&mut self, item: &PocketItem, reason: &str) {
+        let (id, url) = match (item.best_id(), item.best_url()) {
+            (Some(id), Some(url)) if self.write_placeholders => (id, url),
+            _ => return,
+        };
+
+        let epub = match articlehandler::placeholder_epub(&url, reason) {
+            Ok(epub) => epub,
+            Err(e) => {
+                println!("🚨 Could not make a placeholder for item id {id}: {e}");
+                return;
+            },
+        };
+
+        let uid = UniqID { uuid: self.document_uuid(item) };
+        let uuid = utils::uuid_to_string(uid.uuid);
+        let fname = xochitl_root().to_string() + "/" + &uuid + ".epub";
+
+        if let Err(e) = std::fs::write(&fname, epub) {
+            println!("🚨 Could not write {fname}: {e}");
+            return;
+        }
+
+        let (failed, title) = (strings::current().failed_title, ArticleHandler::new(item).title());
+        let name = match title.is_empty() {
+            true => failed.to_string(),
+            false => format!("{failed}: {title}"),
+        };
+        let written = Self::write_file(&(xochitl_root().to_string() + "/" + &uuid + ".content"), &Content::new("epub"))
+            .and_then(|()| Self::write_file(&Self::metadata_file(&uid), &Metadata::new("DocumentType", &name, &self.parent_uuid_string())));
+
+        if let Err(e) = written {
+            println!("🚨 Could not write the placeholder of item id {id}: {e}");
+            return;
         }
+
+        self.placeholders.insert(id, uid);
     }
-}
 
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Metadata {
-    deleted: bool,
-    #[serde(rename = "lastModified")]
-    last_modified: String,
-    #[serde(rename = "lastOpenedPage")]
-    last_opened_page: u64,
-    #[serde(rename = "metadatamodified")]
-    metadata_modified: bool,
-    modified: bool,
-    parent: String,
-    pinned: bool,
-    synced: bool,
-    #[serde(rename = "type")]
-    dtype: String,
-    version: u64,
-    #[serde(rename = "visibleName")]
-    visible_name: String,
-}
+    // Placeholders of items archived or deleted in Pocket since, returns how many were removed.
+    fn scrub_placeholders(&mut self, statuses: &HashMap<u64, ItemStatus>) -> usize {
+        let gone: Vec<u64> = self.placeholders.keys()
+            .filter(|id| matches!(statuses.get(id), Some(ItemStatus::Archived) | Some(ItemStatus::Deleted)))
+            .copied()
+            .collect();
 
-impl Metadata {
-    pub fn new(
-        dtype: &str,
-        name: &str,
-        parent: &str
-     ) -> Self {
-        let start = SystemTime::now();
-        let since_epoch = start.duration_since(UNIX_EPOCH).expect("🚨 Time went backwards");
-        let last_modified = since_epoch.as_millis();
+        for id in &gone {
+            if let Some(uid) = self.placeholders.remove(id) {
+                Self::remove_document(&utils::uuid_to_string(uid.uuid));
+            }
+            self.failed_items.remove(id);
 
-        Self {
-            deleted: false,
-            last_modified: last_modified.to_string(),
-            last_opened_page: 0,
-            metadata_modified: false,
-            modified: false,
-            parent: parent.to_string(),
-            pinned: false,
-            synced: false,
-            dtype: dtype.to_string(),
-            version: 1,
-            visible_name: name.to_string(),
+            // There's no tag to take off an item no longer in Pocket.
+            if statuses.get(id) == Some(&ItemStatus::Deleted) {
+                self.error_tagged.remove(id);
+            }
         }
+
+        gone.len()
     }
 
 
-    pub fn load(fname: &str) -> Self {
-        // Open the file in read-only mode with buffer.
-        let fh = File::open(fname).expect(&format!("Couldn't open metadata file {}", fname));
-        let reader = BufReader::new(fh);
+    // A document of rePocket's own in the Pocket folder, e.g. a report, always the same one for
+    // the same name. Returns where the epub went.
+    pub fn write_note(&self, name: &str, title: &str, epub: &[u8]) -> Option<String> {
+        self.note(name, title, epub, false)
+    }
 
-        // Read the JSON contents of the file as an instance of `User`.
-        let metadata = serde_json::from_reader(reader).expect("Couldn't parse JSON from buffer");
 
-        metadata
+    // The same, pinned, so that it's never taken for read, and recorded with its version, see
+    // note_version().
+    pub fn write_pinned_note(&mut self, name: &str, version: u32, title: &str, epub: &[u8]) -> Option<String> {
+        let fname = self.note(name, title, epub, true)?;
+        self.pinned_notes.insert(name.to_string(), version);
+
+        Some(fname)
     }
 
 
-    pub fn json(&self) -> serde_json::Result<String> {
-        serde_json::to_string(self)
+    // The version of the pinned note last written, if any.
+    pub fn note_version(&self, name: &str) -> Option<u32> {
+        self.pinned_notes.get(name).copied()
     }
-}
 
 
-#[derive(Clone, Debug, Serialize)]
-pub struct Content {
-    // coverPageNumber: i64,
-    // documentMetadata: json,
-    // dummyContent: bool,
-    #[serde(rename(serialize = "extraMetadata"))]
-    extra_meta: serde_json::Value,
-    #[serde(rename(serialize = "fileType"))]
-    ftype: String,
-    #[serde(rename(serialize = "fontName"))]
-    font_name: String,
-    #[serde(rename(serialize = "lineHeight"))]
-    line_height: i64,
-    margins: u64,
-    #[serde(rename(serialize = "orientation"))]
-    orientation: String,
-    #[serde(rename(serialize = "pageCount"))]
+    pub fn note_digest(&self, name: &str) -> Option<&str> {
+        self.note_digests.get(name).map(String::as_str)
+    }
+
+
+    pub fn set_note_digest(&mut self, name: &str, digest: String) {
+        self.note_digests.insert(name.to_string(), digest);
+    }
+
+
+    fn note_uid(name: &str) -> UniqID {
+        UniqID { uuid: Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()) }
+    }
+
+
+    fn note(&self, name: &str, title: &str, epub: &[u8], pinned: bool) -> Option<String> {
+        let uuid = utils::uuid_to_string(Self::note_uid(name).uuid);
+        let fname = xochitl_root().to_string() + "/" + &uuid + ".epub";
+
+        if let Err(e) = std::fs::write(&fname, epub) {
+            println!("🚨 Could not write {fname}: {e}");
+            return None;
+        }
+
+        let mut metadata = Metadata::new("DocumentType", title, &self.parent_uuid_string());
+        metadata.pinned = pinned;
+
+        // Over what was there, a version up, for xochitl to notice.
+        if let Some(replaced) = Metadata::try_load(&(xochitl_root().to_string() + "/" + &uuid + ".metadata")) {
+            metadata.version = replaced.version + 1;
+        }
+
+        let written = Self::write_file(&(xochitl_root().to_string() + "/" + &uuid + ".content"), &Content::new("epub"))
+            .and_then(|()| Self::write_file(&(xochitl_root().to_string() + "/" + &uuid + ".metadata"), &metadata));
+
+        if let Err(e) = written {
+            println!("🚨 {e}");
+            return None;
+        }
+
+        Some(fname)
+    }
+
+
+    //
+    // Bring an item archived in Pocket back into the Pocket folder. If its document is still
+    // around (i.e. it's in the Archive folder) it's moved back, keeping its UUID and reading
+    // position, otherwise it's downloaded again.
+    //
+    pub async fn restore_article(&mut self, item: &PocketItem, options: ArticleOptions) -> Restored {
+        let id = match item.best_id() {
+            Some(id) => id,
+            None => return Restored::Failed,
+        };
+
+        let archived = self.archived_items.iter()
+            .chain(&self.mirrored_items)
+            .find(|(_, val)| **val == id)
+            .map(|(uid, _)| uid.clone());
+
+        if let Some(uid) = archived {
+            let uuid = utils::uuid_to_string(uid.uuid);
+            let fname = xochitl_root().to_string() + "/" + &uuid + ".metadata";
+
+            match Metadata::try_load(&fname) {
+                Some(metadata) if metadata.parent != "trash" && !metadata.deleted && Self::has_document(&uuid) => {
+                    let parent = self.parent_uuid_string();
+
+                    match self.update_metadata(&uid, |metadata| metadata.parent = parent.clone()) {
+                        Update::Written => (),
+                        update => {
+                            println!("🚨 Could not move item with uuid {uuid} back into the Pocket folder: {update:?}");
+                            return Restored::Failed;
+                        },
+                    }
+
+                    self.archived_items.remove(&uid);
+                    self.mirrored_items.remove(&uid);
+                    self.current_items.insert(uid, id);
+                    self.restored_items.insert(id);
+                    println!("ℹ Moved item with uuid {uuid} back into the Pocket folder");
+
+                    return Restored::Reparented(uuid);
+                },
+                _ => println!("ℹ Item with uuid {uuid} is gone from the device, downloading it again"),
+            }
+
+            self.archived_items.remove(&uid);
+            self.mirrored_items.remove(&uid);
+        }
+
+        match self.new_article(item, options).await {
+            Some(article) => {
+                self.restored_items.insert(id);
+                Restored::Regenerated(article)
+            },
+            None => Restored::Failed,
+        }
+    }
+
+
+    //
+    // Keep the Archive folder showing the archived items, newest first, up to count of them, for
+    // rereading. An item's document still around from when it was read is moved back in, otherwise
+    // it's written again when download is set, i.e. when the budget allows. Mirrored items no longer
+    // among those go to the trash, items tracked otherwise are left alone. Returns how many were
+    // added and how many were trashed.
+    //
+    pub async fn mirror_archive(&mut self, items: &[PocketItem], count: usize, options: ArticleOptions, download: bool) -> (usize, usize) {
+        if !self.has_archive_folder() {
+            println!("ℹ There's no Archive folder to mirror Pocket's archive into");
+            return (0, 0);
+        }
+
+        let archive = self.archive_uuid_string();
+        let wanted: Vec<(u64, &PocketItem)> = items.iter()
+            .filter_map(|item| Some((item.best_id()?, item)))
+            .take(count)
+            .collect();
+        let (mut added, mut trashed) = (0, 0);
+
+        for (id, item) in &wanted {
+            if self.mirrored_items.values().any(|val| val == id) || self.tracks(*id) {
+                continue;
+            }
+
+            if self.reuse_archived(*id, &archive) || (download && self.write_mirrored(item, options, &archive).await) {
+                added += 1;
+            }
+        }
+
+        for (uid, id) in self.mirrored_items.clone() {
+            if wanted.iter().any(|(val, _)| *val == id) {
+                continue;
+            }
+
+            match self.update_metadata(&uid, |metadata| metadata.parent = "trash".to_string()) {
+                Update::Written => (),
+                update => {
+                    println!("ℹ Left mirrored item with uuid {} where it was: {update:?}", utils::uuid_to_string(uid.uuid));
+                    continue;
+                },
+            }
+
+            self.mirrored_items.remove(&uid);
+            self.archived_items.insert(uid, id);
+            trashed += 1;
+        }
+
+        (added, trashed)
+    }
+
+
+    // Whether the item has a document in the Pocket folder, or read and not yet archived.
+    fn tracks(&self, id: u64) -> bool {
+        [&self.current_items, &self.new_items, &self.read_items, &self.withheld_items]
+            .iter()
+            .any(|items| items.values().any(|val| *val == id))
+    }
+
+
+    // The item's document, from when it was read, moved into the Archive folder, trash or not.
+    fn reuse_archived(&mut self, id: u64, archive: &str) -> bool {
+        let uid = match self.archived_items.iter().find(|(_, val)| **val == id) {
+            Some((uid, _)) => uid.clone(),
+            None => return false,
+        };
+        let uuid = utils::uuid_to_string(uid.uuid);
+
+        match Metadata::try_load(&Self::metadata_file(&uid)) {
+            Some(metadata) if !metadata.deleted && Self::has_document(&uuid) => (),
+            _ => return false,
+        }
+
+        if self.update_metadata(&uid, |metadata| metadata.parent = archive.to_string()) != Update::Written {
+            return false;
+        }
+
+        println!("ℹ Moved item with uuid {uuid} back into the Archive folder");
+        self.archived_items.remove(&uid);
+        self.mirrored_items.insert(uid, id);
+
+        true
+    }
+
+
+    // Written as any other, and then moved into the Archive folder.
+    async fn write_mirrored(&mut self, item: &PocketItem, options: ArticleOptions, archive: &str) -> bool {
+        let id = item.best_id().unwrap_or(0);
+
+        let written = match self.new_article(item, options).await {
+            Some(written) => written,
+            None => {
+                // Nothing to tell Pocket about an archived item, nor a placeholder to show for it.
+                self.failed_items.remove(&id);
+                self.other_language.remove(&id);
+                if let Some(uid) = self.placeholders.remove(&id) {
+                    Self::remove_document(&utils::uuid_to_string(uid.uuid));
+                }
+                return false;
+            },
+        };
+
+        let uid = UniqID { uuid: Uuid::parse_str(&written.uuid).unwrap_or_default() };
+        let moved = self.update_metadata(&uid, |metadata| metadata.parent = archive.to_string());
+        self.new_items.remove(&uid);
+
+        if moved != Update::Written {
+            println!("🚨 Could not move item id {id} into the Archive folder: {moved:?}");
+            Self::remove_document(&written.uuid);
+            return false;
+        }
+
+        println!("ℹ Mirrored item id {id} into the Archive folder");
+        self.mirrored_items.insert(uid, id);
+
+        true
+    }
+
+
+    // Mirrored items moved back into the Pocket folder, to be re-added in Pocket.
+    pub fn readd_ids(&self) -> btree_set::IntoIter<u64> {
+        self.readd_items.clone().into_iter()
+    }
+
+
+    // Only the given ids, those Pocket confirmed. Their documents are where they should be already.
+    pub fn readded(&mut self, ids: &[u64]) {
+        for id in ids {
+            self.readd_items.remove(id);
+            self.readded_items.insert(*id);
+        }
+    }
+
+
+    // Whether the item, just retrieved, is one re-added with its document in the Pocket folder
+    // already, once.
+    pub fn take_readded(&mut self, id: u64) -> bool {
+        self.readded_items.remove(&id)
+    }
+
+
+    // Left for when the device is charging, see articlehandler::Verdict::Defer.
+    pub fn defer_large(&mut self, item: &PocketItem) {
+        if let Some(id) = item.best_id() {
+            self.large_items.insert(id, item.clone());
+        }
+    }
+
+
+    pub fn large_items(&self) -> Vec<PocketItem> {
+        self.large_items.values().cloned().collect()
+    }
+
+
+    // Done with, whether it was written or not.
+    pub fn take_large(&mut self, id: u64) {
+        self.large_items.remove(&id);
+    }
+
+
+    //
+    // For an article that was being written when the process died: if its document and both
+    // sidecars made it to the disk it's tracked from now on, and true is returned. Also true when
+    // it's tracked already.
+    //
+    pub fn adopt(&mut self, item: &PocketItem) -> bool {
+        let id = match (item.best_id(), item.best_url()) {
+            (Some(id), Some(_)) => id,
+            _ => return false,
+        };
+        let article = ArticleHandler::new(item).set_uuid(self.document_uuid(item));
+        let uid = UniqID { uuid: article.uuid() };
+
+        // What's there is the placeholder, not the document.
+        if self.placeholders.contains_key(&id) {
+            return false;
+        }
+
+        if self.current_items.contains_key(&uid) || self.new_items.contains_key(&uid) || self.archived_items.contains_key(&uid) {
+            return true;
+        }
+
+        let uuid = article.uuid_string();
+        let sidecars = ["content", "metadata"].iter()
+            .all(|ext| Path::new(&(xochitl_root().to_string() + "/" + &uuid + "." + ext)).exists());
+
+        if !Self::has_document(&uuid) || !sidecars {
+            return false;
+        }
+
+        println!("ℹ Item id {id} was written before the last sync was cut short, tracking it");
+        self.new_items.insert(uid, id);
+
+        true
+    }
+
+
+    //
+    // For a resync, see sync::resync: whether the item's document is on the disk already, under the
+    // UUID its URL makes, tracking it if it wasn't, see adopt(). Whatever is tracked under that UUID
+    // but gone from the disk is forgotten, for the item to be written again rather than next to it.
+    //
+    pub fn resync_item(&mut self, item: &PocketItem) -> bool {
+        let id = match (item.best_id(), item.best_url()) {
+            (Some(id), Some(_)) => id,
+            _ => return false,
+        };
+        let uid = UniqID { uuid: self.document_uuid(item) };
+        let uuid = utils::uuid_to_string(uid.uuid);
+        let sidecars = ["content", "metadata"].iter()
+            .all(|ext| Path::new(&(xochitl_root().to_string() + "/" + &uuid + "." + ext)).exists());
+
+        if Self::has_document(&uuid) && sidecars {
+            return self.adopt(item);
+        }
+
+        for items in [&mut self.current_items, &mut self.new_items, &mut self.archived_items] {
+            if items.remove(&uid).is_some() {
+                println!("ℹ Item id {id} is tracked but not on the device, writing it again");
+            }
+        }
+
+        false
+    }
+
+
+    // The documents in the Pocket folder that don't pass integrity::check().
+    pub fn verify(&self) -> Vec<Broken> {
+        self.current_items.iter()
+            .chain(&self.new_items)
+            .filter_map(|(uid, id)| {
+                let uuid = utils::uuid_to_string(uid.uuid);
+                let problems = integrity::check(&uuid, None);
+
+                match problems.is_empty() {
+                    true => None,
+                    false => Some(Broken { uuid, id: *id, problems }),
+                }
+            })
+            .collect()
+    }
+
+
+    //
+    // Write the broken documents again, for those items still in Pocket, i.e. in items. The rest
+    // are removed and no longer tracked. Returns what was done, one line per document.
+    //
+    pub async fn repair(&mut self, broken: &[Broken], items: &[PocketItem], options: ArticleOptions) -> Vec<String> {
+        let mut done = Vec::new();
+
+        for doc in broken {
+            let uid = UniqID { uuid: Uuid::parse_str(&doc.uuid).unwrap_or_default() };
+
+            Self::remove_document(&doc.uuid);
+            self.current_items.remove(&uid);
+            self.new_items.remove(&uid);
+
+            match items.iter().find(|item| item.best_id() == Some(doc.id)) {
+                Some(item) => match self.new_article(item, options).await {
+                    Some(_) => done.push(format!("{} (item {}) written again", doc.uuid, doc.id)),
+                    None => done.push(format!("{} (item {}) removed, it couldn't be written again", doc.uuid, doc.id)),
+                },
+                None => done.push(format!("{} (item {}) removed, it's no longer in Pocket", doc.uuid, doc.id)),
+            }
+        }
+
+        done
+    }
+
+
+    //
+    // Give the tracked documents, and the placeholders, the UUIDs they'd get from source, renaming
+    // their files and whatever xochitl keeps next to them. urls has the items' URLs by id, going by
+    // URL those not in it keep theirs, and so does a document whose new UUID is taken. With apply
+    // false nothing changes. Returns what was, or would be, done, one line per document.
+    //
+    pub fn migrate_uuids(&mut self, source: UuidSource, urls: &HashMap<u64, String>, apply: bool) -> Vec<String> {
+        let mut documents: BTreeMap<UniqID, u64> = [&self.current_items, &self.new_items, &self.read_items, &self.archived_items, &self.withheld_items, &self.mirrored_items]
+            .into_iter()
+            .flatten()
+            .map(|(uid, id)| (uid.clone(), *id))
+            .collect();
+        documents.extend(self.placeholders.iter().map(|(id, uid)| (uid.clone(), *id)));
+
+        let mut taken: BTreeSet<Uuid> = documents.keys().map(|uid| uid.uuid).collect();
+        let mut done = Vec::new();
+
+        for (uid, id) in documents {
+            let from = utils::uuid_to_string(uid.uuid);
+            let to = match (source, urls.get(&id)) {
+                (UuidSource::ItemId, _) => document_uuid(source, Some(id), ""),
+                (UuidSource::Url, Some(url)) => document_uuid(source, Some(id), url),
+                (UuidSource::Url, None) => {
+                    done.push(format!("{from} (item {id}) kept, its URL isn't known"));
+                    continue;
+                },
+            };
+
+            if to == uid.uuid {
+                continue;
+            }
+
+            let uuid = utils::uuid_to_string(to);
+
+            if taken.contains(&to) || !Self::document_files(&uuid).is_empty() {
+                done.push(format!("{from} (item {id}) kept, {uuid} is taken"));
+                continue;
+            }
+
+            if apply {
+                if let Err(e) = Self::rename_document(&from, &uuid) {
+                    done.push(format!("{from} (item {id}) kept, {e}"));
+                    continue;
+                }
+
+                let moved = UniqID { uuid: to };
+                self.rekey(&uid, &moved);
+                // A new document as far as xochitl, and its cloud, are concerned.
+                let _ = self.update_metadata(&moved, |metadata| {
+                    metadata.version += 1;
+                    metadata.synced = false;
+                });
+            }
+
+            taken.remove(&uid.uuid);
+            taken.insert(to);
+            done.push(format!("{from} (item {id}) -> {uuid}"));
+        }
+
+        done
+    }
+
+
+    // The names of the files, and directories, xochitl has for the document, e.g. its .epub,
+    // .content, .metadata, .thumbnails.
+    fn document_files(uuid: &str) -> Vec<String> {
+        root::document_files(xochitl_root(), uuid)
+    }
+
+
+    // All of them or none, those renamed are put back if one can't be.
+    fn rename_document(from: &str, to: &str) -> Result<(), std::io::Error> {
+        let root = Path::new(xochitl_root());
+        let mut renamed = Vec::new();
+
+        for name in Self::document_files(from) {
+            let (old, new) = (root.join(&name), root.join(to.to_string() + &name[from.len()..]));
+
+            if let Err(e) = std::fs::rename(&old, &new) {
+                for (old, new) in renamed.iter().rev() {
+                    let _ = std::fs::rename(new, old);
+                }
+                return Err(e);
+            }
+
+            renamed.push((old, new));
+        }
+
+        Ok(())
+    }
+
+
+    fn rekey(&mut self, from: &UniqID, to: &UniqID) {
+        for items in [&mut self.current_items, &mut self.new_items, &mut self.read_items, &mut self.archived_items, &mut self.withheld_items, &mut self.mirrored_items] {
+            if let Some(id) = items.remove(from) {
+                items.insert(to.clone(), id);
+            }
+        }
+
+        for uid in self.placeholders.values_mut().filter(|uid| **uid == *from) {
+            *uid = to.clone();
+        }
+    }
+
+
+    fn has_document(uuid: &str) -> bool {
+        ["epub", "pdf", "html"].iter()
+            .any(|ext| Path::new(&(xochitl_root().to_string() + "/" + uuid + "." + ext)).exists())
+    }
+
+
+    pub fn is_restored(&self, id: u64) -> bool {
+        self.restored_items.contains(&id)
+    }
+
+
+    // Remove whatever was written for an article that didn't make it, e.g. after a timeout.
+    pub fn discard_article(&self, item: &PocketItem) {
+        let article = ArticleHandler::new(item).set_uuid(self.document_uuid(item));
+
+        Self::remove_document(&article.uuid_string());
+    }
+
+
+    fn remove_document(uuid: &str) {
+        for ext in ["epub", "pdf", "html", "content", "metadata"] {
+            let fname = xochitl_root().to_string() + "/" + uuid + "." + ext;
+
+            if std::fs::remove_file(&fname).is_ok() {
+                println!("ℹ Removed partial file {fname}");
+            }
+        }
+    }
+
+
+    pub fn fail_item(&mut self, item: &PocketItem, reason: &str) {
+        let id = item.best_id().unwrap_or(0);
+
+        self.failed_items.insert(id, FailedItem {
+            url: item.best_url().unwrap_or_default(),
+            reason: reason.to_string(),
+            trace: None,
+        });
+    }
+
+
+    pub fn failure_reason(&self, id: u64) -> Option<&str> {
+        self.failed_items.get(&id).map(|failed| failed.reason.as_str())
+    }
+
+
+    // As if the item had never been tried, for one whose failure wasn't its own, e.g. the device
+    // suspending in the middle of it. Its placeholder goes too.
+    pub fn forgive(&mut self, item: &PocketItem) {
+        let id = item.best_id().unwrap_or(0);
+
+        self.failed_items.remove(&id);
+        if let Some(uid) = self.placeholders.remove(&id) {
+            Self::remove_document(&utils::uuid_to_string(uid.uuid));
+        }
+    }
+
+
+    fn trace_failure(&mut self, item: &PocketItem, trace: &ExtractionTrace) {
+        let id = item.best_id().unwrap_or(0);
+
+        if let Some(failed) = self.failed_items.get_mut(&id) {
+            failed.trace = Some(trace.clone());
+        }
+    }
+
+
+    //
+    // Everything known about how the document with the given UUID came about, or about why it
+    // didn't, for items that failed (their UUID is the same they'd have had as documents).
+    //
+    pub fn inspect(&self, uuid: &str) -> Option<Vec<String>> {
+        let uuid = Uuid::parse_str(uuid).ok()?;
+        let tracked = self.current_items.iter()
+            .chain(&self.new_items)
+            .chain(&self.archived_items)
+            .find(|(uid, _)| uid.uuid == uuid)
+            .map(|(_, id)| *id);
+
+        let (id, trace, failed) = match tracked {
+            Some(id) => (id, self.traces.get(&id), None),
+            None => {
+                let (id, failed) = self.failed_items.iter()
+                    .find(|(id, failed)| document_uuid(self.uuid_source, Some(**id), &failed.url) == uuid)?;
+                (*id, failed.trace.as_ref(), Some(failed))
+            },
+        };
+
+        let mut lines = vec![format!("Item {id}")];
+
+        if let Some(failed) = failed {
+            lines.push(format!("Failed ({}): {}", failed.url, failed.reason));
+        }
+
+        match trace {
+            Some(trace) => lines.extend(trace.to_string().lines().map(str::to_string)),
+            None => lines.push("No trace kept for it".to_string()),
+        }
+
+        Some(lines)
+    }
+
+
+    //
+    // What's in the Pocket folder, and what couldn't be made into a document, one line each, e.g.
+    //
+    //   Rust lifetimes (1234): 📄 🖼 ⏱ 12 min, images omitted
+    //
+    pub fn status(&self) -> Vec<String> {
+        let report = self.status_report();
+
+        let mut lines = vec![format!("In the Pocket folder: {}", report.in_folder.len())];
+        lines.extend(report.in_folder.iter().take(STATUS_LISTED).flat_map(StatusItem::lines));
+        lines.extend(Self::unlisted(report.in_folder.len()));
+
+        if !report.pinned.is_empty() {
+            lines.push(format!("Pinned: {}", report.pinned.len()));
+            lines.extend(report.pinned.iter().take(STATUS_LISTED).flat_map(StatusItem::lines));
+            lines.extend(Self::unlisted(report.pinned.len()));
+        }
+
+        if !report.failed.is_empty() {
+            lines.push(format!("Failed: {}", report.failed.len()));
+
+            for failed in report.failed.iter().take(STATUS_LISTED) {
+                lines.push(format!("  {} ({}): {}", failed.url, failed.id, failed.reason));
+            }
+            lines.extend(Self::unlisted(report.failed.len()));
+        }
+
+        lines
+    }
+
+
+    fn unlisted(count: usize) -> Option<String> {
+        (count > STATUS_LISTED)
+            .then(|| format!("  ..and {} more, all of them are in the library document in the Pocket folder", count - STATUS_LISTED))
+    }
+
+
+    // The same, for `repocket status --json`.
+    pub fn status_report(&self) -> StatusReport {
+        let (pinned, unpinned): (Vec<_>, Vec<_>) = self.current_items.iter()
+            .chain(&self.new_items)
+            .partition(|(_, id)| self.is_pinned(**id));
+
+        StatusReport {
+            in_folder: unpinned.into_iter().map(|(uid, id)| self.status_item(uid, *id)).collect(),
+            pinned: pinned.into_iter().map(|(uid, id)| self.status_item(uid, *id)).collect(),
+            failed: self.failed_items.iter()
+                .map(|(id, failed)| StatusFailure { id: *id, url: failed.url.clone(), reason: failed.reason.clone() })
+                .collect(),
+            next_sync: None,
+        }
+    }
+
+
+    fn status_item(&self, uid: &UniqID, id: u64) -> StatusItem {
+        let uuid = utils::uuid_to_string(uid.uuid);
+        let name = Metadata::try_load(&Self::metadata_file(uid))
+            .map(|metadata| metadata.visible_name)
+            .unwrap_or_else(|| uuid.clone());
+
+        StatusItem {
+            uuid,
+            id,
+            name,
+            flags: self.flags.get(&id).cloned(),
+            progress: self.progress.get(&id).copied(),
+            trace: self.traces.get(&id).map(|trace| trace.summary()),
+        }
+    }
+
+
+    //
+    // Pinned items stay in the Pocket folder: reconciliation leaves them there, and consolidation
+    // never takes them for read. Either signal is enough, xochitl's "pinned" flag, picked up by
+    // consolidation, or the pin tag in Pocket, picked up when they're written and by reconciliation.
+    //
+    pub fn is_pinned(&self, id: u64) -> bool {
+        self.pinned_on_device.contains(&id) || self.pinned_by_tag.contains(&id)
+    }
+
+
+    // xochitl's, once it has laid out the document, 1 otherwise.
+    fn page_count(uid: &UniqID) -> u64 {
+        let fname = xochitl_root().to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".content";
+
+        read(fname).ok()
+            .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+            .and_then(|content| content["pageCount"].as_u64())
+            .unwrap_or(1)
+    }
+
+
+    //
+    // The items whose progress bucket differs from the one tagged in Pocket, with the tag to take
+    // off and the one to put on, either of which may be missing. Only tracked items count, and the
+    // tag only changes with the bucket, not with every page turned.
+    //
+    pub fn progress_retags(&self) -> Vec<(u64, Option<String>, Option<String>)> {
+        self.current_items.values()
+            .filter_map(|id| {
+                let bucket = progress::bucket(*self.progress.get(id)?);
+                let tagged = self.progress_tagged.get(id).copied();
+
+                (bucket != tagged).then(|| (*id, tagged.map(progress::tag), bucket.map(progress::tag)))
+            })
+            .collect()
+    }
+
+
+    //
+    // The items whose error tag in Pocket isn't what it should be, with the tag to take off and
+    // the one to put on, as progress_retags(). Failed items get the tag, once failed for good,
+    // which is once they're in failed_items, and lose it once they're not, e.g. when written after
+    // all, or when the tag was renamed or turned off.
+    //
+    pub fn error_retags(&self, tag: Option<&str>) -> Vec<(u64, Option<String>, Option<String>)> {
+        let failed = self.failed_items.keys()
+            .filter(|id| **id != 0)
+            .filter_map(|id| {
+                let tagged = self.error_tagged.get(id).map(String::as_str);
+                (tagged != tag).then(|| (*id, tagged.map(str::to_string), tag.map(str::to_string)))
+            });
+        let recovered = self.error_tagged.iter()
+            .filter(|(id, _)| !self.failed_items.contains_key(id))
+            .map(|(id, tagged)| (*id, Some(tagged.clone()), None));
+
+        failed.chain(recovered).collect()
+    }
+
+
+    // Pocket has the given error tag for this item now, or none.
+    pub fn error_retagged(&mut self, id: u64, tag: Option<String>) {
+        match tag {
+            Some(tag) => self.error_tagged.insert(id, tag),
+            None => self.error_tagged.remove(&id),
+        };
+    }
+
+
+    // Pocket has the tag of the bucket this item is in now.
+    pub fn progress_retagged(&mut self, id: u64) {
+        match self.progress.get(&id).and_then(|percent| progress::bucket(*percent)) {
+            Some(bucket) => self.progress_tagged.insert(id, bucket),
+            None => self.progress_tagged.remove(&id),
+        };
+    }
+
+
+    pub fn pin_by_tag(&mut self, id: u64) {
+        self.pinned_by_tag.insert(id);
+    }
+
+
+    // Everything tracked that carries the pin tag in Pocket right now, the rest is unpinned.
+    // Returns how many items changed.
+    pub fn set_tag_pins(&mut self, tagged: &HashMap<u64, ItemStatus>) -> usize {
+        let pins: BTreeSet<u64> = self.current_items.values()
+            .chain(self.new_items.values())
+            .filter(|id| tagged.contains_key(id))
+            .copied()
+            .collect();
+        let changed = pins.symmetric_difference(&self.pinned_by_tag).count();
+
+        self.pinned_by_tag = pins;
+
+        changed
+    }
+
+
+    fn write_file<T>(fname: &str, data: &T) -> Result<(), FsError> where T: Serialize + std::fmt::Debug {
+        let json = serde_json::to_string(&data).map_err(|e| FsError::serde(fname, e))?;
+
+        std::fs::write(fname, json + "\n").map_err(|e| FsError::io(fname, e))
+    }
+
+
+    // Remember where documents are being written, and make some noise when that changes.
+    pub fn update_xochitl_root(&mut self) {
+        if !self.xochitl_root.is_empty() && self.xochitl_root != xochitl_root() {
+            println!("🚨 xochitl's storage moved from {} to {}", self.xochitl_root, xochitl_root());
+        }
+
+        self.xochitl_root = xochitl_root().to_string();
+    }
+
+
+    // Where the state is saved.
+    pub fn state_file(&self) -> &str {
+        self.config_file.as_deref().unwrap_or(config_file())
+    }
+
+
+    // The default query's, see settings::DEFAULT_QUERY.
+    #[cfg(test)]
+    pub fn last_query_ts(&self) -> u64 {
+        self.query_ts(DEFAULT_QUERY)
+    }
+
+
+    #[cfg(test)]
+    pub fn set_last_query_ts(&mut self, ts: u64) {
+        self.set_query_ts(DEFAULT_QUERY, ts);
+    }
+
+
+    // Until consolidate() moves it over, the default query's is still the old one.
+    pub fn query_ts(&self, name: &str) -> u64 {
+        match self.ts_last_queries.get(name) {
+            Some(ts) => *ts,
+            None if name == DEFAULT_QUERY => self.ts_last_query,
+            None => 0,
+        }
+    }
+
+
+    pub fn set_query_ts(&mut self, name: &str, ts: u64) {
+        self.ts_last_queries.insert(name.to_string(), ts);
+    }
+
+
+    pub fn read_ids(&self) -> btree_map::IntoValues<UniqID, u64> {
+        self.read_items.clone().into_values()
+    }
+
+
+    // Counts syncs, and tells whether it's time to reconcile with Pocket.
+    pub fn reconcile_due(&mut self, every: u64) -> bool {
+        if every == 0 {
+            return false;
+        }
+
+        self.syncs_since_reconcile += 1;
+
+        if self.syncs_since_reconcile >= every {
+            self.syncs_since_reconcile = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+
+    pub fn add_downloaded(&mut self, month: &str, bytes: u64) {
+        *self.monthly_bytes.entry(month.to_string()).or_insert(0) += bytes;
+    }
+
+
+    pub fn downloaded_in(&self, month: &str) -> u64 {
+        self.monthly_bytes.get(month).copied().unwrap_or(0)
+    }
+
+
+    pub fn last_reconcile_ts(&self) -> u64 {
+        self.ts_last_reconcile
+    }
+
+
+    pub fn set_last_reconcile_ts(&mut self, ts: u64) {
+        self.ts_last_reconcile = ts;
+    }
+
+
+    // Mirror Pocket's state down to the device: items archived or deleted elsewhere leave the
+    // Pocket folder. Items the user already started reading are left alone unless forced.
+    pub fn reconcile(&mut self, statuses: &HashMap<u64, ItemStatus>, target: ReconcileTarget, force: bool) -> usize {
+        let parent = match target {
+            ReconcileTarget::Archive if self.has_archive_folder() => self.archive_uuid_string(),
+            _ => "trash".to_string(),
+        };
+        let mut moved = 0;
+
+        for (uid, id) in self.current_items.clone() {
+            // Restored items are archived in Pocket, that's the whole point.
+            if self.restored_items.contains(&id) {
+                continue;
+            }
+
+            if self.is_pinned(id) {
+                continue;
+            }
+
+            match statuses.get(&id) {
+                Some(ItemStatus::Archived) | Some(ItemStatus::Deleted) => (),
+                _ => continue,
+            }
+
+            let opened = match Metadata::try_load(&Self::metadata_file(&uid)) {
+                Some(metadata) => metadata.last_opened_page > 0,
+                None => continue,
+            };
+
+            if opened && !force {
+                println!("ℹ Item id {id} is gone from Pocket's list, but it's being read, leaving it");
+                continue;
+            }
+
+            match self.update_metadata(&uid, |metadata| metadata.parent = parent.clone()) {
+                Update::Written => (),
+                update => {
+                    println!("ℹ Item id {id} is gone from Pocket's list, leaving it for now: {update:?}");
+                    continue;
+                },
+            }
+
+            self.current_items.remove(&uid);
+            self.archived_items.insert(uid.clone(), id);
+            moved += 1;
+            println!("ℹ Item with uuid {} was archived or deleted in Pocket", &utils::uuid_to_string(uid.uuid));
+        }
+
+        let scrubbed = self.scrub_placeholders(statuses);
+        if scrubbed > 0 {
+            println!("ℹ Removed {scrubbed} placeholder(s) of items gone from Pocket");
+        }
+
+        // Nothing to wait for the charger for anymore.
+        let waiting = self.large_items.len();
+        self.large_items.retain(|id, _| !matches!(statuses.get(id), Some(ItemStatus::Archived) | Some(ItemStatus::Deleted)));
+        if self.large_items.len() < waiting {
+            println!("ℹ Dropped {} large item(s) gone from Pocket", waiting - self.large_items.len());
+        }
+
+        moved
+    }
+
+
+    pub fn withheld_ids(&self) -> btree_map::IntoValues<UniqID, u64> {
+        self.withheld_items.clone().into_values()
+    }
+
+
+    pub fn pending_ids(&self) -> btree_map::IntoKeys<u64, String> {
+        self.pending_actions.clone().into_keys()
+    }
+
+
+    pub fn pending_tag(&self, id: u64) -> Option<String> {
+        self.pending_actions.get(&id).cloned()
+    }
+
+
+    pub fn tag_pending(&mut self, id: u64, tag: &str) {
+        self.pending_actions.insert(id, tag.to_string());
+    }
+
+
+    pub fn clear_pending(&mut self, id: u64) {
+        self.pending_actions.remove(&id);
+    }
+
+
+    // Same as clear_read(), but remember the items so they can be pushed to Pocket later on.
+    pub fn withhold_read(&mut self) {
+        for (uid, val) in self.read_items.clone() {
+            self.withheld_items.insert(uid, val);
+        }
+
+        let ids: Vec<u64> = self.read_ids().collect();
+        self.clear_read(&ids);
+    }
+
+
+    pub fn clear_withheld(&mut self, ids: &[u64]) {
+        self.withheld_items.retain(|_, val| !ids.contains(val));
+    }
+
+
+    pub fn consolidate(&mut self) {
+        // From before there were several queries.
+        if self.ts_last_query > 0 {
+            self.ts_last_queries.entry(DEFAULT_QUERY.to_string()).or_insert(self.ts_last_query);
+            self.ts_last_query = 0;
+        }
+
+        let plan = self.plan_consolidation();
+
+        self.apply_consolidation(&plan);
+    }
+
+
+    //
+    // What consolidate() would do, worked out without touching anything, neither the state nor the
+    // device. consolidate() does exactly this, so what's shown is what happens.
+    //
+    pub fn plan_consolidation(&self) -> ConsolidationPlan {
+        let mut plan = ConsolidationPlan::default();
+
+        // The first issue needing consolidation is the introduction of the "archive" folder.
+        // Since it is a new field, it may not be loaded from the config file. If that's the case,
+        // force a proper UUID.
+        if self.archive.uuid.is_nil() {
+            plan.new_folders.push("archive".to_string());
+        }
+
+        if self.sync_trigger.uuid.is_nil() {
+            plan.new_folders.push("sync_trigger".to_string());
+        }
+
+
+        // Go through the list of current items:
+        // - If the files is missing, or can't be read, leave it for now
+        // - If it was moved into the archive folder (or the trash), then archive in pocket
+        // - If it was moved anywhere else, stop tracking it
+        // - Otherwise it's all good.
+        for (uid, id) in &self.current_items {
+            let fname = Self::metadata_file(uid);
+            let item = |parent: Option<&str>| PlannedItem { uuid: uid.clone(), id: *id, parent: parent.map(String::from) };
+
+            // Possibly halfway through being written by someone else, there's always the next sync.
+            let metadata = match Metadata::try_load(&fname) {
+                Some(metadata) => metadata,
+                None if !Path::new(&fname).exists() => {
+                    plan.missing.push(item(None));
+                    continue;
+                },
+                None => {
+                    plan.unreadable.push(item(None));
+                    continue;
+                },
+            };
+
+            if metadata.pinned != self.pinned_on_device.contains(id) {
+                match metadata.pinned {
+                    true => plan.pinned.push(item(None)),
+                    false => plan.unpinned.push(item(None)),
+                }
+            }
+
+            if metadata.parent == self.parent_uuid_string() {
+                let percent = progress::percent(metadata.last_opened_page, Self::page_count(uid));
+                if let Some(percent) = percent.filter(|percent| self.progress.get(id) != Some(percent)) {
+                    plan.progress.insert(*id, percent);
+                }
+                continue;
+            }
+
+            // Wherever it was moved, it's still tracked and never archived in Pocket.
+            if metadata.pinned || self.pinned_by_tag.contains(id) {
+                plan.kept.push(item(Some(&metadata.parent)));
+                continue;
+            }
+
+            // Only archive those moved to the archive folder (or the trash, if that's where read
+            // documents go), otherwise assume that the user is intentionally breaking syncing
+            // with Pocket.
+            match self.read_into(&metadata.parent) {
+                true => {
+                    match self.pocket_read {
+                        Some(PocketRead::Archive) => {
+                            plan.pocket_actions.push(PocketAction::Archive { item_id: *id });
+                            if let Some(tag) = self.status_tags.tag(Status::Archived) {
+                                plan.pocket_actions.push(PocketAction::TagsAdd { item_id: *id, tags: tag.to_string() });
+                            }
+                        },
+                        Some(PocketRead::Delete) => plan.pocket_actions.push(PocketAction::Delete { item_id: *id }),
+                        None => (),
+                    }
+                    plan.read.push(item(Some(&metadata.parent)));
+                },
+                false => plan.released.push(item(Some(&metadata.parent))),
+            }
+        }
+
+        // Mirrored items stay in the Archive folder. Moved into the Pocket folder they're to be read
+        // again, and re-added in Pocket, moved anywhere else they're the user's.
+        for (uid, id) in &self.mirrored_items {
+            let parent = match Metadata::try_load(&Self::metadata_file(uid)) {
+                Some(metadata) if metadata.parent == self.archive_uuid_string() => continue,
+                Some(metadata) => metadata.parent,
+                None => continue,
+            };
+            let item = PlannedItem { uuid: uid.clone(), id: *id, parent: Some(parent.clone()) };
+
+            match parent == self.parent_uuid_string() {
+                true => {
+                    plan.pocket_actions.push(PocketAction::Readd { item_id: *id });
+                    plan.readded.push(item);
+                },
+                false => plan.unmirrored.push(item),
+            }
+        }
+
+        // Then move the new items to the current items list.
+        plan.promoted = self.new_items.iter()
+            .map(|(uid, id)| PlannedItem { uuid: uid.clone(), id: *id, parent: None })
+            .collect();
+
+        plan
+    }
+
+
+    // Carry out a plan made by plan_consolidation() on this very state.
+    pub fn apply_consolidation(&mut self, plan: &ConsolidationPlan) {
+        for folder in &plan.new_folders {
+            match folder.as_str() {
+                "archive" => self.archive = UniqID::new(),
+                "sync_trigger" => self.sync_trigger = UniqID::new(),
+                _ => (),
+            }
+        }
+
+        for item in plan.missing.iter().chain(&plan.unreadable) {
+            println!("ℹ Could not read the metadata of item with uuid {}, leaving it", &utils::uuid_to_string(item.uuid.uuid));
+        }
+
+        for item in &plan.pinned {
+            self.pinned_on_device.insert(item.id);
+            println!("ℹ Item with uuid {} was pinned on the device", &utils::uuid_to_string(item.uuid.uuid));
+        }
+
+        for item in &plan.unpinned {
+            self.pinned_on_device.remove(&item.id);
+            println!("ℹ Item with uuid {} was unpinned on the device", &utils::uuid_to_string(item.uuid.uuid));
+        }
+
+        self.progress.extend(&plan.progress);
+
+        for item in &plan.read {
+            if let Some(val) = self.current_items.remove(&item.uuid) {
+                self.read_items.insert(item.uuid.clone(), val);
+                println!("ℹ Moved item with uuid {} into the read_items list", &utils::uuid_to_string(item.uuid.uuid));
+            }
+        }
+
+        for item in &plan.released {
+            self.current_items.remove(&item.uuid);
+        }
+
+        for item in &plan.readded {
+            if let Some(val) = self.mirrored_items.remove(&item.uuid) {
+                self.current_items.insert(item.uuid.clone(), val);
+                self.readd_items.insert(val);
+                println!("ℹ Mirrored item with uuid {} was moved into the Pocket folder", &utils::uuid_to_string(item.uuid.uuid));
+            }
+        }
+
+        for item in &plan.unmirrored {
+            self.mirrored_items.remove(&item.uuid);
+        }
+
+        for item in &plan.promoted {
+            if let Some(val) = self.new_items.remove(&item.uuid) {
+                self.current_items.insert(item.uuid.clone(), val);
+                println!("ℹ Moved item with uuid {} into the current_items list", &utils::uuid_to_string(item.uuid.uuid));
+            }
+        }
+
+        // What's known about the documents of items no longer tracked goes with them.
+        let tracked = self.tracked_ids();
+        self.flags.retain(|id, _| tracked.contains(id));
+        self.traces.retain(|id, _| tracked.contains(id));
+        self.progress.retain(|id, _| tracked.contains(id));
+        self.progress_tagged.retain(|id, _| tracked.contains(id));
+    }
+
+
+    // The items with a document rePocket keeps track of, wherever it is.
+    fn tracked_ids(&self) -> BTreeSet<u64> {
+        self.current_items.values()
+            .chain(self.new_items.values())
+            .chain(self.read_items.values())
+            .chain(self.archived_items.values())
+            .chain(self.withheld_items.values())
+            .chain(self.mirrored_items.values())
+            .copied()
+            .collect()
+    }
+
+
+    //
+    // Undo what rePocket did to the device. Only documents tracked in the maps, and the folders
+    // themselves, are ever touched. Documents are either moved to the top level, so that they
+    // survive as ordinary documents, or to the trash. Items already in the trash are left there.
+    //
+    // Returns a description of every step, which were only described when dry_run is set.
+    //
+    pub fn uninstall(&self, mode: UninstallMode, dry_run: bool, files: &[&str]) -> Vec<String> {
+        let mut steps = Vec::new();
+
+        let documents: BTreeSet<&UniqID> = self.current_items.keys()
+            .chain(self.new_items.keys())
+            .chain(self.read_items.keys())
+            .chain(self.archived_items.keys())
+            .chain(self.withheld_items.keys())
+            .chain(self.mirrored_items.keys())
+            .collect();
+        let notes: Vec<UniqID> = self.pinned_notes.keys().map(|name| Self::note_uid(name)).collect();
+
+        let (parent, verb) = match mode {
+            UninstallMode::KeepDocuments => ("", "Moved to the top level"),
+            UninstallMode::RemoveDocuments => ("trash", "Trashed"),
+        };
+
+        for uid in documents {
+            if let Some(step) = self.reparent(uid, parent, verb, dry_run) {
+                steps.push(step);
+            }
+        }
+
+        // rePocket's own, e.g. the help, of no use without it.
+        for uid in &notes {
+            if let Some(step) = self.reparent(uid, "trash", "Trashed", dry_run) {
+                steps.push(step);
+            }
+        }
+
+        for uid in [&self.sync_trigger, &self.archive, &self.folder] {
+            if let Some(step) = self.reparent(uid, "trash", "Trashed folder", dry_run) {
+                steps.push(step);
+            }
+        }
+
+        for file in files {
+            if Path::new(file).exists() {
+                steps.push(format!("Removed {file}"));
+
+                if !dry_run {
+                    if let Err(e) = std::fs::remove_file(file) {
+                        steps.push(format!("Could not remove {file}: {e}"));
+                    }
+                }
+            }
+        }
+
+        steps
+    }
+
+
+    fn reparent(&self, uid: &UniqID, parent: &str, verb: &str, dry_run: bool) -> Option<String> {
+        let metadata = Metadata::try_load(&Self::metadata_file(uid))?;
+
+        if metadata.parent == "trash" {
+            return None;
+        }
+
+        if !dry_run {
+            match self.update_metadata(uid, |metadata| metadata.parent = parent.to_string()) {
+                Update::Written => (),
+                update => return Some(format!("Left '{}' alone: {update:?}", metadata.visible_name)),
+            }
+        }
+
+        Some(format!("{verb} '{}'", metadata.visible_name))
+    }
+
+
+    //
+    // Other tools write into xochitl's storage too, e.g. to drop PDFs in, some of them into our
+    // folders. Only documents rePocket keeps track of, and the folders themselves, are ever
+    // modified, and, other than those, documents in our folders with a UUID rePocket would have
+    // made, from the URL.
+    //
+    fn is_ours(&self, uid: &UniqID, metadata: &Metadata) -> bool {
+        let tracked = [&self.folder, &self.archive, &self.sync_trigger].contains(&uid)
+            || [&self.current_items, &self.new_items, &self.read_items, &self.archived_items, &self.withheld_items, &self.mirrored_items]
+                .iter()
+                .any(|items| items.contains_key(uid));
+        let in_our_folders = [self.parent_uuid_string(), self.archive_uuid_string(), self.sync_uuid_string()]
+            .contains(&metadata.parent);
+
+        tracked || (in_our_folders && uid.uuid.get_version() == Some(uuid::Version::Sha1))
+    }
+
+
+    //
+    // Change a document's metadata, as long as it's ours. Right before writing it back the file
+    // is read again: if it changed in the meantime (its lastModified did) the change is made on
+    // top of what's there now instead, and if xochitl flagged it as modified meanwhile it's
+    // in the middle of something, and the file is left alone altogether.
+    //
+    fn update_metadata<F: FnMut(&mut Metadata)>(&self, uid: &UniqID, mut change: F) -> Update {
+        let fname = Self::metadata_file(uid);
+
+        for _ in 0..METADATA_ATTEMPTS {
+            let mut metadata = match Metadata::try_load(&fname) {
+                Some(metadata) => metadata,
+                None => return Update::Unreadable,
+            };
+
+            if !self.is_ours(uid, &metadata) {
+                return Update::Foreign;
+            }
+
+            let (last_modified, flagged) = (metadata.last_modified.clone(), metadata.metadata_modified);
+            change(&mut metadata);
+
+            match Metadata::try_load(&fname) {
+                Some(current) if current.metadata_modified != flagged => return Update::Busy,
+                Some(current) if current.last_modified == last_modified => {
+                    return match Self::write_file(&fname, &metadata) {
+                        Ok(()) => Update::Written,
+                        Err(e) => {
+                            println!("🚨 {e}");
+                            Update::Unwritable
+                        },
+                    };
+                },
+                _ => println!("ℹ {fname} changed while updating it, reading it again"),
+            }
+        }
+
+        Update::Contended
+    }
+
+
+    fn metadata_file(uid: &UniqID) -> String {
+        xochitl_root().to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".metadata"
+    }
+
+
+    pub fn parent_uuid_string(&self) -> String {
+        utils::uuid_to_string(self.folder.uuid)
+    }
+
+
+    pub fn archive_uuid_string(&self) -> String {
+        utils::uuid_to_string(self.archive.uuid)
+    }
+
+
+    pub fn sync_uuid_string(&self) -> String {
+        utils::uuid_to_string(self.sync_trigger.uuid)
+    }
+
+
+
+    // As if it had been written, without the document.
+    #[cfg(test)]
+    pub fn mark_current(&mut self, id: u64) {
+        self.current_items.insert(UniqID::new(), id);
+    }
+
+
+    // As if the user had moved a mirrored item into the Pocket folder.
+    #[cfg(test)]
+    pub fn mirror_moved_back(&mut self, id: u64) {
+        let uid = UniqID::new();
+
+        Self::write_file(&Self::metadata_file(&uid), &Metadata::new("DocumentType", "Mirrored", &self.parent_uuid_string())).unwrap();
+        self.mirrored_items.insert(uid, id);
+    }
+
+
+    // As if the user had moved an item into the Archive folder.
+    #[cfg(test)]
+    pub fn mark_read(&mut self, id: u64) {
+        let uid = UniqID::new();
+        let fname = xochitl_root().to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".metadata";
+
+        Self::write_file(&fname, &Metadata::new("DocumentType", "Read", &self.archive_uuid_string())).unwrap();
+        self.read_items.insert(uid, id);
+    }
+
+
+    // Only the given ids, those Pocket confirmed, the rest stay for the next sync.
+    pub fn clear_read(&mut self, ids: &[u64]) {
+        // Move items to the trash in Xochitl, then clear the btreemap
+        for (uid, _) in self.read_items.clone().into_iter().filter(|(_, val)| ids.contains(val)) {
+            // "Move" the item to the trash, unless it's to be kept where it is. It's archived in
+            // Pocket either way, if it can't be moved it stays where the user left it.
+            if self.device_archive != DeviceArchive::Keep {
+                match self.update_metadata(&uid, |metadata| metadata.parent = "trash".to_string()) {
+                    Update::Written => (),
+                    update => println!("ℹ Left item with uuid {} where it was: {update:?}", &utils::uuid_to_string(uid.uuid)),
+                }
+            }
+
+            // Remove the item from the read list.
+            if let Some(val) = self.read_items.remove(&uid) {
+                self.restored_items.remove(&val);
+                self.archived_items.insert(uid.clone(), val);
+                println!("ℹ Archived item with uuid {}", &utils::uuid_to_string(uid.uuid));
+            }
+        }
+    }
+}
+
+
+// How update_metadata() went.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Update {
+    Written,
+    // Someone else's document.
+    Foreign,
+    // Missing, or not JSON, e.g. halfway through being written.
+    Unreadable,
+    // xochitl is in the middle of changing it.
+    Busy,
+    // It kept changing while we tried.
+    Contended,
+    // It couldn't be written, e.g. on a read-only filesystem.
+    Unwritable,
+}
+
+
+// What consolidate() is about to do, see plan_consolidation().
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ConsolidationPlan {
+    // Folders an older state file knows nothing about, to be given a UUID.
+    pub new_folders: Vec<String>,
+    // Tracked documents whose metadata is gone, still tracked.
+    pub missing: Vec<PlannedItem>,
+    // Tracked documents whose metadata can't be read, e.g. halfway through being written.
+    pub unreadable: Vec<PlannedItem>,
+    // Moved into the Archive folder, or the trash, to be archived in Pocket.
+    pub read: Vec<PlannedItem>,
+    // Moved anywhere else, no longer tracked.
+    pub released: Vec<PlannedItem>,
+    // Written by the last sync, tracked from now on.
+    pub promoted: Vec<PlannedItem>,
+    // Pinned or unpinned in xochitl since the last time.
+    pub pinned: Vec<PlannedItem>,
+    pub unpinned: Vec<PlannedItem>,
+    // Moved out of the Pocket folder while pinned, still tracked and not archived.
+    pub kept: Vec<PlannedItem>,
+    // Mirrored from Pocket's archive and moved into the Pocket folder, to be re-added in Pocket.
+    pub readded: Vec<PlannedItem>,
+    // Mirrored and moved anywhere else, the trash included, no longer mirrored.
+    pub unmirrored: Vec<PlannedItem>,
+    // What Pocket will be sent for the read documents, unless upstream is read-only.
+    pub pocket_actions: Vec<PocketAction>,
+    // Read further since the last time, in percent by item id.
+    pub progress: BTreeMap<u64, u8>,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlannedItem {
+    pub uuid: UniqID,
+    pub id: u64,
+    // Where the document is now, when that's what the entry is about.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+
+impl ConsolidationPlan {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+
+impl fmt::Display for ConsolidationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "Nothing to consolidate");
+        }
+
+        for folder in &self.new_folders {
+            writeln!(f, "New UUID for the {folder} folder")?;
+        }
+
+        let sections = [
+            ("Missing, left tracked", &self.missing),
+            ("Unreadable metadata, left tracked", &self.unreadable),
+            ("Read, to be archived in Pocket", &self.read),
+            ("Moved elsewhere, no longer tracked", &self.released),
+            ("New, tracked from now on", &self.promoted),
+            ("Pinned on the device", &self.pinned),
+            ("Unpinned on the device", &self.unpinned),
+            ("Moved while pinned, left tracked", &self.kept),
+            ("Mirrored, moved back to be read again", &self.readded),
+            ("Mirrored, moved elsewhere, no longer mirrored", &self.unmirrored),
+        ];
+
+        for (title, items) in sections.iter().filter(|(_, items)| !items.is_empty()) {
+            writeln!(f, "{title}:")?;
+
+            for item in items.iter() {
+                match &item.parent {
+                    Some(parent) => writeln!(f, "  {} (item {}, in {parent})", utils::uuid_to_string(item.uuid.uuid), item.id)?,
+                    None => writeln!(f, "  {} (item {})", utils::uuid_to_string(item.uuid.uuid), item.id)?,
+                }
+            }
+        }
+
+        if !self.progress.is_empty() {
+            writeln!(f, "Read further:")?;
+
+            for (id, percent) in &self.progress {
+                writeln!(f, "  item {id}: {}", progress::bar(*percent))?;
+            }
+        }
+
+        if !self.pocket_actions.is_empty() {
+            writeln!(f, "Pocket actions:")?;
+
+            for action in &self.pocket_actions {
+                writeln!(f, "  {}", json!(action))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UninstallMode {
+    KeepDocuments,
+    RemoveDocuments,
+}
+
+
+// How restore_article() brought an item back.
+#[derive(Clone, Debug)]
+pub enum Restored {
+    Reparented(String),
+    Regenerated(Written),
+    Failed,
+}
+
+
+// What new_article() wrote to the device.
+#[derive(Clone, Debug)]
+pub struct Written {
+    pub uuid: String,
+    pub title: String,
+    pub url: String,
+    pub path: String,
+    pub format: String,
+}
+
+
+//
+// What `repocket status` shows, as `repocket status --json` prints it:
+//
+//   {"in_folder": [{"uuid": "…", "id": 1234, "name": "Rust lifetimes", "flags": {…}, "progress": 48,
+//     "trace": null}], "pinned": [], "failed": [{"id": 5678, "url": "…", "reason": "…"}],
+//     "next_sync": "2024-10-18T17:00:00+02:00"}
+//
+// With next_sync only when there's a timer, see schedule::Schedule.
+//
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct StatusReport {
+    pub in_folder: Vec<StatusItem>,
+    pub pinned: Vec<StatusItem>,
+    pub failed: Vec<StatusFailure>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_sync: Option<String>,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StatusItem {
+    pub uuid: String,
+    pub id: u64,
+    pub name: String,
+    // None for items from before there were flags.
+    pub flags: Option<ItemFlags>,
+    // In percent, once it's been opened, see the progress module.
+    pub progress: Option<u8>,
+    pub trace: Option<String>,
+}
+
+
+impl StatusItem {
+    fn lines(&self) -> Vec<String> {
+        let glance: Vec<String> = [
+            self.flags.as_ref().map(|flags| flags.to_string()).filter(|flags| !flags.is_empty()),
+            self.progress.map(progress::bar),
+        ].into_iter().flatten().collect();
+
+        let mut lines = match glance.is_empty() {
+            true => vec![format!("  {} ({})", self.name, self.id)],
+            false => vec![format!("  {} ({}): {}", self.name, self.id, glance.join(", "))],
+        };
+
+        if let Some(trace) = &self.trace {
+            lines.push(format!("    {trace}"));
+        }
+
+        lines
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StatusFailure {
+    pub id: u64,
+    pub url: String,
+    pub reason: String,
+}
+
+
+// A document that failed integrity::check(), see FSHandler::verify(). The problems are written out
+// as in `repocket verify`, in --json too.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Broken {
+    pub uuid: String,
+    pub id: u64,
+    pub problems: Vec<Problem>,
+}
+
+
+impl fmt::Display for Broken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let problems: Vec<String> = self.problems.iter().map(|problem| problem.to_string()).collect();
+
+        write!(f, "{} (item {}): {}", self.uuid, self.id, problems.join(", "))
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FailedItem {
+    url: String,
+    reason: String,
+    #[serde(default)]
+    trace: Option<ExtractionTrace>,
+}
+
+
+#[derive(Clone, Default, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct UniqID {
+    uuid: Uuid,
+}
+
+impl UniqID {
+    pub fn new() -> Self {
+        Self {
+            uuid : Uuid::new_v4(),
+        }
+    }
+}
+
+
+impl Serialize for UniqID {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+
+        let mut ebuf = Uuid::encode_buffer();
+        serializer.serialize_str(self.uuid.hyphenated().encode_lower(&mut ebuf))
+    }
+}
+
+
+impl<'de> Deserialize<'de> for UniqID {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        if s.is_empty() {
+            Err(std::io::ErrorKind::InvalidData).map_err(serde::de::Error::custom)
+        } else {
+            Ok(UniqID{ uuid: Uuid::parse_str(&s).map_err(serde::de::Error::custom)? })
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Metadata {
+    deleted: bool,
+    #[serde(rename = "lastModified")]
+    last_modified: String,
+    #[serde(rename = "lastOpenedPage")]
+    last_opened_page: u64,
+    #[serde(rename = "metadatamodified")]
+    metadata_modified: bool,
+    modified: bool,
+    parent: String,
+    #[serde(default)]
+    pinned: bool,
+    synced: bool,
+    #[serde(rename = "type")]
+    dtype: String,
+    version: u64,
+    #[serde(rename = "visibleName")]
+    visible_name: String,
+}
+
+impl Metadata {
+    pub fn new(
+        dtype: &str,
+        name: &str,
+        parent: &str
+     ) -> Self {
+        let last_modified = clock::now_millis();
+
+        Self {
+            deleted: false,
+            last_modified: last_modified.to_string(),
+            last_opened_page: 0,
+            metadata_modified: false,
+            modified: false,
+            parent: parent.to_string(),
+            pinned: false,
+            synced: false,
+            dtype: dtype.to_string(),
+            version: 1,
+            visible_name: name.to_string(),
+        }
+    }
+
+
+    #[cfg(test)]
+    pub fn load(fname: &str) -> Self {
+        // Open the file in read-only mode with buffer.
+        let fh = File::open(fname).expect(&format!("Couldn't open metadata file {}", fname));
+        let reader = BufReader::new(fh);
+
+        // Read the JSON contents of the file as an instance of `User`.
+        let metadata = serde_json::from_reader(reader).expect("Couldn't parse JSON from buffer");
+
+        metadata
+    }
+
+
+    // Like load(), for when a missing or broken file is not the end of the world.
+    pub fn try_load(fname: &str) -> Option<Self> {
+        let fh = File::open(fname).ok()?;
+
+        serde_json::from_reader(BufReader::new(fh)).ok()
+    }
+
+
+    pub fn json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Content {
+    // coverPageNumber: i64,
+    // documentMetadata: json,
+    // dummyContent: bool,
+    #[serde(rename(serialize = "extraMetadata"))]
+    extra_meta: serde_json::Value,
+    #[serde(rename(serialize = "fileType"))]
+    ftype: String,
+    #[serde(rename(serialize = "fontName"))]
+    font_name: String,
+    #[serde(rename(serialize = "lineHeight"))]
+    line_height: i64,
+    margins: u64,
+    #[serde(rename(serialize = "orientation"))]
+    orientation: String,
+    #[serde(rename(serialize = "pageCount"))]
     page_count: u64,
     // pages: array,
     #[serde(rename(serialize = "textAlignment"))]
     text_alignment: String,
     #[serde(rename(serialize = "textScale"))]
-    text_scale: u64,
+    text_scale: f64,
     transform: serde_json::Value,
 }
 
 
-impl Content {
-    pub fn new(ftype: &str) -> Self {
-        Self {
-            extra_meta: json!({}),
-            ftype: ftype.to_string(),
-            font_name: "".to_string(),
-            line_height: -1,
-            margins: 100,
-            orientation: "portrait".to_string(),
-            page_count: 1, // 1 seems to work well enough
-            text_alignment: "left".to_string(),
-            text_scale: 1,
-            transform: json!({
-                "m11": 1,
-                "m12": 0,
-                "m13": 0,
-                "m21": 0,
-                "m22": 1,
-                "m23": 0,
-                "m31": 0,
-                "m32": 0,
-                "m33": 1
-            }),
-        }
+impl Content {
+    pub fn new(ftype: &str) -> Self {
+        Self {
+            extra_meta: json!({}),
+            ftype: ftype.to_string(),
+            font_name: "".to_string(),
+            line_height: -1,
+            margins: 100,
+            orientation: "portrait".to_string(),
+            page_count: 1, // 1 seems to work well enough
+            text_alignment: "left".to_string(),
+            text_scale: 1.0,
+            transform: json!({
+                "m11": 1,
+                "m12": 0,
+                "m13": 0,
+                "m21": 0,
+                "m22": 1,
+                "m23": 0,
+                "m31": 0,
+                "m32": 0,
+                "m33": 1
+            }),
+        }
+    }
+
+
+    // Set per document, e.g. smaller for documentation, see ArticleHandler::text_scale().
+    pub fn set_text_scale(mut self, scale: f64) -> Self {
+        self.text_scale = scale;
+
+        self
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Version;
+    use std::fs;
+    use serial_test::serial;
+    use std::sync::Once;
+    use crate::mockserver::{self, Reply};
+    use crate::testutil;
+
+    static INIT: Once = Once::new();
+
+    const JSON: &'static str = r#"{
+        "folder": "94b8bffc-3e30-4ab8-90d4-64a53140c655",
+        "current_items": {
+            "2CC4E60A-6212-4DA6-BDD2-FDD713D70943": 9200,
+            "4AF52FB0-F787-46AA-84B7-66D0057DBDC5": 42
+        },
+        "archived_items": {
+            "0AE854CA-E195-4029-A861-70D52F71F8E8": 123
+        }
+    }"#;
+
+
+
+    #[test]
+    fn build_new() {
+        initialize();
+
+        let handler = FSHandler::new();
+
+        assert_eq!(Some(Version::Random), handler.folder.uuid.get_version());
+    }
+
+    #[test]
+    #[serial]
+    fn load_new() {
+        initialize();
+
+        let _ = fs::remove_file(config_file());
+
+        let handler = FSHandler::load().unwrap();
+        assert_eq!(Some(Version::Random), handler.folder.uuid.get_version());
+        assert!(handler.current_items.is_empty());
+        assert!(handler.archived_items.is_empty());
+    }
+
+
+    #[test]
+    #[serial]
+    fn load_existing() {
+        initialize();
+        create_test_config();
+
+        let handler = FSHandler::load().unwrap();
+
+        assert_eq!(Some(Version::Random), handler.folder.uuid.get_version());
+        assert_eq!(handler.current_items.len(), 2);
+        assert_eq!(handler.archived_items.len(), 1);
+    }
+
+    #[test]
+    #[serial]
+    fn write_config() {
+        initialize();
+        create_test_config();
+
+        let handler = FSHandler::load().unwrap();
+        let _ = fs::remove_file(config_file());
+        handler.save_config().unwrap();
+
+        let handler = FSHandler::load().unwrap();
+        assert_eq!(Some(Version::Random), handler.folder.uuid.get_version());
+        assert_eq!(handler.current_items.len(), 2);
+        assert_eq!(handler.archived_items.len(), 1);
+    }
+
+    #[test]
+    #[serial]
+    fn long_status() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        for id in 1..=STATUS_LISTED as u64 + 5 {
+            handler.mark_current(id);
+        }
+
+        let status = handler.status();
+        assert_eq!(status[0], format!("In the Pocket folder: {}", STATUS_LISTED + 5));
+        assert_eq!(status.len(), STATUS_LISTED + 2);
+        assert!(status.last().unwrap().starts_with("  ..and 5 more, "));
+        assert_eq!(handler.status_report().in_folder.len(), STATUS_LISTED + 5);
+    }
+
+    #[test]
+    #[serial]
+    fn corrupt_config() {
+        initialize();
+
+        // Cut short, as by a crash halfway through saving it.
+        let corrupt = &JSON[..JSON.len() / 2];
+        fs::write(config_file(), corrupt).unwrap();
+
+        assert!(matches!(FSHandler::load(), Err(FsError::Serde { path, .. }) if path == config_file()));
+        assert!(matches!(FSHandler::load_unconsolidated(config_file()), Err(FsError::Serde { .. })));
+        // Left for the user to look at, rather than written over with a new one.
+        assert_eq!(fs::read_to_string(config_file()).unwrap(), corrupt);
+
+        let _ = fs::remove_file(config_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[serial]
+    fn read_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        initialize();
+
+        let dir = std::env::temp_dir().join("repocket-read-only");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let state = dir.join("repocket.config").to_string_lossy().to_string();
+        let handler = FSHandler::load_from(&state).unwrap();
+
+        let set_mode = |path: &str, mode| fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+        set_mode(dir.to_str().unwrap(), 0o555);
+        set_mode(xochitl_root(), 0o555);
+
+        // Permissions mean nothing to root.
+        let enforced = File::create(dir.join("probe")).is_err();
+        let saved = handler.save_config();
+        let folders = handler.mkdir_pocket();
+
+        set_mode(dir.to_str().unwrap(), 0o755);
+        set_mode(xochitl_root(), 0o755);
+
+        if !enforced {
+            return;
+        }
+
+        assert!(matches!(saved, Err(FsError::Io { ref path, .. }) if *path == state));
+        assert!(saved.unwrap_err().to_string().contains(&state));
+        assert!(matches!(folders, Err(FsError::Io { path, .. }) if path.starts_with(xochitl_root())));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn missing_fields() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let unsaved = testutil::item(42, "");
+
+        assert!(matches!(FSHandler::required(&unsaved), Err(FsError::MissingField { field: "URL", .. })));
+        assert!(handler.new_article(&unsaved, ArticleOptions::default()).await.is_none());
+        assert!(handler.new_items.is_empty());
+        assert!(handler.failed_items.is_empty());
+    }
+
+
+    #[test]
+    #[serial]
+    fn remove_partial_document() {
+        initialize();
+
+        let uuid = utils::uuid_to_string(Uuid::new_v4());
+        for ext in ["epub", "content"] {
+            let _ = File::create(xochitl_root().to_string() + "/" + &uuid + "." + ext);
+        }
+
+        FSHandler::remove_document(&uuid);
+
+        for ext in ["epub", "content", "metadata"] {
+            assert!(!Path::new(&(xochitl_root().to_string() + "/" + &uuid + "." + ext)).exists());
+        }
+    }
+
+
+    #[test]
+    #[serial]
+    fn withhold_read_items() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let uid = UniqID::new();
+        let fname = xochitl_root().to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".metadata";
+        FSHandler::write_file(&fname, &Metadata::new("DocumentType", "Article", &handler.archive_uuid_string())).unwrap();
+        handler.read_items.insert(uid.clone(), 42);
+
+        handler.withhold_read();
+
+        assert!(handler.read_items.is_empty());
+        assert_eq!(handler.archived_items.get(&uid), Some(&42));
+        assert_eq!(handler.withheld_ids().collect::<Vec<u64>>(), vec![42]);
+        assert_eq!(Metadata::load(&fname).parent, "trash");
+
+        handler.clear_withheld(&[7]);
+        assert_eq!(handler.withheld_ids().count(), 1);
+        handler.clear_withheld(&[42]);
+        assert_eq!(handler.withheld_ids().count(), 0);
+    }
+
+
+    #[tokio::test]
+    #[serial]
+    async fn extra_roots_catch_up() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        handler.mkdir_pocket().unwrap();
+
+        let uuid = utils::uuid_to_string(UniqID::new().uuid);
+        let document = xochitl_root().to_string() + "/" + &uuid;
+        fs::write(document.clone() + ".epub", "epub").unwrap();
+        FSHandler::write_file(&(document.clone() + ".content"), &Content::new("epub")).unwrap();
+        FSHandler::write_file(&(document + ".metadata"), &Metadata::new("DocumentType", "Article", &handler.parent_uuid_string())).unwrap();
+
+        // One root that's there, with a document of its own, and one that's yet to be mounted.
+        let dir = std::env::temp_dir().join(format!("repocket-extra-roots-{}", Uuid::new_v4()));
+        let (mounted, unmounted) = (dir.join("mounted"), dir.join("unmounted"));
+        fs::create_dir_all(&mounted).unwrap();
+        fs::create_dir_all(&unmounted).unwrap();
+        fs::write(mounted.join("theirs.metadata"), "{}").unwrap();
+        let roots: Vec<String> = [Path::new(xochitl_root()), &mounted, &unmounted].iter()
+            .map(|root| root.to_string_lossy().to_string())
+            .collect();
+
+        handler.set_extra_roots(&roots);
+        assert_eq!(handler.extra_roots, roots[1..]);
+
+        handler.copy_to_extra_roots(&uuid).await;
+        assert!(mounted.join(uuid.clone() + ".epub").exists());
+        assert!(mounted.join(handler.parent_uuid_string() + ".metadata").exists());
+        assert_eq!(handler.extra_root_pending, BTreeMap::from([(roots[2].clone(), BTreeSet::from([uuid.clone()]))]));
+
+        // Kept with the state, which didn't use to have it.
+        let mut state = serde_json::to_value(&handler).unwrap();
+        let mut reloaded: FSHandler = serde_json::from_value(state.clone()).unwrap();
+        assert_eq!(reloaded.extra_root_pending, handler.extra_root_pending);
+        state.as_object_mut().unwrap().remove("extra_root_pending");
+        assert!(serde_json::from_value::<FSHandler>(state).unwrap().extra_root_pending.is_empty());
+
+        // Nothing until it's mounted.
+        reloaded.set_extra_roots(&roots);
+        assert_eq!(reloaded.catch_up_extra_roots().await, 0);
+        fs::write(unmounted.join("theirs.metadata"), "{}").unwrap();
+        assert_eq!(reloaded.catch_up_extra_roots().await, 1);
+        assert!(unmounted.join(uuid.clone() + ".metadata").exists());
+        assert!(reloaded.extra_root_pending.is_empty());
+
+        // A root no longer set is let go.
+        handler.set_extra_roots(&roots[..2]);
+        assert!(handler.extra_root_pending.is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[serial]
+    fn reconcile_with_pocket() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let mut statuses = HashMap::new();
+        let mut uids = Vec::new();
+
+        // Archived upstream, deleted upstream, still unread and archived upstream but being read.
+        for (id, status, page) in [
+            (1, ItemStatus::Archived, 0),
+            (2, ItemStatus::Deleted, 0),
+            (3, ItemStatus::Unread, 0),
+            (4, ItemStatus::Archived, 5),
+        ] {
+            let uid = UniqID::new();
+            let fname = xochitl_root().to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".metadata";
+            let mut metadata = Metadata::new("DocumentType", "Article", &handler.parent_uuid_string());
+            metadata.last_opened_page = page;
+            FSHandler::write_file(&fname, &metadata).unwrap();
+
+            handler.current_items.insert(uid.clone(), id);
+            statuses.insert(id, status);
+            uids.push((uid, fname));
+        }
+
+        assert_eq!(handler.reconcile(&statuses, ReconcileTarget::Archive, false), 2);
+        assert_eq!(Metadata::load(&uids[0].1).parent, handler.archive_uuid_string());
+        assert_eq!(Metadata::load(&uids[1].1).parent, handler.archive_uuid_string());
+        assert_eq!(Metadata::load(&uids[2].1).parent, handler.parent_uuid_string());
+        assert_eq!(Metadata::load(&uids[3].1).parent, handler.parent_uuid_string());
+        assert_eq!(handler.current_items.len(), 2);
+        assert_eq!(handler.archived_items.len(), 2);
+
+        // Forcing it moves the item being read, to the trash this time.
+        assert_eq!(handler.reconcile(&statuses, ReconcileTarget::Trash, true), 1);
+        assert_eq!(Metadata::load(&uids[3].1).parent, "trash");
+        assert!(handler.current_items.contains_key(&uids[2].0));
+    }
+
+    #[test]
+    #[serial]
+    fn pinned_items() {
+        initialize();
+
+        // (pinned on the device, tagged in Pocket) -> (archived when read, moved when gone from Pocket)
+        let matrix = [
+            ((false, false), (true, true)),
+            ((true, false), (false, false)),
+            ((false, true), (false, false)),
+            ((true, true), (false, false)),
+        ];
+
+        for ((on_device, tagged), (archived, reconciled)) in matrix {
+            let case = format!("pinned on the device: {on_device}, tagged: {tagged}");
+
+            // Read, i.e. moved to the Archive folder.
+            let mut handler = FSHandler::new();
+            let (uid, fname) = tracked_document(&mut handler, 1);
+            let tags = HashMap::from([(1, ItemStatus::Unread)]);
+            if tagged {
+                assert_eq!(handler.set_tag_pins(&tags), 1);
+            }
+            let archive = handler.archive_uuid_string();
+            foreign_write(&fname, |metadata| {
+                metadata.pinned = on_device;
+                metadata.parent = archive;
+            });
+
+            let plan = handler.plan_consolidation();
+            assert_eq!(plan.read.len() == 1, archived, "{case}");
+            assert_eq!(plan.kept.len() == 1, !archived, "{case}");
+            assert_eq!(plan.pocket_actions.is_empty(), !archived, "{case}");
+            handler.apply_consolidation(&plan);
+            assert_eq!(handler.current_items.contains_key(&uid), !archived, "{case}");
+            assert_eq!(handler.is_pinned(1), on_device || tagged, "{case}");
+
+            // Archived in Pocket while still in the Pocket folder.
+            let mut handler = FSHandler::new();
+            let (uid, fname) = tracked_document(&mut handler, 2);
+            let tags = HashMap::from([(2, ItemStatus::Unread)]);
+            if tagged {
+                handler.set_tag_pins(&tags);
+            }
+            foreign_write(&fname, |metadata| metadata.pinned = on_device);
+            handler.consolidate();
+
+            let statuses = HashMap::from([(2, ItemStatus::Archived)]);
+            assert_eq!(handler.reconcile(&statuses, ReconcileTarget::Archive, true) == 1, reconciled, "{case}");
+            assert_eq!(handler.archived_items.contains_key(&uid), reconciled, "{case}");
+        }
+
+        // Unpinning on the device, and removing the tag, each bring an item back to normal.
+        let mut handler = FSHandler::new();
+        let (_, fname) = tracked_document(&mut handler, 3);
+        foreign_write(&fname, |metadata| metadata.pinned = true);
+        handler.set_tag_pins(&HashMap::from([(3, ItemStatus::Unread), (99, ItemStatus::Unread)]));
+        handler.consolidate();
+        assert!(handler.pinned_on_device.contains(&3) && handler.pinned_by_tag == BTreeSet::from([3]));
+
+        foreign_write(&fname, |metadata| metadata.pinned = false);
+        let plan = handler.plan_consolidation();
+        assert_eq!(plan.unpinned.len(), 1);
+        handler.apply_consolidation(&plan);
+        assert!(handler.is_pinned(3));
+        assert_eq!(handler.set_tag_pins(&HashMap::new()), 1);
+        assert!(!handler.is_pinned(3));
+
+        // Pinned items are listed on their own.
+        handler.pin_by_tag(3);
+        let status = handler.status();
+        assert_eq!(status[0], "In the Pocket folder: 0");
+        assert_eq!(status[1], "Pinned: 1");
+        assert_eq!(status[2], "  Tracked (3)");
+    }
+
+    #[test]
+    fn reconcile_is_due_every_n_syncs() {
+        let mut handler = FSHandler::new();
+
+        assert!(!handler.reconcile_due(3));
+        assert!(!handler.reconcile_due(3));
+        assert!(handler.reconcile_due(3));
+        assert!(!handler.reconcile_due(3));
+        assert!(!handler.reconcile_due(0));
+    }
+
+
+    #[tokio::test]
+    #[serial]
+    async fn restore_from_archive_folder() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let uid = UniqID::new();
+        let uuid = utils::uuid_to_string(uid.uuid);
+        let fname = xochitl_root().to_string() + "/" + &uuid + ".metadata";
+        FSHandler::write_file(&fname, &Metadata::new("DocumentType", "Archived", &handler.archive_uuid_string())).unwrap();
+        fs::write(xochitl_root().to_string() + "/" + &uuid + ".epub", "epub").unwrap();
+        handler.archived_items.insert(uid.clone(), 100);
+
+        // Nothing should be downloaded, the URL leads nowhere.
+        let item = testutil::item(100, "http://127.0.0.1:9/gone");
+        let restored = handler.restore_article(&item, ArticleOptions::default()).await;
+
+        assert!(matches!(restored, Restored::Reparented(ref restored) if *restored == uuid));
+        assert_eq!(Metadata::load(&fname).parent, handler.parent_uuid_string());
+        assert_eq!(handler.current_items.get(&uid), Some(&100));
+        assert!(handler.archived_items.is_empty());
+        assert!(handler.is_restored(100));
+
+        // Still archived in Pocket, which is no reason to move it away again.
+        let statuses = HashMap::from([(100, ItemStatus::Archived)]);
+        assert_eq!(handler.reconcile(&statuses, ReconcileTarget::Archive, false), 0);
+    }
+
+
+    #[tokio::test]
+    #[serial]
+    async fn mirror_archive_fills_and_rotates() {
+        initialize();
+
+        let page = |title: &str| format!("<html><head><title>{title}</title></head><body><p>Archived a while ago, worth another look.</p></body></html>");
+        let site = mockserver::serve(vec![
+            ("/fetched", Reply::ok("text/html", page("Fetched").as_bytes())),
+            ("/newer", Reply::ok("text/html", page("Newer").as_bytes())),
+        ]).await;
+
+        let mut handler = FSHandler::new();
+        let _ = handler.mkdir_pocket();
+        let archive = handler.archive_uuid_string();
+
+        // Read a while ago, its document in the trash since. Nothing is downloaded for it.
+        let kept = testutil::item(500, "http://127.0.0.1:9/kept");
+        let kept_uid = UniqID { uuid: handler.document_uuid(&kept) };
+        FSHandler::write_file(&FSHandler::metadata_file(&kept_uid), &Metadata::new("DocumentType", "Kept", "trash")).unwrap();
+        fs::write(xochitl_root().to_string() + "/" + &utils::uuid_to_string(kept_uid.uuid) + ".epub", "epub").unwrap();
+        handler.archived_items.insert(kept_uid.clone(), 500);
+
+        let fetched = testutil::item(501, &format!("{site}/fetched"));
+        let fetched_uid = UniqID { uuid: handler.document_uuid(&fetched) };
+        FSHandler::remove_document(&utils::uuid_to_string(fetched_uid.uuid));
+
+        // Off the budget only what's there already.
+        assert_eq!(handler.mirror_archive(&[kept.clone(), fetched.clone()], 2, ArticleOptions::default(), false).await, (1, 0));
+        assert_eq!(handler.mirrored_items.get(&kept_uid), Some(&500));
+        assert_eq!(Metadata::load(&FSHandler::metadata_file(&kept_uid)).parent, archive);
+        assert!(handler.archived_items.is_empty());
+
+        // In the Pocket folder, and so left alone.
+        handler.current_items.insert(UniqID::new(), 502);
+        let current = testutil::item(502, "http://127.0.0.1:9/current");
+
+        assert_eq!(handler.mirror_archive(&[current, kept.clone(), fetched.clone()], 3, ArticleOptions::default(), true).await, (1, 0));
+        assert_eq!(handler.mirrored_items.get(&fetched_uid), Some(&501));
+        assert_eq!(Metadata::load(&FSHandler::metadata_file(&fetched_uid)).parent, archive);
+        assert!(handler.new_items.is_empty());
+        assert_eq!(handler.mirrored_items.len(), 2);
+
+        // A newer one in, the two oldest out.
+        let newer = testutil::item(503, &format!("{site}/newer"));
+        assert_eq!(handler.mirror_archive(&[newer.clone(), kept, fetched], 1, ArticleOptions::default(), true).await, (1, 2));
+        assert_eq!(handler.mirrored_items.values().collect::<Vec<_>>(), [&503]);
+        assert_eq!(Metadata::load(&FSHandler::metadata_file(&kept_uid)).parent, "trash");
+        assert_eq!(Metadata::load(&FSHandler::metadata_file(&fetched_uid)).parent, "trash");
+        assert_eq!(handler.archived_items.len(), 2);
+
+        for uid in [&kept_uid, &fetched_uid, &UniqID { uuid: handler.document_uuid(&newer) }] {
+            FSHandler::remove_document(&utils::uuid_to_string(uid.uuid));
+        }
+    }
+
+    // Back into the Pocket folder to be read again, or anywhere else and the user's.
+    #[test]
+    #[serial]
+    fn mirrored_items_moved() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let (back, away, stays) = (UniqID::new(), UniqID::new(), UniqID::new());
+        for (uid, parent) in [(&back, handler.parent_uuid_string()), (&away, "trash".to_string()), (&stays, handler.archive_uuid_string())] {
+            FSHandler::write_file(&FSHandler::metadata_file(uid), &Metadata::new("DocumentType", "Mirrored", &parent)).unwrap();
+        }
+        handler.mirrored_items.extend([(back.clone(), 510), (away.clone(), 511), (stays.clone(), 512)]);
+
+        let plan = handler.plan_consolidation();
+        assert_eq!(plan.readded.iter().map(|item| item.id).collect::<Vec<_>>(), [510]);
+        assert_eq!(plan.unmirrored.iter().map(|item| item.id).collect::<Vec<_>>(), [511]);
+        assert_eq!(plan.pocket_actions, vec![PocketAction::Readd { item_id: 510 }]);
+        assert!(plan.read.is_empty() && plan.released.is_empty());
+
+        handler.apply_consolidation(&plan);
+        assert_eq!(handler.current_items.get(&back), Some(&510));
+        assert_eq!(handler.mirrored_items.keys().collect::<Vec<_>>(), [&stays]);
+        assert_eq!(handler.readd_ids().collect::<Vec<_>>(), [510]);
+
+        handler.readded(&[510]);
+        assert_eq!(handler.readd_ids().count(), 0);
+        assert!(handler.take_readded(510));
+        assert!(!handler.take_readded(510));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn restore_trashed_item() {
+        initialize();
+
+        let page = b"<html><head><title>Back</title></head><body><p>Here again, to be read once more.</p></body></html>";
+        let base = mockserver::serve(vec![("/article", Reply::ok("text/html", page))]).await;
+
+        let mut handler = FSHandler::new();
+        let uid = UniqID::new();
+        let uuid = utils::uuid_to_string(uid.uuid);
+        FSHandler::write_file(&(xochitl_root().to_string() + "/" + &uuid + ".metadata"),
+            &Metadata::new("DocumentType", "Trashed", "trash")).unwrap();
+        handler.archived_items.insert(uid.clone(), 200);
+
+        let item = testutil::item(200, &format!("{base}/article"));
+        let article = match handler.restore_article(&item, ArticleOptions::default()).await {
+            Restored::Regenerated(article) => article,
+            other => panic!("Expected the item to be downloaded again, got {other:?}"),
+        };
+
+        let metadata = Metadata::load(&(xochitl_root().to_string() + "/" + &article.uuid + ".metadata"));
+        assert_eq!(metadata.parent, handler.parent_uuid_string());
+        assert!(Path::new(&article.path).exists());
+        assert!(handler.archived_items.is_empty());
+        assert!(handler.new_items.values().any(|id| *id == 200));
+        assert!(handler.is_restored(200));
+
+        // Archiving it again forgets it was ever restored.
+        let new_uid = handler.new_items.keys().next().unwrap().clone();
+        handler.new_items.clear();
+        handler.read_items.insert(new_uid, 200);
+        handler.clear_read(&[200]);
+        assert!(!handler.is_restored(200));
+    }
+
+
+    #[tokio::test]
+    #[serial]
+    async fn smaller_text_for_documentation() {
+        initialize();
+
+        let docs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/docs/docsrs.html")).unwrap();
+        let page = b"<html><head><title>Prose</title></head><body><p>Nothing to look up in here, only to read.</p></body></html>";
+        let base = mockserver::serve(vec![("/docs", Reply::ok("text/html", &docs)), ("/article", Reply::ok("text/html", page))]).await;
+
+        let mut handler = FSHandler::new();
+        for (id, path, scale) in [(300, "docs", 0.8), (301, "article", 1.0)] {
+            let written = handler.new_article(&testutil::item(id, &format!("{base}/{path}")), ArticleOptions::default()).await.unwrap();
+            let content: serde_json::Value = serde_json::from_slice(&fs::read(xochitl_root().to_string() + "/" + &written.uuid + ".content").unwrap()).unwrap();
+
+            assert_eq!(content["textScale"], scale, "{path}");
+        }
+    }
+
+
+    #[tokio::test]
+    #[serial]
+    async fn broken_epub_writes_nothing() {
+        initialize();
+
+        let page = b"<html><head><title>Broken</title></head><body><p>Never makes it to the device.</p></body></html>";
+        let base = mockserver::serve(vec![("/article", Reply::ok("text/html", page))]).await;
+
+        let mut handler = FSHandler::new();
+        let item = testutil::item(300, &format!("{base}/article"));
+        let uuid = ArticleHandler::new(&item).uuid_string();
+
+        crate::articlehandler::BROKEN_ZIP.with(|broken| broken.set(true));
+        let written = handler.new_article(&item, ArticleOptions::default()).await;
+        crate::articlehandler::BROKEN_ZIP.with(|broken| broken.set(false));
+
+        assert!(written.is_none());
+        for ext in ["epub", "content", "metadata"] {
+            assert!(!Path::new(&(xochitl_root().to_string() + "/" + &uuid + "." + ext)).exists(), "{ext}");
+        }
+        assert!(handler.new_items.is_empty());
+        assert!(handler.failed_items.contains_key(&300));
+    }
+
+
+    #[tokio::test]
+    #[serial]
+    async fn flags_in_the_status() {
+        initialize();
+
+        let page = b"<html><head><title>Flagged</title></head><body><p>A few words to read.</p></body></html>";
+        let base = mockserver::serve(vec![("/article", Reply::ok("text/html", page))]).await;
+
+        let mut handler = FSHandler::new();
+        let _ = handler.mkdir_pocket();
+        let item = testutil::pocket_item(json!({
+            "item_id": "400",
+            "resolved_id": "400",
+            "resolved_url": format!("{base}/article"),
+            "has_image": "1",
+            "word_count": "5",
+        }));
+
+        // On a tight data budget.
+        let options = ArticleOptions { images: false, ..Default::default() };
+        handler.new_article(&item, options).await.unwrap();
+
+        let flags = &handler.flags[&400];
+        assert!(flags.article && flags.images && !flags.video && flags.images_omitted);
+        assert_eq!(flags.minutes, Some(1));
+
+        let status = handler.status();
+        assert_eq!(status[0], "In the Pocket folder: 1");
+        assert!(status[1].ends_with("(400): 📄 🖼 ⏱ 1 min, images omitted"), "{}", status[1]);
+
+        // Kept in the state, and older states without any still load.
+        let state: FSHandler = serde_json::from_value(serde_json::to_value(&handler).unwrap()).unwrap();
+        assert_eq!(state.flags, handler.flags);
+        let state: FSHandler = serde_json::from_str(JSON).unwrap();
+        assert!(state.flags.is_empty());
+        assert!(state.status()[1].ends_with("(9200)") || state.status()[1].ends_with("(42)"));
+    }
+
+
+    #[tokio::test]
+    #[serial]
+    async fn inspect_a_document() {
+        initialize();
+
+        let page = b"<html><head><title>Inspected</title></head><body><p>A few words to read.</p></body></html>";
+        let base = mockserver::serve(vec![("/article", Reply::ok("text/html", page))]).await;
+
+        let mut handler = FSHandler::new();
+        let _ = handler.mkdir_pocket();
+        let written = handler.new_article(&testutil::item(500, &format!("{base}/article")), ArticleOptions::default()).await.unwrap();
+
+        let lines = handler.inspect(&written.uuid).unwrap();
+        assert_eq!(lines[0], "Item 500");
+        assert!(lines.iter().any(|line| line.starts_with("Engine: ")));
+        assert!(lines.contains(&"Sanitization: standard".to_string()));
+        assert!(handler.status().iter().any(|line| line.contains(", 0/0 images")));
+
+        // Failed items are found by the UUID their document would have had.
+        let gone = "http://127.0.0.1:1/article".to_string();
+        assert!(handler.new_article(&testutil::item(501, &gone), ArticleOptions::default()).await.is_none());
+        let uuid = utils::uuid_to_string(Uuid::new_v5(&Uuid::NAMESPACE_OID, gone.as_bytes()));
+        let lines = handler.inspect(&uuid).unwrap();
+        assert_eq!(lines[0], "Item 501");
+        assert!(lines[1].starts_with(&format!("Failed ({gone}): ")));
+        assert!(lines.contains(&"Engine: none".to_string()));
+
+        // Kept in the state.
+        let state: FSHandler = serde_json::from_value(serde_json::to_value(&handler).unwrap()).unwrap();
+        assert_eq!(state.inspect(&uuid), Some(lines));
+        assert_eq!(handler.inspect(&UniqID::new().uuid.to_string()), None);
+        assert_eq!(handler.inspect("not a uuid"), None);
+    }
+
+
+    fn verified_item(base: &str, id: u64) -> PocketItem {
+        testutil::item(id, &format!("{base}/article/{id}"))
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn sidecar_gate() {
+        initialize();
+
+        let page = b"<html><head><title>Gated</title></head><body><p>A few words to read.</p></body></html>";
+        let base = mockserver::serve(vec![("/article/700", Reply::ok("text/html", page))]).await;
+        let item = verified_item(&base, 700);
+        let uuid = ArticleHandler::new(&item).uuid_string();
+
+        // The .content file can't be written, as if the disk was full.
+        let blocker = xochitl_root().to_string() + "/" + &uuid + ".content";
+        let _ = fs::create_dir(&blocker);
+
+        let mut handler = FSHandler::new();
+        assert!(handler.new_article(&item, ArticleOptions::default()).await.is_none());
+        let _ = fs::remove_dir(&blocker);
+
+        assert!(handler.new_items.is_empty());
+        assert!(handler.failed_items[&700].reason.contains(".content doesn't parse"), "{}", handler.failed_items[&700].reason);
+        for ext in ["epub", "metadata"] {
+            assert!(!Path::new(&(xochitl_root().to_string() + "/" + &uuid + "." + ext)).exists(), "{ext}");
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn verify_and_repair() {
+        initialize();
+
+        let page = b"<html><head><title>Verified</title></head><body><p>A few words to read.</p></body></html>";
+        let paths: Vec<String> = (710..=713).map(|id| format!("/article/{id}")).collect();
+        let base = mockserver::serve(paths.iter().map(|path| (path.as_str(), Reply::ok("text/html", page))).collect()).await;
+        let items: Vec<PocketItem> = (710..=713).map(|id| verified_item(&base, id)).collect();
+
+        let mut handler = FSHandler::new();
+        let mut uuids = Vec::new();
+        for item in &items {
+            uuids.push(handler.new_article(item, ArticleOptions::default()).await.unwrap().uuid);
+        }
+        assert!(handler.verify().is_empty());
+
+        // Cut short in three different ways, 710 is fine.
+        let file = |uuid: &str, ext: &str| xochitl_root().to_string() + "/" + uuid + "." + ext;
+        let epub = fs::read(file(&uuids[1], "epub")).unwrap();
+        fs::write(file(&uuids[1], "epub"), &epub[..epub.len() - 100]).unwrap();
+        fs::write(file(&uuids[2], "epub"), b"").unwrap();
+        fs::write(file(&uuids[3], "content"), b"{\"fileType\": \"ep").unwrap();
+
+        let broken = handler.verify();
+        let found: BTreeSet<u64> = broken.iter().map(|doc| doc.id).collect();
+        assert_eq!(found, BTreeSet::from([711, 712, 713]));
+        let problems = |id: u64| broken.iter().find(|doc| doc.id == id).unwrap().problems.clone();
+        assert!(matches!(problems(711)[..], [Problem::BrokenZip(_)]));
+        assert_eq!(problems(712), [Problem::TooSmall("epub".to_string(), 0)]);
+        assert!(matches!(problems(713)[..], [Problem::Unparsable(ref ext, _)] if ext == "content"));
+
+        // 713 is gone from Pocket.
+        let done = handler.repair(&broken, &items[..3], ArticleOptions::default()).await;
+        assert!(done.iter().any(|line| line.ends_with("(item 711) written again")));
+        assert!(done.iter().any(|line| line.ends_with("(item 712) written again")));
+        assert!(done.iter().any(|line| line.ends_with("(item 713) removed, it's no longer in Pocket")));
+        assert!(handler.verify().is_empty());
+        assert_eq!(handler.new_items.len(), 3);
+        assert!(!Path::new(&file(&uuids[3], "epub")).exists());
+    }
+
+    #[test]
+    #[serial]
+    fn clear_only_confirmed_reads() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let mut uids = Vec::new();
+        for id in [1, 2, 3] {
+            let uid = UniqID::new();
+            let fname = xochitl_root().to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".metadata";
+            FSHandler::write_file(&fname, &Metadata::new("DocumentType", "Read", &handler.archive_uuid_string())).unwrap();
+            handler.read_items.insert(uid.clone(), id);
+            uids.push(uid);
+        }
+
+        handler.clear_read(&[1, 3]);
+
+        assert_eq!(handler.read_ids().collect::<Vec<u64>>(), vec![2]);
+        assert_eq!(handler.archived_items.len(), 2);
+        assert!(handler.archived_items.contains_key(&uids[0]) && handler.archived_items.contains_key(&uids[2]));
+    }
+
+
+    #[test]
+    fn monthly_accounting() {
+        let mut handler = FSHandler::new();
+
+        handler.add_downloaded("2024-10", 1000);
+        handler.add_downloaded("2024-10", 500);
+        handler.add_downloaded("2024-11", 42);
+
+        assert_eq!(handler.downloaded_in("2024-10"), 1500);
+        assert_eq!(handler.downloaded_in("2024-11"), 42);
+        assert_eq!(handler.downloaded_in("2024-12"), 0);
+    }
+
+
+    // A document in the Pocket folder, then moved into parent by the user.
+    fn moved_document(handler: &mut FSHandler, id: u64, parent: &str) -> String {
+        let uid = UniqID::new();
+        let fname = xochitl_root().to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".metadata";
+
+        FSHandler::write_file(&fname, &Metadata::new("DocumentType", "Moved", parent)).unwrap();
+        handler.current_items.insert(uid, id);
+
+        fname
     }
-}
 
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use uuid::Version;
-    use std::fs;
-    use serial_test::serial;
-    use std::sync::Once;
+    // A document rePocket wrote, still in the Pocket folder.
+    fn tracked_document(handler: &mut FSHandler, id: u64) -> (UniqID, String) {
+        let uid = UniqID::new();
+        let fname = FSHandler::metadata_file(&uid);
 
-    static INIT: Once = Once::new();
+        FSHandler::write_file(&fname, &Metadata::new("DocumentType", "Tracked", &handler.parent_uuid_string())).unwrap();
+        handler.current_items.insert(uid.clone(), id);
 
-    const JSON: &'static str = r#"{
-        "folder": "94b8bffc-3e30-4ab8-90d4-64a53140c655",
-        "current_items": {
-            "2CC4E60A-6212-4DA6-BDD2-FDD713D70943": 9200,
-            "4AF52FB0-F787-46AA-84B7-66D0057DBDC5": 42
-        },
-        "archived_items": {
-            "0AE854CA-E195-4029-A861-70D52F71F8E8": 123
+        (uid, fname)
+    }
+
+
+    // Another tool changing a document, as well behaved as xochitl, i.e. bumping lastModified.
+    fn foreign_write<F: FnOnce(&mut Metadata)>(fname: &str, change: F) {
+        let mut metadata = Metadata::load(fname);
+
+        change(&mut metadata);
+        metadata.last_modified = (metadata.last_modified.parse::<u64>().unwrap() + 1).to_string();
+        FSHandler::write_file(fname, &metadata).unwrap();
+    }
+
+
+    #[test]
+    #[serial]
+    fn concurrent_writer_is_not_clobbered() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let (uid, fname) = tracked_document(&mut handler, 1);
+
+        // Renamed elsewhere right after rePocket read it.
+        let mut calls = 0;
+        let update = handler.update_metadata(&uid, |metadata| {
+            calls += 1;
+            if calls == 1 {
+                foreign_write(&fname, |foreign| foreign.visible_name = "Renamed elsewhere".to_string());
+            }
+            metadata.parent = "trash".to_string();
+        });
+
+        assert_eq!(update, Update::Written);
+        assert_eq!(calls, 2);
+        let metadata = Metadata::load(&fname);
+        assert_eq!(metadata.visible_name, "Renamed elsewhere");
+        assert_eq!(metadata.parent, "trash");
+
+        // Changed elsewhere every single time.
+        let update = handler.update_metadata(&uid, |metadata| {
+            foreign_write(&fname, |foreign| foreign.pinned = !foreign.pinned);
+            metadata.parent = String::new();
+        });
+
+        assert_eq!(update, Update::Contended);
+        assert_eq!(Metadata::load(&fname).parent, "trash");
+    }
+
+
+    #[test]
+    #[serial]
+    fn xochitl_busy_with_the_document() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let (uid, fname) = tracked_document(&mut handler, 1);
+
+        let update = handler.update_metadata(&uid, |metadata| {
+            let mut busy = Metadata::load(&fname);
+            busy.metadata_modified = true;
+            FSHandler::write_file(&fname, &busy).unwrap();
+
+            metadata.parent = "trash".to_string();
+        });
+
+        assert_eq!(update, Update::Busy);
+        let metadata = Metadata::load(&fname);
+        assert_eq!(metadata.parent, handler.parent_uuid_string());
+        assert!(metadata.metadata_modified);
+
+        // Flagged before rePocket got to it, that's just how the file is.
+        assert_eq!(handler.update_metadata(&uid, |metadata| metadata.parent = "trash".to_string()), Update::Written);
+    }
+
+
+    #[test]
+    #[serial]
+    fn foreign_documents_are_left_alone() {
+        initialize();
+
+        let handler = FSHandler::new();
+        let _ = handler.mkdir_pocket();
+
+        // A PDF another tool dropped into the Pocket folder, and an article of ours rePocket lost track of.
+        let pdf = UniqID::new();
+        let pdf_file = FSHandler::metadata_file(&pdf);
+        FSHandler::write_file(&pdf_file, &Metadata::new("DocumentType", "Dropped in", &handler.parent_uuid_string())).unwrap();
+        let lost = UniqID { uuid: Uuid::new_v5(&Uuid::NAMESPACE_OID, b"https://example.com/lost") };
+        FSHandler::write_file(&FSHandler::metadata_file(&lost), &Metadata::new("DocumentType", "Lost", &handler.parent_uuid_string())).unwrap();
+
+        assert_eq!(handler.update_metadata(&pdf, |metadata| metadata.parent = "trash".to_string()), Update::Foreign);
+        assert_eq!(handler.update_metadata(&lost, |metadata| metadata.parent = "trash".to_string()), Update::Written);
+
+        // Uninstalling trashes the folders, and leaves whatever else is in them where it is.
+        handler.uninstall(UninstallMode::RemoveDocuments, false, &[]);
+        assert_eq!(Metadata::load(&pdf_file).parent, handler.parent_uuid_string());
+        assert_eq!(Metadata::load(&FSHandler::metadata_file(&handler.folder)).parent, "trash");
+    }
+
+
+    #[test]
+    #[serial]
+    fn consolidate_while_being_written() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let _ = handler.mkdir_pocket();
+        let (uid, fname) = tracked_document(&mut handler, 1);
+
+        // Halfway through being written by someone else.
+        fs::write(&fname, "{\"deleted\": false, \"lastModi").unwrap();
+        handler.consolidate();
+        assert_eq!(handler.current_items.get(&uid), Some(&1));
+        assert_eq!(handler.read_ids().count(), 0);
+
+        // Written whole by the next sync, moved into the Archive folder by the user.
+        FSHandler::write_file(&fname, &Metadata::new("DocumentType", "Tracked", &handler.archive_uuid_string())).unwrap();
+        handler.consolidate();
+        assert_eq!(handler.read_ids().collect::<Vec<u64>>(), vec![1]);
+
+        // Renamed elsewhere before Pocket confirmed the archive.
+        foreign_write(&fname, |foreign| foreign.visible_name = "Renamed elsewhere".to_string());
+        handler.clear_read(&[1]);
+
+        let metadata = Metadata::load(&fname);
+        assert_eq!(metadata.parent, "trash");
+        assert_eq!(metadata.visible_name, "Renamed elsewhere");
+    }
+
+
+    #[test]
+    #[serial]
+    fn query_ts_moved_over() {
+        initialize();
+
+        // The state of a version with a single query.
+        let mut state = serde_json::to_value(FSHandler::new()).unwrap();
+        state.as_object_mut().unwrap().remove("ts_last_queries");
+        state["ts_last_query"] = 1729763686.into();
+        let mut handler: FSHandler = serde_json::from_value(state).unwrap();
+        let _ = handler.mkdir_pocket();
+        assert_eq!(handler.last_query_ts(), 1729763686);
+        assert_eq!(handler.query_ts("favorites"), 0);
+
+        handler.consolidate();
+        handler.set_query_ts("favorites", 1729763700);
+        let saved = serde_json::to_value(&handler).unwrap();
+        assert_eq!(saved["ts_last_queries"], serde_json::json!({ DEFAULT_QUERY: 1729763686, "favorites": 1729763700 }));
+        assert_eq!(saved["ts_last_query"], 0);
+        assert_eq!(handler.last_query_ts(), 1729763686);
+    }
+
+
+    #[test]
+    #[serial]
+    fn consolidation_plan() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let _ = handler.mkdir_pocket();
+        // From before there was a Sync folder.
+        handler.sync_trigger = UniqID::default();
+
+        let (kept, _) = tracked_document(&mut handler, 1);
+        let (missing, fname) = tracked_document(&mut handler, 2);
+        fs::remove_file(&fname).unwrap();
+        let (unreadable, fname) = tracked_document(&mut handler, 3);
+        fs::write(&fname, "{\"deleted\": false, \"lastModi").unwrap();
+        let (read, fname) = tracked_document(&mut handler, 4);
+        foreign_write(&fname, |metadata| metadata.parent = handler.archive_uuid_string());
+        let (released, fname) = tracked_document(&mut handler, 5);
+        foreign_write(&fname, |metadata| metadata.parent = String::new());
+        let promoted = UniqID::new();
+        FSHandler::write_file(&FSHandler::metadata_file(&promoted), &Metadata::new("DocumentType", "New", &handler.parent_uuid_string())).unwrap();
+        handler.new_items.insert(promoted.clone(), 6);
+
+        for id in [1, 5] {
+            handler.flags.insert(id, ItemFlags::default());
+            handler.traces.insert(id, ExtractionTrace::default());
+            handler.progress.insert(id, 40);
+            handler.progress_tagged.insert(id, 25);
         }
-    }"#;
 
+        let before = serde_json::to_value(&handler).unwrap();
+        let plan = handler.plan_consolidation();
+        assert_eq!(serde_json::to_value(&handler).unwrap(), before);
+
+        let uuids = |items: &[PlannedItem]| items.iter().map(|item| item.uuid.clone()).collect::<Vec<UniqID>>();
+        assert_eq!(plan.new_folders, vec!["sync_trigger"]);
+        assert_eq!(uuids(&plan.missing), vec![missing.clone()]);
+        assert_eq!(uuids(&plan.unreadable), vec![unreadable.clone()]);
+        assert_eq!(uuids(&plan.read), vec![read.clone()]);
+        assert_eq!(plan.read[0].parent, Some(handler.archive_uuid_string()));
+        assert_eq!(uuids(&plan.released), vec![released.clone()]);
+        assert_eq!(plan.released[0].parent.as_deref(), Some(""));
+        assert_eq!(uuids(&plan.promoted), vec![promoted.clone()]);
+        assert_eq!(plan.pocket_actions, vec![
+            PocketAction::Archive { item_id: 4 },
+            PocketAction::TagsAdd { item_id: 4, tags: "repocket".to_string() },
+        ]);
+
+        let text = plan.to_string();
+        assert!(text.contains("Read, to be archived in Pocket:"));
+        assert!(text.contains(&format!("{} (item 5, in )", utils::uuid_to_string(released.uuid))));
+        let rendered = serde_json::to_value(&plan).unwrap();
+        assert_eq!(rendered["missing"][0]["id"], 2);
+        assert!(rendered["missing"][0].get("parent").is_none());
+
+        // Carrying it out does what it says, and consolidate() does the same.
+        let mut consolidated = handler.clone();
+        handler.apply_consolidation(&plan);
+        consolidated.consolidate();
+
+        for handler in [&handler, &consolidated] {
+            assert!(!handler.sync_trigger.uuid.is_nil());
+            assert_eq!(handler.current_items.len(), 4);
+            for uid in [&kept, &missing, &unreadable, &promoted] {
+                assert!(handler.current_items.contains_key(uid));
+            }
+            assert_eq!(handler.read_items.keys().cloned().collect::<Vec<UniqID>>(), vec![read.clone()]);
+            assert!(handler.new_items.is_empty());
+        }
+        assert_eq!(handler.current_items, consolidated.current_items);
+        assert_eq!(handler.read_items, consolidated.read_items);
+
+        // The released one is no longer tracked, nor is anything about it.
+        for handler in [&handler, &consolidated] {
+            assert_eq!(handler.flags.keys().copied().collect::<Vec<u64>>(), vec![1]);
+            assert_eq!(handler.traces.keys().copied().collect::<Vec<u64>>(), vec![1]);
+            assert_eq!(handler.progress.keys().copied().collect::<Vec<u64>>(), vec![1]);
+            assert_eq!(handler.progress_tagged.keys().copied().collect::<Vec<u64>>(), vec![1]);
+        }
+
+        // Nothing is left to do but what can't be done yet.
+        let again = handler.plan_consolidation();
+        assert!(again.read.is_empty() && again.released.is_empty() && again.promoted.is_empty() && again.new_folders.is_empty());
+        assert_eq!(uuids(&again.missing), vec![missing]);
+        assert_eq!(uuids(&again.unreadable), vec![unreadable]);
+    }
+
+    // Where read documents go, and what becomes of them in Pocket, as the settings say.
+    #[test]
+    #[serial]
+    fn consolidation_plan_settings() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let _ = handler.mkdir_pocket();
+        let (trashed, fname) = tracked_document(&mut handler, 1);
+        foreign_write(&fname, |metadata| metadata.parent = "trash".to_string());
+
+        // Not read, as far as the folder mode goes.
+        let plan = handler.plan_consolidation();
+        assert!(plan.read.is_empty() && plan.pocket_actions.is_empty());
+
+        let cases = [
+            ("device_archive = \"trash\"", vec![PocketAction::Archive { item_id: 1 }, PocketAction::TagsAdd { item_id: 1, tags: "repocket".to_string() }]),
+            ("device_archive = \"trash\"\n[status_tags]\narchived = \"\"", vec![PocketAction::Archive { item_id: 1 }]),
+            ("device_archive = \"trash\"\npocket_read = \"delete\"", vec![PocketAction::Delete { item_id: 1 }]),
+            ("device_archive = \"trash\"\nread_only_upstream = true", vec![]),
+        ];
+        for (toml, actions) in cases {
+            handler.configure(&Settings::from_toml(toml));
+            let plan = handler.plan_consolidation();
+            assert_eq!(plan.read.iter().map(|item| item.uuid.clone()).collect::<Vec<UniqID>>(), vec![trashed.clone()], "{toml}");
+            assert_eq!(plan.pocket_actions, actions, "{toml}");
+        }
+    }
 
 
     #[test]
-    fn build_new() {
+    #[serial]
+    fn device_archive_folder() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let _ = handler.mkdir_pocket();
+        assert!(handler.has_archive_folder());
+
+        let archive = handler.archive_uuid_string();
+        let archived = moved_document(&mut handler, 1, &archive);
+        let trashed = moved_document(&mut handler, 2, "trash");
+        handler.consolidate();
+
+        // Trashing a document breaks the link with Pocket, it isn't read.
+        assert_eq!(handler.read_ids().collect::<Vec<u64>>(), vec![1]);
+
+        handler.clear_read(&[1]);
+        assert_eq!(Metadata::load(&archived).parent, "trash");
+        assert_eq!(Metadata::load(&trashed).parent, "trash");
+    }
+
+
+    //
+    // Each of the folders in every state a run cut short may leave it in: nothing, the content
+    // file alone, the metadata file alone, a truncated metadata file, or all of it, renamed since.
+    // Making them again finishes them, and again after that changes nothing.
+    //
+    #[test]
+    #[serial]
+    fn folders_made_whatever_was_left() {
         initialize();
 
+        let file = |uuid: &str, ext: &str| xochitl_root().to_string() + "/" + uuid + "." + ext;
+        let states: [fn(&str, &str, &str); 5] = [
+            |_, _, _| (),
+            |content, _, _| fs::write(content, "{}\n").unwrap(),
+            |_, meta, parent| fs::write(meta, Metadata::new("CollectionType", "Half", parent).json().unwrap()).unwrap(),
+            |content, meta, _| {
+                fs::write(content, "{}\n").unwrap();
+                fs::write(meta, "{\"deleted\": fal").unwrap();
+            },
+            |content, meta, parent| {
+                fs::write(content, "{}\n").unwrap();
+                fs::write(meta, Metadata::new("CollectionType", "Renamed", parent).json().unwrap()).unwrap();
+            },
+        ];
+
+        for (i, state) in states.iter().enumerate() {
+            // One folder at a time in that state, the others not there at all.
+            for broken in 0..3 {
+                let handler = FSHandler::new();
+                let uuids = [handler.parent_uuid_string(), handler.archive_uuid_string(), handler.sync_uuid_string()];
+                let parent = if broken == 0 { String::new() } else { uuids[0].clone() };
+                state(&file(&uuids[broken], "content"), &file(&uuids[broken], "metadata"), &parent);
+
+                assert!(handler.mkdir_pocket().is_ok(), "state {i}, folder {broken}");
+                assert!(handler.mkdir_pocket().is_ok(), "state {i}, folder {broken}, again");
+
+                for uuid in &uuids {
+                    assert!(Path::new(&file(uuid, "content")).exists(), "state {i}, folder {broken}");
+                    assert_eq!(Metadata::load(&file(uuid, "metadata")).dtype, "CollectionType", "state {i}, folder {broken}");
+                }
+            }
+        }
+
+        // Renamed by the user, it stays so.
         let handler = FSHandler::new();
+        let renamed = file(&handler.sync_uuid_string(), "metadata");
+        states[4](&file(&handler.sync_uuid_string(), "content"), &renamed, &handler.parent_uuid_string());
+        assert!(handler.mkdir_pocket().is_ok());
+        assert_eq!(Metadata::load(&renamed).visible_name, "Renamed");
 
-        assert_eq!(Some(Version::Random), handler.folder.uuid.get_version());
+        // A document where the folder should be is left alone.
+        let handler = FSHandler::new();
+        let document = file(&handler.archive_uuid_string(), "metadata");
+        fs::write(&document, Metadata::new("DocumentType", "Not a folder", "").json().unwrap()).unwrap();
+        assert!(matches!(handler.mkdir_pocket(), Err(FsError::Io { path, source }) if path == document && source.kind() == ErrorKind::AlreadyExists));
+        assert_eq!(Metadata::load(&document).dtype, "DocumentType");
+        assert!(handler.has_pocket_folder());
     }
 
+
     #[test]
     #[serial]
-    fn load_new() {
+    fn device_archive_trash() {
         initialize();
 
-        let _ = fs::remove_file(CONFIG_FILE);
+        let mut handler = FSHandler::new();
+        handler.set_device_archive(DeviceArchive::Trash);
+        let _ = handler.mkdir_pocket();
+        assert!(!handler.has_archive_folder());
+        assert!(Path::new(&(xochitl_root().to_string() + "/" + &handler.sync_uuid_string() + ".metadata")).exists());
+
+        let trashed = moved_document(&mut handler, 1, "trash");
+        let elsewhere = moved_document(&mut handler, 2, "some-other-folder");
+        let parent = handler.parent_uuid_string();
+        let gone = moved_document(&mut handler, 3, &parent);
+        handler.consolidate();
+
+        assert_eq!(handler.read_ids().collect::<Vec<u64>>(), vec![1]);
+
+        handler.clear_read(&[1]);
+        assert_eq!(Metadata::load(&trashed).parent, "trash");
+        assert_eq!(Metadata::load(&elsewhere).parent, "some-other-folder");
+        assert!(handler.archived_items.values().any(|id| *id == 1));
+
+        // Archived elsewhere, and there's no Archive folder to reconcile into.
+        let statuses = HashMap::from([(3, ItemStatus::Archived)]);
+        assert_eq!(handler.reconcile(&statuses, ReconcileTarget::Archive, false), 1);
+        assert_eq!(Metadata::load(&gone).parent, "trash");
+    }
 
-        let handler = FSHandler::load();
-        assert_eq!(Some(Version::Random), handler.folder.uuid.get_version());
-        assert!(handler.current_items.is_empty());
-        assert!(handler.archived_items.is_empty());
+
+    #[test]
+    #[serial]
+    fn device_archive_keep() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        handler.set_device_archive(DeviceArchive::Keep);
+        let _ = handler.mkdir_pocket();
+
+        let archive = handler.archive_uuid_string();
+        let archived = moved_document(&mut handler, 1, &archive);
+        handler.consolidate();
+        handler.clear_read(&[1]);
+
+        assert_eq!(handler.read_ids().count(), 0);
+        assert!(handler.archived_items.values().any(|id| *id == 1));
+        assert_eq!(Metadata::load(&archived).parent, archive);
     }
 
 
     #[test]
     #[serial]
-    fn load_existing() {
+    fn device_archive_migration() {
         initialize();
-        create_test_config();
 
-        let handler = FSHandler::load();
+        // Older state files predate the setting, and had an Archive folder.
+        let state: FSHandler = serde_json::from_str(JSON).unwrap();
+        assert_eq!(state.device_archive, DeviceArchive::Folder);
+
+        // Leaving "trash" brings the Archive folder back, and documents already moved into it
+        // still count as read in "trash" mode.
+        let mut handler = FSHandler::new();
+        handler.set_device_archive(DeviceArchive::Trash);
+        let _ = handler.mkdir_pocket();
+        handler.set_device_archive(DeviceArchive::Folder);
+        assert!(handler.has_archive_folder());
+
+        handler.set_device_archive(DeviceArchive::Trash);
+        let archive = handler.archive_uuid_string();
+        moved_document(&mut handler, 1, &archive);
+        handler.consolidate();
+        assert_eq!(handler.read_ids().collect::<Vec<u64>>(), vec![1]);
+    }
+
 
-        assert_eq!(Some(Version::Random), handler.folder.uuid.get_version());
-        assert_eq!(handler.current_items.len(), 2);
-        assert_eq!(handler.archived_items.len(), 1);
+    fn uninstall_fixture() -> (FSHandler, String, String, String) {
+        let mut handler = FSHandler::new();
+        let _ = handler.mkdir_pocket();
+
+        let mut fnames = Vec::new();
+        for (name, parent) in [("Current", handler.parent_uuid_string()), ("Archived", "trash".to_string())] {
+            let uid = UniqID::new();
+            let fname = xochitl_root().to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".metadata";
+            FSHandler::write_file(&fname, &Metadata::new("DocumentType", name, &parent)).unwrap();
+
+            match name {
+                "Current" => handler.current_items.insert(uid, 1),
+                _ => handler.archived_items.insert(uid, 2),
+            };
+            fnames.push(fname);
+        }
+
+        // Not ours, must never be touched.
+        let foreign = xochitl_root().to_string() + "/" + &utils::uuid_to_string(Uuid::new_v4()) + ".metadata";
+        FSHandler::write_file(&foreign, &Metadata::new("DocumentType", "Foreign", &handler.parent_uuid_string())).unwrap();
+
+        (handler, fnames.remove(0), fnames.remove(0), foreign)
     }
 
     #[test]
     #[serial]
-    fn write_config() {
+    fn uninstall_keeping_documents() {
         initialize();
-        create_test_config();
 
-        let handler = FSHandler::load();
-        let _ = fs::remove_file(CONFIG_FILE);
-        handler.save_config();
+        let (handler, current, archived, foreign) = uninstall_fixture();
+        let state = xochitl_root().to_string() + "/../uninstall-keep.state";
+        let _ = File::create(&state);
 
-        let handler = FSHandler::load();
-        assert_eq!(Some(Version::Random), handler.folder.uuid.get_version());
+        let steps = handler.uninstall(UninstallMode::KeepDocuments, false, &[&state]);
+
+        assert_eq!(steps.len(), 5);
+        assert_eq!(Metadata::load(&current).parent, "");
+        assert_eq!(Metadata::load(&archived).parent, "trash");
+        assert_eq!(Metadata::load(&foreign).parent, handler.parent_uuid_string());
+        let folder = xochitl_root().to_string() + "/" + &handler.parent_uuid_string() + ".metadata";
+        assert_eq!(Metadata::load(&folder).parent, "trash");
+        assert!(!Path::new(&state).exists());
+    }
+
+    #[test]
+    #[serial]
+    fn uninstall_removing_documents() {
+        initialize();
+
+        let (handler, current, _, foreign) = uninstall_fixture();
+
+        handler.uninstall(UninstallMode::RemoveDocuments, false, &[]);
+
+        assert_eq!(Metadata::load(&current).parent, "trash");
+        assert_eq!(Metadata::load(&foreign).parent, handler.parent_uuid_string());
+    }
+
+    #[test]
+    #[serial]
+    fn uninstall_dry_run() {
+        initialize();
+
+        let (handler, current, _, _) = uninstall_fixture();
+        let state = xochitl_root().to_string() + "/../uninstall-dry-run.state";
+        let _ = File::create(&state);
+
+        let steps = handler.uninstall(UninstallMode::RemoveDocuments, true, &[&state]);
+
+        assert_eq!(steps.len(), 5);
+        assert_eq!(Metadata::load(&current).parent, handler.parent_uuid_string());
+        assert!(Path::new(&state).exists());
+        let _ = fs::remove_file(&state);
+    }
+
+    #[test]
+    #[serial]
+    fn progress_tagged_by_bucket() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let (uid, fname) = tracked_document(&mut handler, 1);
+        let content = xochitl_root().to_string() + "/" + &utils::uuid_to_string(uid.uuid) + ".content";
+        FSHandler::write_file(&content, &serde_json::json!({"fileType": "epub", "pageCount": 40})).unwrap();
+
+        let read_to = |handler: &mut FSHandler, page: u64| {
+            foreign_write(&fname, |metadata| metadata.last_opened_page = page);
+            handler.consolidate();
+            handler.progress_retags()
+        };
+
+        assert_eq!(read_to(&mut handler, 4), vec![]);
+        assert_eq!(read_to(&mut handler, 12), vec![(1, None, Some("progress-25".to_string()))]);
+        handler.progress_retagged(1);
+
+        // Same bucket, nothing to tell Pocket, still shown on the device.
+        assert_eq!(read_to(&mut handler, 15), vec![]);
+        assert!(handler.status().iter().any(|line| line.ends_with("▰▰▱▱ 40%")));
+
+        assert_eq!(read_to(&mut handler, 21), vec![(1, Some("progress-25".to_string()), Some("progress-50".to_string()))]);
+        handler.progress_retagged(1);
+        assert_eq!(read_to(&mut handler, 22), vec![]);
+    }
+
+    async fn write_broken(handler: &mut FSHandler, item: &PocketItem) -> Option<Written> {
+        crate::articlehandler::BROKEN_ZIP.with(|broken| broken.set(true));
+        let written = handler.new_article(item, ArticleOptions::default()).await;
+        crate::articlehandler::BROKEN_ZIP.with(|broken| broken.set(false));
+
+        written
+    }
+
+
+    #[tokio::test]
+    #[serial]
+    async fn failure_placeholders() {
+        initialize();
+
+        let page = b"<html><head><title>Second time lucky</title></head><body><p>Made it to the device after all.</p></body></html>";
+        let base = mockserver::serve(vec![("/article", Reply::ok("text/html", page))]).await;
+        let item = testutil::item(310, &format!("{base}/article"));
+        let uuid = ArticleHandler::new(&item).uuid_string();
+        let file = |ext: &str| xochitl_root().to_string() + "/" + &uuid + "." + ext;
+
+        let mut handler = FSHandler::new();
+
+        // None by default.
+        assert!(write_broken(&mut handler, &item).await.is_none());
+        assert!(!Path::new(&file("epub")).exists());
+        assert!(handler.placeholders.is_empty());
+
+        // When asked for, one where the document would have gone, untracked.
+        handler.set_failure_placeholders(true);
+        assert!(write_broken(&mut handler, &item).await.is_none());
+        assert_eq!(integrity::check(&uuid, Some(&handler.parent_uuid_string())), vec![]);
+        let metadata = Metadata::load(&file("metadata"));
+        assert!(metadata.visible_name.starts_with(strings::current().failed_title), "{}", metadata.visible_name);
+        assert!(handler.placeholders.contains_key(&310));
+        assert!(handler.new_items.is_empty());
+        assert!(!handler.adopt(&item));
+
+        // Written over once the item comes through.
+        let written = handler.new_article(&item, ArticleOptions::default()).await.unwrap();
+        assert_eq!(written.uuid, uuid);
+        let metadata = Metadata::load(&file("metadata"));
+        assert_eq!(metadata.version, 2);
+        assert!(!metadata.visible_name.starts_with(strings::current().failed_title), "{}", metadata.visible_name);
+        assert!(handler.placeholders.is_empty());
+        assert!(!handler.failed_items.contains_key(&310));
+        assert_eq!(handler.new_items.values().collect::<Vec<_>>(), [&310]);
+    }
+
+
+    #[test]
+    #[serial]
+    fn placeholders_of_items_gone_from_pocket() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        handler.set_failure_placeholders(true);
+        let item = testutil::item(320, "https://example.com/gone");
+        let uuid = ArticleHandler::new(&item).uuid_string();
+
+        handler.fail_item(&item, "timeout");
+        handler.write_placeholder(&item, "timeout");
+        assert!(FSHandler::has_document(&uuid));
+
+        handler.reconcile(&[(320, ItemStatus::Unread)].into(), ReconcileTarget::Archive, false);
+        assert!(FSHandler::has_document(&uuid));
+
+        handler.reconcile(&[(320, ItemStatus::Deleted)].into(), ReconcileTarget::Archive, false);
+        assert!(!FSHandler::has_document(&uuid));
+        assert!(handler.placeholders.is_empty());
+        assert!(handler.failed_items.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn large_items_gone_from_pocket() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        for id in [330, 331, 332] {
+            handler.defer_large(&testutil::item(id, &format!("https://example.com/{id}")));
+        }
+
+        let statuses = HashMap::from([(330, ItemStatus::Archived), (331, ItemStatus::Deleted), (332, ItemStatus::Unread)]);
+        handler.reconcile(&statuses, ReconcileTarget::Archive, false);
+        assert_eq!(handler.large_items().iter().filter_map(PocketItem::best_id).collect::<Vec<u64>>(), vec![332]);
+    }
+
+    #[test]
+    fn document_uuids_by_source() {
+        let (url, moved) = ("https://example.com/a", "https://example.com/a?page=all");
+
+        // The same item under a new URL.
+        assert_ne!(document_uuid(UuidSource::Url, Some(400), url), document_uuid(UuidSource::Url, Some(400), moved));
+        assert_eq!(document_uuid(UuidSource::ItemId, Some(400), url), document_uuid(UuidSource::ItemId, Some(400), moved));
+
+        // The same URL saved twice.
+        assert_eq!(document_uuid(UuidSource::Url, Some(400), url), document_uuid(UuidSource::Url, Some(401), url));
+        assert_ne!(document_uuid(UuidSource::ItemId, Some(400), url), document_uuid(UuidSource::ItemId, Some(401), url));
+
+        assert_eq!(document_uuid(UuidSource::ItemId, None, url), document_uuid(UuidSource::Url, Some(400), url));
+        assert_eq!(document_uuid(UuidSource::ItemId, Some(400), url).get_version(), Some(Version::Sha1));
+    }
+
+    #[test]
+    #[serial]
+    fn migrate_uuids() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let url = "https://example.com/migrated";
+        let (by_url, by_id) = (document_uuid(UuidSource::Url, Some(410), url), document_uuid(UuidSource::ItemId, Some(410), url));
+        let (from, to) = (utils::uuid_to_string(by_url), utils::uuid_to_string(by_id));
+        let root = xochitl_root().to_string() + "/";
+        let _ = fs::remove_dir_all(root.clone() + &to + ".thumbnails");
+        FSHandler::remove_document(&to);
+
+        fs::write(root.clone() + &from + ".epub", "epub").unwrap();
+        FSHandler::write_file(&(root.clone() + &from + ".content"), &Content::new("epub")).unwrap();
+        FSHandler::write_file(&(root.clone() + &from + ".metadata"), &Metadata::new("DocumentType", "Migrated", &handler.parent_uuid_string())).unwrap();
+        fs::create_dir_all(root.clone() + &from + ".thumbnails").unwrap();
+        handler.current_items.insert(UniqID { uuid: by_url }, 410);
+
+        // Only what would be done.
+        let plan = handler.migrate_uuids(UuidSource::ItemId, &HashMap::new(), false);
+        assert_eq!(plan, vec![format!("{from} (item 410) -> {to}")]);
+        assert!(FSHandler::has_document(&from));
+        assert!(handler.current_items.contains_key(&UniqID { uuid: by_url }));
+
+        assert_eq!(handler.migrate_uuids(UuidSource::ItemId, &HashMap::new(), true), plan);
+        assert!(!FSHandler::has_document(&from) && FSHandler::has_document(&to));
+        assert!(FSHandler::document_files(&from).is_empty());
+        assert_eq!(FSHandler::document_files(&to).len(), 4);
+        assert_eq!(handler.current_items.get(&UniqID { uuid: by_id }), Some(&410));
+        assert_eq!(Metadata::load(&(root.clone() + &to + ".metadata")).version, 2);
+
+        // Nothing left to do, and back by URL, once it's known.
+        assert!(handler.migrate_uuids(UuidSource::ItemId, &HashMap::new(), true).is_empty());
+        assert_eq!(handler.migrate_uuids(UuidSource::Url, &HashMap::new(), true), vec![format!("{to} (item 410) kept, its URL isn't known")]);
+        assert_eq!(handler.migrate_uuids(UuidSource::Url, &HashMap::from([(410, url.to_string())]), true), vec![format!("{to} (item 410) -> {from}")]);
+        assert_eq!(handler.current_items.get(&UniqID { uuid: by_url }), Some(&410));
+
+        let _ = fs::remove_dir_all(root.clone() + &from + ".thumbnails");
+        FSHandler::remove_document(&from);
+    }
+
+    // Two items with the same URL have a document each by item id, only one of them can have the
+    // URL's, the other keeps its own.
+    #[test]
+    #[serial]
+    fn migrate_uuids_collision() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let url = "https://example.com/saved-twice";
+        let shared = utils::uuid_to_string(document_uuid(UuidSource::Url, None, url));
+        FSHandler::remove_document(&shared);
+
+        for id in [420, 421] {
+            let uid = UniqID { uuid: document_uuid(UuidSource::ItemId, Some(id), url) };
+            let uuid = utils::uuid_to_string(uid.uuid);
+            fs::write(xochitl_root().to_string() + "/" + &uuid + ".epub", "epub").unwrap();
+            FSHandler::write_file(&FSHandler::metadata_file(&uid), &Metadata::new("DocumentType", "Twice", &handler.parent_uuid_string())).unwrap();
+            handler.current_items.insert(uid, id);
+        }
+
+        let urls = HashMap::from([(420, url.to_string()), (421, url.to_string())]);
+        let done = handler.migrate_uuids(UuidSource::Url, &urls, true);
+
+        assert_eq!(done.len(), 2);
+        assert_eq!(done.iter().filter(|line| line.ends_with(&format!("-> {shared}"))).count(), 1);
+        assert_eq!(done.iter().filter(|line| line.ends_with(&format!("{shared} is taken"))).count(), 1);
         assert_eq!(handler.current_items.len(), 2);
-        assert_eq!(handler.archived_items.len(), 1);
+        assert_eq!(handler.current_items.keys().filter(|uid| utils::uuid_to_string(uid.uuid) == shared).count(), 1);
+
+        for uid in handler.current_items.keys() {
+            FSHandler::remove_document(&utils::uuid_to_string(uid.uuid));
+        }
+    }
+
+    // On once failed, renamed with the settings, off once written after all or turned off, and
+    // forgotten with the item.
+    #[test]
+    #[serial]
+    fn error_tags() {
+        initialize();
+
+        let mut handler = FSHandler::new();
+        let error = Some("repocket-error");
+        assert!(handler.error_retags(error).is_empty());
+
+        handler.fail_item(&testutil::item(330, "https://example.com/failed"), "timeout");
+        assert!(handler.error_retags(None).is_empty());
+        assert_eq!(handler.error_retags(error), vec![(330, None, Some("repocket-error".to_string()))]);
+
+        handler.error_retagged(330, Some("repocket-error".to_string()));
+        assert!(handler.error_retags(error).is_empty());
+        assert_eq!(handler.error_retags(Some("failed")), vec![(330, Some("repocket-error".to_string()), Some("failed".to_string()))]);
+        assert_eq!(handler.error_retags(None), vec![(330, Some("repocket-error".to_string()), None)]);
+
+        handler.failed_items.remove(&330);
+        assert_eq!(handler.error_retags(error), vec![(330, Some("repocket-error".to_string()), None)]);
+        handler.error_retagged(330, None);
+        assert!(handler.error_retags(error).is_empty());
+
+        let item = testutil::item(331, "https://example.com/deleted");
+        handler.set_failure_placeholders(true);
+        handler.fail_item(&item, "timeout");
+        handler.write_placeholder(&item, "timeout");
+        handler.error_retagged(331, Some("repocket-error".to_string()));
+        handler.reconcile(&[(331, ItemStatus::Deleted)].into(), ReconcileTarget::Archive, false);
+        assert!(handler.error_retags(error).is_empty());
     }
 
 
     fn initialize() {
         INIT.call_once(|| {
-            let _ = fs::remove_dir_all(XOCHITL_ROOT);
-            let _ = fs::create_dir_all(XOCHITL_ROOT);
+            let _ = fs::remove_dir_all(xochitl_root());
+            let _ = fs::create_dir_all(xochitl_root());
         });
     }
 
 
     fn create_test_config() {
-        let _ = fs::remove_file(CONFIG_FILE);
+        let _ = fs::remove_file(config_file());
 
-        match File::create(CONFIG_FILE) {
+        match File::create(config_file()) {
             Ok(fh) => {
                 let mut buffer = BufWriter::new(fh);
                 let _ = buffer.write_all(JSON.as_bytes());
@@ -0,0 +1,80 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//
+// How far into a document the user got, from where xochitl left it open. xochitl keeps the page
+// in the .metadata, lastOpenedPage counting from zero, and the length in the .content, pageCount,
+// once it has laid the document out. Until then pageCount is the 1 written with the document.
+//
+// Upstream it's only a coarse tag, e.g. "progress-50", changed when the bucket changes and not on
+// every page turned.
+//
+pub const TAG_PREFIX: &str = "progress-";
+pub const BUCKETS: [u8; 3] = [25, 50, 75];
+// Blocks in the bar shown by status.
+const BAR: usize = 4;
+
+
+// None until the document has been read past its first page.
+pub fn percent(last_opened_page: u64, page_count: u64) -> Option<u8> {
+    if page_count <= 1 || last_opened_page == 0 {
+        return None;
+    }
+
+    Some(((last_opened_page + 1) * 100 / page_count).min(100) as u8)
+}
+
+
+// The highest bucket reached, None below the first.
+pub fn bucket(percent: u8) -> Option<u8> {
+    BUCKETS.iter().rev().find(|bucket| percent >= **bucket).copied()
+}
+
+
+pub fn tag(bucket: u8) -> String {
+    format!("{TAG_PREFIX}{bucket}")
+}
+
+
+// e.g. "▰▰▱▱ 48%".
+pub fn bar(percent: u8) -> String {
+    let filled = ((percent as usize * BAR) as f64 / 100.0).round() as usize;
+
+    format!("{}{} {percent}%", "▰".repeat(filled), "▱".repeat(BAR - filled))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        assert_eq!(percent(0, 40), None);
+        assert_eq!(percent(12, 1), None);
+        assert_eq!(percent(9, 40), Some(25));
+        assert_eq!(percent(39, 40), Some(100));
+        assert_eq!(percent(80, 40), Some(100));
+
+        let buckets: Vec<Option<u8>> = [0, 24, 25, 49, 50, 74, 75, 100].into_iter().map(bucket).collect();
+        assert_eq!(buckets, [None, None, Some(25), Some(25), Some(50), Some(50), Some(75), Some(75)]);
+        assert_eq!(tag(50), "progress-50");
+
+        assert_eq!(bar(48), "▰▰▱▱ 48%");
+        assert_eq!(bar(0), "▱▱▱▱ 0%");
+        assert_eq!(bar(100), "▰▰▰▰ 100%");
+    }
+}
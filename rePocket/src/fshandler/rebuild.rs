@@ -0,0 +1,256 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+
+use super::{document_uuid, FSHandler, Metadata, UniqID};
+use crate::settings::UuidSource;
+use super::root::xochitl_root;
+
+
+//
+// For when the state is gone but the documents aren't, e.g. after a botched update took
+// ~/.repocket with it. Starting over would make a second Pocket folder and write every article
+// again, so the state is made again from the device instead, see `repocket rebuild-state`.
+//
+// The Pocket folder is the one with the name in the settings, not deleted, with the most in it,
+// and Archive and Sync are the folders by those names in it. The documents in the Pocket and
+// Archive folders are tracked again when an item in Pocket makes their UUID, by URL or by item id
+// whatever uuid_source says now, see document_uuid(). The rest are left as they are.
+//
+#[derive(Debug, Default, PartialEq)]
+pub struct Rebuilt {
+    pub folder: String,
+    pub archive: Option<String>,
+    pub sync: Option<String>,
+    pub current: usize,
+    pub archived: usize,
+    // The documents no item makes the UUID of.
+    pub unmatched: Vec<String>,
+}
+
+
+// Every folder and document in xochitl's storage, by UUID, but those deleted.
+fn entries() -> Vec<(String, Metadata)> {
+    let dir = match fs::read_dir(xochitl_root()) {
+        Ok(dir) => dir,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut entries: Vec<(String, Metadata)> = dir.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "metadata"))
+        .filter_map(|path| {
+            let uuid = path.file_stem()?.to_str()?.to_string();
+            let metadata = Metadata::try_load(path.to_str()?)?;
+
+            Some((uuid, metadata)).filter(|(_, metadata)| !metadata.deleted && metadata.parent != "trash")
+        })
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    entries
+}
+
+
+// The folders called name, the one with the most in it first.
+pub fn candidates(name: &str) -> Vec<String> {
+    let entries = entries();
+    let inside = |uuid: &str| entries.iter().filter(|(_, metadata)| metadata.parent == uuid).count();
+
+    let mut folders: Vec<(String, usize)> = entries.iter()
+        .filter(|(_, metadata)| metadata.dtype == "CollectionType" && metadata.visible_name == name)
+        .map(|(uuid, _)| (uuid.clone(), inside(uuid)))
+        .collect();
+    folders.sort_by(|a, b| b.1.cmp(&a.1));
+
+    folders.into_iter().map(|(uuid, _)| uuid).collect()
+}
+
+
+// Whether there's a valid state at fname, one that's there but doesn't parse may as well not be.
+pub fn valid_state(fname: &str) -> bool {
+    fs::read(fname).ok().is_some_and(|data| serde_json::from_slice::<FSHandler>(&data).is_ok())
+}
+
+
+// No state, but folders that look like rePocket's, for rebuild() to start from.
+pub fn orphaned(fname: &str, name: &str) -> bool {
+    !Path::new(fname).exists() && !candidates(name).is_empty()
+}
+
+
+impl FSHandler {
+    //
+    // A state for the folder called name, see above, with urls by item id as Pocket has them.
+    // None if there's no such folder.
+    //
+    pub fn rebuild(name: &str, urls: &HashMap<u64, String>, source: UuidSource) -> Option<(Self, Rebuilt)> {
+        let folder = candidates(name).into_iter().next()?;
+        let entries = entries();
+        let child = |name: &str| entries.iter()
+            .find(|(_, metadata)| metadata.dtype == "CollectionType" && metadata.parent == folder && metadata.visible_name == name)
+            .map(|(uuid, _)| uuid.clone());
+
+        let mut handler = Self::new();
+        let mut rebuilt = Rebuilt {
+            folder: folder.clone(),
+            archive: child("Archive"),
+            sync: child("Sync"),
+            ..Rebuilt::default()
+        };
+
+        let parse = |uuid: &str| Uuid::parse_str(uuid).ok().map(|uuid| UniqID { uuid });
+        handler.folder = parse(&folder)?;
+        if let Some(archive) = rebuilt.archive.as_deref().and_then(parse) {
+            handler.archive = archive;
+        }
+        if let Some(sync) = rebuilt.sync.as_deref().and_then(parse) {
+            handler.sync_trigger = sync;
+        }
+
+        let ids: HashMap<Uuid, u64> = urls.iter()
+            .map(|(id, url)| (document_uuid(source, Some(*id), url), *id))
+            .collect();
+
+        for (uuid, metadata) in entries.iter().filter(|(_, metadata)| metadata.dtype == "DocumentType") {
+            let archived = match &metadata.parent {
+                parent if *parent == folder => false,
+                parent if Some(parent) == rebuilt.archive.as_ref() => true,
+                _ => continue,
+            };
+            let found = parse(uuid).and_then(|uid| Some((ids.get(&uid.uuid).copied()?, uid)));
+            let (id, uid) = match found {
+                Some(found) => found,
+                None => {
+                    rebuilt.unmatched.push(uuid.clone());
+                    continue;
+                },
+            };
+
+            match archived {
+                true => {
+                    handler.archived_items.insert(uid, id);
+                    rebuilt.archived += 1;
+                },
+                false => {
+                    handler.current_items.insert(uid, id);
+                    rebuilt.current += 1;
+                },
+            }
+        }
+
+        handler.uuid_source = source;
+
+        Some((handler, rebuilt))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+    use serial_test::serial;
+
+    fn plant(uuid: &str, dtype: &str, name: &str, parent: &str) {
+        let path = format!("{}/{uuid}", xochitl_root());
+
+        fs::write(format!("{path}.content"), "{}").unwrap();
+        fs::write(format!("{path}.metadata"), Metadata::new(dtype, name, parent).json().unwrap()).unwrap();
+        if dtype == "DocumentType" {
+            fs::write(format!("{path}.epub"), "epub").unwrap();
+        }
+    }
+
+    // The layout rePocket leaves, with an empty folder by the same name, e.g. made by the run that
+    // started over, and a document the user put in the Pocket folder.
+    #[test]
+    #[serial]
+    fn from_the_device() {
+        let _ = fs::create_dir_all(xochitl_root());
+        let name = format!("Pocket {}", Uuid::new_v4());
+        let new = || utils::uuid_to_string(Uuid::new_v4());
+        let (folder, archive, sync, empty) = (new(), new(), new(), new());
+        let url = |id: u64| format!("https://example.com/{id}");
+        let doc = |id: u64| utils::uuid_to_string(document_uuid(UuidSource::Url, Some(id), &url(id)));
+
+        plant(&folder, "CollectionType", &name, "");
+        plant(&archive, "CollectionType", "Archive", &folder);
+        plant(&sync, "CollectionType", "Sync", &folder);
+        plant(&empty, "CollectionType", &name, "");
+        plant(&doc(1), "DocumentType", "One", &folder);
+        plant(&doc(2), "DocumentType", "Two", &folder);
+        plant(&doc(3), "DocumentType", "Three", &archive);
+        let theirs = new();
+        plant(&theirs, "DocumentType", "Theirs", &folder);
+        // Somewhere else, not rePocket's to track.
+        plant(&doc(4), "DocumentType", "Four", "");
+
+        assert_eq!(candidates(&name), vec![folder.clone(), empty.clone()]);
+
+        let urls: HashMap<u64, String> = (1..=4).map(|id| (id, url(id))).collect();
+        let (handler, rebuilt) = FSHandler::rebuild(&name, &urls, UuidSource::Url).unwrap();
+
+        assert_eq!(rebuilt, Rebuilt {
+            folder: folder.clone(),
+            archive: Some(archive.clone()),
+            sync: Some(sync.clone()),
+            current: 2,
+            archived: 1,
+            unmatched: vec![theirs.clone()],
+        });
+        assert_eq!(handler.parent_uuid_string(), folder);
+        assert_eq!(handler.archive_uuid_string(), archive);
+        assert_eq!(handler.sync_uuid_string(), sync);
+        let mut current: Vec<u64> = handler.current_items.values().copied().collect();
+        current.sort();
+        assert_eq!(current, vec![1, 2]);
+        assert_eq!(handler.archived_items.values().copied().collect::<Vec<u64>>(), vec![3]);
+
+        // By item id, nothing matches.
+        let (_, rebuilt) = FSHandler::rebuild(&name, &urls, UuidSource::ItemId).unwrap();
+        assert_eq!((rebuilt.current, rebuilt.archived, rebuilt.unmatched.len()), (0, 0, 4));
+
+        assert!(FSHandler::rebuild("Not there", &urls, UuidSource::Url).is_none());
+
+        // Nothing left behind for the tests after this one, e.g. folders they would count.
+        for uuid in [folder, archive, sync, empty, doc(1), doc(2), doc(3), doc(4), theirs] {
+            for extension in ["content", "metadata", "epub"] {
+                let _ = fs::remove_file(format!("{}/{uuid}.{extension}", xochitl_root()));
+            }
+        }
+    }
+
+    #[test]
+    fn state_files() {
+        let dir = std::env::temp_dir().join(format!("repocket-rebuild-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let fname = dir.join("repocket.config").to_string_lossy().to_string();
+
+        assert!(!valid_state(&fname));
+        fs::write(&fname, "{\"folder\": ").unwrap();
+        assert!(!valid_state(&fname));
+        fs::write(&fname, serde_json::to_string(&FSHandler::new()).unwrap()).unwrap();
+        assert!(valid_state(&fname));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,314 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, read_dir};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use super::default_xochitl_root;
+
+
+// Firmware updates and mods have been known to move (or bind-mount) xochitl's storage. These are
+// the places worth looking at when the configured one looks dead.
+pub const CANDIDATES: [&'static str; 3] = [
+    "/home/root/.local/share/remarkable/xochitl",
+    "/root/.local/share/remarkable/xochitl",
+    "/mnt/root/.local/share/remarkable/xochitl",
+];
+
+static ROOT: OnceLock<String> = OnceLock::new();
+
+
+// The directory documents are written to, the default one unless something else was resolved.
+pub fn xochitl_root() -> &'static str {
+    ROOT.get().map(|root| root.as_str()).unwrap_or_else(default_xochitl_root)
+}
+
+
+pub fn set_xochitl_root(path: &str) {
+    if ROOT.set(path.to_string()).is_err() {
+        println!("🚨 The xochitl root was already set to {}", xochitl_root());
+    }
+}
+
+
+// What a directory looks like, as far as telling whether xochitl is using it goes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RootSnapshot {
+    pub path: String,
+    pub metadata_files: usize,
+    pub newest: Option<SystemTime>,
+}
+
+
+impl RootSnapshot {
+    pub fn take(path: &str) -> Self {
+        let mut snapshot = Self {
+            path: path.to_string(),
+            metadata_files: 0,
+            newest: None,
+        };
+
+        if let Ok(entries) = read_dir(path) {
+            for entry in entries.flatten() {
+                if entry.path().extension().is_some_and(|ext| ext == "metadata") {
+                    snapshot.metadata_files += 1;
+
+                    let modified = entry.metadata().and_then(|m| m.modified()).ok();
+                    snapshot.newest = std::cmp::max(snapshot.newest, modified);
+                }
+            }
+        }
+
+        snapshot
+    }
+
+
+    fn is_live(&self) -> bool {
+        self.metadata_files > 0
+    }
+}
+
+
+// Stick with the configured root if it looks live, otherwise go with the candidate that was
+// modified most recently. None means there's nowhere sensible to write to.
+pub fn choose(configured: &RootSnapshot, candidates: &[RootSnapshot]) -> Option<String> {
+    if configured.is_live() {
+        return Some(configured.path.clone());
+    }
+
+    candidates.iter()
+        .filter(|candidate| candidate.is_live() && candidate.path != configured.path)
+        .max_by_key(|candidate| candidate.newest)
+        .map(|candidate| candidate.path.clone())
+}
+
+
+// Whether xochitl is using the directory, as far as can be told, false e.g. for an unmounted
+// mount point.
+pub fn is_live(path: &str) -> bool {
+    RootSnapshot::take(path).is_live()
+}
+
+
+// The names of the files, and directories, xochitl has for the document in root, e.g. its .epub,
+// .content, .metadata, .thumbnails.
+pub fn document_files(root: &str, uuid: &str) -> Vec<String> {
+    let entries = match read_dir(root) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries.filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name == uuid || name.starts_with(&(uuid.to_string() + ".")))
+        .collect()
+}
+
+
+// A document from one root to another, after the folders it's in, unless they're there already.
+// The .metadata goes last, xochitl doesn't show a document without it.
+pub fn copy_document(from: &str, to: &str, uuid: &str) -> Result<(), Error> {
+    let mut names = document_files(from, uuid);
+
+    if names.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("nothing for {uuid} in {from}")));
+    }
+
+    let parent = fs::read_to_string(Path::new(from).join(uuid.to_string() + ".metadata")).ok()
+        .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+        .and_then(|metadata| metadata["parent"].as_str().map(str::to_string))
+        .filter(|parent| !parent.is_empty() && parent != "trash");
+
+    if let Some(parent) = parent {
+        if !Path::new(to).join(parent.clone() + ".metadata").exists() {
+            copy_document(from, to, &parent)?;
+        }
+    }
+
+    names.sort_by_key(|name| name.ends_with(".metadata"));
+
+    for name in names {
+        copy_path(&Path::new(from).join(&name), &Path::new(to).join(&name))?;
+    }
+
+    Ok(())
+}
+
+
+// The documents waiting for each root, by UUID, copied from the given one to those that are
+// there. Those copied, or gone in the meantime, are taken off. Returns how many were copied.
+pub fn catch_up(from: &str, pending: &mut BTreeMap<String, BTreeSet<String>>) -> usize {
+    let mut copied = 0;
+
+    for (root, uuids) in pending.iter_mut() {
+        if !is_live(root) {
+            println!("ℹ {root} isn't there, {} document(s) waiting to be copied to it", uuids.len());
+            continue;
+        }
+
+        uuids.retain(|uuid| match copy_document(from, root, uuid) {
+            Ok(()) => {
+                copied += 1;
+                false
+            },
+            // Gone from the root in the meantime, e.g. discarded.
+            Err(e) if e.kind() == ErrorKind::NotFound => false,
+            Err(e) => {
+                println!("🚨 Could not copy {uuid} to {root}, trying again with the next sync: {e}");
+                true
+            },
+        });
+    }
+
+    pending.retain(|_, uuids| !uuids.is_empty());
+
+    copied
+}
+
+
+fn copy_path(from: &Path, to: &Path) -> Result<(), Error> {
+    if !from.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+
+    fs::create_dir_all(to)?;
+
+    for entry in read_dir(from)?.flatten() {
+        copy_path(&entry.path(), &to.join(entry.file_name()))?;
+    }
+
+    Ok(())
+}
+
+
+pub fn resolve(configured: &str) -> Option<String> {
+    let snapshot = RootSnapshot::take(configured);
+    let candidates: Vec<RootSnapshot> = CANDIDATES.iter()
+        .map(|candidate| RootSnapshot::take(candidate))
+        .collect();
+
+    match choose(&snapshot, &candidates) {
+        Some(path) if path == configured => Some(path),
+        Some(path) => {
+            println!("🚨 {configured} doesn't look like xochitl's storage!");
+            println!("🚨 Using {path} instead, set xochitl_root in the settings to override");
+            Some(path)
+        },
+        None => {
+            println!("🚨 Neither {configured} nor any of {:?} look like xochitl's storage", CANDIDATES);
+            None
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    fn snapshot(path: &str, metadata_files: usize, newest: u64) -> RootSnapshot {
+        RootSnapshot {
+            path: path.to_string(),
+            metadata_files,
+            newest: match metadata_files {
+                0 => None,
+                _ => Some(SystemTime::UNIX_EPOCH + Duration::from_secs(newest)),
+            },
+        }
+    }
+
+    #[test]
+    fn standard_root() {
+        let configured = snapshot("/standard", 12, 100);
+        let candidates = [snapshot("/moved", 3, 200)];
+
+        assert_eq!(choose(&configured, &candidates), Some("/standard".to_string()));
+    }
+
+    #[test]
+    fn moved_root() {
+        let configured = snapshot("/standard", 0, 0);
+        let candidates = [
+            snapshot("/standard", 0, 0),
+            snapshot("/stale", 40, 100),
+            snapshot("/moved", 3, 200),
+        ];
+
+        assert_eq!(choose(&configured, &candidates), Some("/moved".to_string()));
+    }
+
+    #[test]
+    fn empty_roots() {
+        let configured = snapshot("/standard", 0, 0);
+        let candidates = [snapshot("/empty", 0, 0)];
+
+        assert_eq!(choose(&configured, &candidates), None);
+    }
+
+    #[test]
+    fn take_snapshot() {
+        let dir = std::env::temp_dir().join(format!("repocket-root-{}", uuid::Uuid::new_v4()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(RootSnapshot::take(dir.to_str().unwrap()).metadata_files, 0);
+
+        fs::write(dir.join("a.metadata"), "{}").unwrap();
+        fs::write(dir.join("a.content"), "{}").unwrap();
+        fs::write(dir.join("b.metadata"), "{}").unwrap();
+
+        let snapshot = RootSnapshot::take(dir.to_str().unwrap());
+        assert_eq!(snapshot.metadata_files, 2);
+        assert!(snapshot.newest.is_some());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn copy_with_the_folders() {
+        let dir = std::env::temp_dir().join(format!("repocket-roots-{}", uuid::Uuid::new_v4()));
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(from.join("doc.thumbnails")).unwrap();
+        fs::create_dir_all(&to).unwrap();
+
+        fs::write(from.join("folder.metadata"), r#"{"parent": "", "type": "CollectionType"}"#).unwrap();
+        fs::write(from.join("folder.content"), "{}").unwrap();
+        fs::write(from.join("doc.metadata"), r#"{"parent": "folder", "type": "DocumentType"}"#).unwrap();
+        fs::write(from.join("doc.content"), "{}").unwrap();
+        fs::write(from.join("doc.epub"), "epub").unwrap();
+        fs::write(from.join("doc.thumbnails/0.png"), "png").unwrap();
+        fs::write(from.join("other.metadata"), "{}").unwrap();
+
+        let (from, to) = (from.to_str().unwrap(), to.to_str().unwrap());
+        copy_document(from, to, "doc").unwrap();
+
+        let mut copied: Vec<String> = read_dir(to).unwrap().flatten().map(|entry| entry.file_name().into_string().unwrap()).collect();
+        copied.sort();
+        assert_eq!(copied, ["doc.content", "doc.epub", "doc.metadata", "doc.thumbnails", "folder.content", "folder.metadata"]);
+        assert_eq!(fs::read_to_string(Path::new(to).join("doc.thumbnails/0.png")).unwrap(), "png");
+
+        assert_eq!(copy_document(from, to, "missing").unwrap_err().kind(), ErrorKind::NotFound);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
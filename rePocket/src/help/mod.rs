@@ -0,0 +1,150 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::articlehandler;
+use crate::fshandler::FSHandler;
+use crate::schedule::WatchSyncs;
+use crate::settings::{DeviceArchive, PocketRead, Settings};
+use crate::strings::{self, Strings};
+use crate::text::{HtmlText, PlainText};
+
+
+//
+// A short document in the Pocket folder for whoever else reads on the device, on why documents
+// come and go: what marks one as read, when syncs happen, and where to look when they don't. It's
+// made from the settings, in the language of the device, and pinned, so that it's never taken for
+// read, see FSHandler::write_pinned_note().
+//
+// Written on the first sync, and again whenever HELP_VERSION goes up, that is, whenever what it
+// says changes. Trashing it is respected until then.
+//
+pub const HELP_VERSION: u32 = 1;
+const HELP_NAME: &str = "repocket-help";
+
+
+pub fn render(settings: &Settings, strings: &Strings) -> (PlainText, HtmlText) {
+    let pocket = match settings.pocket_read() {
+        PocketRead::Archive => strings.help_archived,
+        PocketRead::Delete => strings.help_deleted,
+    };
+    let read = match settings.device_archive() {
+        DeviceArchive::Trash => strings.help_read_trash,
+        DeviceArchive::Folder | DeviceArchive::Keep => strings.help_read_folder,
+    };
+
+    let schedule = settings.schedule();
+    let days: Vec<&str> = schedule.days().iter().map(|day| strings.weekdays[day.num_days_from_monday() as usize]).collect();
+    let days = match days.is_empty() {
+        true => strings.help_every_day.to_string(),
+        false => days.join(", "),
+    };
+    let hours = match schedule.hours() {
+        hours if hours.is_empty() => strings.help_any_time.to_string(),
+        hours => hours,
+    };
+    let window = [("days", days.as_str()), ("hours", hours.as_str())];
+    let minutes = (settings.sync_interval().as_secs() / 60).max(1).to_string();
+
+    let mut sync = vec![strings.help_sync_folder.to_string()];
+    if !settings.sync_interval().is_zero() {
+        sync.push(strings::fill(strings.help_sync_every, &[("minutes", minutes.as_str()), window[0], window[1]]));
+    }
+    if !schedule.is_always() && schedule.watch() == WatchSyncs::Respect {
+        sync.push(strings::fill(strings.help_sync_window, &window));
+    }
+
+    let body = strings::fill(strings.help_body, &[
+        ("folder", PlainText::new(settings.folder_name()).to_html().as_str()),
+        ("read", &strings::fill(read, &[("pocket", pocket)])),
+        ("sync", &sync.join(" ")),
+    ]);
+
+    (PlainText::new(strings.help_title), HtmlText::trusted(&body))
+}
+
+
+// Puts the help in the Pocket folder, unless this version of it is there already. True if it was
+// written.
+pub fn place(fhandler: &mut FSHandler, settings: &Settings) -> bool {
+    if fhandler.note_version(HELP_NAME) == Some(HELP_VERSION) {
+        return false;
+    }
+
+    let (title, body) = render(settings, strings::current());
+
+    match articlehandler::note_epub(&title, &body) {
+        Ok(epub) => match fhandler.write_pinned_note(HELP_NAME, HELP_VERSION, title.as_str(), &epub) {
+            Some(path) => {
+                println!("ℹ Wrote the help to {path}");
+                true
+            },
+            None => false,
+        },
+        Err(e) => {
+            println!("🚨 Could not make the help: {e}");
+            false
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use crate::fshandler::root::xochitl_root;
+
+    #[test]
+    fn defaults_in_english() {
+        let (title, body) = render(&Settings::from_toml(""), &strings::EN);
+
+        assert_eq!(title.as_str(), "How rePocket works");
+        assert!(body.as_str().contains("in the Pocket folder"));
+        assert!(body.as_str().contains("move a document into the Archive folder, it's then archived in Pocket."));
+        assert!(body.as_str().contains("<p>Opening the Sync folder syncs right away.</p>"));
+        assert!(!body.as_str().contains('{'));
+    }
+
+    #[test]
+    fn settings_in_german() {
+        let settings = Settings::from_toml("folder_name = \"Lesen & Co\"\ndevice_archive = \"trash\"\npocket_read = \"delete\"\n\
+            sync_interval = 7200\n[schedule]\ndays = [\"fri\", \"sat\"]\nhours = \"17-22\"\nwatch = \"respect\"\n");
+        let (title, body) = render(&settings, &strings::DE);
+
+        assert_eq!(title.as_str(), "So funktioniert rePocket");
+        assert!(body.as_str().contains("im Ordner Lesen &amp; Co ab"));
+        assert!(body.as_str().contains("in den Papierkorb verschieben, in Pocket werden sie dann gelöscht."));
+        assert!(body.as_str().contains("alle 120 Minuten, Freitag, Samstag, 17-22."));
+        assert!(body.as_str().contains("Synchronisiert wird nur Freitag, Samstag, 17-22."));
+        assert!(!body.as_str().contains('{'));
+    }
+
+    #[test]
+    #[serial]
+    fn placed_once_and_pinned() {
+        let _ = std::fs::create_dir_all(xochitl_root());
+        let mut fhandler = FSHandler::new();
+
+        assert!(place(&mut fhandler, &Settings::from_toml("")));
+        assert_eq!(fhandler.note_version(HELP_NAME), Some(HELP_VERSION));
+        assert!(!place(&mut fhandler, &Settings::from_toml("")));
+
+        let uuid = crate::utils::uuid_to_string(uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, HELP_NAME.as_bytes()));
+        let metadata: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(format!("{}/{uuid}.metadata", xochitl_root())).unwrap()).unwrap();
+        assert_eq!(metadata["pinned"], true);
+        assert_eq!(metadata["parent"], fhandler.parent_uuid_string());
+    }
+}
@@ -0,0 +1,227 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::fshandler::Written;
+use crate::settings::Settings;
+
+
+//
+// User commands run after each article is written and after each sync, for whatever post
+// processing people fancy. They run through `sh -c`, and whatever happens to them is logged but
+// never fails the sync.
+//
+// The article hook gets REPOCKET_UUID, REPOCKET_TITLE, REPOCKET_URL, REPOCKET_PATH and
+// REPOCKET_FORMAT in its environment, the sync hook gets the sync summary as JSON on its stdin.
+//
+pub struct Hooks {
+    post_article: Option<String>,
+    post_sync: Option<String>,
+    timeout: Duration,
+    dry_run: bool,
+}
+
+
+impl Hooks {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            post_article: settings.post_article_cmd().map(String::from),
+            post_sync: settings.post_sync_cmd().map(String::from),
+            timeout: settings.hook_timeout(),
+            dry_run: false,
+        }
+    }
+
+
+    // Nothing runs during a dry-run.
+    #[allow(dead_code)]
+    pub fn set_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+
+        self
+    }
+
+
+    pub async fn post_article(&self, article: &Written) {
+        if let Some(cmd) = &self.post_article {
+            let env = vec![
+                ("REPOCKET_UUID", article.uuid.clone()),
+                ("REPOCKET_TITLE", article.title.clone()),
+                ("REPOCKET_URL", article.url.clone()),
+                ("REPOCKET_PATH", article.path.clone()),
+                ("REPOCKET_FORMAT", article.format.clone()),
+            ];
+
+            self.run("post_article_cmd", cmd, env, None).await;
+        }
+    }
+
+
+    pub async fn post_sync(&self, summary: &serde_json::Value) {
+        if let Some(cmd) = &self.post_sync {
+            self.run("post_sync_cmd", cmd, vec![], Some(summary.to_string().into_bytes())).await;
+        }
+    }
+
+
+    // Returns whether the command ran to completion successfully, for the benefit of the tests.
+    async fn run(&self, name: &str, cmd: &str, env: Vec<(&str, String)>, stdin: Option<Vec<u8>>) -> bool {
+        if self.dry_run {
+            println!("ℹ Dry-run, not running {name}");
+            return false;
+        }
+
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .envs(env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                println!("🚨 Could not run {name}: {e}");
+                return false;
+            },
+        };
+
+        // Dropping stdin closes it, so that the command sees EOF.
+        if let Some(mut fh) = child.stdin.take() {
+            if let Some(data) = stdin {
+                if let Err(e) = fh.write_all(&data).await {
+                    println!("🚨 Could not write to the stdin of {name}: {e}");
+                }
+            }
+        }
+
+        match tokio::time::timeout(self.timeout, child.wait_with_output()).await {
+            Ok(Ok(output)) => {
+                for line in String::from_utf8_lossy(&output.stdout).lines() {
+                    println!("ℹ {name}: {line}");
+                }
+                for line in String::from_utf8_lossy(&output.stderr).lines() {
+                    println!("🚨 {name}: {line}");
+                }
+
+                if !output.status.success() {
+                    println!("🚨 {name} exited with {}", output.status);
+                }
+
+                output.status.success()
+            },
+            Ok(Err(e)) => {
+                println!("🚨 {name} failed: {e}");
+                false
+            },
+            Err(_) => {
+                println!("🚨 {name} took longer than {:?}, killed it", self.timeout);
+                false
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("repocket-hooks-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn hooks(post_article: Option<String>, post_sync: Option<String>) -> Hooks {
+        Hooks {
+            post_article,
+            post_sync,
+            timeout: Duration::from_secs(5),
+            dry_run: false,
+        }
+    }
+
+    fn written() -> Written {
+        Written {
+            uuid: "8a0a5a4c-0000-5000-8000-000000000000".to_string(),
+            title: "Rust <T>: lifetimes & you".to_string(),
+            url: "https://example.com/article".to_string(),
+            path: "/tmp/8a0a5a4c-0000-5000-8000-000000000000.epub".to_string(),
+            format: "epub".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn article_hook_environment() {
+        let dir = scratch("article");
+        let out = dir.join("env");
+        let cmd = format!("env | grep ^REPOCKET_ | sort > {}", out.display());
+
+        hooks(Some(cmd), None).post_article(&written()).await;
+
+        let env = fs::read_to_string(&out).unwrap();
+        assert!(env.contains("REPOCKET_FORMAT=epub\n"));
+        assert!(env.contains("REPOCKET_PATH=/tmp/8a0a5a4c-0000-5000-8000-000000000000.epub\n"));
+        assert!(env.contains("REPOCKET_TITLE=Rust <T>: lifetimes & you\n"));
+        assert!(env.contains("REPOCKET_URL=https://example.com/article\n"));
+        assert!(env.contains("REPOCKET_UUID=8a0a5a4c-0000-5000-8000-000000000000\n"));
+    }
+
+    #[tokio::test]
+    async fn sync_hook_stdin() {
+        let dir = scratch("sync");
+        let out = dir.join("stdin");
+        let summary = serde_json::json!({"new": 3, "failed": 1});
+
+        hooks(None, Some(format!("cat > {}", out.display()))).post_sync(&summary).await;
+
+        let stdin: serde_json::Value = serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(stdin, summary);
+    }
+
+    #[tokio::test]
+    async fn failures_and_timeouts_are_contained() {
+        let mut hooks = hooks(None, None);
+        hooks.timeout = Duration::from_millis(200);
+
+        assert!(hooks.run("test", "true", vec![], None).await);
+        assert!(!hooks.run("test", "exit 3", vec![], None).await);
+        assert!(!hooks.run("test", "sleep 5", vec![], None).await);
+    }
+
+    #[tokio::test]
+    async fn dry_run_skips_hooks() {
+        let dir = scratch("dry-run");
+        let out = dir.join("ran");
+        let hooks = hooks(Some(format!("touch {}", out.display())), None).set_dry_run(true);
+
+        hooks.post_article(&written()).await;
+
+        assert!(!out.exists());
+    }
+}
@@ -0,0 +1,131 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use url::Url;
+
+use crate::fshandler::root;
+use crate::pocket::PocketApi;
+
+
+//
+// URLs to add to Pocket from the device, one per line, in a text file in the xochitl folder,
+// e.g. copied over ssh. The watcher picks it up as soon as it's written, and again before every
+// sync it runs, for the URLs Pocket couldn't take the first time. Lines that aren't web addresses are
+// skipped, with a warning, and dropped.
+//
+pub const INBOX_FILE: &str = "to-pocket.txt";
+
+
+pub fn inbox_file() -> PathBuf {
+    Path::new(root::xochitl_root()).join(INBOX_FILE)
+}
+
+
+pub fn parse(text: &str) -> Vec<Url> {
+    text.lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .filter_map(|(i, line)| match Url::parse(line) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Some(url),
+            _ => {
+                println!("🚨 Skipping line {} of {INBOX_FILE}, it's not a web address: {line}", i + 1);
+                None
+            },
+        })
+        .collect()
+}
+
+
+// Add the URLs in the file to Pocket and empty it, but for those Pocket didn't take. How many went
+// in.
+pub async fn add_all(pocket: &impl PocketApi, path: &Path) -> usize {
+    let text = match fs::read_to_string(path) {
+        Ok(text) if !text.trim().is_empty() => text,
+        _ => return 0,
+    };
+
+    let mut added = 0;
+    let mut left = String::new();
+
+    for url in parse(&text) {
+        match pocket.add(url.as_str(), None, None).await {
+            Ok(id) => {
+                println!("ℹ Added {url} to Pocket ({id})");
+                added += 1;
+            },
+            Err(e) => {
+                println!("🚨 Could not add {url} to Pocket, trying again with the next sync: {e}");
+                left += &format!("{url}\n");
+            },
+        }
+    }
+
+    // Unchanged, it'd only wake the watcher up for nothing.
+    if left != text {
+        if let Err(e) = fs::write(path, left) {
+            println!("🚨 Could not empty {}: {e}", path.display());
+        }
+    }
+
+    added
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mockserver::{self, Reply};
+    use crate::pocket::Pocket;
+
+    #[test]
+    fn urls_and_malformed_lines() {
+        let urls = parse("https://example.com/a\n\n  http://example.com/b?c=d  \nexample.com/c\nftp://example.com/d\nnot a url\n");
+
+        assert_eq!(urls.iter().map(Url::as_str).collect::<Vec<_>>(), ["https://example.com/a", "http://example.com/b?c=d"]);
+    }
+
+    #[tokio::test]
+    async fn add_and_keep_what_failed() {
+        let added = |id: u64| Reply::ok("application/json", format!("{{\"status\": 1, \"item\": {{\"item_id\": \"{id}\"}}}}").as_bytes());
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/add", added(1)),
+            ("/v3/add", Reply { status: 400, ..Reply::not_found() }),
+            ("/v3/add", added(3)),
+            ("/v3/add", added(2)),
+        ]).await;
+        let pocket = Pocket::mock(&format!("{base}/v3"));
+
+        let path = std::env::temp_dir().join(format!("repocket-inbox-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, "https://example.com/1\nhttps://example.com/2\nnope\nhttps://example.com/3").unwrap();
+
+        assert_eq!(add_all(&pocket, &path).await, 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "https://example.com/2\n");
+
+        assert_eq!(add_all(&pocket, &path).await, 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        // Nothing left to add.
+        assert_eq!(add_all(&pocket, &path).await, 0);
+        assert_eq!(requests.lock().unwrap().len(), 4);
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,241 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use uuid::Uuid;
+
+use crate::articlehandler;
+use crate::fshandler::{FSHandler, StatusReport};
+use crate::settings::Settings;
+use crate::strings::{self, Strings};
+use crate::text::{HtmlText, PlainText};
+
+
+//
+// A document in the Pocket folder listing what's in it, pinned or not, and what couldn't be
+// written, as `repocket status` does. With hundreds of items a single page is slow to open and
+// slower to find anything in, so there's a summary first, with how many there are of each, and
+// then chapters of Settings::library_chapter_size() items, for xochitl's table of contents.
+//
+// It's pinned, so that it's never taken for read, and written again only when what it lists
+// changed, going by a digest of just that kept in the state, see digest(). Reading progress and
+// the like don't count.
+//
+const LIBRARY_VERSION: u32 = 1;
+const LIBRARY_NAME: &str = "repocket-library";
+
+
+// What the library lists, by section, a line per item.
+fn sections<'a>(report: &StatusReport, strings: &'a Strings) -> [(&'a str, Vec<String>); 3] {
+    [
+        (strings.library_in_folder, report.in_folder.iter().map(|item| format!("{} ({})", item.name, item.id)).collect()),
+        (strings.library_pinned, report.pinned.iter().map(|item| format!("{} ({})", item.name, item.id)).collect()),
+        (strings.library_failed, report.failed.iter().map(|failed| format!("{} ({}): {}", failed.url, failed.id, failed.reason)).collect()),
+    ]
+}
+
+
+// The same as long as the library would be, a UUID for the SHA-1 of what it says, as for the
+// documents' UUIDs, see fshandler::document_uuid().
+pub fn digest(report: &StatusReport, chapter_size: usize, strings: &Strings) -> String {
+    let mut listed = format!("{} {chapter_size}", strings.code);
+
+    for (section, lines) in sections(report, strings) {
+        listed.extend(["\n\n", section]);
+        for line in lines {
+            listed.extend(["\n", line.as_str()]);
+        }
+    }
+
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, listed.as_bytes()).to_string()
+}
+
+
+// The title, and the chapters, the summary first. Sections with nothing in them have no chapters.
+pub fn render(report: &StatusReport, chapter_size: usize, folder: &str, strings: &Strings) -> (PlainText, Vec<(PlainText, HtmlText)>) {
+    let sections = sections(report, strings);
+    let size = chapter_size.to_string();
+
+    let counts: String = sections.iter()
+        .map(|(section, lines)| format!("<li>{}: {}</li>", PlainText::new(section).to_html().as_str(), lines.len()))
+        .collect();
+    let summary = strings::fill(strings.library_summary, &[
+        ("folder", PlainText::new(folder).to_html().as_str()),
+        ("size", size.as_str()),
+    ]) + "<ul>" + &counts + "</ul>";
+
+    let title = PlainText::new(strings.library_title);
+    let mut chapters = vec![(title.clone(), HtmlText::trusted(&summary))];
+
+    for (section, lines) in &sections {
+        for (i, chunk) in lines.chunks(chapter_size.max(1)).enumerate() {
+            let first = i * chapter_size.max(1) + 1;
+            let listed: String = chunk.iter()
+                .map(|line| format!("<li>{}</li>", PlainText::new(line).to_html().as_str()))
+                .collect();
+
+            chapters.push((
+                PlainText::new(&format!("{section}, {first}–{}", first + chunk.len() - 1)),
+                HtmlText::trusted(&format!("<ol start=\"{first}\">{listed}</ol>")),
+            ));
+        }
+    }
+
+    (title, chapters)
+}
+
+
+// Puts the library in the Pocket folder, unless it would say what it said the last time. True if
+// it was written.
+pub fn place(fhandler: &mut FSHandler, settings: &Settings) -> bool {
+    let chapter_size = match settings.library_chapter_size() {
+        Some(size) => size,
+        None => return false,
+    };
+
+    let report = fhandler.status_report();
+    let strings = strings::current();
+    let digest = digest(&report, chapter_size, strings);
+
+    if fhandler.note_digest(LIBRARY_NAME) == Some(digest.as_str()) {
+        return false;
+    }
+
+    let (title, chapters) = render(&report, chapter_size, settings.folder_name(), strings);
+
+    match articlehandler::chapters_epub(&title, &chapters) {
+        Ok(epub) => match fhandler.write_pinned_note(LIBRARY_NAME, LIBRARY_VERSION, title.as_str(), &epub) {
+            Some(path) => {
+                println!("ℹ Wrote the library, {} chapter(s), to {path}", chapters.len());
+                fhandler.set_note_digest(LIBRARY_NAME, digest);
+                true
+            },
+            None => false,
+        },
+        Err(e) => {
+            println!("🚨 Could not make the library: {e}");
+            false
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use crate::fshandler::{StatusFailure, StatusItem};
+    use crate::fshandler::root::xochitl_root;
+
+    fn report(items: u64, pinned: u64, failed: u64) -> StatusReport {
+        let item = |id: u64| StatusItem {
+            uuid: format!("uuid-{id}"),
+            id,
+            name: format!("Article {id}"),
+            flags: None,
+            progress: Some(10),
+            trace: None,
+        };
+
+        StatusReport {
+            in_folder: (1..=items).map(item).collect(),
+            pinned: (items + 1..=items + pinned).map(item).collect(),
+            failed: (1..=failed).map(|id| StatusFailure { id: 9000 + id, url: format!("https://example.com/{id}"), reason: "timeout".to_string() }).collect(),
+            next_sync: None,
+        }
+    }
+
+    #[test]
+    fn chapters() {
+        for (items, chapters) in [(5, 2), (150, 4), (1200, 25)] {
+            let (_, rendered) = render(&report(items, 0, 0), 50, "Pocket", &strings::EN);
+            assert_eq!(rendered.len(), chapters, "{items} item(s)");
+        }
+
+        let (title, rendered) = render(&report(120, 3, 51), 50, "Lesen & Co", &strings::EN);
+        let titles: Vec<&str> = rendered.iter().map(|(title, _)| title.as_str()).collect();
+        assert_eq!(title.as_str(), "rePocket library");
+        assert_eq!(titles, ["rePocket library", "In the folder, 1–50", "In the folder, 51–100", "In the folder, 101–120",
+            "Pinned, 1–3", "Could not be written, 1–50", "Could not be written, 51–51"]);
+
+        let summary = rendered[0].1.as_str();
+        assert!(summary.contains("in the Lesen &amp; Co folder"));
+        assert!(summary.contains("<li>In the folder: 120</li><li>Pinned: 3</li><li>Could not be written: 51</li>"));
+        assert!(rendered[3].1.as_str().starts_with("<ol start=\"101\"><li>Article 101 (101)</li>"));
+        assert!(rendered[6].1.as_str().contains("https://example.com/51 (9051): timeout"));
+    }
+
+    #[test]
+    fn digests() {
+        let before = report(150, 2, 1);
+        let digest_of = |report: &StatusReport| digest(report, 50, &strings::EN);
+
+        // Only what's listed counts.
+        let mut read_on = before.clone();
+        read_on.in_folder[0].progress = Some(80);
+        assert_eq!(digest_of(&before), digest_of(&read_on));
+
+        let mut renamed = before.clone();
+        renamed.in_folder[0].name = "Renamed".to_string();
+        assert_ne!(digest_of(&before), digest_of(&renamed));
+
+        let mut pinned = before.clone();
+        pinned.pinned.push(pinned.in_folder.remove(0));
+        assert_ne!(digest_of(&before), digest_of(&pinned));
+
+        assert_ne!(digest_of(&before), digest(&before, 100, &strings::EN));
+        assert_ne!(digest_of(&before), digest(&before, 50, &strings::DE));
+    }
+
+    #[test]
+    #[serial]
+    fn written_when_changed() {
+        let _ = std::fs::create_dir_all(xochitl_root());
+        let settings = Settings::from_toml("library_chapter_size = 50");
+
+        for items in [5, 150, 1200] {
+            let mut fhandler = FSHandler::new();
+            for id in 1..=items {
+                fhandler.mark_current(id);
+            }
+
+            assert!(place(&mut fhandler, &settings), "{items} item(s)");
+            assert_eq!(fhandler.note_version(LIBRARY_NAME), Some(LIBRARY_VERSION));
+            assert!(!place(&mut fhandler, &settings), "{items} item(s), again");
+
+            fhandler.mark_current(items + 1);
+            assert!(place(&mut fhandler, &settings), "{items} item(s), and one more");
+        }
+
+        remove_library();
+
+        // Off unless asked for.
+        for off in ["", "library_chapter_size = 0"] {
+            let mut fhandler = FSHandler::new();
+            assert!(!place(&mut fhandler, &Settings::from_toml(off)), "{off}");
+            assert_eq!(fhandler.note_version(LIBRARY_NAME), None);
+        }
+    }
+
+    // What place() left in xochitl_root(), for the tests after this one not to find it.
+    fn remove_library() {
+        let uuid = crate::utils::uuid_to_string(uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, LIBRARY_NAME.as_bytes()));
+
+        for extension in ["epub", "content", "metadata"] {
+            let _ = std::fs::remove_file(format!("{}/{uuid}.{extension}", xochitl_root()));
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::clock;
+use crate::fshandler;
+
+
+//
+// How much rePocket says. The ℹ and 🚨 lines always; at Debug also the 🪼 ones, e.g. the queries
+// sent to Pocket, without the credentials; at Trace also whole responses, which go to files in
+// dump_dir() rather than to the journal.
+//
+// The level comes from REPOCKET_LOG, "debug" or "trace", or from -v and -vv, whichever says more,
+// e.g. for the service:
+//
+//   systemctl set-environment REPOCKET_LOG=debug && systemctl restart repocket
+//
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Info,
+    Debug,
+    Trace,
+}
+
+const VARIABLE: &str = "REPOCKET_LOG";
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "info" | "0" => Some(Self::Info),
+            "debug" | "1" => Some(Self::Debug),
+            "trace" | "2" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+
+
+    // As many as there were -v, at most Trace.
+    pub fn from_count(count: u8) -> Self {
+        match count {
+            0 => Self::Info,
+            1 => Self::Debug,
+            _ => Self::Trace,
+        }
+    }
+}
+
+
+// Once, first thing in main.
+pub fn init(verbose: u8) {
+    let variable = std::env::var(VARIABLE).ok();
+    let named = variable.as_deref().map(|name| (name, Level::from_name(name)));
+
+    if let Some((name, None)) = named {
+        println!("🚨 Unknown {VARIABLE} '{name}', use info, debug or trace");
+    }
+
+    let level = named.and_then(|(_, level)| level).unwrap_or(Level::Info).max(Level::from_count(verbose));
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Info,
+        1 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+
+pub fn debug() -> bool {
+    level() >= Level::Debug
+}
+
+
+pub fn trace() -> bool {
+    level() >= Level::Trace
+}
+
+
+pub fn dump_dir() -> PathBuf {
+    fshandler::state_dir().join("debug")
+}
+
+
+// At Trace, e.g. a response from Pocket, as <name>-<timestamp>.json in dump_dir().
+pub fn dump(name: &str, json: &serde_json::Value) {
+    if !trace() {
+        return;
+    }
+
+    let dir = dump_dir();
+    let path = dir.join(format!("{name}-{}.json", clock::now_local().format("%Y%m%d-%H%M%S%.3f")));
+    let text = serde_json::to_string_pretty(json).unwrap_or_default();
+
+    match fs::create_dir_all(&dir).and_then(|_| fs::write(&path, text)) {
+        Ok(()) => println!("🪼 Dumped the {name} to {}", path.display()),
+        Err(e) => println!("🚨 Could not dump the {name} to {}: {e}", path.display()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        assert_eq!(Level::from_name("debug"), Some(Level::Debug));
+        assert_eq!(Level::from_name(" TRACE\n"), Some(Level::Trace));
+        assert_eq!(Level::from_name("1"), Some(Level::Debug));
+        assert_eq!(Level::from_name("10"), None);
+        assert_eq!(Level::from_name("loud"), None);
+
+        assert_eq!(Level::from_count(0), Level::Info);
+        assert_eq!(Level::from_count(2), Level::Trace);
+        assert_eq!(Level::from_count(5), Level::Trace);
+        assert!(Level::Info < Level::Debug && Level::Debug < Level::Trace);
+    }
+}
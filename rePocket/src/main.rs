@@ -14,19 +14,61 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+mod budget;
+mod cli;
+mod clock;
+mod compat;
+mod cookies;
+mod crashlog;
+mod device;
+mod dns;
+mod export;
 mod pocket;
 mod pocketquery;
 mod pocketitem;
+mod remote;
+mod safemode;
+mod schedule;
+mod scheduler;
+mod screen;
+mod setup;
 mod articlehandler;
 mod fshandler;
+mod help;
+mod hooks;
+mod inbox;
+mod library;
+mod logging;
+#[cfg(any(test, feature = "offline-fixtures"))]
+mod mockserver;
+mod output;
+mod settings;
+mod strings;
+mod sync;
+mod text;
+#[cfg(test)]
+mod testutil;
 mod utils;
 
+use cli::Command;
+use articlehandler::ArticleOptions;
 use pocket::Pocket;
-use fshandler::FSHandler;
+#[cfg(feature = "offline-fixtures")]
+use pocket::fixtures::FixturePocket;
+use fshandler::{FSHandler, FsError, Restored, UninstallMode};
+use fshandler::root;
 use pocketquery::QueryBuilder;
+use settings::{Settings, UuidSource};
+use clock::{Clock, CorrectedClock, SystemClock};
+use scheduler::{Scheduler, TcpProber};
+use safemode::CrashCounter;
+use output::Json;
+use sync::{SyncContext, SyncError, SyncSummary};
 
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
 use std::path::Path;
-use reqwest::StatusCode;
+use std::sync::atomic::{AtomicI32, Ordering};
 use tokio::sync::mpsc::{channel, Receiver};
 use notify_debouncer_mini::{
     notify::*,
@@ -37,24 +79,957 @@ use notify_debouncer_mini::{
 };
 
 
+// Where `repocket setup` puts the service, on the device.
+const UNIT_FILE: &str = "/etc/systemd/system/repocket.service";
+
+// Where to knock to tell whether we're online.
+const POCKET_HOST: &str = "getpocket.com:443";
+
+// What rePocket exits with when there are no credentials to use, EX_CONFIG, which the service isn't
+// restarted on, see repocket.service.
+const EXIT_NO_CREDENTIALS: i32 = 78;
+
+// What rePocket exits with when the settings file doesn't parse, EX_CONFIG as well. Going ahead on
+// the defaults would archive and tag in Pocket whatever read_only_upstream said.
+const EXIT_BAD_SETTINGS: i32 = 78;
+
+// What rePocket exits with when the state can't be read, EX_DATAERR, which the service isn't
+// restarted on either, it would only find the same state again.
+const EXIT_BAD_STATE: i32 = 65;
+
+// What rePocket exits with when the state couldn't be saved at the end of a command, EX_CANTCREAT.
+const EXIT_NOT_SAVED: i32 = 73;
+
+// The exit code for main() to leave with once the command is done, for failures that shouldn't cut
+// it short.
+static EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
+
 #[tokio::main]
 async fn main() {
+    crashlog::install(crashlog::crash_dir());
+
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    logging::init(cli::take_verbosity(&mut args));
+    let json = cli::take_json(&mut args);
+
+    let (creds, command) = match cli::take_creds(&mut args).and_then(|creds| Ok((creds, cli::parse(args)?))) {
+        Ok((creds, command)) => {
+            pocket::configure_credentials(creds.clone(), Settings::load().strict_credentials());
+            (creds, command)
+        },
+        Err(e) => {
+            println!("🚨 {e}");
+            return;
+        },
+    };
+
+    if json && !command.speaks_json() {
+        println!("🚨 --json only goes with sync, consolidate, status, verify and lint");
+        return;
+    }
+
+    // From here on println! goes to stderr, see the output module.
+    let mut json = match json.then(Json::take_stdout) {
+        Some(Err(e)) => {
+            println!("🚨 Cannot set stdout aside for --json: {e}");
+            return;
+        },
+        taken => taken.and_then(Result::ok),
+    };
+
+    match command {
+        Command::Watch { skip_verify } => watch(skip_verify).await,
+        Command::Sync { ignore_budget, offline_fixtures: None, once, resync } => sync(ignore_budget, once, resync, json.as_mut()).await,
+        Command::Sync { ignore_budget, offline_fixtures: Some(dir), .. } => sync_offline(Path::new(&dir), ignore_budget).await,
+        Command::PushPending { include_readonly_history } => push_pending(include_readonly_history).await,
+        Command::Uninstall { keep_documents, dry_run } => uninstall(keep_documents, dry_run),
+        Command::Restore { search, tag, count, select } => restore(search, tag, count, select).await,
+        Command::Consolidate { dry_run } => consolidate(dry_run, json.as_mut()),
+        Command::MigrateUuids { apply } => migrate_uuids(apply).await,
+        Command::RebuildState { force } => rebuild_state(force).await,
+        Command::Status => status(json.as_mut()),
+        Command::Inspect { uuid } => inspect(&uuid),
+        Command::Lint { file } => lint(&file, json.as_mut()),
+        Command::Verify { repair } => verify(repair, json.as_mut()).await,
+        Command::SafeMode { clear } => safe_mode(clear),
+        Command::ExportAll { out, format, state } => export_all(&out, &format, &state).await,
+        Command::Setup { defaults, consumer_key, skip } => setup(creds, defaults, consumer_key, skip).await,
+    }
+
+    match EXIT_CODE.load(Ordering::Relaxed) {
+        0 => (),
+        code => std::process::exit(code),
+    }
+}
+
+
+// Pocket, with the credentials found, or how to get some, and out. Restarting wouldn't help.
+fn open_pocket() -> Pocket {
+    match Pocket::new() {
+        Ok(pocket) => pocket,
+        Err(e) => no_credentials(e),
+    }
+}
+
+
+// The same, sending and trying again as the settings say.
+fn pocket_for(settings: &Settings) -> Pocket {
+    open_pocket()
+        .set_max_actions(settings.max_actions())
+        .set_retries(settings.pocket_attempts(), settings.pocket_retry_delay())
+}
+
+
+fn no_credentials(e: impl std::fmt::Display) -> ! {
+    println!("🚨 {e}");
+    println!("ℹ Run rePocketAuth on a computer and copy the .repocket.key it writes to ~/.repocket/ on the device,");
+    println!("  ..or run 'repocket setup' on the device");
+    std::process::exit(EXIT_NO_CREDENTIALS);
+}
+
+
+// The settings, for what goes to Pocket, or why they can't be had and out.
+fn settings_or_exit() -> Settings {
+    match Settings::try_load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            println!("🚨 {e}");
+            println!("ℹ Fix it, or move it aside for the defaults, and sync again");
+            std::process::exit(EXIT_BAD_SETTINGS);
+        },
+    }
+}
+
+
+// The state, or why it can't be had and out, e.g. a config file cut short. Starting over instead
+// would write every article again, it's left as it is for the user to look at.
+fn state_or_exit(loaded: Result<FSHandler, FsError>) -> FSHandler {
+    match loaded {
+        Ok(fhandler) => fhandler,
+        Err(e) => {
+            println!("🚨 {e}");
+            println!("ℹ Move it aside, or run 'repocket rebuild-state' to make it again from the device");
+            std::process::exit(EXIT_BAD_STATE);
+        },
+    }
+}
+
+
+// Saves the state, or says why it couldn't and has rePocket exit with EXIT_NOT_SAVED.
+fn save_or_report(fhandler: &FSHandler) -> bool {
+    match fhandler.save_config() {
+        Ok(()) => true,
+        Err(e) => {
+            println!("🚨 {e}");
+            EXIT_CODE.store(EXIT_NOT_SAVED, Ordering::Relaxed);
+            false
+        },
+    }
+}
+
+
+// Make sure documents land where xochitl is actually looking, returns false if there's no such place.
+fn resolve_root(settings: &Settings) -> bool {
+    let configured = settings.xochitl_root().unwrap_or(fshandler::default_xochitl_root());
+
+    match root::resolve(configured) {
+        Some(path) => root::set_xochitl_root(&path),
+        None if device::kind().is_device() => return false,
+        None => {
+            println!("ℹ Not on a reMarkable, using {configured} regardless");
+            root::set_xochitl_root(configured);
+        },
+    }
+
+    true
+}
+
+
+// Get the device ready for documents: where they go, what they're called, and the folders they go in.
+fn prepare_device(fhandler: &mut FSHandler, settings: &Settings) {
+    fhandler.update_xochitl_root();
+    fhandler.set_device_archive(settings.device_archive());
+    fhandler.set_uuid_source(settings.uuid_source());
+    fhandler.set_extra_roots(settings.extra_roots());
+    if let Err(e) = fhandler.mkdir_pocket_named(settings.folder_name()) {
+        println!("🚨 Could not make the folders: {e}");
+    }
+}
+
+
+fn uninstall(keep_documents: bool, dry_run: bool) {
+    if !Path::new(fshandler::config_file()).exists() {
+        println!("ℹ Nothing to uninstall, {} doesn't exist", fshandler::config_file());
+        return;
+    }
+
+    if !resolve_root(&Settings::load()) {
+        println!("🚨 Can't find xochitl's storage, not touching anything");
+        return;
+    }
+
+    let mode = match keep_documents {
+        true => UninstallMode::KeepDocuments,
+        false => UninstallMode::RemoveDocuments,
+    };
+
+    // A dry run changes nothing, not even what loading consolidates.
+    let fhandler = match dry_run {
+        true => state_or_exit(FSHandler::load_unconsolidated(fshandler::config_file())),
+        false => state_or_exit(FSHandler::load()),
+    };
+    let steps = fhandler.uninstall(mode, dry_run, &[fshandler::config_file(), settings::settings_file()]);
+
+    for step in steps {
+        match dry_run {
+            true => println!("ℹ Would have: {step}"),
+            false => println!("ℹ {step}"),
+        }
+    }
+
+    println!("ℹ The credentials in {} were left alone", pocket::creds_file());
+    println!("ℹ To stop the service run: systemctl disable --now repocket");
+}
+
+
+fn status(json: Option<&mut Json>) {
+    let settings = Settings::load();
+    resolve_root(&settings);
+
+    let fhandler = state_or_exit(FSHandler::load_unconsolidated(fshandler::config_file()));
+    let next_sync = next_sync(&settings, &CorrectedClock);
+
+    match json {
+        Some(json) => json.emit(&fshandler::StatusReport {
+            next_sync: next_sync.map(|next| next.to_rfc3339()),
+            ..fhandler.status_report()
+        }),
+        None => {
+            fhandler.status().iter().for_each(|line| println!("{line}"));
+
+            if let Some(next) = next_sync {
+                println!("Next timer sync allowed: {}", next.format("%a %Y-%m-%d %H:%M"));
+            }
+        },
+    }
+}
+
+
+// The first time the schedule lets the timer sync, from now on. None without a timer.
+fn next_sync(settings: &Settings, clock: &dyn Clock) -> Option<chrono::DateTime<chrono::Local>> {
+    if settings.sync_interval().is_zero() {
+        return None;
+    }
+
+    settings.schedule().next_eligible(&clock.now().into())
+}
+
+
+async fn export_all(out: &str, format: &str, state: &str) {
+    let settings = Settings::load();
+    articlehandler::configure(&settings);
+
+    let format = export::Format::from_name(format).unwrap_or(export::Format::Epub);
+    let options = ArticleOptions { images: settings.images(), favicons: settings.favicons(), limits: settings.epub_limits(), colors: settings.color_policy() };
+    let export = export::Export::new(Path::new(out), format, state).set_options(options);
+
+    match export.run(&open_pocket()).await {
+        Ok(summary) => println!("ℹ Exported {} item(s) to {out}, {} were there already, {} failed",
+            summary.exported, summary.skipped, summary.failed),
+        Err(e) => {
+            println!("🚨 The export stopped: {e}");
+            println!("ℹ Run the same command again to go on from where it stopped");
+        },
+    }
+}
+
+
+// The credentials go wherever --creds says, otherwise where rePocket looks for them last.
+async fn setup(creds: Option<String>, defaults: bool, consumer_key: Option<String>, skip: Vec<String>) {
+    println!("ℹ Setting up rePocket, every step can be left out and run again later");
+
+    let settings = Settings::load();
+    resolve_root(&settings);
+    // As it is, the next sync consolidates it. Only a new one, for the folders' UUIDs, is saved.
+    let fresh = !Path::new(fshandler::config_file()).exists();
+    let fhandler = state_or_exit(FSHandler::load_unconsolidated(fshandler::config_file()));
+
+    let paths = setup::Paths {
+        creds: creds.unwrap_or_else(|| pocket::creds_file().to_string()).into(),
+        settings: settings::settings_file().into(),
+        unit: UNIT_FILE.into(),
+    };
+    let (mut terminal, mut defaults_prompt) = (setup::Terminal, setup::Defaults);
+    let prompt: &mut dyn setup::Prompt = match defaults {
+        true => &mut defaults_prompt,
+        false => &mut terminal,
+    };
+
+    let outcomes = setup::Setup::new(prompt, paths, device::kind().is_device())
+        .set_consumer_key(consumer_key)
+        .set_skip(skip)
+        .run(&fhandler)
+        .await;
+
+    if fresh {
+        save_or_report(&fhandler);
+    }
+
+    match outcomes.iter().any(|(_, outcome)| matches!(outcome, setup::Outcome::Failed(_))) {
+        true => println!("🚨 Some steps didn't go through, run 'repocket setup' again once they're sorted out"),
+        false => println!("ℹ All set, put something in the Sync folder to sync"),
+    }
+}
+
+
+// Documents left broken by a crash, written again from Pocket with repair.
+async fn verify(repair: bool, json: Option<&mut Json>) {
+    let settings = Settings::load();
+
+    if !resolve_root(&settings) {
+        println!("🚨 Can't find xochitl's storage, nothing to verify");
+        if let Some(json) = json {
+            json.fail("Can't find xochitl's storage");
+        }
+        return;
+    }
+
+    let mut fhandler = state_or_exit(FSHandler::load_unconsolidated(fshandler::config_file()));
+    fhandler.set_uuid_source(settings.uuid_source());
+    let mut report = output::VerifyReport { broken: fhandler.verify(), repaired: Vec::new() };
+
+    if report.broken.is_empty() {
+        println!("ℹ Every document in the Pocket folder reads back fine");
+    }
+
+    for doc in &report.broken {
+        println!("🚨 {doc}");
+    }
+
+    if !report.broken.is_empty() && !repair {
+        println!("ℹ Run 'repocket verify --repair' to write them again");
+    }
+
+    if report.broken.is_empty() || !repair {
+        if let Some(json) = json {
+            json.emit(&report);
+        }
+        return;
+    }
+
+    // There's no asking Pocket for an item by id, so it's the whole list.
+    let mut pocket = open_pocket();
+    let query = QueryBuilder::default()
+        .set_state("All")
+        .set_detail_type("Complete")
+        .build()
+        .unwrap();
+
+    if let Err(e) = pocket::PocketApi::fetch(&mut pocket, &query).await {
+        println!("🚨 {e}, nothing was changed");
+        if let Some(json) = json {
+            json.fail(e);
+        }
+        return;
+    }
+
+    articlehandler::configure(&settings);
+    let options = ArticleOptions { images: settings.images(), favicons: settings.favicons(), limits: settings.epub_limits(), colors: settings.color_policy() };
+
+    report.repaired = fhandler.repair(&report.broken, &pocket, options).await;
+    for line in &report.repaired {
+        println!("ℹ {line}");
+    }
+    if let Some(json) = json {
+        json.emit(&report);
+    }
+
+    save_or_report(&fhandler);
+    sync::refresher().refresh();
+}
+
+
+fn inspect(uuid: &str) {
+    let settings = Settings::load();
+    resolve_root(&settings);
+
+    let mut fhandler = state_or_exit(FSHandler::load_unconsolidated(fshandler::config_file()));
+    fhandler.set_uuid_source(settings.uuid_source());
+
+    match fhandler.inspect(uuid) {
+        Some(lines) => lines.iter().for_each(|line| println!("{line}")),
+        None => println!("🚨 No item with uuid {uuid}"),
+    }
+}
+
+
+fn lint(file: &str, json: Option<&mut Json>) {
+    let epub = std::fs::read(file)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| compat::Epub::read(&bytes));
+
+    let findings = match epub {
+        Ok(epub) => compat::lint(&epub),
+        Err(e) => {
+            println!("🚨 Can't read {file} as an epub: {e}");
+            if let Some(json) = json {
+                json.fail(format!("Can't read {file} as an epub: {e}"));
+            }
+            return;
+        },
+    };
+
+    if let Some(json) = json {
+        json.emit(&findings);
+        return;
+    }
+
+    if findings.is_empty() {
+        println!("ℹ Nothing in {file} xochitl is known not to take");
+    }
+
+    for finding in &findings {
+        println!("🚨 {} in {}: {}", finding.rule, finding.entry, finding.detail);
+        if let Some(rule) = compat::rule(finding.rule) {
+            println!("  ..{}", rule.about);
+        }
+    }
+}
+
+
+fn consolidate(dry_run: bool, json: Option<&mut Json>) {
+    let settings = settings_or_exit();
+
+    if !resolve_root(&settings) {
+        println!("🚨 Can't find xochitl's storage, not touching anything");
+        if let Some(json) = json {
+            json.fail("Can't find xochitl's storage");
+        }
+        return;
+    }
+
+    let mut fhandler = state_or_exit(FSHandler::load_unconsolidated(fshandler::config_file()));
+    // The Archive folder, if switching to a mode with one, only when it's for real.
+    if !dry_run {
+        fhandler.set_device_archive(settings.device_archive());
+    }
+    fhandler.configure(&settings);
+    let plan = fhandler.plan_consolidation();
+
+    match json {
+        Some(json) => json.emit(&plan),
+        None => print!("{plan}"),
+    }
+
+    if !dry_run {
+        fhandler.apply_consolidation(&plan);
+        save_or_report(&fhandler);
+    }
+}
+
+
+// The documents' UUIDs, as uuid_source says they should be made. Going by URL needs the items'
+// URLs, i.e. the whole list from Pocket.
+async fn migrate_uuids(apply: bool) {
+    let settings = Settings::load();
+
+    if !resolve_root(&settings) {
+        println!("🚨 Can't find xochitl's storage, not touching anything");
+        return;
+    }
+
+    let mut urls = HashMap::new();
+
+    if settings.uuid_source() == UuidSource::Url {
+        let mut pocket = open_pocket();
+        let query = QueryBuilder::default()
+            .set_state("All")
+            .set_detail_type("Simple")
+            .build()
+            .unwrap();
+
+        if let Err(e) = pocket::PocketApi::fetch(&mut pocket, &query).await {
+            println!("🚨 {e}, nothing was changed");
+            return;
+        }
+
+        urls.extend(pocket.iter().filter_map(|item| Some((item.best_id()?, item.best_url()?))));
+    }
+
+    let mut fhandler = state_or_exit(FSHandler::load_unconsolidated(fshandler::config_file()));
+    let done = fhandler.migrate_uuids(settings.uuid_source(), &urls, apply);
+
+    if done.is_empty() {
+        println!("ℹ Every document has the UUID it should have");
+    }
+
+    for line in &done {
+        println!("ℹ {line}");
+    }
+
+    if !apply {
+        if !done.is_empty() {
+            println!("ℹ Run 'repocket migrate-uuids --apply' to rename them");
+        }
+        return;
+    }
+
+    save_or_report(&fhandler);
+    sync::refresher().refresh();
+}
+
+
+// The state again from what's on the device, see fshandler::rebuild, with the items in Pocket to
+// tell which document is which. A resync is asked for, for the next sync to take it from there.
+async fn rebuild_state(force: bool) {
+    let settings = Settings::load();
+
+    if !resolve_root(&settings) {
+        println!("🚨 Can't find xochitl's storage, not touching anything");
+        return;
+    }
+
+    if !force && fshandler::rebuild::valid_state(fshandler::config_file()) {
+        println!("🚨 {} is there and valid, not replacing it, add --force to anyway", fshandler::config_file());
+        return;
+    }
+
+    let mut pocket = open_pocket();
+    let query = QueryBuilder::default()
+        .set_state("All")
+        .set_detail_type("Simple")
+        .set_count(30)
+        .build()
+        .unwrap();
+
+    // Every item there is, page after page. The folders are worth it alone, the resync adopts the
+    // documents later.
+    let urls: HashMap<u64, String> = match pocket::PocketApi::fetch_all(&mut pocket, &query, usize::MAX).await {
+        Ok(()) => pocket.iter().filter_map(|item| Some((item.best_id()?, item.best_url()?))).collect(),
+        Err(e) => {
+            println!("🚨 Could not get the items from Pocket, only the folders are rebuilt: {e}");
+            HashMap::new()
+        },
+    };
+
+    let (fhandler, rebuilt) = match FSHandler::rebuild(settings.folder_name(), &urls, settings.uuid_source()) {
+        Some(rebuilt) => rebuilt,
+        None => {
+            println!("ℹ No {} folder on the device, nothing to rebuild the state from", settings.folder_name());
+            return;
+        },
+    };
+
+    println!("ℹ Using the {} folder {}, with {} document(s) and {} in Archive", settings.folder_name(), rebuilt.folder, rebuilt.current, rebuilt.archived);
+    if rebuilt.archive.is_none() || rebuilt.sync.is_none() {
+        println!("ℹ The Archive or Sync folder wasn't there, it's made with the next sync");
+    }
+    if !rebuilt.unmatched.is_empty() {
+        println!("ℹ {} document(s) aren't any item's in Pocket, leaving them as they are:", rebuilt.unmatched.len());
+        for uuid in &rebuilt.unmatched {
+            println!("  ..{uuid}");
+        }
+    }
+
+    if let Err(e) = std::fs::create_dir_all(fshandler::state_dir()) {
+        println!("🚨 Could not make {}: {e}", fshandler::state_dir().display());
+        return;
+    }
+
+    if !save_or_report(&fhandler) {
+        return;
+    }
+    sync::resync::request();
+    println!("ℹ Saved the state to {}, the next sync is a resync", fshandler::config_file());
+}
+
+
+// No state, but a Pocket folder on the device: starting over would make another one, and write
+// every article in it again.
+fn orphaned_folders(settings: &Settings) -> bool {
+    let orphaned = fshandler::rebuild::orphaned(fshandler::config_file(), settings.folder_name());
+
+    if orphaned {
+        println!("🚨 There's a {} folder on the device but no state in {}", settings.folder_name(), fshandler::config_file());
+        println!("ℹ Run 'repocket rebuild-state' to make it again from the folder, rather than starting over");
+    }
+
+    orphaned
+}
+
+
+async fn watch(skip_verify: bool) {
     println!("ℹ Starting rePocket");
 
+    let settings = settings_or_exit();
+
+    // There's no Sync folder to watch on another machine.
+    if let Some(remote) = settings.remote() {
+        println!("🚨 Set up to sync for {}, run 'repocket sync' on a timer instead of watching", remote.host);
+        return;
+    }
+
+    if !resolve_root(&settings) {
+        println!("🚨 Refusing to sync into a directory xochitl doesn't read");
+        return;
+    }
+
+    if orphaned_folders(&settings) {
+        return;
+    }
+
     // Initialize the "App"
-    let mut pocket = Pocket::new();
-    let mut fhandler = FSHandler::load();
-    let _ = fhandler.mkdir_pocket().map_err(|_| { println!("ℹ Skipping, folder file already exists") });
+    let pocket = open_pocket();
+
+    // Rather now than once the Sync folder is touched, with xochitl about to be restarted for
+    // nothing. Without a network yet there's no telling, the first sync will.
+    if !skip_verify {
+        match pocket.verify_credentials().await {
+            Ok(()) => println!("ℹ Pocket takes the credentials"),
+            Err(e @ pocket::Error::Unauthorized(_)) => no_credentials(e),
+            Err(e) => println!("🚨 Could not check the credentials with Pocket, going ahead: {e}"),
+        }
+    }
+
+    // Consolidated by each sync, where a crash counts, see begin_counted().
+    let mut fhandler = state_or_exit(FSHandler::load_unconsolidated(fshandler::config_file()));
+    prepare_device(&mut fhandler, &settings);
     // Path to the Pocket/Sync folder.
-    let path = fshandler::XOCHITL_ROOT.to_string();
+    let path = root::xochitl_root().to_string();
 
 
-    if let Err(e) = async_watch(path, &mut pocket, &mut fhandler).await {
+    if let Err(e) = async_watch(path, &mut fhandler).await {
         println!("🚨 Error: {:?}", e)
     }
 }
 
 
+// A single sync, right now, instead of waiting for the Sync folder to be touched. With once, a
+// whole one even in safe mode, which ends safe mode if it goes through. With resync, of every
+// unread item, see sync::resync.
+async fn sync(ignore_budget: bool, once: bool, resync: bool, json: Option<&mut Json>) {
+    let settings = settings_or_exit();
+
+    let synced = match settings.remote() {
+        Some(remote) => sync_remote(remote, ignore_budget, once, resync).await.map_err(|e| e.to_string()),
+        None => sync_local(&settings, ignore_budget, once, resync).await,
+    };
+
+    match (json, synced) {
+        (Some(json), Ok(summary)) => json.emit(&summary),
+        (Some(json), Err(e)) => json.fail(e),
+        (None, _) => (),
+    }
+}
+
+
+async fn sync_local(settings: &Settings, ignore_budget: bool, once: bool, resync: bool) -> Result<SyncSummary, String> {
+    if !resolve_root(settings) {
+        println!("🚨 Refusing to sync into a directory xochitl doesn't read");
+        return Err("Refusing to sync into a directory xochitl doesn't read".to_string());
+    }
+
+    if orphaned_folders(settings) {
+        return Err("No state, but a folder from before, see 'repocket rebuild-state'".to_string());
+    }
+
+    if resync {
+        sync::resync::request();
+    }
+
+    let counter = CrashCounter::new();
+    let safe_mode = !once && counter.safe_mode();
+
+    let mut pocket = pocket_for(&settings_or_exit());
+    let (mut fhandler, before) = begin_counted(&counter, safe_mode);
+    prepare_device(&mut fhandler, &settings_or_exit());
+
+    let mut ctx = SyncContext {
+        pocket: &mut pocket,
+        fhandler: &mut fhandler,
+        settings: settings_or_exit(),
+        clock: &CorrectedClock,
+        refresher: sync::refresher(),
+        ignore_budget,
+        safe_mode,
+    };
+
+    run_counted(&counter, before, &mut ctx).await.map_err(|e| e.to_string())
+}
+
+
+// The same, on a home server, see the remote module: into the staging directory, with the read
+// state pulled from the device first, and pushing the results to it in place of a restart.
+#[cfg(feature = "remote-push")]
+async fn sync_remote(remote: &remote::Remote, ignore_budget: bool, once: bool, resync: bool) -> Result<SyncSummary, SyncError> {
+    let staging = remote.staging();
+    let companion = remote::Companion::new(remote::Ssh::new(remote), &staging, &remote.refresh_cmd);
+
+    root::set_xochitl_root(&staging.to_string_lossy());
+    println!("ℹ Syncing into {} for {}", staging.display(), remote.host);
+
+    if resync {
+        sync::resync::request();
+    }
+
+    // Without the device's metadata nothing is taken for read, which is safe.
+    if let Err(e) = companion.pull() {
+        println!("🚨 Could not get the metadata from the device, nothing is taken for read: {e}");
+    }
+
+    let counter = CrashCounter::new();
+    let safe_mode = !once && counter.safe_mode();
+
+    let mut pocket = pocket_for(&settings_or_exit());
+    let (mut fhandler, before) = begin_counted(&counter, safe_mode);
+    prepare_device(&mut fhandler, &settings_or_exit());
+
+    let mut ctx = SyncContext {
+        pocket: &mut pocket,
+        fhandler: &mut fhandler,
+        settings: settings_or_exit(),
+        clock: &CorrectedClock,
+        refresher: &companion,
+        ignore_budget,
+        safe_mode,
+    };
+
+    run_counted(&counter, before, &mut ctx).await
+}
+
+
+// Set up for a device elsewhere, with nothing to push to it with.
+#[cfg(not(feature = "remote-push"))]
+async fn sync_remote(remote: &remote::Remote, _ignore_budget: bool, _once: bool, _resync: bool) -> Result<SyncSummary, String> {
+    let e = format!("Set up to sync for {}, but companion mode isn't compiled in, build with --features remote-push", remote.host);
+    println!("🚨 {e}");
+
+    Err(e)
+}
+
+
+// The state for a sync, loaded once the sync counts as a crash, for a crash while consolidating it
+// to count too. Not consolidated in safe mode, as that may be what keeps crashing. Returns the count
+// from before, for run_counted(), None in safe mode.
+fn begin_counted(counter: &CrashCounter, safe_mode: bool) -> (FSHandler, Option<u32>) {
+    if safe_mode {
+        println!("🚨 The last {} syncs didn't finish, starting in safe mode", counter.count());
+        return (state_or_exit(FSHandler::load_unconsolidated(fshandler::config_file())), None);
+    }
+
+    let before = counter.begin();
+
+    (state_or_exit(FSHandler::load()), Some(before))
+}
+
+
+// A sync that never returns, i.e. that panics, counts as a crash, see the safemode module.
+async fn run_counted<P: pocket::PocketApi>(counter: &CrashCounter, before: Option<u32>, ctx: &mut SyncContext<'_, P>) -> Result<SyncSummary, SyncError> {
+    let synced = sync::run_sync(ctx).await;
+
+    match &synced {
+        Ok(summary) => {
+            println!("ℹ {summary:?}");
+            if before.is_some() {
+                counter.finished();
+            }
+        },
+        Err(e) => {
+            println!("🚨 {e}");
+            if let Some(count) = before {
+                counter.restore(count);
+            }
+        },
+    }
+
+    synced
+}
+
+
+fn safe_mode(clear: bool) {
+    let counter = CrashCounter::new();
+
+    if clear {
+        counter.clear();
+        println!("ℹ Cleared, the next sync is a normal one");
+    } else if counter.safe_mode() {
+        println!("🚨 In safe mode, the last {} syncs didn't finish", counter.count());
+        println!("ℹ Run 'repocket sync --once' or 'repocket safe-mode --clear' to leave it");
+    } else {
+        println!("ℹ Not in safe mode, {} unfinished sync(s) in a row", counter.count());
+    }
+}
+
+
+// A whole sync against the fixtures in dir instead of Pocket, writing into a scratch directory
+// instead of xochitl's, with the actions that would have been sent in its actions.log.
+#[cfg(feature = "offline-fixtures")]
+async fn sync_offline(fixtures: &Path, ignore_budget: bool) {
+    if !fixtures.join("retrieve.json").exists() {
+        println!("🚨 No retrieve.json in {}", fixtures.display());
+        return;
+    }
+
+    let out = std::env::temp_dir().join("repocket-offline");
+    if let Err(e) = std::fs::create_dir_all(&out) {
+        println!("🚨 Could not create {}: {e}", out.display());
+        return;
+    }
+
+    let out_str = out.to_string_lossy().to_string();
+    root::set_xochitl_root(&out_str);
+    println!("ℹ Syncing against {} into {out_str}", fixtures.display());
+
+    let mut pocket = FixturePocket::new(fixtures, &out.join("actions.log"));
+    let mut fhandler = state_or_exit(FSHandler::load_from(&(out_str.clone() + "/repocket.config")));
+    prepare_device(&mut fhandler, &Settings::load());
+
+    let mut ctx = SyncContext {
+        pocket: &mut pocket,
+        fhandler: &mut fhandler,
+        settings: Settings::load(),
+        clock: &CorrectedClock,
+        refresher: &sync::NoRefresh,
+        ignore_budget,
+        safe_mode: false,
+    };
+
+    match sync::run_sync(&mut ctx).await {
+        Ok(summary) => println!("ℹ {summary:?}"),
+        Err(e) => println!("🚨 {e}"),
+    }
+}
+
+
+// The device build has no use for the fixtures, nor for the server they're served from.
+#[cfg(not(feature = "offline-fixtures"))]
+async fn sync_offline(fixtures: &Path, _ignore_budget: bool) {
+    println!("🚨 Not syncing against {}, the offline fixtures aren't compiled in, build with --features offline-fixtures", fixtures.display());
+}
+
+
+// Bring items back from Pocket's archive, either those in select or those picked from a list.
+async fn restore(search: Option<String>, tag: Option<String>, count: u8, select: Option<String>) {
+    let settings = settings_or_exit();
+
+    if !resolve_root(&settings) {
+        println!("🚨 Refusing to restore into a directory xochitl doesn't read");
+        return;
+    }
+
+    let mut pocket = open_pocket();
+    let mut fhandler = state_or_exit(FSHandler::load());
+    prepare_device(&mut fhandler, &settings);
+    articlehandler::configure(&settings);
+
+    let mut builder = QueryBuilder::default()
+        .set_state("Archive")
+        .set_sort("Newest")
+        .set_detail_type("Complete")
+        .set_count(count);
+
+    if let Some(search) = &search {
+        builder = builder.set_search(search);
+    }
+    if let Some(tag) = &tag {
+        builder = builder.set_tag(tag);
+    }
+
+    if let Err(e) = pocket::PocketApi::fetch(&mut pocket, &builder.build().unwrap()).await {
+        println!("🚨 {e}");
+        return;
+    }
+
+    if pocket.is_empty() {
+        println!("ℹ Nothing in the archive matches");
+        return;
+    }
+
+    for (i, item) in pocket.iter().enumerate() {
+        println!("{:>3}. {}\n     {}", i + 1, item.get_title(), item.best_url().unwrap_or_default());
+    }
+
+    let select = match select {
+        Some(select) => select,
+        None if std::io::stdin().is_terminal() => {
+            print!("Restore which ones? (e.g. 1,3-4 or all, nothing to cancel): ");
+            let _ = std::io::stdout().flush();
+
+            let mut line = String::new();
+            let _ = std::io::stdin().read_line(&mut line);
+            line
+        },
+        None => {
+            println!("ℹ Use --select to pick the items to restore, e.g. --select 1,3-4");
+            return;
+        },
+    };
+
+    let selection = match cli::parse_selection(&select, pocket.len()) {
+        Ok(selection) if selection.is_empty() => return,
+        Ok(selection) => selection,
+        Err(e) => {
+            println!("🚨 {e}");
+            return;
+        },
+    };
+
+    let options = ArticleOptions { images: settings.images(), favicons: settings.favicons(), limits: settings.epub_limits(), colors: settings.color_policy() };
+
+    for i in selection {
+        let item = &pocket[i];
+
+        match fhandler.restore_article(item, options).await {
+            Restored::Reparented(_) => println!("ℹ Restored '{}' from the Archive folder", item.get_title()),
+            Restored::Regenerated(_) => println!("ℹ Restored '{}' by downloading it again", item.get_title()),
+            Restored::Failed => println!("🚨 Could not restore '{}'", item.get_title()),
+        }
+    }
+
+    save_or_report(&fhandler);
+    sync::refresher().refresh();
+}
+
+
+// Archive, or delete, in Pocket whatever is waiting on the device. Items archived on the device while in
+// read-only upstream mode are only pushed when explicitly asked for.
+async fn push_pending(include_readonly_history: bool) {
+    let settings = settings_or_exit();
+    let pocket = pocket_for(&settings);
+    let mut fhandler = state_or_exit(FSHandler::load());
+
+    let mut ids: Vec<u64> = fhandler.read_ids().chain(fhandler.pending_ids()).collect();
+    let withheld: Vec<u64> = fhandler.withheld_ids().collect();
+
+    if include_readonly_history {
+        ids.extend(withheld.iter());
+    } else if !withheld.is_empty() {
+        println!("ℹ {} item(s) were archived in read-only mode, use --include-readonly-history to push them", withheld.len());
+    }
+
+    // Read items may be pending a tag too.
+    ids.sort();
+    ids.dedup();
+
+    if ids.is_empty() {
+        println!("ℹ Nothing pending");
+    } else {
+        let archived = sync::push_upstream(&pocket, &ids, &mut fhandler, &settings).await;
+        fhandler.clear_read(&archived);
+
+        if include_readonly_history {
+            fhandler.clear_withheld(&archived);
+        }
+
+        if archived.len() < ids.len() {
+            println!("🚨 {} item(s) could not be pushed, they'll be tried again", ids.len() - archived.len());
+        }
+    }
+
+    save_or_report(&fhandler);
+}
+
 
 // The async watcher uses the Debouncer version of Notify to filter-out multiple events for the
 // same Path. This seems to work better, and produces less useless iterations than the
@@ -76,138 +1051,110 @@ fn async_watcher() -> notify::Result<(Debouncer<RecommendedWatcher>, Receiver<no
 }
 
 
-async fn async_watch<P: AsRef<Path>>(path: P, pocket: &mut Pocket, fhandler: &mut FSHandler) -> notify::Result<()> {
+async fn async_watch<P: AsRef<Path>>(path: P, fhandler: &mut FSHandler) -> notify::Result<()> {
     let wfname = path.as_ref().join(fhandler.sync_uuid_string() + ".metadata");
+    let inbox = inbox::inbox_file();
     let (mut debouncer, mut rx) = async_watcher().expect("Could not start notify");
 
     // Add the path (file, in this case to be watched)
     debouncer.watcher().watch(path.as_ref(), RecursiveMode::NonRecursive).unwrap();
 
-    while let Some(res) = rx.recv().await {
-        match res {
-            Ok(events) => {
-
-                for event in events {
-                    if event.path == wfname && event.kind == DebouncedEventKind::Any {
-                        println!("ℹ Found syncing event: {:?}", event);
-
-                        // This should be the entry point for the watching changes to the Sync Folder.
-                        let since = fhandler.last_query_ts();
-
-                        let complete_query = QueryBuilder::default()
-                            .set_state("Unread")
-                            .set_favorite(0)
-                            //.set_tag("pdf")
-                            //.set_content_type("Article")
-                            .set_sort("Newest")
-                            .set_detail_type("Complete")
-                            //.set_search("learn")
-                            //.set_domain(".com")
-                            .set_since(since)
-                            .set_count(10)
-                            .set_offset(0)
-                            .set_total(1)
-                            .build();
-
-                        let res = pocket.retrieve(&complete_query.unwrap()).await;
-
-                        // Send the result for processing, that is, create a list of PocketItems.
-                        //
-                        // This are the higher-level fields for the response.
-                        //      "maxActions":30,
-                        //      "cachetype":"db",
-                        //      "status":1,
-                        //      "error":null,
-                        //      "complete":1,
-                        //      "since":1729763686,
-                        //      "list": { // This is the list object referred to in the documentation with as many
-                        //          id : { response fields as requested up to a max of 30 }
-                        //
-                        // The value "since" should be stored so as to pass it again on the next _efficient_ request.
-                        //
-                        match res {
-                            Ok(val) => {
-                                pocket.init(val).await;
-                                fhandler.set_last_query_ts(pocket.since());
-                            },
-                            Err(e) => println!("🚨 Error {e}"),
-                        };
-
-
-                        for item in pocket.iter() {
-                            println!("ℹ Working on item id {:?} with URL\n  ..{:?}", item.get_resolved_id(), item.get_resolved_url());
-                            fhandler.new_article(&item).await;
-                        }
-
-                        // Archive all the items in the Read folder
-                        let ids : Vec<u64> = fhandler.read_ids().collect();
-
-                        if !ids.is_empty() {
-                            let res = pocket.archive(ids.clone()).await;
-
-                            // TODO: When proper error handling is implemented, this could be absorved by archive() and
-                            // dealt with over there. Returning error unless we get a 200 response.
-                            match res {
-                                Ok(val) => {
-                                    let status = val.status();
-                                    match status.clone() {
-                                        StatusCode::OK => {
-                                            // Tag all items
-                                            for id in fhandler.read_ids() {
-                                                println!("ℹ Tagging item id {:?} with tag 'repocket'", id);
-                                                let _res = pocket.add_tag(id, vec!["repocket".to_string()]).await;
-                                            }
-
-                                            // Remove all items form the read_items entry in the FSHandler.
-                                            fhandler.clear_read();
-                                        },
-                                        _ => println!("🚨 Error, archive() returned with status {:?}", status),
-                                    }
-                                },
-                                Err(e) =>  println!("🚨 Error {e}"),
-                            }
-                        }
-
-
-                        // Save and reload fhandler.
-                        fhandler.save_config();
-
-                        let fhandler = FSHandler::load();
-
-                        println!("ℹ Unwatching the Sync folder while Xochitl restarts");
-                        let _ = debouncer.watcher().unwatch(path.as_ref());
-
-                        if cfg!(target_abi = "eabihf") {
-                            let cmd = std::thread::spawn(move || {
-                                std::process::Command::new("systemctl")
-                                    .arg("restart")
-                                    .arg("xochitl")
-                                    .output()
-                                    .expect("Could not restart Xochitl");
-
-                                println!(" .. sleeping for some empirical number of seconds during restart");
-
-                                std::thread::sleep(std::time::Duration::new(30, 0));
-                            });
-
-                            let _result = cmd.join().unwrap();
-                        } else {
-                            println!("ℹ In the remarkable we'd be restarting Xochitl");
-                        }
-
-                        println!("ℹ Watching the Sync folder again");
-                        debouncer.watcher().watch(path.as_ref(), RecursiveMode::NonRecursive).unwrap();
-
-                        // This could be taken out and logged once, at the end, for instance.
-                        println!("ℹ {:#?}", fhandler);
-
-                        // Break out of the loop early
-                        break;
+    // Syncs can also happen on a timer, if configured.
+    let mut scheduler = Scheduler::new(SystemClock, TcpProber::new(POCKET_HOST), &settings_or_exit());
+    let timer = scheduler.enabled();
+    // When Pocket takes requests again, after a sync skipped for its rate limit.
+    let mut retry_at: Option<tokio::time::Instant> = None;
+
+    loop {
+        let triggered = tokio::select! {
+            res = rx.recv() => match res {
+                Some(Ok(events)) => {
+                    if events.iter().any(|event| event.path == inbox) {
+                        inbox::add_all(&open_pocket(), &inbox).await;
+                    }
+
+                    match events.iter().find(|event| event.path == wfname && event.kind == DebouncedEventKind::Any) {
+                        Some(event) if scheduler.allows_watch() => {
+                            println!("ℹ Found syncing event: {:?}", event);
+                            true
+                        },
+                        Some(_) => {
+                            println!("ℹ Not syncing, it's outside the schedule in the settings");
+                            false
+                        },
+                        None => false,
                     }
-                }
+                },
+                Some(Err(e)) => {
+                    println!("watch error: {:?}", e);
+                    false
+                },
+                None => break,
+            },
+            _ = scheduler.wait(), if timer || scheduler.resuming() => {
+                println!("ℹ Time for a scheduled sync");
+                true
+            },
+            _ = tokio::time::sleep_until(retry_at.unwrap_or_else(tokio::time::Instant::now)), if retry_at.is_some() => {
+                println!("ℹ Pocket takes requests again, syncing");
+                true
+            },
+        };
+
+        if !triggered {
+            continue;
+        }
+
+        // The sync writes documents and restarts xochitl, none of which should trigger another one.
+        println!("ℹ Unwatching the Sync folder during the sync");
+        let _ = debouncer.watcher().unwatch(path.as_ref());
+
+        // Afresh for every sync, a Pocket keeps the items it retrieved, and a command may have
+        // changed the state on disk since the last one.
+        let settings = settings_or_exit();
+        let mut pocket = pocket_for(&settings);
+        inbox::add_all(&pocket, &inbox).await;
+
+        // Checked every time, safe mode may have been cleared in the meantime.
+        let counter = CrashCounter::new();
+        let safe_mode = counter.safe_mode();
+        let (state, before) = begin_counted(&counter, safe_mode);
+        *fhandler = state;
+        prepare_device(fhandler, &settings);
+
+        let mut ctx = SyncContext {
+            pocket: &mut pocket,
+            fhandler: &mut *fhandler,
+            settings,
+            clock: &CorrectedClock,
+            refresher: sync::refresher(),
+            ignore_budget: false,
+            safe_mode,
+        };
+
+        let synced = run_counted(&counter, before, &mut ctx).await;
+        match &synced {
+            Ok(summary) if summary.suspended => scheduler.interrupted(),
+            _ => scheduler.finished(synced.is_ok()),
+        }
+
+        // Tried again once Pocket's limit resets, rather than on the next touch of the Sync folder.
+        retry_at = match synced {
+            Err(SyncError::Rejected(pocket::Error::RateLimited { retry_after: Some(secs) })) => {
+                println!("ℹ Syncing again in {secs} s, once Pocket's rate limit resets");
+                Some(tokio::time::Instant::now() + std::time::Duration::from_secs(secs.max(1)))
             },
-            Err(e) => println!("watch error: {:?}", e),
+            _ => None,
+        };
+
+        // Every sync from now on would fail the same way.
+        if let Err(SyncError::Rejected(pocket::Error::Unauthorized(_))) = synced {
+            println!("🚨 Stopping, run rePocketAuth, or 'repocket setup', for new credentials and start rePocket again");
+            return Ok(());
         }
+
+        println!("ℹ Watching the Sync folder again");
+        debouncer.watcher().watch(path.as_ref(), RecursiveMode::NonRecursive).unwrap();
     }
 
     Ok(())
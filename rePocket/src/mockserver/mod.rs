@@ -0,0 +1,267 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+
+//
+// A tiny HTTP server for the tests and the offline fixtures, serving canned replies on 127.0.0.1
+// so that nothing has to go out to the internet. Each reply is sent whole, with a Content-Length, and the connection is
+// closed afterwards.
+//
+// A path given more than once gets its replies in order, the last one sticks.
+//
+#[derive(Clone, Debug)]
+pub struct Reply {
+    pub status: u16,
+    pub content_type: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    // How long to wait before answering, e.g. for a page that takes a while.
+    pub delay: Option<Duration>,
+    // Close the connection instead of answering, as a device going to sleep would see it.
+    pub dropped: bool,
+    // Keep the connection open and never answer at all.
+    pub stalled: bool,
+}
+
+
+impl Reply {
+    pub fn ok(content_type: &str, body: &[u8]) -> Self {
+        Self {
+            status: 200,
+            content_type: content_type.to_string(),
+            headers: Vec::new(),
+            body: body.to_vec(),
+            delay: None,
+            dropped: false,
+            stalled: false,
+        }
+    }
+
+
+    pub fn not_found() -> Self {
+        Self {
+            status: 404,
+            content_type: "text/plain".to_string(),
+            headers: Vec::new(),
+            body: b"Not Found".to_vec(),
+            delay: None,
+            dropped: false,
+            stalled: false,
+        }
+    }
+
+
+    #[cfg(test)]
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+
+        self
+    }
+
+
+    #[cfg(test)]
+    pub fn slow(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+
+        self
+    }
+
+
+    // Takes the request, and never answers it, as a server dripping nothing would.
+    #[cfg(test)]
+    pub fn stalled() -> Self {
+        Self {
+            stalled: true,
+            ..Self::not_found()
+        }
+    }
+
+
+    #[cfg(test)]
+    pub fn dropped() -> Self {
+        Self {
+            dropped: true,
+            ..Self::not_found()
+        }
+    }
+}
+
+
+// What the server was asked for, in order.
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+
+#[cfg(test)]
+impl Request {
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    }
+}
+
+pub type Requests = Arc<Mutex<Vec<Request>>>;
+
+
+// Serves the given paths, returns the base URL, e.g. "http://127.0.0.1:34567".
+#[cfg(test)]
+pub async fn serve(routes: Vec<(&str, Reply)>) -> String {
+    serve_recording(routes).await.0
+}
+
+
+// Same as serve(), but keeps the requests around for the test to look at.
+#[cfg(test)]
+pub async fn serve_recording(routes: Vec<(&str, Reply)>) -> (String, Requests) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let routes = routes.into_iter()
+        .map(|(path, reply)| (path.to_string(), reply))
+        .collect();
+
+    (base, spawn(listener, routes))
+}
+
+
+// Same as serve(), for replies that need to know where they're served from, e.g. pages linking to
+// their images.
+pub async fn serve_with<F: FnOnce(&str) -> Vec<(String, Reply)>>(routes: F) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+
+    spawn(listener, routes(&base));
+
+    base
+}
+
+
+fn spawn(listener: TcpListener, routes: Vec<(String, Reply)>) -> Requests {
+    let requests: Requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    let routes = Arc::new(Mutex::new(routes));
+
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let routes = routes.clone();
+            let recorded = recorded.clone();
+
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+
+                // Read the headers, and then as much body as they announce.
+                let head_end = loop {
+                    if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                };
+
+                let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+                let length = head.lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+
+                while request.len() < head_end + length {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+
+                let mut line = head.lines().next().unwrap_or_default().split_whitespace();
+                let method = line.next().unwrap_or_default().to_string();
+                let path = line.next().unwrap_or_default().to_string();
+
+                recorded.lock().unwrap().push(Request {
+                    method: method.clone(),
+                    path: path.clone(),
+                    headers: head.lines()
+                        .skip(1)
+                        .filter_map(|line| line.split_once(':'))
+                        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                        .collect(),
+                    body: String::from_utf8_lossy(&request[head_end..]).to_string(),
+                });
+
+                let reply = {
+                    let mut routes = routes.lock().unwrap();
+                    let matching: Vec<usize> = routes.iter()
+                        .enumerate()
+                        .filter(|(_, (route, _))| *route == path)
+                        .map(|(i, _)| i)
+                        .collect();
+
+                    match matching.as_slice() {
+                        [] => Reply::not_found(),
+                        [only] => routes[*only].1.clone(),
+                        [first, ..] => routes.remove(*first).1,
+                    }
+                };
+
+                if let Some(delay) = reply.delay {
+                    tokio::time::sleep(delay).await;
+                }
+
+                if reply.stalled {
+                    std::future::pending::<()>().await;
+                }
+
+                if reply.dropped {
+                    return;
+                }
+
+                // No Content-Type at all for an empty one, as some servers send images.
+                let content_type = match reply.content_type.is_empty() {
+                    true => String::new(),
+                    false => format!("Content-Type: {}\r\n", reply.content_type),
+                };
+                let extra: String = reply.headers.iter()
+                    .map(|(name, value)| format!("{name}: {value}\r\n"))
+                    .collect();
+                let head = format!(
+                    "HTTP/1.1 {} X\r\n{content_type}Content-Length: {}\r\n{extra}Connection: close\r\n\r\n",
+                    reply.status, reply.body.len()
+                );
+
+                let _ = socket.write_all(head.as_bytes()).await;
+                if method != "HEAD" {
+                    let _ = socket.write_all(&reply.body).await;
+                }
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+
+    requests
+}
@@ -0,0 +1,238 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+#[cfg(test)]
+use std::{cell::RefCell, rc::Rc};
+use serde::Serialize;
+
+use crate::fshandler::Broken;
+
+
+//
+// With --json the commands that take it, see cli::Command::speaks_json(), print a single JSON
+// document on stdout, for scripts on the device, and everything meant for people goes to stderr
+// instead. Rather than telling every println!
+// about it, stdout itself is pointed at stderr as soon as the arguments are parsed, and the
+// document is written to what stdout was before. The exit codes are the same either way.
+//
+// The documents are the serde structs the commands already have, see FSHandler::status_report(),
+// ConsolidationPlan, VerifyReport and sync::SyncSummary, or a Failure when there's nothing else to
+// say. data/fixtures/json has one of each.
+//
+pub struct Json {
+    out: Box<dyn Write>,
+}
+
+
+impl Json {
+    // Only once, and before anything is printed.
+    pub fn take_stdout() -> io::Result<Self> {
+        io::stdout().flush()?;
+
+        // SAFETY: plain descriptor juggling, the duplicate is owned by nothing but the File.
+        let out = unsafe {
+            let saved = libc::dup(io::stdout().as_raw_fd());
+            if saved < 0 || libc::dup2(io::stderr().as_raw_fd(), io::stdout().as_raw_fd()) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            File::from_raw_fd(saved)
+        };
+
+        Ok(Self { out: Box::new(out) })
+    }
+
+
+    // Into a buffer instead, for the tests to see what a command printed.
+    #[cfg(test)]
+    pub fn capture() -> (Self, Rc<RefCell<Vec<u8>>>) {
+        struct Shared(Rc<RefCell<Vec<u8>>>);
+
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        (Self { out: Box::new(Shared(buffer.clone())) }, buffer)
+    }
+
+
+    pub fn emit<T: Serialize>(&mut self, document: &T) {
+        let written = serde_json::to_string_pretty(document)
+            .map_err(io::Error::from)
+            .and_then(|text| writeln!(self.out, "{text}"));
+
+        if let Err(e) = written {
+            println!("🚨 Cannot write the json document! {e}");
+        }
+    }
+
+
+    pub fn fail(&mut self, error: impl ToString) {
+        self.emit(&Failure { error: error.to_string() });
+    }
+}
+
+
+// What `repocket verify --json` prints: the broken documents, and with --repair what became of them.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct VerifyReport {
+    pub broken: Vec<Broken>,
+    pub repaired: Vec<String>,
+}
+
+
+// A command that stopped short, e.g. a sync that couldn't reach Pocket.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Failure {
+    pub error: String,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fshandler::{ConsolidationPlan, PlannedItem, StatusFailure, StatusItem, StatusReport};
+    use crate::compat::Finding;
+    use crate::fshandler::flags::ItemFlags;
+    use crate::fshandler::integrity::Problem;
+    use crate::pocket::PocketAction;
+    use crate::sync::SyncSummary;
+
+    const FIXTURES: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/json");
+    const EPUBS: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/epub");
+
+    fn fixture(name: &str) -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(format!("{FIXTURES}/{name}.json")).unwrap()).unwrap()
+    }
+
+    // What went out through the Json, which has to be a single document and nothing else.
+    fn printed(command: impl FnOnce(&mut Json)) -> serde_json::Value {
+        let (mut json, buffer) = Json::capture();
+        command(&mut json);
+
+        let text = String::from_utf8(buffer.borrow().clone()).unwrap();
+        let documents: Vec<serde_json::Value> = serde_json::Deserializer::from_str(&text)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(documents.len(), 1, "{text}");
+        documents[0].clone()
+    }
+
+    fn snapshot<T: Serialize>(name: &str, document: &T) {
+        assert_eq!(printed(|json| json.emit(document)), fixture(name), "{name}.json");
+    }
+
+    #[test]
+    fn status() {
+        let flags = ItemFlags {
+            article: true,
+            images: true,
+            minutes: Some(12),
+            lang: Some("en".to_string()),
+            ..ItemFlags::default()
+        };
+
+        snapshot("status", &StatusReport {
+            in_folder: vec![StatusItem {
+                uuid: "b9a44b3e-5d43-4d5c-9d4a-0b4c7a1d2f10".to_string(),
+                id: 1234,
+                name: "Rust lifetimes".to_string(),
+                flags: Some(flags),
+                progress: Some(48),
+                trace: None,
+            }],
+            pinned: vec![],
+            failed: vec![StatusFailure {
+                id: 5678,
+                url: "https://example.com/video".to_string(),
+                reason: "Not an article".to_string(),
+            }],
+            next_sync: None,
+        });
+    }
+
+    #[test]
+    fn consolidate() {
+        let uuid = "b9a44b3e-5d43-4d5c-9d4a-0b4c7a1d2f10";
+
+        snapshot("consolidate", &ConsolidationPlan {
+            read: vec![PlannedItem { uuid: serde_json::from_value(serde_json::json!(uuid)).unwrap(), id: 1234, parent: Some("trash".to_string()) }],
+            pocket_actions: vec![PocketAction::Archive { item_id: 1234 }],
+            progress: [(5678, 30)].into(),
+            ..ConsolidationPlan::default()
+        });
+    }
+
+    #[test]
+    fn verify() {
+        snapshot("verify", &VerifyReport {
+            broken: vec![Broken {
+                uuid: "b9a44b3e-5d43-4d5c-9d4a-0b4c7a1d2f10".to_string(),
+                id: 1234,
+                problems: vec![Problem::TooSmall("epub".to_string(), 12), Problem::WrongType("pdf".to_string())],
+            }],
+            repaired: vec!["Wrote Rust lifetimes again".to_string()],
+        });
+    }
+
+    #[test]
+    fn lint() {
+        snapshot("lint", &vec![Finding {
+            rule: "css-position",
+            entry: "OEBPS/stylesheet.css".to_string(),
+            detail: "position: fixed".to_string(),
+            fixed: false,
+        }]);
+
+        // The command itself: the finding above is among what it prints for the epub with every quirk.
+        let quirks = printed(|json| crate::lint(&format!("{EPUBS}/quirks.epub"), Some(json)));
+        assert!(quirks.as_array().unwrap().contains(&fixture("lint")[0]), "{quirks}");
+        assert_eq!(printed(|json| crate::lint(&format!("{EPUBS}/clean.epub"), Some(json))), serde_json::json!([]));
+
+        let file = format!("{FIXTURES}/lint.json");
+        let failed = printed(|json| crate::lint(&file, Some(json)));
+        assert!(failed["error"].as_str().unwrap().starts_with(&format!("Can't read {file} as an epub")), "{failed}");
+    }
+
+    #[test]
+    fn sync() {
+        snapshot("sync", &SyncSummary {
+            written: 3,
+            failed: 1,
+            archived: 2,
+            bytes: 1048576,
+            month_bytes: 5242880,
+            budget: "5 MB of 100 MB".to_string(),
+            skipped: vec!["https://example.com/video".to_string()],
+            too_old: 1,
+            dns_lookups: 12,
+            dns_misses: 4,
+            ..SyncSummary::default()
+        });
+        snapshot("failure", &Failure { error: "Could not reach Pocket".to_string() });
+    }
+}
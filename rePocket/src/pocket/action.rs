@@ -0,0 +1,200 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+
+//
+// What can be sent to Pocket, many at a time and of any kind, see Pocket::send_actions(). Each one
+// goes as an object named after its action, e.g.
+//
+//   {"action": "archive", "item_id": 123}
+//   {"action": "tags_add", "item_id": 123, "tags": "repocket"}
+//
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PocketAction {
+    Archive { item_id: u64 },
+    Delete { item_id: u64 },
+    Favorite { item_id: u64 },
+    Unfavorite { item_id: u64 },
+    // Back to the list, unread, from the archive.
+    Readd { item_id: u64 },
+    // Tags, comma separated.
+    TagsAdd { item_id: u64, tags: String },
+    TagsRemove { item_id: u64, tags: String },
+    TagsReplace { item_id: u64, tags: String },
+    TagsClear { item_id: u64 },
+}
+
+
+//
+// What goes in a send, the actions, e.g.
+//
+//   {"actions": [{"action": "archive", "item_id": 123}]}
+//
+// The credentials go along with them, see Authenticated.
+//
+#[derive(Debug, Serialize)]
+pub struct SendRequest<'a> {
+    actions: &'a [PocketAction],
+}
+
+
+impl<'a> SendRequest<'a> {
+    pub fn new(actions: &'a [PocketAction]) -> Self {
+        Self { actions }
+    }
+}
+
+
+//
+// What Pocket answers a send with, one result per action, in order, and the errors of those that
+// failed, e.g.
+//
+//   {"status": 1, "action_results": [true, false], "action_errors": [null, {"message": "..."}]}
+//
+// A result is false for an action that failed, true, or the item, for one that went through.
+//
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SendResponse {
+    pub status: u8,
+    pub action_results: Option<Vec<serde_json::Value>>,
+    pub action_errors: Vec<Option<ActionError>>,
+}
+
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ActionError {
+    pub message: Option<String>,
+    pub code: Option<u32>,
+}
+
+
+impl SendResponse {
+    // Whether each of the count actions sent went through. Without per-action results, they all
+    // go with the overall status.
+    pub fn confirmed(&self, count: usize) -> Vec<bool> {
+        (0..count)
+            .map(|i| match &self.action_results {
+                Some(results) => results.get(i).is_some_and(|result| !matches!(result, serde_json::Value::Bool(false) | serde_json::Value::Null)),
+                None => self.status == 1,
+            })
+            .collect()
+    }
+
+
+    // Pocket's reason for the action at i failing, if it gave one.
+    pub fn error(&self, i: usize) -> Option<&str> {
+        self.action_errors.get(i)?.as_ref()?.message.as_deref()
+    }
+}
+
+
+
+//
+// What Pocket answers an add with, the item as it now is in the list, e.g.
+//
+//   {"status": 1, "item": {"item_id": "1234", "normal_url": "...", ...}}
+//
+#[derive(Debug, Deserialize)]
+pub struct AddResponse {
+    item: AddedItem,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct AddedItem {
+    #[serde(deserialize_with = "item_id")]
+    item_id: u64,
+}
+
+
+// Ids come in as strings, mostly.
+fn item_id<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(id) => id.parse().map_err(serde::de::Error::custom),
+        serde_json::Value::Number(id) => id.as_u64().ok_or_else(|| serde::de::Error::custom("not an item id")),
+        other => Err(serde::de::Error::custom(format!("expected an item id, got {other}"))),
+    }
+}
+
+
+impl AddResponse {
+    pub fn item_id(&self) -> u64 {
+        self.item.item_id
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn one_actions_array() {
+        let actions = vec![
+            PocketAction::Archive { item_id: 1 },
+            PocketAction::TagsAdd { item_id: 1, tags: "repocket".to_string() },
+            PocketAction::Delete { item_id: 2 },
+            PocketAction::TagsRemove { item_id: 3, tags: "a,b".to_string() },
+            PocketAction::Favorite { item_id: 4 },
+            PocketAction::Unfavorite { item_id: 5 },
+        ];
+
+        assert_eq!(json!(actions), json!([
+            {"action": "archive", "item_id": 1},
+            {"action": "tags_add", "item_id": 1, "tags": "repocket"},
+            {"action": "delete", "item_id": 2},
+            {"action": "tags_remove", "item_id": 3, "tags": "a,b"},
+            {"action": "favorite", "item_id": 4},
+            {"action": "unfavorite", "item_id": 5},
+        ]));
+    }
+
+    #[test]
+    fn send_requests() {
+        let actions = vec![PocketAction::Archive { item_id: 1 }];
+
+        assert_eq!(json!(SendRequest::new(&actions)), json!({"actions": [{"action": "archive", "item_id": 1}]}));
+        assert_eq!(json!(SendRequest::new(&[])), json!({"actions": []}));
+    }
+
+    #[test]
+    fn send_responses() {
+        let response = |json: serde_json::Value| serde_json::from_value::<SendResponse>(json).unwrap();
+
+        let partial = response(json!({
+            "status": 1,
+            "action_results": [true, false, {"item_id": "3"}, null],
+            "action_errors": [null, {"message": "Invalid item id", "code": 422}, null, null],
+        }));
+        assert_eq!(partial.confirmed(5), vec![true, false, true, false, false]);
+        assert_eq!(partial.error(1), Some("Invalid item id"));
+        assert_eq!(partial.error(0), None);
+        assert_eq!(partial.error(7), None);
+
+        assert_eq!(response(json!({"status": 1})).confirmed(2), vec![true, true]);
+        assert_eq!(response(json!({"status": 0})).confirmed(2), vec![false, false]);
+    }
+}
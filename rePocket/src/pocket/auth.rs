@@ -0,0 +1,120 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use serde::Deserialize;
+use std::time::Duration;
+
+use super::credentials::Credentials;
+
+
+//
+// Pocket's OAuth, for `repocket setup`. It's the same dance as rePocketAuth's, only without the
+// local server for the redirect: the user opens the authorization page wherever they like, e.g.
+// on their phone when rePocket runs on the device, and says when they're done.
+//
+// 1. POST /oauth/request with the consumer key, for a request token.
+// 2. The user approves rePocket at authorize_url().
+// 3. POST /oauth/authorize with the request token, for the access token.
+//
+// Pocket says what went wrong in the X-Error header, e.g. "Invalid consumer key."
+//
+pub const DEVELOPER_URL: &str = "https://getpocket.com/developer/apps/new";
+const AUTHORIZE_PAGE: &str = "https://getpocket.com/auth/authorize";
+// Where the browser ends up once approved, anywhere will do.
+const REDIRECT_URI: &str = "https://getpocket.com/";
+
+
+#[derive(Deserialize)]
+struct RequestToken {
+    code: String,
+}
+
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+    username: String,
+}
+
+
+pub struct Auth {
+    client: reqwest::Client,
+    api: String,
+    consumer_key: String,
+}
+
+
+impl Auth {
+    pub fn new(api: &str, consumer_key: &str) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .unwrap_or_default(),
+            api: api.to_string(),
+            consumer_key: consumer_key.trim().to_string(),
+        }
+    }
+
+
+    pub async fn request_token(&self) -> Result<String, String> {
+        let body = serde_json::json!({ "consumer_key": self.consumer_key, "redirect_uri": REDIRECT_URI });
+        let token: RequestToken = self.post("/oauth/request", body).await?;
+
+        Ok(token.code)
+    }
+
+
+    pub fn authorize_url(code: &str) -> String {
+        format!("{AUTHORIZE_PAGE}?request_token={code}&redirect_uri={REDIRECT_URI}")
+    }
+
+
+    // The credentials, and whose they are.
+    pub async fn access_token(&self, code: &str) -> Result<(Credentials, String), String> {
+        let body = serde_json::json!({ "consumer_key": self.consumer_key, "code": code });
+        let token: AccessToken = self.post("/oauth/authorize", body).await?;
+
+        let creds = Credentials {
+            consumer_key: self.consumer_key.clone(),
+            access_token: token.access_token,
+        };
+
+        Ok((creds, token.username))
+    }
+
+
+    async fn post<T: for<'de> Deserialize<'de>>(&self, path: &str, body: serde_json::Value) -> Result<T, String> {
+        let res = self.client.post(self.api.clone() + path)
+            .header(reqwest::header::CONTENT_TYPE, "application/json; charset=UTF8")
+            .header("X-Accept", "application/json")
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !res.status().is_success() {
+            let why = res.headers().get("X-Error")
+                .and_then(|error| error.to_str().ok())
+                .unwrap_or("no reason given")
+                .to_string();
+
+            return Err(format!("Pocket said {}: {why}", res.status()));
+        }
+
+        res.json().await.map_err(|e| e.to_string())
+    }
+}
@@ -14,13 +14,40 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::fs::File;
-use std::path::Path;
+use std::fmt;
+use std::fs::{self, read_to_string};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Serialize};
 
 
+//
+// The credentials file has been known to end up somewhere other than where the binary looks for
+// it, e.g. built with different paths, or copied into the wrong home. So it's looked for in a few
+// places, in this order:
+//
+// - wherever --creds says, which then has to be there
+// - $REPOCKET_CREDS
+// - ~/.repocket/.repocket.key
+// - /home/root/.repocket/.repocket.key
+// - where the binary was built to look, see creds_file()
+//
+// A file that's there but can't be read, or isn't two lines, consumer key and access token, is
+// skipped with a warning, unless strict, when it's an error. Editors leave all sorts in it, a BOM, Windows line
+// endings, trailing spaces, which don't count, and the lines the wrong way round are taken
+// swapped, see Credentials::parse(). Anything else would only come back as a 403 much later.
+//
+const CREDS_ENV: &str = "REPOCKET_CREDS";
+const HOME_CREDS: &str = ".repocket/.repocket.key";
+const DEVICE_HOME: &str = "/home/root";
+
+// e.g. 123456-0123456789abcdef0c0ffee, and a UUID, e.g. 5678defa-1234-5678-9abc-def012345678.
+static CONSUMER_KEY: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[0-9]+-[0-9a-fA-F]+$").unwrap());
+static ACCESS_TOKEN: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[0-9a-fA-F]+(-[0-9a-fA-F]+)+$").unwrap());
+
+
 #[derive(Serialize)]
 pub struct Credentials {
     pub consumer_key: String,
@@ -29,19 +56,323 @@ pub struct Credentials {
 
 
 impl Credentials {
-    pub fn new(fname: &str) -> Self {
-        let fh = match File::open(&Path::new(fname)) {
-            Err(why) => panic!("🚨 Couldn't open {fname}: {}", why),
-            Ok(file) => file,
+    pub fn new(search: &Search) -> Result<(Self, PathBuf), CredsError> {
+        search.find()
+    }
+
+
+    pub fn load(path: &Path) -> Result<Self, CredsError> {
+        let text = read_to_string(path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => CredsError::NotFound(vec![path.to_path_buf()]),
+            _ => CredsError::Unreadable(path.to_path_buf(), e),
+        })?;
+        let (creds, swapped) = Self::parse(&text).map_err(|why| CredsError::Malformed(path.to_path_buf(), why))?;
+
+        if swapped {
+            println!("🚨 The access token comes before the consumer key in {}, reading them the other way round", path.display());
+        }
+
+        Ok(creds)
+    }
+
+
+    // The credentials, and whether the lines were the wrong way round.
+    pub fn parse(text: &str) -> Result<(Self, bool), String> {
+        let mut lines = text.trim_start_matches('\u{feff}').lines().map(str::trim).filter(|line| !line.is_empty());
+
+        let (first, second) = match (lines.next(), lines.next()) {
+            (Some(first), Some(second)) => (first, second),
+            (Some(_), None) => return Err("only one line, expected the consumer key and the access token, one per line".to_string()),
+            _ => return Err("empty, expected the consumer key and the access token, one per line".to_string()),
         };
+        if lines.next().is_some() {
+            return Err("more than two lines, expected the consumer key and the access token, one per line".to_string());
+        }
 
-        let mut lines = BufReader::new(fh).lines().flatten();
+        let creds = |consumer_key: &str, access_token: &str| Self {
+            consumer_key: consumer_key.to_string(),
+            access_token: access_token.to_string(),
+        };
 
-        let (ck, at) = (lines.next(), lines.next());
+        match (CONSUMER_KEY.is_match(first), ACCESS_TOKEN.is_match(second)) {
+            (true, true) => Ok((creds(first, second), false)),
+            _ if CONSUMER_KEY.is_match(second) && ACCESS_TOKEN.is_match(first) => Ok((creds(second, first), true)),
+            (false, _) => Err(format!("the first line, '{first}', doesn't look like a consumer key, e.g. 123456-0123456789abcdef0c0ffee")),
+            (true, false) => Err(format!("the second line, '{second}', doesn't look like an access token, e.g. 5678defa-1234-5678-9abc-def012345678")),
+        }
+    }
+
+
+    // The way load() reads them, making the directory if need be. For the user's eyes only, one
+    // that was already there included.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 
+            options.mode(0o600);
+            if path.exists() {
+                fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+            }
+        }
+
+        options.open(path)?.write_all(format!("{}\n{}\n", self.consumer_key, self.access_token).as_bytes())
+    }
+}
+
+
+#[derive(Debug)]
+pub enum CredsError {
+    // Where it was looked for.
+    NotFound(Vec<PathBuf>),
+    // There, but e.g. a directory, or not for this user to read.
+    Unreadable(PathBuf, std::io::Error),
+    Malformed(PathBuf, String),
+}
+
+
+impl fmt::Display for CredsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredsError::NotFound(tried) => {
+                write!(f, "No credentials file found, looked in:")?;
+                for path in tried {
+                    write!(f, "\n  {}", path.display())?;
+                }
+                Ok(())
+            },
+            CredsError::Unreadable(path, e) => write!(f, "Could not open the credentials file {}: {e}", path.display()),
+            CredsError::Malformed(path, why) => write!(f, "Could not read the credentials in {}: {why}", path.display()),
+        }
+    }
+}
+
+
+// Where to look, see above. Made up in the tests.
+#[derive(Clone, Debug, Default)]
+pub struct Search {
+    pub flag: Option<PathBuf>,
+    pub env: Option<PathBuf>,
+    pub home: Option<PathBuf>,
+    pub device_home: PathBuf,
+    pub legacy: PathBuf,
+    pub strict: bool,
+}
+
+
+impl Search {
+    pub fn system(flag: Option<&str>, strict: bool) -> Self {
         Self {
-            consumer_key: ck.expect("🚨 Could not parse consumer_key from credentials file."),
-            access_token: at.expect("🚨 Could not parse access_token from credentials file."),
+            flag: flag.map(PathBuf::from),
+            env: std::env::var_os(CREDS_ENV).filter(|path| !path.is_empty()).map(PathBuf::from),
+            home: std::env::var_os("HOME").filter(|path| !path.is_empty()).map(PathBuf::from),
+            device_home: PathBuf::from(DEVICE_HOME),
+            legacy: PathBuf::from(super::creds_file()),
+            strict,
+        }
+    }
+
+
+    // In order, without repeats, e.g. when HOME is /home/root.
+    pub fn locations(&self) -> Vec<PathBuf> {
+        let mut locations: Vec<PathBuf> = Vec::new();
+        let candidates = [
+            self.flag.clone(),
+            self.env.clone(),
+            self.home.as_ref().map(|home| home.join(HOME_CREDS)),
+            Some(self.device_home.join(HOME_CREDS)),
+            Some(self.legacy.clone()),
+        ];
+
+        for path in candidates.into_iter().flatten() {
+            if !locations.contains(&path) {
+                locations.push(path);
+            }
+        }
+
+        locations
+    }
+
+
+    pub fn find(&self) -> Result<(Credentials, PathBuf), CredsError> {
+        if let Some(flag) = &self.flag {
+            if !flag.exists() {
+                return Err(CredsError::NotFound(vec![flag.clone()]));
+            }
+        }
+
+        let locations = self.locations();
+
+        for path in locations.iter().filter(|path| path.exists()) {
+            match Credentials::load(path) {
+                Ok(creds) => return Ok((creds, path.clone())),
+                Err(e) if self.strict => return Err(e),
+                Err(e) => println!("🚨 {e}, skipping it"),
+            }
         }
+
+        Err(CredsError::NotFound(locations))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use uuid::Uuid;
+
+    // A home of its own, with the places to look at in it.
+    fn search(strict: bool) -> Search {
+        let base = std::env::temp_dir().join(format!("repocket-creds-{}", Uuid::new_v4()));
+
+        Search {
+            flag: None,
+            env: None,
+            home: Some(base.join("home")),
+            device_home: base.join("device"),
+            legacy: base.join("legacy/.repocket.key"),
+            strict,
+        }
+    }
+
+    fn write(path: &Path, text: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    // Told apart by n.
+    fn creds(n: u8) -> String {
+        format!("{n}-0123456789abcdef\n{n:08}-1234-5678-9abc-def012345678\n")
+    }
+
+    fn key(n: u8) -> String {
+        format!("{n}-0123456789abcdef")
+    }
+
+    #[test]
+    fn search_order() {
+        let mut search = search(false);
+        let env = search.device_home.parent().unwrap().join("env.key");
+        search.env = Some(env.clone());
+        let home = search.home.clone().unwrap().join(HOME_CREDS);
+        let device = search.device_home.join(HOME_CREDS);
+
+        assert_eq!(search.locations(), vec![env.clone(), home.clone(), device.clone(), search.legacy.clone()]);
+
+        // Found wherever it is, the earliest one first.
+        write(&search.legacy, &creds(1));
+        let (found, path) = search.find().unwrap();
+        assert_eq!((found.consumer_key, found.access_token), (key(1), "00000001-1234-5678-9abc-def012345678".to_string()));
+        assert_eq!(path, search.legacy);
+
+        write(&device, &creds(2));
+        assert_eq!(search.find().unwrap().1, device);
+
+        write(&home, &creds(3));
+        assert_eq!(search.find().unwrap().1, home);
+
+        write(&env, &creds(4));
+        assert_eq!(search.find().unwrap().1, env);
+
+        // Given on the command line.
+        let flag = home.with_file_name("flag.key");
+        write(&flag, &format!("\n {} \n\n00000005-1234-5678-9abc-def012345678\n", key(5)));
+        search.flag = Some(flag.clone());
+        let (found, path) = search.find().unwrap();
+        assert_eq!((found.consumer_key, path), (key(5), flag));
+    }
+
+    #[test]
+    fn same_home_twice() {
+        let mut search = search(false);
+        search.home = Some(search.device_home.clone());
+
+        assert_eq!(search.locations().len(), 2);
+    }
+
+    #[test]
+    fn malformed_earlier_location() {
+        let search = search(false);
+        let home = search.home.clone().unwrap().join(HOME_CREDS);
+        write(&home, &key(1));
+        write(&search.legacy, &creds(2));
+
+        // Skipped with a warning.
+        assert_eq!(search.find().unwrap().1, search.legacy);
+
+        // Or not at all.
+        let strict = Search { strict: true, ..search };
+        assert!(matches!(strict.find(), Err(CredsError::Malformed(path, _)) if path == home));
+    }
+
+    #[test]
+    fn unreadable() {
+        let search = search(false);
+        let home = search.home.clone().unwrap().join(HOME_CREDS);
+        fs::create_dir_all(&home).unwrap();
+        write(&search.legacy, &creds(1));
+
+        assert_eq!(search.find().unwrap().1, search.legacy);
+
+        let strict = Search { strict: true, ..search };
+        assert!(matches!(strict.find(), Err(CredsError::Unreadable(path, _)) if path == home));
+        assert!(matches!(Credentials::load(&home.with_file_name("missing.key")), Err(CredsError::NotFound(_))));
+    }
+
+    #[test]
+    fn nowhere_to_be_found() {
+        let search = search(false);
+
+        let error = search.find().unwrap_err();
+        let message = error.to_string();
+        for path in search.locations() {
+            assert!(message.contains(&path.display().to_string()));
+        }
+
+        // An explicit --creds must be there.
+        write(&search.legacy, &creds(1));
+        let missing = search.legacy.with_file_name("missing.key");
+        let flagged = Search { flag: Some(missing.clone()), ..search };
+        assert!(matches!(flagged.find(), Err(CredsError::NotFound(tried)) if tried == vec![missing]));
+    }
+
+    #[test]
+    fn what_editors_leave_behind() {
+        let token = "5678defa-1234-5678-9abc-def012345678";
+        let expected = |(creds, swapped): (Credentials, bool)| (creds.consumer_key, creds.access_token, swapped);
+
+        let crlf = format!("{}\r\n{token}\r\n", key(1));
+        assert_eq!(Credentials::parse(&crlf).map(expected), Ok((key(1), token.to_string(), false)));
+
+        let bom = format!("\u{feff}{} \n{token}\t\n\n", key(1));
+        assert_eq!(Credentials::parse(&bom).map(expected), Ok((key(1), token.to_string(), false)));
+
+        let swapped = format!("{token}\n{}\n", key(1));
+        assert_eq!(Credentials::parse(&swapped).map(expected), Ok((key(1), token.to_string(), true)));
+    }
+
+    #[test]
+    fn not_credentials() {
+        let token = "5678defa-1234-5678-9abc-def012345678";
+        let problem = |text: &str| Credentials::parse(text).err().unwrap();
+
+        assert!(problem("").starts_with("empty"));
+        assert!(problem(&key(1)).starts_with("only one line"));
+        assert!(problem(&format!("{}\n{token}\n{token}", key(1))).starts_with("more than two lines"));
+        assert!(problem(&format!("consumer-key\n{token}")).contains("'consumer-key'"));
+        assert!(problem(&format!("{}\naccess token", key(1))).contains("'access token'"));
+
+        // Named along with the file.
+        let search = Search { strict: true, ..search(false) };
+        write(&search.legacy, "consumer-key\naccess-token\n");
+        let message = search.find().err().unwrap().to_string();
+        assert!(message.contains(&search.legacy.display().to_string()) && message.contains("'consumer-key'"), "{message}");
     }
 }
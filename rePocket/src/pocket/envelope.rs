@@ -0,0 +1,112 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+
+use serde::Serialize;
+
+use super::credentials::Credentials;
+use super::error::Error;
+
+
+//
+// The body of any call with the user's credentials, the payload's fields along with them, e.g. a
+// retrieve, or a send,
+//
+//   {"consumer_key": "...", "access_token": "...", "state": "unread", "count": 10}
+//   {"consumer_key": "...", "access_token": "...", "actions": [{"action": "archive", "item_id": 123}]}
+//
+// The payload has to serialize as an object, and one of its own with either name would end up
+// twice in the body, so new() won't take it.
+//
+#[derive(Serialize)]
+pub struct Authenticated<'a, T: Serialize> {
+    #[serde(flatten)]
+    creds: &'a Credentials,
+    #[serde(flatten)]
+    payload: &'a T,
+}
+
+
+impl<'a, T: Serialize> Authenticated<'a, T> {
+    pub fn new(creds: &'a Credentials, payload: &'a T) -> Result<Self, Error> {
+        match serde_json::to_value(payload)? {
+            serde_json::Value::Object(fields) => {
+                if let Some(key) = ["consumer_key", "access_token"].into_iter().find(|key| fields.contains_key(*key)) {
+                    return Err(Error::Request(format!("the payload has its own {key}")));
+                }
+            },
+            other => return Err(Error::Request(format!("the payload isn't an object: {other}"))),
+        }
+
+        Ok(Self { creds, payload })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pocket::{PocketAction, SendRequest};
+    use crate::pocketquery::QueryBuilder;
+    use serde_json::json;
+
+    fn creds() -> Credentials {
+        Credentials {
+            consumer_key: "1234-abcd".to_string(),
+            access_token: "5678-efgh".to_string(),
+        }
+    }
+
+    #[test]
+    fn query() {
+        let creds = creds();
+        let query = QueryBuilder::default().set_count(10).set_since(1700000000).build().unwrap();
+        let expected = {
+            let mut body = json!(query);
+            body["consumer_key"] = "1234-abcd".into();
+            body["access_token"] = "5678-efgh".into();
+            body
+        };
+
+        assert_eq!(json!(Authenticated::new(&creds, &query).unwrap()), expected);
+        assert_eq!(expected["count"], 10);
+    }
+
+    #[test]
+    fn actions() {
+        let creds = creds();
+        let actions = vec![
+            PocketAction::Archive { item_id: 1 },
+            PocketAction::TagsAdd { item_id: 1, tags: "say \"hi\", \\o/".to_string() },
+        ];
+
+        assert_eq!(serde_json::to_string(&Authenticated::new(&creds, &SendRequest::new(&actions)).unwrap()).unwrap(),
+            r#"{"consumer_key":"1234-abcd","access_token":"5678-efgh","actions":[{"action":"archive","item_id":1},{"action":"tags_add","item_id":1,"tags":"say \"hi\", \\o/"}]}"#);
+        assert_eq!(json!(Authenticated::new(&creds, &SendRequest::new(&[])).unwrap()),
+            json!({"consumer_key": "1234-abcd", "access_token": "5678-efgh", "actions": []}));
+    }
+
+    #[test]
+    fn collisions() {
+        let creds = creds();
+
+        assert!(matches!(Authenticated::new(&creds, &json!({"url": "https://example.com", "consumer_key": "other"})), Err(Error::Request(_))));
+        assert!(matches!(Authenticated::new(&creds, &json!({"access_token": null})), Err(Error::Request(_))));
+        assert!(matches!(Authenticated::new(&creds, &json!(["not", "an", "object"])), Err(Error::Request(_))));
+        assert!(Authenticated::new(&creds, &json!({"url": "https://example.com"})).is_ok());
+    }
+}
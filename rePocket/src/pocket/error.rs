@@ -0,0 +1,184 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::fmt;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+
+
+//
+// What went wrong talking to Pocket. Pocket answers with the status, and says why in the X-Error
+// and X-Error-Code headers, e.g. "Invalid consumer key." and 152, and how long until it takes
+// requests again in X-Limit-User-Reset and X-Limit-Key-Reset, in seconds, once over the limit.
+//
+//   400 Invalid request, e.g. a malformed query
+//   401 Problem authenticating the user, i.e. credentials Pocket no longer takes
+//   403 Authenticated but denied, for lack of permission, or rate limiting
+//   503 Down for maintenance
+//
+#[derive(Debug)]
+pub enum Error {
+    Unauthorized(String),
+    Forbidden(String),
+    RateLimited {
+        retry_after: Option<u64>,
+    },
+    BadRequest(String),
+    ServiceUnavailable,
+    // Any other status, with the X-Error if any.
+    Status(u16, String),
+    // Pocket couldn't be reached, or the response was cut short.
+    Transport(reqwest::Error),
+    Parse(serde_json::Error),
+    // A body that can't go out as it is, see Authenticated::new().
+    Request(String),
+}
+
+
+impl Error {
+    // The error for a response, if it is one.
+    pub fn from_response(status: StatusCode, headers: &HeaderMap) -> Option<Self> {
+        if status.is_success() {
+            return None;
+        }
+
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+        let reason = header("X-Error").unwrap_or("no reason given").to_string();
+
+        let exhausted = ["X-Limit-User-Remaining", "X-Limit-Key-Remaining"].iter().any(|name| header(name) == Some("0"));
+        let retry_after = ["Retry-After", "X-Limit-User-Reset", "X-Limit-Key-Reset"].iter()
+            .filter_map(|name| header(name)?.parse::<u64>().ok())
+            .next();
+
+        Some(match status {
+            StatusCode::BAD_REQUEST => Error::BadRequest(reason),
+            StatusCode::UNAUTHORIZED => Error::Unauthorized(reason),
+            StatusCode::FORBIDDEN if exhausted => Error::RateLimited { retry_after },
+            StatusCode::TOO_MANY_REQUESTS => Error::RateLimited { retry_after },
+            // Access denied outright, the consumer key or the access token are no good.
+            StatusCode::FORBIDDEN if matches!(header("X-Error-Code"), Some("107") | Some("152")) => {
+                Error::Unauthorized(reason)
+            },
+            StatusCode::FORBIDDEN => Error::Forbidden(reason),
+            StatusCode::SERVICE_UNAVAILABLE => Error::ServiceUnavailable,
+            _ => Error::Status(status.as_u16(), reason),
+        })
+    }
+
+
+    // Worth trying again in a while, e.g. on flaky Wi-Fi, rather than a request Pocket won't take.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Transport(_) | Error::RateLimited { .. } | Error::ServiceUnavailable)
+    }
+
+
+    // The response, unless it's an error.
+    pub fn check(res: reqwest::Response) -> Result<reqwest::Response, Self> {
+        match Self::from_response(res.status(), res.headers()) {
+            Some(e) => Err(e),
+            None => Ok(res),
+        }
+    }
+}
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unauthorized(why) => write!(f, "Pocket doesn't take the credentials ({why}), run rePocketAuth again for new ones"),
+            Error::Forbidden(why) => write!(f, "Pocket denied access ({why}), the consumer key may lack a permission"),
+            Error::RateLimited { retry_after: Some(secs) } => write!(f, "Pocket's rate limit was hit, it takes requests again in {secs} s"),
+            Error::RateLimited { retry_after: None } => write!(f, "Pocket's rate limit was hit"),
+            Error::BadRequest(why) => write!(f, "Pocket didn't take the request: {why}"),
+            Error::ServiceUnavailable => write!(f, "Pocket is down for maintenance"),
+            Error::Status(status, why) => write!(f, "Pocket answered with status {status}: {why}"),
+            Error::Transport(e) => write!(f, "Could not reach Pocket: {e}"),
+            Error::Parse(e) => write!(f, "Could not make sense of Pocket's response: {e}"),
+            Error::Request(why) => write!(f, "Could not put the request together: {why}"),
+        }
+    }
+}
+
+
+impl std::error::Error for Error {}
+
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Transport(e)
+    }
+}
+
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(e)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    fn error(status: u16, headers: &[(&'static str, &'static str)]) -> Option<Error> {
+        let headers: HeaderMap = headers.iter()
+            .map(|(name, value)| (HeaderName::from_static(name), HeaderValue::from_static(value)))
+            .collect();
+
+        Error::from_response(StatusCode::from_u16(status).unwrap(), &headers)
+    }
+
+    #[test]
+    fn statuses() {
+        assert!(error(200, &[]).is_none());
+        assert!(matches!(error(400, &[("x-error", "Missing consumer key.")]), Some(Error::BadRequest(why)) if why == "Missing consumer key."));
+        assert!(matches!(error(401, &[]), Some(Error::Unauthorized(why)) if why == "no reason given"));
+        assert!(matches!(error(503, &[]), Some(Error::ServiceUnavailable)));
+        assert!(matches!(error(500, &[("x-error", "Pocket server issue.")]), Some(Error::Status(500, why)) if why == "Pocket server issue."));
+    }
+
+    #[test]
+    fn transient() {
+        for status in [429, 503] {
+            assert!(error(status, &[]).unwrap().is_transient(), "{status}");
+        }
+
+        for status in [400, 401, 403, 500] {
+            assert!(!error(status, &[]).unwrap().is_transient(), "{status}");
+        }
+    }
+
+    #[test]
+    fn forbidden() {
+        let limited = error(403, &[("x-limit-user-remaining", "0"), ("x-limit-user-reset", "1800")]);
+        assert!(matches!(limited, Some(Error::RateLimited { retry_after: Some(1800) })));
+
+        let limited = error(403, &[("x-limit-key-remaining", "0")]);
+        assert!(matches!(limited, Some(Error::RateLimited { retry_after: None })));
+
+        let limited = error(429, &[("retry-after", "60"), ("x-limit-key-reset", "900")]);
+        assert!(matches!(limited, Some(Error::RateLimited { retry_after: Some(60) })));
+
+        let revoked = error(403, &[("x-error", "Invalid consumer key."), ("x-error-code", "152")]);
+        assert!(matches!(revoked, Some(Error::Unauthorized(why)) if why == "Invalid consumer key."));
+
+        let denied = error(403, &[("x-error", "User does not have permission."), ("x-limit-user-remaining", "120")]);
+        assert!(matches!(denied, Some(Error::Forbidden(_))));
+    }
+}
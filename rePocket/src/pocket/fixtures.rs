@@ -0,0 +1,272 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::{Error, Pocket, PocketAction, PocketApi};
+use crate::mockserver::{self, Reply};
+use crate::pocketitem::{PocketItem, PocketResponse, ItemStatus};
+use crate::pocketquery::PocketQuery;
+
+
+//
+// Pocket, as far as a sync can tell, without an account or a network. For `repocket sync
+// --offline-fixtures <dir>`, and the tests. The directory holds:
+//
+//  retrieve.json   The response to every retrieve.
+//  statuses.json   The response when reconciling, optional.
+//  tagged.json     The response when looking for tagged items, whatever the tag, optional.
+//  archived.json   The response for a single page, i.e. the archive mirror, optional.
+//  articles/       The pages the items point to, and their images.
+//
+// The pages are served on 127.0.0.1, and {{fixtures}} in retrieve.json and in the pages stands for
+// where. Sends never leave the machine, they're appended to the actions log, one per line.
+//
+pub struct FixturePocket {
+    dir: PathBuf,
+    log: PathBuf,
+    base: Option<String>,
+    items_list: Vec<PocketItem>,
+    since: u64,
+}
+
+
+impl FixturePocket {
+    pub fn new(dir: &Path, log: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            log: log.to_path_buf(),
+            base: None,
+            items_list: Vec::new(),
+            since: 0,
+        }
+    }
+
+
+    // Start serving the articles the first time they're needed.
+    async fn base(&mut self) -> String {
+        if let Some(base) = &self.base {
+            return base.clone();
+        }
+
+        let mut pages = Vec::new();
+        let articles = fs::read_dir(self.dir.join("articles"))
+            .unwrap_or_else(|e| panic!("🚨 Could not read the articles in {}: {e}", self.dir.display()));
+
+        for entry in articles.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let data = fs::read(entry.path()).expect("🚨 Could not read fixture");
+            pages.push((format!("/articles/{name}"), name, data));
+        }
+
+        // The pages point at their images with {{fixtures}} too.
+        let base = mockserver::serve_with(|base| pages.into_iter()
+            .map(|(path, name, data)| (path, Self::reply(&name, &data, base)))
+            .collect()
+        ).await;
+
+        self.base = Some(base.clone());
+
+        base
+    }
+
+
+    fn reply(name: &str, data: &[u8], base: &str) -> Reply {
+        let content_type = match Path::new(name).extension().and_then(|ext| ext.to_str()) {
+            Some("html") | Some("htm") => "text/html; charset=utf-8",
+            Some("png") => "image/png",
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("pdf") => "application/pdf",
+            _ => "application/octet-stream",
+        };
+
+        match content_type.starts_with("text/") {
+            true => Reply::ok(content_type, String::from_utf8_lossy(data).replace("{{fixtures}}", base).as_bytes()),
+            false => Reply::ok(content_type, data),
+        }
+    }
+
+
+    fn read_json(&self, name: &str, base: &str) -> Option<serde_json::Value> {
+        let data = fs::read_to_string(self.dir.join(name)).ok()?;
+
+        Some(serde_json::from_str(&data.replace("{{fixtures}}", base))
+            .unwrap_or_else(|e| panic!("🚨 {name} in {} is not valid JSON: {e}", self.dir.display())))
+    }
+
+
+    fn log_actions(&self, actions: &[PocketAction]) {
+        self.log(actions.iter().map(|action| serde_json::json!(action)).collect());
+    }
+
+
+    fn log(&self, entries: Vec<serde_json::Value>) {
+        let mut fh = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log)
+            .unwrap_or_else(|e| panic!("🚨 Could not open {}: {e}", self.log.display()));
+
+        for entry in entries {
+            writeln!(fh, "{entry}").expect("🚨 Could not write to the actions log");
+        }
+    }
+}
+
+
+impl PocketApi for FixturePocket {
+    async fn fetch(&mut self, query: &PocketQuery) -> Result<(), Error> {
+        let base = self.base().await;
+        let json = self.read_json("retrieve.json", &base)
+            .unwrap_or_else(|| panic!("🚨 No retrieve.json in {}", self.dir.display()));
+
+        let response: PocketResponse = serde_json::from_value(json)?;
+
+        self.since = response.since.unwrap_or(0);
+        self.items_list = response.items().into_iter().filter(|item| query.keeps(item)).collect();
+
+        Ok(())
+    }
+
+    // A single page, as many items as there are.
+    async fn fetch_all(&mut self, query: &PocketQuery, _max: usize) -> Result<(), Error> {
+        self.fetch(query).await
+    }
+
+    fn items(&self) -> &[PocketItem] {
+        &self.items_list
+    }
+
+    fn since(&self) -> u64 {
+        self.since
+    }
+
+    async fn page(&self, _query: &PocketQuery) -> Result<Vec<PocketItem>, Error> {
+        match self.read_json("archived.json", self.base.as_deref().unwrap_or_default()) {
+            Some(json) => Ok(serde_json::from_value::<PocketResponse>(json)?.items()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn item_statuses(&self, query: &PocketQuery) -> Result<(HashMap<u64, ItemStatus>, u64), Error> {
+        let name = match query.tag() {
+            Some(_) => "tagged.json",
+            None => "statuses.json",
+        };
+
+        match self.read_json(name, self.base.as_deref().unwrap_or_default()) {
+            Some(json) => {
+                let response: PocketResponse = serde_json::from_value(json)?;
+
+                Ok((response.statuses(), response.since.unwrap_or(0)))
+            },
+            None => Ok((HashMap::new(), self.since)),
+        }
+    }
+
+    async fn send_actions(&self, actions: &[PocketAction]) -> Vec<bool> {
+        self.log_actions(actions);
+
+        vec![true; actions.len()]
+    }
+
+    async fn retag(&self, item: u64, remove: Option<String>, add: Option<String>) -> Result<(), Error> {
+        self.log_actions(&Pocket::retag_actions(item, remove, add));
+
+        Ok(())
+    }
+
+    // Logged as Pocket's own "add" action would be, no item is made for it.
+    async fn add(&self, url: &str, _title: Option<&str>, _tags: Option<Vec<String>>) -> Result<u64, Error> {
+        self.log(vec![serde_json::json!({"action": "add", "url": url})]);
+
+        Ok(0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use crate::fshandler::FSHandler;
+    use crate::fshandler::root::xochitl_root;
+    use crate::settings::Settings;
+    use crate::sync;
+    use crate::testutil::context;
+
+    const FIXTURES: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures");
+
+    #[tokio::test]
+    #[serial]
+    async fn offline_sync_end_to_end() {
+        let _ = fs::create_dir_all(xochitl_root());
+        let out = std::env::temp_dir().join("repocket-fixtures-test");
+        let _ = fs::remove_dir_all(&out);
+        fs::create_dir_all(&out).unwrap();
+
+        let log = out.join("actions.log");
+        let state = out.join("repocket.config");
+        let mut pocket = FixturePocket::new(Path::new(FIXTURES), &log);
+        let mut fhandler = FSHandler::load_from(state.to_str().unwrap()).unwrap();
+        fhandler.mark_read(4242);
+
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 1"));
+
+        let summary = sync::run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!(summary.written, 2);
+        assert_eq!(summary.failed, 0);
+        assert_eq!(summary.archived, 1);
+        assert!(summary.bytes > 0);
+        assert_eq!(fhandler.last_query_ts(), 1729763686);
+        assert_eq!(fhandler.last_reconcile_ts(), 1729763700);
+
+        let actions: Vec<serde_json::Value> = fs::read_to_string(&log).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(actions, vec![
+            serde_json::json!({"action": "archive", "item_id": 4242}),
+            serde_json::json!({"action": "tags_add", "item_id": 4242, "tags": "repocket"}),
+        ]);
+
+        // The state went next to the log, not to config_file().
+        let saved: serde_json::Value = serde_json::from_str(&fs::read_to_string(&state).unwrap()).unwrap();
+        assert_eq!(saved["ts_last_queries"]["default"], 1729763686);
+    }
+
+    #[tokio::test]
+    async fn pages_point_at_the_fixture_server() {
+        let log = std::env::temp_dir().join("repocket-fixtures-urls.log");
+        let mut pocket = FixturePocket::new(Path::new(FIXTURES), &log);
+
+        pocket.fetch(&crate::pocketquery::QueryBuilder::default().build().unwrap()).await.unwrap();
+
+        let base = pocket.base.clone().unwrap();
+        let url = pocket.items().iter()
+            .find_map(|item| item.best_url().filter(|url| url.ends_with("e-ink.html")))
+            .unwrap();
+        assert!(url.starts_with(&base));
+
+        let page = reqwest::get(&url).await.unwrap().text().await.unwrap();
+        assert!(page.contains(&format!("{base}/articles/capsules.png")));
+        assert!(!page.contains("{{fixtures}}"));
+    }
+}
@@ -14,143 +14,503 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-mod credentials;
+pub mod action;
+pub mod auth;
+pub mod credentials;
+pub mod envelope;
+pub mod error;
+#[cfg(any(test, feature = "offline-fixtures"))]
+pub mod fixtures;
+pub mod ratelimit;
 
-use crate::pocketquery::PocketQuery;
-use crate::pocketitem::PocketItem;
+use crate::pocketquery::{PocketQuery, QueryBuilder};
+use crate::pocketitem::{PocketItem, PocketResponse, ItemStatus};
+use crate::clock;
+use crate::device;
+use crate::logging;
 
-use reqwest::{StatusCode};
-use credentials::Credentials;
+use credentials::{Credentials, CredsError, Search};
 use serde_json;
-use std::fs::File;
-use std::io::Write;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-const GET_MURL: &'static str = "https://getpocket.com/v3/get";
-const MOD_MURL: &'static str = "https://getpocket.com/v3/send";
+pub use action::{AddResponse, PocketAction, SendRequest, SendResponse};
+pub use envelope::Authenticated;
+pub use error::Error;
+pub use ratelimit::RateLimit;
 
-#[cfg(not(target_abi = "eabihf"))]
-const CREDS_FILE: &'static str = env!("CREDS_FILE_HOST");
-#[cfg(target_abi = "eabihf")]
-const CREDS_FILE: &'static str = env!("CREDS_FILE_RM");
+pub const API_URL: &'static str = "https://getpocket.com/v3";
+const GET_MURL: &'static str = "/get";
+const MOD_MURL: &'static str = "/send";
+const ADD_MURL: &'static str = "/add";
+
+// The most actions Pocket takes in a single send, as per the maxActions of its responses.
+pub const MAX_ACTIONS: usize = 30;
+
+// Tries at each request, and the pause after the first failure, doubled after each one after that.
+pub const ATTEMPTS: u32 = 4;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+// Pocket asking for a longer wait than this ends the retries, the next sync will do.
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+// For a whole request, a connection Pocket took but never answers on is tried again.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+// --creds, if given, and whether a broken credentials file stops the search, see credentials.rs.
+static CREDS_SEARCH: OnceLock<(Option<String>, bool)> = OnceLock::new();
+
+
+pub fn creds_file() -> &'static str {
+    match device::kind().is_device() {
+        true => env!("CREDS_FILE_RM"),
+        false => env!("CREDS_FILE_HOST"),
+    }
+}
+
+
+// Called once, before the first Pocket::new().
+pub fn configure_credentials(flag: Option<String>, strict: bool) {
+    let _ = CREDS_SEARCH.set((flag, strict));
+}
 
 
 pub struct Pocket {
     client: reqwest::Client,
+    api: String,
     creds: Credentials,
     items_list: Vec<PocketItem>,
     since: u64,
+    // Actions per send, and the pause between sends.
+    max_actions: usize,
+    send_delay: Duration,
+    // For transient failures, see post().
+    attempts: u32,
+    retry_delay: Duration,
+    // As of the last response, see rate_limit_status().
+    rate_limit: Mutex<Option<RateLimit>>,
 }
 
 
+// What a sync needs from Pocket, so that it can be driven by something else in the tests.
+#[allow(async_fn_in_trait)]
+pub trait PocketApi {
+    // Retrieve the items matching the query.
+    async fn fetch(&mut self, query: &PocketQuery) -> Result<(), Error>;
+    // Every page of them, up to max items.
+    async fn fetch_all(&mut self, query: &PocketQuery, max: usize) -> Result<(), Error>;
+    fn items(&self) -> &[PocketItem];
+    fn since(&self) -> u64;
+    // A single page of items, on its own rather than added to items().
+    async fn page(&self, query: &PocketQuery) -> Result<Vec<PocketItem>, Error>;
+    async fn item_statuses(&self, query: &PocketQuery) -> Result<(HashMap<u64, ItemStatus>, u64), Error>;
+    // Whether Pocket confirmed each action, in the same order.
+    async fn send_actions(&self, actions: &[PocketAction]) -> Vec<bool>;
+    // Swap one tag for another, either may be missing.
+    async fn retag(&self, item: u64, remove: Option<String>, add: Option<String>) -> Result<(), Error>;
+    // Add a URL to the list, and tell the id Pocket gave the item.
+    async fn add(&self, url: &str, title: Option<&str>, tags: Option<Vec<String>>) -> Result<u64, Error>;
+
+    // How many more requests Pocket takes, if it said so, see RateLimit.
+    fn rate_limit_status(&self) -> Option<RateLimit> {
+        None
+    }
+
+    // Delete the items from the Pocket account for good, returns the ids Pocket confirmed.
+    async fn delete(&self, items: Vec<u64>) -> Vec<u64> {
+        self.item_actions(|item_id| PocketAction::Delete { item_id }, items).await
+    }
+
+    // Mark the items as favorites, or not anymore, returns the ids Pocket confirmed.
+    async fn favorite(&self, items: Vec<u64>) -> Vec<u64> {
+        self.item_actions(|item_id| PocketAction::Favorite { item_id }, items).await
+    }
+
+    async fn unfavorite(&self, items: Vec<u64>) -> Vec<u64> {
+        self.item_actions(|item_id| PocketAction::Unfavorite { item_id }, items).await
+    }
+
+    // Move archived items back to the list, returns the ids Pocket confirmed.
+    async fn readd(&self, items: Vec<u64>) -> Vec<u64> {
+        self.item_actions(|item_id| PocketAction::Readd { item_id }, items).await
+    }
+
+    // Add, remove or replace the item's tags, or take them all off, returns whether Pocket confirmed
+    // it.
+    async fn add_tags(&self, item_id: u64, tags: &[&str]) -> bool {
+        self.send_action(PocketAction::TagsAdd { item_id, tags: tags.join(",") }).await
+    }
+
+    async fn remove_tags(&self, item_id: u64, tags: &[&str]) -> bool {
+        self.send_action(PocketAction::TagsRemove { item_id, tags: tags.join(",") }).await
+    }
+
+    async fn replace_tags(&self, item_id: u64, tags: &[&str]) -> bool {
+        self.send_action(PocketAction::TagsReplace { item_id, tags: tags.join(",") }).await
+    }
+
+    async fn clear_tags(&self, item_id: u64) -> bool {
+        self.send_action(PocketAction::TagsClear { item_id }).await
+    }
+
+    async fn send_action(&self, action: PocketAction) -> bool {
+        self.send_actions(&[action]).await == [true]
+    }
+
+    // The same action for each of the items, returns the ids Pocket confirmed.
+    async fn item_actions(&self, action: fn(u64) -> PocketAction, items: Vec<u64>) -> Vec<u64> {
+        let actions: Vec<PocketAction> = items.iter().map(|&item_id| action(item_id)).collect();
+        let results = self.send_actions(&actions).await;
+
+        items.into_iter().zip(results).filter_map(|(id, ok)| ok.then_some(id)).collect()
+    }
+}
+
 impl Pocket {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, CredsError> {
+        Ok(Self::with_credentials(API_URL, Self::credentials()?))
+    }
+
+
+    // Credentials from somewhere other than the credentials file, e.g. `repocket setup` checking
+    // the ones it just got.
+    pub fn with_credentials(api: &str, creds: Credentials) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            creds: Credentials::new(CREDS_FILE),
+            client: Self::client(REQUEST_TIMEOUT),
+            api: api.to_string(),
+            creds,
             items_list: Vec::new(),
             since: 0,
+            max_actions: MAX_ACTIONS,
+            send_delay: Duration::from_secs(1),
+            attempts: ATTEMPTS,
+            retry_delay: RETRY_DELAY,
+            rate_limit: Mutex::new(None),
         }
     }
 
-    pub async fn retrieve(&self, query: &PocketQuery) -> Result<reqwest::Response, reqwest::Error> {
-        let     c: serde_json::Value = serde_json::json!(self.creds);
-        let mut q: serde_json::Value = serde_json::json!(query);
+    fn client(timeout: Duration) -> reqwest::Client {
+        reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default()
+    }
+
+    fn credentials() -> Result<Credentials, CredsError> {
+        let (flag, strict) = CREDS_SEARCH.get().cloned().unwrap_or_default();
+        let (creds, path) = Credentials::new(&Search::system(flag.as_deref(), strict))?;
+
+        println!("ℹ Using credentials from {}", path.display());
+
+        Ok(creds)
+    }
+
+    // Fewer actions per send than Pocket allows, for safety.
+    pub fn set_max_actions(mut self, max_actions: usize) -> Self {
+        self.max_actions = max_actions.clamp(1, MAX_ACTIONS);
 
-        Self::merge_values_into_hashmap(&mut q, &c);
+        self
+    }
+
+    // How many times to try each request at most, and the first pause between tries.
+    pub fn set_retries(mut self, attempts: u32, delay: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.retry_delay = delay;
+
+        self
+    }
+
+    #[cfg(test)]
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.client = Self::client(timeout);
 
-        if env!("VERBOSITY") > "0" {
-            println!("🪼 Query =>\n{:#?}", q);
+        self
+    }
+
+    // Talk to something other than getpocket.com, e.g. a local server in the tests.
+    #[cfg(test)]
+    pub fn mock(api: &str) -> Self {
+        let creds = Credentials {
+            consumer_key: "consumer-key".to_string(),
+            access_token: "access-token".to_string(),
+        };
+
+        Self {
+            send_delay: Duration::ZERO,
+            retry_delay: Duration::ZERO,
+            ..Self::with_credentials(api, creds)
         }
+    }
 
-        let msg = self.client.post(GET_MURL)
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .json(&q);
+    // The response, once it's known not to be an error, see error::Error.
+    pub async fn retrieve(&self, query: &PocketQuery) -> Result<reqwest::Response, Error> {
+        // Without the credentials.
+        if logging::debug() {
+            println!("🪼 Query =>\n{:#}", serde_json::json!(query));
+        }
 
-        msg.send().await
+        self.post(GET_MURL, &Authenticated::new(&self.creds, query)?).await
     }
 
 
-    // Can this be substituted for a trait implementation?
-    // Also, substitute the () output for something proper, like Result<_, Error>
-    pub async fn init(&mut self, val: reqwest::Response) {
-        if env!("VERBOSITY") > "0" {
-            println!("🪼 Reached init() with status {}", val.status());
+    //
+    // Post to the API, and try again as long as the failure is a transient one, see
+    // Error::is_transient(), up to the attempts set with set_retries(). Anything else, e.g.
+    // credentials Pocket doesn't take, fails right away.
+    //
+    async fn post<B: serde::Serialize>(&self, path: &str, body: &Authenticated<'_, B>) -> Result<reqwest::Response, Error> {
+        let mut attempt = 1;
+
+        loop {
+            let res = self.client.post(self.api.clone() + path)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .json(body)
+                .send()
+                .await;
+
+            // Pocket's clock is a better reference than the device's.
+            if let Some(date) = res.as_ref().ok().and_then(|res| res.headers().get(reqwest::header::DATE)?.to_str().ok()) {
+                clock::observe(&clock::SystemClock, date);
+            }
+
+            if let Some(limit) = res.as_ref().ok().and_then(|res| RateLimit::from_headers(res.headers(), clock::now())) {
+                *self.rate_limit.lock().unwrap() = Some(limit);
+            }
+
+            let e = match res.map_err(Error::from).and_then(Error::check) {
+                Err(e) if e.is_transient() && attempt < self.attempts => e,
+                res => return res,
+            };
+
+            let delay = match self.backoff(attempt, &e) {
+                Some(delay) => delay,
+                None => return Err(e),
+            };
+
+            println!("🚨 Attempt {attempt} of {} failed, {e}, trying again in {delay:.1?}", self.attempts);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
-        match val.status() {
-            StatusCode::OK => {
-                // It's a kind of magic
-                self.init_from_json(val.json().await.unwrap());
-            },
-            StatusCode::BAD_REQUEST => {
-                println!("🚨 Pocket: Invalid request, please make sure you follow the documentation for proper syntax");
-            },
-            StatusCode::UNAUTHORIZED => {
-                println!("🚨 Pocket: Problem authenticating the user");
-            },
-            StatusCode::FORBIDDEN => {
-                println!("🚨 Pocket: User was authenticated, but access denied due to lack of permission or rate limiting");
-            },
-            StatusCode::SERVICE_UNAVAILABLE => {
-                println!("🚨 Pocket: Pocket's sync server is down for scheduled maintenance");
-            },
-            _ => {
-                println!("🚨 Pocket: Unknown error encountered");
-            },
+    }
+
+
+    // As of the last response that said, errors included.
+    pub fn rate_limit_status(&self) -> Option<RateLimit> {
+        *self.rate_limit.lock().unwrap()
+    }
+
+
+    // Twice as long after each attempt, plus up to half as much again so that a few devices on the
+    // same Wi-Fi don't all come back at once, or as long as Pocket says if that's longer. None
+    // when that's longer than MAX_RETRY_WAIT.
+    fn backoff(&self, attempt: u32, e: &Error) -> Option<Duration> {
+        let delay = self.retry_delay.saturating_mul(1 << (attempt - 1).min(16));
+        let jitter = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos() as f64 / 2e9;
+        let delay = delay + delay.mul_f64(jitter);
+
+        match e {
+            Error::RateLimited { retry_after: Some(secs) } if Duration::from_secs(*secs) > MAX_RETRY_WAIT => None,
+            Error::RateLimited { retry_after: Some(secs) } => Some(delay.max(Duration::from_secs(*secs))),
+            _ => Some(delay),
         }
     }
 
 
-    // Archive one or more items.
-    pub async fn archive(&self, items: Vec<u64>) -> Result<reqwest::Response, reqwest::Error> {
-        // In the Pocket API, actions is a JSON array of "actions", not confusing at all. Anyways,
-        // what that means is that each "action" must have at least 2 fields "action": "archive"
-        // and the "item_id": _integer_.
+    //
+    // Add the items matching the query to items(), page after page, from the query's offset on and
+    // as many at a time as its count, until there are no more or max of them. Pocket may move
+    // items between pages as they're added, those seen already are left out. The "since" is the
+    // first page's, for nothing added in the meantime to be missed by the next query. Cut short at
+    // max, it's the query's own instead, the older items left on later pages are still to come.
+    //
+    pub async fn retrieve_all(&mut self, query: &PocketQuery, max: usize) -> Result<(), Error> {
+        let mut seen: HashSet<u64> = self.items_list.iter().filter_map(PocketItem::best_id).collect();
+        let mut offset = query.offset();
+        let mut since = None;
+        let mut added = 0;
+
+        while added < max {
+            let response = Self::response(self.retrieve(&query.at_offset(offset)).await?).await?;
+            let page = response.items();
+
+            since.get_or_insert(response.since);
+
+            if page.is_empty() {
+                break;
+            }
+
+            let page_len = page.len();
+            offset += page_len as u32;
+
+            for item in page {
+                if added == max {
+                    break;
+                }
 
-        // For this to work, we need a Value -> Object(Map<String, Value>)
-        // The Hash map should be:
-        // "actions": [Array]
-        // Each array item is:
-        // {
-        //      "action": "archive"
-        //      "item_id": integer
-        // }
-        // Thus, create a vector of Strings!
-        let mut actions: Vec<String> = Vec::<String>::new();
+                if !query.keeps(&item) || item.best_id().is_some_and(|id| !seen.insert(id)) {
+                    continue;
+                }
 
-        for id in items {
-            actions.push(format!(r#"{{"action": "archive", "item_id": {}}}"#, id));
+                self.items_list.push(item);
+                added += 1;
+            }
+
+            // A short page is the last one. Pocket also tells how many there are in all when asked,
+            // as a string or not.
+            let total = response.total();
+            let full = query.count().is_some_and(|count| page_len >= count as usize);
+
+            if !full || total.is_some_and(|total| offset as u64 >= total) {
+                break;
+            }
         }
 
-        // Join all entries of the vector into a single String
-        let actions = actions.join(",");
-        let actions = actions.trim_end_matches(",");
-        let actions = "[".to_string() + actions + "]";
-        let actions: serde_json::Value = serde_json::from_str(&actions).unwrap();
-        let mut actions: serde_json::Value = serde_json::json!({"actions": actions});
-        let c: serde_json::Value = serde_json::json!(self.creds);
-        Self::merge_values_into_hashmap(&mut actions, &c);
+        if added == max {
+            println!("ℹ Retrieved {max} item(s), the most there may be in a sync, the rest wait for the next one");
+            self.since = query.since();
+        } else {
+            self.since = since.flatten().unwrap_or(self.since);
+        }
+
+        Ok(())
+    }
+
 
-        let msg = self.client.post(MOD_MURL)
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .json(&actions);
+    // Get the status of every item matching the query, keyed by both item_id and resolved_id, plus
+    // the "since" of the response, see PocketResponse::statuses().
+    pub async fn item_statuses(&self, query: &PocketQuery) -> Result<(HashMap<u64, ItemStatus>, u64), Error> {
+        let response = Self::response(self.retrieve(query).await?).await?;
 
-        msg.send().await
+        Ok((response.statuses(), response.since.unwrap_or(0)))
     }
 
 
-    // Add one or more tags to an item.
-    pub async fn add_tag(&self, item: u64, tags: Vec<String>) -> Result<reqwest::Response, reqwest::Error> {
-        let tags: serde_json::Value = serde_json::json!({"action": "tags_add", "item_id": item, "tags": tags.join(",")});
-        let mut actions: serde_json::Value = serde_json::json!({"actions": [tags]});
-        let c: serde_json::Value = serde_json::json!(self.creds);
+    // One page of items, on its own rather than added to items(), e.g. for paging through the
+    // whole account with the offset.
+    pub async fn page(&self, query: &PocketQuery) -> Result<Vec<PocketItem>, Error> {
+        let response = Self::response(self.retrieve(query).await?).await?;
 
-        Self::merge_values_into_hashmap(&mut actions, &c);
+        Ok(response.items().into_iter().filter(|item| query.keeps(item)).collect())
+    }
+
+
+    //
+    // Whether Pocket takes the credentials, asking for as little as it can be asked for. Credentials
+    // turned down, whether with a 401 or a 403 that isn't about the rate limit, are
+    // Error::Unauthorized. Anything else, e.g. no network yet, says nothing about them.
+    //
+    pub async fn verify_credentials(&self) -> Result<(), Error> {
+        let query = QueryBuilder::default()
+            .set_count(1)
+            .set_detail_type("Simple")
+            .build()
+            .unwrap();
 
-        let msg = self.client.post(MOD_MURL)
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .json(&actions);
+        match self.retrieve(&query).await {
+            Ok(_) => Ok(()),
+            Err(Error::Forbidden(why)) => Err(Error::Unauthorized(why)),
+            Err(e) => Err(e),
+        }
+    }
+
+
+    // Add the items in a response from retrieve() to items().
+    pub async fn init(&mut self, val: reqwest::Response) -> Result<(), Error> {
+        if logging::debug() {
+            println!("🪼 Reached init() with status {}", val.status());
+        }
 
-        msg.send().await
+        // It's a kind of magic
+        self.init_from_json(Self::json(val).await?)
+    }
+
+
+    async fn response(res: reqwest::Response) -> Result<PocketResponse, Error> {
+        Ok(serde_json::from_value(Self::json(res).await?)?)
+    }
+
+
+    // The whole body first, so that a cut short response is told apart from one that doesn't parse.
+    async fn json(res: reqwest::Response) -> Result<serde_json::Value, Error> {
+        let body = res.bytes().await?;
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+
+    //
+    // Send the actions, whatever their kind, in as many sends as it takes, and tell which ones
+    // Pocket confirmed, in the same order. A failed send, or one whose response doesn't parse,
+    // fails every action in it, the rest go ahead.
+    //
+    pub async fn send_actions(&self, actions: &[PocketAction]) -> Vec<bool> {
+        let mut results = Vec::with_capacity(actions.len());
+
+        for (i, chunk) in actions.chunks(self.max_actions).enumerate() {
+            if i > 0 {
+                tokio::time::sleep(self.send_delay).await;
+            }
+
+            let request = SendRequest::new(chunk);
+            let response = match Authenticated::new(&self.creds, &request) {
+                Ok(body) => self.post(MOD_MURL, &body).await,
+                Err(e) => Err(e),
+            };
+            let response = match response {
+                Ok(res) => Self::json(res).await.and_then(|json| Ok(serde_json::from_value::<SendResponse>(json)?)),
+                Err(e) => Err(e),
+            };
+
+            match response {
+                Ok(response) => {
+                    let confirmed = response.confirmed(chunk.len());
+
+                    for (j, action) in chunk.iter().enumerate().filter(|(j, _)| !confirmed[*j]) {
+                        println!("🚨 Pocket didn't take {}: {}", serde_json::json!(action), response.error(j).unwrap_or("no reason given"));
+                    }
+
+                    results.extend(confirmed);
+                },
+                Err(e) => {
+                    println!("🚨 Error sending {} action(s) to Pocket: {e}", chunk.len());
+                    results.extend(vec![false; chunk.len()]);
+                },
+            }
+        }
+
+        results
+    }
+
+
+    // Add a URL to the list, and tell the id Pocket gave the item. Tags go comma separated.
+    pub async fn add(&self, url: &str, title: Option<&str>, tags: Option<Vec<String>>) -> Result<u64, Error> {
+        let mut body = serde_json::json!({"url": url});
+
+        if let Some(title) = title {
+            body["title"] = title.into();
+        }
+        if let Some(tags) = tags.filter(|tags| !tags.is_empty()) {
+            body["tags"] = tags.join(",").into();
+        }
+
+        let added: AddResponse = serde_json::from_value(Self::json(self.post(ADD_MURL, &Authenticated::new(&self.creds, &body)?).await?).await?)?;
+
+        Ok(added.item_id())
+    }
+
+
+    // In a single send, rather than tags_replace, which would take the user's other tags too.
+    pub async fn retag(&self, item: u64, remove: Option<String>, add: Option<String>) -> Result<reqwest::Response, Error> {
+        let actions = Self::retag_actions(item, remove, add);
+
+        self.post(MOD_MURL, &Authenticated::new(&self.creds, &SendRequest::new(&actions))?).await
+    }
+
+
+    pub fn retag_actions(item_id: u64, remove: Option<String>, add: Option<String>) -> Vec<PocketAction> {
+        let remove = remove.map(|tags| PocketAction::TagsRemove { item_id, tags });
+        let add = add.map(|tags| PocketAction::TagsAdd { item_id, tags });
+
+        remove.into_iter().chain(add).collect()
     }
 
 
@@ -159,7 +519,7 @@ impl Pocket {
         let mut urls = Vec::<String>::new();
 
         for item in self.items_list.iter() {
-            match item.get_resolved_url() {
+            match item.best_url() {
                 Some(url) => urls.push(url),
                 None => (),
             }
@@ -178,45 +538,78 @@ impl Pocket {
     }
 
 
-    fn init_from_json(&mut self, json: serde_json::Value) {
-        if env!("VERBOSITY") > "0" {
-            println!("🪼 Reached init_from_json()");
-            println!("🪼 {:#?}", json["list"]);
+    fn init_from_json(&mut self, json: serde_json::Value) -> Result<(), Error> {
+        if logging::debug() {
+            println!("🪼 Reached init_from_json() with {} item(s)", json["list"].as_object().map_or(0, |list| list.len()));
+        }
+        logging::dump("response", &json);
 
-            match File::create("response.json") {
-                Ok(mut fh) => {
-                    writeln!(&mut fh, "{:#?}", json).unwrap();
-                },
-                Err(err) => println!("🚨 Error!  {:?}", err),
-            };
+        let response: PocketResponse = serde_json::from_value(json)?;
+        self.items_list.extend(response.items());
+
+        // This field is undocumented, however, it is present in the json. This actually makes it
+        // all easier! Without it, the last one stays, for the next sync not to miss anything.
+        if let Some(since) = response.since {
+            self.since = since;
         }
 
+        Ok(())
+    }
+}
 
-        // This filed is undocumented, however, it is present in the json. This actually makes it
-        // all easier!
-        self.since = json["since"].as_u64().expect("Expected a timestamp");
+impl PocketApi for Pocket {
+    async fn fetch(&mut self, query: &PocketQuery) -> Result<(), Error> {
+        let res = self.retrieve(query).await?;
 
-        let map = serde_json::Map::from(json["list"].as_object().unwrap().clone());
-        for (_, v) in map.iter() {
-            if v.is_object() {
-                self.items_list.push(serde_json::from_value(v.clone())
-                    .expect("🚨 Could not convert this Value to a PocketItem"));
-            }
-        }
+        self.init(res).await?;
+        self.items_list.retain(|item| query.keeps(item));
+
+        Ok(())
+    }
+
+    // Only what this retrieve brought, as with the fixtures, a Pocket kept from one sync to the next,
+    // e.g. by the watcher, would hand out the last one's items again otherwise.
+    async fn fetch_all(&mut self, query: &PocketQuery, max: usize) -> Result<(), Error> {
+        self.items_list.clear();
+        self.retrieve_all(query, max).await
+    }
+
+    fn items(&self) -> &[PocketItem] {
+        &self.items_list
     }
 
+    fn since(&self) -> u64 {
+        Pocket::since(self)
+    }
 
-    // Take 2 serde_json::Value and modify the first argument to return a merged HashMap
-    fn merge_values_into_hashmap(vj: &mut serde_json::Value, wj: &serde_json::Value) {
-        let n = wj.as_object().unwrap();
-        let m = vj.as_object_mut().unwrap();
+    async fn page(&self, query: &PocketQuery) -> Result<Vec<PocketItem>, Error> {
+        Pocket::page(self, query).await
+    }
 
-        for (k, v) in n.iter() {
-            m.insert(k.clone(), v.clone());
-        }
+    async fn item_statuses(&self, query: &PocketQuery) -> Result<(HashMap<u64, ItemStatus>, u64), Error> {
+        Pocket::item_statuses(self, query).await
+    }
+
+    async fn send_actions(&self, actions: &[PocketAction]) -> Vec<bool> {
+        Pocket::send_actions(self, actions).await
+    }
+
+    async fn retag(&self, item: u64, remove: Option<String>, add: Option<String>) -> Result<(), Error> {
+        Pocket::retag(self, item, remove, add).await?;
+
+        Ok(())
+    }
+
+    async fn add(&self, url: &str, title: Option<&str>, tags: Option<Vec<String>>) -> Result<u64, Error> {
+        Pocket::add(self, url, title, tags).await
+    }
+
+    fn rate_limit_status(&self) -> Option<RateLimit> {
+        Pocket::rate_limit_status(self)
     }
 }
 
+
 use std::ops::Deref;
 
 impl Deref for Pocket {
@@ -226,3 +619,525 @@ impl Deref for Pocket {
         &self.items_list
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mockserver::{self, Reply};
+    use crate::testutil::{item_json, item_with, tags};
+
+    #[tokio::test]
+    async fn actions_in_chunks() {
+        let ids: Vec<u64> = (1..=70).collect();
+        let actions: Vec<PocketAction> = ids.iter().map(|&item_id| PocketAction::Archive { item_id }).collect();
+        let mut partial = vec![true; 10];
+        partial[4] = false;
+
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/send", Reply::ok("application/json", serde_json::json!({"status": 1, "action_results": vec![true; 30]}).to_string().as_bytes())),
+            ("/v3/send", Reply { status: 503, ..Reply::ok("text/plain", b"Maintenance") }),
+            ("/v3/send", Reply::ok("application/json", serde_json::json!({"status": 1, "action_results": partial}).to_string().as_bytes())),
+        ]).await;
+
+        // Not trying again, so that the failed send stays failed.
+        let results = Pocket::mock(&format!("{base}/v3")).set_retries(1, Duration::ZERO).send_actions(&actions).await;
+
+        let requests = requests.lock().unwrap();
+        let sent: Vec<Vec<u64>> = requests.iter()
+            .map(|req| serde_json::from_str::<serde_json::Value>(&req.body).unwrap())
+            .map(|body| body["actions"].as_array().unwrap().iter()
+                .inspect(|action| assert_eq!(action["action"], "archive"))
+                .map(|action| action["item_id"].as_u64().unwrap())
+                .collect())
+            .collect();
+
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent.iter().map(Vec::len).collect::<Vec<usize>>(), vec![30, 30, 10]);
+        assert_eq!(sent.concat(), ids);
+
+        // The first chunk went through, the second one failed whole, the third one but for item 65.
+        let failed: Vec<u64> = ids.iter().zip(&results).filter(|(_, ok)| !**ok).map(|(id, _)| *id).collect();
+        let mut expected: Vec<u64> = (31..=60).collect();
+        expected.push(65);
+        assert_eq!(results.len(), 70);
+        assert_eq!(failed, expected);
+    }
+
+    #[tokio::test]
+    async fn delete_items() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/send", Reply::ok("application/json", b"{\"status\": 1, \"action_results\": [true, false, true]}")),
+        ]).await;
+
+        let deleted = Pocket::mock(&format!("{base}/v3")).delete(vec![11, 12, 13]).await;
+
+        let requests = requests.lock().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["actions"], serde_json::json!([
+            {"action": "delete", "item_id": 11},
+            {"action": "delete", "item_id": 12},
+            {"action": "delete", "item_id": 13},
+        ]));
+        assert_eq!(deleted, vec![11, 13]);
+    }
+
+    #[tokio::test]
+    async fn favorite_items() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/send", Reply::ok("application/json", b"{\"status\": 1}")),
+        ]).await;
+
+        let pocket = Pocket::mock(&format!("{base}/v3"));
+        assert_eq!(pocket.favorite(vec![21, 22, 23]).await, vec![21, 22, 23]);
+        assert_eq!(pocket.unfavorite(vec![24, 25]).await, vec![24, 25]);
+        assert_eq!(pocket.readd(vec![26]).await, vec![26]);
+
+        let requests = requests.lock().unwrap();
+        let actions: Vec<serde_json::Value> = requests.iter()
+            .map(|req| serde_json::from_str::<serde_json::Value>(&req.body).unwrap()["actions"].clone())
+            .collect();
+        assert_eq!(actions, vec![
+            serde_json::json!([
+                {"action": "favorite", "item_id": 21},
+                {"action": "favorite", "item_id": 22},
+                {"action": "favorite", "item_id": 23},
+            ]),
+            serde_json::json!([
+                {"action": "unfavorite", "item_id": 24},
+                {"action": "unfavorite", "item_id": 25},
+            ]),
+            serde_json::json!([{"action": "readd", "item_id": 26}]),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn fewer_actions_per_send() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/send", Reply::ok("application/json", b"{\"status\": 1}")),
+        ]).await;
+
+        let actions: Vec<PocketAction> = (1..=5).map(|item_id| PocketAction::Archive { item_id }).collect();
+        let results = Pocket::mock(&format!("{base}/v3")).set_max_actions(4).send_actions(&actions).await;
+
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert_eq!(results, vec![true; 5]);
+    }
+
+    #[tokio::test]
+    async fn retag_in_one_send() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/send", Reply::ok("application/json", b"{\"status\": 1}")),
+        ]).await;
+        let pocket = Pocket::mock(&format!("{base}/v3"));
+
+        PocketApi::retag(&pocket, 7, Some("progress-25".to_string()), Some("progress-50".to_string())).await.unwrap();
+        PocketApi::retag(&pocket, 7, None, Some("progress-25".to_string())).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let body = serde_json::from_str::<serde_json::Value>(&requests[0].body).unwrap();
+        assert_eq!(body["actions"], serde_json::json!([
+            {"action": "tags_remove", "item_id": 7, "tags": "progress-25"},
+            {"action": "tags_add", "item_id": 7, "tags": "progress-50"},
+        ]));
+        assert_eq!(body["access_token"], "access-token");
+
+        let body = serde_json::from_str::<serde_json::Value>(&requests[1].body).unwrap();
+        assert_eq!(body["actions"], serde_json::json!([{"action": "tags_add", "item_id": 7, "tags": "progress-25"}]));
+    }
+
+    #[tokio::test]
+    async fn retrieve_from_mock_api() {
+        let response = serde_json::json!({
+            "status": 1,
+            "complete": 1,
+            "since": 1729763686,
+            "list": {
+                "100": item_json(100, "https://example.com/a"),
+                "200": item_json(200, "https://example.com/b")
+            }
+        });
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", Reply::ok("application/json", response.to_string().as_bytes())),
+        ]).await;
+
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        let query = QueryBuilder::default()
+            .set_state("Archive")
+            .set_search("rust")
+            .build()
+            .unwrap();
+
+        let res = pocket.retrieve(&query).await.unwrap();
+        pocket.init(res).await.unwrap();
+
+        assert_eq!(pocket.len(), 2);
+        assert_eq!(pocket.since(), 1729763686);
+
+        let sent: serde_json::Value = serde_json::from_str(&requests.lock().unwrap()[0].body).unwrap();
+        assert_eq!(sent["state"], "archive");
+        assert_eq!(sent["search"], "rust");
+        assert_eq!(sent["access_token"], "access-token");
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials() {
+        let rejected = Reply { status: 401, ..Reply::not_found() }
+            .header("X-Error", "Invalid access token.")
+            .header("X-Error-Code", "107");
+        let base = mockserver::serve(vec![("/v3/get", rejected)]).await;
+
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        let query = QueryBuilder::default().build().unwrap();
+
+        let error = PocketApi::fetch(&mut pocket, &query).await.unwrap_err();
+        assert!(matches!(&error, Error::Unauthorized(why) if why == "Invalid access token."), "{error:?}");
+        assert!(pocket.is_empty());
+
+        let error = Pocket::mock("http://127.0.0.1:1/gone").page(&query).await.unwrap_err();
+        assert!(matches!(error, Error::Transport(_)));
+    }
+
+    #[tokio::test]
+    async fn verify_credentials() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", Reply::ok("application/json", b"{\"status\": 1, \"list\": []}")),
+            ("/v3/get", Reply { status: 403, ..Reply::not_found() }.header("X-Error", "Access denied.")),
+            ("/v3/get", Reply { status: 401, ..Reply::not_found() }.header("X-Error", "Invalid access token.")),
+        ]).await;
+        let pocket = Pocket::mock(&format!("{base}/v3"));
+
+        assert!(pocket.verify_credentials().await.is_ok());
+        let sent: serde_json::Value = serde_json::from_str(&requests.lock().unwrap()[0].body).unwrap();
+        assert_eq!((&sent["count"], &sent["detailType"]), (&serde_json::json!(1), &serde_json::json!("simple")));
+
+        let error = pocket.verify_credentials().await.unwrap_err();
+        assert!(matches!(&error, Error::Unauthorized(why) if why == "Access denied."), "{error:?}");
+        let error = pocket.verify_credentials().await.unwrap_err();
+        assert!(matches!(&error, Error::Unauthorized(why) if why == "Invalid access token."), "{error:?}");
+
+        // Not being able to ask isn't being turned down.
+        let error = Pocket::mock("http://127.0.0.1:1/gone").verify_credentials().await.unwrap_err();
+        assert!(matches!(error, Error::Transport(_)));
+    }
+
+    #[tokio::test]
+    async fn retry_transient_failures() {
+        let list = serde_json::json!({"status": 1, "since": 1, "list": {}}).to_string();
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", Reply { status: 503, ..Reply::not_found() }),
+            ("/v3/get", Reply { status: 429, ..Reply::not_found() }),
+            ("/v3/get", Reply::ok("application/json", list.as_bytes())),
+        ]).await;
+        let query = QueryBuilder::default().build().unwrap();
+
+        assert!(Pocket::mock(&format!("{base}/v3")).page(&query).await.unwrap().is_empty());
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        // Down for longer than the attempts allow.
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", Reply { status: 503, ..Reply::not_found() }),
+        ]).await;
+        let error = Pocket::mock(&format!("{base}/v3")).set_retries(2, Duration::ZERO).page(&query).await.unwrap_err();
+        assert!(matches!(error, Error::ServiceUnavailable));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+
+        // Unreachable all along.
+        let error = Pocket::mock("http://127.0.0.1:1/gone").page(&query).await.unwrap_err();
+        assert!(matches!(error, Error::Transport(_)));
+
+        // Taking the connection, and never answering.
+        let (base, requests) = mockserver::serve_recording(vec![("/v3/get", Reply::stalled())]).await;
+        let started = std::time::Instant::now();
+        let error = Pocket::mock(&format!("{base}/v3"))
+            .set_timeout(Duration::from_millis(200))
+            .set_retries(2, Duration::ZERO)
+            .page(&query).await.unwrap_err();
+        assert!(matches!(&error, Error::Transport(e) if e.is_timeout()), "{error:?}");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn no_retry_for_the_rest() {
+        let query = QueryBuilder::default().build().unwrap();
+
+        for status in [400, 401, 403] {
+            let (base, requests) = mockserver::serve_recording(vec![
+                ("/v3/get", Reply { status, ..Reply::not_found() }),
+            ]).await;
+
+            assert!(Pocket::mock(&format!("{base}/v3")).page(&query).await.is_err());
+            assert_eq!(requests.lock().unwrap().len(), 1, "{status}");
+        }
+
+        // Pocket won't take requests for a good while, the next sync will do.
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/send", Reply { status: 429, ..Reply::not_found() }.header("Retry-After", "3600")),
+        ]).await;
+        let error = Pocket::mock(&format!("{base}/v3")).retag(1, None, Some("tag".to_string())).await.unwrap_err();
+        assert!(matches!(error, Error::RateLimited { retry_after: Some(3600) }));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_after_every_response() {
+        let list = serde_json::json!({"status": 1, "since": 1, "list": {}}).to_string();
+        let base = mockserver::serve(vec![
+            ("/v3/get", Reply::ok("application/json", list.as_bytes()).header("X-Limit-User-Remaining", "5").header("X-Limit-User-Reset", "600")),
+            ("/v3/get", Reply { status: 403, ..Reply::not_found() }.header("X-Limit-User-Remaining", "0").header("X-Limit-User-Reset", "300")),
+        ]).await;
+        let pocket = Pocket::mock(&format!("{base}/v3"));
+        let query = QueryBuilder::default().build().unwrap();
+        assert!(pocket.rate_limit_status().is_none());
+
+        pocket.page(&query).await.unwrap();
+        let limit = pocket.rate_limit_status().unwrap();
+        assert_eq!(limit.remaining, 5);
+        assert!(limit.resets_in(clock::now()) <= Duration::from_secs(600));
+
+        assert!(matches!(pocket.page(&query).await, Err(Error::RateLimited { retry_after: Some(300) })));
+        assert_eq!(pocket.rate_limit_status().unwrap().remaining, 0);
+    }
+
+    fn page(ids: &[u64], since: u64, total: Option<u64>) -> Reply {
+        let list: serde_json::Map<String, serde_json::Value> = ids.iter()
+            .map(|id| (id.to_string(), item_with(serde_json::json!({"item_id": id.to_string(), "resolved_id": id.to_string()}))))
+            .collect();
+        let mut body = match ids.is_empty() {
+            true => serde_json::json!({"status": 1, "since": since, "list": []}),
+            false => serde_json::json!({"status": 1, "since": since, "list": list}),
+        };
+
+        if let Some(total) = total {
+            body["total"] = serde_json::json!(total.to_string());
+        }
+
+        Reply::ok("application/json", body.to_string().as_bytes())
+    }
+
+    fn offsets(requests: &mockserver::Requests) -> Vec<u64> {
+        requests.lock().unwrap().iter()
+            .map(|req| serde_json::from_str::<serde_json::Value>(&req.body).unwrap()["offset"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn retrieve_every_page() {
+        let query = QueryBuilder::default().set_count(2).set_total(1).build().unwrap();
+
+        // Item 2 moved down a page, since an item was added while paging.
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", page(&[1, 2], 500, Some(5))),
+            ("/v3/get", page(&[2, 3], 600, Some(6))),
+            ("/v3/get", page(&[4, 5], 700, Some(6))),
+        ]).await;
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        pocket.retrieve_all(&query, 100).await.unwrap();
+
+        let mut ids: Vec<u64> = pocket.items().iter().filter_map(PocketItem::best_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+        assert_eq!(pocket.since(), 500);
+        assert_eq!(offsets(&requests), vec![0, 2, 4]);
+
+        // Until a page comes back empty, when Pocket doesn't say how many there are.
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", page(&[1, 2], 500, None)),
+            ("/v3/get", page(&[], 500, None)),
+        ]).await;
+        let query = QueryBuilder::default().set_count(2).build().unwrap();
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        pocket.retrieve_all(&query, 100).await.unwrap();
+        assert_eq!(pocket.items().len(), 2);
+        assert_eq!(offsets(&requests), vec![0, 2]);
+    }
+
+    #[tokio::test]
+    async fn retrieve_at_most() {
+        let query = QueryBuilder::default().set_count(2).set_total(1).build().unwrap();
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", page(&[1, 2], 500, Some(80))),
+            ("/v3/get", page(&[3, 4], 600, Some(80))),
+        ]).await;
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        pocket.retrieve_all(&query, 3).await.unwrap();
+
+        assert_eq!(pocket.items().len(), 3);
+        assert_eq!(offsets(&requests), vec![0, 2]);
+
+        // The items left out are older than the first page's since, the next query starts where
+        // this one did.
+        assert_eq!(pocket.since(), 0);
+        let base = mockserver::serve(vec![
+            ("/v3/get", page(&[1, 2], 500, Some(80))),
+            ("/v3/get", page(&[3, 4], 600, Some(80))),
+        ]).await;
+        let query = QueryBuilder::default().set_count(2).set_total(1).set_since(400).build().unwrap();
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        pocket.retrieve_all(&query, 3).await.unwrap();
+        assert_eq!(pocket.since(), 400);
+    }
+
+    #[tokio::test]
+    async fn retrieve_excluding_tags() {
+        let mut later = item_json(2, "https://example.com/2");
+        later["tags"] = tags(2, &["later"]);
+        let mut rust = item_json(3, "https://example.com/3");
+        rust["tags"] = tags(3, &["rust"]);
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", Reply::ok("application/json", serde_json::json!({
+                "status": 1, "since": 500, "list": { "1": item_json(1, "https://example.com/1"), "2": later, "3": rust },
+            }).to_string().as_bytes())),
+        ]).await;
+
+        let query = QueryBuilder::default().set_untagged().set_exclude_tags(&["later"]).build().unwrap();
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        pocket.retrieve_all(&query, 100).await.unwrap();
+
+        let mut ids: Vec<u64> = pocket.items().iter().filter_map(PocketItem::best_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 3]);
+        let sent: serde_json::Value = serde_json::from_str(&requests.lock().unwrap()[0].body).unwrap();
+        assert_eq!(sent["tag"], "_untagged_");
+        assert_eq!(sent.get("exclude_tags"), None);
+    }
+
+    #[test]
+    fn statuses_from_list() {
+        let json = serde_json::json!({
+            "status": 1,
+            "since": 1729763686,
+            "list": {
+                "100": { "item_id": "100", "resolved_id": "101", "status": "1" },
+                "200": { "item_id": "200", "status": "2" },
+                "300": { "item_id": "300", "resolved_id": "300", "status": "0" },
+                "400": { "item_id": "400", "status": "9" }
+            }
+        });
+
+        let statuses = serde_json::from_value::<PocketResponse>(json).unwrap().statuses();
+
+        assert_eq!(statuses.get(&100), Some(&ItemStatus::Archived));
+        assert_eq!(statuses.get(&101), Some(&ItemStatus::Archived));
+        assert_eq!(statuses.get(&200), Some(&ItemStatus::Deleted));
+        assert_eq!(statuses.get(&300), Some(&ItemStatus::Unread));
+        assert_eq!(statuses.get(&400), None);
+    }
+
+    #[test]
+    fn statuses_from_empty_list() {
+        let json = serde_json::json!({ "status": 2, "since": 1729763686, "list": [] });
+
+        assert!(serde_json::from_value::<PocketResponse>(json).unwrap().statuses().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tag_actions() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/send", Reply::ok("application/json", b"{\"status\": 1, \"action_results\": [true]}")),
+            ("/v3/send", Reply::ok("application/json", b"{\"status\": 1, \"action_results\": [true]}")),
+            ("/v3/send", Reply::ok("application/json", b"{\"status\": 1, \"action_results\": [false]}")),
+            ("/v3/send", Reply::ok("application/json", b"{\"status\": 1, \"action_results\": [true]}")),
+        ]).await;
+        let pocket = Pocket::mock(&format!("{base}/v3"));
+
+        assert!(pocket.add_tags(31, &["rust", "later"]).await);
+        assert!(pocket.remove_tags(31, &["to-remarkable"]).await);
+        assert!(!pocket.replace_tags(32, &["read"]).await);
+        assert!(pocket.clear_tags(33).await);
+
+        let requests = requests.lock().unwrap();
+        let sent: Vec<serde_json::Value> = requests.iter().map(|req| serde_json::from_str(&req.body).unwrap()).collect();
+        assert_eq!(sent[0], serde_json::json!({
+            "actions": [{"action": "tags_add", "item_id": 31, "tags": "rust,later"}],
+            "consumer_key": "consumer-key",
+            "access_token": "access-token",
+        }));
+        assert_eq!(sent[1]["actions"], serde_json::json!([{"action": "tags_remove", "item_id": 31, "tags": "to-remarkable"}]));
+        assert_eq!(sent[2]["actions"], serde_json::json!([{"action": "tags_replace", "item_id": 32, "tags": "read"}]));
+        assert_eq!(sent[3]["actions"], serde_json::json!([{"action": "tags_clear", "item_id": 33}]));
+    }
+
+    #[tokio::test]
+    async fn add_url() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/add", Reply::ok("application/json", b"{\"status\": 1, \"item\": {\"item_id\": \"3001\", \"normal_url\": \"http://example.com/a\"}}")),
+            ("/v3/add", Reply::ok("application/json", b"{\"status\": 1, \"item\": {\"item_id\": 3002}}")),
+            ("/v3/add", Reply::ok("application/json", b"{\"status\": 1}")),
+        ]).await;
+        let pocket = Pocket::mock(&format!("{base}/v3"));
+
+        let tags = Some(vec!["rust".to_string(), "later".to_string()]);
+        assert_eq!(pocket.add("https://example.com/a", Some("A"), tags).await.unwrap(), 3001);
+        assert_eq!(pocket.add("https://example.com/b", None, Some(vec![])).await.unwrap(), 3002);
+        assert!(matches!(pocket.add("https://example.com/c", None, None).await, Err(Error::Parse(_))));
+
+        let requests = requests.lock().unwrap();
+        let sent: Vec<serde_json::Value> = requests.iter().map(|req| serde_json::from_str(&req.body).unwrap()).collect();
+        assert_eq!(sent[0], serde_json::json!({
+            "url": "https://example.com/a",
+            "title": "A",
+            "tags": "rust,later",
+            "consumer_key": "consumer-key",
+            "access_token": "access-token",
+        }));
+        assert_eq!(sent[1].get("tags"), None);
+        assert_eq!(sent[1].get("title"), None);
+    }
+
+    const GET: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/get");
+
+    fn get_fixture(name: &str) -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(format!("{GET}/{name}")).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn response_with_items() {
+        let mut pocket = Pocket::mock("http://127.0.0.1:1/v3");
+        pocket.init_from_json(get_fixture("items.json")).unwrap();
+
+        let ids: HashSet<u64> = pocket.items().iter().filter_map(PocketItem::best_id).collect();
+        assert_eq!(ids, HashSet::from([2001, 2002]));
+        assert_eq!(pocket.since(), 1729763686);
+
+        let response: PocketResponse = serde_json::from_value(get_fixture("items.json")).unwrap();
+        assert_eq!(response.total(), Some(2));
+    }
+
+    #[test]
+    fn response_without_items() {
+        let mut pocket = Pocket::mock("http://127.0.0.1:1/v3");
+        pocket.init_from_json(get_fixture("empty.json")).unwrap();
+
+        assert!(pocket.is_empty());
+        assert_eq!(pocket.since(), 1729763690);
+    }
+
+    #[test]
+    fn response_without_since() {
+        let mut pocket = Pocket::mock("http://127.0.0.1:1/v3");
+        pocket.since = 1729763686;
+        pocket.init_from_json(get_fixture("no_since.json")).unwrap();
+
+        assert_eq!(pocket.len(), 1);
+        assert_eq!(pocket.since(), 1729763686);
+    }
+
+    #[test]
+    fn response_that_does_not_parse() {
+        let mut pocket = Pocket::mock("http://127.0.0.1:1/v3");
+
+        for json in [
+            serde_json::json!({"since": 1, "list": "none"}),
+            serde_json::json!({"since": "yesterday", "list": []}),
+            serde_json::json!({"since": 1, "list": {"1": {"item_id": "one"}}}),
+        ] {
+            let error = pocket.init_from_json(json).unwrap_err();
+            assert!(matches!(error, Error::Parse(_)), "{error:?}");
+        }
+
+        assert!(pocket.is_empty());
+        assert_eq!(pocket.since(), 0);
+    }
+}
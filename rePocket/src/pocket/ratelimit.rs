@@ -0,0 +1,84 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::time::{Duration, SystemTime};
+use reqwest::header::HeaderMap;
+
+
+//
+// How many more requests Pocket takes from the user, as its last response said in the
+// X-Limit-User-Remaining and X-Limit-User-Reset headers, the latter in seconds. Once none are
+// left Pocket answers with 403s until the reset, see error::Error::RateLimited.
+//
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub remaining: u64,
+    pub resets_at: SystemTime,
+}
+
+
+impl RateLimit {
+    // None unless the response has both headers, received at now.
+    pub fn from_headers(headers: &HeaderMap, now: SystemTime) -> Option<Self> {
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<u64>().ok();
+
+        Some(Self {
+            remaining: header("X-Limit-User-Remaining")?,
+            resets_at: now + Duration::from_secs(header("X-Limit-User-Reset")?),
+        })
+    }
+
+
+    // Whether there's room for that many requests at now, which there is once past the reset.
+    pub fn allows(&self, requests: u64, now: SystemTime) -> bool {
+        self.remaining >= requests || now >= self.resets_at
+    }
+
+
+    pub fn resets_in(&self, now: SystemTime) -> Duration {
+        self.resets_at.duration_since(now).unwrap_or_default()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers.iter()
+            .map(|(name, value)| (HeaderName::from_static(name), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn from_headers() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let limit = RateLimit::from_headers(&headers(&[("x-limit-user-remaining", "12"), ("x-limit-user-reset", "600")]), now).unwrap();
+
+        assert_eq!(limit.remaining, 12);
+        assert_eq!(limit.resets_in(now), Duration::from_secs(600));
+        assert!(limit.allows(12, now));
+        assert!(!limit.allows(13, now));
+        assert!(limit.allows(13, now + Duration::from_secs(600)));
+        assert_eq!(limit.resets_in(now + Duration::from_secs(900)), Duration::ZERO);
+
+        assert!(RateLimit::from_headers(&headers(&[("x-limit-user-remaining", "12")]), now).is_none());
+        assert!(RateLimit::from_headers(&headers(&[("x-limit-user-remaining", "lots"), ("x-limit-user-reset", "600")]), now).is_none());
+    }
+}
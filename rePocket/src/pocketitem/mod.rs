@@ -14,24 +14,91 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json;
 
-// TODO: Consider this for deletion. Right now it serves more as documentation than anything else,
-// since the only field in use, namely 'since', I actually read it directly in the pocket mod.
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+use crate::screen;
+
+//
+// A response from /v3/get, e.g.
+//
+//   {"status": 1, "complete": 1, "since": 1729763686, "total": "42", "list": {"1234": {...}}}
+//
+// Only what's used is kept. The list is keyed by item_id, and comes in as [] rather than {} when
+// there are no items. Since is undocumented, and total only there when asked for, as a string or
+// not.
+//
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct PocketResponse {
-    max_actions: usize,
-    cachetype: String,
-    status: usize,
-    complete: usize,
-    since: usize,
-    list: serde_json::Value,
+    pub since: Option<u64>,
+    total: Option<serde_json::Value>,
+    #[serde(deserialize_with = "object_or_empty_array")]
+    list: serde_json::Map<String, serde_json::Value>,
+}
+
+
+fn object_or_empty_array<'de, D>(deserializer: D) -> Result<serde_json::Map<String, serde_json::Value>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Object(list) => Ok(list),
+        serde_json::Value::Array(list) if list.is_empty() => Ok(serde_json::Map::new()),
+        other => Err(serde::de::Error::custom(format!("expected a list of items, got {other}"))),
+    }
+}
+
+
+impl PocketResponse {
+    // An item Pocket sent in a shape of its own is left out, rather than the whole response.
+    pub fn items(&self) -> Vec<PocketItem> {
+        self.list.iter()
+            .filter(|(_, item)| item.is_object())
+            .filter_map(|(id, item)| match serde_json::from_value(item.clone()) {
+                Ok(item) => Some(item),
+                Err(e) => {
+                    println!("🚨 Leaving out item id {id}, Pocket sent it as something else: {e}");
+                    None
+                },
+            })
+            .collect()
+    }
+
+
+    // The status of every item, keyed by both item_id and resolved_id. This doesn't go through
+    // PocketItem on purpose, deleted items come back with little more than their id and status.
+    pub fn statuses(&self) -> HashMap<u64, ItemStatus> {
+        let mut statuses = HashMap::new();
+
+        for (k, v) in self.list.iter() {
+            let status = match v["status"].as_str().and_then(ItemStatus::from_code) {
+                Some(status) => status,
+                None => continue,
+            };
+
+            let ids = [Some(k.as_str()), v["item_id"].as_str(), v["resolved_id"].as_str()];
+            for id in ids.into_iter().flatten().filter_map(|id| id.parse::<u64>().ok()) {
+                statuses.insert(id, status);
+            }
+        }
+
+        statuses
+    }
+
+
+    pub fn total(&self) -> Option<u64> {
+        let total = self.total.as_ref()?;
+
+        total.as_u64().or_else(|| total.as_str()?.parse().ok())
+    }
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PocketItem {
     // Note that it comes in as a string.
     item_id: U64Item,
@@ -46,9 +113,8 @@ pub struct PocketItem {
     resolved_title: Option<String>,
     // Ideally, enum like QBool, or a boolean
     favorite: U8Item,
-    // Ideally, enum { default, archived, to_delete }
     // Note that the returned json has this as a String!
-    status: Option<String>,
+    status: Option<ItemStatus>,
     excerpt: Option<String>,
     // Ideally, enum like QBool, or a boolean
     // Note that it comes in as a string.
@@ -64,17 +130,24 @@ pub struct PocketItem {
     // Note that it comes in as a string.
     word_count: U64Item,
     lang: Option<String>,
-    tags: Option<serde_json::Value>,
-    authors: Option<serde_json::Value>,
+    #[serde(default)]
+    tags: Tags,
+    #[serde(default)]
+    authors: Authors,
     images: Option<serde_json::Value>,
     videos: Option<serde_json::Value>,
+    // The image Pocket shows on the item's card, not always one of those in images.
+    top_image_url: Option<String>,
     // This is an assumption, I still haven't seen the actual format other than "0"
     //time_favorited: Option<DateTime<Local>>,
-    // This fields were not in the documentation!
-    //time_added: Option<DateTime<Local>>,
-    //time_updated: Option<DateTime<Local>>,
-    // This is an assumption, I still haven't seen the actual format other than "0"
-    //time_read: Option<DateTime<Local>>,
+    // This fields were not in the documentation! Seconds since the epoch, as a string, "0" for
+    // never, e.g. time_read for an unread item.
+    #[serde(default)]
+    time_added: TimeItem,
+    #[serde(default)]
+    time_updated: TimeItem,
+    #[serde(default)]
+    time_read: TimeItem,
     // Ideally this would be something to convert into minutes easily, if it isn't already. I
     // haven't seen it. Comes as an integer! May be minutes.
     time_to_read: Option<usize>,
@@ -90,6 +163,10 @@ impl PocketItem {
         self.resolved_url.clone()
     }
 
+    pub fn get_given_url(&self) -> Option<String> {
+        self.given_url.clone()
+    }
+
     pub fn get_resolved_id(&self) -> Option<u64> {
         match self.resolved_id.0 {
             Some(val) => Some(val as u64),
@@ -97,49 +174,292 @@ impl PocketItem {
         }
     }
 
+    // The URL to fetch, the given one until Pocket has resolved it, e.g. for a fresh save or some
+    // redirects, when the resolved one is missing or "".
+    pub fn best_url(&self) -> Option<String> {
+        self.get_resolved_url()
+            .filter(|url| !url.trim().is_empty())
+            .or_else(|| self.get_given_url().filter(|url| !url.trim().is_empty()))
+    }
+
+    // The id the item goes by, the item_id until Pocket has resolved it, when the resolved one is
+    // missing or "0".
+    pub fn best_id(&self) -> Option<u64> {
+        self.get_resolved_id()
+            .filter(|id| *id > 0)
+            .or_else(|| self.item_id.0.filter(|id| *id > 0))
+    }
+
+    // Whatever Pocket has to show for the item, the URL as a last resort.
+    pub fn get_title(&self) -> String {
+        self.resolved_title.clone()
+            .or_else(|| self.given_title.clone())
+            .filter(|title| !title.is_empty())
+            .or_else(|| self.best_url())
+            .unwrap_or_default()
+    }
+
+    // None when Pocket doesn't know, e.g. for PDFs, which it says as "0" or "".
+    pub fn get_word_count(&self) -> Option<u64> {
+        self.word_count.0.filter(|&words| words > 0)
+    }
+
+    // Pocket's guess at the language, e.g. "es", None when it made none.
+    pub fn get_lang(&self) -> Option<String> {
+        self.lang.as_deref().and_then(screen::language)
+    }
+
+    // Pocket's guesses, "2" meaning the item is an image or a video itself.
+    pub fn is_article(&self) -> bool {
+        self.is_article.0 == Some(1)
+    }
+
+    pub fn has_image(&self) -> bool {
+        self.has_image.0.unwrap_or(0) > 0
+    }
+
+    pub fn has_video(&self) -> bool {
+        self.has_video.0.unwrap_or(0) > 0
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.0.contains(tag)
+    }
+
+    pub fn get_tags(&self) -> Vec<String> {
+        self.tags.0.iter().cloned().collect()
+    }
+
+    // The names Pocket has for the authors, by author_id, nameless ones left out.
+    pub fn get_authors(&self) -> Vec<String> {
+        self.authors.0.iter()
+            .map(|author| author.name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    pub fn is_favorite(&self) -> bool {
+        self.favorite.0 == Some(1)
+    }
+
+    pub fn get_status(&self) -> Option<ItemStatus> {
+        self.status
+    }
+
+    // None when Pocket doesn't say, as with the other times.
+    pub fn get_time_added(&self) -> Option<DateTime<Utc>> {
+        self.time_added.0
+    }
+
+    #[allow(dead_code)]
+    pub fn get_time_updated(&self) -> Option<DateTime<Utc>> {
+        self.time_updated.0
+    }
+
     #[allow(dead_code)]
+    pub fn get_time_read(&self) -> Option<DateTime<Utc>> {
+        self.time_read.0
+    }
+
+    // The images Pocket lists for the item, those it says it has, but doesn't list, or lists in a
+    // shape of its own, are left out.
     pub fn get_image_refs(&self) -> Vec<Image> {
-        let mut img_list = Vec::<Image>::default();
+        if self.has_image.0 != Some(1) {
+            return Vec::new();
+        }
 
-        let has_image = self.has_image.0.unwrap_or(0);
+        self.images.as_ref()
+            .and_then(|images| images.as_object())
+            .map(|images| images.values()
+                .filter_map(|image| serde_json::from_value(image.clone()).ok())
+                .collect())
+            .unwrap_or_default()
+    }
 
-        if has_image == 1 {
-            let map = serde_json::Map::from(self.images.as_ref().expect("Expected an 'images' Object")
-                .as_object().unwrap().clone());
+    // The image Pocket picked for the item, which needn't be one of the article's own.
+    pub fn get_top_image_url(&self) -> Option<String> {
+        self.top_image_url.clone().filter(|url| !url.trim().is_empty())
+    }
 
-            for (_, v) in map.iter() {
-                if v.is_object() {
-                    img_list.push(serde_json::from_value(v.clone())
-                        .expect("🚨 Could not convert this Value to a PocketItem::Image"));
-                }
-            }
+    // The URLs Pocket would put on the cover, best first: its top image, then the images it lists
+    // by image_id, the lead one, "1", first.
+    pub fn get_cover_urls(&self) -> Vec<String> {
+        let mut refs = self.get_image_refs();
+        refs.sort_by_key(|image| (image.image_id.parse::<u64>().unwrap_or(u64::MAX), image.image_id.clone()));
+
+        self.get_top_image_url().into_iter()
+            .chain(refs.into_iter().map(|image| image.src))
+            .collect()
+    }
+}
+
+
+
+// The item's tags, which come in keyed by name, e.g. {"keep": {"item_id": "123", "tag": "keep"}},
+// and go back out the same way. Anything else, e.g. [] for none, is no tags.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tags(BTreeSet<String>);
+
+impl<'de> Deserialize<'de> for Tags {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::Object(tags) => Ok(Tags(tags.into_iter().map(|(tag, _)| tag).collect())),
+            _ => Ok(Tags::default()),
         }
+    }
+}
 
-        img_list
+impl Serialize for Tags {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(self.0.iter().map(|tag| (tag, serde_json::json!({ "tag": tag }))))
     }
 }
 
 
 
-#[derive(Debug)]
-struct U8Item(Option<u8>);
+// One of the item's authors, which come in keyed by author_id, e.g.
+// {"58129071": {"item_id": "123", "author_id": "58129071", "name": "Ada Ramos", "url": ""}}.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Author {
+    #[serde(default)]
+    pub author_id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub url: String,
+}
 
-impl<'de> Deserialize<'de> for U8Item {
+// The authors go back out keyed the same way. Anything else, e.g. [] for none, is no authors, and
+// an author in a shape of its own is left out, like images.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Authors(Vec<Author>);
+
+impl<'de> Deserialize<'de> for Authors {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let s: String = Deserialize::deserialize(deserializer)?;
-        if s.is_empty() {
-            Ok(U8Item(None))
-        } else {
-            let v = s.parse::<u8>().map_err(serde::de::Error::custom)?;
-            Ok(U8Item(Some(v)))
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::Object(authors) => Ok(Authors(authors.into_iter()
+                .filter_map(|(_, author)| serde_json::from_value(author).ok())
+                .collect())),
+            _ => Ok(Authors::default()),
+        }
+    }
+}
+
+impl Serialize for Authors {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(self.0.iter().enumerate().map(|(index, author)| {
+            let key = if author.author_id.is_empty() { index.to_string() } else { author.author_id.clone() };
+            (key, author)
+        }))
+    }
+}
+
+// The "status" of an item in Pocket, which comes in as "0", "1" or "2".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemStatus {
+    Unread,
+    Archived,
+    Deleted,
+}
+
+impl ItemStatus {
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(Self::Unread),
+            "1" => Some(Self::Archived),
+            "2" => Some(Self::Deleted),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unread => "0",
+            Self::Archived => "1",
+            Self::Deleted => "2",
         }
     }
 }
 
+impl<'de> Deserialize<'de> for ItemStatus {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        ItemStatus::from_code(&s).ok_or_else(|| serde::de::Error::custom(format!("unknown status {s:?}")))
+    }
+}
+
+impl Serialize for ItemStatus {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.code())
+    }
+}
+
+
+
+// Most numbers come in as strings, but now and then Pocket sends one as a number, and none at all
+// as "" or null.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrNumber {
+    String(String),
+    Number(u64),
+}
+
+fn number<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<StringOrNumber>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(StringOrNumber::Number(n)) => Ok(Some(n)),
+        Some(StringOrNumber::String(s)) if s.is_empty() => Ok(None),
+        Some(StringOrNumber::String(s)) => s.parse::<u64>().map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+
 #[derive(Debug, Clone)]
+struct U8Item(Option<u8>);
+
+impl<'de> Deserialize<'de> for U8Item {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let v = number(deserializer)?.map(u8::try_from).transpose().map_err(serde::de::Error::custom)?;
+        Ok(U8Item(v))
+    }
+}
+
+// Back as the string it came in as, e.g. for the in-flight batch in the sync module.
+impl Serialize for U8Item {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0.map(|v| v.to_string()).unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 struct U64Item(Option<u64>);
 
 impl<'de> Deserialize<'de> for U64Item {
@@ -147,13 +467,43 @@ impl<'de> Deserialize<'de> for U64Item {
     where
         D: Deserializer<'de>,
     {
-        let s: String = Deserialize::deserialize(deserializer)?;
-        if s.is_empty() {
-            Ok(U64Item(None))
-        } else {
-            let v = s.parse::<u64>().map_err(serde::de::Error::custom)?;
-            Ok(U64Item(Some(v)))
-        }
+        Ok(U64Item(number(deserializer)?))
+    }
+}
+
+impl Serialize for U64Item {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0.map(|v| v.to_string()).unwrap_or_default())
+    }
+}
+
+// Seconds since the epoch, as a string, with "0", or nothing, for no time at all.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct TimeItem(Option<DateTime<Utc>>);
+
+impl<'de> Deserialize<'de> for TimeItem {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let secs = U64Item::deserialize(deserializer)?;
+
+        Ok(TimeItem(secs.0
+            .filter(|secs| *secs > 0)
+            .and_then(|secs| i64::try_from(secs).ok())
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))))
+    }
+}
+
+impl Serialize for TimeItem {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0.map_or(0, |time| time.timestamp()).to_string())
     }
 }
 
@@ -169,3 +519,230 @@ pub struct Image {
     caption: String,
     credit: String,
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use serde_json::{json, Value};
+    use crate::testutil::pocket_item;
+
+    // A few per test run, many more with the fuzz feature.
+    const CASES: u32 = if cfg!(feature = "fuzz") { 100_000 } else { 256 };
+
+    // The fields seen coming from Pocket, documented or not.
+    const FIELDS: [&str; 32] = [
+        "item_id", "resolved_id", "resolved_id_str", "sort_id", "given_url", "resolved_url",
+        "given_title", "resolved_title", "favorite", "status", "excerpt", "is_article", "is_index",
+        "has_image", "has_video", "word_count", "lang", "tags", "authors", "images", "videos",
+        "time_added", "time_updated", "time_read", "time_favorited", "time_to_read",
+        "listen_duration_estimate", "top_image_url", "domain_metadata", "amp_url", "image", "listen",
+    ];
+
+    fn value() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            Just(Value::Null),
+            Just(json!("")),
+            Just(json!({})),
+            Just(json!([])),
+            any::<bool>().prop_map(Value::from),
+            any::<u64>().prop_map(|n| json!(n.to_string())),
+            any::<u64>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<f64>().prop_map(Value::from),
+            ".{0,12}".prop_map(Value::from),
+            (".{0,6}", ".{0,6}").prop_map(|(k, v)| json!({ k: { "src": v, "image_id": v } })),
+        ]
+    }
+
+    fn item_json() -> impl Strategy<Value = Value> {
+        prop::collection::btree_map(prop::sample::select(FIELDS.to_vec()), value(), 0..FIELDS.len())
+            .prop_map(|fields| Value::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect()))
+    }
+
+    #[test]
+    fn status() {
+        assert_eq!(pocket_item(json!({})).get_status(), None);
+        assert_eq!(pocket_item(json!({"status": "0"})).get_status(), Some(ItemStatus::Unread));
+        assert_eq!(pocket_item(json!({"status": "1"})).get_status(), Some(ItemStatus::Archived));
+        assert_eq!(pocket_item(json!({"status": "2"})).get_status(), Some(ItemStatus::Deleted));
+        assert!(serde_json::from_value::<PocketItem>(json!({"item_id": "1", "status": "3"})).is_err());
+
+        // Back as it came in, for the state file.
+        let archived = serde_json::to_value(pocket_item(json!({"status": "1"}))).unwrap();
+        assert_eq!(archived["status"], "1");
+    }
+
+    // A save Pocket hasn't resolved yet.
+    #[test]
+    fn unresolved() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/unresolved.json");
+        let saved: PocketItem = serde_json::from_str(&std::fs::read_to_string(fixture).unwrap()).unwrap();
+
+        assert_eq!(saved.best_id(), Some(4012589363));
+        assert_eq!(saved.best_url().as_deref(), Some("https://example.com/just-saved"));
+        assert_eq!(saved.get_title(), "https://example.com/just-saved");
+
+        let resolved = pocket_item(json!({"item_id": "1", "resolved_id": "2", "given_url": "https://example.com/a", "resolved_url": "https://example.com/b"}));
+        assert_eq!((resolved.best_id(), resolved.best_url().as_deref()), (Some(2), Some("https://example.com/b")));
+
+        let nothing = pocket_item(json!({"item_id": "0", "resolved_id": "", "given_url": "", "resolved_url": " "}));
+        assert_eq!((nothing.best_id(), nothing.best_url()), (None, None));
+    }
+
+    #[test]
+    fn numbers() {
+        for (value, expected) in [(json!("1234"), Some(1234)), (json!(1234), Some(1234)), (json!(""), None), (Value::Null, None)] {
+            let item = pocket_item(json!({"item_id": value, "resolved_id": value, "word_count": value, "favorite": value}));
+            assert_eq!(item.get_resolved_id(), expected, "{value}");
+            assert_eq!(item.get_word_count(), expected, "{value}");
+        }
+
+        let favorite = |value: Value| serde_json::from_value::<U8Item>(value).map(|item| item.0);
+        assert_eq!(favorite(json!("1")).unwrap(), Some(1));
+        assert_eq!(favorite(json!(1)).unwrap(), Some(1));
+        assert_eq!(favorite(json!("")).unwrap(), None);
+        assert_eq!(favorite(Value::Null).unwrap(), None);
+        for wrong in [json!(256), json!(-1), json!("one"), json!(1.5), json!(true), json!([])] {
+            assert!(favorite(wrong.clone()).is_err(), "{wrong}");
+        }
+    }
+
+    #[test]
+    fn malformed_items() {
+        let response: PocketResponse = serde_json::from_value(json!({"status": 1, "list": {
+            "1": {"item_id": 1, "resolved_id": "1", "favorite": "0", "is_article": "1", "is_index": "0", "has_image": "0", "has_video": "0", "word_count": 100},
+            "2": {"item_id": "2", "resolved_id": "2", "favorite": "zero", "is_article": "1", "is_index": "0", "has_image": "0", "has_video": "0", "word_count": "1"},
+            "3": {"item_id": "3"},
+        }})).unwrap();
+
+        let ids: Vec<Option<u64>> = response.items().iter().map(PocketItem::get_resolved_id).collect();
+        assert_eq!(ids, vec![Some(1)]);
+    }
+
+    #[test]
+    fn tags() {
+        let tagged = pocket_item(json!({"tags": {
+            "later": {"item_id": "1", "tag": "later"},
+            "rust": {"item_id": "1", "tag": "rust"},
+        }}));
+        assert_eq!(tagged.get_tags(), vec!["later", "rust"]);
+        assert!(tagged.has_tag("later") && !tagged.has_tag("Later"));
+
+        for none in [json!({}), json!({"tags": []}), json!({"tags": null}), json!({"tags": ""})] {
+            assert!(pocket_item(none).get_tags().is_empty());
+        }
+
+        // Back as they came in, for the state file.
+        let saved: PocketItem = serde_json::from_value(serde_json::to_value(&tagged).unwrap()).unwrap();
+        assert_eq!(saved.get_tags(), tagged.get_tags());
+    }
+
+    #[test]
+    fn times() {
+        let added = DateTime::from_timestamp(1521560000, 0);
+
+        let saved = pocket_item(json!({"time_added": "1521560000", "time_updated": "1521560400", "time_read": "0"}));
+        assert_eq!(saved.get_time_added(), added);
+        assert_eq!(saved.get_time_updated(), DateTime::from_timestamp(1521560400, 0));
+        assert_eq!(saved.get_time_read(), None);
+
+        let untimed = pocket_item(json!({}));
+        assert_eq!((untimed.get_time_added(), untimed.get_time_updated(), untimed.get_time_read()), (None, None, None));
+        assert_eq!(pocket_item(json!({"time_added": ""})).get_time_added(), None);
+        let mut garbled = serde_json::to_value(&untimed).unwrap();
+        garbled["time_added"] = json!("yesterday");
+        assert!(serde_json::from_value::<PocketItem>(garbled).is_err());
+
+        // Back as they came in, for the state file, "0" for none.
+        let json = serde_json::to_value(&saved).unwrap();
+        assert_eq!((&json["time_added"], &json["time_read"]), (&json!("1521560000"), &json!("0")));
+        let again: PocketItem = serde_json::from_value(json).unwrap();
+        assert_eq!((again.get_time_added(), again.get_time_read()), (added, None));
+    }
+
+    #[test]
+    fn authors() {
+        let authors = pocket_item(json!({"authors": {
+            "2": {"item_id": "1", "author_id": "2", "name": " Lin Okafor ", "url": ""},
+            "1": {"item_id": "1", "author_id": "1", "name": "Ada Ramos", "url": "https://example.com/ada"},
+            "3": {"item_id": "1", "author_id": "3", "name": ""},
+            "4": {"name": 4},
+        }}));
+        assert_eq!(authors.get_authors(), vec!["Ada Ramos", "Lin Okafor"]);
+
+        for none in [json!({}), json!({"authors": []}), json!({"authors": null}), json!({"authors": ""})] {
+            assert!(pocket_item(none).get_authors().is_empty());
+        }
+
+        // Back as they came in, for the state file.
+        let saved: PocketItem = serde_json::from_value(serde_json::to_value(&authors).unwrap()).unwrap();
+        assert_eq!(saved.get_authors(), authors.get_authors());
+    }
+
+    // An item as Pocket sends it, with the fields the documentation leaves out.
+    #[test]
+    fn fixture() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/item.json");
+        let item: PocketItem = serde_json::from_str(&std::fs::read_to_string(fixture).unwrap()).unwrap();
+
+        assert_eq!(item.get_tags(), vec!["displays", "history"]);
+        assert_eq!(item.get_authors(), vec!["Ada Ramos", "Lin Okafor"]);
+        assert_eq!(item.get_title(), "A short history of e-ink");
+        assert_eq!(item.get_word_count(), Some(2214));
+        assert_eq!(item.get_time_added(), DateTime::from_timestamp(1521560000, 0));
+        assert_eq!(item.get_time_read(), None);
+        assert_eq!(item.get_image_refs().len(), 1);
+    }
+
+    #[test]
+    fn image_refs() {
+        assert!(pocket_item(json!({})).get_image_refs().is_empty());
+        assert!(pocket_item(json!({"images": []})).get_image_refs().is_empty());
+
+        let images = json!({"images": {
+            "1": {"item_id": "1", "image_id": "1", "src": "https://example.com/a.png", "width": "0", "height": "0", "caption": "", "credit": ""},
+            "2": {"src": 2},
+        }});
+        let refs = pocket_item(images).get_image_refs();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].src, "https://example.com/a.png");
+    }
+
+    #[test]
+    fn cover_urls() {
+        assert!(pocket_item(json!({"top_image_url": ""})).get_cover_urls().is_empty());
+        assert_eq!(pocket_item(json!({"top_image_url": " "})).get_top_image_url(), None);
+
+        let image = |id: &str| json!({"item_id": "1", "image_id": id, "src": format!("https://example.com/{id}.png"),
+            "width": "0", "height": "0", "caption": "", "credit": ""});
+        let listed = json!({"images": {"10": image("10"), "2": image("2"), "1": image("1")}});
+        assert_eq!(pocket_item(listed.clone()).get_cover_urls(), vec![
+            "https://example.com/1.png", "https://example.com/2.png", "https://example.com/10.png",
+        ]);
+
+        let mut topped = listed;
+        topped["top_image_url"] = json!("https://example.com/top.jpg");
+        assert_eq!(pocket_item(topped).get_cover_urls()[..2], ["https://example.com/top.jpg", "https://example.com/1.png"]);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+        // Whatever shape the fields come in, an error at worst, and nothing a PocketItem answers
+        // panics either.
+        #[test]
+        fn any_item_json(fields in item_json()) {
+            if let Ok(item) = serde_json::from_value::<PocketItem>(fields) {
+                let _ = (item.get_resolved_url(), item.get_given_url(), item.get_resolved_id(), item.get_title());
+                let _ = (item.best_url(), item.best_id());
+                let _ = (item.get_word_count(), item.get_lang(), item.is_article(), item.has_image(), item.has_video());
+                let _ = (item.has_tag("keep"), item.get_tags(), item.is_favorite(), item.get_status(), item.get_time_added());
+                let _ = (item.get_time_updated(), item.get_time_read());
+                let _ = (item.get_image_refs(), item.get_cover_urls(), item.get_authors());
+                let _ = serde_json::to_value(&item);
+            }
+        }
+    }
+}
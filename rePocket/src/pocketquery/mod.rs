@@ -14,43 +14,68 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use chrono::{DateTime, Local};
+use std::fmt;
 use std::str::FromStr;
 use strum_macros::{EnumString, FromRepr};
-use serde::{Serialize};
+use serde::{Serialize, Serializer};
 use serde_repr::*;
 
+use crate::pocketitem::PocketItem;
 
-#[derive(Debug, PartialEq, Serialize, EnumString)]
+// What Pocket takes as the tag for items without any.
+const UNTAGGED: &str = "_untagged_";
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, EnumString)]
 #[serde(rename_all = "lowercase")]
+#[strum(ascii_case_insensitive)]
 pub enum QState {
     Unread,
     Archive,
     All,
 }
 
-#[derive(Debug, PartialEq, Serialize_repr, FromRepr)]
+#[derive(Clone, Debug, PartialEq, Serialize_repr, FromRepr)]
 #[repr(u8)]
 pub enum QBool {
     No = 0,
     Yes,
 }
 
-#[derive(Debug, PartialEq, Serialize, EnumString)]
+#[derive(Clone, Debug, PartialEq, EnumString)]
 pub enum QTag {
     Tag(String),
     Untagged,
 }
 
-#[derive(Debug, PartialEq, Serialize, EnumString)]
+impl QTag {
+    fn as_str(&self) -> &str {
+        match self {
+            QTag::Tag(tag) => tag,
+            QTag::Untagged => UNTAGGED,
+        }
+    }
+}
+
+impl Serialize for QTag {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, EnumString)]
 #[serde(rename_all = "lowercase")]
+#[strum(ascii_case_insensitive)]
 pub enum QContentType {
     Article,
     Video,
     Image,
 }
 
-#[derive(Debug, PartialEq, Serialize, EnumString)]
+#[derive(Clone, Debug, PartialEq, Serialize, EnumString)]
 #[serde(rename_all = "lowercase")]
+#[strum(ascii_case_insensitive)]
 pub enum QSort {
     Newest,
     Oldest,
@@ -58,22 +83,23 @@ pub enum QSort {
     Site,
 }
 
-#[derive(Debug, PartialEq, Serialize, EnumString)]
+#[derive(Clone, Debug, PartialEq, Serialize, EnumString)]
 #[serde(rename_all = "lowercase")]
+#[strum(ascii_case_insensitive)]
 pub enum QDetailType {
     Simple,
     Complete,
 }
 
 
-#[derive(Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct PocketQuery {
     #[serde(skip_serializing_if = "Option::is_none")]
     state: Option<QState>,
     #[serde(skip_serializing_if = "Option::is_none")]
     favorite: Option<QBool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    tag: Option<String>,
+    tag: Option<QTag>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename(serialize = "contentType"))]
     content_type: Option<QContentType>,
@@ -86,7 +112,8 @@ pub struct PocketQuery {
     search: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     domain: Option<String>,
-    // Although Pocket expects an integer, perhaps we can make it nice, like a DateTime<Local>
+    // Seconds since the epoch, see QueryBuilder::set_since_datetime().
+    #[serde(skip_serializing_if = "Option::is_none")]
     since: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     count: Option<u8>,
@@ -94,6 +121,9 @@ pub struct PocketQuery {
     offset: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     total: Option<QBool>,
+    // Left out once retrieved, Pocket has no way to, see keeps().
+    #[serde(skip)]
+    exclude_tags: Vec<String>,
 }
 
 
@@ -101,7 +131,7 @@ impl PocketQuery {
     pub fn new(
         state: Option<QState>,
         favorite: Option<QBool>,
-        tag: Option<String>,
+        tag: Option<QTag>,
         content_type: Option<QContentType>,
         sort: Option<QSort>,
         detail_type: Option<QDetailType>,
@@ -111,8 +141,8 @@ impl PocketQuery {
         count: Option<u8>,
         offset: Option<u32>,
         total: Option<QBool>,
-    ) -> Result<Self, ()> {
-        let query = Self {
+    ) -> Self {
+        Self {
             state,
             favorite,
             tag,
@@ -125,9 +155,43 @@ impl PocketQuery {
             count,
             offset,
             total,
-        };
+            exclude_tags: Vec::new(),
+        }
+    }
 
-        Ok(query)
+
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_ref().map(QTag::as_str)
+    }
+
+
+    // Whether the item is one the query asked for, as far as the tags it leaves out go.
+    pub fn keeps(&self, item: &PocketItem) -> bool {
+        !self.exclude_tags.iter().any(|tag| item.has_tag(tag))
+    }
+
+
+    pub fn count(&self) -> Option<u8> {
+        self.count
+    }
+
+
+    pub fn offset(&self) -> u32 {
+        self.offset.unwrap_or(0)
+    }
+
+
+    pub fn since(&self) -> u64 {
+        self.since.unwrap_or(0)
+    }
+
+
+    // The same query, from another item on, to page through the results.
+    pub fn at_offset(&self, offset: u32) -> Self {
+        Self {
+            offset: Some(offset),
+            ..self.clone()
+        }
     }
 }
 
@@ -136,7 +200,7 @@ impl PocketQuery {
 pub struct QueryBuilder {
     state: Option<QState>,
     favorite: Option<QBool>,
-    tag: Option<String>,
+    tag: Option<QTag>,
     content_type: Option<QContentType>,
     sort: Option<QSort>,
     detail_type: Option<QDetailType>,
@@ -146,43 +210,71 @@ pub struct QueryBuilder {
     count: Option<u8>,
     offset: Option<u32>,
     total: Option<QBool>,
+    exclude_tags: Vec<String>,
+    // What didn't parse, by field, for build() to turn down.
+    invalid: Vec<(&'static str, String)>,
+}
+
+
+// Every value a QueryBuilder was given that Pocket wouldn't take, e.g. a misspelt state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryError {
+    pub invalid: Vec<(&'static str, String)>,
 }
 
 impl QueryBuilder {
     pub fn set_state(mut self, state: &str) -> Self {
-        self.state = Some(QState::from_str(state).unwrap());
+        self.state = self.parse("state", state);
 
         self
     }
 
     pub fn set_favorite(mut self, favorite: u8) -> Self {
-        self.favorite = QBool::from_repr(favorite);
+        self.favorite = self.flag("favorite", favorite);
 
         self
     }
 
-    #[allow(dead_code)]
+    // Pocket's own name for no tags is taken too, see set_untagged().
     pub fn set_tag(mut self, tag: &str) -> Self {
-        self.tag = Some(tag.to_string());
+        self.tag = match tag {
+            UNTAGGED => Some(QTag::Untagged),
+            tag => Some(QTag::Tag(tag.to_string())),
+        };
+
+        self
+    }
+
+    // Only the items without any tags.
+    #[allow(dead_code)]
+    pub fn set_untagged(mut self) -> Self {
+        self.tag = Some(QTag::Untagged);
+
+        self
+    }
+
+    // Items with any of these tags are left out, once retrieved.
+    pub fn set_exclude_tags<T: AsRef<str>>(mut self, tags: &[T]) -> Self {
+        self.exclude_tags = tags.iter().map(|tag| tag.as_ref().to_string()).collect();
 
         self
     }
 
     #[allow(dead_code)]
     pub fn set_content_type(mut self, content_type: &str) -> Self {
-        self.content_type = Some(QContentType::from_str(content_type).unwrap());
+        self.content_type = self.parse("contentType", content_type);
 
         self
     }
 
     pub fn set_sort(mut self, sort: &str) -> Self {
-        self.sort = Some(QSort::from_str(sort).unwrap());
+        self.sort = self.parse("sort", sort);
 
         self
     }
 
     pub fn set_detail_type(mut self, detail_type: &str) -> Self {
-        self.detail_type = Some(QDetailType::from_str(detail_type).unwrap());
+        self.detail_type = self.parse("detailType", detail_type);
 
         self
     }
@@ -201,13 +293,18 @@ impl QueryBuilder {
         self
     }
 
-    // Perhaps timestamp should be of a type similar to whatever the crate chrono uses.
     pub fn set_since(mut self, timestamp: u64) -> Self {
         self.since = Some(timestamp);
 
         self
     }
 
+    // The same, as the seconds since the epoch Pocket takes. Anything before it is the epoch.
+    #[allow(dead_code)]
+    pub fn set_since_datetime(self, datetime: DateTime<Local>) -> Self {
+        self.set_since(datetime.timestamp().max(0) as u64)
+    }
+
     // Truncates at 30, as per the Pocket API
     pub fn set_count(mut self, count: u8) -> Self {
         self.count = Some(std::cmp::min(count, 30));
@@ -222,13 +319,17 @@ impl QueryBuilder {
     }
 
     pub fn set_total(mut self, total: u8) -> Self {
-        self.total = QBool::from_repr(total);
+        self.total = self.flag("total", total);
 
         self
     }
 
-    pub fn build(self) -> Result<PocketQuery, ()> {
-        PocketQuery::new(
+    pub fn build(self) -> Result<PocketQuery, QueryError> {
+        if !self.invalid.is_empty() {
+            return Err(QueryError { invalid: self.invalid });
+        }
+
+        let mut query = PocketQuery::new(
             self.state,
             self.favorite,
             self.tag,
@@ -241,14 +342,75 @@ impl QueryBuilder {
             self.count,
             self.offset,
             self.total,
-        )   
+        );
+        query.exclude_tags = self.exclude_tags;
+
+        Ok(query)
+    }
+
+
+    fn parse<T: FromStr>(&mut self, field: &'static str, value: &str) -> Option<T> {
+        let parsed = T::from_str(value).ok();
+
+        if parsed.is_none() {
+            self.invalid.push((field, value.to_string()));
+        }
+
+        parsed
+    }
+
+
+    // 0 or 1, anything else isn't left out but turned down.
+    fn flag(&mut self, field: &'static str, value: u8) -> Option<QBool> {
+        let flag = QBool::from_repr(value);
+
+        if flag.is_none() {
+            self.invalid.push((field, value.to_string()));
+        }
+
+        flag
     }
 }
 
 
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let invalid: Vec<String> = self.invalid.iter()
+            .map(|(field, value)| format!("{field} '{value}'"))
+            .collect();
+
+        write!(f, "Pocket wouldn't take the query, invalid {}", invalid.join(", "))
+    }
+}
+
+
+impl std::error::Error for QueryError {}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testutil::{pocket_item, tagged};
+
+    #[test]
+    fn nothing_set() {
+        let query = QueryBuilder::default().build().unwrap();
+
+        assert_eq!(serde_json::to_string(&query).unwrap(), "{}");
+    }
+
+    #[test]
+    fn since_datetime() {
+        use chrono::TimeZone;
+
+        let datetime = Local.timestamp_opt(1700000000, 0).unwrap();
+        let query = QueryBuilder::default().set_since_datetime(datetime).build().unwrap();
+        assert_eq!(query.since, Some(1700000000));
+        assert_eq!(serde_json::json!(query), serde_json::json!({"since": 1700000000}));
+
+        let before = Local.timestamp_opt(-3600, 0).unwrap();
+        assert_eq!(QueryBuilder::default().set_since_datetime(before).build().unwrap().since, Some(0));
+    }
 
     #[test]
     fn build_query_using_default_builder() {
@@ -289,7 +451,7 @@ mod tests {
 
         assert_eq!(query.state, Some(QState::Unread));
         assert_eq!(query.favorite, Some(QBool::No));
-        assert_eq!(query.tag, Some("rust".to_string()));
+        assert_eq!(query.tag, Some(QTag::Tag("rust".to_string())));
         assert_eq!(query.content_type, Some(QContentType::Article));
         assert_eq!(query.sort, Some(QSort::Newest));
         assert_eq!(query.detail_type, Some(QDetailType::Simple));
@@ -300,4 +462,52 @@ mod tests {
         assert_eq!(query.offset, Some(0));
         assert_eq!(query.total, Some(QBool::Yes));
     }
+
+    #[test]
+    fn invalid_values() {
+        let invalid = |builder: QueryBuilder| builder.build().unwrap_err().invalid;
+
+        assert_eq!(invalid(QueryBuilder::default().set_state("Unred")), vec![("state", "Unred".to_string())]);
+        assert_eq!(invalid(QueryBuilder::default().set_content_type("article ")), vec![("contentType", "article ".to_string())]);
+        assert_eq!(invalid(QueryBuilder::default().set_sort("")), vec![("sort", "".to_string())]);
+        assert_eq!(invalid(QueryBuilder::default().set_detail_type("Full")), vec![("detailType", "Full".to_string())]);
+        assert_eq!(invalid(QueryBuilder::default().set_favorite(7)), vec![("favorite", "7".to_string())]);
+        assert_eq!(invalid(QueryBuilder::default().set_total(2)), vec![("total", "2".to_string())]);
+
+        // Every one of them, in the order they were set, and the rest still counts for nothing.
+        let error = QueryBuilder::default()
+            .set_sort("Newst")
+            .set_count(5)
+            .set_state("Archive")
+            .set_favorite(3)
+            .build()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Pocket wouldn't take the query, invalid sort 'Newst', favorite '3'");
+    }
+
+    #[test]
+    fn untagged_and_excluded() {
+        let untagged = QueryBuilder::default().set_untagged().build().unwrap();
+        assert_eq!(serde_json::to_value(&untagged).unwrap()["tag"], "_untagged_");
+        assert_eq!(QueryBuilder::default().set_tag("_untagged_").build().unwrap().tag, Some(QTag::Untagged));
+        assert_eq!(serde_json::to_value(QueryBuilder::default().set_tag("rust").build().unwrap()).unwrap()["tag"], "rust");
+
+        let query = QueryBuilder::default().set_exclude_tags(&["later"]).build().unwrap();
+
+        assert!(!query.keeps(&tagged(1, "https://example.com/article", &["later"])));
+        assert!(query.keeps(&tagged(1, "https://example.com/article", &["rust"])));
+        assert!(query.keeps(&pocket_item(serde_json::json!({"tags": []}))));
+        // Never sent to Pocket.
+        assert_eq!(serde_json::to_value(&query).unwrap().get("exclude_tags"), None);
+    }
+
+    #[test]
+    fn next_page() {
+        let query = QueryBuilder::default().set_state("Unread").set_count(30).build().unwrap();
+        let next = query.at_offset(30);
+
+        assert_eq!(query.offset(), 0);
+        assert_eq!(next.offset(), 30);
+        assert_eq!((next.state, next.count()), (Some(QState::Unread), Some(30)));
+    }
 }
@@ -0,0 +1,88 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use serde::Deserialize;
+
+use crate::fshandler;
+
+#[cfg(feature = "remote-push")]
+mod push;
+
+#[cfg(feature = "remote-push")]
+pub use push::{Companion, Ssh};
+
+
+//
+// Companion mode: the whole sync runs on a home server, into a staging directory standing in for
+// xochitl's, and the reMarkable only gets the finished files. Before a sync, the .metadata files
+// are pulled from the device, so that consolidation sees what was read. After it, instead of
+// restarting xochitl, whatever changed in the staging directory is pushed to the device, and
+// xochitl is restarted over there, see Companion.
+//
+// It's set up in the settings, e.g.
+//
+// [remote]
+// host = "192.168.1.20"
+// key = "/home/me/.ssh/remarkable"
+// method = "rsync"
+//
+// Pushing is only there with the remote-push feature, see push.rs. The settings are read without
+// it too, so that a sync can say it's not compiled in.
+//
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+#[cfg_attr(not(feature = "remote-push"), allow(dead_code))]
+pub struct Remote {
+    pub host: String,
+    pub user: String,
+    pub port: u16,
+    // The private key to log in with, ssh's own choice if unset.
+    pub key: Option<String>,
+    pub method: Method,
+    // Where xochitl keeps its documents on the device.
+    pub device_root: String,
+    // Where the documents are made before going to the device, next to the state if unset.
+    pub staging: Option<String>,
+    // Run on the device once the files are there.
+    pub refresh_cmd: String,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(not(feature = "remote-push"), allow(dead_code))]
+pub enum Method {
+    Scp,
+    #[default]
+    Rsync,
+}
+
+
+impl Default for Remote {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            user: "root".to_string(),
+            port: 22,
+            key: None,
+            method: Method::default(),
+            device_root: fshandler::default_xochitl_root().to_string(),
+            staging: None,
+            refresh_cmd: "systemctl restart xochitl".to_string(),
+        }
+    }
+}
@@ -0,0 +1,478 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Method, Remote};
+use crate::fshandler;
+use crate::sync::Refresher;
+
+
+//
+// Companion mode itself, see the remote module: the commands it runs, and what's pushed when.
+//
+impl Remote {
+    pub fn staging(&self) -> PathBuf {
+        match &self.staging {
+            Some(staging) => PathBuf::from(staging),
+            None => Path::new(fshandler::config_file()).with_file_name("staging"),
+        }
+    }
+
+
+    fn destination(&self) -> String {
+        format!("{}@{}:{}/", self.user, self.host, self.device_root.trim_end_matches('/'))
+    }
+
+
+    // How ssh is told about the port and the key, for ssh itself and for rsync's -e.
+    fn ssh_options(&self) -> Vec<String> {
+        let mut options = vec!["-p".to_string(), self.port.to_string(), "-o".to_string(), "BatchMode=yes".to_string()];
+
+        if let Some(key) = &self.key {
+            options.extend(["-i".to_string(), key.clone()]);
+        }
+
+        options
+    }
+
+
+    // scp takes the port as -P, and the rest as ssh does.
+    fn scp_options(&self) -> Vec<String> {
+        let mut options = self.ssh_options();
+        options[0] = "-P".to_string();
+
+        options
+    }
+
+
+    pub fn pull_command(&self, staging: &Path) -> Vec<String> {
+        let staging = format!("{}/", staging.display());
+
+        match self.method {
+            Method::Rsync => [
+                vec!["rsync".to_string(), "-t".to_string(), "-e".to_string(), format!("ssh {}", self.ssh_options().join(" "))],
+                vec!["--include=*.metadata".to_string(), "--exclude=*".to_string(), self.destination(), staging],
+            ].concat(),
+            Method::Scp => [
+                vec!["scp".to_string(), "-p".to_string()],
+                self.scp_options(),
+                vec![format!("{}*.metadata", self.destination()), staging],
+            ].concat(),
+        }
+    }
+
+
+    pub fn push_command(&self, files: &[PathBuf]) -> Vec<String> {
+        let files = files.iter().map(|file| file.display().to_string());
+
+        match self.method {
+            Method::Rsync => [
+                vec!["rsync".to_string(), "-t".to_string(), "-e".to_string(), format!("ssh {}", self.ssh_options().join(" "))],
+                files.collect(),
+                vec![self.destination()],
+            ].concat(),
+            Method::Scp => [
+                vec!["scp".to_string(), "-p".to_string()],
+                self.scp_options(),
+                files.collect(),
+                vec![self.destination()],
+            ].concat(),
+        }
+    }
+
+
+    pub fn run_command(&self, cmd: &str) -> Vec<String> {
+        [
+            vec!["ssh".to_string()],
+            self.ssh_options(),
+            vec![format!("{}@{}", self.user, self.host), cmd.to_string()],
+        ].concat()
+    }
+}
+
+
+#[derive(Debug)]
+pub enum RemoteError {
+    // The command couldn't be started at all, e.g. there's no rsync.
+    Spawn(String, std::io::Error),
+    // It ran, and failed.
+    Failed(String, String),
+    IO(std::io::Error),
+}
+
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteError::Spawn(cmd, e) => write!(f, "could not run {cmd}: {e}"),
+            RemoteError::Failed(cmd, stderr) => write!(f, "{cmd} failed: {}", stderr.trim()),
+            RemoteError::IO(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+
+impl From<std::io::Error> for RemoteError {
+    fn from(error: std::io::Error) -> Self {
+        RemoteError::IO(error)
+    }
+}
+
+
+// Getting files to and from the device, and running things on it.
+pub trait Transport {
+    // Every .metadata file on the device into staging.
+    fn pull_metadata(&self, staging: &Path) -> Result<(), RemoteError>;
+    // The files into xochitl's directory on the device.
+    fn push(&self, files: &[PathBuf]) -> Result<(), RemoteError>;
+    fn run(&self, cmd: &str) -> Result<(), RemoteError>;
+}
+
+
+// Shelling out to ssh, and scp or rsync.
+pub struct Ssh {
+    remote: Remote,
+}
+
+
+impl Ssh {
+    pub fn new(remote: &Remote) -> Self {
+        Self {
+            remote: remote.clone(),
+        }
+    }
+
+
+    fn execute(argv: Vec<String>) -> Result<(), RemoteError> {
+        let output = Command::new(&argv[0])
+            .args(&argv[1..])
+            .output()
+            .map_err(|e| RemoteError::Spawn(argv[0].clone(), e))?;
+
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(RemoteError::Failed(argv[0].clone(), String::from_utf8_lossy(&output.stderr).to_string())),
+        }
+    }
+}
+
+
+impl Transport for Ssh {
+    fn pull_metadata(&self, staging: &Path) -> Result<(), RemoteError> {
+        Self::execute(self.remote.pull_command(staging))
+    }
+
+
+    fn push(&self, files: &[PathBuf]) -> Result<(), RemoteError> {
+        Self::execute(self.remote.push_command(files))
+    }
+
+
+    fn run(&self, cmd: &str) -> Result<(), RemoteError> {
+        Self::execute(self.remote.run_command(cmd))
+    }
+}
+
+
+//
+// The staging directory and the device, kept in step. What was made since the last push that
+// went through is pushed: the time it started is kept in a file in the staging directory, and
+// it's only moved forward once the files are on the device. A push that fails leaves everything
+// as it was, to be pushed again after the next sync.
+//
+// Pulled .metadata files keep the device's modification time, which is also kept aside, so that
+// those rePocket didn't touch aren't pushed back over whatever xochitl wrote in the meantime. Nor
+// are changes that didn't make it to the device yet overwritten by a pull.
+//
+pub struct Companion<T: Transport> {
+    transport: T,
+    staging: PathBuf,
+    refresh_cmd: String,
+}
+
+
+const DOCUMENT_FILES: [&str; 5] = ["epub", "pdf", "html", "content", "metadata"];
+
+
+fn modified(path: &Path) -> Result<u128, RemoteError> {
+    let modified = fs::metadata(path)?.modified()?;
+
+    Ok(modified.duration_since(UNIX_EPOCH).map(|modified| modified.as_millis()).unwrap_or(0))
+}
+
+
+impl<T: Transport> Companion<T> {
+    pub fn new(transport: T, staging: &Path, refresh_cmd: &str) -> Self {
+        Self {
+            transport,
+            staging: staging.to_path_buf(),
+            refresh_cmd: refresh_cmd.to_string(),
+        }
+    }
+
+
+    fn marker(&self) -> PathBuf {
+        self.staging.join(".repocket.pushed")
+    }
+
+
+    fn pulled_file(&self) -> PathBuf {
+        self.staging.join(".repocket.pulled")
+    }
+
+
+    // Milliseconds since the epoch when the last push that went through started, zero if never.
+    fn last_push(&self) -> u128 {
+        fs::read_to_string(self.marker()).ok()
+            .and_then(|text| text.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+
+    // The modification time of each file as it came from the device, by name.
+    fn pulled(&self) -> BTreeMap<String, u128> {
+        fs::read_to_string(self.pulled_file()).ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+
+    pub fn pull(&self) -> Result<(), RemoteError> {
+        let incoming = self.staging.join(".incoming");
+        let _ = fs::remove_dir_all(&incoming);
+        fs::create_dir_all(&incoming)?;
+
+        self.transport.pull_metadata(&incoming)?;
+
+        let unpushed = self.pending()?;
+        let mut pulled = self.pulled();
+
+        for entry in fs::read_dir(&incoming)?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let target = self.staging.join(&name);
+
+            if unpushed.contains(&target) {
+                continue;
+            }
+
+            fs::rename(entry.path(), &target)?;
+            pulled.insert(name, modified(&target)?);
+        }
+
+        let _ = fs::remove_dir_all(&incoming);
+        fs::write(self.pulled_file(), serde_json::to_string(&pulled).map_err(std::io::Error::from)?)?;
+
+        Ok(())
+    }
+
+
+    // The documents and sidecars changed here since the last push.
+    pub fn pending(&self) -> Result<Vec<PathBuf>, RemoteError> {
+        let since = self.last_push();
+        let pulled = self.pulled();
+        let mut files = Vec::new();
+
+        for entry in fs::read_dir(&self.staging)?.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            let ours = path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| DOCUMENT_FILES.contains(&ext));
+
+            if !ours {
+                continue;
+            }
+
+            let modified = modified(&path)?;
+
+            if modified > since && pulled.get(&name) != Some(&modified) {
+                files.push(path);
+            }
+        }
+
+        files.sort();
+
+        Ok(files)
+    }
+
+
+    // Returns how many files were pushed.
+    pub fn push(&self) -> Result<usize, RemoteError> {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_millis()).unwrap_or(0);
+        let files = self.pending()?;
+
+        if !files.is_empty() {
+            self.transport.push(&files)?;
+        }
+
+        fs::write(self.marker(), started.to_string())?;
+
+        Ok(files.len())
+    }
+}
+
+
+impl<T: Transport> Refresher for Companion<T> {
+    fn refresh(&self) {
+        match self.push() {
+            Ok(0) => println!("ℹ Nothing new for the device"),
+            Ok(pushed) => {
+                println!("ℹ Pushed {pushed} file(s) to the device");
+
+                if let Err(e) = self.transport.run(&self.refresh_cmd) {
+                    println!("🚨 Could not restart xochitl on the device: {e}");
+                }
+            },
+            Err(e) => println!("🚨 Could not push to the device, the files stay in {} for the next sync: {e}", self.staging.display()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    // The device as a directory, for a push that may or may not go through.
+    struct Local {
+        device: PathBuf,
+        broken: Cell<bool>,
+        ran: RefCell<Vec<String>>,
+    }
+
+    impl Transport for Local {
+        fn pull_metadata(&self, staging: &Path) -> Result<(), RemoteError> {
+            for entry in fs::read_dir(&self.device)?.flatten() {
+                if entry.path().extension().is_some_and(|ext| ext == "metadata") {
+                    fs::copy(entry.path(), staging.join(entry.file_name()))?;
+                }
+            }
+
+            Ok(())
+        }
+
+        fn push(&self, files: &[PathBuf]) -> Result<(), RemoteError> {
+            if self.broken.get() {
+                return Err(RemoteError::Failed("rsync".to_string(), "Connection refused".to_string()));
+            }
+
+            for file in files {
+                fs::copy(file, self.device.join(file.file_name().unwrap()))?;
+            }
+
+            Ok(())
+        }
+
+        fn run(&self, cmd: &str) -> Result<(), RemoteError> {
+            self.ran.borrow_mut().push(cmd.to_string());
+
+            Ok(())
+        }
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn names(files: &[PathBuf]) -> Vec<String> {
+        files.iter().map(|file| file.file_name().unwrap().to_string_lossy().to_string()).collect()
+    }
+
+    #[test]
+    fn commands() {
+        let remote = Remote {
+            host: "10.11.99.1".to_string(),
+            key: Some("/home/me/.ssh/remarkable".to_string()),
+            ..Remote::default()
+        };
+        let files = [PathBuf::from("/staging/a.epub"), PathBuf::from("/staging/a.metadata")];
+        let root = "root@10.11.99.1:/home/root/.local/share/remarkable/xochitl/";
+
+        assert_eq!(remote.push_command(&files), [
+            "rsync", "-t", "-e", "ssh -p 22 -o BatchMode=yes -i /home/me/.ssh/remarkable",
+            "/staging/a.epub", "/staging/a.metadata", root,
+        ]);
+        assert_eq!(remote.pull_command(Path::new("/staging")), [
+            "rsync", "-t", "-e", "ssh -p 22 -o BatchMode=yes -i /home/me/.ssh/remarkable",
+            "--include=*.metadata", "--exclude=*", root, "/staging/",
+        ]);
+        assert_eq!(remote.run_command("systemctl restart xochitl"), [
+            "ssh", "-p", "22", "-o", "BatchMode=yes", "-i", "/home/me/.ssh/remarkable", "root@10.11.99.1", "systemctl restart xochitl",
+        ]);
+
+        let remote = Remote { method: Method::Scp, port: 2222, key: None, ..remote };
+        assert_eq!(remote.push_command(&files[..1]), ["scp", "-p", "-P", "2222", "-o", "BatchMode=yes", "/staging/a.epub", root]);
+        assert_eq!(remote.pull_command(Path::new("/staging")), [
+            "scp", "-p", "-P", "2222", "-o", "BatchMode=yes", &format!("{root}*.metadata"), "/staging/",
+        ]);
+    }
+
+    #[test]
+    fn push_what_changed() {
+        let device = scratch("repocket-remote-device");
+        let staging = scratch("repocket-remote-staging");
+        fs::write(device.join("read.metadata"), "{\"parent\": \"archive\"}").unwrap();
+        fs::write(device.join("read.epub"), "epub").unwrap();
+
+        let transport = Local { device: device.clone(), broken: Cell::new(false), ran: RefCell::new(Vec::new()) };
+        let companion = Companion::new(transport, &staging, "systemctl restart xochitl");
+
+        // Only the metadata comes over, and it's not going back as it is.
+        companion.pull().unwrap();
+        assert!(staging.join("read.metadata").exists() && !staging.join("read.epub").exists());
+        assert!(companion.pending().unwrap().is_empty());
+
+        // What the sync made, and what it changed, a little later.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        for file in ["new.epub", "new.content", "new.metadata", "read.metadata", "notes.txt"] {
+            fs::write(staging.join(file), "from the sync").unwrap();
+        }
+        assert_eq!(names(&companion.pending().unwrap()), ["new.content", "new.epub", "new.metadata", "read.metadata"]);
+
+        // Out of reach, nothing is lost nor restarted.
+        companion.transport.broken.set(true);
+        companion.refresh();
+        assert_eq!(companion.pending().unwrap().len(), 4);
+        assert!(companion.transport.ran.borrow().is_empty());
+
+        // Pulling again doesn't undo what's waiting to go.
+        companion.pull().unwrap();
+        assert_eq!(fs::read_to_string(staging.join("read.metadata")).unwrap(), "from the sync");
+
+        companion.transport.broken.set(false);
+        companion.refresh();
+        assert_eq!(fs::read_to_string(device.join("new.epub")).unwrap(), "from the sync");
+        assert_eq!(fs::read_to_string(device.join("read.metadata")).unwrap(), "from the sync");
+        assert!(!device.join("notes.txt").exists());
+        assert_eq!(*companion.transport.ran.borrow(), ["systemctl restart xochitl"]);
+
+        // All pushed, nothing to restart for.
+        assert!(companion.pending().unwrap().is_empty());
+        companion.refresh();
+        assert_eq!(companion.transport.ran.borrow().len(), 1);
+    }
+}
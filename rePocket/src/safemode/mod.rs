@@ -0,0 +1,191 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::articlehandler;
+use crate::fshandler::{self, FSHandler};
+use crate::strings;
+use crate::text::{HtmlText, PlainText};
+
+
+//
+// A single pathological article, or a corrupt metadata file, shouldn't stop syncing for good
+// until someone SSHes in. Every sync bumps a counter in a file next to the state, and clears it
+// once it's done, panic or not. After CRASH_LIMIT syncs in a row that never got that far, rePocket
+// starts in safe mode: no consolidation, no downloads, only the pending archives and tags are
+// pushed, and a document in the Pocket folder says what happened.
+//
+// Safe mode ends after `repocket sync --once` goes through, or with `repocket safe-mode --clear`.
+//
+pub const CRASH_LIMIT: u32 = 3;
+// The document the report goes in, always the same one.
+const REPORT_NAME: &str = "repocket-safe-mode-report";
+
+
+pub fn crash_file() -> PathBuf {
+    Path::new(fshandler::config_file()).with_file_name(".repocket.crashes")
+}
+
+
+pub struct CrashCounter {
+    path: PathBuf,
+}
+
+
+impl CrashCounter {
+    pub fn new() -> Self {
+        Self::at(&crash_file())
+    }
+
+
+    pub fn at(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+
+    // Syncs in a row that didn't finish, zero if there's no counter or it can't be read.
+    pub fn count(&self) -> u32 {
+        fs::read_to_string(&self.path).ok()
+            .and_then(|text| text.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+
+    pub fn safe_mode(&self) -> bool {
+        self.count() >= CRASH_LIMIT
+    }
+
+
+    // Right before a sync, which counts as a crash until finished() says otherwise. Returns the
+    // count from before, for restore().
+    pub fn begin(&self) -> u32 {
+        let count = self.count();
+        self.write(count + 1);
+
+        count
+    }
+
+
+    pub fn finished(&self) {
+        self.write(0);
+    }
+
+
+    // The sync didn't crash, but it didn't go through either, e.g. Pocket was unreachable.
+    pub fn restore(&self, count: u32) {
+        self.write(count);
+    }
+
+
+    pub fn clear(&self) {
+        let _ = fs::remove_file(&self.path);
+    }
+
+
+    fn write(&self, count: u32) {
+        if let Err(e) = fs::write(&self.path, count.to_string()) {
+            println!("🚨 Could not update {}: {e}", self.path.display());
+        }
+    }
+}
+
+
+// Put the report in the Pocket folder, replacing the one from the last safe mode sync.
+pub fn write_report(fhandler: &FSHandler) {
+    let strings = strings::current();
+    let title = PlainText::new(strings.safe_mode_title);
+    let body = HtmlText::trusted(&strings::fill(strings.safe_mode_body, &[("limit", &CRASH_LIMIT.to_string())]));
+
+    match articlehandler::note_epub(&title, &body) {
+        Ok(epub) => {
+            if let Some(path) = fhandler.write_note(REPORT_NAME, title.as_str(), &epub) {
+                println!("ℹ Wrote the safe mode report to {path}");
+            }
+        },
+        Err(e) => println!("🚨 Could not make the safe mode report: {e}"),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn counter() -> CrashCounter {
+        CrashCounter::at(&std::env::temp_dir().join(format!("repocket-crashes-{}", Uuid::new_v4())))
+    }
+
+    #[test]
+    fn panics_in_a_row() {
+        let counter = counter();
+        assert!(!counter.safe_mode());
+
+        // Started, and never heard of again.
+        for _ in 0..CRASH_LIMIT - 1 {
+            counter.begin();
+            assert!(!counter.safe_mode());
+        }
+        counter.begin();
+        assert!(counter.safe_mode());
+        assert_eq!(counter.count(), CRASH_LIMIT);
+
+        counter.clear();
+        assert!(!counter.safe_mode());
+    }
+
+    #[test]
+    fn finishing_starts_over() {
+        let counter = counter();
+
+        counter.begin();
+        counter.begin();
+        counter.begin();
+        counter.finished();
+        assert_eq!(counter.count(), 0);
+
+        // Two crashes, a sync that couldn't reach Pocket, and another crash.
+        counter.begin();
+        counter.begin();
+        let before = counter.begin();
+        counter.restore(before);
+        assert!(!counter.safe_mode());
+        counter.begin();
+        assert!(counter.safe_mode());
+
+        // In safe mode, an unsuccessful sync --once leaves it as it was.
+        let before = counter.begin();
+        counter.restore(before);
+        assert!(counter.safe_mode());
+        counter.begin();
+        counter.finished();
+        assert!(!counter.safe_mode());
+    }
+
+    #[test]
+    fn unreadable_counter() {
+        let counter = counter();
+        fs::write(&counter.path, "lots").unwrap();
+
+        assert_eq!(counter.count(), 0);
+        assert_eq!(counter.begin(), 0);
+        assert_eq!(counter.count(), 1);
+    }
+}
@@ -0,0 +1,351 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Datelike, LocalResult, NaiveDateTime, TimeDelta, TimeZone, Timelike, Weekday};
+use serde::Deserialize;
+
+
+//
+// When syncs may happen, in local time, as in the settings:
+//
+// [schedule]
+// days = ["fri", "sat"]
+// hours = "17-22"
+// watch = "bypass"
+//
+// Hours go from the first to the last one, both included, and several ranges are separated by
+// commas, e.g. "7-8, 17-22". A range may wrap around midnight, e.g. "22-2", and the hours after
+// midnight then belong to the day the range started on, so Friday's "22-2" is also early
+// Saturday. No days means every day, no hours means every hour, so an empty schedule is always.
+//
+// The timer waits for the schedule, syncs triggered from the Sync folder only do with
+// watch = "respect".
+//
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Schedule {
+    days: Vec<Day>,
+    hours: Hours,
+    watch: WatchSyncs,
+}
+
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchSyncs {
+    #[default]
+    Bypass,
+    Respect,
+}
+
+
+// Inclusive ranges of hours, the first one past the second when they wrap around midnight.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Hours(Vec<(u32, u32)>);
+
+
+impl From<Day> for Weekday {
+    fn from(day: Day) -> Self {
+        match day {
+            Day::Mon => Weekday::Mon,
+            Day::Tue => Weekday::Tue,
+            Day::Wed => Weekday::Wed,
+            Day::Thu => Weekday::Thu,
+            Day::Fri => Weekday::Fri,
+            Day::Sat => Weekday::Sat,
+            Day::Sun => Weekday::Sun,
+        }
+    }
+}
+
+
+impl TryFrom<String> for Hours {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let hour = |hour: &str| match hour.trim().parse::<u32>() {
+            Ok(hour) if hour < 24 => Ok(hour),
+            _ => Err(format!("\"{}\" is not an hour from 0 to 23", hour.trim())),
+        };
+
+        text.split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(|range| match range.split_once('-') {
+                Some((first, last)) => Ok((hour(first)?, hour(last)?)),
+                None => hour(range).map(|hour| (hour, hour)),
+            })
+            .collect::<Result<_, _>>()
+            .map(Hours)
+    }
+}
+
+
+impl Schedule {
+    pub fn is_always(&self) -> bool {
+        self.days.is_empty() && self.hours.0.is_empty()
+    }
+
+
+    pub fn watch(&self) -> WatchSyncs {
+        self.watch
+    }
+
+
+    // Every day when empty.
+    pub fn days(&self) -> Vec<Weekday> {
+        self.days.iter().map(|day| Weekday::from(*day)).collect()
+    }
+
+
+    // As in the settings, e.g. "7-8, 17-22", every hour when empty.
+    pub fn hours(&self) -> String {
+        self.hours.0.iter()
+            .map(|(first, last)| match first == last {
+                true => first.to_string(),
+                false => format!("{first}-{last}"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+
+    pub fn allows<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        self.allows_at(time.weekday(), time.hour())
+    }
+
+
+    fn allows_at(&self, weekday: Weekday, hour: u32) -> bool {
+        let on = |weekday: Weekday| self.days.is_empty() || self.days.iter().any(|day| Weekday::from(*day) == weekday);
+
+        match self.hours.0.is_empty() {
+            true => on(weekday),
+            false => self.hours.0.iter().any(|&(first, last)| match first <= last {
+                true => on(weekday) && (first..=last).contains(&hour),
+                false => (hour >= first && on(weekday)) || (hour <= last && on(weekday.pred())),
+            }),
+        }
+    }
+
+
+    //
+    // The first time from the given one on when syncs are allowed. Hours skipped by a daylight
+    // saving change are skipped by the schedule too, and hours that happen twice start with the
+    // first one. None if the schedule never allows anything, which takes a schedule made only of
+    // skipped hours.
+    //
+    pub fn next_eligible<Tz: TimeZone>(&self, from: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        if self.allows(from) {
+            return Some(from.clone());
+        }
+
+        let tz = from.timezone();
+        let mut hour: NaiveDateTime = from.naive_local().date().and_hms_opt(from.hour(), 0, 0)?;
+
+        // A week and a day covers every day of the week and a range wrapping into the next.
+        for _ in 0..8 * 24 {
+            hour += TimeDelta::hours(1);
+
+            if !self.allows_at(hour.weekday(), hour.hour()) {
+                continue;
+            }
+
+            match tz.from_local_datetime(&hour) {
+                LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => return Some(time),
+                LocalResult::None => continue,
+            }
+        }
+
+        None
+    }
+
+
+    // How long until syncs are allowed in local time, zero if they are now, or never will be.
+    pub fn wait(&self, now: SystemTime) -> Duration {
+        let now: DateTime<chrono::Local> = now.into();
+
+        self.next_eligible(&now)
+            .and_then(|next| (next - now).to_std().ok())
+            .unwrap_or_default()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveDate};
+    use crate::settings::Settings;
+
+    // Central European time in 2024, when summer time went from the 31st of March to the 27th of
+    // October, at 01:00 UTC both times.
+    #[derive(Clone, Copy, Debug)]
+    struct Cet;
+
+    impl Cet {
+        fn offset_at(utc: &NaiveDateTime) -> FixedOffset {
+            let start = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap().and_hms_opt(1, 0, 0).unwrap();
+            let end = NaiveDate::from_ymd_opt(2024, 10, 27).unwrap().and_hms_opt(1, 0, 0).unwrap();
+
+            match (start..end).contains(utc) {
+                true => FixedOffset::east_opt(2 * 3600).unwrap(),
+                false => FixedOffset::east_opt(3600).unwrap(),
+            }
+        }
+    }
+
+    impl TimeZone for Cet {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Cet
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            // Summer time first, it's the earlier of the two when the hour happens twice.
+            let offsets: Vec<FixedOffset> = [2, 1].into_iter()
+                .map(|hours| FixedOffset::east_opt(hours * 3600).unwrap())
+                .filter(|offset| Self::offset_at(&(*local - TimeDelta::seconds(offset.local_minus_utc() as i64))) == *offset)
+                .collect();
+
+            match offsets[..] {
+                [offset] => LocalResult::Single(offset),
+                [first, second] => LocalResult::Ambiguous(first, second),
+                _ => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            Self::offset_at(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            Self::offset_at(utc)
+        }
+    }
+
+    fn schedule(toml: &str) -> Schedule {
+        Settings::from_toml(&format!("[schedule]\n{toml}")).schedule().clone()
+    }
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Cet> {
+        Cet.with_ymd_and_hms(y, m, d, h, min, 0).earliest().unwrap()
+    }
+
+    fn next(schedule: &Schedule, from: DateTime<Cet>) -> String {
+        schedule.next_eligible(&from).unwrap().to_rfc3339()
+    }
+
+    #[test]
+    fn empty_schedule_is_always() {
+        let always = schedule("");
+        let now = at(2024, 10, 16, 4, 12);
+
+        assert!(always.is_always());
+        assert!(always.allows(&now));
+        assert_eq!(always.next_eligible(&now), Some(now));
+        assert_eq!(always.watch(), WatchSyncs::Bypass);
+        assert_eq!(always.wait(SystemTime::now()), Duration::ZERO);
+    }
+
+    #[test]
+    fn weekend_evenings() {
+        let weekend = schedule("days = [\"fri\", \"sat\"]\nhours = \"17-22\"");
+
+        // Wednesday morning, Friday in the evening, Friday night, and Saturday night.
+        assert_eq!(next(&weekend, at(2024, 10, 16, 10, 0)), "2024-10-18T17:00:00+02:00");
+        assert_eq!(next(&weekend, at(2024, 10, 18, 22, 59)), "2024-10-18T22:59:00+02:00");
+        assert_eq!(next(&weekend, at(2024, 10, 18, 23, 30)), "2024-10-19T17:00:00+02:00");
+        assert_eq!(next(&weekend, at(2024, 10, 19, 23, 0)), "2024-10-25T17:00:00+02:00");
+    }
+
+    #[test]
+    fn days_only_and_hours_only() {
+        let sundays = schedule("days = [\"sun\"]");
+        assert_eq!(next(&sundays, at(2024, 10, 16, 10, 0)), "2024-10-20T00:00:00+02:00");
+
+        let mornings = schedule("hours = \"7-8, 12\"");
+        assert_eq!(next(&mornings, at(2024, 10, 16, 8, 45)), "2024-10-16T08:45:00+02:00");
+        assert_eq!(next(&mornings, at(2024, 10, 16, 9, 0)), "2024-10-16T12:00:00+02:00");
+        assert_eq!(next(&mornings, at(2024, 10, 16, 13, 0)), "2024-10-17T07:00:00+02:00");
+    }
+
+    #[test]
+    fn hours_wrapping_around_midnight() {
+        let nights = schedule("hours = \"22-2\"");
+        assert!(nights.allows(&at(2024, 10, 16, 23, 0)));
+        assert!(nights.allows(&at(2024, 10, 16, 1, 59)));
+        assert!(!nights.allows(&at(2024, 10, 16, 3, 0)));
+        assert_eq!(next(&nights, at(2024, 10, 16, 21, 59)), "2024-10-16T22:00:00+02:00");
+
+        // Friday night goes on into Saturday, but Thursday's doesn't.
+        let friday_nights = schedule("days = [\"fri\"]\nhours = \"22-2\"");
+        assert!(friday_nights.allows(&at(2024, 10, 19, 1, 0)));
+        assert!(!friday_nights.allows(&at(2024, 10, 18, 1, 0)));
+        assert!(!friday_nights.allows(&at(2024, 10, 19, 22, 0)));
+        assert_eq!(next(&friday_nights, at(2024, 10, 19, 3, 0)), "2024-10-25T22:00:00+02:00");
+    }
+
+    #[test]
+    fn skipped_hour_in_spring() {
+        // 02:00 to 02:59 never happened on the 31st of March.
+        assert_eq!(next(&schedule("hours = \"2\""), at(2024, 3, 31, 0, 30)), "2024-04-01T02:00:00+02:00");
+        assert_eq!(next(&schedule("hours = \"2-3\""), at(2024, 3, 31, 0, 30)), "2024-03-31T03:00:00+02:00");
+        assert_eq!(next(&schedule("hours = \"4\""), at(2024, 3, 31, 1, 30)), "2024-03-31T04:00:00+02:00");
+    }
+
+    #[test]
+    fn repeated_hour_in_autumn() {
+        // 02:00 to 02:59 happened twice on the 27th of October, first in summer time.
+        let two = schedule("hours = \"2\"");
+        assert_eq!(next(&two, at(2024, 10, 27, 0, 30)), "2024-10-27T02:00:00+02:00");
+
+        let second = Cet.from_utc_datetime(&NaiveDate::from_ymd_opt(2024, 10, 27).unwrap().and_hms_opt(1, 10, 0).unwrap());
+        assert_eq!(next(&two, second), "2024-10-27T02:10:00+01:00");
+        assert_eq!(next(&schedule("hours = \"3\""), second), "2024-10-27T03:00:00+01:00");
+    }
+
+    #[test]
+    fn hours_grammar() {
+        assert_eq!(Hours::try_from("17-22".to_string()), Ok(Hours(vec![(17, 22)])));
+        assert_eq!(Hours::try_from(" 7 - 9 ,22-2,12".to_string()), Ok(Hours(vec![(7, 9), (22, 2), (12, 12)])));
+        assert_eq!(Hours::try_from("".to_string()), Ok(Hours(vec![])));
+        assert!(Hours::try_from("17-24".to_string()).is_err());
+        assert!(Hours::try_from("17-".to_string()).is_err());
+        assert!(Hours::try_from("evening".to_string()).is_err());
+    }
+}
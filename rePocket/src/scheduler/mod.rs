@@ -0,0 +1,481 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime};
+
+use crate::clock::Clock;
+use crate::schedule::{Schedule, WatchSyncs};
+use crate::settings::Settings;
+
+
+//
+// Timer-based syncs, for when the Sync folder isn't enough.
+//
+// The reMarkable suspends aggressively. A sync that came due while asleep fires as soon as the
+// device wakes up, before the wifi has reassociated, fails, and the backoff then pushes the next
+// attempt past the moment the device is actually online. So wake-ups are detected (the monotonic
+// clock stops while suspended, the wall clock doesn't) and, after one, the sync waits for the
+// network for a grace period, and a failure during that period isn't held against the backoff.
+//
+// A sync that comes due outside the schedule in the settings waits for it to allow syncs again.
+//
+
+// How often the timer looks at the clocks, which bounds how late a wake-up is noticed.
+const TICK: Duration = Duration::from_secs(60);
+// Wall-clock time unaccounted for by the monotonic clock beyond this means we were asleep.
+const WAKE_THRESHOLD: Duration = Duration::from_secs(60);
+// How often to look for the network after a wake-up.
+const POLL: Duration = Duration::from_secs(10);
+// Backoff after failed syncs, doubling up to the sync interval.
+const BACKOFF_BASE: Duration = Duration::from_secs(60);
+
+
+pub trait Prober {
+    fn online(&self) -> bool;
+}
+
+
+// Online is being able to open a connection to Pocket.
+pub struct TcpProber {
+    host: String,
+}
+
+
+impl TcpProber {
+    pub fn new(host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+        }
+    }
+}
+
+
+impl Prober for TcpProber {
+    fn online(&self) -> bool {
+        tokio::task::block_in_place(|| {
+            match self.host.to_socket_addrs() {
+                Ok(mut addrs) => addrs.any(|addr| TcpStream::connect_timeout(&addr, Duration::from_secs(5)).is_ok()),
+                Err(_) => false,
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    Sleep(Duration),
+    Sync,
+}
+
+
+#[derive(Default)]
+pub struct WakeDetector {
+    last: Option<(SystemTime, Duration)>,
+}
+
+
+impl WakeDetector {
+    // How long the device was asleep since the previous tick, if it was.
+    pub fn tick(&mut self, wall: SystemTime, monotonic: Duration) -> Option<Duration> {
+        let (last_wall, last_monotonic) = self.last.replace((wall, monotonic))?;
+
+        let wall_elapsed = wall.duration_since(last_wall).unwrap_or_default();
+        let slept = wall_elapsed.saturating_sub(monotonic.saturating_sub(last_monotonic));
+
+        (slept > WAKE_THRESHOLD).then_some(slept)
+    }
+}
+
+
+pub struct Backoff {
+    failures: u32,
+    max: Duration,
+}
+
+
+impl Backoff {
+    pub fn new(max: Duration) -> Self {
+        Self {
+            failures: 0,
+            max,
+        }
+    }
+
+
+    pub fn failed(&mut self) {
+        self.failures += 1;
+    }
+
+
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+
+    pub fn delay(&self) -> Duration {
+        match self.failures {
+            0 => Duration::ZERO,
+            n => std::cmp::min(BACKOFF_BASE.saturating_mul(1 << (n - 1).min(16)), self.max),
+        }
+    }
+}
+
+
+pub struct Scheduler<C: Clock, P: Prober> {
+    clock: C,
+    prober: P,
+    interval: Duration,
+    grace: Duration,
+    detector: WakeDetector,
+    backoff: Backoff,
+    schedule: Schedule,
+    next_due: SystemTime,
+    // When the device last woke up, for as long as its grace period lasts.
+    woke_at: Option<SystemTime>,
+    waiting_for_network: bool,
+    // After a sync cut short by the device suspending, until the one that makes up for it.
+    resuming: bool,
+}
+
+
+impl<C: Clock, P: Prober> Scheduler<C, P> {
+    pub fn new(clock: C, prober: P, settings: &Settings) -> Self {
+        let next_due = clock.now() + settings.sync_interval();
+
+        Self {
+            clock,
+            prober,
+            interval: settings.sync_interval(),
+            grace: settings.wake_grace(),
+            detector: WakeDetector::default(),
+            backoff: Backoff::new(settings.sync_interval()),
+            schedule: settings.schedule().clone(),
+            next_due,
+            woke_at: None,
+            waiting_for_network: false,
+            resuming: false,
+        }
+    }
+
+
+    pub fn enabled(&self) -> bool {
+        !self.interval.is_zero()
+    }
+
+
+    // Called on every tick of the timer, says whether to sync now or how long to sleep for.
+    pub fn next(&mut self) -> Step {
+        let now = self.clock.now();
+
+        if let Some(slept) = self.detector.tick(now, self.clock.monotonic()) {
+            println!("ℹ Woke up after {}s asleep, giving the network up to {:?}", slept.as_secs(), self.grace);
+            self.woke_at = Some(now);
+            self.waiting_for_network = true;
+        }
+
+        if self.waiting_for_network {
+            if self.prober.online() {
+                self.waiting_for_network = false;
+            } else if !self.grace_over(now) {
+                return Step::Sleep(POLL);
+            } else {
+                println!("🚨 Still offline {:?} after waking up", self.grace);
+                self.waiting_for_network = false;
+            }
+        }
+
+        match self.next_due.duration_since(now) {
+            Ok(left) if !left.is_zero() => Step::Sleep(std::cmp::min(left, TICK)),
+            _ => match self.schedule.wait(now) {
+                wait if !wait.is_zero() => Step::Sleep(std::cmp::min(wait, TICK)),
+                _ => Step::Sync,
+            },
+        }
+    }
+
+
+    // Whether a sync triggered from the Sync folder goes ahead now.
+    pub fn allows_watch(&self) -> bool {
+        let now = self.clock.now();
+
+        match self.schedule.watch() {
+            WatchSyncs::Respect => self.schedule.wait(now).is_zero(),
+            WatchSyncs::Bypass => true,
+        }
+    }
+
+
+    // Called after every sync, timer or not.
+    pub fn finished(&mut self, success: bool) {
+        let now = self.clock.now();
+
+        if success {
+            self.backoff.succeeded();
+            self.woke_at = None;
+            self.next_due = now + self.interval;
+            self.resuming = false;
+        } else if !self.grace_over(now) {
+            println!("ℹ Sync failed right after waking up, retrying without backing off");
+            self.waiting_for_network = true;
+            self.next_due = now;
+        } else {
+            self.backoff.failed();
+            self.next_due = now + self.backoff.delay();
+            self.resuming = false;
+            println!("🚨 Sync failed, backing off for {:?}", self.backoff.delay());
+        }
+    }
+
+
+    // Called instead of finished() after a sync cut short by the device suspending, see
+    // sync::suspend. The next one goes ahead as soon as the network is back, as after any other
+    // wake-up, timer or not.
+    pub fn interrupted(&mut self) {
+        let now = self.clock.now();
+
+        println!("ℹ The sync was cut short by a suspend, syncing again once the network is back");
+        self.woke_at = Some(now);
+        self.waiting_for_network = true;
+        self.next_due = now;
+        self.resuming = true;
+    }
+
+
+    // Whether there's a sync to make up for, see interrupted().
+    pub fn resuming(&self) -> bool {
+        self.resuming
+    }
+
+
+    fn grace_over(&self, now: SystemTime) -> bool {
+        match self.woke_at {
+            Some(woke_at) => now >= woke_at + self.grace,
+            None => true,
+        }
+    }
+
+
+    // Sleeps until it's time to sync.
+    pub async fn wait(&mut self) {
+        while let Step::Sleep(duration) = self.next() {
+            tokio::time::sleep(duration).await;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::time::UNIX_EPOCH;
+
+    #[derive(Default)]
+    struct MockClock {
+        wall: Cell<u64>,
+        monotonic: Cell<u64>,
+    }
+
+    impl MockClock {
+        fn awake(&self, secs: u64) {
+            self.wall.set(self.wall.get() + secs);
+            self.monotonic.set(self.monotonic.get() + secs);
+        }
+
+        fn asleep(&self, secs: u64) {
+            self.wall.set(self.wall.get() + secs);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(self.wall.get())
+        }
+
+        fn monotonic(&self) -> Duration {
+            Duration::from_secs(self.monotonic.get())
+        }
+    }
+
+    // Offline for the given number of probes, online afterwards.
+    struct MockProber {
+        offline: Cell<u32>,
+    }
+
+    impl Prober for MockProber {
+        fn online(&self) -> bool {
+            match self.offline.get() {
+                0 => true,
+                n => {
+                    self.offline.set(n - 1);
+                    false
+                },
+            }
+        }
+    }
+
+    fn scheduler(offline: u32) -> Scheduler<MockClock, MockProber> {
+        scheduled(offline, "")
+    }
+
+    fn scheduled(offline: u32, schedule: &str) -> Scheduler<MockClock, MockProber> {
+        let settings = Settings::from_toml(&format!("sync_interval = 3600\nwake_grace = 120\n[schedule]\n{schedule}"));
+        let clock = MockClock::default();
+        clock.wall.set(1_730_000_000);
+
+        Scheduler::new(clock, MockProber { offline: Cell::new(offline) }, &settings)
+    }
+
+    // Runs the timer for the given number of seconds of awake time, syncing with the given outcome.
+    fn run(scheduler: &mut Scheduler<MockClock, MockProber>, secs: u64, success: bool) -> u32 {
+        let mut syncs = 0;
+        let end = scheduler.clock.monotonic.get() + secs;
+
+        while scheduler.clock.monotonic.get() < end {
+            match scheduler.next() {
+                Step::Sleep(duration) => scheduler.clock.awake(duration.as_secs()),
+                Step::Sync => {
+                    syncs += 1;
+                    scheduler.finished(success);
+                    scheduler.clock.awake(1);
+                },
+            }
+        }
+
+        syncs
+    }
+
+    #[test]
+    fn detect_sleep_gap() {
+        let mut detector = WakeDetector::default();
+        let t0 = UNIX_EPOCH + Duration::from_secs(1000);
+
+        assert_eq!(detector.tick(t0, Duration::from_secs(0)), None);
+        assert_eq!(detector.tick(t0 + Duration::from_secs(60), Duration::from_secs(60)), None);
+        assert_eq!(detector.tick(t0 + Duration::from_secs(7260), Duration::from_secs(120)),
+            Some(Duration::from_secs(7140)));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_interval() {
+        let mut backoff = Backoff::new(Duration::from_secs(600));
+
+        assert_eq!(backoff.delay(), Duration::ZERO);
+        backoff.failed();
+        assert_eq!(backoff.delay(), Duration::from_secs(60));
+        backoff.failed();
+        assert_eq!(backoff.delay(), Duration::from_secs(120));
+        for _ in 0..10 {
+            backoff.failed();
+        }
+        assert_eq!(backoff.delay(), Duration::from_secs(600));
+        backoff.succeeded();
+        assert_eq!(backoff.delay(), Duration::ZERO);
+    }
+
+    #[test]
+    fn regular_interval() {
+        let mut scheduler = scheduler(0);
+
+        assert_eq!(run(&mut scheduler, 3 * 3600 + 60, true), 3);
+    }
+
+    #[test]
+    fn deferred_sync_after_wake() {
+        // The network takes three probes (30s) to come back after waking up.
+        let mut scheduler = scheduler(3);
+
+        assert_eq!(run(&mut scheduler, 600, true), 0);
+
+        scheduler.clock.asleep(4 * 3600);
+
+        assert_eq!(scheduler.next(), Step::Sleep(POLL));
+        assert_eq!(run(&mut scheduler, 60, true), 1);
+        assert_eq!(scheduler.backoff.failures, 0);
+        assert_eq!(scheduler.prober.offline.get(), 0);
+    }
+
+    #[test]
+    fn failure_right_after_wake_is_not_held_against_the_backoff() {
+        let mut scheduler = scheduler(0);
+
+        run(&mut scheduler, 60, true);
+        scheduler.clock.asleep(4 * 3600);
+
+        // The network looked fine, but the sync failed anyway.
+        assert_eq!(scheduler.next(), Step::Sync);
+        scheduler.finished(false);
+
+        assert_eq!(scheduler.backoff.failures, 0);
+        assert_eq!(run(&mut scheduler, 30, true), 1);
+    }
+
+    #[test]
+    fn offline_past_the_grace_period_backs_off() {
+        let mut scheduler = scheduler(u32::MAX);
+
+        run(&mut scheduler, 60, true);
+        scheduler.clock.asleep(4 * 3600);
+
+        // One sync once the grace period is over, and then it's up to the backoff.
+        assert_eq!(run(&mut scheduler, 125, false), 1);
+        assert_eq!(scheduler.backoff.failures, 1);
+    }
+
+    #[test]
+    fn interrupted_by_a_suspend() {
+        let mut scheduler = scheduler(2);
+
+        run(&mut scheduler, 60, true);
+        scheduler.interrupted();
+        assert!(scheduler.resuming());
+
+        // Right away, once the network is back, rather than an interval later.
+        assert_eq!(scheduler.next(), Step::Sleep(POLL));
+        assert_eq!(run(&mut scheduler, 30, true), 1);
+        assert!(!scheduler.resuming());
+        assert_eq!(scheduler.backoff.failures, 0);
+
+        // Without a timer too, for that one sync.
+        let clock = MockClock::default();
+        clock.wall.set(1_730_000_000);
+        let mut untimed = Scheduler::new(clock, MockProber { offline: Cell::new(0) }, &Settings::from_toml(""));
+        assert!(!untimed.enabled());
+        untimed.interrupted();
+        assert_eq!(untimed.next(), Step::Sync);
+        untimed.finished(true);
+        assert!(!untimed.resuming());
+    }
+
+    #[test]
+    fn syncs_wait_for_the_schedule() {
+        use chrono::Timelike;
+
+        // Only from six hours after the start, in local time, for two hours.
+        let start: chrono::DateTime<chrono::Local> = (UNIX_EPOCH + Duration::from_secs(1_730_000_000)).into();
+        let hours = format!("hours = \"{}-{}\"", (start.hour() + 6) % 24, (start.hour() + 7) % 24);
+        let mut scheduler = scheduled(0, &format!("{hours}\nwatch = \"respect\""));
+
+        assert_eq!(run(&mut scheduler, 4 * 3600, true), 0);
+        assert!(!scheduler.allows_watch());
+        assert!(run(&mut scheduler, 4 * 3600, true) >= 1);
+
+        // Bypassed by default.
+        assert!(scheduled(0, &hours).allows_watch());
+    }
+}
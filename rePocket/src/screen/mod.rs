@@ -0,0 +1,348 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::time::{Duration, SystemTime};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use url::Url;
+
+use crate::pocketitem::PocketItem;
+
+
+static HTML_LANG: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?is)<html\b[^>]*?\blang\s*=\s*["']?([a-z_-]+)"#).unwrap());
+static META_LANG: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?is)<meta\b[^>]*?http-equiv\s*=\s*["']?content-language["']?[^>]*?\bcontent\s*=\s*["']?([a-z_-]+)"#).unwrap()
+});
+
+
+//
+// Login pages, OAuth redirects, unsubscribe links and click trackers end up in Pocket by mistake,
+// and make for one-line documents. They're screened out by URL before anything is fetched.
+//
+// A pattern is either a host, which also matches its subdomains, optionally followed by a path
+// prefix, e.g. "github.com/login", or a path starting with "/", which matches those segments
+// anywhere in the path, e.g. "/unsubscribe". Segments match whole, ignoring case and extension,
+// so "/login" matches "/users/login.php" but not "/blog/building-a-login-page".
+//
+const NON_ARTICLES: &[&str] = &[
+    // Sign-in pages and OAuth flows
+    "accounts.google.com",
+    "login.microsoftonline.com",
+    "login.live.com",
+    "appleid.apple.com",
+    "github.com/login",
+    "facebook.com/login",
+    "twitter.com/i/flow/login",
+    "x.com/i/flow/login",
+    "linkedin.com/uas/login",
+    "/login",
+    "/signin",
+    "/sign-in",
+    "/logout",
+    "/oauth",
+    "/oauth2",
+    "/authorize",
+    "/sso",
+    // Newsletter management
+    "list-manage.com/unsubscribe",
+    "/unsubscribe",
+    "/email-preferences",
+    "/manage-preferences",
+    "/optout",
+    "/opt-out",
+    // Click trackers
+    "/track/click",
+    "/ls/click",
+];
+
+
+// The pattern the URL matches, if it's not an article. Extra patterns come from the settings.
+pub fn non_article(url: &str, extra: &[String]) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str().unwrap_or_default().to_lowercase();
+    let segments: Vec<String> = url.path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).map(segment).collect())
+        .unwrap_or_default();
+
+    NON_ARTICLES.iter()
+        .copied()
+        .chain(extra.iter().map(String::as_str))
+        .find(|pattern| matches(pattern, &host, &segments))
+        .map(String::from)
+}
+
+
+fn matches(pattern: &str, host: &str, segments: &[String]) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+
+    match pattern.strip_prefix('/') {
+        Some(path) => {
+            let wanted: Vec<String> = path.split('/').filter(|s| !s.is_empty()).map(segment).collect();
+
+            !wanted.is_empty() && segments.windows(wanted.len()).any(|window| window == wanted.as_slice())
+        },
+        None => {
+            let (domain, path) = match pattern.split_once('/') {
+                Some((domain, path)) => (domain, path),
+                None => (pattern.as_str(), ""),
+            };
+            let wanted: Vec<String> = path.split('/').filter(|s| !s.is_empty()).map(segment).collect();
+
+            !domain.is_empty()
+                && (host == domain || host.ends_with(&format!(".{domain}")))
+                && segments.starts_with(&wanted)
+        },
+    }
+}
+
+
+// The primary subtag of a language tag, e.g. "pt" for "pt-BR" or "es_ES", if it looks like one.
+pub fn language(tag: &str) -> Option<String> {
+    let primary = tag.trim().split(['-', '_']).next().unwrap_or_default().to_lowercase();
+
+    match (2..=3).contains(&primary.len()) && primary.chars().all(|c| c.is_ascii_alphabetic()) {
+        true => Some(primary),
+        false => None,
+    }
+}
+
+
+// Whether an item in lang isn't wanted, given the languages in the settings. Nothing is when
+// those are empty, and "es" takes "es-MX" too.
+pub fn other_language(lang: &str, wanted: &[String]) -> bool {
+    let lang = match language(lang) {
+        Some(lang) => lang,
+        None => return false,
+    };
+
+    !wanted.is_empty() && !wanted.iter().filter_map(|tag| language(tag)).any(|tag| tag == lang)
+}
+
+
+// The language a page says it's in, for the items Pocket didn't detect one for.
+pub fn document_language(html: &str) -> Option<String> {
+    [&HTML_LANG, &META_LANG].iter()
+        .find_map(|re| re.captures(html))
+        .and_then(|caps| language(&caps[1]))
+}
+
+
+// Whether the item was added to Pocket longer than max_age before now. Items Pocket has no time
+// for are never too old, and neither is anything without a max_age.
+pub fn too_old(item: &PocketItem, now: SystemTime, max_age: Option<Duration>) -> bool {
+    let (added, max_age) = match (item.get_time_added(), max_age) {
+        (Some(added), Some(max_age)) => (SystemTime::from(added), max_age),
+        _ => return false,
+    };
+
+    now.duration_since(added).is_ok_and(|age| age > max_age)
+}
+
+
+// Whether the item is shorter than min or longer than max words. Those Pocket didn't count, e.g.
+// PDFs, never are.
+pub fn off_length(item: &PocketItem, min: Option<u64>, max: Option<u64>) -> bool {
+    match item.get_word_count() {
+        Some(words) => min.is_some_and(|min| words < min) || max.is_some_and(|max| words > max),
+        None => false,
+    }
+}
+
+
+// The first of the excluded tags the item has, if any. Pocket keeps tags lowercase, the settings
+// may not.
+pub fn excluded_tag<'a>(item: &PocketItem, excluded: &'a [String]) -> Option<&'a str> {
+    let tags = item.get_tags();
+
+    excluded.iter()
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .find(|tag| tags.iter().any(|name| name.eq_ignore_ascii_case(tag)))
+}
+
+
+// The item's places in the user's queue, by the number after the prefix in its tags, e.g. 2 for
+// "rm-2" with the prefix "rm-", lowest first. Tags with anything but a number after the prefix
+// aren't in the queue.
+pub fn queue_tags(item: &PocketItem, prefix: &str) -> Vec<(u64, String)> {
+    let prefix = prefix.to_lowercase();
+    let mut queued: Vec<(u64, String)> = item.get_tags().into_iter()
+        .filter_map(|tag| {
+            let number = tag.to_lowercase().strip_prefix(&prefix)?.to_string();
+            if !number.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+
+            Some((number.parse().ok()?, tag))
+        })
+        .collect();
+
+    queued.sort();
+    queued
+}
+
+
+// A path segment as compared, e.g. "Login.php" is "login".
+fn segment(s: &str) -> String {
+    let s = s.to_lowercase();
+
+    match s.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+        _ => s,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+    use serde_json::json;
+    use crate::testutil::{pocket_item, tagged};
+
+    #[test]
+    fn screened_out() {
+        let urls = [
+            ("https://accounts.google.com/o/oauth2/v2/auth?client_id=abc", "accounts.google.com"),
+            ("https://login.microsoftonline.com/common/oauth2/authorize", "login.microsoftonline.com"),
+            ("https://github.com/login?return_to=%2Fsettings", "github.com/login"),
+            ("https://www.facebook.com/login/?next=x", "facebook.com/login"),
+            ("https://example.com/users/login.php", "/login"),
+            ("https://news.example.com/account/SignIn", "/signin"),
+            ("https://example.us1.list-manage.com/unsubscribe?u=1&id=2", "list-manage.com/unsubscribe"),
+            ("https://newsletter.example.com/unsubscribe/abcdef", "/unsubscribe"),
+            ("https://example.com/email-preferences?token=1", "/email-preferences"),
+            ("https://links.example.com/ls/click?upn=xyz", "/ls/click"),
+            ("https://auth.example.com/oauth2/callback", "/oauth2"),
+        ];
+
+        for (url, pattern) in urls {
+            assert_eq!(non_article(url, &[]).as_deref(), Some(pattern), "{url}");
+        }
+    }
+
+    #[test]
+    fn articles_pass() {
+        let urls = [
+            "https://blog.example.com/2024/05/building-a-login-page",
+            "https://example.com/why-oauth-is-hard",
+            "https://developers.google.com/identity/passkeys",
+            "https://www.notgoogle.com/accounts",
+            "https://github.com/rust-lang/rust/blob/master/README.md",
+            "https://example.com/articles/unsubscribe-from-everything-a-guide",
+            "https://medium.com/@someone/the-logout-problem-3f2a",
+            "https://en.wikipedia.org/wiki/Single_sign-on",
+            "not a url at all",
+        ];
+
+        for url in urls {
+            assert_eq!(non_article(url, &[]), None, "{url}");
+        }
+    }
+
+    #[test]
+    fn extra_patterns() {
+        let extra = vec!["paywall.example.com".to_string(), "/subscribe/confirm".to_string()];
+
+        assert_eq!(non_article("https://paywall.example.com/a/story", &extra).as_deref(), Some("paywall.example.com"));
+        assert_eq!(non_article("https://example.com/subscribe/confirm?id=1", &extra).as_deref(), Some("/subscribe/confirm"));
+        assert_eq!(non_article("https://example.com/subscribe/now", &extra), None);
+    }
+
+    #[test]
+    fn old_items() {
+        let added = |time: u64| pocket_item(json!({"time_added": time.to_string()}));
+        let day = Duration::from_secs(86400);
+        let now = UNIX_EPOCH + Duration::from_secs(1_730_000_000);
+        let two_weeks = Some(14 * day);
+
+        assert!(too_old(&added(1_730_000_000 - 15 * 86400), now, two_weeks));
+        assert!(!too_old(&added(1_730_000_000 - 13 * 86400), now, two_weeks));
+        assert!(!too_old(&added(1_730_000_000 - 14 * 86400), now, two_weeks));
+        // Added "after" now, the clocks disagree.
+        assert!(!too_old(&added(1_730_000_000 + 60), now, two_weeks));
+        // No time added, or no limit.
+        assert!(!too_old(&added(0), now, two_weeks));
+        assert!(!too_old(&added(1), now, None));
+    }
+
+    #[test]
+    fn languages() {
+        let wanted = vec!["es".to_string(), "pt-BR".to_string()];
+
+        assert!(!other_language("es", &wanted));
+        assert!(!other_language("es-MX", &wanted));
+        assert!(!other_language("pt_PT", &wanted));
+        assert!(other_language("en", &wanted));
+        assert!(!other_language("en", &[]));
+        // Nothing to go by, nothing is skipped.
+        assert!(!other_language("", &wanted));
+    }
+
+    #[test]
+    fn document_languages() {
+        assert_eq!(document_language(r#"<!DOCTYPE html><html class="no-js" lang="es-ES"><head>"#).as_deref(), Some("es"));
+        assert_eq!(document_language("<HTML LANG=de><body>").as_deref(), Some("de"));
+        assert_eq!(
+            document_language(r#"<html><head><meta http-equiv="Content-Language" content="fr"></head>"#).as_deref(),
+            Some("fr"),
+        );
+        assert_eq!(document_language("<html><body><p lang=\"en\">Hi</p>"), None);
+    }
+
+    #[test]
+    fn word_counts() {
+        let words = |count: &str| pocket_item(json!({"word_count": count}));
+        let (min, max) = (Some(300), Some(5000));
+
+        assert!(off_length(&words("120"), min, max));
+        assert!(off_length(&words("12000"), min, max));
+        assert!(!off_length(&words("300"), min, max));
+        assert!(!off_length(&words("5000"), min, max));
+        assert!(!off_length(&words("120"), None, max));
+        assert!(!off_length(&words("12000"), min, None));
+        // Not counted, not left out.
+        assert!(!off_length(&words("0"), min, max));
+        assert!(!off_length(&words(""), min, max));
+    }
+
+    #[test]
+    fn excluded_tags() {
+        let excluded = vec!["recipe".to_string(), " Gift-Ideas ".to_string(), "".to_string()];
+        let tagged = |with: &[&str]| tagged(1, "https://example.com/article", with);
+
+        assert_eq!(excluded_tag(&tagged(&["recipe"]), &excluded), Some("recipe"));
+        assert_eq!(excluded_tag(&tagged(&["to-remarkable", "gift-ideas"]), &excluded), Some("Gift-Ideas"));
+        assert_eq!(excluded_tag(&tagged(&["to-remarkable"]), &excluded), None);
+        assert_eq!(excluded_tag(&tagged(&[]), &excluded), None);
+        // Nothing excluded, nothing left out.
+        assert_eq!(excluded_tag(&tagged(&["recipe"]), &[]), None);
+    }
+
+    #[test]
+    fn queue_tags() {
+        let queued = |tags: &[&str]| super::queue_tags(&tagged(1, "https://example.com/article", tags), "rm-");
+
+        assert_eq!(queued(&["rm-2", "later"]), vec![(2, "rm-2".to_string())]);
+        // Lowest first, however they're written.
+        assert_eq!(queued(&["rm-10", "RM-3", "rm-003"]), vec![
+            (3, "RM-3".to_string()), (3, "rm-003".to_string()), (10, "rm-10".to_string()),
+        ]);
+        for tags in [&["rm-"][..], &["rm-one"], &["rm-+1"], &["rm-1.5"], &["later"], &[]] {
+            assert!(queued(tags).is_empty(), "{tags:?}");
+        }
+    }
+}
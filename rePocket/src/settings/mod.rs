@@ -0,0 +1,1274 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::articlehandler::{ColorPolicy, EpubLimits, Sanitization, SizeLimits};
+use crate::cookies::Cookies;
+use crate::device;
+use crate::pocketquery::QueryBuilder;
+use crate::remote::Remote;
+use crate::schedule::Schedule;
+use crate::strings::{self, Strings};
+
+pub mod statustags;
+
+pub use statustags::{Status, StatusTags};
+
+
+pub fn settings_file() -> &'static str {
+    match device::kind().is_device() {
+        true => env!("SETTINGS_FILE_RM"),
+        false => env!("SETTINGS_FILE_HOST"),
+    }
+}
+
+
+//
+// User facing settings, as opposed to the state kept by the FSHandler in the config file. The
+// file is optional, and so is every field in it, e.g.:
+//
+// sync_count = 10
+// retrieve_max = 300
+// max_items_per_cycle = 10
+// item_order = "pocket"
+// folder_name = "Pocket"
+// images = true
+// favicons = false
+// colors = "auto"
+// article_timeout = 180
+// sync_timeout = 1200
+// read_only_upstream = false
+// reconcile_every = 10
+// reconcile_to = "archive"
+// reconcile_opened = false
+// device_archive = "folder"
+// uuid_source = "url"
+// xochitl_root = "/home/root/.local/share/remarkable/xochitl"
+// xochitl_roots = ["/home/root/.local/share/remarkable/xochitl", "/mnt/old-remarkable/xochitl"]
+// post_article_cmd = "/home/root/.local/bin/after-article.sh"
+// post_sync_cmd = "/home/root/.local/bin/after-sync.sh"
+// hook_timeout = 30
+// monthly_budget_mb = 500
+// sync_interval = 3600
+// wake_grace = 120
+// max_actions = 30
+// pocket_attempts = 4
+// pocket_retry_delay = 1
+// skip_url_patterns = ["paywall.example.com", "/subscribe/confirm"]
+// languages = ["es", "en"]
+// max_item_age_days = 14
+// old_items = "ignore"
+// min_word_count = 300
+// max_word_count = 20000
+// off_length_items = "ignore"
+// excluded_tags = ["recipe", "gift-ideas"]
+// tag_excluded = false
+// large_item_mb = 10
+// max_item_mb = 100
+// pocket_read = "archive"
+// favorite_read = false
+// github_token = "github_pat_..."
+// epub_soft_limit_mb = 30
+// epub_hard_limit_mb = 50
+// epub_soft_images = 300
+// epub_hard_images = 1000
+// strict_credentials = false
+// pin_tag = "keep"
+// trigger_tag = "to-remarkable"
+// queue_tag_prefix = "rm-"
+// progress_tags = true
+// failure_placeholders = false
+// library_chapter_size = 50
+// locale = "de"
+//
+// [cookies."example.eu"]
+// consent = "yes"
+//
+// [status_tags]
+// error = "repocket-error"
+//
+// [archive_mirror]
+// count = 20
+//
+// [query]
+// state = "unread"
+// favorite = 0
+// tag = "remarkable"
+// content_type = "article"
+// sort = "newest"
+// count = 30
+// exclude_tags = ["later"]
+//
+// [[queries]]
+// name = "favorites"
+// favorite = 1
+//
+// [[queries]]
+// name = "tagged"
+// tag = "rm"
+//
+// [schedule]
+// days = ["fri", "sat"]
+// hours = "17-22"
+//
+// [sanitization]
+// preset = "standard"
+// add_tags = ["figure", "figcaption"]
+// keep_classes = false
+//
+// [remote]
+// host = "192.168.1.20"
+// key = "/home/me/.ssh/remarkable"
+//
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // Articles to ask Pocket for at a time, up to 30, and in all in each sync. More than that wait
+    // for the next one.
+    sync_count: u8,
+    retrieve_max: usize,
+    // Articles written in each sync, the rest of what's retrieved is queued for the next ones, see
+    // sync::inflight::Batch.
+    max_items_per_cycle: usize,
+    // Which of what's retrieved is written first, in the order Pocket sends it, or by when it was
+    // added to Pocket, see sync::order_items().
+    item_order: ItemOrder,
+    // The name of the folder made on the device, only when it's first made.
+    folder_name: String,
+    // Download the articles' images, on top of what the monthly budget allows.
+    images: bool,
+    // Put the site's icon next to the link on each article's first page, one request per site.
+    favicons: bool,
+    // Style the documents for a color screen or a greyscale one, or go by the device rePocket runs
+    // on, greyscale when that's not a reMarkable, see articlehandler::ColorPolicy. Always greyscale
+    // without the color feature.
+    colors: Colors,
+    // Seconds allowed to fetch and write a single article, images included.
+    article_timeout: u64,
+    // Seconds allowed for a whole sync cycle, remaining items wait for the next one.
+    sync_timeout: u64,
+    // Never archive or tag in Pocket, only keep the device folder tidy.
+    read_only_upstream: bool,
+    // Check every so many syncs whether tracked items were archived or deleted elsewhere.
+    reconcile_every: u64,
+    // Where to move those items on the device.
+    reconcile_to: ReconcileTarget,
+    // Also move items the user has started reading.
+    reconcile_opened: bool,
+    // Where read documents go on the device.
+    device_archive: DeviceArchive,
+    // What the documents' UUIDs are made from, the item's URL or its Pocket id. Switching it leaves
+    // the documents already written alone, see `repocket migrate-uuids`.
+    uuid_source: UuidSource,
+    // Where xochitl keeps its documents, when it's not where it's expected to be.
+    xochitl_root: Option<String>,
+    // Or several, every document is written to all of them. The first one is xochitl_root, the only
+    // one read from, see FSHandler::set_extra_roots().
+    xochitl_roots: Vec<String>,
+    // Commands to run after each article is written, and after each sync.
+    post_article_cmd: Option<String>,
+    post_sync_cmd: Option<String>,
+    // Seconds a hook is allowed to run for.
+    hook_timeout: u64,
+    // Megabytes of articles, images and PDFs to download per calendar month, no limit if unset.
+    monthly_budget_mb: Option<u64>,
+    // Seconds between syncs on a timer, on top of the Sync folder, zero means only the folder.
+    sync_interval: u64,
+    // Seconds to wait for the network after the device wakes up, before syncing anyway.
+    wake_grace: u64,
+    // Actions per request to Pocket, which takes 30 at most.
+    max_actions: usize,
+    // How many times to try each request to Pocket, when it fails in a way worth trying again.
+    pocket_attempts: u32,
+    // Seconds to wait before trying again, twice as long after each try, see Pocket::backoff().
+    pocket_retry_delay: u64,
+    // URLs not worth fetching on top of the built-in ones, see screen::non_article().
+    skip_url_patterns: Vec<String>,
+    // Only items in these languages are synced, all of them when empty. The rest are archived with
+    // the skipped tag, see screen::other_language().
+    languages: Vec<String>,
+    // Only items added to Pocket in the last so many days are synced, no limit if unset. The rest
+    // are left alone or archived with the skipped tag, see screen::too_old().
+    max_item_age_days: Option<u64>,
+    old_items: OldItems,
+    // Only items of at least min_word_count and at most max_word_count words are synced, those
+    // Pocket didn't count always are. The rest are left alone or archived with the skipped tag,
+    // see screen::off_length().
+    min_word_count: Option<u64>,
+    max_word_count: Option<u64>,
+    off_length_items: OldItems,
+    // Items with any of these tags are never synced, whatever the queries bring, and left as they
+    // are in Pocket, or tagged with the skipped tag if tag_excluded, see screen::excluded_tag().
+    excluded_tags: Vec<String>,
+    tag_excluded: bool,
+    // Items larger than large_item_mb, going by a HEAD request, wait until the device is charging and
+    // online, those larger than max_item_mb are skipped, see articlehandler::precheck().
+    large_item_mb: Option<u64>,
+    max_item_mb: Option<u64>,
+    // What's done in Pocket with the items read on the device.
+    pocket_read: PocketRead,
+    // Also mark the archived ones as favorites.
+    favorite_read: bool,
+    // Cookies to send to some sites, see the cookies module.
+    cookies: Cookies,
+    // For more than 60 requests an hour to GitHub's API, see articlehandler::github.
+    github_token: Option<String>,
+    // How big an epub may get before the cover page warns about it, and before it's made again
+    // with fewer images, see articlehandler::EpubLimits.
+    epub_soft_limit_mb: usize,
+    epub_hard_limit_mb: usize,
+    epub_soft_images: usize,
+    epub_hard_images: usize,
+    // Stop at a credentials file that can't be read, rather than look further, see pocket::credentials.
+    strict_credentials: bool,
+    // What's kept of the articles' HTML, see articlehandler::Sanitization.
+    sanitization: Sanitization,
+    // Items with this tag in Pocket stay in the Pocket folder whatever happens, see FSHandler::pin.
+    // An empty tag turns it off.
+    pin_tag: String,
+    // Only sync the items with this tag in Pocket, and take it off once they're on the device. An
+    // empty tag, the default, syncs every unread item.
+    trigger_tag: String,
+    // Items tagged with this and a number in Pocket, e.g. "rm-1", "rm-2", are written first, in that
+    // order, and the tag is taken off once they're on the device, see screen::queue_tags(). An
+    // empty prefix, the default, turns it off.
+    queue_tag_prefix: String,
+    // The tags left on items in Pocket, see StatusTags.
+    status_tags: StatusTags,
+    // When timer syncs may happen, and maybe those from the Sync folder, see schedule::Schedule.
+    schedule: Schedule,
+    // Tag items in Pocket with how far they were read on the device, e.g. "progress-50", see
+    // fshandler::progress. Otherwise it's only shown by `repocket status`.
+    progress_tags: bool,
+    // A "rePocket Failed!" document in the Pocket folder for every item that couldn't be written,
+    // replaced by the item's own once it can be, see FSHandler::write_placeholder(). Otherwise
+    // they're only listed by `repocket status`.
+    failure_placeholders: bool,
+    // A document in the Pocket folder listing what's in it, and what couldn't be written, in
+    // chapters of this many items, see the library module. 0, the default, for none.
+    library_chapter_size: usize,
+    // The language of what rePocket writes into the documents, see the strings module. The
+    // device's LANG when unset.
+    locale: Option<String>,
+    // Where the device is, when rePocket runs on another machine, see the remote module.
+    remote: Remote,
+    // The most recently archived items, kept in the Archive folder, see FSHandler::mirror_archive().
+    archive_mirror: ArchiveMirror,
+    // What's asked of Pocket for new items, see Settings::query(), or several queries instead, see
+    // Settings::queries().
+    query: QuerySettings,
+    queries: Vec<QuerySettings>,
+}
+
+
+// The name of the [query], the one there was before [[queries]], for its "since" in the state.
+pub const DEFAULT_QUERY: &str = "default";
+
+
+// read_only_upstream, from a file that doesn't parse as a whole, as long as its own line does. The
+// one setting not to lose to a typo elsewhere.
+fn read_only_line(data: &str) -> bool {
+    data.lines()
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .filter_map(|line| toml::from_str::<toml::Table>(line).ok())
+        .filter_map(|table| table.get("read_only_upstream")?.as_bool())
+        .last()
+        .unwrap_or(false)
+}
+
+
+//
+// The query for new items, each value as QueryBuilder takes it, whatever the case. Those it
+// doesn't take are left out for the default, and so are keys that aren't in a query. The count is
+// sync_count's if not set, and the tag trigger_tag's, only in the [query] though. The name, only in
+// [[queries]] and a must there, keeps the "since" of each apart in the state. The tag may be
+// "_untagged_" for items without any, and those with any of exclude_tags are left out once
+// retrieved.
+//
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct QuerySettings {
+    name: Option<String>,
+    state: Option<String>,
+    favorite: Option<u8>,
+    tag: Option<String>,
+    content_type: Option<String>,
+    sort: Option<String>,
+    count: Option<u8>,
+    exclude_tags: Vec<String>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+
+impl QuerySettings {
+    // What's wrong with the section, which is left out.
+    fn validate(&mut self) -> Vec<String> {
+        let mut problems: Vec<String> = self.unknown.keys()
+            .map(|key| format!("'{key}' isn't part of a query, ignoring it"))
+            .collect();
+
+        Self::keep_valid("state", &mut self.state, |builder, state| builder.set_state(state), &mut problems);
+        Self::keep_valid("favorite", &mut self.favorite, |builder, favorite| builder.set_favorite(*favorite), &mut problems);
+        Self::keep_valid("content_type", &mut self.content_type, |builder, content_type| builder.set_content_type(content_type), &mut problems);
+        Self::keep_valid("sort", &mut self.sort, |builder, sort| builder.set_sort(sort), &mut problems);
+        self.unknown.clear();
+
+        problems
+    }
+
+
+    // Unread items, not favorites, the newest first, unless set otherwise.
+    fn builder(&self, count: u8, tag: Option<&str>) -> QueryBuilder {
+        let mut builder = QueryBuilder::default()
+            .set_state(self.state.as_deref().unwrap_or("Unread"))
+            .set_favorite(self.favorite.unwrap_or(0))
+            .set_sort(self.sort.as_deref().unwrap_or("Newest"))
+            .set_detail_type("Complete")
+            .set_count(self.count.unwrap_or(count))
+            .set_offset(0)
+            .set_total(1)
+            .set_exclude_tags(&self.exclude_tags);
+
+        if let Some(content_type) = &self.content_type {
+            builder = builder.set_content_type(content_type);
+        }
+        if let Some(tag) = self.tag.as_deref().map(str::trim).filter(|tag| !tag.is_empty()).or(tag) {
+            builder = builder.set_tag(tag);
+        }
+
+        builder
+    }
+
+
+    fn keep_valid<T: ToString>(field: &str, value: &mut Option<T>, set: fn(QueryBuilder, &T) -> QueryBuilder, problems: &mut Vec<String>) {
+        if value.as_ref().is_some_and(|valid| set(QueryBuilder::default(), valid).build().is_err()) {
+            let invalid = value.take().unwrap().to_string();
+            problems.push(format!("{field} '{invalid}' isn't one Pocket takes, using the default"));
+        }
+    }
+}
+
+
+// How many archived items to keep in the Archive folder, none by default.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ArchiveMirror {
+    pub count: usize,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReconcileTarget {
+    #[default]
+    Archive,
+    Trash,
+}
+
+
+// Documents are marked as read by moving them into the Pocket/Archive folder, and leave it for
+// the trash once archived in Pocket. With "trash" there's no Archive folder, trashing a document
+// marks it as read, and with "keep" read documents stay in the Archive folder for good.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceArchive {
+    #[default]
+    Folder,
+    Trash,
+    Keep,
+}
+
+
+// With "url" an item saved twice under different ids shares a document, and one whose URL
+// changes gets a new one. With "item_id" it's one document per item id, whatever its URL.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UuidSource {
+    #[default]
+    Url,
+    ItemId,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Colors {
+    #[default]
+    Auto,
+    Color,
+    Greyscale,
+}
+
+
+// Those Pocket has no time for go last, either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemOrder {
+    #[default]
+    Pocket,
+    Newest,
+    Oldest,
+}
+
+
+// What's done with the items older than max_item_age_days, or of a word count not synced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OldItems {
+    #[default]
+    Ignore,
+    Archive,
+}
+
+
+// Archived, and tagged, or gone from the account for good.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PocketRead {
+    #[default]
+    Archive,
+    Delete,
+}
+
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            sync_count: 10,
+            retrieve_max: 300,
+            max_items_per_cycle: 10,
+            item_order: ItemOrder::Pocket,
+            folder_name: "Pocket".to_string(),
+            images: true,
+            favicons: false,
+            colors: Colors::Auto,
+            article_timeout: 180,
+            sync_timeout: 1200,
+            read_only_upstream: false,
+            reconcile_every: 10,
+            reconcile_to: ReconcileTarget::Archive,
+            reconcile_opened: false,
+            device_archive: DeviceArchive::Folder,
+            uuid_source: UuidSource::Url,
+            xochitl_root: None,
+            xochitl_roots: Vec::new(),
+            post_article_cmd: None,
+            post_sync_cmd: None,
+            hook_timeout: 30,
+            monthly_budget_mb: None,
+            sync_interval: 0,
+            wake_grace: 120,
+            max_actions: 30,
+            pocket_attempts: 4,
+            pocket_retry_delay: 1,
+            skip_url_patterns: Vec::new(),
+            languages: Vec::new(),
+            max_item_age_days: None,
+            old_items: OldItems::Ignore,
+            min_word_count: None,
+            max_word_count: None,
+            off_length_items: OldItems::Ignore,
+            excluded_tags: Vec::new(),
+            tag_excluded: false,
+            large_item_mb: None,
+            max_item_mb: None,
+            pocket_read: PocketRead::Archive,
+            favorite_read: false,
+            cookies: Cookies::default(),
+            github_token: None,
+            epub_soft_limit_mb: 30,
+            epub_hard_limit_mb: 50,
+            epub_soft_images: 300,
+            epub_hard_images: 1000,
+            strict_credentials: false,
+            sanitization: Sanitization::default(),
+            pin_tag: "keep".to_string(),
+            trigger_tag: String::new(),
+            queue_tag_prefix: String::new(),
+            status_tags: StatusTags::default(),
+            schedule: Schedule::default(),
+            progress_tags: false,
+            failure_placeholders: false,
+            library_chapter_size: 0,
+            locale: None,
+            remote: Remote::default(),
+            archive_mirror: ArchiveMirror::default(),
+            query: QuerySettings::default(),
+            queries: Vec::new(),
+        }
+    }
+}
+
+
+impl Settings {
+    pub fn load() -> Self {
+        match read_to_string(settings_file()) {
+            Ok(data) => Self::from_toml(&data),
+            Err(_) => Self::default(),
+        }
+    }
+
+
+    // The same, but a file that doesn't parse is an error rather than the defaults, for what would
+    // otherwise archive and tag in Pocket against a read_only_upstream it couldn't see.
+    pub fn try_load() -> Result<Self, String> {
+        match read_to_string(settings_file()) {
+            Ok(data) => Self::parse(&data).map_err(|e| format!("Could not parse {}: {e}", settings_file())),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+
+    pub fn from_toml(data: &str) -> Self {
+        match Self::parse(data) {
+            Ok(settings) => settings,
+            Err(e) => {
+                println!("🚨 Could not parse {}, using defaults: {e}", settings_file());
+                Self { read_only_upstream: read_only_line(data), ..Self::default() }
+            },
+        }
+    }
+
+
+    // A value that can't be used, e.g. article_timeout = "soon", leaves its field to the default
+    // and the rest of the file as it is. Only a file that isn't TOML at all is an error.
+    fn parse(data: &str) -> Result<Self, toml::de::Error> {
+        let mut table = toml::from_str::<toml::Table>(data)?;
+
+        for (key, value) in table.clone() {
+            let field = toml::Table::from_iter([(key.clone(), value)]);
+            if let Err(e) = toml::Value::Table(field).try_into::<Self>() {
+                println!("🚨 Could not use {key} in {}, using its default: {}", settings_file(), e.message());
+                table.remove(&key);
+            }
+        }
+
+        let mut settings: Self = toml::Value::Table(table).try_into()?;
+
+        for problem in settings.sanitization.problems() {
+            println!("🚨 In the [sanitization] of {}: {problem}, ignoring it", settings_file());
+        }
+        for problem in settings.query.validate() {
+            println!("🚨 In the [query] of {}: {problem}", settings_file());
+        }
+        for problem in settings.validate_queries() {
+            println!("🚨 In the [[queries]] of {}: {problem}", settings_file());
+        }
+        if settings.colors == Colors::Color && !cfg!(feature = "color") {
+            println!("🚨 colors = \"color\" in {}, but colors aren't compiled in, build with --features color", settings_file());
+        }
+        if let Some(locale) = settings.locale.as_deref().filter(|locale| strings::find(locale).is_none()) {
+            println!("🚨 No strings for the locale {locale} in {}, using English", settings_file());
+        }
+
+        Ok(settings)
+    }
+
+
+    pub fn sync_count(&self) -> u8 {
+        self.sync_count
+    }
+
+
+    pub fn retrieve_max(&self) -> usize {
+        self.retrieve_max.max(1)
+    }
+
+
+    pub fn max_items_per_cycle(&self) -> usize {
+        self.max_items_per_cycle.max(1)
+    }
+
+
+    pub fn item_order(&self) -> ItemOrder {
+        self.item_order
+    }
+
+
+    pub fn folder_name(&self) -> &str {
+        &self.folder_name
+    }
+
+
+    pub fn images(&self) -> bool {
+        self.images
+    }
+
+
+    pub fn favicons(&self) -> bool {
+        self.favicons
+    }
+
+
+    pub fn color_policy(&self) -> ColorPolicy {
+        match self.colors {
+            _ if !cfg!(feature = "color") => ColorPolicy::Greyscale,
+            Colors::Auto => ColorPolicy::for_device(device::kind()),
+            Colors::Color => ColorPolicy::Color,
+            Colors::Greyscale => ColorPolicy::Greyscale,
+        }
+    }
+
+
+    pub fn article_timeout(&self) -> Duration {
+        Duration::from_secs(self.article_timeout)
+    }
+
+
+    pub fn sync_timeout(&self) -> Duration {
+        Duration::from_secs(self.sync_timeout)
+    }
+
+
+    pub fn read_only_upstream(&self) -> bool {
+        self.read_only_upstream
+    }
+
+
+    // Zero disables reconciliation altogether.
+    pub fn reconcile_every(&self) -> u64 {
+        self.reconcile_every
+    }
+
+
+    pub fn reconcile_to(&self) -> ReconcileTarget {
+        self.reconcile_to
+    }
+
+
+    pub fn reconcile_opened(&self) -> bool {
+        self.reconcile_opened
+    }
+
+
+    pub fn device_archive(&self) -> DeviceArchive {
+        self.device_archive
+    }
+
+
+    pub fn uuid_source(&self) -> UuidSource {
+        self.uuid_source
+    }
+
+
+    pub fn xochitl_root(&self) -> Option<&str> {
+        self.xochitl_roots.first().map(String::as_str).or(self.xochitl_root.as_deref())
+    }
+
+
+    pub fn extra_roots(&self) -> &[String] {
+        self.xochitl_roots.get(1..).unwrap_or_default()
+    }
+
+
+    pub fn post_article_cmd(&self) -> Option<&str> {
+        self.post_article_cmd.as_deref()
+    }
+
+
+    pub fn post_sync_cmd(&self) -> Option<&str> {
+        self.post_sync_cmd.as_deref()
+    }
+
+
+    pub fn hook_timeout(&self) -> Duration {
+        Duration::from_secs(self.hook_timeout)
+    }
+
+
+    // In bytes.
+    pub fn monthly_budget(&self) -> Option<u64> {
+        self.monthly_budget_mb.map(|mb| mb * 1024 * 1024)
+    }
+
+
+    pub fn sync_interval(&self) -> Duration {
+        Duration::from_secs(self.sync_interval)
+    }
+
+
+    pub fn wake_grace(&self) -> Duration {
+        Duration::from_secs(self.wake_grace)
+    }
+
+
+    pub fn max_actions(&self) -> usize {
+        self.max_actions
+    }
+
+
+    pub fn pocket_attempts(&self) -> u32 {
+        self.pocket_attempts.max(1)
+    }
+
+
+    pub fn pocket_retry_delay(&self) -> Duration {
+        Duration::from_secs(self.pocket_retry_delay)
+    }
+
+
+    pub fn skip_url_patterns(&self) -> &[String] {
+        &self.skip_url_patterns
+    }
+
+
+    pub fn languages(&self) -> &[String] {
+        &self.languages
+    }
+
+
+    pub fn max_item_age(&self) -> Option<Duration> {
+        self.max_item_age_days.map(|days| Duration::from_secs(days * 24 * 60 * 60))
+    }
+
+
+    pub fn old_items(&self) -> OldItems {
+        self.old_items
+    }
+
+
+    pub fn min_word_count(&self) -> Option<u64> {
+        self.min_word_count
+    }
+
+
+    pub fn max_word_count(&self) -> Option<u64> {
+        self.max_word_count
+    }
+
+
+    pub fn off_length_items(&self) -> OldItems {
+        self.off_length_items
+    }
+
+
+    pub fn excluded_tags(&self) -> &[String] {
+        &self.excluded_tags
+    }
+
+
+    pub fn tag_excluded(&self) -> bool {
+        self.tag_excluded
+    }
+
+
+    pub fn pocket_read(&self) -> PocketRead {
+        self.pocket_read
+    }
+
+
+    pub fn favorite_read(&self) -> bool {
+        self.favorite_read
+    }
+
+
+    pub fn cookies(&self) -> &Cookies {
+        &self.cookies
+    }
+
+
+    pub fn github_token(&self) -> Option<&str> {
+        self.github_token.as_deref()
+    }
+
+
+    pub fn sanitization(&self) -> &Sanitization {
+        &self.sanitization
+    }
+
+
+    pub fn strict_credentials(&self) -> bool {
+        self.strict_credentials
+    }
+
+
+    // Only with a host to push to.
+    pub fn remote(&self) -> Option<&Remote> {
+        Some(&self.remote).filter(|remote| !remote.host.is_empty())
+    }
+
+
+    pub fn pin_tag(&self) -> Option<&str> {
+        Some(self.pin_tag.as_str()).filter(|tag| !tag.is_empty())
+    }
+
+
+    // Only with a count.
+    pub fn archive_mirror(&self) -> Option<usize> {
+        Some(self.archive_mirror.count).filter(|count| *count > 0)
+    }
+
+
+    pub fn trigger_tag(&self) -> Option<&str> {
+        Some(self.trigger_tag.trim()).filter(|tag| !tag.is_empty())
+    }
+
+
+    pub fn queue_tag_prefix(&self) -> Option<&str> {
+        Some(self.queue_tag_prefix.trim()).filter(|prefix| !prefix.is_empty())
+    }
+
+
+    pub fn library_chapter_size(&self) -> Option<usize> {
+        Some(self.library_chapter_size).filter(|size| *size > 0)
+    }
+
+
+    // What's asked of Pocket for new items, unread ones, not favorites, the newest first, unless
+    // the [query] says otherwise.
+    pub fn query(&self) -> QueryBuilder {
+        self.query.builder(self.sync_count, self.trigger_tag())
+    }
+
+
+    // Every query of a sync by name, in order, either those in [[queries]] or else the [query].
+    pub fn queries(&self) -> Vec<(String, QueryBuilder)> {
+        if self.queries.is_empty() {
+            return vec![(DEFAULT_QUERY.to_string(), self.query())];
+        }
+
+        // Whatever the validation left is taken, every one of them named, see validate_queries().
+        self.queries.iter()
+            .filter_map(|query| Some((query.name.clone()?, query.builder(self.sync_count, None))))
+            .collect()
+    }
+
+
+    // The same as for the [query], and the same name twice would share the "since", only the first
+    // is kept. One without a name is left out, going by what's in it instead a change to any of it
+    // would start its "since" over.
+    fn validate_queries(&mut self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut names = BTreeSet::new();
+
+        if !self.queries.is_empty() && self.query != QuerySettings::default() {
+            problems.push("there's a [query] too, ignoring it".to_string());
+        }
+
+        for query in &mut self.queries {
+            problems.extend(query.validate());
+        }
+
+        self.queries.retain(|query| match &query.name {
+            Some(name) if !names.insert(name.clone()) => {
+                problems.push(format!("the name '{name}' is taken, ignoring the query"));
+                false
+            },
+            Some(_) => true,
+            None => {
+                problems.push("one of them has no name, ignoring it".to_string());
+                false
+            },
+        });
+
+        problems
+    }
+
+
+    pub fn status_tags(&self) -> &StatusTags {
+        &self.status_tags
+    }
+
+
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+
+    pub fn progress_tags(&self) -> bool {
+        self.progress_tags
+    }
+
+
+    pub fn failure_placeholders(&self) -> bool {
+        self.failure_placeholders
+    }
+
+
+    // English for a locale with no strings.
+    pub fn locale(&self) -> &'static Strings {
+        match &self.locale {
+            Some(locale) => strings::find(locale).unwrap_or(&strings::EN),
+            None => strings::from_env(),
+        }
+    }
+
+
+    // In bytes.
+    pub fn size_limits(&self) -> SizeLimits {
+        SizeLimits {
+            defer_bytes: self.large_item_mb.map(|mb| mb * 1024 * 1024),
+            skip_bytes: self.max_item_mb.map(|mb| mb * 1024 * 1024),
+        }
+    }
+
+
+    // The soft limits never above the hard ones.
+    pub fn epub_limits(&self) -> EpubLimits {
+        EpubLimits {
+            soft_bytes: self.epub_soft_limit_mb.min(self.epub_hard_limit_mb) * 1024 * 1024,
+            hard_bytes: self.epub_hard_limit_mb * 1024 * 1024,
+            soft_images: self.epub_soft_images.min(self.epub_hard_images),
+            hard_images: self.epub_hard_images,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_when_empty() {
+        let settings = Settings::from_toml("");
+
+        assert_eq!(settings.article_timeout(), Duration::from_secs(180));
+        assert_eq!(settings.sync_timeout(), Duration::from_secs(1200));
+        assert!(!settings.read_only_upstream());
+    }
+
+    #[test]
+    fn partial_settings() {
+        let settings = Settings::from_toml("article_timeout = 5");
+
+        assert_eq!(settings.article_timeout(), Duration::from_secs(5));
+        assert_eq!(settings.sync_timeout(), Duration::from_secs(1200));
+    }
+
+    #[test]
+    fn setup_settings() {
+        let settings = Settings::from_toml("sync_count = 25\nfolder_name = \"Lesen\"\nimages = false");
+
+        assert_eq!((settings.sync_count(), settings.folder_name(), settings.images()), (25, "Lesen", false));
+        assert_eq!(Settings::from_toml("").folder_name(), "Pocket");
+    }
+
+    #[test]
+    fn favicons() {
+        assert!(!Settings::from_toml("").favicons());
+        assert!(Settings::from_toml("favicons = true").favicons());
+    }
+
+    #[test]
+    fn reconcile_settings() {
+        let settings = Settings::from_toml("reconcile_every = 3\nreconcile_to = \"trash\"");
+
+        assert_eq!(settings.reconcile_every(), 3);
+        assert_eq!(settings.reconcile_to(), ReconcileTarget::Trash);
+        assert!(!settings.reconcile_opened());
+    }
+
+    #[test]
+    fn monthly_budget() {
+        assert_eq!(Settings::from_toml("").monthly_budget(), None);
+        assert_eq!(Settings::from_toml("monthly_budget_mb = 2").monthly_budget(), Some(2 * 1024 * 1024));
+    }
+
+    #[test]
+    fn size_limits() {
+        assert!(!Settings::from_toml("").size_limits().any());
+
+        let limits = Settings::from_toml("large_item_mb = 10\nmax_item_mb = 100").size_limits();
+        assert_eq!(limits.defer_bytes, Some(10 * 1024 * 1024));
+        assert_eq!(limits.skip_bytes, Some(100 * 1024 * 1024));
+    }
+
+    #[test]
+    fn epub_limits() {
+        assert_eq!(Settings::from_toml("").epub_limits(), EpubLimits::default());
+
+        let limits = Settings::from_toml("epub_soft_limit_mb = 80\nepub_hard_limit_mb = 20").epub_limits();
+        assert_eq!(limits.soft_bytes, 20 * 1024 * 1024);
+        assert_eq!(limits.hard_bytes, 20 * 1024 * 1024);
+    }
+
+    #[test]
+    fn sanitization() {
+        let settings = Settings::from_toml("[sanitization]\npreset = \"lenient\"\nremove_tags = [\"a\"]\n\
+            [sanitization.tag_attributes]\nimg = [\"alt\"]\n");
+        let sanitization = settings.sanitization();
+
+        assert_eq!(format!("{:?}", sanitization.preset), "Lenient");
+        assert_eq!(sanitization.remove_tags, vec!["a"]);
+        assert_eq!(sanitization.tag_attributes["img"], vec!["alt"]);
+        assert!(sanitization.problems().is_empty());
+        assert_eq!(Settings::from_toml("").sanitization(), &Sanitization::default());
+    }
+
+    #[test]
+    fn remote() {
+        assert_eq!(Settings::from_toml("").remote(), None);
+
+        let settings = Settings::from_toml("[remote]\nhost = \"192.168.1.20\"\nmethod = \"scp\"\nport = 2222");
+        let remote = settings.remote().unwrap();
+        assert_eq!((remote.host.as_str(), remote.port, remote.method), ("192.168.1.20", 2222, crate::remote::Method::Scp));
+        assert_eq!(remote.user, "root");
+        assert_eq!(remote.refresh_cmd, "systemctl restart xochitl");
+    }
+
+    #[test]
+    fn pin_tag() {
+        assert_eq!(Settings::from_toml("").pin_tag(), Some("keep"));
+        assert_eq!(Settings::from_toml("pin_tag = \"reference\"").pin_tag(), Some("reference"));
+        assert_eq!(Settings::from_toml("pin_tag = \"\"").pin_tag(), None);
+    }
+
+    #[test]
+    fn query() {
+        let query = |toml: &str| serde_json::to_value(Settings::from_toml(toml).query().build().unwrap()).unwrap();
+
+        assert_eq!(query(""), serde_json::json!({
+            "state": "unread", "favorite": 0, "sort": "newest", "detailType": "complete",
+            "count": 10, "offset": 0, "total": 1,
+        }));
+        assert_eq!(query("trigger_tag = \"to-remarkable\"")["tag"], "to-remarkable");
+
+        let set = query("sync_count = 5\ntrigger_tag = \"to-remarkable\"\n[query]\ntag = \"remarkable\"\ncount = 30\n\
+            state = \"all\"\nfavorite = 1\ncontent_type = \"Video\"\nsort = \"OLDEST\"");
+        assert_eq!((&set["tag"], &set["count"], &set["state"], &set["favorite"]), (&"remarkable".into(), &30.into(), &"all".into(), &1.into()));
+        assert_eq!((&set["contentType"], &set["sort"]), (&"video".into(), &"oldest".into()));
+
+        let untagged = Settings::from_toml("trigger_tag = \"to-remarkable\"\n[query]\ntag = \"_untagged_\"\nexclude_tags = [\"later\"]").query().build().unwrap();
+        assert_eq!(serde_json::to_value(&untagged).unwrap()["tag"], "_untagged_");
+        let later = crate::testutil::tagged(1, "https://example.com/article", &["later"]);
+        assert!(!untagged.keeps(&later));
+
+        // What Pocket wouldn't take is left for the default.
+        let settings = Settings::from_toml("[query]\nstate = \"unred\"\nfavorite = 7\nsort = \"title\"\nlimit = 5");
+        assert_eq!(settings.query, QuerySettings { sort: Some("title".to_string()), ..QuerySettings::default() });
+        let fallen_back = serde_json::to_value(settings.query().build().unwrap()).unwrap();
+        assert_eq!((&fallen_back["state"], &fallen_back["favorite"], &fallen_back["sort"]), (&"unread".into(), &0.into(), &"title".into()));
+    }
+
+    #[test]
+    fn queries() {
+        let queries = |toml: &str| -> Vec<(String, serde_json::Value)> {
+            Settings::from_toml(toml).queries().into_iter()
+                .map(|(name, query)| (name, serde_json::to_value(query.build().unwrap()).unwrap()))
+                .collect()
+        };
+
+        // Just the [query] if there are no [[queries]].
+        let single = queries("trigger_tag = \"to-remarkable\"");
+        assert_eq!(single.len(), 1);
+        assert_eq!((single[0].0.as_str(), &single[0].1["tag"]), (DEFAULT_QUERY, &"to-remarkable".into()));
+
+        // In order, the trigger_tag isn't theirs, and the same name twice is left out.
+        let several = queries("trigger_tag = \"to-remarkable\"\n[query]\ntag = \"ignored\"\n\
+            [[queries]]\nname = \"favorites\"\nfavorite = 1\n[[queries]]\nname = \"tagged\"\ntag = \"rm\"\n[[queries]]\nname = \"favorites\"\nstate = \"all\"");
+        let names: Vec<&str> = several.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["favorites", "tagged"]);
+        assert_eq!((&several[0].1["favorite"], several[0].1.get("tag")), (&1.into(), None));
+        assert_eq!((&several[1].1["tag"], &several[1].1["state"]), (&"rm".into(), &"unread".into()));
+
+        // One without a name is left out, and only that one.
+        let unnamed = Settings::from_toml("sync_count = 25\n[[queries]]\nname = \"favorites\"\nfavorite = 1\n[[queries]]\ntag = \"rm\"");
+        assert_eq!(unnamed.queries().into_iter().map(|(name, _)| name).collect::<Vec<_>>(), vec!["favorites"]);
+        assert_eq!(unnamed.sync_count, 25);
+    }
+
+    #[test]
+    fn trigger_tag() {
+        assert_eq!(Settings::from_toml("").trigger_tag(), None);
+        assert_eq!(Settings::from_toml("trigger_tag = \"to-remarkable\"").trigger_tag(), Some("to-remarkable"));
+    }
+
+    #[test]
+    fn queue_tag_prefix() {
+        assert_eq!(Settings::from_toml("").queue_tag_prefix(), None);
+        assert_eq!(Settings::from_toml("queue_tag_prefix = \" \"").queue_tag_prefix(), None);
+        assert_eq!(Settings::from_toml("queue_tag_prefix = \"rm-\"").queue_tag_prefix(), Some("rm-"));
+    }
+
+    #[test]
+    fn library_chapter_size() {
+        assert_eq!(Settings::from_toml("").library_chapter_size(), None);
+        assert_eq!(Settings::from_toml("library_chapter_size = 200").library_chapter_size(), Some(200));
+        assert_eq!(Settings::from_toml("library_chapter_size = 0").library_chapter_size(), None);
+    }
+
+    #[test]
+    fn progress_tags() {
+        assert!(!Settings::from_toml("").progress_tags());
+        assert!(Settings::from_toml("progress_tags = true").progress_tags());
+    }
+
+    #[test]
+    fn languages() {
+        assert!(Settings::from_toml("").languages().is_empty());
+        assert_eq!(Settings::from_toml("languages = [\"es\", \"pt-BR\"]").languages(), ["es", "pt-BR"]);
+    }
+
+    #[test]
+    fn max_item_age() {
+        let settings = Settings::from_toml("");
+        assert_eq!(settings.max_item_age(), None);
+        assert_eq!(settings.old_items(), OldItems::Ignore);
+
+        let settings = Settings::from_toml("max_item_age_days = 14\nold_items = \"archive\"");
+        assert_eq!(settings.max_item_age(), Some(Duration::from_secs(14 * 86400)));
+        assert_eq!(settings.old_items(), OldItems::Archive);
+    }
+
+    #[test]
+    fn word_counts() {
+        let settings = Settings::from_toml("");
+        assert_eq!((settings.min_word_count(), settings.max_word_count()), (None, None));
+        assert_eq!(settings.off_length_items(), OldItems::Ignore);
+
+        let settings = Settings::from_toml("min_word_count = 300\nmax_word_count = 20000\noff_length_items = \"archive\"");
+        assert_eq!((settings.min_word_count(), settings.max_word_count()), (Some(300), Some(20000)));
+        assert_eq!(settings.off_length_items(), OldItems::Archive);
+    }
+
+    #[test]
+    fn excluded_tags() {
+        let settings = Settings::from_toml("");
+        assert!(settings.excluded_tags().is_empty());
+        assert!(!settings.tag_excluded());
+
+        let settings = Settings::from_toml("excluded_tags = [\"recipe\", \"gift-ideas\"]\ntag_excluded = true");
+        assert_eq!(settings.excluded_tags(), ["recipe", "gift-ideas"]);
+        assert!(settings.tag_excluded());
+    }
+
+    #[test]
+    fn pocket_read() {
+        assert_eq!(Settings::from_toml("").pocket_read(), PocketRead::Archive);
+        assert_eq!(Settings::from_toml("pocket_read = \"delete\"").pocket_read(), PocketRead::Delete);
+    }
+
+    #[test]
+    fn favorite_read() {
+        assert!(!Settings::from_toml("").favorite_read());
+        assert!(Settings::from_toml("favorite_read = true").favorite_read());
+    }
+
+    #[test]
+    fn retrieve_max() {
+        assert_eq!(Settings::from_toml("").retrieve_max(), 300);
+        assert_eq!(Settings::from_toml("retrieve_max = 80").retrieve_max(), 80);
+        assert_eq!(Settings::from_toml("retrieve_max = 0").retrieve_max(), 1);
+    }
+
+    #[test]
+    fn pocket_retries() {
+        let settings = Settings::from_toml("");
+        assert_eq!((settings.pocket_attempts(), settings.pocket_retry_delay()), (4, Duration::from_secs(1)));
+        let settings = Settings::from_toml("pocket_attempts = 0
+pocket_retry_delay = 10");
+        assert_eq!((settings.pocket_attempts(), settings.pocket_retry_delay()), (1, Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn max_items_per_cycle() {
+        assert_eq!(Settings::from_toml("").max_items_per_cycle(), 10);
+        assert_eq!(Settings::from_toml("max_items_per_cycle = 25").max_items_per_cycle(), 25);
+        assert_eq!(Settings::from_toml("max_items_per_cycle = 0").max_items_per_cycle(), 1);
+    }
+
+    #[test]
+    fn item_order() {
+        assert_eq!(Settings::from_toml("").item_order(), ItemOrder::Pocket);
+        assert_eq!(Settings::from_toml("item_order = \"newest\"").item_order(), ItemOrder::Newest);
+        assert_eq!(Settings::from_toml("item_order = \"oldest\"").item_order(), ItemOrder::Oldest);
+    }
+
+    #[test]
+    fn failure_placeholders() {
+        assert!(!Settings::from_toml("").failure_placeholders());
+        assert!(Settings::from_toml("failure_placeholders = true").failure_placeholders());
+    }
+
+    #[test]
+    fn locale() {
+        assert_eq!(Settings::from_toml("locale = \"de_AT.UTF-8\"").locale(), &strings::DE);
+        assert_eq!(Settings::from_toml("locale = \"fr\"").locale(), &strings::FR);
+        assert_eq!(Settings::from_toml("locale = \"tlh\"").locale(), &strings::EN);
+    }
+
+    #[test]
+    #[cfg(feature = "color")]
+    fn color_policy() {
+        assert_eq!(Settings::from_toml("").color_policy(), ColorPolicy::for_device(device::kind()));
+        assert_eq!(Settings::from_toml("colors = \"color\"").color_policy(), ColorPolicy::Color);
+        assert_eq!(Settings::from_toml("colors = \"greyscale\"").color_policy(), ColorPolicy::Greyscale);
+    }
+
+    #[test]
+    #[cfg(not(feature = "color"))]
+    fn no_colors_compiled_in() {
+        assert_eq!(Settings::from_toml("colors = \"color\"").color_policy(), ColorPolicy::Greyscale);
+    }
+
+    #[test]
+    fn archive_mirror() {
+        assert_eq!(Settings::from_toml("").archive_mirror(), None);
+        assert_eq!(Settings::from_toml("archive_mirror = { count = 20 }").archive_mirror(), Some(20));
+        assert_eq!(Settings::from_toml("[archive_mirror]\ncount = 0").archive_mirror(), None);
+    }
+
+    #[test]
+    fn uuid_source() {
+        assert_eq!(Settings::from_toml("").uuid_source(), UuidSource::Url);
+        assert_eq!(Settings::from_toml("uuid_source = \"item_id\"").uuid_source(), UuidSource::ItemId);
+    }
+
+    #[test]
+    fn xochitl_roots() {
+        assert_eq!(Settings::from_toml("").xochitl_root(), None);
+        assert!(Settings::from_toml("xochitl_root = \"/a\"").extra_roots().is_empty());
+
+        let settings = Settings::from_toml("xochitl_root = \"/a\"\nxochitl_roots = [\"/b\", \"/c\", \"/d\"]");
+        assert_eq!(settings.xochitl_root(), Some("/b"));
+        assert_eq!(settings.extra_roots(), ["/c", "/d"]);
+    }
+
+    #[test]
+    fn schedule() {
+        assert!(Settings::from_toml("").schedule().is_always());
+        assert!(!Settings::from_toml("[schedule]\ndays = [\"sat\"]").schedule().is_always());
+        assert_eq!(Settings::from_toml("[schedule]\nwatch = \"respect\"").schedule().watch(), crate::schedule::WatchSyncs::Respect);
+        assert!(Settings::from_toml("[schedule]\nhours = \"evening\"").schedule().is_always());
+    }
+
+    // Not even TOML, but read_only_upstream is still read on its own line, and only there.
+    #[test]
+    fn unparsable_keeps_read_only() {
+        assert!(Settings::from_toml("read_only_upstream = true\narticle_timeout = ").read_only_upstream());
+        assert!(!Settings::from_toml("read_only_upstream = false\narticle_timeout = ").read_only_upstream());
+        assert!(!Settings::from_toml("article_timeout = \n[remote]\nread_only_upstream = true").read_only_upstream());
+    }
+
+    #[test]
+    fn invalid_settings_fall_back_one_at_a_time() {
+        let settings = Settings::from_toml("article_timeout = \"soon\"\nread_only_upstream = true\nsync_count = 25\n\
+            item_order = \"random\"\ntrigger_tag = \"to-remarkable\"\n[schedule]\nhours = 5");
+
+        assert_eq!(settings.article_timeout(), Duration::from_secs(180));
+        assert_eq!(settings.item_order(), ItemOrder::Pocket);
+        assert_eq!(settings.schedule, Schedule::default());
+        assert!(settings.read_only_upstream());
+        assert_eq!(settings.sync_count(), 25);
+        assert_eq!(settings.trigger_tag(), Some("to-remarkable"));
+    }
+}
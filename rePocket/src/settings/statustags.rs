@@ -0,0 +1,99 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use serde::Deserialize;
+
+
+// The tags as they've always been, unless the settings say otherwise.
+pub const ARCHIVED_TAG: &str = "repocket";
+pub const SKIPPED_TAG: &str = "repocket-skipped";
+
+
+//
+// The tags rePocket leaves on items in Pocket, for those who live in the Pocket app, as in the
+// settings:
+//
+// [status_tags]
+// archived = "repocket"
+// skipped = "repocket-skipped"
+// error = "repocket-error"
+//
+// Archived is for the items read on the device, skipped for those it wouldn't even fetch, and error
+// for those it couldn't write, taken off again if it later can. An empty tag turns it off, error
+// is off unless set.
+//
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct StatusTags {
+    archived: String,
+    skipped: String,
+    error: String,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Archived,
+    Skipped,
+    Error,
+}
+
+
+impl Default for StatusTags {
+    fn default() -> Self {
+        Self {
+            archived: ARCHIVED_TAG.to_string(),
+            skipped: SKIPPED_TAG.to_string(),
+            error: String::new(),
+        }
+    }
+}
+
+
+impl StatusTags {
+    // The tag for items in that state, if any.
+    pub fn tag(&self, status: Status) -> Option<&str> {
+        let tag = match status {
+            Status::Archived => &self.archived,
+            Status::Skipped => &self.skipped,
+            Status::Error => &self.error,
+        };
+
+        Some(tag.trim()).filter(|tag| !tag.is_empty())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    #[test]
+    fn mapping() {
+        let tags = Settings::from_toml("").status_tags().clone();
+        assert_eq!(tags.tag(Status::Archived), Some(ARCHIVED_TAG));
+        assert_eq!(tags.tag(Status::Skipped), Some(SKIPPED_TAG));
+        assert_eq!(tags.tag(Status::Error), None);
+
+        let settings = Settings::from_toml("[status_tags]\narchived = \"read\"\nskipped = \"\"\nerror = \" repocket-error \"");
+        let tags = settings.status_tags();
+        assert_eq!(tags.tag(Status::Archived), Some("read"));
+        assert_eq!(tags.tag(Status::Skipped), None);
+        assert_eq!(tags.tag(Status::Error), Some("repocket-error"));
+    }
+}
@@ -0,0 +1,513 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::fshandler::FSHandler;
+use crate::pocket::Pocket;
+use crate::pocket::auth::{self, Auth};
+use crate::pocket::credentials::Credentials;
+use crate::pocketquery::QueryBuilder;
+use crate::settings::Settings;
+
+
+//
+// `repocket setup`, for a first run. It goes through these steps, in order:
+//
+// - credentials: get a consumer key, authorize rePocket and write the credentials file.
+// - settings: a few questions, written to the settings file.
+// - connection: ask Pocket for a single item with those credentials.
+// - folders: make the Pocket folder on the device.
+// - service: install the systemd unit, and enable it.
+//
+// Each one is asked for first, can be left out with --skip, and leaves alone whatever is already
+// there unless told otherwise, so running setup again only does what's missing. With --defaults
+// nothing is asked, the default answer is taken every time.
+//
+pub const STEPS: [&str; 5] = ["credentials", "settings", "connection", "folders", "service"];
+
+const UNIT: &str = include_str!("../../repocket.service");
+const UNIT_NAME: &str = "repocket";
+
+
+// The questions, answered on the terminal or with the defaults.
+pub trait Prompt {
+    // The answer, or the default for an empty one.
+    fn ask(&mut self, question: &str, default: &str) -> String;
+
+    fn confirm(&mut self, question: &str, default: bool) -> bool {
+        let answer = self.ask(&format!("{question} (y/n)"), if default { "y" } else { "n" });
+
+        matches!(answer.to_lowercase().as_str(), "y" | "yes")
+    }
+}
+
+
+pub struct Terminal;
+
+
+impl Prompt for Terminal {
+    fn ask(&mut self, question: &str, default: &str) -> String {
+        match default.is_empty() {
+            true => print!("{question}: "),
+            false => print!("{question} [{default}]: "),
+        }
+        let _ = io::stdout().flush();
+
+        let mut answer = String::new();
+        let _ = io::stdin().lock().read_line(&mut answer);
+
+        match answer.trim() {
+            "" => default.to_string(),
+            answer => answer.to_string(),
+        }
+    }
+}
+
+
+pub struct Defaults;
+
+
+impl Prompt for Defaults {
+    fn ask(&mut self, question: &str, default: &str) -> String {
+        println!("{question} [{default}]: {default}");
+
+        default.to_string()
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Done(String),
+    // Already there, left alone.
+    Kept(String),
+    Skipped,
+    Failed(String),
+}
+
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Done(what) => write!(f, "{what}"),
+            Outcome::Kept(what) => write!(f, "{what}, left alone"),
+            Outcome::Skipped => write!(f, "skipped"),
+            Outcome::Failed(why) => write!(f, "failed, {why}"),
+        }
+    }
+}
+
+
+// What gets written to the settings file.
+#[derive(Debug, PartialEq)]
+pub struct Answers {
+    pub sync_count: u8,
+    pub folder_name: String,
+    pub images: bool,
+    // In minutes, zero for only the Sync folder.
+    pub sync_interval: u64,
+}
+
+
+impl Default for Answers {
+    fn default() -> Self {
+        let settings = Settings::default();
+
+        Self {
+            sync_count: settings.sync_count(),
+            folder_name: settings.folder_name().to_string(),
+            images: settings.images(),
+            sync_interval: settings.sync_interval().as_secs() / 60,
+        }
+    }
+}
+
+
+impl Answers {
+    fn ask(prompt: &mut dyn Prompt) -> Self {
+        let defaults = Self::default();
+
+        Self {
+            sync_count: prompt.ask("Articles to get in each sync, up to 30", &defaults.sync_count.to_string())
+                .parse().unwrap_or(defaults.sync_count).clamp(1, 30),
+            folder_name: Some(prompt.ask("Name of the folder on the device", &defaults.folder_name))
+                .filter(|name| !name.trim().is_empty())
+                .unwrap_or(defaults.folder_name),
+            images: prompt.confirm("Download the articles' images", defaults.images),
+            sync_interval: prompt.ask("Minutes between syncs, 0 to only sync from the Sync folder", &defaults.sync_interval.to_string())
+                .parse().unwrap_or(defaults.sync_interval),
+        }
+    }
+
+
+    pub fn toml(&self) -> String {
+        format!(
+            "# Written by repocket setup, every other setting is described in the README.\n\
+            sync_count = {}\n\
+            folder_name = {}\n\
+            images = {}\n\
+            sync_interval = {}\n",
+            self.sync_count,
+            toml::Value::String(self.folder_name.clone()),
+            self.images,
+            self.sync_interval * 60,
+        )
+    }
+}
+
+
+// Where everything goes, made up in the tests.
+pub struct Paths {
+    pub creds: PathBuf,
+    pub settings: PathBuf,
+    pub unit: PathBuf,
+}
+
+
+pub struct Setup<'a> {
+    prompt: &'a mut dyn Prompt,
+    paths: Paths,
+    api: String,
+    consumer_key: Option<String>,
+    skip: Vec<String>,
+    on_device: bool,
+    systemctl: String,
+}
+
+
+impl<'a> Setup<'a> {
+    pub fn new(prompt: &'a mut dyn Prompt, paths: Paths, on_device: bool) -> Self {
+        Self {
+            prompt,
+            paths,
+            api: crate::pocket::API_URL.to_string(),
+            consumer_key: None,
+            skip: Vec::new(),
+            on_device,
+            systemctl: "systemctl".to_string(),
+        }
+    }
+
+
+    pub fn set_api(mut self, api: &str) -> Self {
+        self.api = api.to_string();
+
+        self
+    }
+
+
+    pub fn set_consumer_key(mut self, consumer_key: Option<String>) -> Self {
+        self.consumer_key = consumer_key;
+
+        self
+    }
+
+
+    pub fn set_skip(mut self, skip: Vec<String>) -> Self {
+        self.skip = skip;
+
+        self
+    }
+
+
+    pub fn set_systemctl(mut self, systemctl: &str) -> Self {
+        self.systemctl = systemctl.to_string();
+
+        self
+    }
+
+
+    // Every step, in order, and what came of it. A failed step doesn't stop the ones after it,
+    // they say for themselves whether they can go on.
+    pub async fn run(&mut self, fhandler: &FSHandler) -> Vec<(&'static str, Outcome)> {
+        let mut outcomes = Vec::new();
+
+        for step in STEPS {
+            let outcome = match self.skip.iter().any(|skip| skip == step) {
+                true => Outcome::Skipped,
+                false => match step {
+                    "credentials" => self.credentials().await,
+                    "settings" => self.settings(),
+                    "connection" => self.connection().await,
+                    "folders" => self.folders(fhandler),
+                    _ => self.service(),
+                },
+            };
+
+            match &outcome {
+                Outcome::Failed(_) => println!("🚨 {step}: {outcome}"),
+                _ => println!("ℹ {step}: {outcome}"),
+            }
+            outcomes.push((step, outcome));
+        }
+
+        outcomes
+    }
+
+
+    async fn credentials(&mut self) -> Outcome {
+        let path = self.paths.creds.display().to_string();
+
+        match Credentials::load(&self.paths.creds) {
+            Ok(_) if !self.prompt.confirm(&format!("There are credentials in {path}, authorize again"), false) => {
+                return Outcome::Kept(format!("credentials in {path}"));
+            },
+            Err(e) if self.paths.creds.exists() => println!("🚨 {e}, they can't be used"),
+            _ => (),
+        }
+        if !self.prompt.confirm("Authorize rePocket with Pocket", true) {
+            return Outcome::Skipped;
+        }
+
+        let consumer_key = match self.consumer_key.clone() {
+            Some(key) => key,
+            None => {
+                println!("ℹ rePocket needs a consumer key of your own. Make one at {} with the Retrieve and Modify permissions", auth::DEVELOPER_URL);
+                self.prompt.ask("Consumer key, e.g. 123456-0123456789abcdef0c0ffee", "")
+            },
+        };
+        if consumer_key.trim().is_empty() {
+            return Outcome::Failed("there's no consumer key".to_string());
+        }
+
+        let auth = Auth::new(&self.api, &consumer_key);
+        let code = match auth.request_token().await {
+            Ok(code) => code,
+            Err(e) => return Outcome::Failed(format!("could not get a request token, is the consumer key right? {e}")),
+        };
+
+        let url = Auth::authorize_url(&code);
+        match self.on_device {
+            true => println!("ℹ Open this on your phone or computer, and authorize rePocket:\n  {url}"),
+            false => {
+                println!("ℹ Authorize rePocket at:\n  {url}");
+                let _ = Command::new(if cfg!(target_os = "macos") { "open" } else { "xdg-open" }).arg(&url).spawn();
+            },
+        }
+        self.prompt.ask("Press Enter once rePocket is authorized", "");
+
+        match auth.access_token(&code).await {
+            Ok((creds, username)) => match creds.save(&self.paths.creds) {
+                Ok(()) => Outcome::Done(format!("authorized for {username}, credentials in {path}")),
+                Err(e) => Outcome::Failed(format!("could not write {path}: {e}")),
+            },
+            Err(e) => Outcome::Failed(format!("rePocket wasn't authorized: {e}")),
+        }
+    }
+
+
+    fn settings(&mut self) -> Outcome {
+        let path = self.paths.settings.display().to_string();
+
+        if self.paths.settings.exists() && !self.prompt.confirm(&format!("Replace the settings in {path}"), false) {
+            return Outcome::Kept(format!("settings in {path}"));
+        }
+        if !self.prompt.confirm("Write the settings", true) {
+            return Outcome::Skipped;
+        }
+
+        let answers = Answers::ask(&mut *self.prompt);
+
+        match write(&self.paths.settings, &answers.toml()) {
+            Ok(()) => Outcome::Done(format!("settings in {path}")),
+            Err(e) => Outcome::Failed(format!("could not write {path}: {e}")),
+        }
+    }
+
+
+    async fn connection(&mut self) -> Outcome {
+        let creds = match Credentials::load(&self.paths.creds) {
+            Ok(creds) => creds,
+            Err(e) => return Outcome::Failed(format!("no credentials to try, {e}")),
+        };
+        if !self.prompt.confirm("Check that Pocket answers", true) {
+            return Outcome::Skipped;
+        }
+
+        let pocket = Pocket::with_credentials(&self.api, creds);
+        let query = QueryBuilder::default()
+            .set_state("All")
+            .set_detail_type("Simple")
+            .set_count(1)
+            .build()
+            .unwrap();
+
+        match pocket.item_statuses(&query).await {
+            Ok(_) => Outcome::Done("Pocket answered".to_string()),
+            Err(e) => Outcome::Failed(e.to_string()),
+        }
+    }
+
+
+    fn folders(&mut self, fhandler: &FSHandler) -> Outcome {
+        if fhandler.has_pocket_folder() {
+            return Outcome::Kept("the folders on the device".to_string());
+        }
+        if !self.prompt.confirm("Make the folders on the device", true) {
+            return Outcome::Skipped;
+        }
+
+        let settings = fs::read_to_string(&self.paths.settings)
+            .map(|text| Settings::from_toml(&text))
+            .unwrap_or_default();
+
+        match fhandler.mkdir_pocket_named(settings.folder_name()) {
+            Ok(()) => Outcome::Done(format!("made the {} folder", settings.folder_name())),
+            Err(e) => Outcome::Failed(format!("could not make the folders: {e}")),
+        }
+    }
+
+
+    fn service(&mut self) -> Outcome {
+        let path = self.paths.unit.display().to_string();
+        let installed = fs::read_to_string(&self.paths.unit).is_ok_and(|unit| unit == UNIT);
+
+        // There's only a systemd unit for the device.
+        if !self.prompt.confirm("Install the service, to sync whenever the Sync folder is opened", self.on_device) {
+            return Outcome::Skipped;
+        }
+
+        if !installed {
+            if let Err(e) = write(&self.paths.unit, UNIT) {
+                return Outcome::Failed(format!("could not write {path}: {e}"));
+            }
+        }
+
+        // Both are fine to run again.
+        for args in [vec!["daemon-reload"], vec!["enable", "--now", UNIT_NAME]] {
+            match Command::new(&self.systemctl).args(&args).status() {
+                Ok(status) if status.success() => (),
+                Ok(status) => return Outcome::Failed(format!("{} {} exited with {status}", self.systemctl, args.join(" "))),
+                Err(e) => return Outcome::Failed(format!("could not run {}: {e}", self.systemctl)),
+            }
+        }
+
+        match installed {
+            true => Outcome::Kept(format!("the service in {path}, enabled")),
+            false => Outcome::Done(format!("installed the service in {path}, and enabled it")),
+        }
+    }
+}
+
+
+fn write(path: &std::path::Path, text: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, text)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use crate::fshandler::root::xochitl_root;
+    use crate::mockserver::{self, Reply};
+    use uuid::Uuid;
+
+    fn json(value: serde_json::Value) -> Reply {
+        Reply::ok("application/json", value.to_string().as_bytes())
+    }
+
+    fn paths() -> Paths {
+        let base = std::env::temp_dir().join(format!("repocket-setup-{}", Uuid::new_v4()));
+
+        Paths {
+            creds: base.join(".repocket/.repocket.key"),
+            settings: base.join("repocket.toml"),
+            unit: base.join("systemd/repocket.service"),
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn setup_with_the_defaults() {
+        let _ = fs::create_dir_all(xochitl_root());
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/oauth/request", json(serde_json::json!({ "code": "request-code" }))),
+            ("/v3/oauth/authorize", json(serde_json::json!({ "access_token": "5678defa-1234-5678-9abc-def012345678", "username": "reader" }))),
+            ("/v3/get", json(serde_json::json!({ "status": 1, "since": 1729763686, "list": {} }))),
+        ]).await;
+        let api = format!("{api}/v3");
+        let paths = paths();
+        let fhandler = FSHandler::new();
+
+        let mut prompt = Defaults;
+        let mut setup = Setup::new(&mut prompt, paths, true)
+            .set_api(&api)
+            .set_consumer_key(Some("123456-abcdef\n".to_string()))
+            .set_systemctl("true");
+
+        let outcomes = setup.run(&fhandler).await;
+        assert!(outcomes.iter().all(|(_, outcome)| matches!(outcome, Outcome::Done(_))), "{outcomes:?}");
+
+        let creds = Credentials::load(&setup.paths.creds).unwrap();
+        assert_eq!((creds.consumer_key.as_str(), creds.access_token.as_str()), ("123456-abcdef", "5678defa-1234-5678-9abc-def012345678"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&setup.paths.creds).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let settings = Settings::from_toml(&fs::read_to_string(&setup.paths.settings).unwrap());
+        assert_eq!((settings.sync_count(), settings.folder_name(), settings.images()), (10, "Pocket", true));
+        assert!(fhandler.has_pocket_folder());
+        assert_eq!(fs::read_to_string(&setup.paths.unit).unwrap(), UNIT);
+
+        let requests = requests.lock().unwrap();
+        let paths: Vec<&str> = requests.iter().map(|request| request.path.as_str()).collect();
+        assert_eq!(paths, ["/v3/oauth/request", "/v3/oauth/authorize", "/v3/get"]);
+        assert!(requests[1].body.contains("request-code"));
+        assert!(requests[2].body.contains("\"access_token\":\"5678defa-1234-5678-9abc-def012345678\""));
+        drop(requests);
+
+        // Once more, everything is already there.
+        let outcomes = setup.run(&fhandler).await;
+        let kept: Vec<&str> = outcomes.iter().filter(|(_, outcome)| matches!(outcome, Outcome::Kept(_))).map(|(step, _)| *step).collect();
+        assert_eq!(kept, ["credentials", "settings", "folders", "service"]);
+        assert!(matches!(outcomes[2].1, Outcome::Done(_)));
+    }
+
+    #[tokio::test]
+    async fn setup_without_a_consumer_key_or_the_service() {
+        let api = mockserver::serve(vec![
+            ("/v3/oauth/request", Reply { status: 403, ..Reply::not_found() }.header("X-Error", "Invalid consumer key.")),
+        ]).await;
+
+        let mut prompt = Defaults;
+        let mut setup = Setup::new(&mut prompt, paths(), false)
+            .set_api(&format!("{api}/v3"))
+            .set_consumer_key(Some("nonsense".to_string()))
+            .set_skip(vec!["settings".to_string(), "folders".to_string()]);
+
+        let outcomes = setup.run(&FSHandler::new()).await;
+
+        assert!(matches!(&outcomes[0].1, Outcome::Failed(why) if why.contains("Invalid consumer key.")), "{outcomes:?}");
+        assert_eq!(outcomes[1].1, Outcome::Skipped);
+        assert!(matches!(&outcomes[2].1, Outcome::Failed(why) if why.starts_with("no credentials")));
+        assert_eq!(outcomes[3].1, Outcome::Skipped);
+        // Off the device, the service is left out unless asked for.
+        assert_eq!(outcomes[4].1, Outcome::Skipped);
+        assert!(!setup.paths.unit.exists());
+    }
+}
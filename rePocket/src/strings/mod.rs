@@ -0,0 +1,431 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::sync::RwLock;
+use chrono::{DateTime, Datelike, TimeZone, Timelike};
+
+
+//
+// What rePocket writes into the documents themselves, the header of every article, the page it
+// puts in place of one it couldn't get, the safe mode report, in the language of the device.
+// The logs and the command line stay in English.
+//
+// A locale is nothing but one more Strings in LOCALES. The templates take their values by name,
+// in braces, in whatever order the language wants them:
+//
+// - {link}, {date}: the article and when it was retrieved, or saved to Pocket.
+// - {minutes}: the reading time.
+// - {reason}, {size}, {images}: why an article is bigger, or smaller, than it should be.
+// - {limit}: syncs in a row that didn't finish, see safemode::CRASH_LIMIT.
+// - {day}, {month}, {year}, {time}: the parts of a date.
+// - {folder}, {read}, {pocket}, {sync}, {minutes}, {days}, {hours}: the help, see the help module.
+//
+#[derive(Debug, PartialEq)]
+pub struct Strings {
+    // The language part of LANG, e.g. "de" for "de_AT.UTF-8".
+    pub code: &'static str,
+    pub retrieved: &'static str,
+    pub saved: &'static str,
+    pub reading_time: &'static str,
+    pub written: &'static str,
+    pub large_article: &'static str,
+    pub slow_to_open: &'static str,
+    pub failed_title: &'static str,
+    pub failed_header: &'static str,
+    pub failed_reason: &'static str,
+    pub safe_mode_title: &'static str,
+    pub safe_mode_body: &'static str,
+    pub help_title: &'static str,
+    pub help_body: &'static str,
+    // What marks a document as read, by Settings::device_archive(), and what's then done in Pocket.
+    pub help_read_folder: &'static str,
+    pub help_read_trash: &'static str,
+    pub help_archived: &'static str,
+    pub help_deleted: &'static str,
+    // When syncs happen, by the sync_interval and the schedule in the settings.
+    pub help_sync_folder: &'static str,
+    pub help_sync_every: &'static str,
+    pub help_sync_window: &'static str,
+    pub help_every_day: &'static str,
+    pub help_any_time: &'static str,
+    pub library_title: &'static str,
+    pub library_summary: &'static str,
+    // The sections of the library, see the library module.
+    pub library_in_folder: &'static str,
+    pub library_pinned: &'static str,
+    pub library_failed: &'static str,
+    pub date: &'static str,
+    pub months: [&'static str; 12],
+    // Monday first.
+    pub weekdays: [&'static str; 7],
+}
+
+
+pub static EN: Strings = Strings {
+    code: "en",
+    retrieved: "A rePocket-able version of {link}<br />Retrieved on {date}",
+    saved: "Saved to Pocket on {date}",
+    reading_time: "About {minutes} min read",
+    written: "Written by rePocket on {date}",
+    large_article: "Large article: {reason}",
+    slow_to_open: "Large article: {size} with {images} images, xochitl may be slow to open it",
+    failed_title: "rePocket Failed!",
+    failed_header: "Could not get the article contents",
+    failed_reason: "Could not get the article contents. Reason:\n{reason}",
+    safe_mode_title: "rePocket is in safe mode",
+    safe_mode_body: "<p>The last {limit} syncs, or more, never finished. Until that's sorted out rePocket \
+        only archives and tags in Pocket what was already read, it doesn't look at the documents \
+        on the device, and it doesn't download any articles.</p>\
+        <p>What went wrong is in the logs, <code>journalctl -u repocket</code> over SSH.</p>\
+        <p>To leave safe mode, run <code>repocket sync --once</code>, which leaves it once a whole \
+        sync goes through, or <code>repocket safe-mode --clear</code>, which leaves it right away.</p>",
+    help_title: "How rePocket works",
+    help_body: "<p>rePocket puts the articles saved in Pocket in the {folder} folder, and keeps the two \
+        in step.</p>\
+        <p>{read} Moving a document anywhere else stops rePocket from syncing it, and pinned \
+        documents, like this one, are left where they are.</p>\
+        <p>{sync}</p>\
+        <p>What rePocket is up to is in <code>repocket status</code>, over SSH. If syncs keep failing, \
+        a report on it shows up in the {folder} folder.</p>",
+    help_read_folder: "Once read, move a document into the Archive folder, it's then {pocket} in Pocket.",
+    help_read_trash: "Once read, move a document to the trash, it's then {pocket} in Pocket.",
+    help_archived: "archived",
+    help_deleted: "deleted",
+    help_sync_folder: "Opening the Sync folder syncs right away.",
+    help_sync_every: "rePocket also syncs every {minutes} minutes, on {days}, {hours}.",
+    help_sync_window: "Syncs only happen on {days}, {hours}.",
+    help_every_day: "any day",
+    help_any_time: "at any time",
+    library_title: "rePocket library",
+    library_summary: "<p>What rePocket keeps in the {folder} folder, as of the last sync that \
+        changed it. Every chapter lists up to {size} items.</p>",
+    library_in_folder: "In the folder",
+    library_pinned: "Pinned",
+    library_failed: "Could not be written",
+    date: "{month} {day}, {year}, {time}",
+    months: ["January", "February", "March", "April", "May", "June", "July", "August",
+        "September", "October", "November", "December"],
+    weekdays: ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"],
+};
+
+
+pub static DE: Strings = Strings {
+    code: "de",
+    retrieved: "Eine rePocket-fähige Version von {link}<br />Abgerufen am {date}",
+    saved: "In Pocket gespeichert am {date}",
+    reading_time: "Etwa {minutes} Min. Lesezeit",
+    written: "Von rePocket erstellt am {date}",
+    large_article: "Großer Artikel: {reason}",
+    slow_to_open: "Großer Artikel: {size} mit {images} Bildern, xochitl öffnet ihn womöglich nur langsam",
+    failed_title: "rePocket ist gescheitert!",
+    failed_header: "Der Inhalt des Artikels konnte nicht abgerufen werden",
+    failed_reason: "Der Inhalt des Artikels konnte nicht abgerufen werden. Grund:\n{reason}",
+    safe_mode_title: "rePocket ist im abgesicherten Modus",
+    safe_mode_body: "<p>Die letzten {limit} Synchronisierungen, oder mehr, wurden nie abgeschlossen. Bis das \
+        geklärt ist, archiviert und verschlagwortet rePocket in Pocket nur, was schon gelesen wurde, \
+        sieht sich die Dokumente auf dem Gerät nicht an und lädt keine Artikel herunter.</p>\
+        <p>Was schiefging, steht in den Logs, <code>journalctl -u repocket</code> über SSH.</p>\
+        <p>Den abgesicherten Modus verlässt <code>repocket sync --once</code>, sobald eine ganze \
+        Synchronisierung durchläuft, oder <code>repocket safe-mode --clear</code>, sofort.</p>",
+    help_title: "So funktioniert rePocket",
+    help_body: "<p>rePocket legt die in Pocket gespeicherten Artikel im Ordner {folder} ab und hält \
+        beide auf demselben Stand.</p>\
+        <p>{read} Wird ein Dokument woandershin verschoben, synchronisiert rePocket es nicht mehr, \
+        und angeheftete Dokumente, wie dieses, bleiben, wo sie sind.</p>\
+        <p>{sync}</p>\
+        <p>Was rePocket gerade tut, zeigt <code>repocket status</code> über SSH. Schlagen die \
+        Synchronisierungen immer wieder fehl, erscheint ein Bericht dazu im Ordner {folder}.</p>",
+    help_read_folder: "Gelesene Dokumente in den Ordner Archive verschieben, in Pocket werden sie dann {pocket}.",
+    help_read_trash: "Gelesene Dokumente in den Papierkorb verschieben, in Pocket werden sie dann {pocket}.",
+    help_archived: "archiviert",
+    help_deleted: "gelöscht",
+    help_sync_folder: "Das Öffnen des Ordners Sync synchronisiert sofort.",
+    help_sync_every: "Außerdem synchronisiert rePocket alle {minutes} Minuten, {days}, {hours}.",
+    help_sync_window: "Synchronisiert wird nur {days}, {hours}.",
+    help_every_day: "an jedem Tag",
+    help_any_time: "zu jeder Uhrzeit",
+    library_title: "rePocket-Bibliothek",
+    library_summary: "<p>Was rePocket im Ordner {folder} ablegt, Stand der letzten Synchronisierung, \
+        die etwas daran geändert hat. Jedes Kapitel führt bis zu {size} Einträge auf.</p>",
+    library_in_folder: "Im Ordner",
+    library_pinned: "Angeheftet",
+    library_failed: "Konnte nicht erstellt werden",
+    date: "{day}. {month} {year}, {time}",
+    months: ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August",
+        "September", "Oktober", "November", "Dezember"],
+    weekdays: ["Montag", "Dienstag", "Mittwoch", "Donnerstag", "Freitag", "Samstag", "Sonntag"],
+};
+
+
+pub static ES: Strings = Strings {
+    code: "es",
+    retrieved: "Una versión para rePocket de {link}<br />Obtenida el {date}",
+    saved: "Guardada en Pocket el {date}",
+    reading_time: "Unos {minutes} min de lectura",
+    written: "Escrito por rePocket el {date}",
+    large_article: "Artículo grande: {reason}",
+    slow_to_open: "Artículo grande: {size} con {images} imágenes, xochitl puede tardar en abrirlo",
+    failed_title: "¡rePocket ha fallado!",
+    failed_header: "No se pudo obtener el contenido del artículo",
+    failed_reason: "No se pudo obtener el contenido del artículo. Motivo:\n{reason}",
+    safe_mode_title: "rePocket está en modo seguro",
+    safe_mode_body: "<p>Las últimas {limit} sincronizaciones, o más, nunca terminaron. Hasta que se resuelva, \
+        rePocket solo archiva y etiqueta en Pocket lo que ya se ha leído, no mira los documentos del \
+        dispositivo y no descarga ningún artículo.</p>\
+        <p>Lo que falló está en los logs, <code>journalctl -u repocket</code> por SSH.</p>\
+        <p>Para salir del modo seguro, ejecuta <code>repocket sync --once</code>, que sale en cuanto \
+        una sincronización termina entera, o <code>repocket safe-mode --clear</code>, que sale al momento.</p>",
+    help_title: "Cómo funciona rePocket",
+    help_body: "<p>rePocket pone los artículos guardados en Pocket en la carpeta {folder}, y mantiene \
+        ambos al día.</p>\
+        <p>{read} Mover un documento a cualquier otro sitio hace que rePocket deje de sincronizarlo, \
+        y los documentos fijados, como este, se quedan donde están.</p>\
+        <p>{sync}</p>\
+        <p>Lo que está haciendo rePocket lo dice <code>repocket status</code>, por SSH. Si las \
+        sincronizaciones fallan una y otra vez, aparece un informe en la carpeta {folder}.</p>",
+    help_read_folder: "Una vez leído, mueve el documento a la carpeta Archive, y se habrá {pocket} en Pocket.",
+    help_read_trash: "Una vez leído, mueve el documento a la papelera, y se habrá {pocket} en Pocket.",
+    help_archived: "archivado",
+    help_deleted: "borrado",
+    help_sync_folder: "Abrir la carpeta Sync sincroniza al momento.",
+    help_sync_every: "rePocket también sincroniza cada {minutes} minutos, {days}, {hours}.",
+    help_sync_window: "Solo se sincroniza {days}, {hours}.",
+    help_every_day: "cualquier día",
+    help_any_time: "a cualquier hora",
+    library_title: "Biblioteca de rePocket",
+    library_summary: "<p>Lo que rePocket guarda en la carpeta {folder}, según la última \
+        sincronización que lo cambió. Cada capítulo enumera hasta {size} elementos.</p>",
+    library_in_folder: "En la carpeta",
+    library_pinned: "Fijados",
+    library_failed: "No se pudieron escribir",
+    date: "{day} de {month} de {year}, {time}",
+    months: ["enero", "febrero", "marzo", "abril", "mayo", "junio", "julio", "agosto",
+        "septiembre", "octubre", "noviembre", "diciembre"],
+    weekdays: ["lunes", "martes", "miércoles", "jueves", "viernes", "sábado", "domingo"],
+};
+
+
+pub static FR: Strings = Strings {
+    code: "fr",
+    retrieved: "Une version rePocket de {link}<br />Récupérée le {date}",
+    saved: "Enregistrée dans Pocket le {date}",
+    reading_time: "Environ {minutes} min de lecture",
+    written: "Écrit par rePocket le {date}",
+    large_article: "Article volumineux : {reason}",
+    slow_to_open: "Article volumineux : {size} avec {images} images, xochitl risque de l'ouvrir lentement",
+    failed_title: "Échec de rePocket !",
+    failed_header: "Impossible de récupérer le contenu de l'article",
+    failed_reason: "Impossible de récupérer le contenu de l'article. Raison :\n{reason}",
+    safe_mode_title: "rePocket est en mode sans échec",
+    safe_mode_body: "<p>Les {limit} dernières synchronisations, ou plus, ne se sont jamais terminées. En \
+        attendant, rePocket se contente d'archiver et d'étiqueter dans Pocket ce qui a déjà été lu, \
+        ne regarde pas les documents de l'appareil et ne télécharge aucun article.</p>\
+        <p>Ce qui s'est mal passé est dans les journaux, <code>journalctl -u repocket</code> par SSH.</p>\
+        <p>Pour quitter le mode sans échec, lancez <code>repocket sync --once</code>, qui le quitte dès \
+        qu'une synchronisation complète aboutit, ou <code>repocket safe-mode --clear</code>, qui le \
+        quitte tout de suite.</p>",
+    help_title: "Comment fonctionne rePocket",
+    help_body: "<p>rePocket place les articles enregistrés dans Pocket dans le dossier {folder}, et \
+        garde les deux à jour.</p>\
+        <p>{read} Déplacer un document ailleurs empêche rePocket de le synchroniser, et les documents \
+        épinglés, comme celui-ci, restent où ils sont.</p>\
+        <p>{sync}</p>\
+        <p>Ce que fait rePocket est dans <code>repocket status</code>, par SSH. Si les \
+        synchronisations échouent sans cesse, un rapport apparaît dans le dossier {folder}.</p>",
+    help_read_folder: "Une fois lu, déplacez le document dans le dossier Archive, il est alors {pocket} dans Pocket.",
+    help_read_trash: "Une fois lu, mettez le document à la corbeille, il est alors {pocket} dans Pocket.",
+    help_archived: "archivé",
+    help_deleted: "supprimé",
+    help_sync_folder: "Ouvrir le dossier Sync synchronise tout de suite.",
+    help_sync_every: "rePocket synchronise aussi toutes les {minutes} minutes, {days}, {hours}.",
+    help_sync_window: "Les synchronisations n'ont lieu que {days}, {hours}.",
+    help_every_day: "n'importe quel jour",
+    help_any_time: "à toute heure",
+    library_title: "Bibliothèque rePocket",
+    library_summary: "<p>Ce que rePocket garde dans le dossier {folder}, d'après la dernière \
+        synchronisation qui l'a modifié. Chaque chapitre liste jusqu'à {size} éléments.</p>",
+    library_in_folder: "Dans le dossier",
+    library_pinned: "Épinglés",
+    library_failed: "Impossible à écrire",
+    date: "{day} {month} {year} à {time}",
+    months: ["janvier", "février", "mars", "avril", "mai", "juin", "juillet", "août",
+        "septembre", "octobre", "novembre", "décembre"],
+    weekdays: ["lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche"],
+};
+
+
+pub static LOCALES: &[&Strings] = &[&EN, &DE, &ES, &FR];
+
+// The one in use, see configure().
+static CURRENT: RwLock<&Strings> = RwLock::new(&EN);
+
+
+pub fn configure(strings: &'static Strings) {
+    *CURRENT.write().unwrap() = strings;
+}
+
+
+pub fn current() -> &'static Strings {
+    *CURRENT.read().unwrap()
+}
+
+
+// A locale as LANG has it, "de_DE.UTF-8", "de-AT" or just "de".
+pub fn find(tag: &str) -> Option<&'static Strings> {
+    let language = tag.split(['_', '-', '.', '@']).next()?.to_lowercase();
+
+    LOCALES.iter().copied().find(|strings| strings.code == language)
+}
+
+
+// The first of LC_ALL, LC_MESSAGES and LANG that's set, English for anything not shipped.
+pub fn from_env() -> &'static Strings {
+    ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| find(&value))
+        .unwrap_or(&EN)
+}
+
+
+// Puts the values in a template, leaving alone braces that aren't one of them.
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}')
+            .and_then(|end| values.iter().find(|(name, _)| *name == &rest[1..end]).map(|(_, value)| (end, value)));
+
+        match value {
+            Some((end, value)) => {
+                filled.push_str(value);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+    filled.push_str(rest);
+
+    filled
+}
+
+
+impl Strings {
+    pub fn date<Tz: TimeZone>(&self, when: &DateTime<Tz>) -> String {
+        fill(self.date, &[
+            ("day", &when.day().to_string()),
+            ("month", self.months[when.month0() as usize]),
+            ("year", &when.year().to_string()),
+            ("time", &format!("{:02}:{:02}", when.hour(), when.minute())),
+        ])
+    }
+
+
+    // The link is HTML already.
+    pub fn retrieved<Tz: TimeZone>(&self, link: &str, when: &DateTime<Tz>) -> String {
+        fill(self.retrieved, &[("link", link), ("date", &self.date(when))])
+    }
+
+
+    pub fn saved<Tz: TimeZone>(&self, when: &DateTime<Tz>) -> String {
+        fill(self.saved, &[("date", &self.date(when))])
+    }
+
+
+    pub fn written<Tz: TimeZone>(&self, when: &DateTime<Tz>) -> String {
+        fill(self.written, &[("date", &self.date(when))])
+    }
+
+
+    pub fn reading_time(&self, minutes: usize) -> String {
+        fill(self.reading_time, &[("minutes", &minutes.to_string())])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn headers() {
+        let when = Utc.with_ymd_and_hms(2024, 11, 2, 13, 5, 0).unwrap();
+        let link = "<a href=\"https://example.com/\">https://example.com/</a>";
+        let header = |strings: &Strings| format!("{}<br />{}", strings.retrieved(link, &when), strings.reading_time(7));
+
+        assert_eq!(header(&EN), format!("A rePocket-able version of {link}<br />Retrieved on November 2, 2024, 13:05<br />About 7 min read"));
+        assert_eq!(header(&DE), format!("Eine rePocket-fähige Version von {link}<br />Abgerufen am 2. November 2024, 13:05<br />Etwa 7 Min. Lesezeit"));
+        assert_eq!(header(&ES), format!("Una versión para rePocket de {link}<br />Obtenida el 2 de noviembre de 2024, 13:05<br />Unos 7 min de lectura"));
+        assert_eq!(header(&FR), format!("Une version rePocket de {link}<br />Récupérée le 2 novembre 2024 à 13:05<br />Environ 7 min de lecture"));
+
+        assert_eq!(DE.written(&when), "Von rePocket erstellt am 2. November 2024, 13:05");
+        assert_eq!(EN.saved(&when), "Saved to Pocket on November 2, 2024, 13:05");
+        assert_eq!(ES.saved(&when), "Guardada en Pocket el 2 de noviembre de 2024, 13:05");
+        assert_eq!(FR.date(&Utc.with_ymd_and_hms(2025, 8, 15, 9, 0, 0).unwrap()), "15 août 2025 à 09:00");
+    }
+
+    #[test]
+    fn every_locale_fills_in_everything() {
+        for strings in LOCALES {
+            let filled = [
+                fill(strings.retrieved, &[("link", ""), ("date", "")]),
+                fill(strings.reading_time, &[("minutes", "")]),
+                fill(strings.saved, &[("date", "")]),
+                fill(strings.written, &[("date", "")]),
+                fill(strings.large_article, &[("reason", "")]),
+                fill(strings.slow_to_open, &[("size", ""), ("images", "")]),
+                fill(strings.failed_reason, &[("reason", "")]),
+                fill(strings.safe_mode_body, &[("limit", "")]),
+                fill(strings.date, &[("day", ""), ("month", ""), ("year", ""), ("time", "")]),
+                strings.failed_title.to_string(),
+                strings.failed_header.to_string(),
+                strings.safe_mode_title.to_string(),
+                fill(strings.help_body, &[("folder", ""), ("read", ""), ("sync", "")]),
+                fill(strings.help_read_folder, &[("pocket", "")]),
+                fill(strings.help_read_trash, &[("pocket", "")]),
+                fill(strings.help_sync_every, &[("minutes", ""), ("days", ""), ("hours", "")]),
+                fill(strings.help_sync_window, &[("days", ""), ("hours", "")]),
+                fill(strings.library_summary, &[("folder", ""), ("size", "")]),
+            ];
+
+            for text in filled {
+                assert!(!text.contains('{'), "{}: {text}", strings.code);
+            }
+        }
+    }
+
+    #[test]
+    fn locales() {
+        assert_eq!(find("de_DE.UTF-8"), Some(&DE));
+        assert_eq!(find("es-MX"), Some(&ES));
+        assert_eq!(find("FR"), Some(&FR));
+        assert_eq!(find("C"), None);
+        assert_eq!(find("pt_BR.UTF-8"), None);
+    }
+
+    #[test]
+    fn fill_leaves_other_braces() {
+        assert_eq!(fill("{a} {b} {", &[("a", "{b}")]), "{b} {b} {");
+    }
+}
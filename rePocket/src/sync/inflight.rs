@@ -0,0 +1,211 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::pocketitem::PocketItem;
+use crate::settings::DEFAULT_QUERY;
+
+
+//
+// The items of a retrieve, kept in a file next to the state until every one of them is done with,
+// so that a sync cut short carries on where it left off. The order of things:
+//
+//  1. The items are retrieved, and written to the file along with the "since" of the response, one
+//     for each query, see Settings::queries().
+//  2. Each item is written, or found to have failed, or skipped. The state is saved, then the item
+//     is marked as done in the file.
+//  3. Once they're all done the skipped items are archived upstream, the "since" goes into the
+//     state, the state is saved, and the file is removed.
+//
+// A sync that finds the file goes through the remaining items first, those whose document made it
+// to the disk count as done, see FSHandler::adopt(). Only then is Pocket asked for anything new.
+// Items left for later, out of time or past Settings::max_items_per_cycle(), keep the batch open
+// for the next sync. Those the data budget doesn't allow are skipped, the budget may stay tight.
+//
+// The batch is then the queue of what's retrieved but not yet written. Its "since" could go into
+// the state right away, the items are in the file, but kept until the queue is drained an
+// unreadable file only means retrieving them again rather than losing them. Either way each item is
+// written once, nothing new is asked for while the queue isn't empty.
+//
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Batch {
+    // By query name.
+    #[serde(deserialize_with = "since")]
+    pub since: BTreeMap<String, u64>,
+    pub items: Vec<PocketItem>,
+    // By resolved id.
+    pub done: BTreeSet<u64>,
+    // Non-articles, to be archived and tagged once the batch is done.
+    pub skipped: Vec<u64>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+
+pub fn inflight_file(state_file: &str) -> PathBuf {
+    Path::new(state_file).with_file_name(".repocket.inflight")
+}
+
+
+impl Batch {
+    // Items without an id are left out, there's nothing to tell them apart by, or to tell Pocket.
+    pub fn start(path: &Path, since: BTreeMap<String, u64>, items: &[PocketItem]) -> Self {
+        for item in items.iter().filter(|item| item.best_id().is_none()) {
+            println!("🚨 Leaving out '{}', Pocket has no id for it", item.get_title());
+        }
+
+        let batch = Self {
+            since,
+            items: items.iter().filter(|item| item.best_id().is_some()).cloned().collect(),
+            path: path.to_path_buf(),
+            ..Self::default()
+        };
+
+        batch.save();
+
+        batch
+    }
+
+
+    // An unreadable file is as good as none, the items come back with the next retrieve anyway.
+    pub fn load(path: &Path) -> Option<Self> {
+        let data = fs::read_to_string(path).ok()?;
+
+        match serde_json::from_str::<Self>(&data) {
+            Ok(batch) => Some(Self { path: path.to_path_buf(), ..batch }),
+            Err(e) => {
+                println!("🚨 Ignoring {}, it can't be read: {e}", path.display());
+                None
+            },
+        }
+    }
+
+
+    pub fn remaining(&self) -> Vec<PocketItem> {
+        self.items.iter()
+            .filter(|item| item.best_id().is_some_and(|id| !self.done.contains(&id)))
+            .cloned()
+            .collect()
+    }
+
+
+    pub fn done(&mut self, item: &PocketItem) {
+        if let Some(id) = item.best_id() {
+            self.done.insert(id);
+        }
+        self.save();
+    }
+
+
+    pub fn skip(&mut self, item: &PocketItem) {
+        if let Some(id) = item.best_id() {
+            self.skipped.push(id);
+        }
+
+        self.done(item);
+    }
+
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining().is_empty()
+    }
+
+
+    pub fn finish(self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            println!("🚨 Could not remove {}: {e}", self.path.display());
+        }
+    }
+
+
+    // Written aside and renamed, so that the file is either the old one or the new one.
+    fn save(&self) {
+        let tmp = self.path.with_extension("tmp");
+        let res = serde_json::to_vec(self)
+            .map_err(std::io::Error::from)
+            .and_then(|data| fs::write(&tmp, data))
+            .and_then(|()| fs::rename(&tmp, &self.path));
+
+        if let Err(e) = res {
+            println!("🚨 Could not save the batch in progress to {}: {e}", self.path.display());
+        }
+    }
+}
+
+
+// From before there were several queries, the default one's.
+fn since<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Since {
+        Single(u64),
+        ByQuery(BTreeMap<String, u64>),
+    }
+
+    Ok(match Since::deserialize(deserializer)? {
+        Since::Single(since) => BTreeMap::from([(DEFAULT_QUERY.to_string(), since)]),
+        Since::ByQuery(since) => since,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_file() {
+        let path = std::env::temp_dir().join("repocket-inflight-test");
+        let items: Vec<PocketItem> = serde_json::from_value(serde_json::json!([
+            { "item_id": "1", "resolved_id": "1", "resolved_url": "https://example.com/1", "favorite": "0", "is_article": "1", "is_index": "0", "has_image": "0", "has_video": "0", "word_count": "12", "tags": { "keep": { "tag": "keep" } } },
+            { "item_id": "2", "resolved_id": "2", "resolved_url": "https://example.com/2", "favorite": "0", "is_article": "1", "is_index": "0", "has_image": "1", "has_video": "0", "word_count": "" },
+            { "item_id": "", "resolved_id": "", "resolved_url": "https://example.com/a", "favorite": "0", "is_article": "1", "is_index": "0", "has_image": "0", "has_video": "0", "word_count": "" },
+            { "item_id": "0", "resolved_id": "0", "resolved_url": "https://example.com/b", "favorite": "0", "is_article": "1", "is_index": "0", "has_image": "0", "has_video": "0", "word_count": "" },
+        ])).unwrap();
+
+        // Neither of those without an id is kept, to take the other's place.
+        let mut batch = Batch::start(&path, BTreeMap::from([("favorites".to_string(), 100)]), &items);
+        assert_eq!(batch.items.len(), 2);
+        batch.skip(&items[1]);
+
+        // Just as it was retrieved, minus what's done.
+        let batch = Batch::load(&path).unwrap();
+        assert_eq!(batch.since, BTreeMap::from([("favorites".to_string(), 100)]));
+        assert_eq!(batch.skipped, vec![2]);
+        let remaining = batch.remaining();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].best_url(), items[0].best_url());
+        assert!(remaining[0].has_tag("keep"));
+        assert_eq!(batch.items[1].get_word_count(), None);
+        assert!(!batch.is_complete());
+
+        batch.finish();
+        assert!(Batch::load(&path).is_none());
+
+        // Written by a version with a single query.
+        fs::write(&path, r#"{"since": 100, "items": [], "done": [], "skipped": []}"#).unwrap();
+        assert_eq!(Batch::load(&path).unwrap().since, BTreeMap::from([(DEFAULT_QUERY.to_string(), 100)]));
+
+        fs::write(&path, "{ half a batch").unwrap();
+        assert!(Batch::load(&path).is_none());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,2399 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::Duration;
+use serde::Serialize;
+
+use crate::articlehandler::{self, ArticleOptions, Verdict};
+use crate::budget;
+use crate::clock::Clock;
+use crate::crashlog;
+use crate::device;
+use crate::dns;
+use crate::fshandler::{FSHandler, FsError};
+use crate::help;
+use crate::library;
+use crate::hooks::Hooks;
+use crate::pocket::{self, Pocket, PocketAction, PocketApi};
+use crate::pocketitem::PocketItem;
+use crate::pocketquery::{QueryBuilder, QueryError};
+use crate::safemode;
+use crate::screen;
+use crate::settings::{ItemOrder, OldItems, PocketRead, Settings, Status};
+
+mod inflight;
+pub mod resync;
+mod suspend;
+
+use inflight::Batch;
+
+
+//
+// A sync cycle, whatever triggered it: the Sync folder, the timer or the command line. Everything
+// it needs comes in the SyncContext, so that the tests can drive it against a mock Pocket.
+//
+// The steps, in order:
+//  1. Pick up the settings and what the user did on the device since the last sync, re-adding in
+//     Pocket the mirrored items moved back into the Pocket folder, and put the help in the Pocket
+//     folder if it's not there yet.
+//  2. Work out what the data budget allows.
+//  3. Finish what a sync cut short left undone, see inflight::Batch.
+//  4. Retrieve what's new from Pocket, or everything unread when resyncing, see the resync module,
+//     giving up if Pocket can't be reached, and write the new articles, skipping those that aren't,
+//     see screen::non_article(), and those tagged to be left out, see screen::excluded_tag(), and
+//     leaving the large ones for when the device is charging, see articlehandler::precheck().
+//     Past Settings::max_items_per_cycle() the rest is queued for the next syncs.
+//  5. Reconcile with Pocket, every so often.
+//  6. Archive and tag what's been read, and mirror Pocket's archive into the Archive folder, if
+//     so set.
+//  7. Account for the downloaded bytes, write the library, see the library module, if what it
+//     lists changed, run the hook and save the state.
+//  8. Have xochitl pick up the changes.
+//
+// In safe mode, see the safemode module, only 6 and 8 are done, along with writing the report.
+//
+// The FSHandler is the sync's alone while it runs, nothing else in the process reads it. `repocket
+// status` reads the state the sync saves, whole every time, see FSHandler::save_config().
+//
+pub struct SyncContext<'a, P: PocketApi> {
+    pub pocket: &'a mut P,
+    pub fhandler: &'a mut FSHandler,
+    pub settings: Settings,
+    pub clock: &'a dyn Clock,
+    pub refresher: &'a dyn Refresher,
+    pub ignore_budget: bool,
+    pub safe_mode: bool,
+}
+
+
+impl<P: PocketApi> SyncContext<'_, P> {
+    // Going on without would write documents the state doesn't know about, so it's up to the
+    // caller to stop.
+    async fn save(&mut self) -> Result<(), FsError> {
+        self.fhandler.save_config()
+    }
+}
+
+
+// What a sync did, which is also what the post_sync hook gets on its stdin.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SyncSummary {
+    pub written: usize,
+    pub failed: usize,
+    pub deferred: usize,
+    pub archived: usize,
+    // Instead of archived, see Settings::pocket_read().
+    pub deleted: usize,
+    pub bytes: u64,
+    pub month_bytes: u64,
+    pub budget: String,
+    // Skipped as non-articles or in other languages, by URL.
+    pub skipped: Vec<String>,
+    // Added to Pocket longer than max_item_age_days ago, see screen::too_old().
+    pub too_old: usize,
+    // Shorter than min_word_count or longer than max_word_count, see screen::off_length().
+    pub off_length: usize,
+    // Left for when the device is charging, see articlehandler::precheck().
+    pub large: usize,
+    // Left in the batch for the next syncs, past Settings::max_items_per_cycle().
+    pub queued: usize,
+    // Excluded by tag, see screen::excluded_tag().
+    pub excluded: usize,
+    // Cut short by the device suspending, the rest of the batch left for the next sync, see
+    // suspend::StormWatch.
+    pub suspended: bool,
+    // Host names resolved, and how many weren't in the DNS cache, see the dns module.
+    pub dns_lookups: u64,
+    pub dns_misses: u64,
+}
+
+
+// Other than State, nothing was changed.
+#[derive(Debug)]
+pub enum SyncError {
+    // Pocket couldn't be reached.
+    Unreachable(pocket::Error),
+    // Pocket answered, but not with the items, e.g. it no longer takes the credentials.
+    Rejected(pocket::Error),
+    // The query wasn't sent, it had values Pocket wouldn't take.
+    Query(QueryError),
+    // The state couldn't be saved, e.g. on a read-only filesystem, the sync stopped there. What was
+    // written since the last save is written again, in place, by the next one.
+    State(FsError),
+}
+
+
+impl From<pocket::Error> for SyncError {
+    fn from(e: pocket::Error) -> Self {
+        match e {
+            pocket::Error::Transport(_) => SyncError::Unreachable(e),
+            _ => SyncError::Rejected(e),
+        }
+    }
+}
+
+
+impl From<FsError> for SyncError {
+    fn from(e: FsError) -> Self {
+        SyncError::State(e)
+    }
+}
+
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Unreachable(e) | SyncError::Rejected(e) => write!(f, "{e}"),
+            SyncError::Query(e) => write!(f, "{e}"),
+            SyncError::State(e) => write!(f, "Could not save the state, stopping the sync: {e}"),
+        }
+    }
+}
+
+
+// Makes the reader show the documents written during a sync.
+pub trait Refresher {
+    fn refresh(&self);
+}
+
+
+// xochitl only picks up new documents after a restart.
+pub struct XochitlRestart;
+
+
+impl Refresher for XochitlRestart {
+    fn refresh(&self) {
+        let cmd = std::thread::spawn(move || {
+            std::process::Command::new("systemctl")
+                .arg("restart")
+                .arg("xochitl")
+                .output()
+                .expect("Could not restart Xochitl");
+
+            println!(" .. sleeping for some empirical number of seconds during restart");
+
+            std::thread::sleep(std::time::Duration::new(30, 0));
+        });
+
+        let _result = cmd.join().unwrap();
+    }
+}
+
+
+// Restart xochitl on a reMarkable, there's none to restart anywhere else.
+pub fn refresher() -> &'static dyn Refresher {
+    match device::kind().is_device() {
+        true => &XochitlRestart,
+        false => &NoRefresh,
+    }
+}
+
+
+// For when there's no xochitl to restart, e.g. when syncing against the offline fixtures.
+pub struct NoRefresh;
+
+
+impl Refresher for NoRefresh {
+    fn refresh(&self) {
+        println!("ℹ Not restarting xochitl");
+    }
+}
+
+
+pub async fn run_sync<P: PocketApi>(ctx: &mut SyncContext<'_, P>) -> Result<SyncSummary, SyncError> {
+    if ctx.safe_mode {
+        return Ok(safe_sync(ctx).await?);
+    }
+
+    let settings = ctx.settings.clone();
+    let hooks = Hooks::new(&settings);
+    articlehandler::configure(&settings);
+
+    // Items moved into the Archive folder since the last sync become read items.
+    ctx.fhandler.set_device_archive(settings.device_archive());
+    ctx.fhandler.set_failure_placeholders(settings.failure_placeholders());
+    ctx.fhandler.set_languages(settings.languages());
+    ctx.fhandler.set_uuid_source(settings.uuid_source());
+    ctx.fhandler.configure(&settings);
+    ctx.fhandler.set_extra_roots(settings.extra_roots());
+    ctx.fhandler.consolidate();
+
+    // What couldn't be copied to the other roots before, e.g. one was unmounted.
+    let copied = ctx.fhandler.catch_up_extra_roots().await;
+    if copied > 0 {
+        println!("ℹ Copied {copied} document(s) to the other roots");
+    }
+
+    // For whoever else reads on the device, see the help module.
+    help::place(ctx.fhandler, &settings);
+
+    // Not worth starting what Pocket would turn down halfway, see pocket::RateLimit.
+    let requests = planned_requests(ctx.fhandler, &settings);
+    if let Some(limit) = ctx.pocket.rate_limit_status().filter(|limit| !limit.allows(requests, ctx.clock.now())) {
+        let reset = chrono::DateTime::<chrono::Local>::from(limit.resets_at).format("%H:%M");
+        println!("🚨 Pocket takes {} more request(s) until {reset}, the sync needs {requests}, skipping it", limit.remaining);
+        ctx.save().await?;
+
+        return Err(SyncError::Rejected(pocket::Error::RateLimited { retry_after: Some(limit.resets_in(ctx.clock.now()).as_secs()) }));
+    }
+
+    if !settings.read_only_upstream() {
+        push_readds(&*ctx.pocket, ctx.fhandler).await;
+    }
+
+    if settings.progress_tags() && !settings.read_only_upstream() {
+        tag_progress(&*ctx.pocket, ctx.fhandler).await;
+    }
+
+    // On a data budget, fetch less as the month goes on.
+    let month = budget::month(ctx.clock.now().into());
+    let used = ctx.fhandler.downloaded_in(&month);
+    let level = match ctx.ignore_budget {
+        true => budget::Level::Full,
+        false => budget::level(used, settings.monthly_budget()),
+    };
+    let options = ArticleOptions {
+        images: level == budget::Level::Full && settings.images(),
+        favicons: level == budget::Level::Full && settings.favicons(),
+        limits: settings.epub_limits(),
+        colors: settings.color_policy(),
+    };
+
+    match level {
+        budget::Level::Full => (),
+        budget::Level::Skip => {
+            println!("🚨 {} of the monthly budget already downloaded, skipping the sync", budget::human(used));
+            println!("🚨 Run 'repocket sync --ignore-budget' to sync regardless");
+        },
+        _ => println!("ℹ {} of the monthly budget already downloaded, syncing with level {level}", budget::human(used)),
+    }
+
+    let mut summary = SyncSummary {
+        budget: level.to_string(),
+        ..SyncSummary::default()
+    };
+
+    // Each item gets its own time budget, so that a server dripping bytes can't
+    // stall the whole cycle, and the cycle as a whole has a budget too.
+    let cycle_start = ctx.clock.monotonic();
+
+    // What's left of a sync cut short goes first, and only then is Pocket asked for anything new,
+    // see inflight::Batch. The "since" of the response is stored once every item is done with, so
+    // as to pass it again on the next _efficient_ request.
+    let inflight = inflight::inflight_file(ctx.fhandler.state_file());
+    let mut resumed = Batch::load(&inflight);
+
+    if let Some(batch) = &mut resumed {
+        println!("ℹ Resuming the last sync, {} item(s) to go", batch.remaining().len());
+
+        for item in batch.remaining() {
+            if ctx.fhandler.adopt(&item) {
+                batch.done(&item);
+            }
+        }
+    }
+
+    let mut retrieved = false;
+    // Everything unread rather than what changed since the last sync, see the resync module.
+    let resync = resync::requested();
+
+    if resync {
+        println!("ℹ Resyncing, asking Pocket for every unread item");
+    }
+
+    loop {
+        let mut batch = match resumed.take() {
+            Some(batch) => batch,
+            None if retrieved || level == budget::Level::Skip => break,
+            None => {
+                // As the settings say, e.g. only what's been tagged to go to the device, re-read for
+                // every sync. Each query has its own "since", and an item two of them bring is the
+                // first one's.
+                let mut items = Vec::new();
+                let mut since = BTreeMap::new();
+
+                for (name, mut query) in settings.queries() {
+                    if !resync {
+                        query = query.set_since(ctx.fhandler.query_ts(&name));
+                    }
+
+                    let complete_query = query.build().map_err(SyncError::Query)?;
+
+                    ctx.pocket.fetch_all(&complete_query, settings.retrieve_max()).await?;
+                    merge_items(&mut items, ctx.pocket.items());
+                    since.insert(name, ctx.pocket.since());
+                }
+
+                order_items(&mut items, settings.item_order(), settings.queue_tag_prefix());
+
+                retrieved = true;
+                let mut batch = Batch::start(&inflight, since, &items);
+
+                // Those on the device already are done with, whatever the state said.
+                if resync {
+                    for item in batch.remaining() {
+                        if ctx.fhandler.resync_item(&item) {
+                            batch.done(&item);
+                        }
+                    }
+                }
+
+                batch
+            },
+        };
+
+        // The hosts of the articles about to be fetched, all at once rather than one after the other.
+        dns::cache().warm(batch.remaining().iter().filter_map(|item| dns::host(&item.best_url()?))).await;
+
+        write_batch(ctx, &mut batch, options, level, cycle_start, &hooks, &mut summary).await?;
+
+        // The same items would only come back with the next retrieve.
+        if !batch.is_complete() {
+            println!("ℹ Deferring {} item(s) to the next run", batch.remaining().len());
+            break;
+        }
+
+        // Non-articles go straight to Pocket's archive, tagged so that they can be found again.
+        if !batch.skipped.is_empty() && !settings.read_only_upstream() {
+            archive_upstream(&*ctx.pocket, &batch.skipped, ctx.fhandler, settings.status_tags().tag(Status::Skipped)).await;
+        }
+
+        for (name, since) in &batch.since {
+            ctx.fhandler.set_query_ts(name, *since);
+        }
+        ctx.save().await?;
+        batch.finish();
+
+        if retrieved && resync {
+            println!("ℹ Resync done");
+            resync::done();
+        }
+    }
+
+    // Not after a suspend, the network may not even be back yet.
+    if device::charging() && !summary.suspended {
+        write_large(ctx, options, level, cycle_start, &hooks, &mut summary).await?;
+    } else if !ctx.fhandler.large_items().is_empty() {
+        println!("ℹ {} large item(s) waiting for the device to be charging", ctx.fhandler.large_items().len());
+    }
+
+    // Every so often, look for items that were archived or deleted elsewhere.
+    if ctx.fhandler.reconcile_due(settings.reconcile_every()) {
+        let mut builder = QueryBuilder::default()
+            .set_state("All")
+            .set_detail_type("Simple");
+
+        if ctx.fhandler.last_reconcile_ts() > 0 {
+            builder = builder.set_since(ctx.fhandler.last_reconcile_ts());
+        }
+
+        // The whole list of pinned items every time, so that removing the tag is noticed too.
+        if let Some(tag) = settings.pin_tag() {
+            let query = QueryBuilder::default()
+                .set_state("All")
+                .set_tag(tag)
+                .set_detail_type("Simple");
+
+            match ctx.pocket.item_statuses(&query.build().unwrap()).await {
+                Ok((tagged, _)) => {
+                    let changed = ctx.fhandler.set_tag_pins(&tagged);
+                    println!("ℹ {changed} item(s) pinned or unpinned with the '{tag}' tag");
+                },
+                Err(e) => println!("🚨 Could not look for items tagged '{tag}': {e}"),
+            }
+        }
+
+        match ctx.pocket.item_statuses(&builder.build().unwrap()).await {
+            Ok((statuses, ts)) => {
+                let moved = ctx.fhandler.reconcile(&statuses, settings.reconcile_to(), settings.reconcile_opened());
+                ctx.fhandler.set_last_reconcile_ts(ts);
+                println!("ℹ Reconciled with Pocket, {moved} item(s) archived or deleted elsewhere");
+            },
+            Err(e) => println!("🚨 Could not reconcile with Pocket: {e}"),
+        }
+    }
+
+    if summary.too_old > 0 {
+        println!("ℹ Left out {} item(s) added to Pocket too long ago", summary.too_old);
+    }
+
+    if summary.off_length > 0 {
+        println!("ℹ Left out {} item(s) for their word count", summary.off_length);
+    }
+
+    if summary.excluded > 0 {
+        println!("ℹ Left out {} item(s) excluded by tag", summary.excluded);
+    }
+
+    if !summary.skipped.is_empty() {
+        println!("ℹ Skipped as non-articles:");
+        for url in &summary.skipped {
+            println!("  ..{url}");
+        }
+    }
+
+    push_read(ctx, &mut summary).await;
+
+    if let Some(count) = settings.archive_mirror() {
+        mirror_archive(ctx, count, options, level != budget::Level::Skip).await;
+    }
+
+    if !settings.read_only_upstream() {
+        tag_errors(&*ctx.pocket, ctx.fhandler, settings.status_tags().tag(Status::Error)).await;
+    }
+
+    summary.bytes = budget::take();
+    ctx.fhandler.add_downloaded(&month, summary.bytes);
+    summary.month_bytes = ctx.fhandler.downloaded_in(&month);
+    println!("ℹ Downloaded {} during this sync, {} this month", budget::human(summary.bytes), budget::human(summary.month_bytes));
+
+    let dns = dns::cache().take_stats();
+    (summary.dns_lookups, summary.dns_misses) = (dns.lookups, dns.misses);
+
+    // What the Pocket folder ended up with, unless it's what it had.
+    library::place(ctx.fhandler, &settings);
+
+    hooks.post_sync(&serde_json::json!(summary)).await;
+
+    ctx.save().await?;
+    ctx.refresher.refresh();
+
+    Ok(summary)
+}
+
+
+// Those not in yet, by resolved id, in the order they came.
+fn merge_items(items: &mut Vec<PocketItem>, more: &[PocketItem]) {
+    let mut seen: BTreeSet<u64> = items.iter().filter_map(PocketItem::best_id).collect();
+
+    for item in more {
+        match item.best_id() {
+            Some(id) if !seen.insert(id) => (),
+            _ => items.push(item.clone()),
+        }
+    }
+}
+
+
+// As the settings say, ties, and everything with ItemOrder::Pocket, staying as they came. Those in
+// the user's queue go first, whatever the order, by their place in it, and then by id.
+fn order_items(items: &mut [PocketItem], order: ItemOrder, queue_prefix: Option<&str>) {
+    match order {
+        ItemOrder::Pocket => (),
+        ItemOrder::Newest => items.sort_by_key(|item| std::cmp::Reverse(item.get_time_added())),
+        ItemOrder::Oldest => items.sort_by_key(|item| (item.get_time_added().is_none(), item.get_time_added())),
+    }
+
+    if let Some(prefix) = queue_prefix {
+        items.sort_by_key(|item| match screen::queue_tags(item, prefix).first() {
+            Some((place, _)) => (false, *place, item.best_id()),
+            None => (true, 0, None),
+        });
+    }
+}
+
+
+// Requests to Pocket a sync makes at the least: the retrieves, and the sends for what's been read.
+fn planned_requests(fhandler: &FSHandler, settings: &Settings) -> u64 {
+    let actions = fhandler.read_ids().count() + fhandler.pending_ids().count();
+    let sends = match settings.read_only_upstream() {
+        true => 0,
+        false => actions.div_ceil(settings.max_actions().max(1)) + fhandler.readd_ids().len().min(1),
+    };
+    let mirror = settings.archive_mirror().map_or(0, |count| count.div_ceil(30));
+
+    (settings.queries().len() + sends + mirror) as u64
+}
+
+
+// The mirrored items moved back into the Pocket folder, unread again in Pocket. Those Pocket
+// doesn't confirm are sent again with the next sync.
+async fn push_readds<P: PocketApi>(pocket: &P, fhandler: &mut FSHandler) {
+    let ids: Vec<u64> = fhandler.readd_ids().collect();
+
+    if ids.is_empty() {
+        return;
+    }
+
+    let readded = pocket.readd(ids.clone()).await;
+    if readded.len() < ids.len() {
+        println!("🚨 Pocket didn't re-add {} of the item(s) moved back into the Pocket folder", ids.len() - readded.len());
+    }
+
+    fhandler.readded(&readded);
+}
+
+
+// The most recently archived items, into the Archive folder, see FSHandler::mirror_archive().
+// Only those whose documents are around when the budget says not to download.
+async fn mirror_archive<P: PocketApi>(ctx: &mut SyncContext<'_, P>, count: usize, options: ArticleOptions, download: bool) {
+    let query = QueryBuilder::default()
+        .set_state("Archive")
+        .set_sort("Newest")
+        .set_detail_type("Complete")
+        .set_count(count.min(30) as u8)
+        .build()
+        .unwrap();
+    let mut items = Vec::new();
+
+    while items.len() < count {
+        match ctx.pocket.page(&query.at_offset(items.len() as u32)).await {
+            // The last one, short of a whole page.
+            Ok(page) if page.len() < query.count().unwrap_or(30) as usize => {
+                items.extend(page);
+                break;
+            },
+            Ok(page) => items.extend(page),
+            Err(e) => {
+                println!("🚨 Could not get Pocket's archive to mirror it: {e}");
+                return;
+            },
+        }
+    }
+
+    let (added, trashed) = ctx.fhandler.mirror_archive(&items, count, options, download).await;
+    println!("ℹ Mirrored {added} more archived item(s), trashed {trashed} no longer among the last {count}");
+}
+
+
+// Write what's left of the batch, marking each item as done once the state says what came of it.
+async fn write_batch<P: PocketApi>(
+    ctx: &mut SyncContext<'_, P>,
+    batch: &mut Batch,
+    options: ArticleOptions,
+    level: budget::Level,
+    cycle_start: Duration,
+    hooks: &Hooks,
+    summary: &mut SyncSummary,
+) -> Result<(), FsError> {
+    let settings = ctx.settings.clone();
+    let mut storm = suspend::StormWatch::default();
+
+    for item in batch.remaining() {
+        // Nothing to fetch, or nothing to know it by, which Pocket may yet have for the next sync.
+        if item.best_id().is_none() || item.best_url().is_none() {
+            println!("🚨 Leaving out '{}', Pocket has no id or no URL for it", item.get_title());
+            batch.done(&item);
+            continue;
+        }
+
+        // Already in the Pocket folder, moved there from the archive mirror, see push_readds().
+        if item.best_id().is_some_and(|id| ctx.fhandler.take_readded(id)) {
+            batch.done(&item);
+            continue;
+        }
+
+        // Before the cap, they don't take anyone's place.
+        if let Some(tag) = screen::excluded_tag(&item, settings.excluded_tags()) {
+            println!("ℹ Leaving out item id {:?}, it's tagged '{tag}'", item.best_id());
+            summary.excluded += 1;
+            // Tagged only, they're still the user's to read in Pocket. Right away, as the batch may
+            // not make it to the end.
+            let skipped = settings.status_tags().tag(Status::Skipped).filter(|_| settings.tag_excluded() && !settings.read_only_upstream());
+            if let (Some(skipped), Some(id)) = (skipped, item.best_id()) {
+                if !ctx.pocket.add_tags(id, &[skipped]).await {
+                    println!("🚨 Pocket didn't tag item id {id}, excluded by tag, with '{skipped}'");
+                }
+            }
+            batch.done(&item);
+            continue;
+        }
+
+        let urls = [item.get_given_url(), item.best_url()];
+        let screened = urls.iter()
+            .flatten()
+            .find_map(|url| screen::non_article(url, settings.skip_url_patterns()).map(|pattern| (url, pattern)));
+
+        if let (Some((url, pattern)), Some(id)) = (screened, item.best_id()) {
+            println!("ℹ Skipping item id {id}, {url} matches '{pattern}'");
+            summary.skipped.push(url.clone());
+            batch.skip(&item);
+            continue;
+        }
+
+        if let (Some(lang), Some(id)) = (item.get_lang(), item.best_id()) {
+            if screen::other_language(&lang, settings.languages()) {
+                println!("ℹ Skipping item id {id}, it's in '{lang}'");
+                summary.skipped.push(item.best_url().unwrap_or_default());
+                batch.skip(&item);
+                continue;
+            }
+        }
+
+        if screen::too_old(&item, ctx.clock.now(), settings.max_item_age()) {
+            println!("ℹ Leaving out item id {:?}, added to Pocket too long ago", item.best_id());
+            summary.too_old += 1;
+            match settings.old_items() {
+                OldItems::Archive => batch.skip(&item),
+                OldItems::Ignore => batch.done(&item),
+            }
+            continue;
+        }
+
+        if screen::off_length(&item, settings.min_word_count(), settings.max_word_count()) {
+            println!("ℹ Leaving out item id {:?}, '{}' is {} words long", item.best_id(), item.get_title(), item.get_word_count().unwrap_or(0));
+            summary.off_length += 1;
+            match settings.off_length_items() {
+                OldItems::Archive => batch.skip(&item),
+                OldItems::Ignore => batch.done(&item),
+            }
+            continue;
+        }
+
+        // Enough for one sync, what's left keeps the batch open for the next ones.
+        if summary.written + summary.failed >= settings.max_items_per_cycle() {
+            summary.queued += 1;
+            continue;
+        }
+
+        let elapsed = ctx.clock.monotonic().saturating_sub(cycle_start);
+
+        if elapsed >= settings.sync_timeout() {
+            summary.deferred += 1;
+            continue;
+        }
+
+        // Still unread in Pocket, it keeps the batch open until the budget allows it.
+        if !budget::allows(level, item.get_word_count().unwrap_or(0)) {
+            println!("ℹ Leaving item id {:?} for a later sync, too long for what's left of the data budget", item.best_id());
+            summary.deferred += 1;
+            continue;
+        }
+
+        storm.observe(ctx.clock.now(), ctx.clock.monotonic());
+
+        let _current = crashlog::working_on(&item);
+        crashlog::stage("precheck");
+
+        // Whether it's worth the slot, as far as a HEAD request tells.
+        if let (Some(url), Some(id)) = (item.best_url().filter(|_| settings.size_limits().any()), item.best_id()) {
+            match articlehandler::precheck(&url, settings.size_limits()).await {
+                Verdict::Process => (),
+                Verdict::Defer if device::charging() => (),
+                Verdict::Defer => {
+                    println!("ℹ Leaving item id {id} for when the device is charging, it's a large one");
+                    ctx.fhandler.defer_large(&item);
+                    ctx.save().await?;
+                    summary.large += 1;
+                    batch.done(&item);
+                    continue;
+                },
+                // Not fetched, but still the user's to read in Pocket.
+                Verdict::Skip(reason) => {
+                    println!("ℹ Skipping item id {id}, {reason}");
+                    summary.skipped.push(url);
+                    batch.done(&item);
+                    continue;
+                },
+            }
+        }
+
+        let (written, failed) = (summary.written, summary.failed);
+        let other_language = write_item(ctx, &item, options, hooks, summary).await;
+
+        // Not the item's fault, it's tried again with the rest of the batch once the device is back.
+        storm.observe(ctx.clock.now(), ctx.clock.monotonic());
+        let reason = item.best_id().and_then(|id| ctx.fhandler.failure_reason(id)).unwrap_or_default();
+        if summary.failed > failed && storm.suspended(ctx.clock.monotonic(), reason) {
+            println!("ℹ Item id {:?} failed right after the device woke up, leaving the rest for the next sync", item.best_id());
+            ctx.fhandler.forgive(&item);
+            ctx.save().await?;
+            summary.failed = failed;
+            summary.suspended = true;
+            break;
+        }
+
+        ctx.save().await?;
+        // With the save that says it's on the device, not to be lost with the rest of the batch.
+        if summary.written > written {
+            untag_synced(&*ctx.pocket, &item, &settings).await;
+        }
+        match other_language {
+            true => batch.skip(&item),
+            false => batch.done(&item),
+        }
+    }
+
+    Ok(())
+}
+
+
+// The tags that brought an item to the device, in one action to take them off once it's there:
+// the trigger tag, not to come back with the next query, and its queue tags.
+fn synced_tags(item: &PocketItem, settings: &Settings) -> Option<PocketAction> {
+    let item_id = item.best_id()?;
+    let mut tags: Vec<String> = settings.trigger_tag()
+        .filter(|tag| item.has_tag(tag))
+        .map(str::to_string)
+        .into_iter()
+        .collect();
+    if let Some(prefix) = settings.queue_tag_prefix() {
+        tags.extend(screen::queue_tags(item, prefix).into_iter().map(|(_, tag)| tag));
+    }
+
+    (!tags.is_empty()).then(|| PocketAction::TagsRemove { item_id, tags: tags.join(",") })
+}
+
+
+// Takes off what synced_tags() gives for an item written.
+async fn untag_synced<P: PocketApi>(pocket: &P, item: &PocketItem, settings: &Settings) {
+    let Some(action) = synced_tags(item, settings).filter(|_| !settings.read_only_upstream()) else {
+        return;
+    };
+
+    if !pocket.send_action(action).await {
+        println!("🚨 Pocket didn't take the trigger or queue tags off item id {:?}, it may be synced again", item.best_id());
+    }
+}
+
+
+// The large items left for when the device is charging, see write_batch(). The network is there,
+// or Pocket wouldn't have answered, and on the device that's the Wi-Fi.
+async fn write_large<P: PocketApi>(
+    ctx: &mut SyncContext<'_, P>,
+    options: ArticleOptions,
+    level: budget::Level,
+    cycle_start: Duration,
+    hooks: &Hooks,
+    summary: &mut SyncSummary,
+) -> Result<(), FsError> {
+    let mut skipped = Vec::new();
+
+    for item in ctx.fhandler.large_items() {
+        let elapsed = ctx.clock.monotonic().saturating_sub(cycle_start);
+
+        if elapsed >= ctx.settings.sync_timeout() || !budget::allows(level, item.get_word_count().unwrap_or(0)) {
+            break;
+        }
+
+        let _current = crashlog::working_on(&item);
+
+        let written = summary.written;
+        if write_item(ctx, &item, options, hooks, summary).await {
+            skipped.extend(item.best_id());
+        }
+
+        if let Some(id) = item.best_id() {
+            ctx.fhandler.take_large(id);
+        }
+        ctx.save().await?;
+        if summary.written > written {
+            untag_synced(&*ctx.pocket, &item, &ctx.settings).await;
+        }
+    }
+
+    if !skipped.is_empty() && !ctx.settings.read_only_upstream() {
+        let tag = ctx.settings.status_tags().tag(Status::Skipped);
+        archive_upstream(&*ctx.pocket, &skipped, ctx.fhandler, tag).await;
+    }
+    Ok(())
+}
+
+
+// Fetch and write a single item, true if it turned out to be in another language, see
+// FSHandler::take_other_language().
+async fn write_item<P: PocketApi>(
+    ctx: &mut SyncContext<'_, P>,
+    item: &PocketItem,
+    options: ArticleOptions,
+    hooks: &Hooks,
+    summary: &mut SyncSummary,
+) -> bool {
+    let settings = &ctx.settings;
+
+    println!("ℹ Working on item id {:?} with URL\n  ..{:?}", item.best_id(), item.best_url());
+    let res = tokio::time::timeout(settings.article_timeout(), ctx.fhandler.new_article(item, options)).await;
+
+    match res {
+        Ok(Some(article)) => {
+            if let (Some(tag), Some(id)) = (settings.pin_tag(), item.best_id()) {
+                if item.has_tag(tag) {
+                    ctx.fhandler.pin_by_tag(id);
+                }
+            }
+            summary.written += 1;
+            hooks.post_article(&article).await;
+        },
+        Ok(None) => match ctx.fhandler.take_other_language(item) {
+            Some(_) => {
+                summary.skipped.push(item.best_url().unwrap_or_default());
+                return true;
+            },
+            None => summary.failed += 1,
+        },
+        Err(_) => {
+            println!("🚨 Timed out after {:?} working on item id {:?}", settings.article_timeout(), item.best_id());
+            ctx.fhandler.discard_article(item);
+            ctx.fhandler.fail_item(item, "timeout");
+            ctx.fhandler.write_placeholder(item, "timeout");
+            summary.failed += 1;
+        },
+    }
+
+    false
+}
+
+
+// Archive, or delete, all the items in the Read folder, and count them in the summary. What's left
+// of earlier archives goes along, see archive_upstream().
+async fn push_read<P: PocketApi>(ctx: &mut SyncContext<'_, P>, summary: &mut SyncSummary) {
+    let ids: Vec<u64> = ctx.fhandler.read_ids().collect();
+    let pending: Vec<u64> = ctx.fhandler.pending_ids().filter(|id| !ids.contains(id)).collect();
+
+    if ctx.settings.read_only_upstream() {
+        // Keep the device tidy, but leave the Pocket account alone.
+        if !ids.is_empty() {
+            println!("ℹ Read-only upstream, would have pushed {} item(s)", ids.len());
+            ctx.fhandler.withhold_read();
+        }
+        return;
+    }
+
+    if ids.is_empty() && pending.is_empty() {
+        return;
+    }
+
+    // Remove the items done with from the read_items entry in the FSHandler.
+    let upstream = ctx.settings.pocket_read();
+    let succeeded = push_upstream(&*ctx.pocket, &[ids.as_slice(), &pending].concat(), ctx.fhandler, &ctx.settings).await;
+    ctx.fhandler.clear_read(&succeeded);
+
+    let read: Vec<u64> = succeeded.into_iter().filter(|id| ids.contains(id)).collect();
+    if upstream == PocketRead::Archive && ctx.settings.favorite_read() && !read.is_empty() {
+        let favorited = ctx.pocket.favorite(read.clone()).await;
+        if favorited.len() < read.len() {
+            println!("🚨 Pocket didn't favorite {} of the archived item(s)", read.len() - favorited.len());
+        }
+    }
+
+    let count = read.len();
+    match upstream {
+        PocketRead::Archive => summary.archived = count,
+        PocketRead::Delete => summary.deleted = count,
+    }
+}
+
+
+// Archive or delete the items in Pocket, as the settings say, returns the ids done with. Only
+// what was read is ever deleted, an item that's only waiting on a tag, e.g. one skipped for its
+// size, gets the tag and stays in Pocket.
+pub async fn push_upstream<P: PocketApi>(pocket: &P, ids: &[u64], fhandler: &mut FSHandler, settings: &Settings) -> Vec<u64> {
+    match settings.pocket_read() {
+        PocketRead::Archive => archive_upstream(pocket, ids, fhandler, settings.status_tags().tag(Status::Archived)).await,
+        PocketRead::Delete => {
+            let read: BTreeSet<u64> = fhandler.read_ids().chain(fhandler.withheld_ids()).collect();
+            let (deleted, tagged): (Vec<u64>, Vec<u64>) = ids.iter().partition(|id| read.contains(id));
+            let tagged: Vec<u64> = tagged.into_iter().filter(|id| fhandler.pending_tag(*id).is_some()).collect();
+
+            let mut done = match deleted.is_empty() {
+                true => Vec::new(),
+                false => delete_upstream(pocket, &deleted, fhandler).await,
+            };
+            if !tagged.is_empty() {
+                done.extend(archive_upstream(pocket, &tagged, fhandler, None).await);
+            }
+
+            done
+        },
+    }
+}
+
+
+// Nothing but what's already known to be read goes to Pocket, and the report to the device.
+async fn safe_sync<P: PocketApi>(ctx: &mut SyncContext<'_, P>) -> Result<SyncSummary, FsError> {
+    println!("🚨 In safe mode, only pushing what's pending to Pocket");
+
+    let mut summary = SyncSummary {
+        budget: "safe mode".to_string(),
+        ..SyncSummary::default()
+    };
+    push_read(ctx, &mut summary).await;
+
+    safemode::write_report(ctx.fhandler);
+    ctx.save().await?;
+    ctx.refresher.refresh();
+
+    Ok(summary)
+}
+
+
+// How far into each document the user got, for whoever reads in Pocket too. Only the items whose
+// progress bucket changed since the last time.
+async fn tag_progress<P: PocketApi>(pocket: &P, fhandler: &mut FSHandler) {
+    for (id, remove, add) in fhandler.progress_retags() {
+        match pocket.retag(id, remove, add).await {
+            Ok(()) => fhandler.progress_retagged(id),
+            Err(e) => println!("🚨 Could not tag the reading progress of item id {id}: {e}"),
+        }
+    }
+}
+
+
+//
+// Archive and tag the items in Pocket, in the same sends, returns the ids done with. An item is
+// done with once Pocket confirmed every action for it:
+//
+//   archive    tag        afterwards
+//   confirmed  confirmed  done with
+//   confirmed  failed     pending, only the tag is sent the next time, see FSHandler::pending_actions
+//   failed     either     as it was, both are sent again the next time
+//
+// Restored items were tagged the first time around, only pending ones are tagged again, with the
+// tag they were meant to get.
+//
+pub async fn archive_upstream<P: PocketApi>(pocket: &P, ids: &[u64], fhandler: &mut FSHandler, tag: Option<&str>) -> Vec<u64> {
+    let mut actions = Vec::new();
+    let mut planned = Vec::new();
+
+    for &id in ids {
+        let pending = fhandler.pending_tag(id);
+        let archive = match pending {
+            Some(_) => None,
+            None => {
+                actions.push(PocketAction::Archive { item_id: id });
+                Some(actions.len() - 1)
+            },
+        };
+        let tag = match pending {
+            Some(pending) => Some(pending),
+            None if !fhandler.is_restored(id) => tag.map(str::to_string),
+            None => None,
+        };
+        let tag = tag.map(|tag| {
+            actions.push(PocketAction::TagsAdd { item_id: id, tags: tag.clone() });
+            (actions.len() - 1, tag)
+        });
+
+        planned.push((id, archive, tag));
+    }
+
+    let results = pocket.send_actions(&actions).await;
+    let confirmed = |i: usize| results.get(i).copied().unwrap_or(false);
+    let mut done = Vec::new();
+
+    for (id, archive, tag) in planned {
+        if archive.is_some_and(|i| !confirmed(i)) {
+            println!("🚨 Pocket didn't archive item id {id}");
+            continue;
+        }
+
+        match tag {
+            Some((i, tag)) if !confirmed(i) => {
+                println!("🚨 Pocket didn't tag item id {id} with tag '{tag}', trying again next time");
+                fhandler.tag_pending(id, &tag);
+            },
+            _ => {
+                fhandler.clear_pending(id);
+                done.push(id);
+            },
+        }
+    }
+
+    done
+}
+
+
+// The error tag on the items that couldn't be written, and off those that could after all, or
+// whose tag was renamed since, all in the same send. Only for items done with, those left for the
+// next sync are neither.
+async fn tag_errors<P: PocketApi>(pocket: &P, fhandler: &mut FSHandler, tag: Option<&str>) {
+    let retags = fhandler.error_retags(tag);
+
+    if retags.is_empty() {
+        return;
+    }
+
+    let mut actions = Vec::new();
+    let mut spans = Vec::new();
+
+    for (id, remove, add) in &retags {
+        let start = actions.len();
+        actions.extend(Pocket::retag_actions(*id, remove.clone(), add.clone()));
+        spans.push(start..actions.len());
+    }
+
+    let results = pocket.send_actions(&actions).await;
+
+    for ((id, _, add), span) in retags.into_iter().zip(spans) {
+        match results.get(span).is_some_and(|results| results.iter().all(|ok| *ok)) {
+            true => fhandler.error_retagged(id, add),
+            false => println!("🚨 Pocket didn't retag item id {id}, trying again next time"),
+        }
+    }
+}
+
+
+// Delete the items in Pocket, returns the ids Pocket confirmed. A tag still pending for one of
+// them goes with it, the others are sent again the next time.
+pub async fn delete_upstream<P: PocketApi>(pocket: &P, ids: &[u64], fhandler: &mut FSHandler) -> Vec<u64> {
+    let done = pocket.delete(ids.to_vec()).await;
+
+    for id in ids {
+        match done.contains(id) {
+            true => fhandler.clear_pending(*id),
+            false => println!("🚨 Pocket didn't delete item id {id}"),
+        }
+    }
+
+    done
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::statustags::{ARCHIVED_TAG, SKIPPED_TAG};
+    use std::cell::Cell;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use std::fs;
+    use serial_test::serial;
+    use crate::clock::SystemClock;
+    use crate::fshandler::root::xochitl_root;
+    use crate::mockserver::{self, Reply};
+    use crate::settings::{UuidSource, DEFAULT_QUERY};
+    use crate::pocket::Pocket;
+    use crate::testutil::{context, item_json, pocket_items, tags};
+
+    // The system's clocks, with the wall clock an hour ahead once the given page has been asked for,
+    // as if the device had slept through it.
+    struct SuspendingClock {
+        pages: mockserver::Requests,
+        path: &'static str,
+    }
+
+    impl Clock for SuspendingClock {
+        fn now(&self) -> SystemTime {
+            match self.pages.lock().unwrap().iter().any(|req| req.path == self.path) {
+                true => SystemTime::now() + Duration::from_secs(3600),
+                false => SystemTime::now(),
+            }
+        }
+
+        fn monotonic(&self) -> Duration {
+            SystemClock.monotonic()
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingRefresher(Cell<u32>);
+
+    impl Refresher for CountingRefresher {
+        fn refresh(&self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    fn json(value: serde_json::Value) -> Reply {
+        Reply::ok("application/json", value.to_string().as_bytes())
+    }
+
+    fn initialize() {
+        let _ = fs::create_dir_all(xochitl_root());
+        let _ = fs::remove_file(inflight::inflight_file(crate::fshandler::config_file()));
+    }
+
+    async fn sync(pocket: &mut Pocket, fhandler: &mut FSHandler, refresher: &CountingRefresher) -> Result<SyncSummary, SyncError> {
+        let mut ctx = SyncContext { refresher, ..context(pocket, fhandler, Settings::from_toml("reconcile_every = 0")) };
+
+        run_sync(&mut ctx).await
+    }
+
+    // An article whose server takes the request and never answers fails as timed out, and the
+    // sync goes on to the next one.
+    #[tokio::test]
+    #[serial]
+    async fn stalled_article() {
+        initialize();
+        let page = b"<html><head><title>Prompt</title></head><body><p>Something that arrives right away.</p></body></html>";
+        let site = mockserver::serve(vec![("/stalled", Reply::stalled()), ("/prompt", Reply::ok("text/html", page))]).await;
+        let api = mockserver::serve(vec![
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 1729763686,
+                "list": { "110": item_json(110, &format!("{site}/stalled")), "111": item_json(111, &format!("{site}/prompt")) },
+            }))),
+        ]).await;
+
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut fhandler = FSHandler::new();
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\narticle_timeout = 1"));
+
+        let started = std::time::Instant::now();
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(30), "{:?}", started.elapsed());
+        assert_eq!((summary.written, summary.failed), (1, 1));
+        assert_eq!(fhandler.failure_reason(110), Some("timeout"));
+        assert_eq!(fhandler.failure_reason(111), None);
+    }
+
+    // `repocket status` reads the state from disk while a sync saves it, after every article, and
+    // always finds it whole, with no fewer articles than the time before.
+    #[tokio::test]
+    #[serial]
+    async fn status_during_a_sync() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        initialize();
+        let page = b"<html><head><title>Meanwhile</title></head><body><p>Something to read while it's looked at.</p></body></html>";
+        let paths: Vec<String> = (120..126).map(|id| format!("/{id}")).collect();
+        let site = mockserver::serve(paths.iter()
+            .map(|path| (path.as_str(), Reply::ok("text/html", page).slow(Duration::from_millis(50))))
+            .collect()).await;
+        let list: serde_json::Map<String, serde_json::Value> = (120..126)
+            .map(|id| (id.to_string(), item_json(id, &format!("{site}/{id}"))))
+            .collect();
+        let api = mockserver::serve(vec![("/v3/get", json(serde_json::json!({"status": 1, "since": 1729763686, "list": list})))]).await;
+
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut fhandler = FSHandler::new();
+        let state = fhandler.state_file().to_string();
+        fhandler.save_config().unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = std::thread::spawn({
+            let done = done.clone();
+            move || {
+                let (mut reads, mut seen) = (0, 0);
+                while !done.load(Ordering::Relaxed) {
+                    let in_folder = FSHandler::load_unconsolidated(&state).unwrap().status_report().in_folder.len();
+                    assert!(in_folder >= seen, "{in_folder} after {seen}");
+                    reads += 1;
+                    seen = in_folder;
+                }
+                reads
+            }
+        });
+
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0"));
+        let summary = run_sync(&mut ctx).await;
+        done.store(true, Ordering::Relaxed);
+
+        assert!(reader.join().unwrap() > 0);
+        assert_eq!(summary.unwrap().written, 6);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn successful_sync() {
+        let page = b"<html><head><title>Fresh</title></head><body><p>Something new to read, at last.</p></body></html>";
+        let site = mockserver::serve(vec![("/article", Reply::ok("text/html", page))]).await;
+        let api = mockserver::serve(vec![
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 1729763686,
+                "list": { "100": item_json(100, &format!("{site}/article")) },
+            }))),
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true]}))),
+        ]).await;
+
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        initialize();
+        let mut fhandler = FSHandler::new();
+        fhandler.mark_read(300);
+        let refresher = CountingRefresher::default();
+
+        let summary = sync(&mut pocket, &mut fhandler, &refresher).await.unwrap();
+
+        assert_eq!(summary.written, 1);
+        assert_eq!(summary.archived, 1);
+        assert_eq!(summary.deferred, 0);
+        assert!(summary.bytes > 0);
+        assert_eq!(summary.budget, "full");
+        assert_eq!(fhandler.last_query_ts(), 1729763686);
+        assert_eq!(fhandler.read_ids().count(), 0);
+        assert_eq!(refresher.0.get(), 1);
+    }
+
+    // A mirrored item moved back into the Pocket folder is re-added, and when Pocket returns it as
+    // unread its document is the one already there.
+    #[tokio::test]
+    #[serial]
+    async fn mirrored_item_read_again() {
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true]}))),
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 1729763686, "list": { "600": item_json(600, "http://127.0.0.1:9/never") }}))),
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 1729763686, "list": []}))),
+        ]).await;
+
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        initialize();
+        let mut fhandler = FSHandler::new();
+        fhandler.mirror_moved_back(600);
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\narchive_mirror = { count = 5 }"));
+
+        let summary = run_sync(&mut ctx).await.unwrap();
+        assert_eq!((summary.written, summary.failed), (0, 0));
+
+        let requests = requests.lock().unwrap();
+        let paths: Vec<String> = requests.iter().map(|req| req.path.clone()).collect();
+        assert_eq!(paths, vec!["/v3/send", "/v3/get", "/v3/get"]);
+        let sent: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(sent["actions"], serde_json::json!([{"action": "readd", "item_id": 600}]));
+        let mirror: serde_json::Value = serde_json::from_str(&requests[2].body).unwrap();
+        assert_eq!((&mirror["state"], &mirror["count"]), (&serde_json::json!("archive"), &serde_json::json!(5)));
+        assert_eq!(fhandler.readd_ids().count(), 0);
+        assert!(!fhandler.take_readded(600));
+    }
+
+    // Pocket said, last time around, that it takes no more requests for a while.
+    #[tokio::test]
+    #[serial]
+    async fn deferred_by_rate_limit() {
+        let sent = json(serde_json::json!({"status": 1, "action_results": [true]}))
+            .header("X-Limit-User-Remaining", "1")
+            .header("X-Limit-User-Reset", "600");
+        let (api, requests) = mockserver::serve_recording(vec![("/v3/send", sent)]).await;
+
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        assert!(pocket.add_tags(100, &["seen"]).await);
+        initialize();
+        let mut fhandler = FSHandler::new();
+        fhandler.mark_read(300);
+        let refresher = CountingRefresher::default();
+
+        // The retrieve and the archive send are one more than what's left.
+        let synced = sync(&mut pocket, &mut fhandler, &refresher).await;
+
+        assert!(matches!(synced, Err(SyncError::Rejected(pocket::Error::RateLimited { retry_after: Some(secs) })) if secs > 0 && secs <= 600));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(fhandler.read_ids().collect::<Vec<u64>>(), vec![300]);
+        assert_eq!(refresher.0.get(), 0);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn trigger_tag_taken_off() {
+        let page = b"<html><head><title>Tagged</title></head><body><p>Sent over on purpose, to be read soon.</p></body></html>";
+        let site = mockserver::serve(vec![("/article", Reply::ok("text/html", page)), ("/other", Reply::ok("text/html", page))]).await;
+        let mut tagged = item_json(100, &format!("{site}/article"));
+        tagged["tags"] = tags(100, &["to-remarkable"]);
+        let mut queued = item_json(200, &format!("{site}/other"));
+        queued["tags"] = tags(200, &["to-remarkable", "rm-1"]);
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 1729763686, "list": { "100": tagged, "200": queued }}))),
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true]}))),
+        ]).await;
+
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        initialize();
+        let mut fhandler = FSHandler::new();
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\ntrigger_tag = \"to-remarkable\"\nqueue_tag_prefix = \"rm-\""));
+
+        assert_eq!(run_sync(&mut ctx).await.unwrap().written, 2);
+
+        let requests = requests.lock().unwrap();
+        let query: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(query["tag"], "to-remarkable");
+        // One action for each, the trigger and the queue tag together, sent as it's saved.
+        let mut actions: Vec<serde_json::Value> = requests[1..].iter()
+            .map(|req| serde_json::from_str::<serde_json::Value>(&req.body).unwrap()["actions"].clone())
+            .collect();
+        actions.sort_by_key(|actions| actions[0]["item_id"].as_u64());
+        assert_eq!(actions, vec![
+            serde_json::json!([{"action": "tags_remove", "item_id": 100, "tags": "to-remarkable"}]),
+            serde_json::json!([{"action": "tags_remove", "item_id": 200, "tags": "to-remarkable,rm-1"}]),
+        ]);
+        assert_eq!(requests.len(), 3);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn partial_archive_failure() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 1729763686, "list": {}}))),
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true, false, false]}))),
+        ]).await;
+
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        initialize();
+        let mut fhandler = FSHandler::new();
+        fhandler.mark_read(1);
+        fhandler.mark_read(2);
+        let refresher = CountingRefresher::default();
+
+        let summary = sync(&mut pocket, &mut fhandler, &refresher).await.unwrap();
+
+        assert_eq!(summary.written, 0);
+        assert_eq!(summary.archived, 1);
+        assert_eq!(refresher.0.get(), 1);
+
+        // Archived and tagged in the same send, whichever item went second wasn't.
+        let requests = requests.lock().unwrap();
+        let paths: Vec<String> = requests.iter().map(|req| req.path.clone()).collect();
+        assert_eq!(paths, vec!["/v3/get", "/v3/send"]);
+        let actions = &serde_json::from_str::<serde_json::Value>(&requests[1].body).unwrap()["actions"];
+        assert_eq!(fhandler.read_ids().collect::<Vec<u64>>(), vec![actions[2]["item_id"].as_u64().unwrap()]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn skip_non_articles() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 1729763686,
+                "list": { "500": item_json(500, "https://accounts.google.com/o/oauth2/v2/auth?client_id=abc") },
+            }))),
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true]}))),
+        ]).await;
+
+        initialize();
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        let mut fhandler = FSHandler::new();
+        let refresher = CountingRefresher::default();
+
+        let summary = sync(&mut pocket, &mut fhandler, &refresher).await.unwrap();
+
+        assert_eq!(summary.written, 0);
+        assert_eq!(summary.skipped, vec!["https://accounts.google.com/o/oauth2/v2/auth?client_id=abc"]);
+        assert_eq!(fhandler.last_query_ts(), 1729763686);
+
+        // Nothing was fetched, the item was archived and tagged as skipped.
+        let requests = requests.lock().unwrap();
+        let actions = &serde_json::from_str::<serde_json::Value>(&requests[1].body).unwrap()["actions"];
+        assert_eq!(requests.len(), 2);
+        assert_eq!(actions[0]["action"], "archive");
+        assert_eq!(actions[0]["item_id"], 500);
+        assert_eq!(actions[1]["action"], "tags_add");
+        assert_eq!(actions[1]["tags"], SKIPPED_TAG);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn resync_writes_what_is_missing() {
+        initialize();
+        let page = b"<html><head><title>Resync</title></head><body><p>Something to read, again.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(vec![
+            ("/901", Reply::ok("text/html", page)),
+            ("/902", Reply::ok("text/html", page)),
+            ("/903", Reply::ok("text/html", page)),
+        ]).await;
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 100,
+                "list": { "901": item_json(901, &format!("{site}/901")), "902": item_json(902, &format!("{site}/902")) },
+            }))),
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 200,
+                "list": {
+                    "901": item_json(901, &format!("{site}/901")),
+                    "902": item_json(902, &format!("{site}/902")),
+                    "903": item_json(903, &format!("{site}/903")),
+                },
+            }))),
+        ]).await;
+
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut fhandler = FSHandler::new();
+        let refresher = CountingRefresher::default();
+        assert_eq!(sync(&mut pocket, &mut fhandler, &refresher).await.unwrap().written, 2);
+
+        // As if restored from an older backup, 902's document went.
+        let uuid = crate::utils::uuid_to_string(crate::fshandler::document_uuid(UuidSource::Url, Some(902), &format!("{site}/902")));
+        for entry in fs::read_dir(xochitl_root()).unwrap().flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&uuid) {
+                fs::remove_file(entry.path()).unwrap();
+            }
+        }
+
+        resync::request();
+        let summary = sync(&mut pocket, &mut fhandler, &refresher).await.unwrap();
+
+        assert_eq!(summary.written, 2);
+        assert!(!resync::requested());
+        assert_eq!(fhandler.last_query_ts(), 200);
+        assert_eq!(fhandler.status_report().in_folder.len(), 3);
+
+        // Every unread item, 901 left as it was.
+        let gets: Vec<serde_json::Value> = requests.lock().unwrap().iter()
+            .filter(|req| req.path == "/v3/get")
+            .map(|req| serde_json::from_str(&req.body).unwrap())
+            .collect();
+        assert!(gets[1]["since"].is_null());
+        let fetched: Vec<String> = pages.lock().unwrap().iter().skip(2).map(|req| req.path.clone()).collect();
+        assert_eq!(fetched, vec!["/902".to_string(), "/903".to_string()]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn archived_elsewhere_leaves_the_device() {
+        initialize();
+        let page = b"<html><head><title>Elsewhere</title></head><body><p>Something read on the phone.</p></body></html>";
+        let site = mockserver::serve(vec![
+            ("/701", Reply::ok("text/html", page)),
+            ("/702", Reply::ok("text/html", page)),
+            ("/703", Reply::ok("text/html", page)),
+        ]).await;
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 100,
+                "list": {
+                    "701": item_json(701, &format!("{site}/701")),
+                    "702": item_json(702, &format!("{site}/702")),
+                    "703": item_json(703, &format!("{site}/703")),
+                },
+            }))),
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 200, "list": []}))),
+            // Archived on the phone, deleted on the phone and still unread.
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 300,
+                "list": {
+                    "701": { "item_id": "701", "status": "1" },
+                    "702": { "item_id": "702", "status": "2" },
+                    "703": { "item_id": "703", "resolved_id": "703", "status": "0" },
+                },
+            }))),
+        ]).await;
+
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut fhandler = FSHandler::new();
+        let refresher = CountingRefresher::default();
+        assert_eq!(sync(&mut pocket, &mut fhandler, &refresher).await.unwrap().written, 3);
+
+        let mut ctx = SyncContext { refresher: &refresher, ..context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 1\nreconcile_to = \"trash\"")) };
+        run_sync(&mut ctx).await.unwrap();
+
+        let left: Vec<u64> = fhandler.status_report().in_folder.iter().map(|item| item.id).collect();
+        assert_eq!(left, vec![703]);
+        assert_eq!(fhandler.last_reconcile_ts(), 300);
+
+        for id in [701, 702] {
+            let uuid = crate::utils::uuid_to_string(crate::fshandler::document_uuid(UuidSource::Url, Some(id), &format!("{site}/{id}")));
+            let metadata: serde_json::Value = serde_json::from_str(&fs::read_to_string(format!("{}/{uuid}.metadata", xochitl_root())).unwrap()).unwrap();
+            assert_eq!(metadata["parent"], "trash", "{id}");
+        }
+
+        let gets: Vec<serde_json::Value> = requests.lock().unwrap().iter()
+            .filter(|req| req.path == "/v3/get")
+            .map(|req| serde_json::from_str(&req.body).unwrap())
+            .collect();
+        assert_eq!(gets[2]["state"], "all");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn crash_log_names_the_item() {
+        initialize();
+        let page = b"<html><head><title>Crash</title></head><body><p>Something that never gets read.</p></body></html>";
+        let site = mockserver::serve(vec![("/crash", Reply::ok("text/html", page))]).await;
+        let api = mockserver::serve(vec![
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 100,
+                "list": { "601": item_json(601, &format!("{site}/crash")) },
+            }))),
+        ]).await;
+        let dir = std::env::temp_dir().join(format!("repocket-crashes-{}", uuid::Uuid::new_v4()));
+        let hook = std::panic::take_hook();
+        crashlog::install(dir.clone());
+        crashlog::PANIC_AT.with(|at| at.set(Some("extracting")));
+
+        // On this thread, for PANIC_AT, and in a task of its own, for the panic to stop there.
+        let crashed = tokio::task::LocalSet::new().run_until(tokio::task::spawn_local(async move {
+            let mut pocket = Pocket::mock(&format!("{api}/v3"));
+            let mut fhandler = FSHandler::new();
+            let _ = sync(&mut pocket, &mut fhandler, &CountingRefresher::default()).await;
+        })).await;
+        crashlog::PANIC_AT.with(|at| at.set(None));
+        // The test harness's own hook back, before anything else can panic.
+        std::panic::set_hook(hook);
+
+        assert!(crashed.unwrap_err().is_panic());
+
+        let report = fs::read_dir(&dir).unwrap().flatten()
+            .map(|entry| fs::read_to_string(entry.path()).unwrap())
+            .find(|report| report.contains("A controlled panic while extracting"))
+            .unwrap();
+        assert!(report.contains(&format!("Item id 601 with URL {site}/crash\nStage: extracting")), "{report}");
+        assert!(report.contains("Backtrace:"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn large_items_wait_for_the_charger() {
+        initialize();
+        let small = b"<html><head><title>Small</title></head><body><p>Something short to read.</p></body></html>".to_vec();
+        let mut large = b"<html><head><title>Large</title></head><body><p>Something long to read.</p>".to_vec();
+        large.extend(vec![b' '; 2 * 1024 * 1024]);
+        large.extend(b"</body></html>");
+        let site = mockserver::serve(vec![
+            ("/small", Reply::ok("text/html", &small)),
+            ("/large", Reply::ok("text/html", &large)),
+            ("/video", Reply::ok("video/mp4", b"")),
+        ]).await;
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 100,
+                "list": {
+                    "801": item_json(801, &format!("{site}/small")),
+                    "802": item_json(802, &format!("{site}/large")),
+                    "803": item_json(803, &format!("{site}/video")),
+                },
+            }))),
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 200, "list": []}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\nlarge_item_mb = 1"));
+
+        device::CHARGING.with(|charging| charging.set(Some(false)));
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!(summary.written, 1);
+        assert_eq!(summary.large, 1);
+        assert_eq!(summary.skipped, vec![format!("{site}/video")]);
+        // Not fetched, but neither archived nor tagged.
+        assert!(!requests.lock().unwrap().iter().any(|req| req.path == "/v3/send"));
+        assert_eq!(ctx.fhandler.large_items().len(), 1);
+        assert_eq!(ctx.fhandler.last_query_ts(), 100);
+
+        // Nothing new from Pocket, but the large one is written now.
+        device::CHARGING.with(|charging| charging.set(Some(true)));
+        let summary = run_sync(&mut ctx).await.unwrap();
+        device::CHARGING.with(|charging| charging.set(None));
+
+        assert_eq!(summary.written, 1);
+        assert!(ctx.fhandler.large_items().is_empty());
+        assert_eq!(ctx.fhandler.status_report().in_folder.len(), 2);
+    }
+
+    //
+    // Syncing only what's in Spanish:
+    //  701. in English as far as Pocket knows, skipped before it's fetched,
+    //  702. unknown to Pocket, in English as the page says, skipped once fetched,
+    //  703. unknown to Pocket and the page, written,
+    //  704. in Spanish, written.
+    //
+    #[tokio::test]
+    #[serial]
+    async fn only_some_languages() {
+        initialize();
+        let english = b"<html lang=\"en-US\"><head><title>Hello</title></head><body><p>Something to read, in English.</p></body></html>";
+        let unknown = b"<html><head><title>Hola</title></head><body><p>Algo que leer, sin saber en que idioma.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(vec![
+            ("/701", Reply::ok("text/html", english)),
+            ("/702", Reply::ok("text/html", english)),
+            ("/703", Reply::ok("text/html", unknown)),
+            ("/704", Reply::ok("text/html", unknown)),
+        ]).await;
+        let lang = |id: u64, lang: &str| {
+            let mut item = item_json(id, &format!("{site}/{id}"));
+            item["lang"] = serde_json::json!(lang);
+            item
+        };
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({
+                "status": 1,
+                "since": 100,
+                "list": { "701": lang(701, "en"), "702": lang(702, ""), "703": lang(703, ""), "704": lang(704, "es") },
+            }))),
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true, true, true]}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\nlanguages = [\"es\"]"));
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!(summary.written, 2);
+        assert_eq!(summary.failed, 0);
+        assert_eq!(summary.skipped, vec![format!("{site}/701"), format!("{site}/702")]);
+
+        let fetched: BTreeSet<String> = pages.lock().unwrap().iter().map(|req| req.path.clone()).collect();
+        assert_eq!(fetched, BTreeSet::from(["/702".to_string(), "/703".to_string(), "/704".to_string()]));
+
+        // Skipped, not failed, nothing left on the disk, and both archived with the skipped tag.
+        let report = fhandler.status_report();
+        assert!(report.failed.is_empty());
+        let langs: BTreeMap<u64, Option<String>> = report.in_folder.iter()
+            .map(|item| (item.id, item.flags.as_ref().unwrap().lang.clone()))
+            .collect();
+        assert_eq!(langs, BTreeMap::from([(703, None), (704, Some("es".to_string()))]));
+        let uuid = crate::utils::uuid_to_string(uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, format!("{site}/702").as_bytes()));
+        assert!(!std::path::Path::new(&format!("{}/{uuid}.epub", xochitl_root())).exists());
+
+        let sent: Vec<serde_json::Value> = requests.lock().unwrap().iter()
+            .filter(|req| req.path == "/v3/send")
+            .map(|req| serde_json::from_str::<serde_json::Value>(&req.body).unwrap()["actions"].clone())
+            .collect();
+        assert_eq!(sent, vec![serde_json::json!([
+            {"action": "archive", "item_id": 701},
+            {"action": "tags_add", "item_id": 701, "tags": SKIPPED_TAG},
+            {"action": "archive", "item_id": 702},
+            {"action": "tags_add", "item_id": 702, "tags": SKIPPED_TAG},
+        ])]);
+    }
+
+    // Saved a month ago and an hour ago, only items from the last two weeks are wanted.
+    #[tokio::test]
+    #[serial]
+    async fn old_items_left_out() {
+        initialize();
+        let page = b"<html><head><title>Recent</title></head><body><p>Something saved not long ago.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(vec![
+            ("/old", Reply::ok("text/html", page)),
+            ("/recent", Reply::ok("text/html", page)),
+        ]).await;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let added = |id: u64, path: &str, ago: u64| {
+            let mut item = item_json(id, &format!("{site}/{path}"));
+            item["time_added"] = serde_json::json!((now - ago).to_string());
+            item
+        };
+        let list = serde_json::json!({ "801": added(801, "old", 30 * 86400), "802": added(802, "recent", 3600) });
+
+        for (old_items, sends) in [("ignore", 0), ("archive", 1)] {
+            let (api, requests) = mockserver::serve_recording(vec![
+                ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": list}))),
+                ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true]}))),
+            ]).await;
+
+            let mut fhandler = FSHandler::new();
+            let mut pocket = Pocket::mock(&format!("{api}/v3"));
+            let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml(&format!("reconcile_every = 0\nmax_item_age_days = 14\nold_items = \"{old_items}\"")));
+            let summary = run_sync(&mut ctx).await.unwrap();
+
+            assert_eq!(summary.written, 1, "{old_items}");
+            assert_eq!(summary.too_old, 1, "{old_items}");
+            assert!(summary.skipped.is_empty(), "{old_items}");
+            assert_eq!(fhandler.last_query_ts(), 100, "{old_items}");
+
+            let requests = requests.lock().unwrap();
+            let sent: Vec<serde_json::Value> = requests.iter()
+                .filter(|req| req.path == "/v3/send")
+                .map(|req| serde_json::from_str::<serde_json::Value>(&req.body).unwrap()["actions"].clone())
+                .collect();
+            assert_eq!(sent.len(), sends, "{old_items}");
+            if let [actions] = sent.as_slice() {
+                assert_eq!(actions[0]["item_id"], 801);
+                assert_eq!(actions[1]["tags"], SKIPPED_TAG);
+            }
+        }
+
+        // The old one was never fetched.
+        assert!(pages.lock().unwrap().iter().all(|req| req.path == "/recent"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn safe_mode_only_pushes() {
+        let (base, requests) = mockserver::serve_recording(vec![
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true]}))),
+        ]).await;
+
+        let mut pocket = Pocket::mock(&format!("{base}/v3"));
+        initialize();
+        let mut fhandler = FSHandler::new();
+        fhandler.mark_read(9);
+        let refresher = CountingRefresher::default();
+
+        let mut ctx = SyncContext { refresher: &refresher, safe_mode: true, ..context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 1")) };
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!(summary.written, 0);
+        assert_eq!(summary.archived, 1);
+        assert_eq!(summary.budget, "safe mode");
+        assert_eq!(fhandler.read_ids().count(), 0);
+        assert_eq!(refresher.0.get(), 1);
+
+        // Nothing retrieved nor reconciled, only archived and tagged.
+        let paths: Vec<String> = requests.lock().unwrap().iter().map(|req| req.path.clone()).collect();
+        assert_eq!(paths, vec!["/v3/send"]);
+
+        // The report is there to read, the same document every time.
+        let uuid = crate::utils::uuid_to_string(uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, b"repocket-safe-mode-report"));
+        let report = format!("{}/{uuid}.epub", xochitl_root());
+        assert!(fs::metadata(&report).unwrap().len() > 0);
+        assert!(fs::read_to_string(format!("{}/{uuid}.metadata", xochitl_root())).unwrap().contains(crate::strings::current().safe_mode_title));
+    }
+
+    // Pocket confirming every action but the given ones, e.g. ("archive", 3), with nothing new to
+    // list.
+    #[derive(Default)]
+    struct Scripted {
+        failing: Vec<(&'static str, u64)>,
+        sent: std::cell::RefCell<Vec<serde_json::Value>>,
+    }
+
+    impl PocketApi for Scripted {
+        async fn fetch(&mut self, _query: &crate::pocketquery::PocketQuery) -> Result<(), pocket::Error> {
+            Ok(())
+        }
+
+        async fn fetch_all(&mut self, _query: &crate::pocketquery::PocketQuery, _max: usize) -> Result<(), pocket::Error> {
+            Ok(())
+        }
+
+        fn items(&self) -> &[crate::pocketitem::PocketItem] {
+            &[]
+        }
+
+        fn since(&self) -> u64 {
+            0
+        }
+
+        async fn page(&self, _query: &crate::pocketquery::PocketQuery) -> Result<Vec<crate::pocketitem::PocketItem>, pocket::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn item_statuses(&self, _query: &crate::pocketquery::PocketQuery)
+            -> Result<(std::collections::HashMap<u64, crate::pocketitem::ItemStatus>, u64), pocket::Error> {
+            Ok((std::collections::HashMap::new(), 0))
+        }
+
+        async fn send_actions(&self, actions: &[PocketAction]) -> Vec<bool> {
+            let actions: Vec<serde_json::Value> = actions.iter().map(|action| serde_json::json!(action)).collect();
+            self.sent.borrow_mut().extend(actions.iter().cloned());
+
+            actions.iter()
+                .map(|action| !self.failing.iter().any(|(name, id)| action["action"] == *name && action["item_id"] == *id))
+                .collect()
+        }
+
+        async fn retag(&self, _item: u64, _remove: Option<String>, _add: Option<String>) -> Result<(), pocket::Error> {
+            Ok(())
+        }
+
+        async fn add(&self, _url: &str, _title: Option<&str>, _tags: Option<Vec<String>>) -> Result<u64, pocket::Error> {
+            Ok(0)
+        }
+    }
+
+    async fn push(pocket: &mut Scripted, fhandler: &mut FSHandler, settings: Settings) -> SyncSummary {
+        let mut summary = SyncSummary::default();
+        let mut ctx = context(pocket, fhandler, settings);
+
+        push_read(&mut ctx, &mut summary).await;
+        summary
+    }
+
+    //
+    // Pocket failing some of the actions, item by item:
+    //  1. archived and tagged,
+    //  2. archived, not tagged,
+    //  3. not archived, tagged,
+    //  4. neither,
+    //  5. archived as skipped by an earlier sync, not tagged then, tagged now.
+    // Then everything goes through.
+    //
+    #[tokio::test]
+    #[serial]
+    async fn archive_and_tag_together() {
+        initialize();
+        let mut fhandler = FSHandler::new();
+        (1..=4).for_each(|id| fhandler.mark_read(id));
+        fhandler.tag_pending(5, SKIPPED_TAG);
+
+        let mut pocket = Scripted { failing: vec![("tags_add", 2), ("archive", 3), ("archive", 4), ("tags_add", 4)], ..Scripted::default() };
+        assert_eq!(push(&mut pocket, &mut fhandler, Settings::default()).await.archived, 1);
+
+        let read: BTreeSet<u64> = fhandler.read_ids().collect();
+        assert_eq!(read, BTreeSet::from([2, 3, 4]));
+        assert_eq!(fhandler.pending_ids().collect::<Vec<u64>>(), vec![2]);
+        assert!(pocket.sent.borrow().contains(&serde_json::json!({"action": "tags_add", "item_id": 5, "tags": SKIPPED_TAG})));
+        assert!(!pocket.sent.borrow().iter().any(|action| action["item_id"] == 5 && action["action"] == "archive"));
+
+        // Only the tag for the one archived already.
+        let mut pocket = Scripted::default();
+        assert_eq!(push(&mut pocket, &mut fhandler, Settings::default()).await.archived, 3);
+
+        let mut sent: Vec<(String, u64)> = pocket.sent.borrow().iter()
+            .map(|action| (action["action"].as_str().unwrap().to_string(), action["item_id"].as_u64().unwrap()))
+            .collect();
+        sent.sort();
+        let expected: Vec<(String, u64)> = [("archive", 3), ("archive", 4), ("tags_add", 2), ("tags_add", 3), ("tags_add", 4)]
+            .iter()
+            .map(|(action, id)| (action.to_string(), *id))
+            .collect();
+        assert_eq!(sent, expected);
+        assert_eq!(fhandler.read_ids().count(), 0);
+        assert_eq!(fhandler.pending_ids().count(), 0);
+    }
+
+    // The error tag of every failed item in the same send, those Pocket didn't take are tried again,
+    // and all taken off once the tag is turned off.
+    #[tokio::test]
+    #[serial]
+    async fn error_tags() {
+        initialize();
+        let mut fhandler = FSHandler::new();
+        let items = pocket_items(&[item_json(41, "https://example.com/41"), item_json(42, "https://example.com/42")]);
+        items.iter().for_each(|item| fhandler.fail_item(item, "timeout"));
+        let error = Some("repocket-error");
+
+        let pocket = Scripted { failing: vec![("tags_add", 42)], ..Scripted::default() };
+        tag_errors(&pocket, &mut fhandler, error).await;
+        assert_eq!(*pocket.sent.borrow(), vec![
+            serde_json::json!({"action": "tags_add", "item_id": 41, "tags": "repocket-error"}),
+            serde_json::json!({"action": "tags_add", "item_id": 42, "tags": "repocket-error"}),
+        ]);
+
+        let pocket = Scripted::default();
+        tag_errors(&pocket, &mut fhandler, error).await;
+        assert_eq!(*pocket.sent.borrow(), vec![serde_json::json!({"action": "tags_add", "item_id": 42, "tags": "repocket-error"})]);
+
+        let pocket = Scripted::default();
+        tag_errors(&pocket, &mut fhandler, error).await;
+        assert!(pocket.sent.borrow().is_empty());
+
+        tag_errors(&pocket, &mut fhandler, None).await;
+        assert_eq!(pocket.sent.borrow().len(), 2);
+        assert!(pocket.sent.borrow().iter().all(|action| action["action"] == "tags_remove"));
+        assert!(fhandler.error_retags(None).is_empty());
+    }
+
+    // Favorites, once archived, not before.
+    #[tokio::test]
+    #[serial]
+    async fn favorite_read() {
+        initialize();
+        let mut fhandler = FSHandler::new();
+        (1..=3).for_each(|id| fhandler.mark_read(id));
+        let settings = Settings::from_toml("favorite_read = true");
+
+        let mut pocket = Scripted { failing: vec![("archive", 2)], ..Scripted::default() };
+        assert_eq!(push(&mut pocket, &mut fhandler, settings).await.archived, 2);
+
+        let mut favorited: Vec<u64> = pocket.sent.borrow().iter()
+            .filter(|action| action["action"] == "favorite")
+            .map(|action| action["item_id"].as_u64().unwrap())
+            .collect();
+        favorited.sort();
+        assert_eq!(favorited, vec![1, 3]);
+    }
+
+    // Deleting rather than archiving, only what Pocket confirmed deleting is done with, tags and all.
+    #[tokio::test]
+    #[serial]
+    async fn delete_read() {
+        initialize();
+        let mut fhandler = FSHandler::new();
+        (1..=3).for_each(|id| fhandler.mark_read(id));
+        fhandler.tag_pending(4, ARCHIVED_TAG);
+        let settings = Settings::from_toml("pocket_read = \"delete\"");
+
+        let mut pocket = Scripted { failing: vec![("delete", 2)], ..Scripted::default() };
+        let summary = push(&mut pocket, &mut fhandler, settings.clone()).await;
+
+        // Item 4 was never read, only waiting on its tag, it's tagged and stays in Pocket.
+        assert_eq!((summary.deleted, summary.archived), (2, 0));
+        assert_eq!(*pocket.sent.borrow(), vec![
+            serde_json::json!({"action": "delete", "item_id": 1}),
+            serde_json::json!({"action": "delete", "item_id": 2}),
+            serde_json::json!({"action": "delete", "item_id": 3}),
+            serde_json::json!({"action": "tags_add", "item_id": 4, "tags": ARCHIVED_TAG}),
+        ]);
+        assert_eq!(fhandler.read_ids().collect::<Vec<u64>>(), vec![2]);
+        assert_eq!(fhandler.pending_ids().count(), 0);
+
+        let mut pocket = Scripted::default();
+        assert_eq!(push(&mut pocket, &mut fhandler, settings).await.deleted, 1);
+        assert_eq!(*pocket.sent.borrow(), vec![serde_json::json!({"action": "delete", "item_id": 2})]);
+        assert_eq!(fhandler.read_ids().count(), 0);
+    }
+
+    fn sent_since(requests: &mockserver::Requests) -> Vec<serde_json::Value> {
+        requests.lock().unwrap().iter()
+            .filter(|req| req.path == "/v3/get")
+            .map(|req| serde_json::from_str::<serde_json::Value>(&req.body).unwrap()["since"].clone())
+            .collect()
+    }
+
+    //
+    // A sync cut short at each step of the way, with items 1 to 4 retrieved with since 100:
+    //  1. written, the state saved and the item marked as done,
+    //  2. written, but neither in the state nor marked as done,
+    //  3. written and in the state, but not marked as done,
+    //  4. not even started.
+    // Then a new sync, with item 5 waiting in Pocket.
+    //
+    #[tokio::test]
+    #[serial]
+    async fn resume_after_being_cut_short() {
+        initialize();
+        let page = b"<html><head><title>Resumed</title></head><body><p>Something to read, sooner or later.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(
+            ["/1", "/2", "/3", "/4", "/5"].into_iter().map(|path| (path, Reply::ok("text/html", page))).collect()
+        ).await;
+        let items: Vec<serde_json::Value> = (1..=5).map(|id| item_json(id, &format!("{site}/{id}"))).collect();
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 200, "list": { "5": items[4] }}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let path = inflight::inflight_file(fhandler.state_file());
+        let retrieved = pocket_items(&items[..4]);
+        let mut batch = Batch::start(&path, BTreeMap::from([(DEFAULT_QUERY.to_string(), 100)]), &retrieved);
+
+        fhandler.new_article(&retrieved[0], ArticleOptions::default()).await.unwrap();
+        batch.done(&retrieved[0]);
+        FSHandler::new().new_article(&retrieved[1], ArticleOptions::default()).await.unwrap();
+        fhandler.new_article(&retrieved[2], ArticleOptions::default()).await.unwrap();
+        pages.lock().unwrap().clear();
+
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let refresher = CountingRefresher::default();
+        let summary = sync(&mut pocket, &mut fhandler, &refresher).await.unwrap();
+
+        // Only what wasn't on the disk is written, and only then does the new query go out.
+        assert_eq!(summary.written, 2);
+        let fetched: BTreeSet<String> = pages.lock().unwrap().iter().map(|req| req.path.clone()).collect();
+        assert_eq!(fetched, BTreeSet::from(["/4".to_string(), "/5".to_string()]));
+        assert_eq!(sent_since(&requests), vec![serde_json::json!(100)]);
+        assert_eq!(fhandler.last_query_ts(), 200);
+        assert_eq!(fhandler.status()[0], "In the Pocket folder: 5");
+        assert!(!path.exists());
+    }
+
+    // Every item done, cut short before the "since" was kept.
+    #[tokio::test]
+    #[serial]
+    async fn resume_a_finished_batch() {
+        initialize();
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 200, "list": {}}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        fhandler.set_last_query_ts(50);
+        let path = inflight::inflight_file(fhandler.state_file());
+        let retrieved = pocket_items(&[item_json(500, "https://accounts.google.com/o/oauth2/v2/auth?client_id=abc")]);
+        let mut batch = Batch::start(&path, BTreeMap::from([(DEFAULT_QUERY.to_string(), 100)]), &retrieved);
+        batch.skip(&retrieved[0]);
+
+        // Read-only upstream, so that the skipped item isn't sent anywhere.
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\nread_only_upstream = true"));
+        run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!(sent_since(&requests), vec![serde_json::json!(100)]);
+        assert_eq!(fhandler.last_query_ts(), 200);
+        assert!(!path.exists());
+    }
+
+    // Out of time, the batch is kept for the next sync, and nothing new is asked for until it's done.
+    #[tokio::test]
+    #[serial]
+    async fn deferred_items_keep_the_batch() {
+        initialize();
+        let page = b"<html><head><title>Later</title></head><body><p>Something to read, later.</p></body></html>";
+        let site = mockserver::serve(vec![("/later", Reply::ok("text/html", page))]).await;
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": { "600": item_json(600, &format!("{site}/later")) }}))),
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 200, "list": {}}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let path = inflight::inflight_file(fhandler.state_file());
+        let _ = fs::remove_file(&path);
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+
+        for (timeout, deferred, since, gets) in [(0, 1, 0, 1), (0, 1, 0, 1), (1200, 0, 200, 2)] {
+            let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml(&format!("reconcile_every = 0\nsync_timeout = {timeout}")));
+            let summary = run_sync(&mut ctx).await.unwrap();
+
+            assert_eq!(summary.deferred, deferred);
+            assert_eq!(fhandler.last_query_ts(), since);
+            assert_eq!(sent_since(&requests).len(), gets);
+            assert_eq!(path.exists(), deferred > 0);
+        }
+
+        assert_eq!(sent_since(&requests), vec![serde_json::json!(0), serde_json::json!(100)]);
+    }
+
+    #[test]
+    fn merged_items() {
+        let mut items = pocket_items(&[item_json(1, "https://example.com/1"), item_json(2, "https://example.com/2")]);
+        let mut again = item_json(2, "https://example.com/two");
+        again["given_url"] = "https://example.com/two".into();
+
+        merge_items(&mut items, &pocket_items(&[item_json(3, "https://example.com/3"), again, item_json(1, "https://example.com/1")]));
+
+        let urls: Vec<String> = items.iter().filter_map(PocketItem::best_url).collect();
+        assert_eq!(urls, vec!["https://example.com/1", "https://example.com/2", "https://example.com/3"]);
+    }
+
+    #[test]
+    fn ordered_items() {
+        let added = |id: u64, time: &str| {
+            let mut item = item_json(id, &format!("https://example.com/{id}"));
+            item["time_added"] = time.into();
+            item
+        };
+        let items = pocket_items(&[added(1, "300"), added(2, "0"), added(3, "100"), added(4, "200"), added(5, "100")]);
+        let ids = |order: ItemOrder| {
+            let mut items = items.clone();
+            order_items(&mut items, order, None);
+            items.iter().filter_map(PocketItem::best_id).collect::<Vec<u64>>()
+        };
+
+        assert_eq!(ids(ItemOrder::Pocket), vec![1, 2, 3, 4, 5]);
+        assert_eq!(ids(ItemOrder::Newest), vec![1, 4, 3, 5, 2]);
+        assert_eq!(ids(ItemOrder::Oldest), vec![3, 5, 4, 1, 2]);
+    }
+
+    #[test]
+    fn queued_items_first() {
+        let queued = |id: u64, tag: &str, time: &str| {
+            let mut item = item_json(id, &format!("https://example.com/{id}"));
+            item["time_added"] = time.into();
+            if !tag.is_empty() {
+                item["tags"] = tags(id, &[tag]);
+            }
+            item
+        };
+        // A gap after rm-1, and rm-5 twice.
+        let items = pocket_items(&[
+            queued(1, "", "500"), queued(2, "rm-5", "100"), queued(3, "rm-1", "200"),
+            queued(4, "later", "400"), queued(5, "rm-5", "300"),
+        ]);
+        let ids = |order: ItemOrder, prefix: Option<&str>| {
+            let mut items = items.clone();
+            order_items(&mut items, order, prefix);
+            items.iter().filter_map(PocketItem::best_id).collect::<Vec<u64>>()
+        };
+
+        assert_eq!(ids(ItemOrder::Pocket, Some("rm-")), vec![3, 2, 5, 1, 4]);
+        assert_eq!(ids(ItemOrder::Newest, Some("rm-")), vec![3, 2, 5, 1, 4]);
+        assert_eq!(ids(ItemOrder::Oldest, Some("rm-")), vec![3, 2, 5, 4, 1]);
+        assert_eq!(ids(ItemOrder::Pocket, None), vec![1, 2, 3, 4, 5]);
+    }
+
+    // Favorites and tagged items, one of them both, each query with its own "since".
+    #[tokio::test]
+    #[serial]
+    async fn several_queries() {
+        initialize();
+        let page = b"<html><head><title>Either</title></head><body><p>Something to read, whichever way it came.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(
+            ["/1", "/2", "/3"].into_iter().map(|path| (path, Reply::ok("text/html", page))).collect()
+        ).await;
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": {
+                "1": item_json(1, &format!("{site}/1")), "2": item_json(2, &format!("{site}/2")),
+            }}))),
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 200, "list": {
+                "2": item_json(2, &format!("{site}/2")), "3": item_json(3, &format!("{site}/3")),
+            }}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        fhandler.set_query_ts("tagged", 50);
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\n\
+                [[queries]]\nname = \"favorites\"\nfavorite = 1\n[[queries]]\nname = \"tagged\"\ntag = \"rm\""));
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!(summary.written, 3);
+        let mut fetched: Vec<String> = pages.lock().unwrap().iter().map(|req| req.path.clone()).collect();
+        fetched.sort();
+        assert_eq!(fetched, vec!["/1", "/2", "/3"]);
+
+        let sent: Vec<serde_json::Value> = requests.lock().unwrap().iter()
+            .filter(|req| req.path == "/v3/get")
+            .map(|req| serde_json::from_str(&req.body).unwrap())
+            .collect();
+        assert_eq!((&sent[0]["favorite"], &sent[0]["since"], sent[0].get("tag")), (&1.into(), &0.into(), None));
+        assert_eq!((&sent[1]["tag"], &sent[1]["since"]), (&"rm".into(), &50.into()));
+        assert_eq!((fhandler.query_ts("favorites"), fhandler.query_ts("tagged")), (100, 200));
+        assert_eq!(fhandler.last_query_ts(), 0);
+    }
+
+    // A backlog of 35 items, 10 at a time: nothing new is asked for until the queue is drained, and
+    // every item is written exactly once.
+    #[tokio::test]
+    #[serial]
+    async fn backlog_over_several_syncs() {
+        initialize();
+        let page = b"<html><head><title>Backlog</title></head><body><p>Something to read, after the holidays.</p></body></html>";
+        let paths: Vec<String> = (1..=35).map(|id| format!("/{id}")).collect();
+        let (site, pages) = mockserver::serve_recording(
+            paths.iter().map(|path| (path.as_str(), Reply::ok("text/html", page))).collect()
+        ).await;
+        let list: serde_json::Map<String, serde_json::Value> = (1..=35)
+            .map(|id| (id.to_string(), item_json(id, &format!("{site}/{id}"))))
+            .collect();
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": list}))),
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 200, "list": {}}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let path = inflight::inflight_file(fhandler.state_file());
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+
+        for (written, queued, since, gets) in [(10, 25, 0, 1), (10, 15, 0, 1), (10, 5, 0, 1), (5, 0, 200, 2)] {
+            let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\n[query]\ncount = 50"));
+            let summary = run_sync(&mut ctx).await.unwrap();
+
+            assert_eq!((summary.written, summary.queued), (written, queued));
+            // The "since" of the retrieve is only kept once its last item is written.
+            assert_eq!(fhandler.last_query_ts(), since);
+            assert_eq!(sent_since(&requests).len(), gets);
+            assert_eq!(path.exists(), queued > 0);
+        }
+
+        let fetched: Vec<String> = pages.lock().unwrap().iter().map(|req| req.path.clone()).collect();
+        assert_eq!(fetched.len(), 35);
+        assert_eq!(fetched.iter().collect::<BTreeSet<_>>().len(), 35);
+        assert_eq!(sent_since(&requests), vec![serde_json::json!(0), serde_json::json!(100)]);
+        assert_eq!(fhandler.status()[0], "In the Pocket folder: 35");
+    }
+
+    // Item 1 is too short to be worth it, and goes to Pocket's archive with the skipped tag.
+    #[tokio::test]
+    #[serial]
+    async fn off_length_archived() {
+        initialize();
+        let page = b"<html><head><title>Long read</title></head><body><p>Something long to read on the device.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(vec![("/2", Reply::ok("text/html", page))]).await;
+        let mut long = item_json(2, &format!("{site}/2"));
+        long["word_count"] = "5000".into();
+        let mut short = item_json(1, &format!("{site}/1"));
+        short["word_count"] = "12".into();
+        let list = serde_json::json!({"1": short, "2": long});
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": list}))),
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true]}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\nmin_word_count = 300\noff_length_items = \"archive\""));
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!((summary.written, summary.off_length), (1, 1));
+        assert_eq!(pages.lock().unwrap().len(), 1);
+
+        let sent: Vec<serde_json::Value> = requests.lock().unwrap().iter()
+            .filter(|req| req.path == "/v3/send")
+            .map(|req| serde_json::from_str(&req.body).unwrap())
+            .collect();
+        assert_eq!(sent[0]["actions"], serde_json::json!([
+            {"action": "archive", "item_id": 1},
+            {"action": "tags_add", "item_id": 1, "tags": "repocket-skipped"},
+        ]));
+    }
+
+    // Items 1 and 3 are tagged to be left out, and don't count towards the 2 written per sync.
+    #[tokio::test]
+    #[serial]
+    async fn excluded_by_tag() {
+        initialize();
+        let page = b"<html><head><title>Wanted</title></head><body><p>Something to read on the device.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(vec![("/2", Reply::ok("text/html", page)), ("/4", Reply::ok("text/html", page))]).await;
+        let tagged = |id: u64, tag: &str| {
+            let mut item = item_json(id, &format!("{site}/{id}"));
+            item["tags"] = tags(id, &[tag]);
+            item
+        };
+        let list = serde_json::json!({
+            "1": tagged(1, "recipe"),
+            "2": tagged(2, "to-remarkable"),
+            "3": tagged(3, "gift-ideas"),
+            "4": item_json(4, &format!("{site}/4")),
+        });
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": list}))),
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true]}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\nmax_items_per_cycle = 2\n\
+                excluded_tags = [\"recipe\", \"gift-ideas\"]\ntag_excluded = true"));
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!((summary.written, summary.excluded, summary.queued), (2, 2, 0));
+        let fetched: Vec<String> = pages.lock().unwrap().iter().map(|req| req.path.clone()).collect();
+        assert_eq!(fetched, vec!["/2".to_string(), "/4".to_string()]);
+
+        // Tagged with the skipped tag, but not archived.
+        let sent: Vec<serde_json::Value> = requests.lock().unwrap().iter()
+            .filter(|req| req.path == "/v3/send")
+            .map(|req| serde_json::from_str(&req.body).unwrap())
+            .collect();
+        let actions: Vec<&serde_json::Value> = sent.iter().map(|sent| &sent["actions"]).collect();
+        assert_eq!(actions, vec![
+            &serde_json::json!([{"action": "tags_add", "item_id": 1, "tags": "repocket-skipped"}]),
+            &serde_json::json!([{"action": "tags_add", "item_id": 3, "tags": "repocket-skipped"}]),
+        ]);
+    }
+
+    // The queue gets the slots, in its order, and is tidied up in Pocket as each is written.
+    #[tokio::test]
+    #[serial]
+    async fn queue_tags_first() {
+        initialize();
+        let page = b"<html><head><title>Queued</title></head><body><p>Something to read, in the order asked for.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(vec![("/2", Reply::ok("text/html", page)), ("/3", Reply::ok("text/html", page))]).await;
+        let tagged = |id: u64, tag: &str| {
+            let mut item = item_json(id, &format!("{site}/{id}"));
+            item["tags"] = tags(id, &[tag]);
+            item
+        };
+        let list = serde_json::json!({
+            "1": item_json(1, &format!("{site}/1")),
+            "2": tagged(2, "rm-7"),
+            "3": tagged(3, "rm-2"),
+            "4": tagged(4, "later"),
+        });
+        let (api, requests) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": list}))),
+            ("/v3/send", json(serde_json::json!({"status": 1, "action_results": [true, true]}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\nmax_items_per_cycle = 2\nqueue_tag_prefix = \"rm-\""));
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!((summary.written, summary.queued), (2, 2));
+        let fetched: Vec<String> = pages.lock().unwrap().iter().map(|req| req.path.clone()).collect();
+        assert_eq!(fetched, vec!["/3".to_string(), "/2".to_string()]);
+
+        let sent: Vec<serde_json::Value> = requests.lock().unwrap().iter()
+            .filter(|req| req.path == "/v3/send")
+            .map(|req| serde_json::from_str(&req.body).unwrap())
+            .collect();
+        let actions: Vec<&serde_json::Value> = sent.iter().map(|sent| &sent["actions"]).collect();
+        assert_eq!(actions, vec![
+            &serde_json::json!([{"action": "tags_remove", "item_id": 3, "tags": "rm-2"}]),
+            &serde_json::json!([{"action": "tags_remove", "item_id": 2, "tags": "rm-7"}]),
+        ]);
+    }
+
+    // Fresh saves, written from what was given to Pocket, and one with nothing to go by left out.
+    #[tokio::test]
+    #[serial]
+    async fn unresolved_items() {
+        initialize();
+        let page = b"<html><head><title>Fresh</title></head><body><p>Saved a moment ago, not resolved yet.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(vec![("/fresh", Reply::ok("text/html", page))]).await;
+        let fixture = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/data/fixtures/unresolved.json")).unwrap();
+        let mut fresh: serde_json::Value = serde_json::from_str(&fixture).unwrap();
+        fresh["given_url"] = format!("{site}/fresh").into();
+        let mut nothing = fresh.clone();
+        nothing["item_id"] = "4012589364".into();
+        nothing["given_url"] = "".into();
+        let (api, _) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": {"4012589363": fresh, "4012589364": nothing}}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let mut ctx = context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0"));
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert_eq!((summary.written, summary.failed), (1, 0));
+        assert_eq!(pages.lock().unwrap().len(), 1);
+        assert!(fhandler.status()[1].contains("(4012589363)"), "{:?}", fhandler.status());
+    }
+
+    // Nowhere to save the state: the sync stops at the first save, rather than going on writing
+    // documents the state doesn't know about, and says where it couldn't save it.
+    #[tokio::test]
+    #[serial]
+    async fn unsaved_state() {
+        initialize();
+        let page = b"<html><head><title>Unsaved</title></head><body><p>Written, but nowhere to say so.</p></body></html>";
+        let site = mockserver::serve(vec![("/article", Reply::ok("text/html", page))]).await;
+        let api = mockserver::serve(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": { "100": item_json(100, &format!("{site}/article")) }}))),
+        ]).await;
+
+        let dir = std::env::temp_dir().join("repocket-unsaved-state");
+        let _ = fs::remove_dir_all(&dir);
+        let state = dir.join("repocket.config").to_string_lossy().to_string();
+        let mut fhandler = FSHandler::load_from(&state).unwrap();
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let refresher = CountingRefresher::default();
+
+        let res = sync(&mut pocket, &mut fhandler, &refresher).await;
+
+        assert!(matches!(res, Err(SyncError::State(FsError::Io { ref path, .. })) if *path == state), "{res:?}");
+        assert!(res.unwrap_err().to_string().contains(&state));
+        assert_eq!(refresher.0.get(), 0);
+    }
+
+    // The connection drops as the device goes to sleep, which is nobody's fault: nothing failed,
+    // and the rest of the batch is left for the sync after it.
+    #[tokio::test]
+    #[serial]
+    async fn suspended_mid_sync() {
+        initialize();
+        let page = b"<html><head><title>Asleep</title></head><body><p>Something to read, once awake again.</p></body></html>";
+        let (site, pages) = mockserver::serve_recording(vec![
+            ("/1", Reply::ok("text/html", page)),
+            ("/2", Reply::dropped()),
+            ("/3", Reply::ok("text/html", page)),
+        ]).await;
+        let items: Vec<serde_json::Value> = (1..=3).map(|id| item_json(id, &format!("{site}/{id}"))).collect();
+        let (api, _) = mockserver::serve_recording(vec![
+            ("/v3/get", json(serde_json::json!({"status": 1, "since": 100, "list": {"1": items[0], "2": items[1], "3": items[2]}}))),
+        ]).await;
+
+        let mut fhandler = FSHandler::new();
+        let path = inflight::inflight_file(fhandler.state_file());
+        let mut pocket = Pocket::mock(&format!("{api}/v3"));
+        let clock = SuspendingClock { pages: pages.clone(), path: "/2" };
+        let mut ctx = SyncContext { clock: &clock, ..context(&mut pocket, &mut fhandler, Settings::from_toml("reconcile_every = 0\nfailure_placeholders = true")) };
+        let summary = run_sync(&mut ctx).await.unwrap();
+
+        assert!(summary.suspended);
+        assert_eq!((summary.written, summary.failed), (1, 0));
+        let fetched: Vec<String> = pages.lock().unwrap().iter().map(|req| req.path.clone()).collect();
+        assert_eq!(fetched, vec!["/1".to_string(), "/2".to_string()]);
+
+        // No failure, no placeholder, and the batch still open with what's left.
+        assert_eq!(fhandler.failure_reason(2), None);
+        assert_eq!(fhandler.status()[0], "In the Pocket folder: 1");
+        let remaining: Vec<u64> = Batch::load(&path).unwrap().remaining().iter().filter_map(PocketItem::best_id).collect();
+        assert_eq!(remaining, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn offline() {
+        // Nothing listens on port 1.
+        let mut pocket = Pocket::mock("http://127.0.0.1:1/v3");
+        initialize();
+        let mut fhandler = FSHandler::new();
+        fhandler.set_last_query_ts(42);
+        fhandler.mark_read(7);
+        let refresher = CountingRefresher::default();
+
+        let res = sync(&mut pocket, &mut fhandler, &refresher).await;
+
+        assert!(matches!(res, Err(SyncError::Unreachable(_))));
+        assert_eq!(fhandler.last_query_ts(), 42);
+        assert_eq!(fhandler.read_ids().collect::<Vec<u64>>(), vec![7]);
+        assert_eq!(refresher.0.get(), 0);
+    }
+}
@@ -0,0 +1,55 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::fshandler::root;
+
+
+//
+// A sync that asks Pocket for every unread item rather than those changed since the last one, for
+// when the state and the device disagree, e.g. after restoring either from a backup. Requested
+// with 'repocket sync --resync', or by putting this file in the xochitl folder, e.g. over ssh,
+// and taken back once the resync's batch is done with.
+//
+// Items whose document is there already are left as they are, see FSHandler::resync_item(), the
+// rest are written like any other.
+//
+pub const RESYNC_FILE: &str = "repocket-resync";
+
+
+pub fn resync_file() -> PathBuf {
+    Path::new(root::xochitl_root()).join(RESYNC_FILE)
+}
+
+
+pub fn requested() -> bool {
+    resync_file().exists()
+}
+
+
+pub fn request() {
+    if let Err(e) = fs::write(resync_file(), "") {
+        println!("🚨 Could not write {}: {e}", resync_file().display());
+    }
+}
+
+
+pub fn done() {
+    let _ = fs::remove_file(resync_file());
+}
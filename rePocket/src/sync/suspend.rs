@@ -0,0 +1,136 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+
+use std::time::{Duration, SystemTime};
+
+use crate::scheduler::WakeDetector;
+
+
+//
+// The reMarkable suspends whenever the folio is closed, in the middle of a sync or not. The
+// downloads under way then fail when it wakes up, the connections reset or timed out, and the
+// network not back yet for the next ones, none of which says anything about the articles. Rather
+// than a failed item each, the sync stops there, the batch kept for the next one, see
+// write_batch(), and the scheduler syncs again once the network is back.
+//
+// A failure is the suspend's when it's of the kind a suspend causes, see transient(), and comes
+// within WINDOW of the clocks showing the device was asleep (the monotonic clock stops while
+// suspended, the wall clock doesn't, see scheduler::WakeDetector). The first one ends the cycle, so
+// the rest of the storm never happens. Any other failure is the item's, as it always was.
+//
+
+// Monotonic time after a wake-up within which a failure may be the suspend's.
+const WINDOW: Duration = Duration::from_secs(30);
+
+// What the errors say, lowercase and without spaces, when the connection went away rather than
+// the site. The reasons are the errors' Debug as often as their Display, "TimedOut" as often as
+// "timed out".
+const TRANSIENT: [&str; 11] = [
+    "connectionreset",
+    "connectionclosed",
+    "connectionaborted",
+    "connectionrefused",
+    "brokenpipe",
+    "timedout",
+    "timeout",
+    "networkunreachable",
+    "networkisunreachable",
+    "incompletemessage",
+    "errorsendingrequest",
+];
+
+
+// Whether the reason an item failed is one a suspend would give.
+pub fn transient(reason: &str) -> bool {
+    let reason: String = reason.to_lowercase().chars().filter(|c| !c.is_whitespace() && *c != '_').collect();
+
+    TRANSIENT.iter().any(|pattern| reason.contains(pattern))
+}
+
+
+#[derive(Default)]
+pub struct StormWatch {
+    detector: WakeDetector,
+    // The monotonic time of the last wake-up seen.
+    woke_at: Option<Duration>,
+}
+
+
+impl StormWatch {
+    // Called before and after each item, with the clocks as they are then.
+    pub fn observe(&mut self, wall: SystemTime, monotonic: Duration) {
+        if let Some(slept) = self.detector.tick(wall, monotonic) {
+            println!("ℹ The device was asleep for {}s during the sync", slept.as_secs());
+            self.woke_at = Some(monotonic);
+        }
+    }
+
+
+    // Whether a failure for this reason, now, is the suspend's rather than the item's.
+    pub fn suspended(&self, monotonic: Duration, reason: &str) -> bool {
+        self.woke_at.is_some_and(|woke_at| monotonic.saturating_sub(woke_at) <= WINDOW) && transient(reason)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    // The clocks, wall and monotonic seconds, at each item and the reason it failed, if it did.
+    // Which failures were the suspend's.
+    fn storm(timeline: &[(u64, u64, Option<&str>)]) -> Vec<bool> {
+        let mut watch = StormWatch::default();
+
+        timeline.iter()
+            .filter_map(|(wall, monotonic, failed)| {
+                let monotonic = Duration::from_secs(*monotonic);
+                watch.observe(UNIX_EPOCH + Duration::from_secs(*wall), monotonic);
+                failed.map(|reason| watch.suspended(monotonic, reason))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reasons() {
+        assert!(transient("error sending request for url (http://example.com/): connection closed before message completed"));
+        assert!(transient("Connection reset by peer (os error 104)"));
+        assert!(transient("timeout"));
+        assert!(transient("Reqwest(reqwest::Error { kind: Request, source: hyper::Error(IncompleteMessage) })"));
+        assert!(transient("Reqwest(reqwest::Error { kind: Request, source: Os { code: 110, kind: TimedOut } })"));
+        assert!(!transient("HTTP status client error (404 Not Found) for url (http://example.com/)"));
+        assert!(!transient("example.com.content doesn't parse"));
+    }
+
+    #[test]
+    fn after_a_gap() {
+        let reset = Some("Connection reset by peer (os error 104)");
+
+        // An hour asleep while the second item was being fetched.
+        assert_eq!(storm(&[(0, 0, None), (10, 10, None), (3620, 20, reset), (3625, 25, reset)]), vec![true, true]);
+        // No gap, the site's problem.
+        assert_eq!(storm(&[(0, 0, None), (10, 10, reset), (20, 20, reset)]), vec![false, false]);
+        // Not the kind of failure a suspend makes.
+        assert_eq!(storm(&[(0, 0, None), (3610, 10, Some("HTTP status client error (404 Not Found)"))]), vec![false]);
+        // Long after waking up.
+        assert_eq!(storm(&[(0, 0, None), (3610, 10, None), (3700, 100, reset)]), vec![false]);
+        // A minute unaccounted for isn't a suspend, the clocks drift.
+        assert_eq!(storm(&[(0, 0, None), (70, 20, reset)]), vec![false]);
+    }
+}
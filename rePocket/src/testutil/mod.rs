@@ -0,0 +1,104 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+
+use serde_json::{json, Value};
+
+use crate::clock::SystemClock;
+use crate::fshandler::FSHandler;
+use crate::pocket::PocketApi;
+use crate::pocketitem::PocketItem;
+use crate::settings::Settings;
+use crate::sync::{NoRefresh, SyncContext};
+
+
+//
+// What the tests of every module build their items and syncs from: items as Pocket lists them, on
+// top of an article of 1000 words with no images nor video, and a sync with nothing around it but
+// the system clock.
+//
+pub fn item_with(fields: Value) -> Value {
+    let mut item = json!({
+        "item_id": "1",
+        "resolved_id": "1",
+        "favorite": "0",
+        "is_article": "1",
+        "is_index": "0",
+        "has_image": "0",
+        "has_video": "0",
+        "word_count": "1000",
+    });
+    item.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+
+    item
+}
+
+
+pub fn pocket_item(fields: Value) -> PocketItem {
+    serde_json::from_value(item_with(fields)).unwrap()
+}
+
+
+pub fn pocket_items(items: &[Value]) -> Vec<PocketItem> {
+    items.iter().map(|item| serde_json::from_value(item.clone()).unwrap()).collect()
+}
+
+
+// Unread, at url.
+pub fn item_json(id: u64, url: &str) -> Value {
+    item_with(json!({
+        "item_id": id.to_string(),
+        "resolved_id": id.to_string(),
+        "resolved_url": url,
+        "status": "0",
+    }))
+}
+
+
+pub fn item(id: u64, url: &str) -> PocketItem {
+    pocket_item(item_json(id, url))
+}
+
+
+// The "tags" of the item, as Pocket lists them.
+pub fn tags(id: u64, tags: &[&str]) -> Value {
+    tags.iter()
+        .map(|tag| (tag.to_string(), json!({"item_id": id.to_string(), "tag": tag})))
+        .collect::<serde_json::Map<String, Value>>()
+        .into()
+}
+
+
+pub fn tagged(id: u64, url: &str, with: &[&str]) -> PocketItem {
+    let mut item = item_json(id, url);
+    item["tags"] = tags(id, with);
+
+    pocket_item(item)
+}
+
+
+pub fn context<'a, P: PocketApi>(pocket: &'a mut P, fhandler: &'a mut FSHandler, settings: Settings) -> SyncContext<'a, P> {
+    SyncContext {
+        pocket,
+        fhandler,
+        settings,
+        clock: &SystemClock,
+        refresher: &NoRefresh,
+        ignore_budget: false,
+        safe_mode: false,
+    }
+}
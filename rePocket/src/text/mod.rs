@@ -0,0 +1,282 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::fmt;
+use unicode_segmentation::UnicodeSegmentation;
+
+
+// Longer titles are cut, xochitl doesn't do anything sensible with them anyway.
+pub const MAX_VISIBLE_NAME: usize = 100;
+
+// Reading speeds, in words per minute and, for Chinese, Japanese and Korean, in characters.
+const WORDS_PER_MINUTE: usize = 230;
+const CJK_CHARS_PER_MINUTE: usize = 500;
+
+
+//
+// Titles, authors and descriptions end up in three different places, each wanting something
+// different:
+//
+// - The XHTML of the article wants them encoded, that's HtmlText.
+// - The epub metadata wants them as they are, epub_builder escapes the values it puts in the OPF
+//   by itself, encoding them first means "&amp;amp;".
+// - xochitl's visibleName wants them as they are too, but on a single line.
+//
+// Keeping the two apart in the type system means they can't be mixed up.
+//
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlainText(String);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HtmlText(String);
+
+
+impl PlainText {
+    pub fn new(text: &str) -> Self {
+        Self(text.to_string())
+    }
+
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+
+    // This function is similar to (I guess) what html_scape::encode_text() does.
+    // In summary, encondes &, >, < and " in strings to make them HTML-able.
+    pub fn to_html(&self) -> HtmlText {
+        // This is also to make XHTML happy, or remarkable, I don't even know anymore
+        HtmlText(self.0
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;"))
+    }
+
+
+    // Something fit for xochitl's visibleName: a single line, no control characters, not too long.
+    pub fn visible_name(&self) -> String {
+        let name = self.0
+            .split(|c: char| c.is_whitespace() || c.is_control())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        if name.is_empty() {
+            "Article".to_string()
+        } else {
+            truncate(&name, MAX_VISIBLE_NAME)
+        }
+    }
+}
+
+
+// Cut to at most max user-perceived characters, ellipsis included. Never splits a character, nor
+// an emoji or a letter from its combining marks.
+pub fn truncate(text: &str, max: usize) -> String {
+    let graphemes: Vec<&str> = text.graphemes(true).collect();
+
+    if graphemes.len() <= max {
+        return text.to_string();
+    }
+
+    let mut cut = graphemes[..max.saturating_sub(1)].concat();
+    cut.truncate(cut.trim_end().len());
+    cut.push('…');
+
+    cut
+}
+
+
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'     // Hiragana, Katakana
+        | '\u{3400}'..='\u{4dbf}'   // CJK Extension A
+        | '\u{4e00}'..='\u{9fff}'   // CJK Unified Ideographs
+        | '\u{ac00}'..='\u{d7af}'   // Hangul
+        | '\u{f900}'..='\u{faff}'   // CJK Compatibility Ideographs
+        | '\u{20000}'..='\u{2fa1f}' // CJK Extensions B and beyond
+    )
+}
+
+
+// Minutes it takes to read the text, at least one. Words don't mean much in Chinese or Japanese,
+// which hardly use spaces, so their characters are counted instead.
+pub fn reading_minutes(text: &str) -> usize {
+    let cjk = text.chars().filter(|c| is_cjk(*c)).count();
+    let words = text
+        .split(|c: char| c.is_whitespace() || is_cjk(c))
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count();
+
+    let minutes = words as f64 / WORDS_PER_MINUTE as f64 + cjk as f64 / CJK_CHARS_PER_MINUTE as f64;
+
+    std::cmp::max(1, minutes.round() as usize)
+}
+
+
+// "rtl" if the first letter in the text belongs to a right-to-left script, "ltr" otherwise.
+pub fn direction(text: &str) -> &'static str {
+    let rtl = |c: char| matches!(c,
+        '\u{0590}'..='\u{08ff}'     // Hebrew, Arabic, Syriac, Thaana, ...
+        | '\u{fb1d}'..='\u{fdff}'   // Hebrew and Arabic presentation forms A
+        | '\u{fe70}'..='\u{feff}'   // Arabic presentation forms B
+    );
+
+    match text.chars().find(|c| c.is_alphabetic()) {
+        Some(c) if rtl(c) => "rtl",
+        _ => "ltr",
+    }
+}
+
+
+impl HtmlText {
+    // For markup that's known to be fine, e.g. generated by us.
+    pub fn trusted(html: &str) -> Self {
+        Self(html.to_string())
+    }
+
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+
+impl fmt::Display for PlainText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+
+impl fmt::Display for HtmlText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADVERSARIAL: [&'static str; 4] = [
+        "Rust <T>: lifetimes & you",
+        "\"Quoted\" & <b>bold</b>",
+        "Tom &amp; Jerry",
+        "Line\nbreaks\tand\u{0007}bells  ",
+    ];
+
+    #[test]
+    fn html_sink() {
+        let html: Vec<String> = ADVERSARIAL.iter().map(|t| PlainText::new(t).to_html().to_string()).collect();
+
+        assert_eq!(html[0], "Rust &lt;T&gt;: lifetimes &amp; you");
+        assert_eq!(html[1], "&quot;Quoted&quot; &amp; &lt;b&gt;bold&lt;/b&gt;");
+        assert_eq!(html[2], "Tom &amp;amp; Jerry");
+
+        for html in html {
+            assert!(!html.contains('<') && !html.contains('>') && !html.contains('"'));
+        }
+    }
+
+    #[test]
+    fn visible_name_sink() {
+        assert_eq!(PlainText::new(ADVERSARIAL[0]).visible_name(), "Rust <T>: lifetimes & you");
+        assert_eq!(PlainText::new(ADVERSARIAL[2]).visible_name(), "Tom &amp; Jerry");
+        assert_eq!(PlainText::new(ADVERSARIAL[3]).visible_name(), "Line breaks and bells");
+        assert_eq!(PlainText::new(" \n ").visible_name(), "Article");
+    }
+
+    const ARABIC: &'static str = "كيف تعمل الحواسيب اللوحية بالحبر الإلكتروني";
+    const JAPANESE: &'static str = "電子ペーパー端末で長い記事を読むための実践的なガイド";
+    const EMOJI: &'static str = "👨‍👩‍👧 Family trips 🇯🇵 and café ☕️";
+
+    #[test]
+    fn truncation_is_boundary_safe() {
+        for title in [ARABIC, JAPANESE, EMOJI] {
+            for max in 1..title.graphemes(true).count() {
+                let cut = truncate(title, max);
+
+                assert!(cut.graphemes(true).count() <= max);
+                assert!(cut.ends_with('…'));
+                assert!(title.starts_with(cut.trim_end_matches('…')));
+            }
+
+            assert_eq!(truncate(title, 200), title);
+        }
+
+        // The family is a single grapheme, and so is the flag.
+        assert_eq!(truncate(EMOJI, 2), "👨‍👩‍👧…");
+        assert_eq!(truncate(JAPANESE, 5), "電子ペー…");
+    }
+
+    #[test]
+    fn long_visible_names() {
+        let long = JAPANESE.repeat(10);
+        let name = PlainText::new(&long).visible_name();
+
+        assert_eq!(name.graphemes(true).count(), MAX_VISIBLE_NAME);
+        assert_eq!(PlainText::new(ARABIC).visible_name(), ARABIC);
+        assert_eq!(PlainText::new(EMOJI).visible_name(), EMOJI);
+    }
+
+    #[test]
+    fn reading_time() {
+        // 520 characters of Japanese, about a minute, and not a single space in them.
+        let japanese = JAPANESE.repeat(20);
+        assert_eq!(reading_minutes(&japanese), 1);
+        assert_eq!(reading_minutes(&JAPANESE.repeat(200)), 10);
+
+        let english = "word ".repeat(2300);
+        assert_eq!(reading_minutes(&english), 10);
+
+        let arabic = (ARABIC.to_string() + " ").repeat(400);
+        assert_eq!(reading_minutes(&arabic), 10);
+
+        assert_eq!(reading_minutes(EMOJI), 1);
+        assert_eq!(reading_minutes(""), 1);
+    }
+
+    #[test]
+    fn text_direction() {
+        assert_eq!(direction(ARABIC), "rtl");
+        assert_eq!(direction("  «שלום»"), "rtl");
+        assert_eq!(direction(JAPANESE), "ltr");
+        assert_eq!(direction(EMOJI), "ltr");
+    }
+
+    #[test]
+    fn plain_sink() {
+        use std::io::Read;
+
+        // The epub metadata gets the text untouched, epub_builder escapes it: what's in the OPF
+        // reads back as the title, once, and is well-formed. The bell isn't XML at all.
+        let dc_title = regex::Regex::new(r"(?s)<dc:title[^>]*>(.*?)</dc:title>").unwrap();
+
+        for title in &ADVERSARIAL[..3] {
+            let epub = crate::articlehandler::note_epub(&PlainText::new(title), &HtmlText::trusted("<p>Body</p>")).unwrap();
+            let mut zip = zip::ZipArchive::new(std::io::Cursor::new(epub)).unwrap();
+            let mut opf = String::new();
+            zip.by_name("OEBPS/content.opf").unwrap().read_to_string(&mut opf).unwrap();
+
+            let written = &dc_title.captures(&opf).expect(&opf)[1];
+            assert!(!written.contains('<'), "{written}");
+            assert_eq!(quick_xml::escape::unescape(written).unwrap(), *title);
+        }
+    }
+}
@@ -41,18 +41,21 @@ async fn main() {
 
     match res {
         Ok(creds)   => {
-            println!("ℹ Writing credentials for user '{:#?}' in {}",
-                env!("CREDS_FILE"),
-                creds.clone().unwrap().username);
+            println!("ℹ Writing credentials for user '{}' in {}",
+                creds.username,
+                env!("CREDS_FILE"));
 
             match File::create(env!("CREDS_FILE")) {
                 Ok(mut fh) => {
-                    writeln!(&mut fh, "{ckey}").unwrap();
-                    writeln!(&mut fh, "{ak}", ak = creds.unwrap().access_token).unwrap();
+                    writeln!(&mut fh, "{}", mypocket.consumer_key).unwrap();
+                    writeln!(&mut fh, "{ak}", ak = creds.access_token).unwrap();
                 },
                 Err(err) => println!("🚨 Error!  {:?}", err),
             };
         },
-        Err(err)    => println!("🚨 Error!  {:?}", err),
+        Err(err)    => {
+            println!("🚨 {err}");
+            println!("  ..{}", err.hint());
+        },
     };
 }
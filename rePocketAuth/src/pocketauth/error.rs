@@ -0,0 +1,158 @@
+//
+// Copyright (c) 2024 Damián Sánchez Moreno
+//
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+
+use std::fmt;
+use std::io;
+use reqwest::header::HeaderMap;
+
+
+//
+// Where signing in went wrong, a step at a time:
+//
+//   RequestToken  asking Pocket for a request token, which is where a bad consumer key shows
+//   Authorize     turning the approved request token into an access token
+//   Callback      the local server the browser is sent back to, port 3030 and its certificate
+//   Io            the terminal, or the files
+//
+// Pocket says why in the X-Error and X-Error-Code headers, e.g. "Invalid consumer key." and 152.
+//
+#[derive(Debug)]
+pub enum AuthError {
+    RequestToken(Rejection),
+    Authorize(Rejection),
+    Callback(String),
+    Io(io::Error),
+}
+
+
+// What Pocket answered, or what kept it from answering at all.
+#[derive(Debug, Default, PartialEq)]
+pub struct Rejection {
+    // None when there was no response, e.g. a timeout.
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub code: Option<u32>,
+    // The transport error, or why the response didn't make sense.
+    pub detail: String,
+}
+
+
+impl Rejection {
+    // The rejection for a response, if it is one.
+    pub fn from_response(status: reqwest::StatusCode, headers: &HeaderMap) -> Option<Self> {
+        if status.is_success() {
+            return None;
+        }
+
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+
+        Some(Self {
+            status: Some(status.as_u16()),
+            error: header("X-Error").map(str::to_string),
+            code: header("X-Error-Code").and_then(|code| code.parse().ok()),
+            detail: String::new(),
+        })
+    }
+
+
+    pub fn transport(e: &reqwest::Error) -> Self {
+        let detail = match e.is_timeout() {
+            true => "getpocket.com didn't answer in time".to_string(),
+            false => e.to_string(),
+        };
+
+        Self { status: e.status().map(|status| status.as_u16()), detail, ..Self::default() }
+    }
+}
+
+
+impl AuthError {
+    // What to do about it.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            AuthError::RequestToken(rejection) | AuthError::Authorize(rejection) if rejection.status.is_none() => {
+                "Check the network connection, and try again"
+            },
+            AuthError::RequestToken(rejection) | AuthError::Authorize(rejection) if rejection.code == Some(199) => {
+                "Pocket is having trouble of its own, try again later"
+            },
+            AuthError::RequestToken(rejection) if rejection.status.is_some_and(|status| status >= 500) => {
+                "Pocket is having trouble of its own, try again later"
+            },
+            AuthError::RequestToken(_) => {
+                "Check the consumer key, it's the one at https://getpocket.com/developer/apps/, copied whole"
+            },
+            AuthError::Authorize(rejection) if rejection.code == Some(158) => {
+                "The authorization was declined in the browser, run rePocketAuth again and approve it"
+            },
+            AuthError::Authorize(_) => {
+                "Approve rePocket in the browser before pressing <Enter>, run rePocketAuth again to retry"
+            },
+            AuthError::Callback(_) => {
+                "Make sure nothing else listens on port 3030, and that the certificate was made, see the README"
+            },
+            AuthError::Io(_) => "Check the terminal, and that the credentials file can be written",
+        }
+    }
+}
+
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::RequestToken(rejection) => write!(f, "Pocket didn't give a request token: {rejection}"),
+            AuthError::Authorize(rejection) => write!(f, "Pocket didn't give an access token: {rejection}"),
+            AuthError::Callback(why) => write!(f, "Can't wait for the browser to come back: {why}"),
+            AuthError::Io(e) => write!(f, "Input/output error: {e}"),
+        }
+    }
+}
+
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+
+        if let Some(status) = self.status {
+            parts.push(format!("status {status}"));
+        }
+        if let Some(error) = &self.error {
+            parts.push(match self.code {
+                Some(code) => format!("{error} ({code})"),
+                None => error.clone(),
+            });
+        }
+        if !self.detail.is_empty() {
+            parts.push(self.detail.clone());
+        }
+
+        match parts.is_empty() {
+            true => write!(f, "no reason given"),
+            false => write!(f, "{}", parts.join(", ")),
+        }
+    }
+}
+
+
+impl std::error::Error for AuthError {}
+
+
+impl From<io::Error> for AuthError {
+    fn from(e: io::Error) -> Self {
+        AuthError::Io(e)
+    }
+}
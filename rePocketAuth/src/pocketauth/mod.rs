@@ -14,30 +14,44 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+pub mod error;
+
 use reqwest;
 use std::io;
 use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
 use std::process::Command;
 use std::convert::Infallible;
+use std::time::Duration;
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
 use tokio::sync::oneshot;
 use warp::Filter;
 use warp::http::StatusCode;
 
 use crate::REDIRECT_URI;
 
+pub use error::{AuthError, Rejection};
+
 // Pocket Authentication API
-const REQUEST_URL:    &'static str = "https://getpocket.com/v3/oauth/request";
-const AUTH_URL:       &'static str = "https://getpocket.com/v3/oauth/authorize";
+const API_URL:        &'static str = "https://getpocket.com/v3";
+const REQUEST_PATH:   &'static str = "/oauth/request";
+const AUTH_PATH:      &'static str = "/oauth/authorize";
 const USER_AUTH_BURL: &'static str = "https://getpocket.com/auth/authorize";
 const CERT_PATH:      &'static str = concat!(env!("CERT_DIR"), "/",  "rePocket.crt");
 const KEY_PATH:       &'static str = concat!(env!("CERT_DIR"), "/",  "rePocket.key");
+// Where the browser is sent back to, see REDIRECT_URI.
+const SINK_ADDR:      ([u8; 4], u16) = ([127, 0, 0, 1], 3030);
+
+// How long getpocket.com gets to answer each request, rather than hanging for good.
+const TIMEOUT: Duration = Duration::from_secs(30);
 
 
 
 #[derive(Default, Clone)]
 pub struct PocketAuth {
     pub client: reqwest::Client,
+    api: String,
     pub consumer_key: String,
     pub request_token: Option<PocketCode>,
     pub credentials: Option<PocketUser>,
@@ -59,29 +73,42 @@ pub struct PocketUser {
 impl PocketAuth {
     pub fn new(key: &str) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            consumer_key: key.to_string(),
+            client: Self::client(TIMEOUT),
+            api: API_URL.to_string(),
+            // Pasted into the terminal, with the newline.
+            consumer_key: key.trim().to_string(),
             request_token: None,
             credentials: None,
             authorized: Box::new(false),
         }
     }
 
-    pub async fn connect(&mut self) -> Result<Option<PocketUser>, reqwest::Error> {
-        // Get the request token
-        let res = self.obtain_request_token().await;
-
-        match res {
-            Ok(val)   => {
-                let json = val.json::<PocketCode>().await;
-                let code = json.unwrap().code;
-                self.set_request_token(code);
-            },
-            Err(e)  => println!("🚨 {e}"),
+
+    // Talk to something other than getpocket.com, e.g. a local server in the tests.
+    #[cfg(test)]
+    pub fn mock(api: &str, timeout: Duration) -> Self {
+        Self {
+            client: Self::client(timeout),
+            api: api.to_string(),
+            ..Self::new("consumer-key")
         }
+    }
 
-        println!("{CERT_PATH}");
-        println!("{KEY_PATH}");
+
+    fn client(timeout: Duration) -> reqwest::Client {
+        reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("🚨 Could not set up the HTTP client")
+    }
+
+
+    pub async fn connect(&mut self) -> Result<PocketUser, AuthError> {
+        // Get the request token
+        let code = self.obtain_request_token().await?;
+        self.set_request_token(code.code);
+
+        Self::check_sink(SINK_ADDR.into(), CERT_PATH, KEY_PATH)?;
 
         let (tx, rx) = oneshot::channel::<bool>();
         let routes = self.get_uri_sink();
@@ -93,30 +120,24 @@ impl PocketAuth {
                 rx.await.ok();
             });
         tokio::task::spawn(server); 
-        let _ = self.redirect_user_for_auth(tx).await;
-        let aures = self.get_user_approval().await;
-
-        match aures {
-            Ok(val)   => {
-                let status = val.status();
-                let json = val.json::<PocketUser>().await;
-                match json {
-                    Ok(res) => {
-                        self.set_access_creds(res.access_token, res.username); 
-                        Ok(self.get_credentials())
-                    },
-                    Err(e) => {
-                        println!("🚨 Couldn't parse the json response with status: '{status}' as credentials. {e}");
-                        Err(e)
-                    }
-                }
-            }
-            Err(e)  => {
-                println!("🚨 Failed while obtaining user approval: {e}");
-                Err(e)
-            }
+        self.redirect_user_for_auth(tx).await?;
+        let user = self.get_user_approval().await?;
+
+        self.set_access_creds(user.access_token.clone(), user.username.clone());
+
+        Ok(user)
+    }
+
+
+    // Whether the server the browser comes back to can start, warp would panic otherwise.
+    fn check_sink(addr: SocketAddr, cert: &str, key: &str) -> Result<(), AuthError> {
+        for path in [cert, key] {
+            std::fs::metadata(path).map_err(|e| AuthError::Callback(format!("{path}: {e}")))?;
         }
 
+        TcpListener::bind(addr)
+            .map(drop)
+            .map_err(|e| AuthError::Callback(format!("{addr}: {e}")))
     }
 
 
@@ -139,10 +160,6 @@ impl PocketAuth {
         );
     }
 
-    fn get_credentials(&self) -> Option<PocketUser> {
-        self.credentials.clone()
-    }
-
     fn get_uri_sink(&self) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
         let pok = self.authorized.clone();
         // Start the server first.
@@ -161,19 +178,36 @@ impl PocketAuth {
 
     // To begin the Pocket authorization process, your application must obtain a request token from
     // our servers by making a POST request.
-    async fn obtain_request_token(&self) -> Result<reqwest::Response, reqwest::Error> {
+    async fn obtain_request_token(&self) -> Result<PocketCode, AuthError> {
         let mut body = HashMap::new();
         body.insert("consumer_key", self.consumer_key.clone());
         body.insert("redirect_uri", REDIRECT_URI.to_string());
 
-        let msg = self.client.post(REQUEST_URL)
+        self.post(REQUEST_PATH, &body).await.map_err(AuthError::RequestToken)
+    }
+
+
+    // Both steps talk to Pocket the same way, and fail the same way, with Pocket's reasons.
+    async fn post<T: DeserializeOwned>(&self, path: &str, body: &HashMap<&str, String>) -> Result<T, Rejection> {
+        let res = self.client.post(format!("{}{path}", self.api))
             .header(reqwest::header::CONTENT_TYPE, "application/json; charset=UTF8")
             .header("X-Accept", "application/json")
-            .json(&body);
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| Rejection::transport(&e))?;
 
-        let res = msg.send().await;
+        if let Some(rejection) = Rejection::from_response(res.status(), res.headers()) {
+            return Err(rejection);
+        }
 
-        res
+        let status = res.status().as_u16();
+
+        res.json::<T>().await.map_err(|e| Rejection {
+            status: Some(status),
+            detail: format!("the response didn't make sense, {e}"),
+            ..Rejection::default()
+        })
     }
 
 
@@ -196,7 +230,7 @@ impl PocketAuth {
     }
 
 
-    async fn redirect_user_for_auth(&self, tx: oneshot::Sender<bool>) {
+    async fn redirect_user_for_auth(&self, tx: oneshot::Sender<bool>) -> Result<(), AuthError> {
         let user_auth_url = self.get_browser_auth_url().unwrap();
 
         println!("ℹ Redirecting to {user_auth_url} for App authorization. Authorize in the browser");
@@ -211,17 +245,18 @@ impl PocketAuth {
             println!("🚨 Hmmm, this is not a Mac so I may not know how to open a URL");
         }
 
-        Command::new(program)
-            .arg(user_auth_url)
-            .spawn()
-            .expect("🚨 Failed to open a browser");
+        // The URL's right there to open by hand otherwise.
+        if let Err(e) = Command::new(program).arg(&user_auth_url).spawn() {
+            println!("🚨 Failed to open a browser ({e}), open the URL above by hand");
+        }
 
         let mut lin = String::new();
 
-        io::stdin().read_line(&mut lin)
-            .expect("🚨 Can't parse provided input");
+        io::stdin().read_line(&mut lin)?;
 
         let _ = tx.send(true);
+
+        Ok(())
     }
 
 
@@ -230,23 +265,125 @@ impl PocketAuth {
     // use to make further calls to the Pocket API.  When your application receives the callback to
     // the redirect_uri supplied in /v3/oauth/request (step 4), you should present some UI to
     // indicate that your application is logging in and make a POST request.
-    async fn get_user_approval(&self) -> Result<reqwest::Response, reqwest::Error> {
+    async fn get_user_approval(&self) -> Result<PocketUser, AuthError> {
         println!("ℹ Requesting an access token");
 
         self.get_access_token().await
     }
 
 
-    async fn get_access_token(&self) -> Result<reqwest::Response, reqwest::Error> {
+    async fn get_access_token(&self) -> Result<PocketUser, AuthError> {
         let mut body = HashMap::new();
         body.insert("consumer_key", self.consumer_key.clone());
         body.insert("code", self.get_request_token());
 
-        let msg = self.client.post(AUTH_URL)
-            .header(reqwest::header::CONTENT_TYPE, "application/json; charset=UTF8")
-            .header("X-Accept", "application/json")
-            .json(&body);
+        self.post(AUTH_PATH, &body).await.map_err(AuthError::Authorize)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::Response;
+
+    // Pocket answering every request the same way, at the returned base URL.
+    async fn serve(status: u16, headers: Vec<(&'static str, &'static str)>, body: &'static str) -> String {
+        let route = warp::any().map(move || {
+            headers.iter()
+                .fold(Response::builder().status(status), |res, (name, value)| res.header(*name, *value))
+                .body(body)
+                .unwrap()
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        format!("http://{addr}/v3")
+    }
+
+    fn auth(api: &str) -> PocketAuth {
+        let mut auth = PocketAuth::mock(api, Duration::from_secs(5));
+        auth.set_request_token("request-token".to_string());
+        auth
+    }
+
+    #[tokio::test]
+    async fn request_token() {
+        let api = serve(200, vec![], r#"{"code": "dcba4321-dcba-4321-dcba-4321dc"}"#).await;
+
+        assert_eq!(auth(&api).obtain_request_token().await.unwrap().code, "dcba4321-dcba-4321-dcba-4321dc");
+    }
+
+    #[tokio::test]
+    async fn invalid_consumer_key() {
+        let api = serve(403, vec![("X-Error", "Invalid consumer key."), ("X-Error-Code", "152")], "403 Forbidden").await;
+        let e = auth(&api).obtain_request_token().await.unwrap_err();
+
+        match &e {
+            AuthError::RequestToken(rejection) => assert_eq!(rejection, &Rejection {
+                status: Some(403),
+                error: Some("Invalid consumer key.".to_string()),
+                code: Some(152),
+                detail: String::new(),
+            }),
+            _ => panic!("{e:?}"),
+        }
+        assert_eq!(e.to_string(), "Pocket didn't give a request token: status 403, Invalid consumer key. (152)");
+        assert!(e.hint().contains("consumer key"));
+    }
+
+    #[tokio::test]
+    async fn declined() {
+        let api = serve(403, vec![("X-Error", "User rejected code."), ("X-Error-Code", "158")], "").await;
+        let e = auth(&api).get_access_token().await.unwrap_err();
+
+        assert!(matches!(&e, AuthError::Authorize(rejection) if rejection.code == Some(158)), "{e:?}");
+        assert!(e.hint().contains("declined"));
+
+        let api = serve(400, vec![("X-Error", "Code not found."), ("X-Error-Code", "185")], "").await;
+        let e = auth(&api).get_access_token().await.unwrap_err();
+        assert!(e.hint().contains("before pressing"));
+    }
+
+    #[tokio::test]
+    async fn no_sense() {
+        let api = serve(200, vec![], "<html>Down for maintenance</html>").await;
+        let e = auth(&api).get_access_token().await.unwrap_err();
+
+        assert!(matches!(&e, AuthError::Authorize(rejection) if rejection.status == Some(200) && rejection.error.is_none()), "{e:?}");
+        assert!(e.to_string().contains("didn't make sense"));
+
+        let api = serve(500, vec![("X-Error-Code", "199")], "").await;
+        assert!(auth(&api).obtain_request_token().await.unwrap_err().hint().contains("try again later"));
+    }
+
+    // Connected, but nothing ever comes back.
+    #[tokio::test]
+    async fn stalled() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api = format!("http://{}/v3", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let _held = listener.accept().await;
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        let e = PocketAuth::mock(&api, Duration::from_millis(200)).obtain_request_token().await.unwrap_err();
+
+        assert!(matches!(&e, AuthError::RequestToken(rejection) if rejection.status.is_none()), "{e:?}");
+        assert!(e.to_string().contains("didn't answer in time"));
+        assert!(e.hint().contains("network"));
+    }
+
+    #[test]
+    fn sink() {
+        let this = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+
+        assert!(matches!(PocketAuth::check_sink(addr, this, "/nonexistent/rePocket.key"), Err(AuthError::Callback(why)) if why.contains("rePocket.key")));
+        assert!(matches!(PocketAuth::check_sink(addr, this, this), Err(AuthError::Callback(why)) if why.contains(&addr.to_string())));
 
-        msg.send().await
+        drop(taken);
+        assert!(PocketAuth::check_sink(addr, this, this).is_ok());
     }
 }
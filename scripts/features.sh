@@ -0,0 +1,30 @@
+#!/bin/sh
+#
+# Copyright (c) 2024 Damián Sánchez Moreno
+#
+# This program is free software: you can redistribute it and/or modify it under
+# the terms of the GNU General Public License as published by the Free Software
+# Foundation, either version 3 of the License, or (at your option) any later
+# version.
+#
+# This program is distributed in the hope that it will be useful, but WITHOUT
+# ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+# FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+#
+# You should have received a copy of the GNU General Public License along with
+# this program. If not, see <https://www.gnu.org/licenses/>.
+#
+
+
+# Every combination of rePocket's features builds, and its tests with them, see the README. On the
+# machine it runs on rather than the targets .cargo/config.toml builds for, another one with e.g.
+# TARGET=aarch64-unknown-linux-gnu.
+set -e
+
+TARGET="${TARGET:-x86_64-unknown-linux-gnu}"
+
+for FEATURES in "" "color" "remote-push" "offline-fixtures" "color,remote-push,offline-fixtures"; do
+    echo "Features: ${FEATURES:-none}.."
+    cargo clippy -p rePocket --target "${TARGET}" --all-targets --no-default-features --features "${FEATURES}" -- -D warnings
+    cargo test -p rePocket --target "${TARGET}" --no-default-features --features "${FEATURES}"
+done